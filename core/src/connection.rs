@@ -34,7 +34,7 @@ pub use substream::{Substream, SubstreamEndpoint, Close};
 pub use pool::{EstablishedConnection, EstablishedConnectionIter, PendingConnection};
 
 use crate::muxing::StreamMuxer;
-use crate::{Multiaddr, PeerId};
+use crate::{Multiaddr, PeerId, multiaddr::Protocol};
 use std::{error::Error, fmt, pin::Pin, task::Context, task::Poll};
 use std::hash::Hash;
 use substream::{Muxing, SubstreamEvent};
@@ -133,6 +133,15 @@ impl ConnectedPoint {
         }
     }
 
+    /// Returns true if the connection is relayed, i.e. the address of the
+    /// remote contains a `/p2p-circuit` component.
+    ///
+    /// A relayed connection is established through a third-party node that
+    /// forwards the traffic, as opposed to a direct connection to the remote.
+    pub fn is_relayed(&self) -> bool {
+        self.get_remote_address().iter().any(|p| p == Protocol::P2pCircuit)
+    }
+
     /// Returns the address of the remote stored in this struct.
     ///
     /// For `Dialer`, this returns `address`. For `Listener`, this returns `send_back_addr`.
@@ -211,6 +220,9 @@ where
     muxing: substream::Muxing<TMuxer, THandler::OutboundOpenInfo>,
     /// Handler that processes substreams.
     handler: THandler,
+    /// Whether an orderly close of the connection has been started, in which
+    /// case new inbound substreams are no longer accepted.
+    closing: bool,
 }
 
 impl<TMuxer, THandler> fmt::Debug for Connection<TMuxer, THandler>
@@ -222,6 +234,7 @@ where
         f.debug_struct("Connection")
             .field("muxing", &self.muxing)
             .field("handler", &self.handler)
+            .field("closing", &self.closing)
             .finish()
     }
 }
@@ -244,6 +257,7 @@ where
         Connection {
             muxing: Muxing::new(muxer),
             handler,
+            closing: false,
         }
     }
 
@@ -262,6 +276,15 @@ where
         self.handler.inject_event(event);
     }
 
+    /// Prepares an orderly shutdown of the connection.
+    ///
+    /// The handler keeps being polled so that it can finish its in-flight
+    /// work, but new inbound substreams are dropped instead of being
+    /// passed to the handler.
+    pub fn start_close(&mut self) {
+        self.closing = true;
+    }
+
     /// Begins an orderly shutdown of the connection, returning a
     /// `Future` that resolves when connection shutdown is complete.
     pub fn close(self) -> Close<TMuxer> {
@@ -281,7 +304,10 @@ where
             match self.muxing.poll(cx) {
                 Poll::Pending => io_pending = true,
                 Poll::Ready(Ok(SubstreamEvent::InboundSubstream { substream })) => {
-                    self.handler.inject_substream(substream, SubstreamEndpoint::Listener)
+                    // Don't accept new work from the remote once closing.
+                    if !self.closing {
+                        self.handler.inject_substream(substream, SubstreamEndpoint::Listener)
+                    }
                 }
                 Poll::Ready(Ok(SubstreamEvent::OutboundSubstream { user_data, substream })) => {
                     let endpoint = SubstreamEndpoint::Dialer(user_data);
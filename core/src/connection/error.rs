@@ -31,6 +31,10 @@ pub enum ConnectionError<THandlerErr> {
 
     /// The connection handler produced an error.
     Handler(THandlerErr),
+
+    /// The connection was closed in an orderly manner because the
+    /// local node is shutting down.
+    Shutdown,
 }
 
 impl<THandlerErr> fmt::Display
@@ -44,6 +48,8 @@ where
                 write!(f, "Connection error: I/O error: {}", err),
             ConnectionError::Handler(err) =>
                 write!(f, "Connection error: Handler error: {}", err),
+            ConnectionError::Shutdown =>
+                write!(f, "Connection error: Local node shutting down."),
         }
     }
 }
@@ -57,6 +63,7 @@ where
         match self {
             ConnectionError::IO(err) => Some(err),
             ConnectionError::Handler(err) => Some(err),
+            ConnectionError::Shutdown => None,
         }
     }
 }
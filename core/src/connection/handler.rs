@@ -20,7 +20,7 @@
 
 use crate::{Multiaddr, PeerId};
 use std::{task::Context, task::Poll};
-use super::{Connected, SubstreamEndpoint};
+use super::{Connected, ConnectionId, SubstreamEndpoint};
 
 /// The interface of a connection handler.
 ///
@@ -66,6 +66,20 @@ pub trait ConnectionHandler {
     /// Returning an error will close the connection to the remote.
     fn poll(&mut self, cx: &mut Context<'_>)
         -> Poll<Result<ConnectionHandlerEvent<Self::OutboundOpenInfo, Self::OutEvent>, Self::Error>>;
+
+    /// Polls the handler for the completion of its in-flight work once an orderly
+    /// close of the connection has been requested, e.g. through
+    /// [`EstablishedConnection::start_close`](super::EstablishedConnection::start_close).
+    ///
+    /// Until this method returns `Poll::Ready`, the handler keeps being polled and
+    /// notified of events as usual, except that new inbound substreams are no
+    /// longer accepted. The connection is closed afterwards, or once the configured
+    /// close timeout expires, whichever comes first.
+    ///
+    /// The default implementation returns `Poll::Ready` immediately.
+    fn poll_close(&mut self, _: &mut Context<'_>) -> Poll<()> {
+        Poll::Ready(())
+    }
 }
 
 /// Prototype for a `ConnectionHandler`.
@@ -75,9 +89,10 @@ pub trait IntoConnectionHandler<TConnInfo = PeerId> {
 
     /// Builds the node handler.
     ///
-    /// The implementation is given a `Connected` value that holds information about
-    /// the newly established connection for which a handler should be created.
-    fn into_handler(self, connected: &Connected<TConnInfo>) -> Self::Handler;
+    /// The implementation is given the ID of the newly established connection for
+    /// which a handler should be created and a `Connected` value that holds information
+    /// about it.
+    fn into_handler(self, id: ConnectionId, connected: &Connected<TConnInfo>) -> Self::Handler;
 }
 
 impl<T, TConnInfo> IntoConnectionHandler<TConnInfo> for T
@@ -86,7 +101,7 @@ where
 {
     type Handler = Self;
 
-    fn into_handler(self, _: &Connected<TConnInfo>) -> Self {
+    fn into_handler(self, _: ConnectionId, _: &Connected<TConnInfo>) -> Self {
         self
     }
 }
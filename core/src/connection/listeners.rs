@@ -219,6 +219,11 @@ where
         }
     }
 
+    /// Returns an iterator over the IDs of the listeners.
+    pub fn listener_ids(&self) -> impl Iterator<Item = ListenerId> + '_ {
+        self.listeners.iter().map(|l| l.id)
+    }
+
     /// Returns the transport passed when building this object.
     pub fn transport(&self) -> &TTrans {
         &self.transport
@@ -35,6 +35,7 @@ use std::{
     mem,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 use super::{
     Connected,
@@ -104,6 +105,10 @@ pub struct Manager<I, O, H, E, HE, C> {
     /// Size of the task command buffer (per task).
     task_command_buffer_size: usize,
 
+    /// Maximum time a connection handler is given to finish its in-flight
+    /// work when the connection is closed (per task).
+    task_close_timeout: Duration,
+
     /// The executor to use for running the background tasks. If `None`,
     /// the tasks are kept in `local_spawns` instead and polled on the
     /// current thread when the manager is polled for new events.
@@ -135,7 +140,8 @@ where
 /// Configuration options when creating a [`Manager`].
 ///
 /// The default configuration specifies no dedicated task executor, a
-/// task event buffer size of 32, and a task command buffer size of 7.
+/// task event buffer size of 32, a task command buffer size of 7 and a
+/// task close timeout of 10 seconds.
 #[non_exhaustive]
 pub struct ManagerConfig {
     /// Executor to use to spawn tasks.
@@ -146,6 +152,10 @@ pub struct ManagerConfig {
 
     /// Size of the task event buffer (for all tasks).
     pub task_event_buffer_size: usize,
+
+    /// Maximum time a connection handler is given to finish its in-flight
+    /// work when the connection is closed (per task).
+    pub task_close_timeout: Duration,
 }
 
 impl Default for ManagerConfig {
@@ -154,6 +164,7 @@ impl Default for ManagerConfig {
             executor: None,
             task_event_buffer_size: 32,
             task_command_buffer_size: 7,
+            task_close_timeout: Duration::from_secs(10),
         }
     }
 }
@@ -244,6 +255,7 @@ impl<I, O, H, TE, HE, C> Manager<I, O, H, TE, HE, C> {
             tasks: FnvHashMap::default(),
             next_task_id: TaskId(0),
             task_command_buffer_size: config.task_command_buffer_size,
+            task_close_timeout: config.task_close_timeout,
             executor: config.executor,
             local_spawns: FuturesUnordered::new(),
             events_tx: tx,
@@ -280,7 +292,9 @@ impl<I, O, H, TE, HE, C> Manager<I, O, H, TE, HE, C> {
         let (tx, rx) = mpsc::channel(self.task_command_buffer_size);
         self.tasks.insert(task_id, TaskInfo { sender: tx, state: TaskState::Pending });
 
-        let task = Box::pin(Task::pending(task_id, self.events_tx.clone(), rx, future, handler));
+        let task = Box::pin(Task::pending(
+            task_id, self.events_tx.clone(), rx, self.task_close_timeout, future, handler
+        ));
         if let Some(executor) = &mut self.executor {
             executor.exec(task);
         } else {
@@ -318,7 +332,9 @@ impl<I, O, H, TE, HE, C> Manager<I, O, H, TE, HE, C> {
         });
 
         let task: Pin<Box<Task<Pin<Box<future::Pending<_>>>, _, _, _, _, _, _>>> =
-            Box::pin(Task::established(task_id, self.events_tx.clone(), rx, conn));
+            Box::pin(Task::established(
+                task_id, self.events_tx.clone(), rx, self.task_close_timeout, conn
+            ));
 
         if let Some(executor) = &mut self.executor {
             executor.exec(task);
@@ -474,7 +490,9 @@ impl<'a, I, C> EstablishedEntry<'a, I, C> {
     }
 
     /// Sends a close command to the associated background task,
-    /// thus initiating a graceful active close of the connection.
+    /// thus initiating a graceful active close of the connection
+    /// once the connection handler has finished its in-flight work
+    /// (see [`ConnectionHandler::poll_close`](super::ConnectionHandler::poll_close)).
     ///
     /// Has no effect if the connection is already closing.
     ///
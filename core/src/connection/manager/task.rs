@@ -34,8 +34,9 @@ use crate::{
     },
 };
 use futures::{prelude::*, channel::mpsc, stream};
-use std::{pin::Pin, task::Context, task::Poll};
-use super::ConnectResult;
+use futures_timer::Delay;
+use std::{pin::Pin, task::Context, task::Poll, time::Duration};
+use super::{ConnectResult, ConnectionId};
 
 /// Identifier of a [`Task`] in a [`Manager`](super::Manager).
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -47,7 +48,8 @@ pub enum Command<T> {
     /// Notify the connection handler of an event.
     NotifyHandler(T),
     /// Gracefully close the connection (active close) before
-    /// terminating the task.
+    /// terminating the task, once the connection handler has
+    /// finished its in-flight work.
     Close,
 }
 
@@ -97,6 +99,10 @@ where
     /// Receiver for commands sent by the manager of this task.
     commands: stream::Fuse<mpsc::Receiver<Command<I>>>,
 
+    /// Maximum time to wait for the connection handler to finish its
+    /// in-flight work after [`Command::Close`].
+    close_timeout: Duration,
+
     /// Inner state of this `Task`.
     state: State<F, M, H, O, E, C>,
 }
@@ -112,6 +118,7 @@ where
         id: TaskId,
         events: mpsc::Sender<Event<O, H, E, <H::Handler as ConnectionHandler>::Error, C>>,
         commands: mpsc::Receiver<Command<I>>,
+        close_timeout: Duration,
         future: F,
         handler: H
     ) -> Self {
@@ -119,6 +126,7 @@ where
             id,
             events,
             commands: commands.fuse(),
+            close_timeout,
             state: State::Pending {
                 future: Box::pin(future),
                 handler,
@@ -131,13 +139,15 @@ where
         id: TaskId,
         events: mpsc::Sender<Event<O, H, E, <H::Handler as ConnectionHandler>::Error, C>>,
         commands: mpsc::Receiver<Command<I>>,
+        close_timeout: Duration,
         connection: Connection<M, H::Handler>
     ) -> Self {
         Task {
             id,
             events,
             commands: commands.fuse(),
-            state: State::Established { connection, event: None },
+            close_timeout,
+            state: State::Established { connection, event: None, closing: None },
         }
     }
 }
@@ -165,7 +175,12 @@ where
         /// is polled for new events in this state, otherwise the event
         /// must be sent to the `Manager` before the connection can be
         /// polled again.
-        event: Option<Event<O, H, E, <H::Handler as ConnectionHandler>::Error, C>>
+        event: Option<Event<O, H, E, <H::Handler as ConnectionHandler>::Error, C>>,
+        /// If the `Manager` requested the connection to be closed, the timeout
+        /// for the handler to finish its in-flight work, see
+        /// [`ConnectionHandler::poll_close`]. The connection is closed once
+        /// the handler is done or the timeout expires, whichever comes first.
+        closing: Option<Delay>,
     },
 
     /// The connection is closing (active close).
@@ -223,9 +238,10 @@ where
                             this.state = State::Established {
                                 connection: Connection::new(
                                     muxer,
-                                    handler.into_handler(&info),
+                                    handler.into_handler(ConnectionId(id), &info),
                                 ),
-                                event: Some(Event::Established { id, info })
+                                event: Some(Event::Established { id, info }),
+                                closing: None,
                             }
                         }
                         Poll::Pending => {
@@ -242,7 +258,7 @@ where
                     }
                 }
 
-                State::Established { mut connection, event } => {
+                State::Established { mut connection, event, mut closing } => {
                     // Check for commands from the `Manager`.
                     loop {
                         match this.commands.poll_next_unpin(cx) {
@@ -250,11 +266,13 @@ where
                             Poll::Ready(Some(Command::NotifyHandler(event))) =>
                                 connection.inject_event(event),
                             Poll::Ready(Some(Command::Close)) => {
-                                // Don't accept any further commands.
-                                this.commands.get_mut().close();
-                                // Discard the event, if any, and start a graceful close.
-                                this.state = State::Closing(connection.close());
-                                continue 'poll
+                                // Let the handler finish its in-flight work
+                                // before starting a graceful close, but no
+                                // longer than the configured timeout.
+                                if closing.is_none() {
+                                    connection.start_close();
+                                    closing = Some(Delay::new(this.close_timeout));
+                                }
                             }
                             Poll::Ready(None) => {
                                 // The manager has dropped the task or disappeared; abort.
@@ -263,17 +281,28 @@ where
                         }
                     }
 
+                    // Check whether the handler ran out of time to finish its in-flight
+                    // work, regardless of how busy the connection is.
+                    if let Some(timeout) = closing.as_mut() {
+                        if timeout.poll_unpin(cx).is_ready() {
+                            // Don't accept any further commands.
+                            this.commands.get_mut().close();
+                            this.state = State::Closing(connection.close());
+                            continue 'poll
+                        }
+                    }
+
                     if let Some(event) = event {
                         // Send the event to the manager.
                         match this.events.poll_ready(cx) {
                             Poll::Pending => {
-                                this.state = State::Established { connection, event: Some(event) };
+                                this.state = State::Established { connection, event: Some(event), closing };
                                 return Poll::Pending
                             }
                             Poll::Ready(result) => {
                                 if result.is_ok() {
                                     if let Ok(()) = this.events.start_send(event) {
-                                        this.state = State::Established { connection, event: None };
+                                        this.state = State::Established { connection, event: None, closing };
                                         continue 'poll
                                     }
                                 }
@@ -285,19 +314,29 @@ where
                         // Poll the connection for new events.
                         match Connection::poll(Pin::new(&mut connection), cx) {
                             Poll::Pending => {
-                                this.state = State::Established { connection, event: None };
+                                // Once the connection is idle, check whether the handler
+                                // has finished its in-flight work if it is to be closed.
+                                if closing.is_some() && connection.handler_mut().poll_close(cx).is_ready() {
+                                    // Don't accept any further commands.
+                                    this.commands.get_mut().close();
+                                    this.state = State::Closing(connection.close());
+                                    continue 'poll
+                                }
+                                this.state = State::Established { connection, event: None, closing };
                                 return Poll::Pending
                             }
                             Poll::Ready(Ok(connection::Event::Handler(event))) => {
                                 this.state = State::Established {
                                     connection,
-                                    event: Some(Event::Notify { id, event })
+                                    event: Some(Event::Notify { id, event }),
+                                    closing,
                                 };
                             }
                             Poll::Ready(Ok(connection::Event::AddressChange(new_address))) => {
                                 this.state = State::Established {
                                     connection,
-                                    event: Some(Event::AddressChange { id, new_address })
+                                    event: Some(Event::AddressChange { id, new_address }),
+                                    closing,
                                 };
                             }
                             Poll::Ready(Err(error)) => {
@@ -61,6 +61,7 @@ use std::{
     num::NonZeroUsize,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 
 /// Implementation of `Stream` that handles the nodes.
@@ -178,6 +179,11 @@ where
         self.listeners.remove_listener(id)
     }
 
+    /// Returns an iterator over the IDs of the active listeners.
+    pub fn listener_ids(&self) -> impl Iterator<Item = ListenerId> + '_ {
+        self.listeners.listener_ids()
+    }
+
     /// Returns an iterator that produces the list of addresses we are listening on.
     pub fn listen_addrs(&self) -> impl Iterator<Item = &Multiaddr> {
         self.listeners.listen_addrs()
@@ -275,6 +281,25 @@ where
             })
     }
 
+    /// Aborts the dialing attempts to addresses without a known `PeerId`, i.e. those
+    /// started with [`Network::dial`], returning the addresses that were being dialed.
+    pub fn abort_unknown_dials(&mut self) -> Vec<Multiaddr> {
+        let ids = self.pool.iter_pending_info()
+            .filter_map(|(id, endpoint, peer_id)| match endpoint {
+                ConnectedPoint::Dialer { .. } if peer_id.is_none() => Some(*id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut addresses = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(attempt) = self.pool.get_outgoing(id) {
+                addresses.push(attempt.endpoint().get_remote_address().clone());
+                attempt.abort();
+            }
+        }
+        addresses
+    }
+
     /// Returns a list of all connected peers, i.e. peers to whom the `Network`
     /// has at least one established connection.
     pub fn connected_peers(&self) -> impl Iterator<Item = &TPeerId> {
@@ -664,6 +689,14 @@ impl NetworkConfig {
         self
     }
 
+    /// Sets the maximum time a `ConnectionHandler` is given to finish its
+    /// in-flight work once an orderly close of its connection has been
+    /// started, after which the connection is closed regardless.
+    pub fn set_connection_close_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.manager_config.task_close_timeout = timeout;
+        self
+    }
+
     pub fn set_incoming_limit(&mut self, n: usize) -> &mut Self {
         self.pool_limits.max_incoming = Some(n);
         self
@@ -0,0 +1,231 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Peer records, through which a peer announces the addresses it can be
+//! reached at.
+//!
+//! A [`PeerRecord`] is signed by the identity key of the peer it describes
+//! and hence can be passed on by other peers, e.g. in the peer exchange of
+//! gossipsub, without them being able to forge it.
+
+use crate::{Multiaddr, PeerId, identity::{Keypair, error::{DecodingError, SigningError}}};
+use crate::peer_record_proto;
+use crate::signed_envelope::{ReadPayloadError, SignedEnvelope};
+use prost::Message;
+use std::{convert::TryFrom, error, fmt, time::SystemTime};
+
+/// The domain of the signature of peer records.
+const DOMAIN_SEP: &str = "libp2p-peer-record";
+/// The multicodec of peer records.
+const PAYLOAD_TYPE: &[u8] = &[0x03, 0x01];
+
+/// The addresses of a peer, signed by the peer itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerRecord {
+    peer_id: PeerId,
+    seq: u64,
+    addresses: Vec<Multiaddr>,
+    /// The envelope the record has been signed with.
+    envelope: SignedEnvelope,
+}
+
+impl PeerRecord {
+    /// Creates a record of the given addresses, signed with the key of the
+    /// local peer.
+    ///
+    /// The sequence number is the current Unix time in seconds, so that
+    /// later records supersede earlier ones.
+    pub fn new(key: &Keypair, addresses: Vec<Multiaddr>) -> Result<Self, SigningError> {
+        let seq = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let peer_id = key.public().into_peer_id();
+        let record = peer_record_proto::PeerRecord {
+            peer_id: peer_id.clone().into_bytes(),
+            seq,
+            addresses: addresses.iter()
+                .map(|a| peer_record_proto::peer_record::AddressInfo { multiaddr: a.to_vec() })
+                .collect(),
+        };
+        let mut payload = Vec::with_capacity(record.encoded_len());
+        record.encode(&mut payload).expect("Vec<u8> provides capacity as needed");
+        let envelope = SignedEnvelope::new(key, DOMAIN_SEP, PAYLOAD_TYPE.to_vec(), payload)?;
+        Ok(PeerRecord { peer_id, seq, addresses, envelope })
+    }
+
+    /// Reads and verifies the record contained in a signed envelope.
+    ///
+    /// The record must be signed by the key of the peer it describes.
+    pub fn from_signed_envelope(envelope: SignedEnvelope) -> Result<Self, FromEnvelopeError> {
+        let payload = envelope.payload(DOMAIN_SEP, PAYLOAD_TYPE)?;
+        let record = peer_record_proto::PeerRecord::decode(payload)
+            .map_err(|e| DecodingError::new("Protobuf").source(e))?;
+        let peer_id = PeerId::from_bytes(record.peer_id)
+            .map_err(|_| DecodingError::new("Invalid peer ID"))?;
+        if peer_id != envelope.key().clone().into_peer_id() {
+            return Err(FromEnvelopeError::MismatchedSignature)
+        }
+        let addresses = record.addresses.into_iter()
+            .map(|a| Multiaddr::try_from(a.multiaddr))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DecodingError::new("Invalid multiaddr").source(e))?;
+        Ok(PeerRecord { peer_id, seq: record.seq, addresses, envelope })
+    }
+
+    /// Returns the peer the record describes.
+    pub fn peer_id(&self) -> &PeerId {
+        &self.peer_id
+    }
+
+    /// Returns the sequence number of the record. Of two records of the
+    /// same peer, the one with the higher sequence number is more recent.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Returns the addresses of the peer.
+    pub fn addresses(&self) -> &[Multiaddr] {
+        &self.addresses
+    }
+
+    /// Returns the signed envelope of the record, to pass it on.
+    pub fn to_signed_envelope(&self) -> SignedEnvelope {
+        self.envelope.clone()
+    }
+
+    /// Consumes the record, returning its signed envelope.
+    pub fn into_signed_envelope(self) -> SignedEnvelope {
+        self.envelope
+    }
+}
+
+/// An error reading a [`PeerRecord`] from a [`SignedEnvelope`].
+#[derive(Debug)]
+pub enum FromEnvelopeError {
+    /// The envelope does not contain a valid peer record payload.
+    BadPayload(ReadPayloadError),
+    /// The record could not be decoded.
+    InvalidPeerRecord(DecodingError),
+    /// The record is not signed by the peer it describes.
+    MismatchedSignature,
+}
+
+impl From<ReadPayloadError> for FromEnvelopeError {
+    fn from(e: ReadPayloadError) -> Self {
+        FromEnvelopeError::BadPayload(e)
+    }
+}
+
+impl From<DecodingError> for FromEnvelopeError {
+    fn from(e: DecodingError) -> Self {
+        FromEnvelopeError::InvalidPeerRecord(e)
+    }
+}
+
+impl fmt::Display for FromEnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FromEnvelopeError::BadPayload(e) => write!(f, "Bad peer record payload: {}", e),
+            FromEnvelopeError::InvalidPeerRecord(e) => write!(f, "Invalid peer record: {}", e),
+            FromEnvelopeError::MismatchedSignature =>
+                write!(f, "Peer record is not signed by the peer it describes"),
+        }
+    }
+}
+
+impl error::Error for FromEnvelopeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            FromEnvelopeError::BadPayload(e) => Some(e),
+            FromEnvelopeError::InvalidPeerRecord(e) => Some(e),
+            FromEnvelopeError::MismatchedSignature => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_through_signed_envelope() {
+        let key = Keypair::generate_ed25519();
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        let record = PeerRecord::new(&key, vec![addr.clone()]).unwrap();
+        let envelope = SignedEnvelope::from_protobuf_encoding(
+            &record.to_signed_envelope().into_protobuf_encoding()).unwrap();
+        let decoded = PeerRecord::from_signed_envelope(envelope).unwrap();
+        assert_eq!(decoded, record);
+        assert_eq!(decoded.peer_id(), &key.public().into_peer_id());
+        assert_eq!(decoded.addresses(), &[addr][..]);
+    }
+
+    #[test]
+    fn record_of_other_peer_is_rejected() {
+        let key = Keypair::generate_ed25519();
+        let other = Keypair::generate_ed25519();
+        let record = peer_record_proto::PeerRecord {
+            peer_id: other.public().into_peer_id().into_bytes(),
+            seq: 0,
+            addresses: Vec::new(),
+        };
+        let mut payload = Vec::new();
+        record.encode(&mut payload).unwrap();
+        let envelope = SignedEnvelope::new(&key, DOMAIN_SEP, PAYLOAD_TYPE.to_vec(), payload).unwrap();
+        match PeerRecord::from_signed_envelope(envelope) {
+            Err(FromEnvelopeError::MismatchedSignature) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn envelope_of_other_domain_is_rejected() {
+        let key = Keypair::generate_ed25519();
+        let record = PeerRecord::new(&key, Vec::new()).unwrap();
+        let envelope = record.into_signed_envelope();
+        let envelope = SignedEnvelope::new(&key, "other-domain", PAYLOAD_TYPE.to_vec(),
+            envelope.payload(DOMAIN_SEP, PAYLOAD_TYPE).unwrap().to_vec()).unwrap();
+        match PeerRecord::from_signed_envelope(envelope) {
+            Err(FromEnvelopeError::BadPayload(ReadPayloadError::InvalidSignature)) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn envelope_of_other_payload_type_is_rejected() {
+        let key = Keypair::generate_ed25519();
+        let envelope = SignedEnvelope::new(&key, DOMAIN_SEP, b"other".to_vec(), Vec::new()).unwrap();
+        match PeerRecord::from_signed_envelope(envelope) {
+            Err(FromEnvelopeError::BadPayload(ReadPayloadError::UnexpectedPayloadType { .. })) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_record_is_rejected() {
+        let key = Keypair::generate_ed25519();
+        let envelope = SignedEnvelope::new(&key, DOMAIN_SEP, PAYLOAD_TYPE.to_vec(), vec![0xff; 4]).unwrap();
+        match PeerRecord::from_signed_envelope(envelope) {
+            Err(FromEnvelopeError::InvalidPeerRecord(_)) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Signed envelopes, through which a peer vouches for a payload with its
+//! identity key.
+//!
+//! The signature covers a domain separation string, the type of the
+//! payload and the payload itself, so that a signature produced for one
+//! purpose can't be reused for another.
+
+use crate::{PublicKey, identity::{self, Keypair, error::{DecodingError, SigningError}}};
+use crate::envelope_proto;
+use prost::Message;
+use std::{error, fmt};
+
+/// A payload signed by the identity key of a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedEnvelope {
+    key: PublicKey,
+    payload_type: Vec<u8>,
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl SignedEnvelope {
+    /// Signs `payload` of the given type in the given domain.
+    pub fn new(
+        key: &Keypair,
+        domain: &str,
+        payload_type: Vec<u8>,
+        payload: Vec<u8>,
+    ) -> Result<Self, SigningError> {
+        let signature = key.sign(&signature_payload(domain, &payload_type, &payload))?;
+        Ok(SignedEnvelope { key: key.public(), payload_type, payload, signature })
+    }
+
+    /// Returns true if the signature is valid for the given domain.
+    pub fn verify(&self, domain: &str) -> bool {
+        let message = signature_payload(domain, &self.payload_type, &self.payload);
+        self.key.verify(&message, &self.signature)
+    }
+
+    /// Returns the payload if the signature is valid for the given domain
+    /// and the payload is of the expected type.
+    pub fn payload(&self, domain: &str, expected_type: &[u8]) -> Result<&[u8], ReadPayloadError> {
+        if self.payload_type != expected_type {
+            return Err(ReadPayloadError::UnexpectedPayloadType {
+                expected: expected_type.to_vec(),
+                got: self.payload_type.clone(),
+            })
+        }
+        if !self.verify(domain) {
+            return Err(ReadPayloadError::InvalidSignature)
+        }
+        Ok(&self.payload)
+    }
+
+    /// Returns the public key of the signer.
+    pub fn key(&self) -> &PublicKey {
+        &self.key
+    }
+
+    /// Encodes the envelope into its protobuf representation.
+    pub fn into_protobuf_encoding(self) -> Vec<u8> {
+        let envelope = envelope_proto::Envelope {
+            public_key: self.key.into_protobuf_encoding(),
+            payload_type: self.payload_type,
+            payload: self.payload,
+            signature: self.signature,
+        };
+        let mut buf = Vec::with_capacity(envelope.encoded_len());
+        envelope.encode(&mut buf).expect("Vec<u8> provides capacity as needed");
+        buf
+    }
+
+    /// Decodes an envelope from its protobuf representation, without
+    /// verifying its signature.
+    pub fn from_protobuf_encoding(bytes: &[u8]) -> Result<Self, DecodingError> {
+        let envelope = envelope_proto::Envelope::decode(bytes)
+            .map_err(|e| DecodingError::new("Protobuf").source(e))?;
+        Ok(SignedEnvelope {
+            key: identity::PublicKey::from_protobuf_encoding(&envelope.public_key)?,
+            payload_type: envelope.payload_type,
+            payload: envelope.payload,
+            signature: envelope.signature,
+        })
+    }
+}
+
+/// Builds the message that is signed: the domain, payload type and payload,
+/// each prefixed by its length as unsigned varint.
+fn signature_payload(domain: &str, payload_type: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(domain.len() + payload_type.len() + payload.len() + 3 * 10);
+    for part in &[domain.as_bytes(), payload_type, payload] {
+        let mut len = unsigned_varint::encode::usize_buffer();
+        buf.extend_from_slice(unsigned_varint::encode::usize(part.len(), &mut len));
+        buf.extend_from_slice(part);
+    }
+    buf
+}
+
+/// An error reading the payload of a [`SignedEnvelope`].
+#[derive(Debug)]
+pub enum ReadPayloadError {
+    /// The signature of the envelope is invalid.
+    InvalidSignature,
+    /// The payload is not of the expected type.
+    UnexpectedPayloadType {
+        /// The type the payload was expected to be of.
+        expected: Vec<u8>,
+        /// The type of the payload in the envelope.
+        got: Vec<u8>,
+    },
+}
+
+impl fmt::Display for ReadPayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadPayloadError::InvalidSignature => write!(f, "Invalid signature"),
+            ReadPayloadError::UnexpectedPayloadType { expected, got } =>
+                write!(f, "Unexpected payload type, expected {:?} but got {:?}", expected, got),
+        }
+    }
+}
+
+impl error::Error for ReadPayloadError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_roundtrip_and_verify() {
+        let key = Keypair::generate_ed25519();
+        let envelope = SignedEnvelope::new(&key, "domain", b"type".to_vec(), b"payload".to_vec()).unwrap();
+        let decoded = SignedEnvelope::from_protobuf_encoding(&envelope.clone().into_protobuf_encoding()).unwrap();
+        assert_eq!(decoded, envelope);
+        assert_eq!(decoded.payload("domain", b"type").unwrap(), b"payload");
+        assert!(!decoded.verify("other domain"));
+        match decoded.payload("domain", b"other type") {
+            Err(ReadPayloadError::UnexpectedPayloadType { .. }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tampered_payload_is_rejected() {
+        let key = Keypair::generate_ed25519();
+        let mut envelope = SignedEnvelope::new(&key, "domain", b"type".to_vec(), b"payload".to_vec()).unwrap();
+        envelope.payload = b"forged".to_vec();
+        let decoded = SignedEnvelope::from_protobuf_encoding(&envelope.into_protobuf_encoding()).unwrap();
+        match decoded.payload("domain", b"type") {
+            Err(ReadPayloadError::InvalidSignature) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn signature_covers_payload_type() {
+        let key = Keypair::generate_ed25519();
+        let mut envelope = SignedEnvelope::new(&key, "domain", b"type".to_vec(), b"payload".to_vec()).unwrap();
+        envelope.payload_type = b"other type".to_vec();
+        assert!(!envelope.verify("domain"));
+    }
+
+    #[test]
+    fn invalid_encoding_is_rejected() {
+        assert!(SignedEnvelope::from_protobuf_encoding(&[0xff, 0xff, 0xff]).is_err());
+    }
+}
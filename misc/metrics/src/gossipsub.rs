@@ -0,0 +1,128 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Metrics of the gossipsub behaviour.
+
+use crate::{Labels, Recorder, labels};
+use libp2p_gossipsub::{Gossipsub, GossipsubEvent, TopicHash};
+use prometheus_client::{
+    metrics::{counter::Counter, family::Family, gauge::Gauge},
+    registry::Registry,
+};
+use std::{collections::HashSet, sync::Mutex};
+
+pub(crate) struct Metrics {
+    messages: Family<Labels, Counter>,
+    messages_rejected: Counter,
+    subscribed: Counter,
+    unsubscribed: Counter,
+    topics: Gauge,
+    mesh_peers: Family<Labels, Gauge>,
+    /// The topics of the last recorded meshes.
+    mesh_topics: Mutex<HashSet<TopicHash>>,
+}
+
+impl Metrics {
+    pub(crate) fn new(registry: &mut Registry) -> Self {
+        let messages = Family::default();
+        registry.register(
+            "gossipsub_messages",
+            "Number of received messages, by topic",
+            messages.clone());
+
+        let messages_rejected = Counter::default();
+        registry.register(
+            "gossipsub_messages_rejected",
+            "Number of messages rejected by the application",
+            messages_rejected.clone());
+
+        let subscribed = Counter::default();
+        registry.register(
+            "gossipsub_subscribed",
+            "Number of subscriptions of remote peers to topics",
+            subscribed.clone());
+
+        let unsubscribed = Counter::default();
+        registry.register(
+            "gossipsub_unsubscribed",
+            "Number of unsubscriptions of remote peers from topics",
+            unsubscribed.clone());
+
+        let topics = Gauge::default();
+        registry.register(
+            "gossipsub_topics",
+            "Number of topics the local node is subscribed to",
+            topics.clone());
+
+        let mesh_peers = Family::default();
+        registry.register(
+            "gossipsub_mesh_peers",
+            "Number of peers in the mesh of a topic",
+            mesh_peers.clone());
+
+        Metrics {
+            messages,
+            messages_rejected,
+            subscribed,
+            unsubscribed,
+            topics,
+            mesh_peers,
+            mesh_topics: Default::default(),
+        }
+    }
+}
+
+impl Recorder<GossipsubEvent> for Metrics {
+    fn record(&self, event: &GossipsubEvent) {
+        match event {
+            GossipsubEvent::Message(_, _, message) => {
+                for topic in &message.topics {
+                    self.messages.get_or_create(&labels(&[("topic", topic.as_str())])).inc();
+                }
+            }
+            GossipsubEvent::Subscribed { .. } => {
+                self.subscribed.inc();
+            }
+            GossipsubEvent::Unsubscribed { .. } => {
+                self.unsubscribed.inc();
+            }
+            GossipsubEvent::MessageRejected { .. } => {
+                self.messages_rejected.inc();
+            }
+        }
+    }
+}
+
+impl Recorder<Gossipsub> for Metrics {
+    fn record(&self, gossipsub: &Gossipsub) {
+        let mut mesh_topics = self.mesh_topics.lock().expect("not poisoned");
+        let topics = gossipsub.topics().cloned().collect::<HashSet<_>>();
+        for topic in mesh_topics.difference(&topics) {
+            self.mesh_peers.remove(&labels(&[("topic", topic.as_str())]));
+        }
+        for topic in &topics {
+            let num_peers = gossipsub.mesh_peers(topic).count();
+            self.mesh_peers.get_or_create(&labels(&[("topic", topic.as_str())])).set(num_peers as i64);
+        }
+        self.topics.set(topics.len() as i64);
+        *mesh_topics = topics;
+    }
+}
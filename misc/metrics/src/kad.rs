@@ -0,0 +1,143 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Metrics of the Kademlia behaviour.
+
+use crate::{Labels, Recorder, labels};
+use libp2p_kad::{InboundRequest, KademliaEvent, QueryResult};
+use prometheus_client::{
+    metrics::{counter::Counter, family::Family, histogram::{Histogram, exponential_buckets}},
+    registry::Registry,
+};
+
+pub(crate) struct Metrics {
+    query_results: Family<Labels, Counter>,
+    query_result_num_requests: Family<Labels, Histogram, fn() -> Histogram>,
+    query_result_num_successes: Family<Labels, Histogram, fn() -> Histogram>,
+    query_result_num_failures: Family<Labels, Histogram, fn() -> Histogram>,
+    query_result_duration: Family<Labels, Histogram, fn() -> Histogram>,
+    inbound_requests: Family<Labels, Counter>,
+    routing_updated: Counter,
+}
+
+impl Metrics {
+    pub(crate) fn new(registry: &mut Registry) -> Self {
+        let query_results = Family::default();
+        registry.register(
+            "kad_query_results",
+            "Number of finished queries, by type and outcome",
+            query_results.clone());
+
+        let query_result_num_requests: Family<_, _, fn() -> _> = Family::new_with_constructor(||
+            Histogram::new(exponential_buckets(1.0, 2.0, 10)));
+        registry.register(
+            "kad_query_result_num_requests",
+            "Number of requests started for a query, by type",
+            query_result_num_requests.clone());
+
+        let query_result_num_successes: Family<_, _, fn() -> _> = Family::new_with_constructor(||
+            Histogram::new(exponential_buckets(1.0, 2.0, 10)));
+        registry.register(
+            "kad_query_result_num_successes",
+            "Number of successful requests of a query, by type",
+            query_result_num_successes.clone());
+
+        let query_result_num_failures: Family<_, _, fn() -> _> = Family::new_with_constructor(||
+            Histogram::new(exponential_buckets(1.0, 2.0, 10)));
+        registry.register(
+            "kad_query_result_num_failures",
+            "Number of failed requests of a query, by type",
+            query_result_num_failures.clone());
+
+        let query_result_duration: Family<_, _, fn() -> _> = Family::new_with_constructor(||
+            Histogram::new(exponential_buckets(0.1, 2.0, 10)));
+        registry.register(
+            "kad_query_result_duration_seconds",
+            "Duration of a query, by type",
+            query_result_duration.clone());
+
+        let inbound_requests = Family::default();
+        registry.register(
+            "kad_inbound_requests",
+            "Number of inbound requests, by type",
+            inbound_requests.clone());
+
+        let routing_updated = Counter::default();
+        registry.register(
+            "kad_routing_updated",
+            "Number of peers added to or updated in the routing table",
+            routing_updated.clone());
+
+        Metrics {
+            query_results,
+            query_result_num_requests,
+            query_result_num_successes,
+            query_result_num_failures,
+            query_result_duration,
+            inbound_requests,
+            routing_updated,
+        }
+    }
+}
+
+impl Recorder<KademliaEvent> for Metrics {
+    fn record(&self, event: &KademliaEvent) {
+        match event {
+            KademliaEvent::QueryResult { result, stats, .. } => {
+                let (query, ok) = match result {
+                    QueryResult::Bootstrap(r) => ("bootstrap", r.is_ok()),
+                    QueryResult::GetClosestPeers(r) => ("get_closest_peers", r.is_ok()),
+                    QueryResult::GetProviders(r) => ("get_providers", r.is_ok()),
+                    QueryResult::StartProviding(r) => ("start_providing", r.is_ok()),
+                    QueryResult::RepublishProvider(r) => ("republish_provider", r.is_ok()),
+                    QueryResult::GetRecord(r) => ("get_record", r.is_ok()),
+                    QueryResult::PutRecord(r) => ("put_record", r.is_ok()),
+                    QueryResult::RepublishRecord(r) => ("republish_record", r.is_ok()),
+                    QueryResult::Crawl(r) => ("crawl", r.is_ok()),
+                };
+                let outcome = if ok { "ok" } else { "error" };
+                self.query_results.get_or_create(&labels(&[("type", query), ("outcome", outcome)])).inc();
+
+                let labels = labels(&[("type", query)]);
+                self.query_result_num_requests.get_or_create(&labels).observe(stats.num_requests().into());
+                self.query_result_num_successes.get_or_create(&labels).observe(stats.num_successes().into());
+                self.query_result_num_failures.get_or_create(&labels).observe(stats.num_failures().into());
+                if let Some(duration) = stats.duration() {
+                    self.query_result_duration.get_or_create(&labels).observe(duration.as_secs_f64());
+                }
+            }
+            KademliaEvent::InboundRequest { request } => {
+                let request = match request {
+                    InboundRequest::FindNode { .. } => "find_node",
+                    InboundRequest::GetProvider { .. } => "get_provider",
+                    InboundRequest::AddProvider { .. } => "add_provider",
+                    InboundRequest::GetRecord { .. } => "get_record",
+                    InboundRequest::PutRecord { .. } => "put_record",
+                };
+                self.inbound_requests.get_or_create(&labels(&[("type", request)])).inc();
+            }
+            KademliaEvent::RoutingUpdated { .. } => {
+                self.routing_updated.inc();
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Metrics of a libp2p `Swarm` and of its behaviours, encoded in the
+//! [OpenMetrics] text format understood by Prometheus by the
+//! [`prometheus_client`] crate.
+//!
+//! # Usage
+//!
+//! [`Metrics`] registers its metrics in a [`Registry`] when created. The
+//! application then passes the `SwarmEvent`s produced by the `Swarm`, as well
+//! as the events of the Kademlia, gossipsub and ping behaviours, to
+//! [`Recorder::record`]. The state of the `Swarm` and of the behaviours that
+//! is not reported through events, e.g. the `ProtocolStats` of the `Swarm` or
+//! the meshes of gossipsub, is recorded by passing them to
+//! [`Recorder::record`] from time to time.
+//!
+//! The metrics are written with [`encode`], e.g. in order to be served to
+//! Prometheus by an HTTP server of the application.
+//!
+//! ```
+//! use libp2p_metrics::{Metrics, Recorder, Registry, encode};
+//! use libp2p_swarm::{ProtocolStats, SwarmEvent};
+//!
+//! let mut registry = Registry::with_prefix("libp2p");
+//! let metrics = Metrics::new(&mut registry);
+//!
+//! let event: SwarmEvent<(), ()> = SwarmEvent::Dialing(libp2p_core::PeerId::random());
+//! metrics.record(&event);
+//! metrics.record(&ProtocolStats::default());
+//!
+//! let mut encoded = String::new();
+//! encode(&mut encoded, &registry).unwrap();
+//! assert!(encoded.contains("libp2p_swarm_dial_attempts_total 1\n"));
+//! ```
+//!
+//! [OpenMetrics]: https://openmetrics.io/
+
+#[cfg(feature = "gossipsub")]
+mod gossipsub;
+#[cfg(feature = "kad")]
+mod kad;
+#[cfg(feature = "ping")]
+mod ping;
+mod swarm;
+
+pub use prometheus_client;
+pub use prometheus_client::{encoding::text::encode, registry::Registry};
+
+use libp2p_swarm::{ProtocolStats, SwarmEvent};
+
+/// Records the events and the state of something in metrics.
+pub trait Recorder<Event> {
+    /// Records an event, or a snapshot of the state of something.
+    fn record(&self, event: &Event);
+}
+
+/// The metrics of a `Swarm` and of its behaviours.
+pub struct Metrics {
+    swarm: swarm::Metrics,
+    #[cfg(feature = "gossipsub")]
+    gossipsub: gossipsub::Metrics,
+    #[cfg(feature = "kad")]
+    kad: kad::Metrics,
+    #[cfg(feature = "ping")]
+    ping: ping::Metrics,
+}
+
+impl Metrics {
+    /// Creates the metrics and registers them in `registry`.
+    pub fn new(registry: &mut Registry) -> Self {
+        Metrics {
+            swarm: swarm::Metrics::new(registry),
+            #[cfg(feature = "gossipsub")]
+            gossipsub: gossipsub::Metrics::new(registry),
+            #[cfg(feature = "kad")]
+            kad: kad::Metrics::new(registry),
+            #[cfg(feature = "ping")]
+            ping: ping::Metrics::new(registry),
+        }
+    }
+}
+
+/// The labels of a metric, as pairs of names and values.
+pub(crate) type Labels = Vec<(String, String)>;
+
+/// Builds the labels of a metric of a [`prometheus_client::metrics::family::Family`].
+pub(crate) fn labels(labels: &[(&str, &str)]) -> Labels {
+    labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+impl<TBvEv, THandleErr> Recorder<SwarmEvent<TBvEv, THandleErr>> for Metrics {
+    fn record(&self, event: &SwarmEvent<TBvEv, THandleErr>) {
+        self.swarm.record(event)
+    }
+}
+
+/// Records the substreams negotiated and the bytes sent and received per protocol, see
+/// `Swarm::protocol_stats`.
+impl Recorder<ProtocolStats> for Metrics {
+    fn record(&self, stats: &ProtocolStats) {
+        self.swarm.record(stats)
+    }
+}
+
+#[cfg(feature = "gossipsub")]
+impl Recorder<libp2p_gossipsub::GossipsubEvent> for Metrics {
+    fn record(&self, event: &libp2p_gossipsub::GossipsubEvent) {
+        self.gossipsub.record(event)
+    }
+}
+
+/// Records the topics and the sizes of the meshes of gossipsub.
+#[cfg(feature = "gossipsub")]
+impl Recorder<libp2p_gossipsub::Gossipsub> for Metrics {
+    fn record(&self, gossipsub: &libp2p_gossipsub::Gossipsub) {
+        self.gossipsub.record(gossipsub)
+    }
+}
+
+#[cfg(feature = "kad")]
+impl Recorder<libp2p_kad::KademliaEvent> for Metrics {
+    fn record(&self, event: &libp2p_kad::KademliaEvent) {
+        self.kad.record(event)
+    }
+}
+
+#[cfg(feature = "ping")]
+impl Recorder<libp2p_ping::PingEvent> for Metrics {
+    fn record(&self, event: &libp2p_ping::PingEvent) {
+        self.ping.record(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p_core::{ConnectedPoint, PeerId, connection::{ConnectionError, ConnectionId}};
+    use libp2p_swarm::SwarmEvent;
+    use super::{Metrics, Recorder, Registry, encode};
+
+    #[test]
+    fn records_swarm_connections() {
+        let mut registry = Registry::default();
+        let metrics = Metrics::new(&mut registry);
+        let peer_id = PeerId::random();
+        let endpoint = ConnectedPoint::Dialer { address: "/memory/1".parse().unwrap() };
+
+        let events: Vec<SwarmEvent<(), ()>> = vec![
+            SwarmEvent::Dialing(peer_id.clone()),
+            SwarmEvent::ConnectionEstablished {
+                peer_id: peer_id.clone(),
+                connection_id: ConnectionId::new(1),
+                endpoint: endpoint.clone(),
+                num_established: std::num::NonZeroU32::new(1).unwrap(),
+            },
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                connection_id: ConnectionId::new(1),
+                endpoint,
+                num_established: 0,
+                cause: Some(ConnectionError::Shutdown),
+                bandwidth: Default::default(),
+            },
+        ];
+        for event in &events {
+            metrics.record(event);
+        }
+
+        let mut encoded = String::new();
+        encode(&mut encoded, &registry).unwrap();
+        assert!(encoded.contains("swarm_dial_attempts_total 1\n"));
+        assert!(encoded.contains("swarm_connections_established_total{role=\"dialer\"} 1\n"));
+        assert!(encoded.contains("swarm_connections_closed_total{role=\"dialer\",cause=\"shutdown\"} 1\n"));
+        assert!(encoded.contains("swarm_connections_duration_seconds_count{role=\"dialer\"} 1\n"));
+    }
+
+    #[cfg(feature = "ping")]
+    #[test]
+    fn records_ping_rtts() {
+        use libp2p_ping::{PingEvent, PingFailure, PingSuccess};
+        use std::time::Duration;
+
+        let mut registry = Registry::default();
+        let metrics = Metrics::new(&mut registry);
+        let peer = PeerId::random();
+        metrics.record(&PingEvent { peer: peer.clone(), result: Ok(PingSuccess::Ping { rtt: Duration::from_millis(3) }) });
+        metrics.record(&PingEvent { peer, result: Err(PingFailure::Timeout) });
+
+        let mut encoded = String::new();
+        encode(&mut encoded, &registry).unwrap();
+        assert!(encoded.contains("ping_rtt_seconds_bucket{le=\"0.002\"} 0\n"));
+        assert!(encoded.contains("ping_rtt_seconds_bucket{le=\"0.004\"} 1\n"));
+        assert!(encoded.contains("ping_failures_total{reason=\"timeout\"} 1\n"));
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Metrics of the ping behaviour.
+
+use crate::{Labels, Recorder, labels};
+use libp2p_ping::{PingEvent, PingFailure, PingSuccess};
+use prometheus_client::{
+    metrics::{counter::Counter, family::Family, histogram::{Histogram, exponential_buckets}},
+    registry::Registry,
+};
+
+pub(crate) struct Metrics {
+    rtt: Histogram,
+    failures: Family<Labels, Counter>,
+    pongs_sent: Counter,
+}
+
+impl Metrics {
+    pub(crate) fn new(registry: &mut Registry) -> Self {
+        let rtt = Histogram::new(exponential_buckets(0.001, 2.0, 12));
+        registry.register(
+            "ping_rtt_seconds",
+            "Round-trip time of the pings sent to remote peers",
+            rtt.clone());
+
+        let failures = Family::default();
+        registry.register(
+            "ping_failures",
+            "Number of failed pings, by reason",
+            failures.clone());
+
+        let pongs_sent = Counter::default();
+        registry.register(
+            "ping_pongs_sent",
+            "Number of pings of remote peers answered",
+            pongs_sent.clone());
+
+        Metrics { rtt, failures, pongs_sent }
+    }
+}
+
+impl Recorder<PingEvent> for Metrics {
+    fn record(&self, event: &PingEvent) {
+        match &event.result {
+            Ok(PingSuccess::Ping { rtt }) => {
+                self.rtt.observe(rtt.as_secs_f64());
+            }
+            Ok(PingSuccess::Pong) => {
+                self.pongs_sent.inc();
+            }
+            Err(PingFailure::Timeout) => {
+                self.failures.get_or_create(&labels(&[("reason", "timeout")])).inc();
+            }
+            Err(PingFailure::Other { .. }) => {
+                self.failures.get_or_create(&labels(&[("reason", "other")])).inc();
+            }
+        }
+    }
+}
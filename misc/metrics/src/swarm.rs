@@ -0,0 +1,319 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Metrics of the `Swarm`.
+
+use crate::{Labels, Recorder, labels};
+use libp2p_core::{
+    ConnectedPoint,
+    PeerId,
+    connection::{ConnectionError, ConnectionId, PendingConnectionError},
+    transport::TransportError,
+};
+use libp2p_swarm::{ProtocolStats, SwarmEvent, protocols_handler::NodeHandlerWrapperError};
+use prometheus_client::{
+    metrics::{counter::Counter, family::Family, histogram::{Histogram, exponential_buckets}},
+    registry::Registry,
+};
+use std::{collections::HashMap, sync::Mutex};
+use wasm_timer::Instant;
+
+pub(crate) struct Metrics {
+    connections_incoming: Counter,
+    connections_incoming_error: Family<Labels, Counter>,
+    connections_established: Family<Labels, Counter>,
+    connections_closed: Family<Labels, Counter>,
+    connections_duration: Family<Labels, Histogram, fn() -> Histogram>,
+    dial_attempts: Counter,
+    dial_errors: Family<Labels, Counter>,
+    banned_peers: Counter,
+    new_listen_addr: Counter,
+    expired_listen_addr: Counter,
+    listener_closed: Family<Labels, Counter>,
+    listener_errors: Counter,
+    substreams: Family<Labels, Counter>,
+    substream_bytes: Family<Labels, Counter>,
+    /// The established connections with their peer and the time they were established at.
+    connections: Mutex<HashMap<ConnectionId, (PeerId, Instant)>>,
+}
+
+impl Metrics {
+    pub(crate) fn new(registry: &mut Registry) -> Self {
+        let connections_incoming = Counter::default();
+        registry.register(
+            "swarm_connections_incoming",
+            "Number of incoming connections",
+            connections_incoming.clone());
+
+        let connections_incoming_error = Family::default();
+        registry.register(
+            "swarm_connections_incoming_error",
+            "Number of errors of incoming connections during their handshake, by error",
+            connections_incoming_error.clone());
+
+        let connections_established = Family::default();
+        registry.register(
+            "swarm_connections_established",
+            "Number of established connections, by role",
+            connections_established.clone());
+
+        let connections_closed = Family::default();
+        registry.register(
+            "swarm_connections_closed",
+            "Number of closed connections, by role and cause",
+            connections_closed.clone());
+
+        let connections_duration: Family<_, _, fn() -> _> = Family::new_with_constructor(||
+            Histogram::new(exponential_buckets(0.01, 4.0, 12)));
+        registry.register(
+            "swarm_connections_duration_seconds",
+            "Time connections stayed established, by role",
+            connections_duration.clone());
+
+        let dial_attempts = Counter::default();
+        registry.register(
+            "swarm_dial_attempts",
+            "Number of dialing attempts of peers",
+            dial_attempts.clone());
+
+        let dial_errors = Family::default();
+        registry.register(
+            "swarm_dial_errors",
+            "Number of addresses that could not be dialed, by error",
+            dial_errors.clone());
+
+        let banned_peers = Counter::default();
+        registry.register(
+            "swarm_banned_peers",
+            "Number of connections of banned peers that were closed right away",
+            banned_peers.clone());
+
+        let new_listen_addr = Counter::default();
+        registry.register(
+            "swarm_new_listen_addr",
+            "Number of new listening addresses",
+            new_listen_addr.clone());
+
+        let expired_listen_addr = Counter::default();
+        registry.register(
+            "swarm_expired_listen_addr",
+            "Number of expired listening addresses",
+            expired_listen_addr.clone());
+
+        let listener_closed = Family::default();
+        registry.register(
+            "swarm_listener_closed",
+            "Number of closed listeners, by reason",
+            listener_closed.clone());
+
+        let listener_errors = Counter::default();
+        registry.register(
+            "swarm_listener_errors",
+            "Number of non-fatal listener errors",
+            listener_errors.clone());
+
+        let substreams = Family::default();
+        registry.register(
+            "swarm_substreams",
+            "Number of negotiated substreams, by protocol and direction",
+            substreams.clone());
+
+        let substream_bytes = Family::default();
+        registry.register(
+            "swarm_substream_bytes",
+            "Number of bytes sent and received on the substreams, by protocol and direction",
+            substream_bytes.clone());
+
+        Metrics {
+            connections_incoming,
+            connections_incoming_error,
+            connections_established,
+            connections_closed,
+            connections_duration,
+            dial_attempts,
+            dial_errors,
+            banned_peers,
+            new_listen_addr,
+            expired_listen_addr,
+            listener_closed,
+            listener_errors,
+            substreams,
+            substream_bytes,
+            connections: Default::default(),
+        }
+    }
+}
+
+impl<TBvEv, THandleErr> Recorder<SwarmEvent<TBvEv, THandleErr>> for Metrics {
+    fn record(&self, event: &SwarmEvent<TBvEv, THandleErr>) {
+        match event {
+            SwarmEvent::Behaviour(_) => {}
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, .. } => {
+                self.connections_established.get_or_create(&labels(&[("role", role(endpoint))])).inc();
+                self.connections.lock().expect("not poisoned")
+                    .insert(*connection_id, (peer_id.clone(), Instant::now()));
+            }
+            SwarmEvent::ConnectionClosed { peer_id, connection_id, endpoint, cause, num_established, .. } => {
+                let cause = match cause {
+                    None => "active_close",
+                    Some(ConnectionError::IO(_)) => "io",
+                    Some(ConnectionError::Handler(NodeHandlerWrapperError::Handler(_))) => "handler",
+                    Some(ConnectionError::Handler(NodeHandlerWrapperError::KeepAliveTimeout)) => "keep_alive_timeout",
+                    Some(ConnectionError::Shutdown) => "shutdown",
+                };
+                self.connections_closed.get_or_create(&labels(&[("role", role(endpoint)), ("cause", cause)])).inc();
+                let mut connections = self.connections.lock().expect("not poisoned");
+                if let Some((_, established)) = connections.remove(connection_id) {
+                    self.connections_duration
+                        .get_or_create(&labels(&[("role", role(endpoint))]))
+                        .observe(established.elapsed().as_secs_f64());
+                }
+                if *num_established == 0 {
+                    // No connection to the peer is left, including those whose
+                    // closing has not been recorded.
+                    connections.retain(|_, (peer, _)| peer != peer_id);
+                }
+            }
+            SwarmEvent::IncomingConnection { .. } => {
+                self.connections_incoming.inc();
+            }
+            SwarmEvent::IncomingConnectionError { error, .. } => {
+                self.connections_incoming_error.get_or_create(&labels(&[("error", pending_error(error))])).inc();
+            }
+            SwarmEvent::BannedPeer { .. } => {
+                // The connections to a banned peer are forgotten once their
+                // closing is recorded, along with their duration.
+                self.banned_peers.inc();
+            }
+            SwarmEvent::UnreachableAddr { error, .. } => {
+                self.dial_errors.get_or_create(&labels(&[("peer", "known"), ("error", pending_error(error))])).inc();
+            }
+            SwarmEvent::UnknownPeerUnreachableAddr { error, .. } => {
+                self.dial_errors.get_or_create(&labels(&[("peer", "unknown"), ("error", pending_error(error))])).inc();
+            }
+            SwarmEvent::NewListenAddr(_) => {
+                self.new_listen_addr.inc();
+            }
+            SwarmEvent::ExpiredListenAddr(_) => {
+                self.expired_listen_addr.inc();
+            }
+            SwarmEvent::ListenerClosed { reason, .. } => {
+                let reason = if reason.is_ok() { "ok" } else { "error" };
+                self.listener_closed.get_or_create(&labels(&[("reason", reason)])).inc();
+            }
+            SwarmEvent::ListenerError { .. } => {
+                self.listener_errors.inc();
+            }
+            SwarmEvent::Dialing(_) => {
+                self.dial_attempts.inc();
+            }
+        }
+    }
+}
+
+impl Recorder<ProtocolStats> for Metrics {
+    fn record(&self, stats: &ProtocolStats) {
+        for (protocol, counts) in stats.protocols() {
+            for (direction, count) in &[("inbound", counts.inbound), ("outbound", counts.outbound)] {
+                let counter = self.substreams.get_or_create(&labels(&[("protocol", &protocol), ("direction", direction)]));
+                // The protocol stats only ever increase.
+                counter.inc_by(count.saturating_sub(counter.get()));
+            }
+        }
+        for (protocol, bytes) in stats.bandwidth().protocols() {
+            for (direction, count) in &[("received", bytes.received), ("sent", bytes.sent)] {
+                let counter = self.substream_bytes.get_or_create(&labels(&[("protocol", &protocol), ("direction", direction)]));
+                counter.inc_by(count.saturating_sub(counter.get()));
+            }
+        }
+    }
+}
+
+fn role(endpoint: &ConnectedPoint) -> &'static str {
+    match endpoint {
+        ConnectedPoint::Dialer { .. } => "dialer",
+        ConnectedPoint::Listener { .. } => "listener",
+    }
+}
+
+fn pending_error<TTransErr>(error: &PendingConnectionError<TTransErr>) -> &'static str {
+    match error {
+        PendingConnectionError::Transport(TransportError::MultiaddrNotSupported(_)) => "multiaddr_not_supported",
+        PendingConnectionError::Transport(TransportError::Other(_)) => "transport",
+        PendingConnectionError::InvalidPeerId => "invalid_peer_id",
+        PendingConnectionError::ConnectionLimit(_) => "connection_limit",
+        PendingConnectionError::IO(_) => "io",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p_core::{ConnectedPoint, PeerId, connection::ConnectionId};
+    use libp2p_swarm::SwarmEvent;
+    use prometheus_client::registry::Registry;
+    use super::{Metrics, Recorder};
+
+    #[test]
+    fn forgets_connections_of_disconnected_peers() {
+        let metrics = Metrics::new(&mut Registry::default());
+        let peer_id = PeerId::random();
+        let endpoint = ConnectedPoint::Dialer { address: "/memory/1".parse().unwrap() };
+
+        for (id, num) in &[(1, 1), (2, 2)] {
+            metrics.record(&SwarmEvent::<(), ()>::ConnectionEstablished {
+                peer_id: peer_id.clone(),
+                connection_id: ConnectionId::new(*id),
+                endpoint: endpoint.clone(),
+                num_established: std::num::NonZeroU32::new(*num).unwrap(),
+            });
+        }
+        assert_eq!(metrics.connections.lock().unwrap().len(), 2);
+
+        // The closing of the first connection is never recorded.
+        metrics.record(&SwarmEvent::<(), ()>::ConnectionClosed {
+            peer_id: peer_id.clone(),
+            connection_id: ConnectionId::new(2),
+            endpoint: endpoint.clone(),
+            num_established: 0,
+            cause: None,
+            bandwidth: Default::default(),
+        });
+        assert!(metrics.connections.lock().unwrap().is_empty());
+
+        metrics.record(&SwarmEvent::<(), ()>::ConnectionEstablished {
+            peer_id: peer_id.clone(),
+            connection_id: ConnectionId::new(3),
+            endpoint: endpoint.clone(),
+            num_established: std::num::NonZeroU32::new(1).unwrap(),
+        });
+        metrics.record(&SwarmEvent::<(), ()>::BannedPeer { peer_id: peer_id.clone(), endpoint: endpoint.clone() });
+        assert_eq!(metrics.connections.lock().unwrap().len(), 1);
+        metrics.record(&SwarmEvent::<(), ()>::ConnectionClosed {
+            peer_id,
+            connection_id: ConnectionId::new(3),
+            endpoint,
+            num_established: 0,
+            cause: None,
+            bandwidth: Default::default(),
+        });
+        assert!(metrics.connections.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,688 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::protocol::{AutoNatCodec, AutoNatProtocol, DialRequest, DialResponse, ResponseError};
+use futures::prelude::*;
+use libp2p_core::{ConnectedPoint, Multiaddr, PeerId, connection::ConnectionId, multiaddr::Protocol};
+use libp2p_request_response::{
+    handler::{RequestProtocol, RequestResponseHandler, RequestResponseHandlerEvent},
+    OutboundFailure,
+    ProtocolSupport,
+    RequestId,
+    RequestResponse,
+    RequestResponseConfig,
+    RequestResponseEvent,
+    RequestResponseMessage,
+    ResponseChannel
+};
+use libp2p_swarm::{
+    DialPeerCondition,
+    NetworkBehaviour,
+    NetworkBehaviourAction,
+    PollParameters
+};
+use rand::seq::SliceRandom;
+use std::{
+    collections::{HashMap, VecDeque},
+    error,
+    fmt,
+    iter,
+    task::{Context, Poll},
+    time::Duration
+};
+use wasm_timer::{Delay, Instant};
+
+/// The configuration of an [`AutoNat`] behaviour.
+#[derive(Debug, Clone)]
+pub struct AutoNatConfig {
+    timeout: Duration,
+    boot_delay: Duration,
+    refresh_interval: Duration,
+    retry_interval: Duration,
+    throttle_server_period: Duration,
+    use_connected: bool,
+    confidence_max: usize,
+    max_peer_addresses: usize,
+    throttle_clients_global_max: usize,
+    throttle_clients_peer_max: usize,
+    throttle_clients_period: Duration,
+}
+
+impl Default for AutoNatConfig {
+    fn default() -> Self {
+        AutoNatConfig {
+            timeout: Duration::from_secs(30),
+            boot_delay: Duration::from_secs(15),
+            refresh_interval: Duration::from_secs(15 * 60),
+            retry_interval: Duration::from_secs(90),
+            throttle_server_period: Duration::from_secs(90),
+            use_connected: true,
+            confidence_max: 3,
+            max_peer_addresses: 16,
+            throttle_clients_global_max: 30,
+            throttle_clients_peer_max: 3,
+            throttle_clients_period: Duration::from_secs(1),
+        }
+    }
+}
+
+impl AutoNatConfig {
+    /// Sets the timeout of a probe, i.e. of a dial request including the
+    /// remote dialing back.
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the delay before the first probe.
+    pub fn set_boot_delay(&mut self, delay: Duration) -> &mut Self {
+        self.boot_delay = delay;
+        self
+    }
+
+    /// Sets the interval between probes once the NAT status has been
+    /// confirmed with maximum confidence.
+    pub fn set_refresh_interval(&mut self, interval: Duration) -> &mut Self {
+        self.refresh_interval = interval;
+        self
+    }
+
+    /// Sets the interval between probes while the NAT status is not yet
+    /// confirmed with maximum confidence.
+    pub fn set_retry_interval(&mut self, interval: Duration) -> &mut Self {
+        self.retry_interval = interval;
+        self
+    }
+
+    /// Sets the minimum period between two probes sent to the same server.
+    pub fn set_throttle_server_period(&mut self, period: Duration) -> &mut Self {
+        self.throttle_server_period = period;
+        self
+    }
+
+    /// Sets whether connected peers are used as servers, in addition to
+    /// the servers added with [`AutoNat::add_server`].
+    pub fn set_use_connected(&mut self, use_connected: bool) -> &mut Self {
+        self.use_connected = use_connected;
+        self
+    }
+
+    /// Sets the number of consistent probe results after which the NAT
+    /// status is considered confirmed. A confirmed status only changes
+    /// after as many contradicting results.
+    pub fn set_confidence_max(&mut self, confidence_max: usize) -> &mut Self {
+        self.confidence_max = confidence_max;
+        self
+    }
+
+    /// Sets the maximum number of addresses dialed on behalf of a client.
+    pub fn set_max_peer_addresses(&mut self, n: usize) -> &mut Self {
+        self.max_peer_addresses = n;
+        self
+    }
+
+    /// Sets the maximum number of dial requests of all clients served
+    /// within a single throttle period.
+    pub fn set_throttle_clients_global_max(&mut self, n: usize) -> &mut Self {
+        self.throttle_clients_global_max = n;
+        self
+    }
+
+    /// Sets the maximum number of dial requests of a single client served
+    /// within a single throttle period.
+    pub fn set_throttle_clients_peer_max(&mut self, n: usize) -> &mut Self {
+        self.throttle_clients_peer_max = n;
+        self
+    }
+
+    /// Sets the period over which served dial requests are counted for
+    /// throttling clients.
+    pub fn set_throttle_clients_period(&mut self, period: Duration) -> &mut Self {
+        self.throttle_clients_period = period;
+        self
+    }
+
+    /// The interval until the next probe, given the current confidence
+    /// in the NAT status.
+    fn next_probe_in(&self, status: &NatStatus, confidence: usize) -> Duration {
+        match status {
+            NatStatus::Unknown => self.retry_interval,
+            _ if confidence < self.confidence_max => self.retry_interval,
+            _ => self.refresh_interval,
+        }
+    }
+}
+
+/// The assumed reachability of the local node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NatStatus {
+    /// The local node is reachable at the given address.
+    Public(Multiaddr),
+    /// The local node is not reachable at any of its candidate addresses.
+    Private,
+    /// The reachability has not been determined yet.
+    Unknown,
+}
+
+impl NatStatus {
+    /// Returns `true` if the local node is publicly reachable.
+    pub fn is_public(&self) -> bool {
+        matches!(self, NatStatus::Public(..))
+    }
+}
+
+/// Error of a probe sent to a server.
+#[derive(Debug)]
+pub enum OutboundProbeError {
+    /// The request failed, e.g. because the server does not support the
+    /// protocol or timed out.
+    Failure(OutboundFailure),
+    /// The server answered with an error.
+    Response(ResponseError),
+}
+
+impl fmt::Display for OutboundProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutboundProbeError::Failure(e) => write!(f, "Dial request failed: {:?}", e),
+            OutboundProbeError::Response(e) => write!(f, "Dial request answered with error: {:?}", e),
+        }
+    }
+}
+
+impl error::Error for OutboundProbeError {}
+
+/// Event produced by the [`AutoNat`] behaviour.
+#[derive(Debug)]
+pub enum AutoNatEvent {
+    /// The assumed NAT status of the local node changed.
+    StatusChanged {
+        /// The former status.
+        old: NatStatus,
+        /// The new status.
+        new: NatStatus,
+    },
+    /// A probe sent to a server finished.
+    OutboundProbe {
+        /// The server.
+        peer: PeerId,
+        /// The address the server dialed back, or the reason of failure.
+        result: Result<Multiaddr, OutboundProbeError>,
+    },
+    /// A probe of a client has been served.
+    InboundProbe {
+        /// The client.
+        peer: PeerId,
+        /// The address the client has been reached at, or the error
+        /// reported back to the client.
+        result: Result<Multiaddr, ResponseError>,
+    },
+}
+
+/// A `NetworkBehaviour` implementing the AutoNAT protocol.
+///
+/// The behaviour determines the [`NatStatus`] of the local node by
+/// periodically asking servers, i.e. connected peers and peers added via
+/// [`AutoNat::add_server`], to dial back the external addresses of the
+/// local node. The candidate addresses are those returned by
+/// [`PollParameters::external_addresses`], i.e. the addresses added via
+/// `Swarm::add_external_address` as well as the translations of
+/// addresses observed by remotes, see `Network::address_translation`.
+///
+/// At the same time, the behaviour serves the dial requests of other
+/// peers. Dialing back a client uses the regular dialing machinery of the
+/// `Swarm`, thus addresses of the client known to other behaviours may be
+/// dialed as well.
+pub struct AutoNat {
+    inner: RequestResponse<AutoNatCodec>,
+    config: AutoNatConfig,
+    /// The current NAT status.
+    nat_status: NatStatus,
+    /// The number of consistent probe results confirming `nat_status`.
+    confidence: usize,
+    /// Timer of the next probe.
+    next_probe: Delay,
+    /// The ongoing probe, if any.
+    ongoing_outbound: Option<(RequestId, PeerId)>,
+    /// Servers added explicitly.
+    servers: Vec<PeerId>,
+    /// The last time a probe has been sent to a server.
+    last_probed: HashMap<PeerId, Instant>,
+    /// Established connections.
+    connected: HashMap<PeerId, HashMap<ConnectionId, Multiaddr>>,
+    /// Dial-backs in progress, by client.
+    ongoing_inbound: HashMap<PeerId, InboundProbe>,
+    /// Recently served dial requests, for throttling clients.
+    served: VecDeque<(PeerId, Instant)>,
+    /// Actions to yield on the next call to `poll`.
+    pending_actions: VecDeque<NetworkBehaviourAction<RequestProtocol<AutoNatCodec>, AutoNatEvent>>,
+}
+
+struct InboundProbe {
+    addresses: Vec<Multiaddr>,
+    channel: ResponseChannel<DialResponse>,
+    /// Fires when the dial-back took longer than the configured timeout.
+    timeout: Delay,
+}
+
+impl AutoNat {
+    /// Creates a new `AutoNat` behaviour with the given configuration.
+    pub fn new(config: AutoNatConfig) -> Self {
+        let mut cfg = RequestResponseConfig::default();
+        cfg.set_request_timeout(config.timeout);
+        let inner = RequestResponse::new(
+            AutoNatCodec,
+            iter::once((AutoNatProtocol, ProtocolSupport::Full)),
+            cfg,
+        );
+        AutoNat {
+            inner,
+            next_probe: Delay::new(config.boot_delay),
+            config,
+            nat_status: NatStatus::Unknown,
+            confidence: 0,
+            ongoing_outbound: None,
+            servers: Vec::new(),
+            last_probed: HashMap::new(),
+            connected: HashMap::new(),
+            ongoing_inbound: HashMap::new(),
+            served: VecDeque::new(),
+            pending_actions: VecDeque::new(),
+        }
+    }
+
+    /// Returns the current assumed NAT status of the local node.
+    pub fn nat_status(&self) -> &NatStatus {
+        &self.nat_status
+    }
+
+    /// Returns the confidence in the current NAT status, i.e. the number
+    /// of consistent probe results, capped at the configured maximum.
+    pub fn confidence(&self) -> usize {
+        self.confidence
+    }
+
+    /// Adds a peer to ask for dial-backs, optionally with an address to
+    /// reach it at.
+    pub fn add_server(&mut self, peer: PeerId, address: Option<Multiaddr>) {
+        if let Some(address) = address {
+            self.inner.add_address(&peer, address);
+        }
+        if !self.servers.contains(&peer) {
+            self.servers.push(peer);
+        }
+    }
+
+    /// Removes a peer added via [`AutoNat::add_server`].
+    pub fn remove_server(&mut self, peer: &PeerId) {
+        self.servers.retain(|p| p != peer);
+    }
+
+    /// Starts a probe if possible, returning the delay until the next one.
+    fn probe(&mut self, params: &impl PollParameters) -> Duration {
+        if self.ongoing_outbound.is_some() {
+            return self.config.retry_interval
+        }
+
+        let mut addresses = Vec::new();
+        for addr in params.external_addresses() {
+            if !addresses.contains(&addr) {
+                addresses.push(addr);
+            }
+        }
+        addresses.truncate(self.config.max_peer_addresses);
+        if addresses.is_empty() {
+            log::debug!("No candidate addresses to probe.");
+            return self.config.retry_interval
+        }
+
+        let now = Instant::now();
+        let period = self.config.throttle_server_period;
+        let last_probed = &self.last_probed;
+        let mut servers = Vec::new();
+        for peer in self.servers.iter().chain(self.connected.keys().filter(|_| self.config.use_connected)) {
+            let throttled = last_probed.get(peer).map_or(false, |t| now.duration_since(*t) < period);
+            if !throttled && !servers.contains(peer) {
+                servers.push(peer.clone());
+            }
+        }
+        let server = match servers.choose(&mut rand::thread_rng()) {
+            Some(server) => server.clone(),
+            None => {
+                log::debug!("No server available to probe.");
+                return self.config.retry_interval
+            }
+        };
+
+        let request = DialRequest { peer_id: params.local_peer_id().clone(), addresses };
+        let request_id = self.inner.send_request(&server, request);
+        self.last_probed.insert(server.clone(), now);
+        self.ongoing_outbound = Some((request_id, server));
+        // A response reschedules the next probe.
+        self.config.timeout + self.config.retry_interval
+    }
+
+    /// Updates the NAT status according to the result of a probe.
+    fn handle_reported_status(&mut self, reported: NatStatus) {
+        if reported == self.nat_status {
+            if self.confidence < self.config.confidence_max {
+                self.confidence += 1;
+            }
+            return
+        }
+        if self.nat_status.is_public() && reported.is_public() {
+            // Reachable at another address, which does not contradict
+            // the current status.
+        } else if self.nat_status != NatStatus::Unknown && self.confidence > 0 {
+            self.confidence -= 1;
+            return
+        } else {
+            self.confidence = 0;
+        }
+        let old = std::mem::replace(&mut self.nat_status, reported.clone());
+        self.pending_actions.push_back(NetworkBehaviourAction::GenerateEvent(
+            AutoNatEvent::StatusChanged { old, new: reported }
+        ));
+    }
+
+    /// Handles a finished probe sent to a server.
+    fn handle_probe_result(&mut self, peer: PeerId, result: Result<Multiaddr, OutboundProbeError>) {
+        self.ongoing_outbound = None;
+        match &result {
+            Ok(addr) => self.handle_reported_status(NatStatus::Public(addr.clone())),
+            Err(OutboundProbeError::Response(ResponseError::DialError)) =>
+                self.handle_reported_status(NatStatus::Private),
+            Err(_) => {}
+        }
+        self.next_probe.reset(self.config.next_probe_in(&self.nat_status, self.confidence));
+        self.pending_actions.push_back(NetworkBehaviourAction::GenerateEvent(
+            AutoNatEvent::OutboundProbe { peer, result }
+        ));
+    }
+
+    /// Handles a dial request of a client.
+    fn handle_dial_request(&mut self, peer: PeerId, request: DialRequest, channel: ResponseChannel<DialResponse>) {
+        if request.peer_id != peer {
+            return self.respond(peer, channel, Err(ResponseError::BadRequest), "peer ID mismatch")
+        }
+
+        let now = Instant::now();
+        while let Some((_, t)) = self.served.front() {
+            if now.duration_since(*t) < self.config.throttle_clients_period {
+                break
+            }
+            self.served.pop_front();
+        }
+        if self.ongoing_inbound.contains_key(&peer)
+            || self.served.len() >= self.config.throttle_clients_global_max
+            || self.served.iter().filter(|(p, _)| *p == peer).count() >= self.config.throttle_clients_peer_max
+        {
+            return self.respond(peer, channel, Err(ResponseError::DialRefused), "too many dial requests")
+        }
+
+        // Only dial addresses at the IP addresses the client has been
+        // observed at, so that the server cannot be abused to dial
+        // arbitrary third parties.
+        let observed = self.connected.get(&peer)
+            .into_iter()
+            .flat_map(|c| c.values())
+            .filter_map(ip_of)
+            .collect::<Vec<_>>();
+        if observed.is_empty() {
+            return self.respond(peer, channel, Err(ResponseError::DialRefused), "no observed IP address")
+        }
+        let mut addresses = request.addresses.into_iter()
+            .filter(|a| !a.iter().any(|p| p == Protocol::P2pCircuit))
+            .filter(|a| ip_of(a).map_or(false, |ip| observed.contains(&ip)))
+            .collect::<Vec<_>>();
+        addresses.truncate(self.config.max_peer_addresses);
+        if addresses.is_empty() {
+            return self.respond(peer, channel, Err(ResponseError::DialRefused), "no dialable addresses")
+        }
+
+        self.served.push_back((peer.clone(), now));
+        let timeout = Delay::new(self.config.timeout);
+        self.ongoing_inbound.insert(peer.clone(), InboundProbe { addresses, channel, timeout });
+        self.pending_actions.push_back(NetworkBehaviourAction::DialPeer {
+            peer_id: peer,
+            condition: DialPeerCondition::Always,
+        });
+    }
+
+    /// Answers a dial request.
+    fn respond(
+        &mut self,
+        peer: PeerId,
+        channel: ResponseChannel<DialResponse>,
+        result: Result<Multiaddr, ResponseError>,
+        status_text: &str,
+    ) {
+        let response = DialResponse {
+            status_text: Some(status_text.to_owned()),
+            result: result.clone(),
+        };
+        self.inner.send_response(channel, response);
+        self.pending_actions.push_back(NetworkBehaviourAction::GenerateEvent(
+            AutoNatEvent::InboundProbe { peer, result }
+        ));
+    }
+
+    fn handle_inner_event(&mut self, event: RequestResponseEvent<DialRequest, DialResponse>) {
+        match event {
+            RequestResponseEvent::Message { peer, message: RequestResponseMessage::Request { request, channel } } => {
+                self.handle_dial_request(peer, request, channel);
+            }
+            RequestResponseEvent::Message { peer, message: RequestResponseMessage::Response { request_id, response } } => {
+                if self.ongoing_outbound.as_ref().map(|(id, _)| *id) == Some(request_id) {
+                    let result = response.result.map_err(OutboundProbeError::Response);
+                    self.handle_probe_result(peer, result);
+                }
+            }
+            RequestResponseEvent::OutboundFailure { peer, request_id, error } => {
+                if self.ongoing_outbound.as_ref().map(|(id, _)| *id) == Some(request_id) {
+                    self.handle_probe_result(peer, Err(OutboundProbeError::Failure(error)));
+                }
+            }
+            RequestResponseEvent::InboundFailure { peer, error } => {
+                log::debug!("Inbound dial request of {:?} failed: {:?}", peer, error);
+            }
+        }
+    }
+}
+
+impl Default for AutoNat {
+    fn default() -> Self {
+        AutoNat::new(AutoNatConfig::default())
+    }
+}
+
+/// Returns the IP address of an address, if any.
+fn ip_of(addr: &Multiaddr) -> Option<Protocol<'static>> {
+    match addr.iter().next()? {
+        p @ Protocol::Ip4(_) | p @ Protocol::Ip6(_) => Some(p.acquire()),
+        _ => None,
+    }
+}
+
+impl NetworkBehaviour for AutoNat {
+    type ProtocolsHandler = RequestResponseHandler<AutoNatCodec>;
+    type OutEvent = AutoNatEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        self.inner.new_handler()
+    }
+
+    fn addresses_of_peer(&mut self, peer: &PeerId) -> Vec<Multiaddr> {
+        // While dialing back a client, only the addresses requested by the
+        // client are dialed.
+        if let Some(probe) = self.ongoing_inbound.get(peer) {
+            return probe.addresses.clone()
+        }
+        self.inner.addresses_of_peer(peer)
+    }
+
+    fn inject_connected(&mut self, peer: &PeerId) {
+        self.inner.inject_connected(peer)
+    }
+
+    fn inject_disconnected(&mut self, peer: &PeerId) {
+        self.inner.inject_disconnected(peer);
+        // The response could not be delivered anymore.
+        if self.ongoing_inbound.remove(peer).is_some() {
+            log::debug!("Client {:?} disconnected before being dialed back.", peer);
+        }
+    }
+
+    fn inject_connection_established(&mut self, peer: &PeerId, conn: &ConnectionId, endpoint: &ConnectedPoint) {
+        self.inner.inject_connection_established(peer, conn, endpoint);
+        self.connected.entry(peer.clone())
+            .or_default()
+            .insert(*conn, endpoint.get_remote_address().clone());
+
+        if let ConnectedPoint::Dialer { address } = endpoint {
+            if let Some(probe) = self.ongoing_inbound.remove(peer) {
+                if probe.addresses.contains(address) {
+                    self.respond(peer.clone(), probe.channel, Ok(address.clone()), "dial successful");
+                } else {
+                    // The requested addresses are dialed first, so reaching
+                    // the client at another address means none of them
+                    // could be dialed.
+                    self.respond(peer.clone(), probe.channel, Err(ResponseError::DialError),
+                        "dialed back at an address not requested");
+                }
+            }
+        }
+    }
+
+    fn inject_address_change(&mut self, peer: &PeerId, conn: &ConnectionId, old: &ConnectedPoint, new: &ConnectedPoint) {
+        self.inner.inject_address_change(peer, conn, old, new);
+        if let Some(addr) = self.connected.get_mut(peer).and_then(|c| c.get_mut(conn)) {
+            *addr = new.get_remote_address().clone();
+        }
+    }
+
+    fn inject_connection_closed(&mut self, peer: &PeerId, conn: &ConnectionId, endpoint: &ConnectedPoint) {
+        self.inner.inject_connection_closed(peer, conn, endpoint);
+        if let Some(connections) = self.connected.get_mut(peer) {
+            connections.remove(conn);
+            if connections.is_empty() {
+                self.connected.remove(peer);
+            }
+        }
+    }
+
+    fn inject_event(&mut self, peer: PeerId, conn: ConnectionId, event: RequestResponseHandlerEvent<AutoNatCodec>) {
+        self.inner.inject_event(peer, conn, event)
+    }
+
+    fn inject_addr_reach_failure(&mut self, peer: Option<&PeerId>, addr: &Multiaddr, error: &dyn error::Error) {
+        self.inner.inject_addr_reach_failure(peer, addr, error)
+    }
+
+    fn inject_dial_failure(&mut self, peer: &PeerId) {
+        self.inner.inject_dial_failure(peer);
+        if let Some(probe) = self.ongoing_inbound.remove(peer) {
+            self.respond(peer.clone(), probe.channel, Err(ResponseError::DialError), "dial failed");
+        }
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>, params: &mut impl PollParameters)
+        -> Poll<NetworkBehaviourAction<RequestProtocol<AutoNatCodec>, AutoNatEvent>>
+    {
+        loop {
+            if let Some(action) = self.pending_actions.pop_front() {
+                return Poll::Ready(action)
+            }
+
+            match self.inner.poll(cx, params) {
+                Poll::Ready(NetworkBehaviourAction::GenerateEvent(event)) => {
+                    self.handle_inner_event(event);
+                    continue
+                }
+                Poll::Ready(NetworkBehaviourAction::DialAddress { address }) => {
+                    return Poll::Ready(NetworkBehaviourAction::DialAddress { address })
+                }
+                Poll::Ready(NetworkBehaviourAction::DialPeer { peer_id, condition }) => {
+                    return Poll::Ready(NetworkBehaviourAction::DialPeer { peer_id, condition })
+                }
+                Poll::Ready(NetworkBehaviourAction::NotifyHandler { peer_id, handler, event }) => {
+                    return Poll::Ready(NetworkBehaviourAction::NotifyHandler { peer_id, handler, event })
+                }
+                Poll::Ready(NetworkBehaviourAction::ReportObservedAddr { address }) => {
+                    return Poll::Ready(NetworkBehaviourAction::ReportObservedAddr { address })
+                }
+                Poll::Pending => {}
+            }
+
+            let timed_out = self.ongoing_inbound.iter_mut()
+                .filter_map(|(peer, probe)| match probe.timeout.poll_unpin(cx) {
+                    Poll::Ready(_) => Some(peer.clone()),
+                    Poll::Pending => None,
+                })
+                .collect::<Vec<_>>();
+            if !timed_out.is_empty() {
+                for peer in timed_out {
+                    let probe = self.ongoing_inbound.remove(&peer).expect("Found above.");
+                    self.respond(peer, probe.channel, Err(ResponseError::DialError), "dial timed out");
+                }
+                continue
+            }
+
+            if self.next_probe.poll_unpin(cx).is_ready() {
+                let delay = self.probe(params);
+                self.next_probe.reset(delay);
+                continue
+            }
+
+            return Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confidence_delays_status_changes() {
+        let mut config = AutoNatConfig::default();
+        config.set_confidence_max(2);
+        let mut autonat = AutoNat::new(config);
+        let addr: Multiaddr = "/ip4/8.8.8.8/tcp/30333".parse().unwrap();
+
+        autonat.handle_reported_status(NatStatus::Public(addr.clone()));
+        assert_eq!(autonat.nat_status(), &NatStatus::Public(addr.clone()));
+        assert_eq!(autonat.confidence(), 0);
+
+        autonat.handle_reported_status(NatStatus::Public(addr.clone()));
+        autonat.handle_reported_status(NatStatus::Public(addr.clone()));
+        autonat.handle_reported_status(NatStatus::Public(addr.clone()));
+        assert_eq!(autonat.confidence(), 2);
+
+        // Contradicting results first reduce the confidence.
+        autonat.handle_reported_status(NatStatus::Private);
+        autonat.handle_reported_status(NatStatus::Private);
+        assert_eq!(autonat.nat_status(), &NatStatus::Public(addr));
+        assert_eq!(autonat.confidence(), 0);
+
+        autonat.handle_reported_status(NatStatus::Private);
+        assert_eq!(autonat.nat_status(), &NatStatus::Private);
+    }
+}
@@ -0,0 +1,44 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Implementation of the [AutoNAT] protocol.
+//!
+//! AutoNAT allows a node to determine whether it is publicly reachable,
+//! i.e. whether it is behind a NAT or firewall, by asking other peers to
+//! dial it back on its candidate external addresses.
+//!
+//! # Usage
+//!
+//! The [`AutoNat`] struct implements a `NetworkBehaviour` that acts both
+//! as a client, probing servers and emitting [`AutoNatEvent::StatusChanged`]
+//! whenever the assumed [`NatStatus`] of the local node changes, and as a
+//! server, dialing back other peers on request.
+//!
+//! [AutoNAT]: https://github.com/libp2p/specs/tree/master/autonat
+
+pub use self::behaviour::{AutoNat, AutoNatConfig, AutoNatEvent, NatStatus, OutboundProbeError};
+pub use self::protocol::{DialRequest, DialResponse, ResponseError};
+
+mod behaviour;
+mod protocol;
+
+mod structs_proto {
+    include!(concat!(env!("OUT_DIR"), "/structs.rs"));
+}
@@ -0,0 +1,258 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::structs_proto::{self, message};
+use async_trait::async_trait;
+use futures::{prelude::*, io::{AsyncRead, AsyncWrite}};
+use libp2p_core::{Multiaddr, PeerId, upgrade::{read_one, write_with_len_prefix}};
+use libp2p_request_response::{ProtocolName, RequestResponseCodec};
+use prost::Message;
+use std::{convert::TryFrom, io};
+
+/// The maximum size of an AutoNAT message.
+const MAX_MESSAGE_SIZE: usize = 4096;
+
+/// The protocol name of AutoNAT.
+#[derive(Debug, Clone)]
+pub struct AutoNatProtocol;
+
+impl ProtocolName for AutoNatProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/libp2p/autonat/1.0.0"
+    }
+}
+
+/// The [`RequestResponseCodec`] of AutoNAT.
+#[derive(Debug, Clone, Default)]
+pub struct AutoNatCodec;
+
+#[async_trait]
+impl RequestResponseCodec for AutoNatCodec {
+    type Protocol = AutoNatProtocol;
+    type Request = DialRequest;
+    type Response = DialResponse;
+
+    async fn read_request<T>(&mut self, _: &AutoNatProtocol, io: &mut T)
+        -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send
+    {
+        let bytes = read_one(io, MAX_MESSAGE_SIZE)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            .await?;
+        DialRequest::from_bytes(&bytes)
+    }
+
+    async fn read_response<T>(&mut self, _: &AutoNatProtocol, io: &mut T)
+        -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send
+    {
+        let bytes = read_one(io, MAX_MESSAGE_SIZE)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            .await?;
+        DialResponse::from_bytes(&bytes)
+    }
+
+    async fn write_request<T>(&mut self, _: &AutoNatProtocol, io: &mut T, request: DialRequest)
+        -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send
+    {
+        write_with_len_prefix(io, request.into_bytes()).await
+    }
+
+    async fn write_response<T>(&mut self, _: &AutoNatProtocol, io: &mut T, response: DialResponse)
+        -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send
+    {
+        write_with_len_prefix(io, response.into_bytes()).await
+    }
+}
+
+/// A request to dial back the sender on the given addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialRequest {
+    /// The peer to dial back, i.e. the sender of the request.
+    pub peer_id: PeerId,
+    /// The candidate addresses to dial.
+    pub addresses: Vec<Multiaddr>,
+}
+
+impl DialRequest {
+    fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let msg = structs_proto::Message::decode(bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if msg.r#type != Some(message::MessageType::Dial.into()) {
+            return Err(invalid_data("expected dial message"))
+        }
+        let peer = msg.dial
+            .and_then(|dial| dial.peer)
+            .ok_or_else(|| invalid_data("missing peer info"))?;
+        let peer_id = peer.id
+            .and_then(|id| PeerId::from_bytes(id).ok())
+            .ok_or_else(|| invalid_data("invalid peer id"))?;
+        let addresses = peer.addrs.into_iter()
+            .filter_map(|a| match Multiaddr::try_from(a) {
+                Ok(a) => Some(a),
+                Err(e) => {
+                    log::debug!("Ignoring invalid address in dial request: {:?}", e);
+                    None
+                }
+            })
+            .collect();
+        Ok(DialRequest { peer_id, addresses })
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let msg = structs_proto::Message {
+            r#type: Some(message::MessageType::Dial.into()),
+            dial: Some(message::Dial {
+                peer: Some(message::PeerInfo {
+                    id: Some(self.peer_id.into_bytes()),
+                    addrs: self.addresses.into_iter().map(|a| a.to_vec()).collect(),
+                }),
+            }),
+            dial_response: None,
+        };
+        let mut bytes = Vec::with_capacity(msg.encoded_len());
+        msg.encode(&mut bytes).expect("Vec<u8> provides capacity as needed");
+        bytes
+    }
+}
+
+/// The reason for a failed dial-back, as reported by the remote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseError {
+    /// The remote dialed all addresses but could not reach any of them.
+    DialError,
+    /// The remote refused to dial the addresses, e.g. due to rate limiting.
+    DialRefused,
+    /// The request was malformed.
+    BadRequest,
+    /// The remote failed to process the request.
+    InternalError,
+}
+
+impl From<ResponseError> for message::ResponseStatus {
+    fn from(error: ResponseError) -> Self {
+        match error {
+            ResponseError::DialError => message::ResponseStatus::EDialError,
+            ResponseError::DialRefused => message::ResponseStatus::EDialRefused,
+            ResponseError::BadRequest => message::ResponseStatus::EBadRequest,
+            ResponseError::InternalError => message::ResponseStatus::EInternalError,
+        }
+    }
+}
+
+/// The response to a [`DialRequest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialResponse {
+    /// A human readable description of the result.
+    pub status_text: Option<String>,
+    /// The address the remote successfully dialed, or the reason of
+    /// the failure.
+    pub result: Result<Multiaddr, ResponseError>,
+}
+
+impl DialResponse {
+    fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let msg = structs_proto::Message::decode(bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if msg.r#type != Some(message::MessageType::DialResponse.into()) {
+            return Err(invalid_data("expected dial response message"))
+        }
+        let response = msg.dial_response
+            .ok_or_else(|| invalid_data("missing dial response"))?;
+        let status = response.status
+            .and_then(message::ResponseStatus::from_i32)
+            .ok_or_else(|| invalid_data("missing or unknown response status"))?;
+        let result = match status {
+            message::ResponseStatus::Ok => {
+                let addr = response.addr
+                    .and_then(|a| Multiaddr::try_from(a).ok())
+                    .ok_or_else(|| invalid_data("missing or invalid address"))?;
+                Ok(addr)
+            }
+            message::ResponseStatus::EDialError => Err(ResponseError::DialError),
+            message::ResponseStatus::EDialRefused => Err(ResponseError::DialRefused),
+            message::ResponseStatus::EBadRequest => Err(ResponseError::BadRequest),
+            message::ResponseStatus::EInternalError => Err(ResponseError::InternalError),
+        };
+        Ok(DialResponse { status_text: response.status_text, result })
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let (status, addr) = match self.result {
+            Ok(addr) => (message::ResponseStatus::Ok, Some(addr.to_vec())),
+            Err(error) => (error.into(), None),
+        };
+        let msg = structs_proto::Message {
+            r#type: Some(message::MessageType::DialResponse.into()),
+            dial: None,
+            dial_response: Some(message::DialResponse {
+                status: Some(status.into()),
+                status_text: self.status_text,
+                addr,
+            }),
+        };
+        let mut bytes = Vec::with_capacity(msg.encoded_len());
+        msg.encode(&mut bytes).expect("Vec<u8> provides capacity as needed");
+        bytes
+    }
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dial_request_roundtrip() {
+        let request = DialRequest {
+            peer_id: PeerId::random(),
+            addresses: vec![
+                "/ip4/8.8.8.8/tcp/30333".parse().unwrap(),
+                "/memory/1234".parse().unwrap(),
+            ],
+        };
+        let bytes = request.clone().into_bytes();
+        assert_eq!(DialRequest::from_bytes(&bytes).unwrap(), request);
+    }
+
+    #[test]
+    fn dial_response_roundtrip() {
+        let ok = DialResponse {
+            status_text: None,
+            result: Ok("/ip4/8.8.8.8/tcp/30333".parse().unwrap()),
+        };
+        assert_eq!(DialResponse::from_bytes(&ok.clone().into_bytes()).unwrap(), ok);
+
+        let err = DialResponse {
+            status_text: Some("dial refused".to_string()),
+            result: Err(ResponseError::DialRefused),
+        };
+        assert_eq!(DialResponse::from_bytes(&err.clone().into_bytes()).unwrap(), err);
+    }
+}
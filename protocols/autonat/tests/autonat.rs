@@ -0,0 +1,194 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::prelude::*;
+use libp2p_autonat::{AutoNat, AutoNatConfig, AutoNatEvent, NatStatus, OutboundProbeError, ResponseError};
+use libp2p_core::{
+    Multiaddr,
+    PeerId,
+    identity,
+    multiaddr::Protocol,
+    muxing::StreamMuxerBox,
+    transport::{MemoryTransport, Transport, boxed::Boxed},
+    upgrade
+};
+use libp2p_mplex::MplexConfig;
+use libp2p_plaintext::PlainText2Config;
+use libp2p_swarm::{AddressSource, Swarm, SwarmEvent};
+use libp2p_tcp::TcpConfig;
+use std::{io, net::TcpListener, time::Duration};
+
+#[test]
+fn reachable_client_is_public() {
+    let (server_id, server_addr) = spawn_server("/ip4/127.0.0.1/tcp/0".parse().unwrap());
+    let mut client = build_swarm();
+    Swarm::listen_on(&mut client, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+
+    async_std::task::block_on(async move {
+        let client_addr = loop {
+            if let SwarmEvent::NewListenAddr(addr) = client.next_event().await {
+                break addr
+            }
+        };
+        Swarm::add_external_address(&mut client, client_addr.clone());
+        client.add_server(server_id, Some(server_addr));
+
+        loop {
+            if let AutoNatEvent::StatusChanged { old, new } = client.next().await {
+                assert_eq!(old, NatStatus::Unknown);
+                assert_eq!(new, NatStatus::Public(client_addr));
+                break
+            }
+        }
+        assert!(client.nat_status().is_public());
+    });
+}
+
+#[test]
+fn client_without_observed_ip_is_refused() {
+    // The server cannot observe an IP address of a client connected via
+    // a memory (or relayed) connection, so it must not dial the addresses
+    // reported by the client.
+    let (server_id, server_addr) = spawn_server(Protocol::Memory(rand_port()).into());
+    let mut client = build_swarm();
+    Swarm::add_external_address(&mut client, unused_addr());
+    client.add_server(server_id, Some(server_addr));
+
+    async_std::task::block_on(async move {
+        loop {
+            match client.next().await {
+                AutoNatEvent::OutboundProbe { result, .. } => {
+                    match result {
+                        Err(OutboundProbeError::Response(ResponseError::DialRefused)) => break,
+                        other => panic!("Unexpected probe result: {:?}", other),
+                    }
+                }
+                AutoNatEvent::StatusChanged { .. } => panic!("Unexpected status change"),
+                _ => {}
+            }
+        }
+        assert_eq!(client.nat_status(), &NatStatus::Unknown);
+    });
+}
+
+#[test]
+fn unreachable_client_is_private() {
+    let (server_id, server_addr) = spawn_server("/ip4/127.0.0.1/tcp/0".parse().unwrap());
+    let mut client = build_swarm();
+    Swarm::listen_on(&mut client, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+    // An external address nobody is listening on.
+    Swarm::add_external_address(&mut client, unused_addr());
+    client.add_server(server_id, Some(server_addr));
+
+    async_std::task::block_on(async move {
+        loop {
+            if let AutoNatEvent::StatusChanged { old, new } = client.next().await {
+                assert_eq!(old, NatStatus::Unknown);
+                assert_eq!(new, NatStatus::Private);
+                break
+            }
+        }
+    });
+}
+
+#[test]
+fn dial_back_at_other_address_is_an_error() {
+    let mut client = build_swarm();
+    let client_id = Swarm::local_peer_id(&client).clone();
+    Swarm::listen_on(&mut client, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+    let client_addr = async_std::task::block_on(async {
+        loop {
+            if let SwarmEvent::NewListenAddr(addr) = client.next_event().await {
+                break addr
+            }
+        }
+    });
+    // The server knows the actual address of the client, which is dialed
+    // after the unreachable address the client asks to be dialed at.
+    let (server_id, server_addr) = spawn_server_with("/ip4/127.0.0.1/tcp/0".parse().unwrap(), |server| {
+        Swarm::peer_store_mut(server).add_address(&client_id, client_addr, AddressSource::Manual, None);
+    });
+    Swarm::add_external_address(&mut client, unused_addr());
+    client.add_server(server_id, Some(server_addr));
+
+    async_std::task::block_on(async move {
+        loop {
+            if let AutoNatEvent::OutboundProbe { result, .. } = client.next().await {
+                match result {
+                    Err(OutboundProbeError::Response(ResponseError::DialError)) => break,
+                    other => panic!("Unexpected probe result: {:?}", other),
+                }
+            }
+        }
+    });
+}
+
+/// Spawns a server listening on `addr`, returning its peer ID and actual
+/// listen address.
+fn spawn_server(addr: Multiaddr) -> (PeerId, Multiaddr) {
+    spawn_server_with(addr, |_| {})
+}
+
+/// Like [`spawn_server`], but lets `setup` configure the server first.
+fn spawn_server_with(addr: Multiaddr, setup: impl FnOnce(&mut Swarm<AutoNat>)) -> (PeerId, Multiaddr) {
+    let mut server = build_swarm();
+    setup(&mut server);
+    let server_id = Swarm::local_peer_id(&server).clone();
+    Swarm::listen_on(&mut server, addr).unwrap();
+    let server_addr = async_std::task::block_on(async {
+        loop {
+            if let SwarmEvent::NewListenAddr(addr) = server.next_event().await {
+                break addr
+            }
+        }
+    });
+    async_std::task::spawn(server.for_each(|_| future::ready(())));
+    (server_id, server_addr)
+}
+
+fn rand_port() -> u64 {
+    1 + rand::random::<u64>() % (u64::max_value() - 1)
+}
+
+/// Returns a local TCP address nobody is listening on.
+fn unused_addr() -> Multiaddr {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    Multiaddr::empty()
+        .with(Protocol::Ip4([127, 0, 0, 1].into()))
+        .with(Protocol::Tcp(port))
+}
+
+fn build_swarm() -> Swarm<AutoNat> {
+    let local_key = identity::Keypair::generate_ed25519();
+    let local_peer_id = local_key.public().into_peer_id();
+    let transport: Boxed<(PeerId, StreamMuxerBox), io::Error> = TcpConfig::new()
+        .or_transport(MemoryTransport::default())
+        .upgrade(upgrade::Version::V1)
+        .authenticate(PlainText2Config { local_public_key: local_key.public() })
+        .multiplex(MplexConfig::new())
+        .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        .boxed();
+    let mut config = AutoNatConfig::default();
+    config.set_boot_delay(Duration::from_millis(100))
+        .set_retry_interval(Duration::from_millis(500))
+        .set_throttle_server_period(Duration::from_secs(0));
+    Swarm::new(transport, AutoNat::new(config), local_peer_id)
+}
@@ -0,0 +1,357 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The [`Dcutr`] network behaviour.
+
+use crate::handler::{DcutrHandlerEvent, DcutrHandlerIn, Handler};
+use crate::protocol::ProtocolError;
+use libp2p_core::{ConnectedPoint, Multiaddr, PeerId, connection::ConnectionId, multiaddr::Protocol};
+use libp2p_swarm::{
+    DialPeerCondition,
+    NetworkBehaviour,
+    NetworkBehaviourAction,
+    NotifyHandler,
+    PollParameters,
+    ProtocolsHandlerUpgrErr
+};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    error,
+    fmt,
+    task::{Context, Poll}
+};
+
+/// The maximum number of attempts of the initiating peer to establish a
+/// direct connection before giving up.
+pub const MAX_NUMBER_OF_UPGRADE_ATTEMPTS: u8 = 3;
+
+/// The events produced by the [`Dcutr`] behaviour.
+#[derive(Debug)]
+pub enum DcutrEvent {
+    /// The local node initiated a direct connection upgrade with a peer it
+    /// is connected to via a relay.
+    InitiatedDirectConnectionUpgrade {
+        remote_peer_id: PeerId,
+    },
+    /// A peer connected to the local node via a relay initiated a direct
+    /// connection upgrade.
+    RemoteInitiatedDirectConnectionUpgrade {
+        remote_peer_id: PeerId,
+    },
+    /// A direct connection to the peer has been established.
+    DirectConnectionUpgradeSucceeded {
+        remote_peer_id: PeerId,
+        /// The ID of the direct connection.
+        connection_id: ConnectionId,
+        /// The endpoint of the direct connection.
+        endpoint: ConnectedPoint,
+    },
+    /// The direct connection upgrade with the peer failed.
+    DirectConnectionUpgradeFailed {
+        remote_peer_id: PeerId,
+        error: UpgradeError,
+    },
+}
+
+/// The reason a direct connection upgrade failed.
+#[derive(Debug)]
+pub enum UpgradeError {
+    /// Dialing the observed addresses of the remote failed, after
+    /// [`MAX_NUMBER_OF_UPGRADE_ATTEMPTS`] attempts if the local node
+    /// initiated the upgrade.
+    Dial,
+    /// Negotiating an upgrade initiated by the remote failed.
+    Inbound(ProtocolsHandlerUpgrErr<ProtocolError>),
+    /// Negotiating an upgrade initiated by the local node failed.
+    Outbound(ProtocolsHandlerUpgrErr<ProtocolError>),
+}
+
+impl fmt::Display for UpgradeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpgradeError::Dial => write!(f, "Failed to dial the observed addresses of the remote"),
+            UpgradeError::Inbound(err) => write!(f, "Inbound upgrade failed: {}", err),
+            UpgradeError::Outbound(err) => write!(f, "Outbound upgrade failed: {}", err),
+        }
+    }
+}
+
+impl error::Error for UpgradeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            UpgradeError::Dial => None,
+            UpgradeError::Inbound(err) => Some(err),
+            UpgradeError::Outbound(err) => Some(err),
+        }
+    }
+}
+
+/// A network behaviour that upgrades relayed connections to direct ones.
+///
+/// Whenever the local node accepts a relayed connection, it initiates an
+/// upgrade with the remote. The remote answers upgrades on any connection.
+/// See the [crate documentation](crate) for details.
+#[derive(Default)]
+pub struct Dcutr {
+    /// Actions to perform on the next call to `poll`.
+    queued_actions: VecDeque<ActionBuilder>,
+    /// The ongoing upgrades, by remote peer.
+    upgrades: HashMap<PeerId, Upgrade>,
+    /// The direct connections to each peer.
+    direct_connections: HashMap<PeerId, HashSet<ConnectionId>>,
+}
+
+/// An ongoing direct connection upgrade with a peer.
+struct Upgrade {
+    /// The relayed connection used to coordinate the upgrade.
+    relayed_connection: ConnectionId,
+    /// Whether the local node initiated the upgrade.
+    initiator: bool,
+    /// The current attempt, starting at 1.
+    attempt: u8,
+    /// The observed addresses of the remote, once negotiated. These are
+    /// dialed by the local node.
+    remote_addrs: Vec<Multiaddr>,
+}
+
+/// An action whose construction requires the [`PollParameters`], i.e. the
+/// external addresses of the local node.
+enum ActionBuilder {
+    Connect {
+        peer_id: PeerId,
+        connection: ConnectionId,
+    },
+    AcceptInboundConnect {
+        peer_id: PeerId,
+        connection: ConnectionId,
+    },
+    Done(NetworkBehaviourAction<DcutrHandlerIn, DcutrEvent>),
+}
+
+impl Dcutr {
+    /// Creates a new `Dcutr` behaviour.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Initiates a direct connection upgrade with `peer_id` via the given
+    /// relayed connection.
+    fn connect(&mut self, peer_id: PeerId, connection: ConnectionId) {
+        self.queued_actions.push_back(ActionBuilder::Connect { peer_id, connection });
+    }
+
+    fn generate_event(&mut self, event: DcutrEvent) {
+        self.queued_actions.push_back(ActionBuilder::Done(NetworkBehaviourAction::GenerateEvent(event)));
+    }
+
+    /// Handles the establishment of a direct connection to `peer_id`, either
+    /// a new one or an existing one that ceased to be relayed.
+    fn on_direct_connection(&mut self, peer_id: &PeerId, connection: &ConnectionId, endpoint: &ConnectedPoint) {
+        self.direct_connections.entry(peer_id.clone()).or_default().insert(*connection);
+        if self.upgrades.remove(peer_id).is_some() {
+            self.generate_event(DcutrEvent::DirectConnectionUpgradeSucceeded {
+                remote_peer_id: peer_id.clone(),
+                connection_id: *connection,
+                endpoint: endpoint.clone(),
+            });
+        }
+    }
+
+    /// Dials the negotiated observed addresses of `peer_id`.
+    fn dial(&mut self, peer_id: PeerId, remote_addrs: Vec<Multiaddr>) {
+        if let Some(upgrade) = self.upgrades.get_mut(&peer_id) {
+            upgrade.remote_addrs = remote_addrs;
+            self.queued_actions.push_back(ActionBuilder::Done(NetworkBehaviourAction::DialPeer {
+                peer_id,
+                condition: DialPeerCondition::Always,
+            }));
+        }
+    }
+}
+
+impl NetworkBehaviour for Dcutr {
+    type ProtocolsHandler = Handler;
+    type OutEvent = DcutrEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        Handler::new()
+    }
+
+    fn addresses_of_peer(&mut self, peer: &PeerId) -> Vec<Multiaddr> {
+        self.upgrades.get(peer)
+            .map(|u| u.remote_addrs.clone())
+            .unwrap_or_default()
+    }
+
+    fn inject_connected(&mut self, _: &PeerId) {
+    }
+
+    fn inject_disconnected(&mut self, peer: &PeerId) {
+        self.upgrades.remove(peer);
+    }
+
+    fn inject_connection_established(&mut self, peer: &PeerId, conn: &ConnectionId, endpoint: &ConnectedPoint) {
+        if !endpoint.is_relayed() {
+            self.on_direct_connection(peer, conn, endpoint);
+            return
+        }
+
+        // The peer accepting the relayed connection initiates the upgrade,
+        // unless there is a direct connection or an upgrade already.
+        if endpoint.is_listener()
+            && !self.direct_connections.contains_key(peer)
+            && !self.upgrades.contains_key(peer)
+        {
+            self.upgrades.insert(peer.clone(), Upgrade {
+                relayed_connection: *conn,
+                initiator: true,
+                attempt: 1,
+                remote_addrs: Vec::new(),
+            });
+            self.connect(peer.clone(), *conn);
+            self.generate_event(DcutrEvent::InitiatedDirectConnectionUpgrade {
+                remote_peer_id: peer.clone(),
+            });
+        }
+    }
+
+    fn inject_address_change(&mut self, peer: &PeerId, conn: &ConnectionId, old: &ConnectedPoint, new: &ConnectedPoint) {
+        if old.is_relayed() && !new.is_relayed() {
+            self.on_direct_connection(peer, conn, new);
+        }
+    }
+
+    fn inject_connection_closed(&mut self, peer: &PeerId, conn: &ConnectionId, endpoint: &ConnectedPoint) {
+        if !endpoint.is_relayed() {
+            if let Some(connections) = self.direct_connections.get_mut(peer) {
+                connections.remove(conn);
+                if connections.is_empty() {
+                    self.direct_connections.remove(peer);
+                }
+            }
+            return
+        }
+
+        // An upgrade that is still being negotiated cannot proceed without
+        // its relayed connection.
+        let negotiating = self.upgrades.get(peer)
+            .map_or(false, |u| u.relayed_connection == *conn && u.remote_addrs.is_empty());
+        if negotiating {
+            self.upgrades.remove(peer);
+        }
+    }
+
+    fn inject_event(&mut self, peer: PeerId, conn: ConnectionId, event: DcutrHandlerEvent) {
+        match event {
+            DcutrHandlerEvent::InboundConnectRequest { .. } => {
+                self.upgrades.insert(peer.clone(), Upgrade {
+                    relayed_connection: conn,
+                    initiator: false,
+                    attempt: 1,
+                    remote_addrs: Vec::new(),
+                });
+                self.queued_actions.push_back(ActionBuilder::AcceptInboundConnect {
+                    peer_id: peer.clone(),
+                    connection: conn,
+                });
+                self.generate_event(DcutrEvent::RemoteInitiatedDirectConnectionUpgrade {
+                    remote_peer_id: peer,
+                });
+            }
+            DcutrHandlerEvent::InboundConnectNegotiated { remote_addrs } |
+            DcutrHandlerEvent::OutboundConnectNegotiated { remote_addrs } => {
+                self.dial(peer, remote_addrs);
+            }
+            DcutrHandlerEvent::InboundNegotiationFailed { error } => {
+                if self.upgrades.remove(&peer).is_some() {
+                    self.generate_event(DcutrEvent::DirectConnectionUpgradeFailed {
+                        remote_peer_id: peer,
+                        error: UpgradeError::Inbound(error),
+                    });
+                }
+            }
+            DcutrHandlerEvent::OutboundNegotiationFailed { error } => {
+                if self.upgrades.remove(&peer).is_some() {
+                    self.generate_event(DcutrEvent::DirectConnectionUpgradeFailed {
+                        remote_peer_id: peer,
+                        error: UpgradeError::Outbound(error),
+                    });
+                }
+            }
+        }
+    }
+
+    fn inject_dial_failure(&mut self, peer: &PeerId) {
+        let upgrade = match self.upgrades.get_mut(peer) {
+            Some(upgrade) if !upgrade.remote_addrs.is_empty() => upgrade,
+            _ => return,
+        };
+
+        if upgrade.initiator && upgrade.attempt < MAX_NUMBER_OF_UPGRADE_ATTEMPTS {
+            upgrade.attempt += 1;
+            upgrade.remote_addrs.clear();
+            let connection = upgrade.relayed_connection;
+            log::debug!("Direct connection upgrade attempt {} with {:?}.", upgrade.attempt, peer);
+            self.connect(peer.clone(), connection);
+            return
+        }
+
+        self.upgrades.remove(peer);
+        self.generate_event(DcutrEvent::DirectConnectionUpgradeFailed {
+            remote_peer_id: peer.clone(),
+            error: UpgradeError::Dial,
+        });
+    }
+
+    fn poll(&mut self, _: &mut Context<'_>, params: &mut impl PollParameters)
+        -> Poll<NetworkBehaviourAction<DcutrHandlerIn, DcutrEvent>>
+    {
+        if let Some(action) = self.queued_actions.pop_front() {
+            let action = match action {
+                ActionBuilder::Connect { peer_id, connection } => {
+                    NetworkBehaviourAction::NotifyHandler {
+                        peer_id,
+                        handler: NotifyHandler::One(connection),
+                        event: DcutrHandlerIn::Connect { obs_addrs: observed_addresses(params) },
+                    }
+                }
+                ActionBuilder::AcceptInboundConnect { peer_id, connection } => {
+                    NetworkBehaviourAction::NotifyHandler {
+                        peer_id,
+                        handler: NotifyHandler::One(connection),
+                        event: DcutrHandlerIn::AcceptInboundConnect { obs_addrs: observed_addresses(params) },
+                    }
+                }
+                ActionBuilder::Done(action) => action,
+            };
+            return Poll::Ready(action)
+        }
+
+        Poll::Pending
+    }
+}
+
+/// The external addresses of the local node that are usable for a direct
+/// connection, i.e. that are not relayed.
+fn observed_addresses(params: &impl PollParameters) -> Vec<Multiaddr> {
+    params.external_addresses()
+        .filter(|a| !a.iter().any(|p| p == Protocol::P2pCircuit))
+        .collect()
+}
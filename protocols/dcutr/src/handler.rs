@@ -0,0 +1,235 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The [`ProtocolsHandler`] of the [`Dcutr`](crate::Dcutr) behaviour.
+
+use crate::protocol::{inbound, outbound, ProtocolError};
+use futures::{future::BoxFuture, prelude::*};
+use libp2p_core::{Multiaddr, upgrade::UpgradeError};
+use libp2p_swarm::{
+    KeepAlive,
+    ProtocolsHandler,
+    ProtocolsHandlerEvent,
+    ProtocolsHandlerUpgrErr,
+    SubstreamProtocol
+};
+use std::{
+    collections::VecDeque,
+    task::{Context, Poll},
+    time::Duration
+};
+use wasm_timer::{Instant, TryFutureExt};
+
+/// The time an idle connection, i.e. one without an ongoing upgrade, is
+/// kept alive.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The time the remote is given to send the `SYNC` message after an inbound
+/// `CONNECT` request has been answered.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Event sent from the [`Dcutr`](crate::Dcutr) behaviour to a [`Handler`].
+#[derive(Debug, Clone)]
+pub enum DcutrHandlerIn {
+    /// Initiate a direct connection upgrade, advertising the given observed
+    /// addresses of the local node.
+    Connect {
+        obs_addrs: Vec<Multiaddr>,
+    },
+    /// Answer the pending inbound `CONNECT` request with the given observed
+    /// addresses of the local node.
+    AcceptInboundConnect {
+        obs_addrs: Vec<Multiaddr>,
+    },
+}
+
+/// Event produced by a [`Handler`] for the [`Dcutr`](crate::Dcutr) behaviour.
+#[derive(Debug)]
+pub enum DcutrHandlerEvent {
+    /// The remote initiated a direct connection upgrade. The request must be
+    /// answered with [`DcutrHandlerIn::AcceptInboundConnect`].
+    InboundConnectRequest {
+        /// The observed addresses of the remote.
+        remote_addrs: Vec<Multiaddr>,
+    },
+    /// An inbound direct connection upgrade has been negotiated and the
+    /// remote is to be dialed now.
+    InboundConnectNegotiated {
+        /// The observed addresses of the remote.
+        remote_addrs: Vec<Multiaddr>,
+    },
+    /// An inbound direct connection upgrade failed.
+    InboundNegotiationFailed {
+        error: ProtocolsHandlerUpgrErr<ProtocolError>,
+    },
+    /// An outbound direct connection upgrade has been negotiated and the
+    /// remote is to be dialed now.
+    OutboundConnectNegotiated {
+        /// The observed addresses of the remote.
+        remote_addrs: Vec<Multiaddr>,
+    },
+    /// An outbound direct connection upgrade failed.
+    OutboundNegotiationFailed {
+        error: ProtocolsHandlerUpgrErr<ProtocolError>,
+    },
+}
+
+/// Protocol handler of the direct connection upgrade for a single connection.
+pub struct Handler {
+    /// Events to yield on the next call to `poll`.
+    queued_events: VecDeque<ProtocolsHandlerEvent<
+        outbound::Upgrade,
+        (),
+        DcutrHandlerEvent,
+        void::Void,
+    >>,
+    /// An inbound `CONNECT` request awaiting the local addresses.
+    pending_connect: Option<inbound::PendingConnect>,
+    /// An accepted inbound `CONNECT` request awaiting the `SYNC` message.
+    inbound_connect: Option<BoxFuture<'static, Result<Vec<Multiaddr>, ProtocolError>>>,
+    /// Whether an outbound upgrade is in progress.
+    outbound_connect: bool,
+    /// The moment this connection is considered idle.
+    idle_deadline: Instant,
+}
+
+impl Handler {
+    pub(crate) fn new() -> Self {
+        Handler {
+            queued_events: VecDeque::new(),
+            pending_connect: None,
+            inbound_connect: None,
+            outbound_connect: false,
+            idle_deadline: Instant::now() + IDLE_TIMEOUT,
+        }
+    }
+}
+
+impl ProtocolsHandler for Handler {
+    type InEvent = DcutrHandlerIn;
+    type OutEvent = DcutrHandlerEvent;
+    type Error = void::Void;
+    type InboundProtocol = inbound::Upgrade;
+    type OutboundProtocol = outbound::Upgrade;
+    type OutboundOpenInfo = ();
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol> {
+        SubstreamProtocol::new(inbound::Upgrade)
+    }
+
+    fn inject_fully_negotiated_inbound(&mut self, pending: inbound::PendingConnect) {
+        self.idle_deadline = Instant::now() + IDLE_TIMEOUT;
+        if self.pending_connect.is_some() || self.inbound_connect.is_some() {
+            log::debug!("Dropping inbound direct connection upgrade superseded by a new request.");
+        }
+        self.inbound_connect = None;
+        self.queued_events.push_back(ProtocolsHandlerEvent::Custom(
+            DcutrHandlerEvent::InboundConnectRequest {
+                remote_addrs: pending.remote_addrs().to_vec(),
+            }
+        ));
+        self.pending_connect = Some(pending);
+    }
+
+    fn inject_fully_negotiated_outbound(&mut self, remote_addrs: Vec<Multiaddr>, _: ()) {
+        self.idle_deadline = Instant::now() + IDLE_TIMEOUT;
+        self.outbound_connect = false;
+        self.queued_events.push_back(ProtocolsHandlerEvent::Custom(
+            DcutrHandlerEvent::OutboundConnectNegotiated { remote_addrs }
+        ));
+    }
+
+    fn inject_event(&mut self, event: DcutrHandlerIn) {
+        self.idle_deadline = Instant::now() + IDLE_TIMEOUT;
+        match event {
+            DcutrHandlerIn::Connect { obs_addrs } => {
+                self.outbound_connect = true;
+                self.queued_events.push_back(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+                    protocol: SubstreamProtocol::new(outbound::Upgrade::new(obs_addrs)),
+                    info: (),
+                });
+            }
+            DcutrHandlerIn::AcceptInboundConnect { obs_addrs } => {
+                if let Some(pending) = self.pending_connect.take() {
+                    self.inbound_connect = Some(
+                        pending.accept(obs_addrs).timeout(SYNC_TIMEOUT).boxed()
+                    );
+                }
+            }
+        }
+    }
+
+    fn inject_dial_upgrade_error(&mut self, _: (), error: ProtocolsHandlerUpgrErr<ProtocolError>) {
+        self.outbound_connect = false;
+        self.queued_events.push_back(ProtocolsHandlerEvent::Custom(
+            DcutrHandlerEvent::OutboundNegotiationFailed { error }
+        ));
+    }
+
+    fn inject_listen_upgrade_error(&mut self, error: ProtocolsHandlerUpgrErr<ProtocolError>) {
+        self.queued_events.push_back(ProtocolsHandlerEvent::Custom(
+            DcutrHandlerEvent::InboundNegotiationFailed { error }
+        ));
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        if self.outbound_connect || self.pending_connect.is_some() || self.inbound_connect.is_some() {
+            return KeepAlive::Yes
+        }
+        KeepAlive::Until(self.idle_deadline)
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<
+        ProtocolsHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::OutEvent, Self::Error>
+    > {
+        if let Some(event) = self.queued_events.pop_front() {
+            return Poll::Ready(event)
+        }
+
+        if let Some(inbound_connect) = &mut self.inbound_connect {
+            if let Poll::Ready(result) = inbound_connect.poll_unpin(cx) {
+                self.inbound_connect = None;
+                self.idle_deadline = Instant::now() + IDLE_TIMEOUT;
+                let event = match result {
+                    Ok(remote_addrs) => DcutrHandlerEvent::InboundConnectNegotiated { remote_addrs },
+                    Err(error) => DcutrHandlerEvent::InboundNegotiationFailed {
+                        error: ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Apply(error)),
+                    },
+                };
+                return Poll::Ready(ProtocolsHandlerEvent::Custom(event))
+            }
+        }
+
+        Poll::Pending
+    }
+
+    fn poll_close(&mut self, _: &mut Context<'_>) -> Poll<()> {
+        // Let the ongoing upgrades complete, so that the remote is not left
+        // waiting for an answer or the `SYNC` message.
+        let upgrading = self.outbound_connect
+            || self.pending_connect.is_some()
+            || self.inbound_connect.is_some();
+        if self.queued_events.is_empty() && !upgrading {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,49 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Implementation of the [DCUtR] protocol, the *direct connection upgrade
+//! through relay*.
+//!
+//! Two peers that are both behind a NAT or firewall can only reach each other
+//! through a third party, e.g. a relay. Once such an indirect connection is
+//! established, the [`Dcutr`] behaviour uses it to exchange the observed
+//! (i.e. external) addresses of both peers and to measure the round trip
+//! time between them. Both peers then dial each other at the same time,
+//! which allows a direct connection to be established through the NATs by
+//! means of a simultaneous TCP open. For TCP, the transport needs to reuse
+//! the listening port for outgoing connections, see the `port_reuse` option
+//! of `libp2p-tcp`.
+//!
+//! The outcome of the upgrade is reported as a [`DcutrEvent`], which on
+//! success includes the ID and endpoint of the new direct connection. The
+//! relayed connection is left untouched.
+//!
+//! [DCUtR]: https://github.com/libp2p/specs/blob/master/relay/DCUtR.md
+
+mod behaviour;
+
+pub mod handler;
+pub mod protocol;
+
+pub use behaviour::{Dcutr, DcutrEvent, UpgradeError, MAX_NUMBER_OF_UPGRADE_ATTEMPTS};
+
+mod message_proto {
+    include!(concat!(env!("OUT_DIR"), "/holepunch.pb.rs"));
+}
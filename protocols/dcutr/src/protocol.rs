@@ -0,0 +1,171 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Wire protocol of the direct connection upgrade through relay.
+//!
+//! The peer that accepted the relayed connection initiates the upgrade by
+//! opening an outbound substream, see [`outbound`]. The remote answers on
+//! the corresponding inbound substream, see [`inbound`].
+
+use crate::message_proto::{self, hole_punch};
+use futures::prelude::*;
+use libp2p_core::{Multiaddr, multiaddr::Protocol, upgrade};
+use prost::Message;
+use std::{convert::TryFrom, error, fmt, io};
+
+pub mod inbound;
+pub mod outbound;
+
+/// Protocol name of the direct connection upgrade.
+pub const PROTOCOL_NAME: &[u8] = b"/libp2p/dcutr";
+
+/// Maximum size of a single message.
+const MAX_MESSAGE_SIZE: usize = 4096;
+
+/// Error while negotiating the direct connection upgrade on a substream.
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// Reading from or writing to the substream failed.
+    Io(io::Error),
+    /// A message could not be decoded.
+    Decode(prost::DecodeError),
+    /// A message was decoded but is not valid in the current context.
+    InvalidMessage(&'static str),
+    /// The remote did not send any usable observed address.
+    NoAddresses,
+}
+
+impl From<io::Error> for ProtocolError {
+    fn from(err: io::Error) -> Self {
+        ProtocolError::Io(err)
+    }
+}
+
+impl From<upgrade::ReadOneError> for ProtocolError {
+    fn from(err: upgrade::ReadOneError) -> Self {
+        match err {
+            upgrade::ReadOneError::Io(err) => ProtocolError::Io(err),
+            upgrade::ReadOneError::TooLarge { .. } =>
+                ProtocolError::InvalidMessage("message exceeds maximum size"),
+        }
+    }
+}
+
+impl From<prost::DecodeError> for ProtocolError {
+    fn from(err: prost::DecodeError) -> Self {
+        ProtocolError::Decode(err)
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Io(err) => write!(f, "I/O error: {}", err),
+            ProtocolError::Decode(err) => write!(f, "Failed to decode message: {}", err),
+            ProtocolError::InvalidMessage(msg) => write!(f, "Invalid message: {}", msg),
+            ProtocolError::NoAddresses => write!(f, "No observed addresses"),
+        }
+    }
+}
+
+impl error::Error for ProtocolError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ProtocolError::Io(err) => Some(err),
+            ProtocolError::Decode(err) => Some(err),
+            ProtocolError::InvalidMessage(_) | ProtocolError::NoAddresses => None,
+        }
+    }
+}
+
+/// Encodes a `CONNECT` message carrying the given observed addresses.
+fn connect_message(obs_addrs: Vec<Multiaddr>) -> message_proto::HolePunch {
+    message_proto::HolePunch {
+        r#type: hole_punch::Type::Connect.into(),
+        obs_addrs: obs_addrs.into_iter().map(|a| a.to_vec()).collect(),
+    }
+}
+
+/// Encodes a `SYNC` message.
+fn sync_message() -> message_proto::HolePunch {
+    message_proto::HolePunch {
+        r#type: hole_punch::Type::Sync.into(),
+        obs_addrs: Vec::new(),
+    }
+}
+
+/// Writes a single length-prefixed protobuf message to the substream,
+/// flushing it afterwards.
+async fn send_message<S>(substream: &mut S, msg: message_proto::HolePunch) -> Result<(), io::Error>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut bytes = Vec::with_capacity(msg.encoded_len());
+    msg.encode(&mut bytes).expect("Vec<u8> provides capacity as needed");
+    upgrade::write_with_len_prefix(substream, bytes).await
+}
+
+/// Reads a single length-prefixed protobuf message of the expected type
+/// from the substream.
+async fn recv_message<S>(substream: &mut S, expected: hole_punch::Type)
+    -> Result<message_proto::HolePunch, ProtocolError>
+where
+    S: AsyncRead + Unpin,
+{
+    let bytes = upgrade::read_one(substream, MAX_MESSAGE_SIZE).await?;
+    let msg = message_proto::HolePunch::decode(bytes.as_slice())?;
+    if hole_punch::Type::from_i32(msg.r#type) != Some(expected) {
+        return Err(ProtocolError::InvalidMessage("unexpected message type"))
+    }
+    Ok(msg)
+}
+
+/// Parses the observed addresses of a `CONNECT` message.
+///
+/// Invalid and relayed addresses are skipped, since neither can be used
+/// for a direct connection.
+fn parse_obs_addrs(addrs: Vec<Vec<u8>>) -> Result<Vec<Multiaddr>, ProtocolError> {
+    let addrs = addrs.into_iter()
+        .filter_map(|a| Multiaddr::try_from(a).ok())
+        .filter(|a| !a.iter().any(|p| p == Protocol::P2pCircuit))
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        return Err(ProtocolError::NoAddresses)
+    }
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relayed_and_invalid_addrs_are_skipped() {
+        let direct: Multiaddr = "/ip4/1.2.3.4/tcp/4001".parse().unwrap();
+        let relayed: Multiaddr = "/ip4/5.6.7.8/tcp/4001/p2p-circuit".parse().unwrap();
+        let addrs = vec![direct.to_vec(), relayed.to_vec(), vec![0xff, 0xff]];
+        assert_eq!(parse_obs_addrs(addrs).unwrap(), vec![direct]);
+
+        match parse_obs_addrs(vec![relayed.to_vec()]) {
+            Err(ProtocolError::NoAddresses) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
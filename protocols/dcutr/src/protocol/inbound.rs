@@ -0,0 +1,84 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The direct connection upgrade as seen by the peer that dialed the
+//! relayed connection, i.e. for inbound requests.
+
+use crate::message_proto::hole_punch;
+use crate::protocol::{
+    PROTOCOL_NAME, ProtocolError, connect_message, parse_obs_addrs, recv_message, send_message
+};
+use futures::{future::BoxFuture, prelude::*};
+use libp2p_core::{Multiaddr, upgrade};
+use libp2p_swarm::NegotiatedSubstream;
+use std::iter;
+
+/// Upgrade for an inbound substream, reading the `CONNECT` message of the
+/// remote.
+#[derive(Debug, Clone)]
+pub struct Upgrade;
+
+impl upgrade::UpgradeInfo for Upgrade {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(PROTOCOL_NAME)
+    }
+}
+
+impl upgrade::InboundUpgrade<NegotiatedSubstream> for Upgrade {
+    type Output = PendingConnect;
+    type Error = ProtocolError;
+    type Future = BoxFuture<'static, Result<PendingConnect, ProtocolError>>;
+
+    fn upgrade_inbound(self, mut substream: NegotiatedSubstream, _: Self::Info) -> Self::Future {
+        async move {
+            let msg = recv_message(&mut substream, hole_punch::Type::Connect).await?;
+            let remote_addrs = parse_obs_addrs(msg.obs_addrs)?;
+            Ok(PendingConnect { substream, remote_addrs })
+        }.boxed()
+    }
+}
+
+/// A `CONNECT` request of the remote that awaits the observed addresses
+/// of the local node.
+pub struct PendingConnect {
+    substream: NegotiatedSubstream,
+    remote_addrs: Vec<Multiaddr>,
+}
+
+impl PendingConnect {
+    /// The observed addresses of the remote.
+    pub fn remote_addrs(&self) -> &[Multiaddr] {
+        &self.remote_addrs
+    }
+
+    /// Answers the request with the observed addresses of the local node
+    /// and waits for the `SYNC` message of the remote.
+    ///
+    /// Once the returned future resolves, the local node is expected to dial
+    /// the returned observed addresses of the remote immediately.
+    pub async fn accept(mut self, local_addrs: Vec<Multiaddr>) -> Result<Vec<Multiaddr>, ProtocolError> {
+        send_message(&mut self.substream, connect_message(local_addrs)).await?;
+        recv_message(&mut self.substream, hole_punch::Type::Sync).await?;
+        Ok(self.remote_addrs)
+    }
+}
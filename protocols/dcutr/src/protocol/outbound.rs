@@ -0,0 +1,84 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The direct connection upgrade as seen by the peer that accepted the
+//! relayed connection, i.e. for outbound requests.
+
+use crate::message_proto::hole_punch;
+use crate::protocol::{
+    PROTOCOL_NAME, ProtocolError, connect_message, parse_obs_addrs, recv_message, send_message,
+    sync_message
+};
+use futures::{future::BoxFuture, prelude::*};
+use libp2p_core::{Multiaddr, upgrade};
+use libp2p_swarm::NegotiatedSubstream;
+use std::iter;
+use wasm_timer::{Delay, Instant};
+
+/// Upgrade for an outbound substream, exchanging the observed addresses
+/// with the remote and synchronising the subsequent dials.
+///
+/// The upgrade measures the round trip time of the `CONNECT` exchange. After
+/// sending the `SYNC` message, it waits for half that time, i.e. until the
+/// remote is expected to have received the `SYNC` message and started to dial,
+/// before yielding the observed addresses of the remote.
+#[derive(Debug, Clone)]
+pub struct Upgrade {
+    obs_addrs: Vec<Multiaddr>,
+}
+
+impl Upgrade {
+    /// Creates a new upgrade advertising the given observed addresses of the
+    /// local node.
+    pub fn new(obs_addrs: Vec<Multiaddr>) -> Self {
+        Upgrade { obs_addrs }
+    }
+}
+
+impl upgrade::UpgradeInfo for Upgrade {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(PROTOCOL_NAME)
+    }
+}
+
+impl upgrade::OutboundUpgrade<NegotiatedSubstream> for Upgrade {
+    type Output = Vec<Multiaddr>;
+    type Error = ProtocolError;
+    type Future = BoxFuture<'static, Result<Vec<Multiaddr>, ProtocolError>>;
+
+    fn upgrade_outbound(self, mut substream: NegotiatedSubstream, _: Self::Info) -> Self::Future {
+        async move {
+            send_message(&mut substream, connect_message(self.obs_addrs)).await?;
+            let sent = Instant::now();
+
+            let msg = recv_message(&mut substream, hole_punch::Type::Connect).await?;
+            let rtt = sent.elapsed();
+            let remote_addrs = parse_obs_addrs(msg.obs_addrs)?;
+
+            send_message(&mut substream, sync_message()).await?;
+            Delay::new(rtt / 2).await?;
+
+            Ok(remote_addrs)
+        }.boxed()
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Integration tests for the direct connection upgrade through relay.
+
+use futures::{channel::mpsc, future, prelude::*};
+use libp2p::NetworkBehaviour;
+use libp2p::core::{
+    Multiaddr,
+    PeerId,
+    identity,
+    multiaddr::Protocol,
+    muxing::StreamMuxerBox,
+    transport::{MemoryTransport, Transport, boxed::Boxed},
+    upgrade
+};
+use libp2p::mplex::MplexConfig;
+use libp2p::plaintext::PlainText2Config;
+use libp2p::relay::{Client, ClientEvent, Relay, RelayConfig};
+use libp2p::swarm::{Swarm, SwarmEvent};
+use libp2p_dcutr::{Dcutr, DcutrEvent};
+use std::io;
+
+#[test]
+fn connect() {
+    let relay_addr: Multiaddr = Protocol::Memory(rand_port()).into();
+    let mut relay = build_relay();
+    let relay_peer_id = Swarm::local_peer_id(&relay).clone();
+    Swarm::listen_on(&mut relay, relay_addr.clone()).unwrap();
+
+    let mut listener = build_client();
+    let listener_peer_id = Swarm::local_peer_id(&listener).clone();
+    let listener_addr: Multiaddr = Protocol::Memory(rand_port()).into();
+    Swarm::listen_on(&mut listener, listener_addr.clone()).unwrap();
+    Swarm::add_external_address(&mut listener, listener_addr);
+    let circuit_addr = relay_addr
+        .with(Protocol::P2p(relay_peer_id.into()))
+        .with(Protocol::P2pCircuit);
+    Swarm::listen_on(&mut listener, circuit_addr.clone()).unwrap();
+
+    let mut dialer = build_client();
+    let dialer_peer_id = Swarm::local_peer_id(&dialer).clone();
+    let dialer_addr: Multiaddr = Protocol::Memory(rand_port()).into();
+    Swarm::listen_on(&mut dialer, dialer_addr.clone()).unwrap();
+    Swarm::add_external_address(&mut dialer, dialer_addr);
+
+    async_std::task::block_on(async move {
+        async_std::task::spawn(relay.for_each(|_| future::ready(())));
+
+        // Wait for the reservation to be accepted.
+        loop {
+            match listener.next_event().await {
+                SwarmEvent::NewListenAddr(addr) if addr == circuit_addr => break,
+                SwarmEvent::Behaviour(Event::Client(ClientEvent::ReservationReqFailed { .. })) => {
+                    panic!("Reservation failed")
+                }
+                _ => {}
+            }
+        }
+
+        let (mut tx, mut rx) = mpsc::channel(1);
+        async_std::task::spawn(async move {
+            loop {
+                if let SwarmEvent::Behaviour(Event::Dcutr(event)) = listener.next_event().await {
+                    tx.send(event).await.unwrap();
+                }
+            }
+        });
+
+        Swarm::dial_addr(&mut dialer, circuit_addr.with(Protocol::P2p(listener_peer_id.clone().into())))
+            .unwrap();
+
+        loop {
+            match dialer.next_event().await {
+                SwarmEvent::Behaviour(Event::Dcutr(
+                    DcutrEvent::DirectConnectionUpgradeSucceeded { remote_peer_id, endpoint, .. }
+                )) => {
+                    assert_eq!(remote_peer_id, listener_peer_id);
+                    assert!(!endpoint.is_relayed());
+                    break
+                }
+                SwarmEvent::Behaviour(Event::Dcutr(
+                    DcutrEvent::DirectConnectionUpgradeFailed { error, .. }
+                )) => panic!("Upgrade failed: {:?}", error),
+                _ => {}
+            }
+        }
+
+        // The listener initiated the upgrade on the relayed connection.
+        async_std::task::spawn(dialer.for_each(|_| future::ready(())));
+        match rx.next().await.unwrap() {
+            DcutrEvent::InitiatedDirectConnectionUpgrade { remote_peer_id } => {
+                assert_eq!(remote_peer_id, dialer_peer_id);
+            }
+            e => panic!("Unexpected event: {:?}", e),
+        }
+        match rx.next().await.unwrap() {
+            DcutrEvent::DirectConnectionUpgradeSucceeded { endpoint, .. } => {
+                assert!(!endpoint.is_relayed());
+            }
+            e => panic!("Unexpected event: {:?}", e),
+        }
+    });
+}
+
+#[derive(NetworkBehaviour)]
+#[behaviour(out_event = "Event", event_process = false)]
+struct Behaviour {
+    client: Client,
+    dcutr: Dcutr,
+}
+
+#[derive(Debug)]
+enum Event {
+    Client(ClientEvent),
+    Dcutr(DcutrEvent),
+}
+
+impl From<ClientEvent> for Event {
+    fn from(event: ClientEvent) -> Self {
+        Event::Client(event)
+    }
+}
+
+impl From<DcutrEvent> for Event {
+    fn from(event: DcutrEvent) -> Self {
+        Event::Dcutr(event)
+    }
+}
+
+fn rand_port() -> u64 {
+    1 + rand::random::<u64>() % (u64::max_value() - 1)
+}
+
+fn build_relay() -> Swarm<Relay> {
+    let local_key = identity::Keypair::generate_ed25519();
+    let local_peer_id = local_key.public().into_peer_id();
+    let transport = upgrade_transport(MemoryTransport::default(), local_key);
+    Swarm::new(transport, Relay::new(RelayConfig::default()), local_peer_id)
+}
+
+fn build_client() -> Swarm<Behaviour> {
+    let local_key = identity::Keypair::generate_ed25519();
+    let local_peer_id = local_key.public().into_peer_id();
+    let (relay_transport, client) = Client::new_transport_and_behaviour();
+    let transport = upgrade_transport(relay_transport.or_transport(MemoryTransport::default()), local_key);
+    Swarm::new(transport, Behaviour { client, dcutr: Dcutr::new() }, local_peer_id)
+}
+
+fn upgrade_transport<T>(transport: T, local_key: identity::Keypair) -> Boxed<(PeerId, StreamMuxerBox), io::Error>
+where
+    T: Transport + Clone + Send + Sync + 'static,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::Error: Send + Sync + 'static,
+    T::Listener: Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+    T::Dial: Send + 'static,
+{
+    transport
+        .upgrade(upgrade::Version::V1)
+        .authenticate(PlainText2Config { local_public_key: local_key.public() })
+        .multiplex(MplexConfig::new())
+        .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        .boxed()
+}
@@ -0,0 +1,135 @@
+// Copyright 2020 Sigma Prime Pty Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Tracks the backoffs of the peers pruned from, or pruning us from, a topic mesh.
+
+use crate::topic::TopicHash;
+use libp2p_core::PeerId;
+use std::collections::HashMap;
+use std::time::Duration;
+use wasm_timer::Instant;
+
+/// Stores, per topic, the instant until which a peer must not be grafted.
+#[derive(Default)]
+pub(crate) struct BackoffStorage {
+    backoffs: HashMap<TopicHash, HashMap<PeerId, Instant>>,
+}
+
+impl BackoffStorage {
+    /// Sets the backoff of a peer in a topic to expire after `time`. A longer backoff that is
+    /// already in place is kept. A backoff beyond the representable instants is ignored.
+    pub fn update_backoff(&mut self, topic: &TopicHash, peer: &PeerId, time: Duration) {
+        let instant = match checked_instant_after(time) {
+            Some(instant) => instant,
+            None => return,
+        };
+        let expiry = self
+            .backoffs
+            .entry(topic.clone())
+            .or_insert_with(HashMap::new)
+            .entry(peer.clone())
+            .or_insert(instant);
+        if *expiry < instant {
+            *expiry = instant;
+        }
+    }
+
+    /// Returns true if the peer is backing off in the topic.
+    pub fn is_backoff(&self, topic: &TopicHash, peer: &PeerId) -> bool {
+        self.is_backoff_with_slack(topic, peer, Duration::from_secs(0))
+    }
+
+    /// Returns true if the peer is backing off in the topic, or will be for the duration of
+    /// `slack`. Used before grafting a peer so that it doesn't consider the GRAFT premature.
+    pub fn is_backoff_with_slack(&self, topic: &TopicHash, peer: &PeerId, slack: Duration) -> bool {
+        self.backoffs
+            .get(topic)
+            .and_then(|peers| peers.get(peer))
+            .map_or(false, |expiry| *expiry > Instant::now() + slack)
+    }
+
+    /// Removes the expired backoffs.
+    pub fn heartbeat(&mut self) {
+        let now = Instant::now();
+        self.backoffs.retain(|_, peers| {
+            peers.retain(|_, expiry| *expiry > now);
+            !peers.is_empty()
+        });
+    }
+}
+
+/// Returns the instant `time` from now, if it can be represented.
+#[cfg(not(target_arch = "wasm32"))]
+fn checked_instant_after(time: Duration) -> Option<Instant> {
+    Instant::now().checked_add(time)
+}
+
+/// Returns the instant `time` from now. Instants are floating point numbers in the browser,
+/// hence adding never overflows.
+#[cfg(target_arch = "wasm32")]
+fn checked_instant_after(time: Duration) -> Option<Instant> {
+    Some(Instant::now() + time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_expires() {
+        let mut backoffs = BackoffStorage::default();
+        let topic = TopicHash::from_raw("topic");
+        let peer = PeerId::random();
+
+        backoffs.update_backoff(&topic, &peer, Duration::from_millis(20));
+        assert!(backoffs.is_backoff(&topic, &peer));
+        assert!(!backoffs.is_backoff(&TopicHash::from_raw("other"), &peer));
+        assert!(!backoffs.is_backoff(&topic, &PeerId::random()));
+        assert!(backoffs.is_backoff_with_slack(&topic, &peer, Duration::from_millis(10)));
+        assert!(!backoffs.is_backoff_with_slack(&topic, &peer, Duration::from_secs(1)));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!backoffs.is_backoff(&topic, &peer));
+        backoffs.heartbeat();
+        assert!(backoffs.backoffs.is_empty());
+    }
+
+    #[test]
+    fn test_overflowing_backoff_is_ignored() {
+        let mut backoffs = BackoffStorage::default();
+        let topic = TopicHash::from_raw("topic");
+        let peer = PeerId::random();
+
+        backoffs.update_backoff(&topic, &peer, Duration::from_secs(u64::MAX));
+        assert!(!backoffs.is_backoff(&topic, &peer));
+    }
+
+    #[test]
+    fn test_backoff_keeps_the_longest() {
+        let mut backoffs = BackoffStorage::default();
+        let topic = TopicHash::from_raw("topic");
+        let peer = PeerId::random();
+
+        backoffs.update_backoff(&topic, &peer, Duration::from_secs(60));
+        backoffs.update_backoff(&topic, &peer, Duration::from_millis(1));
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(backoffs.is_backoff(&topic, &peer));
+    }
+}
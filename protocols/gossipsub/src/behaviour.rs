@@ -18,22 +18,28 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+use crate::backoff::BackoffStorage;
 use crate::config::{GossipsubConfig, ValidationMode};
 use crate::error::PublishError;
-use crate::handler::GossipsubHandler;
+use crate::gossip_promises::GossipPromises;
+use crate::handler::{GossipsubHandler, HandlerEvent};
 use crate::mcache::MessageCache;
+use crate::peer_score::{PeerScore, PeerScoreParams, PeerScoreThresholds};
 use crate::protocol::{
     GossipsubControlAction, GossipsubMessage, GossipsubSubscription, GossipsubSubscriptionAction,
-    MessageId, SIGNING_PREFIX,
+    MessageId, PeerInfo, PeerKind, SIGNING_PREFIX,
 };
 use crate::rpc_proto;
+use crate::subscription_filter::{MaxCountSubscriptionFilter, TopicSubscriptionFilter};
 use crate::topic::{Topic, TopicHash};
 use futures::prelude::*;
 use libp2p_core::{
-    connection::ConnectionId, identity::error::SigningError, identity::Keypair, Multiaddr, PeerId,
+    connection::{ConnectedPoint, ConnectionId}, identity::error::SigningError,
+    identity::Keypair, multiaddr::Protocol, peer_record::PeerRecord, Multiaddr, PeerId,
 };
 use libp2p_swarm::{
-    NetworkBehaviour, NetworkBehaviourAction, NotifyHandler, PollParameters, ProtocolsHandler,
+    ConnectionManager, DialPeerCondition, NetworkBehaviour, NetworkBehaviourAction, NotifyHandler,
+    PollParameters, ProtocolsHandler,
 };
 use log::{debug, error, info, trace, warn};
 use lru_time_cache::LruCache;
@@ -41,17 +47,30 @@ use prost::Message;
 use rand;
 use rand::{seq::SliceRandom, thread_rng};
 use std::{
+    cmp::Ordering,
     collections::HashSet,
     collections::VecDeque,
     collections::{hash_map::HashMap, BTreeSet},
     fmt, iter,
+    net::IpAddr,
     sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
 use wasm_timer::{Instant, Interval};
 
 mod tests;
 
+/// The maximum number of message ids remembered per peer from the IDONTWANT it sent us.
+const IDONTWANT_CAPACITY: usize = 10_000;
+
+/// The tag protecting the connections to our mesh peers in the [`ConnectionManager`].
+const MESH_PROTECTION_TAG: &str = "gossipsub-mesh";
+
+/// The backoff requested by a peer pruning us is capped to this multiple of our own prune
+/// backoff.
+const MAX_REMOTE_BACKOFF_FACTOR: u32 = 10;
+
 /// Determines if published messages should be signed or not.
 ///
 /// Without signing, a number of privacy preserving modes can be selected.
@@ -143,6 +162,18 @@ impl From<MessageAuthenticity> for PublishConfig {
     }
 }
 
+/// The outcome of the validation of a received message by the application, reported through
+/// [`Gossipsub::report_message_validation_result`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageAcceptance {
+    /// The message is valid and is forwarded to our peers.
+    Accept,
+    /// The message is invalid. It is dropped and the peers that sent it are penalised.
+    Reject,
+    /// The message is dropped without penalising the peers that sent it.
+    Ignore,
+}
+
 /// Network behaviour that handles the gossipsub protocol.
 ///
 /// NOTE: Initialisation requires a [`MessageAuthenticity`] and [`GossipsubConfig`] instance. If message signing is
@@ -171,6 +202,9 @@ pub struct Gossipsub {
     /// A map of all connected peers to their subscribed topics.
     peer_topics: HashMap<PeerId, BTreeSet<TopicHash>>,
 
+    /// The version of the protocol spoken by each connected peer, once known.
+    peer_protocols: HashMap<PeerId, PeerKind>,
+
     /// Overlay network of connected peers - Maps topics to connected gossipsub peers.
     mesh: HashMap<TopicHash, BTreeSet<PeerId>>,
 
@@ -183,28 +217,105 @@ pub struct Gossipsub {
     /// Message cache for the last few heartbeats.
     mcache: MessageCache,
 
+    /// The backoffs of the peers pruned from, or pruning us from, a topic mesh.
+    backoffs: BackoffStorage,
+
+    /// The message ids each peer announced through IDONTWANT, which we therefore don't forward
+    /// to it. Entries expire along with our own duplicate cache.
+    peer_dont_send: HashMap<PeerId, LruCache<MessageId, ()>>,
+
+    /// The explicit peers, which are sent all the messages of the topics they subscribe to and
+    /// are never part of a mesh.
+    explicit_peers: HashMap<PeerId, Vec<Multiaddr>>,
+
+    /// The signed peer records of the peers received through peer exchange or added with
+    /// [`Gossipsub::add_peer_record`], which we dial them at and pass on in our own peer
+    /// exchange. A record is dropped when the peer disconnects or can't be dialed.
+    peer_records: HashMap<PeerId, PeerRecord>,
+
+    /// Decides which topics we and the remote peers can subscribe to.
+    subscription_filter: Box<dyn TopicSubscriptionFilter + Send>,
+
+    /// The messages awaiting their validation by the application, along with the time they were
+    /// received and the peers that sent them to us, in order. Only used when
+    /// `config.validate_messages` is set.
+    pending_validations: HashMap<MessageId, (Instant, Vec<PeerId>)>,
+
+    /// The peers that joined or left a mesh since the last poll, i.e. through a GRAFT or PRUNE,
+    /// whose protection in the connection manager of the `Swarm` is updated on the next poll.
+    mesh_changes: HashSet<PeerId>,
+
     /// Heartbeat interval stream.
     heartbeat: Interval,
+
+    /// Number of heartbeats since the beginning of time; this allows us to amortize some resource
+    /// clean up (e.g. opportunistic grafting).
+    heartbeat_ticks: u64,
+
+    /// The peer score, the thresholds applied to it, the interval at which the scores are
+    /// refreshed and the tracking of the messages promised through IHAVE. Only set when peer
+    /// scoring has been enabled through [`Gossipsub::with_peer_score`].
+    peer_score: Option<(PeerScore, PeerScoreThresholds, Interval, GossipPromises)>,
 }
 
 impl Gossipsub {
     /// Creates a `Gossipsub` struct given a set of parameters specified via a `GossipsubConfig`.
+    ///
+    /// The subscriptions are filtered with the default [`MaxCountSubscriptionFilter`], which
+    /// allows all topics but caps the number of topics each peer can be subscribed to.
     pub fn new(privacy: MessageAuthenticity, config: GossipsubConfig) -> Self {
+        Self::new_with_subscription_filter(privacy, config, MaxCountSubscriptionFilter::default())
+    }
+
+    /// Creates a `Gossipsub` struct given a set of parameters specified via a `GossipsubConfig`,
+    /// filtering the subscriptions with the given [`TopicSubscriptionFilter`].
+    pub fn new_with_subscription_filter(
+        privacy: MessageAuthenticity,
+        config: GossipsubConfig,
+        subscription_filter: impl TopicSubscriptionFilter + Send + 'static,
+    ) -> Self {
         // Set up the router given the configuration settings.
 
         // We do not allow configurations where a published message would also be rejected if it
         // were received locally.
         validate_config(&privacy, &config.validation_mode);
 
+        // The configuration may have been built without the builder, which checks these.
+        assert!(
+            config.opportunistic_graft_ticks > 0,
+            "The opportunistic_graft_ticks must be greater than 0"
+        );
+        assert!(
+            config.check_explicit_peers_ticks > 0,
+            "The check_explicit_peers_ticks must be greater than 0"
+        );
+
         // Set up message publishing parameters.
 
+        // The explicit peers are dialed right away.
+        let mut explicit_peers = HashMap::<PeerId, Vec<Multiaddr>>::new();
+        for (peer_id, addresses) in &config.explicit_peers {
+            add_addresses(
+                explicit_peers.entry(peer_id.clone()).or_default(),
+                addresses,
+            );
+        }
+        let events = explicit_peers
+            .keys()
+            .map(|peer_id| NetworkBehaviourAction::DialPeer {
+                peer_id: peer_id.clone(),
+                condition: DialPeerCondition::Disconnected,
+            })
+            .collect();
+
         Gossipsub {
-            events: VecDeque::new(),
+            events,
             control_pool: HashMap::new(),
             publish_config: privacy.into(),
             duplication_cache: LruCache::with_expiry_duration(config.duplicate_cache_time),
             topic_peers: HashMap::new(),
             peer_topics: HashMap::new(),
+            peer_protocols: HashMap::new(),
             mesh: HashMap::new(),
             fanout: HashMap::new(),
             fanout_last_pub: HashMap::new(),
@@ -213,20 +324,170 @@ impl Gossipsub {
                 config.history_length,
                 config.message_id_fn,
             ),
+            backoffs: BackoffStorage::default(),
+            peer_dont_send: HashMap::new(),
+            explicit_peers,
+            peer_records: HashMap::new(),
+            subscription_filter: Box::new(subscription_filter),
+            pending_validations: HashMap::new(),
+            mesh_changes: HashSet::new(),
             heartbeat: Interval::new_at(
                 Instant::now() + config.heartbeat_initial_delay,
                 config.heartbeat_interval,
             ),
+            heartbeat_ticks: 0,
+            peer_score: None,
             config,
         }
     }
 
+    /// Activates the peer scoring system with the given parameters.
+    ///
+    /// Returns an error if the parameters are not valid or if peer scoring has already been
+    /// activated.
+    pub fn with_peer_score(
+        &mut self,
+        params: PeerScoreParams,
+        thresholds: PeerScoreThresholds,
+    ) -> Result<(), String> {
+        params.validate()?;
+        thresholds.validate()?;
+
+        if self.peer_score.is_some() {
+            return Err("Peer score set twice".into());
+        }
+
+        let interval = Interval::new(params.decay_interval);
+        let mut peer_score = PeerScore::new(params);
+        // Peers that are already connected are scored from now on.
+        for peer_id in self.peer_topics.keys() {
+            peer_score.add_peer(peer_id.clone());
+        }
+        self.peer_score = Some((peer_score, thresholds, interval, GossipPromises::default()));
+        Ok(())
+    }
+
+    /// Returns the topics we are subscribed to.
+    pub fn topics(&self) -> impl Iterator<Item = &TopicHash> {
+        self.mesh.keys()
+    }
+
+    /// Returns the peers in our mesh of a topic.
+    pub fn mesh_peers(&self, topic_hash: &TopicHash) -> impl Iterator<Item = &PeerId> {
+        self.mesh
+            .get(topic_hash)
+            .into_iter()
+            .flat_map(|peers| peers.iter())
+    }
+
+    /// Returns the score of a peer, if peer scoring is enabled.
+    pub fn peer_score(&self, peer_id: &PeerId) -> Option<f64> {
+        self.peer_score
+            .as_ref()
+            .map(|(peer_score, ..)| peer_score.score(peer_id))
+    }
+
+    /// Sets the application specific score of a peer, which is weighted by the
+    /// `app_specific_weight` of the [`PeerScoreParams`].
+    ///
+    /// Returns true if the score could be set, i.e. if peer scoring is enabled and the peer is
+    /// known.
+    pub fn set_application_score(&mut self, peer_id: &PeerId, new_score: f64) -> bool {
+        if let Some((peer_score, ..)) = &mut self.peer_score {
+            peer_score.set_application_score(peer_id, new_score)
+        } else {
+            false
+        }
+    }
+
+    /// Adds an explicit peer, which is sent all the messages of the topics it subscribes to and is
+    /// never part of a mesh. The peer is dialed on the given and otherwise known addresses if it
+    /// is not connected, and redialed every `check_explicit_peers_ticks` heartbeats while
+    /// disconnected. The addresses are added to those of a peer that is already explicit.
+    pub fn add_explicit_peer(&mut self, peer_id: &PeerId, addresses: Vec<Multiaddr>) {
+        debug!("Adding explicit peer {:?}", peer_id);
+        add_addresses(
+            self.explicit_peers.entry(peer_id.clone()).or_default(),
+            &addresses,
+        );
+
+        // the peer is pruned from the meshes and leaves the fanouts it is part of, since it
+        // receives all the messages anyway
+        let topics = self
+            .mesh
+            .iter_mut()
+            .filter_map(|(topic_hash, peers)| {
+                if peers.remove(peer_id) {
+                    Some(topic_hash.clone())
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        if !topics.is_empty() {
+            self.mesh_changes.insert(peer_id.clone());
+        }
+        for topic_hash in topics {
+            info!("Sending PRUNE to explicit peer: {:?}", peer_id);
+            if let Some((peer_score, ..)) = &mut self.peer_score {
+                peer_score.prune(peer_id, topic_hash.clone());
+            }
+            let prune = self.make_prune(&topic_hash, peer_id, self.config.do_px);
+            Self::control_pool_add(&mut self.control_pool, peer_id.clone(), prune);
+        }
+        for peers in self.fanout.values_mut() {
+            peers.remove(peer_id);
+        }
+
+        self.check_explicit_peer_connection(peer_id);
+    }
+
+    /// Removes an explicit peer, which is then treated like any other peer. The connections to
+    /// the peer are kept.
+    pub fn remove_explicit_peer(&mut self, peer_id: &PeerId) {
+        debug!("Removing explicit peer {:?}", peer_id);
+        self.explicit_peers.remove(peer_id);
+    }
+
+    /// Adds the signed record of the addresses of a peer, e.g. obtained from the peer itself.
+    ///
+    /// The addresses are used to dial the peer and the record is passed on to the peers we
+    /// suggest it to through peer exchange. A record is only replaced by one with a higher
+    /// sequence number.
+    pub fn add_peer_record(&mut self, record: PeerRecord) {
+        match self.peer_records.get(record.peer_id()) {
+            Some(known) if known.seq() >= record.seq() => {}
+            _ => {
+                self.peer_records.insert(record.peer_id().clone(), record);
+            }
+        }
+    }
+
+    /// Dials an explicit peer if it is not connected.
+    fn check_explicit_peer_connection(&mut self, peer_id: &PeerId) {
+        if !self.peer_topics.contains_key(peer_id) {
+            debug!("Dialing explicit peer {:?}", peer_id);
+            self.events.push_back(NetworkBehaviourAction::DialPeer {
+                peer_id: peer_id.clone(),
+                condition: DialPeerCondition::Disconnected,
+            });
+        }
+    }
+
     /// Subscribe to a topic.
     ///
-    /// Returns true if the subscription worked. Returns false if we were already subscribed.
+    /// Returns true if the subscription worked. Returns false if we were already subscribed, or
+    /// if the subscription filter doesn't allow the topic.
     pub fn subscribe(&mut self, topic: Topic) -> bool {
         debug!("Subscribing to topic: {}", topic);
         let topic_hash = self.topic_hash(topic.clone());
+        if !self.subscription_filter.can_subscribe(&topic_hash) {
+            warn!(
+                "Topic: {} is not allowed by the subscription filter.",
+                topic
+            );
+            return false;
+        }
         if self.mesh.get(&topic_hash).is_some() {
             debug!("Topic: {} is already in the mesh.", topic);
             return false;
@@ -329,37 +590,69 @@ impl Gossipsub {
 
         debug!("Publishing message: {:?}", msg_id);
 
-        // Forward the message to mesh peers.
-        let mesh_peers_sent = self.forward_msg(message.clone(), None);
-
         let mut recipient_peers = HashSet::new();
-        for topic_hash in &message.topics {
-            // If not subscribed to the topic, use fanout peers.
-            if self.mesh.get(&topic_hash).is_none() {
-                debug!("Topic: {:?} not in the mesh", topic_hash);
-                // Build a list of peers to forward the message to
-                // if we have fanout peers add them to the map.
-                if self.fanout.contains_key(&topic_hash) {
-                    for peer in self.fanout.get(&topic_hash).expect("Topic must exist") {
-                        recipient_peers.insert(peer.clone());
+        let mut mesh_peers_sent = false;
+        if self.config.flood_publish {
+            // Publish to all the peers subscribed to the topics, unless their score is below the
+            // publish threshold. Explicit peers are always published to.
+            let peer_score = &self.peer_score;
+            for topic_hash in &message.topics {
+                if let Some(peers) = self.topic_peers.get(topic_hash) {
+                    for peer in peers {
+                        if self.peer_dont_want(peer, &msg_id) {
+                            continue;
+                        }
+                        if self.explicit_peers.contains_key(peer)
+                            || !score_below_threshold_from(peer_score, peer, |t| {
+                                t.publish_threshold
+                            })
+                            .0
+                        {
+                            recipient_peers.insert(peer.clone());
+                        }
                     }
-                } else {
-                    // we have no fanout peers, select mesh_n of them and add them to the fanout
-                    let mesh_n = self.config.mesh_n;
-                    let new_peers =
-                        Self::get_random_peers(&self.topic_peers, &topic_hash, mesh_n, {
-                            |_| true
-                        });
-                    // add the new peers to the fanout and recipient peers
-                    self.fanout.insert(topic_hash.clone(), new_peers.clone());
-                    for peer in new_peers {
-                        debug!("Peer added to fanout: {:?}", peer);
-                        recipient_peers.insert(peer.clone());
+                }
+            }
+        } else {
+            // Forward the message to mesh peers.
+            mesh_peers_sent = self.forward_msg(message.clone(), None);
+
+            for topic_hash in &message.topics {
+                // If not subscribed to the topic, use fanout peers.
+                if self.mesh.get(&topic_hash).is_none() {
+                    debug!("Topic: {:?} not in the mesh", topic_hash);
+                    // Build a list of peers to forward the message to
+                    // if we have fanout peers add them to the map.
+                    if self.fanout.contains_key(&topic_hash) {
+                        for peer in self.fanout.get(&topic_hash).expect("Topic must exist") {
+                            recipient_peers.insert(peer.clone());
+                        }
+                    } else {
+                        // we have no fanout peers, select mesh_n of them and add them to the fanout
+                        let mesh_n = self.config.mesh_n;
+                        let peer_score = &self.peer_score;
+                        let explicit_peers = &self.explicit_peers;
+                        let new_peers =
+                            Self::get_random_peers(&self.topic_peers, &topic_hash, mesh_n, {
+                                |peer| {
+                                    !explicit_peers.contains_key(peer)
+                                        && !score_below_threshold_from(peer_score, peer, |t| {
+                                            t.publish_threshold
+                                        })
+                                        .0
+                                }
+                            });
+                        // add the new peers to the fanout and recipient peers
+                        self.fanout.insert(topic_hash.clone(), new_peers.clone());
+                        for peer in new_peers {
+                            debug!("Peer added to fanout: {:?}", peer);
+                            recipient_peers.insert(peer.clone());
+                        }
                     }
+                    // we are publishing to fanout peers - update the time we published
+                    self.fanout_last_pub
+                        .insert(topic_hash.clone(), Instant::now());
                 }
-                // we are publishing to fanout peers - update the time we published
-                self.fanout_last_pub
-                    .insert(topic_hash.clone(), Instant::now());
             }
         }
 
@@ -393,23 +686,98 @@ impl Gossipsub {
     /// The `propagation_source` parameter indicates who the message was received by and will not
     /// be forwarded back to that peer.
     ///
-    /// This should only be called once per message.
+    /// This should only be called once per message. It is equivalent to reporting
+    /// [`MessageAcceptance::Accept`] through [`Gossipsub::report_message_validation_result`].
     pub fn validate_message(
         &mut self,
         message_id: &MessageId,
         propagation_source: &PeerId,
     ) -> bool {
-        let message = match self.mcache.validate(message_id) {
-            Some(message) => message.clone(),
+        self.report_message_validation_result(
+            message_id,
+            propagation_source,
+            MessageAcceptance::Accept,
+        )
+    }
+
+    /// Reports the outcome of the validation of a message by the application, when
+    /// `config.validate_messages` is `true`. The message must still be in the ['Memcache'],
+    /// i.e. it must be reported within `config.validation_timeout`, otherwise this function
+    /// returns false.
+    ///
+    /// - [`MessageAcceptance::Accept`] forwards the message to our peers, except to
+    ///   `propagation_source`.
+    /// - [`MessageAcceptance::Reject`] drops the message and penalises `propagation_source` and
+    ///   the peers that also sent it to us in the peer score, if enabled. A
+    ///   [`GossipsubEvent::MessageRejected`] event lists these peers, so that the application can
+    ///   penalise them as well.
+    /// - [`MessageAcceptance::Ignore`] drops the message without penalising anyone.
+    ///
+    /// This should only be called once per message.
+    pub fn report_message_validation_result(
+        &mut self,
+        message_id: &MessageId,
+        propagation_source: &PeerId,
+        acceptance: MessageAcceptance,
+    ) -> bool {
+        let mut peers = self
+            .pending_validations
+            .remove(message_id)
+            .map(|(_, peers)| peers)
+            .unwrap_or_default();
+
+        if let MessageAcceptance::Accept = acceptance {
+            let message = match self.mcache.validate(message_id) {
+                Some(message) => message.clone(),
+                None => {
+                    warn!(
+                        "Message not in cache. Ignoring forwarding. Message Id: {}",
+                        message_id
+                    );
+                    return false;
+                }
+            };
+
+            if let Some((peer_score, ..)) = &mut self.peer_score {
+                peer_score.deliver_message(propagation_source, message_id, &message.topics);
+            }
+
+            self.forward_msg(message, Some(propagation_source));
+            return true;
+        }
+
+        let message = match self.mcache.remove(message_id) {
+            Some(message) => message,
             None => {
                 warn!(
-                    "Message not in cache. Ignoring forwarding. Message Id: {}",
+                    "Message not in cache. Ignoring validation result. Message Id: {}",
                     message_id
                 );
                 return false;
             }
         };
-        self.forward_msg(message, Some(propagation_source));
+
+        if acceptance == MessageAcceptance::Ignore {
+            debug!("Ignoring message: {:?}", message_id);
+            if let Some((peer_score, ..)) = &mut self.peer_score {
+                peer_score.ignore_message(message_id);
+            }
+            return true;
+        }
+
+        debug!("Rejecting message: {:?}", message_id);
+        if let Some((peer_score, ..)) = &mut self.peer_score {
+            peer_score.reject_message(propagation_source, message_id, &message.topics);
+        }
+        if !peers.contains(propagation_source) {
+            peers.insert(0, propagation_source.clone());
+        }
+        self.events.push_back(NetworkBehaviourAction::GenerateEvent(
+            GossipsubEvent::MessageRejected {
+                message_id: message_id.clone(),
+                peers,
+            },
+        ));
         true
     }
 
@@ -432,6 +800,19 @@ impl Gossipsub {
                 "JOIN: Removing peers from the fanout for topic: {:?}",
                 topic_hash
             );
+            // only peers with a non-negative score that are not backing off are added to the
+            // mesh, explicit peers never are
+            let peer_score = &self.peer_score;
+            let backoffs = &self.backoffs;
+            let explicit_peers = &self.explicit_peers;
+            let peers = peers
+                .into_iter()
+                .filter(|peer| {
+                    !explicit_peers.contains_key(peer)
+                        && !score_below_threshold_from(peer_score, peer, |_| 0.0).0
+                        && !backoffs.is_backoff(topic_hash, peer)
+                })
+                .collect::<BTreeSet<_>>();
             // add up to mesh_n of them them to the mesh
             // Note: These aren't randomly added, currently FIFO
             let add_peers = std::cmp::min(peers.len(), self.config.mesh_n);
@@ -451,11 +832,19 @@ impl Gossipsub {
         // check if we need to get more peers, which we randomly select
         if added_peers.len() < self.config.mesh_n {
             // get the peers
+            let peer_score = &self.peer_score;
+            let backoffs = &self.backoffs;
+            let explicit_peers = &self.explicit_peers;
             let new_peers = Self::get_random_peers(
                 &self.topic_peers,
                 topic_hash,
                 self.config.mesh_n - added_peers.len(),
-                |peer| !added_peers.contains(peer),
+                |peer| {
+                    !added_peers.contains(peer)
+                        && !explicit_peers.contains_key(peer)
+                        && !score_below_threshold_from(peer_score, peer, |_| 0.0).0
+                        && !backoffs.is_backoff(topic_hash, peer)
+                },
             );
             added_peers.extend(new_peers.clone());
             // add them to the mesh
@@ -473,6 +862,10 @@ impl Gossipsub {
         for peer_id in added_peers {
             // Send a GRAFT control message
             info!("JOIN: Sending Graft message to peer: {:?}", peer_id);
+            self.mesh_changes.insert(peer_id.clone());
+            if let Some((peer_score, ..)) = &mut self.peer_score {
+                peer_score.graft(&peer_id, topic_hash.clone());
+            }
             Self::control_pool_add(
                 &mut self.control_pool,
                 peer_id.clone(),
@@ -493,13 +886,12 @@ impl Gossipsub {
             for peer in peers {
                 // Send a PRUNE control message
                 info!("LEAVE: Sending PRUNE to peer: {:?}", peer);
-                Self::control_pool_add(
-                    &mut self.control_pool,
-                    peer.clone(),
-                    GossipsubControlAction::Prune {
-                        topic_hash: topic_hash.clone(),
-                    },
-                );
+                self.mesh_changes.insert(peer.clone());
+                if let Some((peer_score, ..)) = &mut self.peer_score {
+                    peer_score.prune(&peer, topic_hash.clone());
+                }
+                let prune = self.make_prune(topic_hash, &peer, self.config.do_px);
+                Self::control_pool_add(&mut self.control_pool, peer.clone(), prune);
             }
         }
         debug!("Completed LEAVE for topic: {:?}", topic_hash);
@@ -508,6 +900,15 @@ impl Gossipsub {
     /// Handles an IHAVE control message. Checks our cache of messages. If the message is unknown,
     /// requests it with an IWANT control message.
     fn handle_ihave(&mut self, peer_id: &PeerId, ihave_msgs: Vec<(TopicHash, Vec<MessageId>)>) {
+        // We ignore IHAVE gossip from any peer whose score is below the gossip threshold
+        if let (true, score) = self.score_below_threshold(peer_id, |t| t.gossip_threshold) {
+            debug!(
+                "IHAVE: ignoring peer {:?} with score below threshold [score = {}]",
+                peer_id, score
+            );
+            return;
+        }
+
         debug!("Handling IHAVE for peer: {:?}", peer_id);
         // use a hashset to avoid duplicates efficiently
         let mut iwant_ids = HashSet::new();
@@ -531,14 +932,23 @@ impl Gossipsub {
         }
 
         if !iwant_ids.is_empty() {
+            let message_ids = iwant_ids.into_iter().collect::<Vec<_>>();
+
+            // The peer is expected to follow up on its advertisement.
+            if let Some((_, _, _, gossip_promises)) = &mut self.peer_score {
+                gossip_promises.add_promise(
+                    peer_id.clone(),
+                    &message_ids,
+                    Instant::now() + self.config.iwant_followup_time,
+                );
+            }
+
             // Send the list of IWANT control messages
             debug!("IHAVE: Sending IWANT message");
             Self::control_pool_add(
                 &mut self.control_pool,
                 peer_id.clone(),
-                GossipsubControlAction::IWant {
-                    message_ids: iwant_ids.iter().cloned().collect(),
-                },
+                GossipsubControlAction::IWant { message_ids },
             );
         }
         debug!("Completed IHAVE handling for peer: {:?}", peer_id);
@@ -547,12 +957,25 @@ impl Gossipsub {
     /// Handles an IWANT control message. Checks our cache of messages. If the message exists it is
     /// forwarded to the requesting peer.
     fn handle_iwant(&mut self, peer_id: &PeerId, iwant_msgs: Vec<MessageId>) {
+        // We ignore IWANT gossip from any peer whose score is below the gossip threshold
+        if let (true, score) = self.score_below_threshold(peer_id, |t| t.gossip_threshold) {
+            debug!(
+                "IWANT: ignoring peer {:?} with score below threshold [score = {}]",
+                peer_id, score
+            );
+            return;
+        }
+
         debug!("Handling IWANT for peer: {:?}", peer_id);
         // build a hashmap of available messages
         let mut cached_messages = HashMap::new();
 
         for id in iwant_msgs {
-            // if we have it, add it do the cached_messages mapping
+            // if we have it, add it do the cached_messages mapping, unless the peer told us
+            // through IDONTWANT that it already has it
+            if self.peer_dont_want(peer_id, &id) {
+                continue;
+            }
             if let Some(msg) = self.mcache.get(&id) {
                 cached_messages.insert(id.clone(), msg.clone());
             }
@@ -580,16 +1003,78 @@ impl Gossipsub {
         debug!("Handling GRAFT message for peer: {:?}", peer_id);
 
         let mut to_prune_topics = HashSet::new();
+
+        // peer exchange is only sent to peers that are refused for lack of space
+        let mut do_px = self.config.do_px;
+
+        // we don't GRAFT peers with negative score
+        let (below_zero, score) = self.score_below_threshold(peer_id, |_| 0.0);
+
         for topic_hash in topics {
             if let Some(peers) = self.mesh.get_mut(&topic_hash) {
-                // if we are subscribed, add peer to the mesh, if not already added
+                // if the peer is already in the mesh ignore the graft
+                if peers.contains(peer_id) {
+                    continue;
+                }
+
+                // explicit peers are never part of the mesh
+                if self.explicit_peers.contains_key(peer_id) {
+                    debug!(
+                        "GRAFT: ignoring explicit peer {:?} [topic = {}]",
+                        peer_id, topic_hash
+                    );
+                    do_px = false;
+                    to_prune_topics.insert(topic_hash.clone());
+                    continue;
+                }
+
+                // a peer grafting during its backoff violates the protocol
+                if self.backoffs.is_backoff(&topic_hash, peer_id) {
+                    debug!(
+                        "GRAFT: ignoring backed off peer {:?} [topic = {}]",
+                        peer_id, topic_hash
+                    );
+                    if let Some((peer_score, ..)) = &mut self.peer_score {
+                        peer_score.add_penalty(peer_id, 1);
+                    }
+                    do_px = false;
+                    to_prune_topics.insert(topic_hash.clone());
+                    continue;
+                }
+
+                if below_zero {
+                    debug!(
+                        "GRAFT: ignoring peer {:?} with negative score [score = {}, topic = {}]",
+                        peer_id, score, topic_hash
+                    );
+                    do_px = false;
+                    to_prune_topics.insert(topic_hash.clone());
+                    continue;
+                }
+
+                // a full mesh refuses the peer, pointing it to other peers
+                if peers.len() >= self.config.mesh_n_high {
+                    debug!(
+                        "GRAFT: mesh full, refusing peer {:?} [topic = {}]",
+                        peer_id, topic_hash
+                    );
+                    to_prune_topics.insert(topic_hash.clone());
+                    continue;
+                }
+
+                // if we are subscribed, add peer to the mesh
                 info!(
                     "GRAFT: Mesh link added for peer: {:?} in topic: {:?}",
                     peer_id, topic_hash
                 );
-                // Duplicates are ignored
                 peers.insert(peer_id.clone());
+                self.mesh_changes.insert(peer_id.clone());
+                if let Some((peer_score, ..)) = &mut self.peer_score {
+                    peer_score.graft(peer_id, topic_hash);
+                }
             } else {
+                // we don't do peer exchange for unknown topics, to avoid leaking our peers
+                do_px = false;
                 to_prune_topics.insert(topic_hash.clone());
             }
         }
@@ -598,9 +1083,7 @@ impl Gossipsub {
             // build the prune messages to send
             let prune_messages = to_prune_topics
                 .iter()
-                .map(|t| GossipsubControlAction::Prune {
-                    topic_hash: t.clone(),
-                })
+                .map(|t| self.make_prune(t, peer_id, do_px))
                 .collect();
             // Send the prune messages to the peer
             info!(
@@ -619,10 +1102,46 @@ impl Gossipsub {
         debug!("Completed GRAFT handling for peer: {:?}", peer_id);
     }
 
-    /// Handles PRUNE control messages. Removes peer from the mesh.
-    fn handle_prune(&mut self, peer_id: &PeerId, topics: Vec<TopicHash>) {
+    /// Handles PRUNE control messages. Removes peer from the mesh, backs off from it and connects
+    /// to the peers it suggested through peer exchange, if any.
+    fn handle_prune(
+        &mut self,
+        peer_id: &PeerId,
+        prune_data: Vec<(TopicHash, Vec<PeerInfo>, Option<u64>)>,
+    ) {
         debug!("Handling PRUNE message for peer: {}", peer_id.to_string());
-        for topic_hash in topics {
+        let (below_threshold, score) =
+            self.score_below_threshold(peer_id, |t| t.accept_px_threshold);
+        for (topic_hash, px, backoff) in prune_data {
+            // a PRUNE for a topic we are not subscribed to is meaningless
+            if !self.mesh.contains_key(&topic_hash) {
+                debug!(
+                    "PRUNE: ignoring PRUNE from peer {:?} for unsubscribed topic {}",
+                    peer_id, topic_hash
+                );
+                continue;
+            }
+
+            // we don't GRAFT the peer again until the backoff has expired, for at most a few
+            // times our own backoff
+            let max_backoff = self.config.prune_backoff * MAX_REMOTE_BACKOFF_FACTOR;
+            let backoff = backoff
+                .map(|b| Duration::from_secs(b).min(max_backoff))
+                .unwrap_or(self.config.prune_backoff);
+            self.backoffs.update_backoff(&topic_hash, peer_id, backoff);
+
+            // connect to the peers suggested through peer exchange
+            if !px.is_empty() {
+                if below_threshold {
+                    debug!(
+                        "PRUNE: ignoring PX from peer {:?} with insufficient score [score = {}, topic = {}]",
+                        peer_id, score, topic_hash
+                    );
+                } else {
+                    self.px_connect(px);
+                }
+            }
+
             if let Some(peers) = self.mesh.get_mut(&topic_hash) {
                 // remove the peer if it exists in the mesh
                 if peers.remove(peer_id) {
@@ -631,12 +1150,92 @@ impl Gossipsub {
                         peer_id.to_string(),
                         topic_hash
                     );
+                    self.mesh_changes.insert(peer_id.clone());
+                    if let Some((peer_score, ..)) = &mut self.peer_score {
+                        peer_score.prune(peer_id, topic_hash);
+                    }
                 }
             }
         }
         debug!("Completed PRUNE handling for peer: {}", peer_id.to_string());
     }
 
+    /// Dials up to `prune_peers` of the peers received through peer exchange, at the addresses
+    /// of their signed peer records if they came with one.
+    fn px_connect(&mut self, mut px: Vec<PeerInfo>) {
+        px.retain(|p| p.peer_id.is_some());
+        if px.len() > self.config.prune_peers {
+            px.partial_shuffle(&mut thread_rng(), self.config.prune_peers);
+            px.truncate(self.config.prune_peers);
+        }
+
+        for info in px {
+            let peer_id = match info.peer_id {
+                Some(peer_id) => peer_id,
+                None => continue,
+            };
+            // only dial the peers we are not connected to
+            if !self.peer_topics.contains_key(&peer_id) {
+                if let Some(record) = info.signed_peer_record {
+                    self.add_peer_record(record);
+                }
+                debug!("PX: Dialing peer {:?}", peer_id);
+                self.events.push_back(NetworkBehaviourAction::DialPeer {
+                    peer_id,
+                    condition: DialPeerCondition::Disconnected,
+                });
+            }
+        }
+    }
+
+    /// Builds a PRUNE for `peer` in `topic_hash` and starts the backoff of the peer. Gossipsub
+    /// v1.1 peers are also sent the backoff and, if `do_px` is set, other peers of the topic.
+    fn make_prune(
+        &mut self,
+        topic_hash: &TopicHash,
+        peer: &PeerId,
+        do_px: bool,
+    ) -> GossipsubControlAction {
+        self.backoffs
+            .update_backoff(topic_hash, peer, self.config.prune_backoff);
+
+        // gossipsub v1.0 peers only understand the topic
+        match self.peer_protocols.get(peer) {
+            Some(PeerKind::Gossipsubv1_2) | Some(PeerKind::Gossipsubv1_1) => {}
+            _ => {
+                return GossipsubControlAction::Prune {
+                    topic_hash: topic_hash.clone(),
+                    peers: Vec::new(),
+                    backoff: None,
+                }
+            }
+        }
+
+        let peers = if do_px {
+            let peer_score = &self.peer_score;
+            Self::get_random_peers(
+                &self.topic_peers,
+                topic_hash,
+                self.config.prune_peers,
+                |p| p != peer && !score_below_threshold_from(peer_score, p, |_| 0.0).0,
+            )
+            .into_iter()
+            .map(|p| PeerInfo {
+                signed_peer_record: self.peer_records.get(&p).cloned(),
+                peer_id: Some(p),
+            })
+            .collect()
+        } else {
+            Vec::new()
+        };
+
+        GossipsubControlAction::Prune {
+            topic_hash: topic_hash.clone(),
+            peers,
+            backoff: Some(self.config.prune_backoff.as_secs()),
+        }
+    }
+
     /// Handles a newly received GossipsubMessage.
     /// Forwards the message to all peers in the mesh.
     fn handle_received_message(&mut self, mut msg: GossipsubMessage, propagation_source: &PeerId) {
@@ -657,9 +1256,41 @@ impl Gossipsub {
         // Add the message to the duplication cache and memcache.
         if self.duplication_cache.insert(msg_id.clone(), ()).is_some() {
             debug!("Message already received, ignoring. Message: {:?}", msg_id);
+            // remember the peers forwarding a message that is still being validated
+            if let Some((_, peers)) = self.pending_validations.get_mut(&msg_id) {
+                if !peers.contains(propagation_source) {
+                    peers.push(propagation_source.clone());
+                }
+            }
+            if let Some((peer_score, ..)) = &mut self.peer_score {
+                peer_score.duplicated_message(propagation_source, &msg_id, &msg.topics);
+            }
             return;
         }
+
+        // Tell the score that the message arrived, and consider the message as delivered for
+        // gossip promises.
+        if let Some((peer_score, .., gossip_promises)) = &mut self.peer_score {
+            peer_score.validate_message(&msg_id);
+            gossip_promises.message_delivered(&msg_id);
+            // Without validation, the message is delivered right away.
+            if !self.config.validate_messages {
+                peer_score.deliver_message(propagation_source, &msg_id, &msg.topics);
+            }
+        }
         self.mcache.put(msg.clone());
+        if self.config.validate_messages {
+            self.pending_validations.insert(
+                msg_id.clone(),
+                (Instant::now(), vec![propagation_source.clone()]),
+            );
+        }
+
+        // Large messages are announced to our mesh peers right away, so that they don't send them
+        // to us as well.
+        if msg.data.len() >= self.config.idontwant_message_size_threshold {
+            self.send_idontwant(&msg, &msg_id, propagation_source);
+        }
 
         // dispatch the message to the user
         if self.mesh.keys().any(|t| msg.topics.iter().any(|u| t == u)) {
@@ -677,6 +1308,67 @@ impl Gossipsub {
         }
     }
 
+    /// Sends an IDONTWANT for `msg` to the mesh peers of its topics speaking gossipsub v1.2,
+    /// except to the peer we received it from.
+    fn send_idontwant(
+        &mut self,
+        msg: &GossipsubMessage,
+        msg_id: &MessageId,
+        propagation_source: &PeerId,
+    ) {
+        let mut recipient_peers = HashSet::new();
+        for topic in &msg.topics {
+            if let Some(mesh_peers) = self.mesh.get(topic) {
+                for peer_id in mesh_peers {
+                    if peer_id != propagation_source
+                        && self.peer_protocols.get(peer_id) == Some(&PeerKind::Gossipsubv1_2)
+                    {
+                        recipient_peers.insert(peer_id.clone());
+                    }
+                }
+            }
+        }
+
+        if recipient_peers.is_empty() {
+            return;
+        }
+
+        debug!("Sending IDONTWANT for message: {:?}", msg_id);
+        let event = Arc::new(GossipsubRpc {
+            subscriptions: Vec::new(),
+            messages: Vec::new(),
+            control_msgs: vec![GossipsubControlAction::IDontWant {
+                message_ids: vec![msg_id.clone()],
+            }],
+        });
+        for peer_id in recipient_peers {
+            self.send_message(peer_id, event.clone());
+        }
+    }
+
+    /// Handles an IDONTWANT control message. The announced messages are not forwarded to the
+    /// peer until they expire.
+    fn handle_idontwant(&mut self, peer_id: &PeerId, message_ids: Vec<MessageId>) {
+        debug!("Handling IDONTWANT for peer: {:?}", peer_id);
+        let ttl = self.config.duplicate_cache_time;
+        let dont_send = self
+            .peer_dont_send
+            .entry(peer_id.clone())
+            .or_insert_with(|| {
+                LruCache::with_expiry_duration_and_capacity(ttl, IDONTWANT_CAPACITY)
+            });
+        for id in message_ids {
+            dont_send.insert(id, ());
+        }
+    }
+
+    /// Returns true if the peer told us through IDONTWANT that it already has the message.
+    fn peer_dont_want(&self, peer_id: &PeerId, msg_id: &MessageId) -> bool {
+        self.peer_dont_send
+            .get(peer_id)
+            .map_or(false, |ids| ids.contains_key(msg_id))
+    }
+
     /// Handles received subscriptions.
     fn handle_received_subscriptions(
         &mut self,
@@ -699,21 +1391,35 @@ impl Gossipsub {
             }
         };
 
+        // drop the subscriptions the filter doesn't allow
+        let allowed = match self
+            .subscription_filter
+            .filter_incoming_subscriptions(subscriptions, subscribed_topics)
+        {
+            Ok(allowed) => allowed,
+            Err(e) => {
+                debug!(
+                    "SUBSCRIPTION: ignoring subscriptions from peer {:?}: {}",
+                    propagation_source, e
+                );
+                return;
+            }
+        };
+
         // Collect potential graft messages for the peer.
         let mut grafts = Vec::new();
 
         // Notify the application about the subscription, after the grafts are sent.
         let mut application_event = Vec::new();
 
-        for subscription in subscriptions {
-            // get the peers from the mapping, or insert empty lists if topic doesn't exist
-            let peer_list = self
-                .topic_peers
-                .entry(subscription.topic_hash.clone())
-                .or_insert_with(Default::default);
-
+        for subscription in subscriptions.iter().filter(|s| allowed.contains(s)) {
             match subscription.action {
                 GossipsubSubscriptionAction::Subscribe => {
+                    // get the peers from the mapping, or insert empty lists if topic doesn't exist
+                    let peer_list = self
+                        .topic_peers
+                        .entry(subscription.topic_hash.clone())
+                        .or_insert_with(Default::default);
                     if peer_list.insert(propagation_source.clone()) {
                         debug!(
                             "SUBSCRIPTION: Adding gossip peer: {} to topic: {:?}",
@@ -725,10 +1431,29 @@ impl Gossipsub {
                     // add to the peer_topics mapping
                     subscribed_topics.insert(subscription.topic_hash.clone());
 
-                    // if the mesh needs peers add the peer to the mesh
+                    // if the mesh needs peers add the peer to the mesh, unless it is an explicit
+                    // peer, its score is negative, or it is backing off
+                    let explicit = self.explicit_peers.contains_key(propagation_source);
+                    let below_zero =
+                        score_below_threshold_from(&self.peer_score, propagation_source, |_| 0.0)
+                            .0;
+                    let backing_off = self.backoffs.is_backoff_with_slack(
+                        &subscription.topic_hash,
+                        propagation_source,
+                        self.config.heartbeat_interval,
+                    );
                     if let Some(peers) = self.mesh.get_mut(&subscription.topic_hash) {
-                        if peers.len() < self.config.mesh_n_low {
+                        if peers.len() < self.config.mesh_n_low
+                            && !explicit
+                            && !below_zero
+                            && !backing_off
+                        {
                             if peers.insert(propagation_source.clone()) {
+                                self.mesh_changes.insert(propagation_source.clone());
+                                if let Some((peer_score, ..)) = &mut self.peer_score {
+                                    peer_score
+                                        .graft(propagation_source, subscription.topic_hash.clone());
+                                }
                                 debug!(
                                     "SUBSCRIPTION: Adding peer {} to the mesh for topic {:?}",
                                     propagation_source.to_string(),
@@ -755,19 +1480,31 @@ impl Gossipsub {
                     ));
                 }
                 GossipsubSubscriptionAction::Unsubscribe => {
-                    if peer_list.remove(propagation_source) {
-                        info!(
-                            "SUBSCRIPTION: Removing gossip peer: {} from topic: {:?}",
-                            propagation_source.to_string(),
-                            subscription.topic_hash
-                        );
+                    if let Some(peer_list) = self.topic_peers.get_mut(&subscription.topic_hash) {
+                        if peer_list.remove(propagation_source) {
+                            info!(
+                                "SUBSCRIPTION: Removing gossip peer: {} from topic: {:?}",
+                                propagation_source.to_string(),
+                                subscription.topic_hash
+                            );
+                        }
+                        // forget the topics without peers
+                        if peer_list.is_empty() {
+                            self.topic_peers.remove(&subscription.topic_hash);
+                        }
                     }
                     // remove topic from the peer_topics mapping
                     subscribed_topics.remove(&subscription.topic_hash);
                     // remove the peer from the mesh if it exists
                     if let Some(peers) = self.mesh.get_mut(&subscription.topic_hash) {
-                        peers.remove(propagation_source);
                         // the peer requested the unsubscription so we don't need to send a PRUNE.
+                        if peers.remove(propagation_source) {
+                            self.mesh_changes.insert(propagation_source.clone());
+                            if let Some((peer_score, ..)) = &mut self.peer_score {
+                                peer_score
+                                    .prune(propagation_source, subscription.topic_hash.clone());
+                            }
+                        }
                     }
 
                     // generate an unsubscribe event to be polled
@@ -809,11 +1546,73 @@ impl Gossipsub {
     fn heartbeat(&mut self) {
         debug!("Starting heartbeat");
 
+        self.heartbeat_ticks += 1;
+
         let mut to_graft = HashMap::new();
         let mut to_prune = HashMap::new();
+        // the peers that don't receive peer exchange along with their PRUNE
+        let mut no_px = HashSet::new();
+
+        // clean up the expired backoffs
+        self.backoffs.heartbeat();
+
+        // dial the disconnected explicit peers
+        if self.heartbeat_ticks % self.config.check_explicit_peers_ticks == 0 {
+            for peer_id in self.explicit_peers.keys().cloned().collect::<Vec<_>>() {
+                self.check_explicit_peer_connection(&peer_id);
+            }
+        }
+
+        // drop the messages the application failed to validate in time
+        self.drop_unvalidated_messages();
+
+        // penalize the peers that didn't follow up on their IHAVE advertisements
+        if let Some((peer_score, .., gossip_promises)) = &mut self.peer_score {
+            for (peer, count) in gossip_promises.get_broken_promises() {
+                peer_score.add_penalty(&peer, count);
+            }
+        }
+
+        // cache the scores of all peers for the duration of the heartbeat
+        let mut scores = HashMap::new();
+        let peer_score = &self.peer_score;
+        let mut score = |p: &PeerId| match peer_score {
+            Some((peer_score, ..)) => *scores
+                .entry(p.clone())
+                .or_insert_with(|| peer_score.score(p)),
+            None => 0.0,
+        };
+
+        // the peers backing off are not grafted, with a slack of one heartbeat so that our GRAFT
+        // doesn't arrive before the end of the backoff
+        let backoffs = &self.backoffs;
+        let heartbeat_interval = self.config.heartbeat_interval;
+
+        // explicit peers are neither grafted nor part of the fanout
+        let explicit_peers = &self.explicit_peers;
 
         // maintain the mesh for each topic
         for (topic_hash, peers) in self.mesh.iter_mut() {
+            // drop all peers with negative score
+            let to_remove = peers
+                .iter()
+                .filter(|p| score(p) < 0.0)
+                .cloned()
+                .collect::<Vec<_>>();
+            for peer in to_remove {
+                debug!(
+                    "HEARTBEAT: Prune peer {:?} with negative score [score = {}, topic = {}]",
+                    peer,
+                    score(&peer),
+                    topic_hash
+                );
+                peers.remove(&peer);
+                let current_topic = to_prune.entry(peer.clone()).or_insert_with(Vec::new);
+                current_topic.push(topic_hash.clone());
+                // we don't exchange peers with peers we have a bad opinion of
+                no_px.insert(peer);
+            }
+
             // too little peers - add some
             if peers.len() < self.config.mesh_n_low {
                 debug!(
@@ -826,7 +1625,16 @@ impl Gossipsub {
                 let desired_peers = self.config.mesh_n - peers.len();
                 let peer_list =
                     Self::get_random_peers(&self.topic_peers, topic_hash, desired_peers, {
-                        |peer| !peers.contains(peer)
+                        |peer| {
+                            !peers.contains(peer)
+                                && !explicit_peers.contains_key(peer)
+                                && score(peer) >= 0.0
+                                && !backoffs.is_backoff_with_slack(
+                                    topic_hash,
+                                    peer,
+                                    heartbeat_interval,
+                                )
+                        }
                     });
                 for peer in &peer_list {
                     let current_topic = to_graft.entry(peer.clone()).or_insert_with(Vec::new);
@@ -846,20 +1654,73 @@ impl Gossipsub {
                     self.config.mesh_n_high
                 );
                 let excess_peer_no = peers.len() - self.config.mesh_n;
-                // shuffle the peers
+                // shuffle the peers, then sort them by score so that the worst peers come first;
+                // the sort is stable, so peers with equal scores stay in random order
                 let mut rng = thread_rng();
                 let mut shuffled = peers.iter().cloned().collect::<Vec<_>>();
                 shuffled.shuffle(&mut rng);
+                shuffled.sort_by(|p1, p2| {
+                    score(p1)
+                        .partial_cmp(&score(p2))
+                        .unwrap_or(Ordering::Equal)
+                });
                 // remove the first excess_peer_no peers adding them to to_prune
-                for _ in 0..excess_peer_no {
-                    let peer = shuffled
-                        .pop()
-                        .expect("There should always be enough peers to remove");
+                for peer in shuffled.into_iter().take(excess_peer_no) {
                     peers.remove(&peer);
                     let current_topic = to_prune.entry(peer).or_insert_with(Vec::new);
                     current_topic.push(topic_hash.clone());
                 }
             }
+
+            // opportunistically graft better peers if the mesh is performing poorly
+            if let Some((_, thresholds, ..)) = peer_score {
+                if self.heartbeat_ticks % self.config.opportunistic_graft_ticks == 0
+                    && peers.len() > 1
+                {
+                    // compute the median score of the mesh
+                    let mut peers_by_score = peers.iter().collect::<Vec<_>>();
+                    peers_by_score.sort_by(|p1, p2| {
+                        score(p1)
+                            .partial_cmp(&score(p2))
+                            .unwrap_or(Ordering::Equal)
+                    });
+                    let middle = peers_by_score.len() / 2;
+                    let median = if peers_by_score.len() % 2 == 0 {
+                        (score(peers_by_score[middle - 1]) + score(peers_by_score[middle])) / 2.0
+                    } else {
+                        score(peers_by_score[middle])
+                    };
+
+                    // graft some peers that score better than the median
+                    if median < thresholds.opportunistic_graft_threshold {
+                        let peer_list = Self::get_random_peers(
+                            &self.topic_peers,
+                            topic_hash,
+                            self.config.opportunistic_graft_peers,
+                            |peer| {
+                                !peers.contains(peer)
+                                    && !explicit_peers.contains_key(peer)
+                                    && score(peer) > median
+                                    && !backoffs.is_backoff_with_slack(
+                                        topic_hash,
+                                        peer,
+                                        heartbeat_interval,
+                                    )
+                            },
+                        );
+                        for peer in &peer_list {
+                            let current_topic =
+                                to_graft.entry(peer.clone()).or_insert_with(Vec::new);
+                            current_topic.push(topic_hash.clone());
+                        }
+                        debug!(
+                            "HEARTBEAT: Opportunistically graft in topic {} with peers {:?}",
+                            topic_hash, peer_list
+                        );
+                        peers.extend(peer_list);
+                    }
+                }
+            }
         }
 
         // remove expired fanout topics
@@ -879,15 +1740,20 @@ impl Gossipsub {
             });
         }
 
+        let publish_threshold = match peer_score {
+            Some((_, thresholds, ..)) => thresholds.publish_threshold,
+            None => 0.0,
+        };
+
         // maintain fanout
         // check if our peers are still a part of the topic
         for (topic_hash, peers) in self.fanout.iter_mut() {
             let mut to_remove_peers = Vec::new();
             for peer in peers.iter() {
-                // is the peer still subscribed to the topic?
+                // is the peer still subscribed to the topic and does it still score well enough?
                 match self.peer_topics.get(peer) {
                     Some(topics) => {
-                        if !topics.contains(&topic_hash) {
+                        if !topics.contains(&topic_hash) || score(peer) < publish_threshold {
                             debug!(
                                 "HEARTBEAT: Peer removed from fanout for topic: {:?}",
                                 topic_hash
@@ -916,16 +1782,34 @@ impl Gossipsub {
                 let new_peers =
                     Self::get_random_peers(&self.topic_peers, topic_hash, needed_peers, |peer| {
                         !peers.contains(peer)
+                            && !explicit_peers.contains_key(peer)
+                            && score(peer) >= publish_threshold
                     });
                 peers.extend(new_peers);
             }
         }
 
+        // update the protection and the scores of the peers that join or leave the mesh
+        self.mesh_changes
+            .extend(to_graft.keys().chain(to_prune.keys()).cloned());
+        if let Some((peer_score, ..)) = &mut self.peer_score {
+            for (peer, topics) in to_graft.iter() {
+                for topic_hash in topics {
+                    peer_score.graft(peer, topic_hash.clone());
+                }
+            }
+            for (peer, topics) in to_prune.iter() {
+                for topic_hash in topics {
+                    peer_score.prune(peer, topic_hash.clone());
+                }
+            }
+        }
+
         self.emit_gossip();
 
         // send graft/prunes
         if !to_graft.is_empty() | !to_prune.is_empty() {
-            self.send_graft_prune(to_graft, to_prune);
+            self.send_graft_prune(to_graft, to_prune, no_px);
         }
 
         // piggyback pooled control messages
@@ -936,33 +1820,81 @@ impl Gossipsub {
         debug!("Completed Heartbeat");
     }
 
+    /// Protects the connections to the peers that joined a mesh since the last call from being
+    /// closed by the connection manager, and lifts the protection of those that left all meshes.
+    fn protect_mesh_peers(&mut self, connection_manager: &mut ConnectionManager) {
+        for peer_id in self.mesh_changes.drain() {
+            if self.mesh.values().any(|peers| peers.contains(&peer_id)) {
+                connection_manager.protect(&peer_id, MESH_PROTECTION_TAG);
+            } else {
+                connection_manager.unprotect(&peer_id, MESH_PROTECTION_TAG);
+            }
+        }
+    }
+
+    /// Drops the messages that have been awaiting their validation for longer than
+    /// `config.validation_timeout`, as if they had been ignored by the application.
+    fn drop_unvalidated_messages(&mut self) {
+        let timeout = self.config.validation_timeout;
+        let expired = self
+            .pending_validations
+            .iter()
+            .filter(|(_, (received, _))| received.elapsed() >= timeout)
+            .map(|(message_id, _)| message_id.clone())
+            .collect::<Vec<_>>();
+        for message_id in expired {
+            debug!("Validation timed out for message: {:?}", message_id);
+            self.pending_validations.remove(&message_id);
+            self.mcache.remove(&message_id);
+            if let Some((peer_score, ..)) = &mut self.peer_score {
+                peer_score.ignore_message(&message_id);
+            }
+        }
+    }
+
     /// Emits gossip - Send IHAVE messages to a random set of gossip peers. This is applied to mesh
     /// and fanout peers
     fn emit_gossip(&mut self) {
         for (topic_hash, peers) in self.mesh.iter().chain(self.fanout.iter()) {
             let message_ids = self.mcache.get_gossip_ids(&topic_hash);
             if message_ids.is_empty() {
-                return;
+                continue;
             }
 
-            // get gossip_lazy random peers
+            // get gossip_lazy random peers, excluding the explicit peers which receive all the
+            // messages, and those with a score below the gossip threshold
+            let peer_score = &self.peer_score;
+            let explicit_peers = &self.explicit_peers;
             let to_msg_peers = Self::get_random_peers(
                 &self.topic_peers,
                 &topic_hash,
                 self.config.gossip_lazy,
-                |peer| !peers.contains(peer),
+                |peer| {
+                    !peers.contains(peer)
+                        && !explicit_peers.contains_key(peer)
+                        && !score_below_threshold_from(peer_score, peer, |t| t.gossip_threshold).0
+                },
             );
 
             debug!("Gossiping IHAVE to {} peers.", to_msg_peers.len());
 
             for peer in to_msg_peers {
+                // don't announce the messages the peer told us through IDONTWANT it already has
+                let message_ids = message_ids
+                    .iter()
+                    .filter(|id| !self.peer_dont_want(&peer, id))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if message_ids.is_empty() {
+                    continue;
+                }
                 // send an IHAVE message
                 Self::control_pool_add(
                     &mut self.control_pool,
                     peer.clone(),
                     GossipsubControlAction::IHave {
                         topic_hash: topic_hash.clone(),
-                        message_ids: message_ids.clone(),
+                        message_ids,
                     },
                 );
             }
@@ -975,6 +1907,7 @@ impl Gossipsub {
         &mut self,
         to_graft: HashMap<PeerId, Vec<TopicHash>>,
         mut to_prune: HashMap<PeerId, Vec<TopicHash>>,
+        no_px: HashSet<PeerId>,
     ) {
         // handle the grafts and overlapping prunes per peer
         for (peer, topics) in to_graft.iter() {
@@ -987,11 +1920,10 @@ impl Gossipsub {
 
             // If there are prunes associated with the same peer add them.
             if let Some(topics) = to_prune.remove(peer) {
+                let do_px = self.config.do_px && !no_px.contains(peer);
                 let mut prunes = topics
                     .iter()
-                    .map(|topic_hash| GossipsubControlAction::Prune {
-                        topic_hash: topic_hash.clone(),
-                    })
+                    .map(|topic_hash| self.make_prune(topic_hash, peer, do_px))
                     .collect::<Vec<_>>();
                 control_msgs.append(&mut prunes);
             }
@@ -1009,11 +1941,10 @@ impl Gossipsub {
 
         // handle the remaining prunes
         for (peer, topics) in to_prune.iter() {
+            let do_px = self.config.do_px && !no_px.contains(peer);
             let remaining_prunes = topics
                 .iter()
-                .map(|topic_hash| GossipsubControlAction::Prune {
-                    topic_hash: topic_hash.clone(),
-                })
+                .map(|topic_hash| self.make_prune(topic_hash, peer, do_px))
                 .collect();
             self.send_message(
                 peer.clone(),
@@ -1026,21 +1957,25 @@ impl Gossipsub {
         }
     }
 
-    /// Helper function which forwards a message to mesh\[topic\] peers.
+    /// Helper function which forwards a message to mesh\[topic\] peers and to the explicit
+    /// peers subscribed to the topic.
     /// Returns true if at least one peer was messaged.
     fn forward_msg(&mut self, message: GossipsubMessage, source: Option<&PeerId>) -> bool {
         let msg_id = (self.config.message_id_fn)(&message);
         debug!("Forwarding message: {:?}", msg_id);
         let mut recipient_peers = HashSet::new();
 
-        // add mesh peers
+        // add mesh and explicit peers, except those which told us they already have the message
         for topic in &message.topics {
-            // mesh
-            if let Some(mesh_peers) = self.mesh.get(&topic) {
-                for peer_id in mesh_peers {
-                    if Some(peer_id) != source {
-                        recipient_peers.insert(peer_id.clone());
-                    }
+            let mesh_peers = self.mesh.get(topic).into_iter().flatten();
+            let explicit_peers = self.topic_peers.get(topic).into_iter().flat_map(|peers| {
+                self.explicit_peers
+                    .keys()
+                    .filter(move |p| peers.contains(*p))
+            });
+            for peer_id in mesh_peers.chain(explicit_peers) {
+                if Some(peer_id) != source && !self.peer_dont_want(peer_id, &msg_id) {
+                    recipient_peers.insert(peer_id.clone());
                 }
             }
         }
@@ -1195,6 +2130,16 @@ impl Gossipsub {
             .push(control);
     }
 
+    /// Returns whether the score of a peer is below the threshold selected by `threshold`, along
+    /// with the score itself. Always false if peer scoring is disabled.
+    fn score_below_threshold(
+        &self,
+        peer_id: &PeerId,
+        threshold: impl Fn(&PeerScoreThresholds) -> f64,
+    ) -> (bool, f64) {
+        score_below_threshold_from(&self.peer_score, peer_id, threshold)
+    }
+
     /// Produces a `TopicHash` for a topic given the gossipsub configuration.
     fn topic_hash(&self, topic: Topic) -> TopicHash {
         if self.config.hash_topics {
@@ -1236,14 +2181,22 @@ impl NetworkBehaviour for Gossipsub {
 
     fn new_handler(&mut self) -> Self::ProtocolsHandler {
         GossipsubHandler::new(
-            self.config.protocol_id.clone(),
+            &self.config.protocol_id_prefix,
             self.config.max_transmit_size,
             self.config.validation_mode.clone(),
         )
     }
 
-    fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
-        Vec::new()
+    fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        let mut addresses = self
+            .explicit_peers
+            .get(peer_id)
+            .cloned()
+            .unwrap_or_default();
+        if let Some(record) = self.peer_records.get(peer_id) {
+            add_addresses(&mut addresses, record.addresses());
+        }
+        addresses
     }
 
     fn inject_connected(&mut self, id: &PeerId) {
@@ -1271,6 +2224,10 @@ impl NetworkBehaviour for Gossipsub {
 
         // For the time being assume all gossipsub peers
         self.peer_topics.insert(id.clone(), Default::default());
+
+        if let Some((peer_score, ..)) = &mut self.peer_score {
+            peer_score.add_peer(id.clone());
+        }
     }
 
     fn inject_disconnected(&mut self, id: &PeerId) {
@@ -1290,7 +2247,9 @@ impl NetworkBehaviour for Gossipsub {
                 // check the mesh for the topic
                 if let Some(mesh_peers) = self.mesh.get_mut(&topic) {
                     // check if the peer is in the mesh and remove it
-                    mesh_peers.remove(id);
+                    if mesh_peers.remove(id) {
+                        self.mesh_changes.insert(id.clone());
+                    }
                 }
 
                 // remove from topic_peers
@@ -1311,12 +2270,85 @@ impl NetworkBehaviour for Gossipsub {
             }
         }
 
-        // remove peer from peer_topics
+        // remove peer from peer_topics, peer_protocols and peer_dont_send
         let was_in = self.peer_topics.remove(id);
         debug_assert!(was_in.is_some());
+        self.peer_protocols.remove(id);
+        self.peer_dont_send.remove(id);
+        self.peer_records.remove(id);
+
+        // the scoring applies the penalties of the meshes the peer was part of
+        if let Some((peer_score, ..)) = &mut self.peer_score {
+            peer_score.remove_peer(id);
+        }
     }
 
-    fn inject_event(&mut self, propagation_source: PeerId, _: ConnectionId, event: GossipsubRpc) {
+    fn inject_connection_established(
+        &mut self,
+        peer_id: &PeerId,
+        _: &ConnectionId,
+        endpoint: &ConnectedPoint,
+    ) {
+        // track the IP of the peer for the IP colocation penalty
+        if let Some((peer_score, ..)) = &mut self.peer_score {
+            if let Some(ip) = get_ip_addr(endpoint.get_remote_address()) {
+                peer_score.add_ip(peer_id, ip);
+            }
+        }
+    }
+
+    fn inject_connection_closed(
+        &mut self,
+        peer_id: &PeerId,
+        _: &ConnectionId,
+        endpoint: &ConnectedPoint,
+    ) {
+        if let Some((peer_score, ..)) = &mut self.peer_score {
+            if let Some(ip) = get_ip_addr(endpoint.get_remote_address()) {
+                peer_score.remove_ip(peer_id, &ip);
+            }
+        }
+    }
+
+    fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+        self.peer_records.remove(peer_id);
+    }
+
+    fn inject_event(&mut self, propagation_source: PeerId, _: ConnectionId, event: HandlerEvent) {
+        let (event, invalid_messages) = match event {
+            HandlerEvent::PeerKind(kind) => {
+                debug!("Peer {:?} speaks {:?}", propagation_source, kind);
+                self.peer_protocols.insert(propagation_source, kind);
+                return;
+            }
+            HandlerEvent::Message {
+                rpc,
+                invalid_messages,
+            } => (rpc, invalid_messages),
+        };
+
+        // Messages that failed validation in the handler count against the sender's score
+        for (message, validation_error) in invalid_messages {
+            warn!(
+                "Invalid message from peer: {:?}. Reason: {:?}",
+                propagation_source, validation_error
+            );
+            if let Some((peer_score, ..)) = &mut self.peer_score {
+                peer_score.reject_invalid_message(&propagation_source, &message.topics);
+            }
+        }
+
+        // Ignore any RPC from peers whose score is below the graylist threshold
+        if let (true, score) =
+            self.score_below_threshold(&propagation_source, |t| t.graylist_threshold)
+        {
+            debug!(
+                "RPC: ignoring RPC from graylisted peer {:?} [score = {}]",
+                propagation_source, score
+            );
+            return;
+        }
+
         // Handle subscriptions
         // Update connected peers topics
         if !event.subscriptions.is_empty() {
@@ -1345,7 +2377,14 @@ impl NetworkBehaviour for Gossipsub {
                     self.handle_iwant(&propagation_source, message_ids)
                 }
                 GossipsubControlAction::Graft { topic_hash } => graft_msgs.push(topic_hash),
-                GossipsubControlAction::Prune { topic_hash } => prune_msgs.push(topic_hash),
+                GossipsubControlAction::Prune {
+                    topic_hash,
+                    peers,
+                    backoff,
+                } => prune_msgs.push((topic_hash, peers, backoff)),
+                GossipsubControlAction::IDontWant { message_ids } => {
+                    self.handle_idontwant(&propagation_source, message_ids)
+                }
             }
         }
         if !ihave_msgs.is_empty() {
@@ -1362,13 +2401,15 @@ impl NetworkBehaviour for Gossipsub {
     fn poll(
         &mut self,
         cx: &mut Context<'_>,
-        _: &mut impl PollParameters,
+        params: &mut impl PollParameters,
     ) -> Poll<
         NetworkBehaviourAction<
             <Self::ProtocolsHandler as ProtocolsHandler>::InEvent,
             Self::OutEvent,
         >,
     > {
+        self.protect_mesh_peers(params.connection_manager());
+
         if let Some(event) = self.events.pop_front() {
             return Poll::Ready(match event {
                 NetworkBehaviourAction::NotifyHandler {
@@ -1399,6 +2440,13 @@ impl NetworkBehaviour for Gossipsub {
             });
         }
 
+        // update the scores
+        if let Some((peer_score, _, interval, _)) = &mut self.peer_score {
+            while let Poll::Ready(Some(())) = interval.poll_next_unpin(cx) {
+                peer_score.refresh_scores();
+            }
+        }
+
         while let Poll::Ready(Some(())) = self.heartbeat.poll_next_unpin(cx) {
             self.heartbeat();
         }
@@ -1407,6 +2455,30 @@ impl NetworkBehaviour for Gossipsub {
     }
 }
 
+/// Same as [`Gossipsub::score_below_threshold`], but only borrows the peer score so that it can
+/// be used while other fields of the behaviour are borrowed.
+fn score_below_threshold_from(
+    peer_score: &Option<(PeerScore, PeerScoreThresholds, Interval, GossipPromises)>,
+    peer_id: &PeerId,
+    threshold: impl Fn(&PeerScoreThresholds) -> f64,
+) -> (bool, f64) {
+    if let Some((peer_score, thresholds, ..)) = peer_score {
+        let score = peer_score.score(peer_id);
+        (score < threshold(thresholds), score)
+    } else {
+        (false, 0.0)
+    }
+}
+
+/// Extracts the IP address of a multiaddress, if any.
+fn get_ip_addr(addr: &Multiaddr) -> Option<IpAddr> {
+    addr.iter().find_map(|p| match p {
+        Protocol::Ip4(addr) => Some(addr.into()),
+        Protocol::Ip6(addr) => Some(addr.into()),
+        _ => None,
+    })
+}
+
 /// An RPC received/sent.
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct GossipsubRpc {
@@ -1457,10 +2529,28 @@ pub enum GossipsubEvent {
         /// The topic it has subscribed from.
         topic: TopicHash,
     },
+
+    /// A message has been rejected through [`Gossipsub::report_message_validation_result`] and
+    /// dropped.
+    MessageRejected {
+        /// The id of the rejected message.
+        message_id: MessageId,
+        /// The peers that sent us the message, starting with the one it was first received from.
+        peers: Vec<PeerId>,
+    },
 }
 
 /// Validates the combination of signing, privacy and message validation to ensure the
 /// configuration will not reject published messages.
+/// Adds the given addresses to the known ones, skipping duplicates.
+fn add_addresses(known: &mut Vec<Multiaddr>, addresses: &[Multiaddr]) {
+    for address in addresses {
+        if !known.contains(address) {
+            known.push(address.clone());
+        }
+    }
+}
+
 fn validate_config(authenticity: &MessageAuthenticity, validation_mode: &ValidationMode) {
     match validation_mode {
         ValidationMode::Anonymous => {
@@ -1500,6 +2590,8 @@ impl fmt::Debug for Gossipsub {
          .field("fanout", &self.fanout)
          .field("fanout_last_pub", &self.fanout_last_pub)
          .field("mcache", &self.mcache)
+         .field("explicit_peers", &self.explicit_peers)
+         .field("pending_validations", &self.pending_validations)
          .field("heartbeat", &self.heartbeat)
          .finish()
     }
@@ -115,6 +115,32 @@ mod tests {
         );
     }
 
+    #[test]
+    /// Test the accessors of the subscribed topics and their meshes.
+    fn test_topics_and_mesh_peers() {
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("topic1")], true);
+        gs.heartbeat();
+
+        assert_eq!(gs.topics().collect::<Vec<_>>(), vec![&topic_hashes[0]]);
+        let mesh_peers = gs.mesh_peers(&topic_hashes[0]).collect::<Vec<_>>();
+        assert!(!mesh_peers.is_empty());
+        assert!(mesh_peers.iter().all(|p| peers.contains(p)));
+        assert_eq!(
+            mesh_peers,
+            gs.mesh
+                .get(&topic_hashes[0])
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            gs.mesh_peers(&Topic::new(String::from("topic2")).no_hash())
+                .count(),
+            0
+        );
+    }
+
     #[test]
     /// Test unsubscribe.
     fn test_unsubscribe() {
@@ -297,6 +323,8 @@ mod tests {
         let publish_topic = String::from("test_publish");
         let (mut gs, _, topic_hashes) =
             build_and_inject_nodes(20, vec![publish_topic.clone()], true);
+        // only publish to the mesh and fanout peers
+        gs.config.flood_publish = false;
 
         assert!(
             gs.mesh.get(&topic_hashes[0]).is_some(),
@@ -355,6 +383,8 @@ mod tests {
         let fanout_topic = String::from("test_fanout");
         let (mut gs, _, topic_hashes) =
             build_and_inject_nodes(20, vec![fanout_topic.clone()], true);
+        // only publish to the mesh and fanout peers
+        gs.config.flood_publish = false;
 
         assert!(
             gs.mesh.get(&topic_hashes[0]).is_some(),
@@ -840,7 +870,13 @@ mod tests {
             "Expected peer to be in mesh"
         );
 
-        gs.handle_prune(&peers[7], topic_hashes.clone());
+        gs.handle_prune(
+            &peers[7],
+            topic_hashes
+                .iter()
+                .map(|h| (h.clone(), vec![], None))
+                .collect(),
+        );
         assert!(
             !gs.mesh.get(&topic_hashes[0]).unwrap().contains(&peers[7]),
             "Expected peer to be removed from mesh"
@@ -859,7 +895,10 @@ mod tests {
         let to_remove_peers = config.mesh_n + 1 - config.mesh_n_low - 1;
 
         for index in 0..to_remove_peers {
-            gs.handle_prune(&peers[index], topics.clone());
+            gs.handle_prune(
+                &peers[index],
+                topics.iter().map(|h| (h.clone(), vec![], None)).collect(),
+            );
         }
 
         // Verify the pruned peers are removed from the mesh.
@@ -884,10 +923,8 @@ mod tests {
         let (mut gs, peers, topics) =
             build_and_inject_nodes(config.mesh_n_high + 10, vec!["test".into()], true);
 
-        // graft all the peers
-        for peer in peers {
-            gs.handle_graft(&peer, topics.clone());
-        }
+        // add all the peers to the mesh, beyond what grafting allows
+        gs.mesh.get_mut(&topics[0]).unwrap().extend(peers);
 
         // run a heartbeat
         gs.heartbeat();
@@ -895,4 +932,1003 @@ mod tests {
         // Peers should be removed to reach mesh_n
         assert_eq!(gs.mesh.get(&topics[0]).unwrap().len(), config.mesh_n);
     }
+
+    // Enables peer scoring on `gs`, where the score of a peer only consists of its application
+    // specific score.
+    fn enable_app_score(gs: &mut Gossipsub) {
+        let params = PeerScoreParams {
+            app_specific_weight: 1.0,
+            ..PeerScoreParams::default()
+        };
+        gs.with_peer_score(params, PeerScoreThresholds::default())
+            .expect("Valid peer score parameters");
+    }
+
+    #[test]
+    // tests the peer score API with and without scoring
+    fn test_peer_score_api() {
+        let (mut gs, peers, _) = build_and_inject_nodes(5, vec![String::from("topic1")], true);
+
+        assert_eq!(gs.peer_score(&peers[0]), None);
+        assert!(!gs.set_application_score(&peers[0], 1.0));
+
+        enable_app_score(&mut gs);
+        assert_eq!(gs.peer_score(&peers[0]), Some(0.0));
+        assert!(gs.set_application_score(&peers[0], 3.0));
+        assert_eq!(gs.peer_score(&peers[0]), Some(3.0));
+
+        assert!(
+            gs.with_peer_score(PeerScoreParams::default(), PeerScoreThresholds::default())
+                .is_err(),
+            "Peer scoring can only be enabled once"
+        );
+    }
+
+    #[test]
+    // tests that a GRAFT from a peer with a negative score is refused with a PRUNE
+    fn test_handle_graft_negative_score() {
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("topic1")], true);
+        enable_app_score(&mut gs);
+        gs.mesh.get_mut(&topic_hashes[0]).unwrap().clear();
+        gs.set_application_score(&peers[7], -1.0);
+
+        gs.handle_graft(&peers[7], topic_hashes.clone());
+
+        assert!(
+            !gs.mesh.get(&topic_hashes[0]).unwrap().contains(&peers[7]),
+            "Expected peer with negative score not to be added to the mesh"
+        );
+        let pruned = gs
+            .events
+            .iter()
+            .any(|e| match e {
+                NetworkBehaviourAction::NotifyHandler { peer_id, event, .. } => {
+                    peer_id == &peers[7]
+                        && event.control_msgs.iter().any(|c| match c {
+                            GossipsubControlAction::Prune { topic_hash, .. } => {
+                                topic_hash == &topic_hashes[0]
+                            }
+                            _ => false,
+                        })
+                }
+                _ => false,
+            });
+        assert!(pruned, "Expected a PRUNE to be sent to the peer");
+    }
+
+    #[test]
+    // tests that the heartbeat removes mesh peers with a negative score and doesn't graft them
+    // back
+    fn test_heartbeat_prunes_negative_score_peers() {
+        let (mut gs, _, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("topic1")], true);
+        enable_app_score(&mut gs);
+
+        let mesh_peers = gs
+            .mesh
+            .get(&topic_hashes[0])
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        assert!(!mesh_peers.is_empty());
+        for peer in &mesh_peers {
+            gs.set_application_score(peer, -1.0);
+        }
+
+        gs.heartbeat();
+
+        let mesh = gs.mesh.get(&topic_hashes[0]).unwrap();
+        assert!(
+            mesh_peers.iter().all(|p| !mesh.contains(p)),
+            "Expected peers with a negative score to be pruned from the mesh"
+        );
+        assert_eq!(mesh.len(), GossipsubConfig::default().mesh_n);
+    }
+
+    #[test]
+    // tests that the heartbeat keeps the best scoring peers when the mesh is oversubscribed
+    fn test_mesh_subtraction_keeps_best_scoring_peers() {
+        let config = GossipsubConfig::default();
+        let (mut gs, peers, topics) =
+            build_and_inject_nodes(config.mesh_n_high + 10, vec!["test".into()], true);
+        enable_app_score(&mut gs);
+
+        for (index, peer) in peers.iter().enumerate() {
+            gs.set_application_score(peer, index as f64);
+        }
+        // add all the peers to the mesh, beyond what grafting allows
+        gs.mesh.get_mut(&topics[0]).unwrap().extend(peers.iter().cloned());
+
+        gs.heartbeat();
+
+        let mesh = gs.mesh.get(&topics[0]).unwrap();
+        assert_eq!(mesh.len(), config.mesh_n);
+        for peer in peers.iter().rev().take(config.mesh_n) {
+            assert!(mesh.contains(peer), "Expected the best peers to stay in the mesh");
+        }
+    }
+
+    #[test]
+    // tests that we ignore IHAVE gossip from peers below the gossip threshold
+    fn test_handle_ihave_below_gossip_threshold() {
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("topic1")], true);
+        enable_app_score(&mut gs);
+        let gossip_threshold = PeerScoreThresholds::default().gossip_threshold;
+        gs.set_application_score(&peers[7], gossip_threshold - 1.0);
+
+        gs.handle_ihave(
+            &peers[7],
+            vec![(topic_hashes[0].clone(), vec![MessageId::new(b"unknown id")])],
+        );
+
+        assert!(
+            gs.control_pool.get(&peers[7]).is_none(),
+            "Expected no IWANT to be sent to a peer below the gossip threshold"
+        );
+    }
+
+    #[test]
+    // tests that peers not following up on their IHAVE advertisements get penalised
+    fn test_broken_iwant_promise_is_penalised() {
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("topic1")], true);
+        let params = PeerScoreParams {
+            behaviour_penalty_weight: -1.0,
+            ..PeerScoreParams::default()
+        };
+        gs.with_peer_score(params, PeerScoreThresholds::default())
+            .unwrap();
+        gs.config.iwant_followup_time = std::time::Duration::from_secs(0);
+
+        gs.handle_ihave(
+            &peers[7],
+            vec![(topic_hashes[0].clone(), vec![MessageId::new(b"unknown id")])],
+        );
+        gs.heartbeat();
+
+        assert_eq!(gs.peer_score(&peers[7]), Some(-1.0));
+        assert_eq!(gs.peer_score(&peers[8]), Some(0.0));
+    }
+
+    #[test]
+    // tests that RPCs of graylisted peers are ignored
+    fn test_graylisted_peer_is_ignored() {
+        let (mut gs, peers, _) = build_and_inject_nodes(20, vec![String::from("topic1")], true);
+        enable_app_score(&mut gs);
+        let graylist_threshold = PeerScoreThresholds::default().graylist_threshold;
+        gs.set_application_score(&peers[7], graylist_threshold - 1.0);
+
+        let topic_hash = TopicHash::from_raw("topic2");
+        let rpc = GossipsubRpc {
+            messages: Vec::new(),
+            subscriptions: vec![GossipsubSubscription {
+                action: GossipsubSubscriptionAction::Subscribe,
+                topic_hash: topic_hash.clone(),
+            }],
+            control_msgs: Vec::new(),
+        };
+        gs.inject_event(
+            peers[7].clone(),
+            ConnectionId::new(0),
+            HandlerEvent::Message {
+                rpc,
+                invalid_messages: Vec::new(),
+            },
+        );
+
+        assert!(
+            !gs.peer_topics.get(&peers[7]).unwrap().contains(&topic_hash),
+            "Expected the subscription of a graylisted peer to be ignored"
+        );
+    }
+
+    // Counts the peers dialed by the behaviour.
+    fn count_dialed_peers(gs: &Gossipsub) -> usize {
+        gs.events
+            .iter()
+            .filter(|e| match e {
+                NetworkBehaviourAction::DialPeer { .. } => true,
+                _ => false,
+            })
+            .count()
+    }
+
+    // Collects the PRUNEs sent to `peer`.
+    fn collect_prunes(gs: &Gossipsub, peer: &PeerId) -> Vec<GossipsubControlAction> {
+        gs.events
+            .iter()
+            .filter_map(|e| match e {
+                NetworkBehaviourAction::NotifyHandler { peer_id, event, .. } if peer_id == peer => {
+                    Some(event.control_msgs.clone())
+                }
+                _ => None,
+            })
+            .flatten()
+            .chain(gs.control_pool.get(peer).cloned().unwrap_or_default())
+            .filter(|c| match c {
+                GossipsubControlAction::Prune { .. } => true,
+                _ => false,
+            })
+            .collect()
+    }
+
+    #[test]
+    // tests that the version of the protocol reported by the handler is tracked
+    fn test_inject_peer_kind() {
+        let (mut gs, peers, _) = build_and_inject_nodes(2, vec![String::from("topic1")], true);
+
+        gs.inject_event(
+            peers[0].clone(),
+            ConnectionId::new(0),
+            HandlerEvent::PeerKind(PeerKind::Gossipsubv1_1),
+        );
+        assert_eq!(
+            gs.peer_protocols.get(&peers[0]),
+            Some(&PeerKind::Gossipsubv1_1)
+        );
+
+        gs.inject_disconnected(&peers[0]);
+        assert!(gs.peer_protocols.get(&peers[0]).is_none());
+    }
+
+    #[test]
+    // tests that PRUNEs only carry peer exchange and backoff for gossipsub v1.1 peers
+    fn test_prune_px_only_sent_to_v1_1_peers() {
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("topic1")], true);
+        gs.config.do_px = true;
+        gs.peer_protocols
+            .insert(peers[0].clone(), PeerKind::Gossipsubv1_1);
+        gs.peer_protocols.insert(peers[1].clone(), PeerKind::Gossipsub);
+
+        match gs.make_prune(&topic_hashes[0], &peers[0], true) {
+            GossipsubControlAction::Prune { peers: px, backoff, .. } => {
+                assert!(!px.is_empty(), "Expected peer exchange for a v1.1 peer");
+                assert!(px.iter().all(|p| p.peer_id.as_ref() != Some(&peers[0])));
+                assert_eq!(backoff, Some(gs.config.prune_backoff.as_secs()));
+            }
+            _ => panic!("Expected a PRUNE"),
+        }
+
+        match gs.make_prune(&topic_hashes[0], &peers[1], true) {
+            GossipsubControlAction::Prune { peers: px, backoff, .. } => {
+                assert!(px.is_empty(), "Expected no peer exchange for a v1.0 peer");
+                assert_eq!(backoff, None);
+            }
+            _ => panic!("Expected a PRUNE"),
+        }
+    }
+
+    #[test]
+    // tests that a full mesh refuses a grafting peer with peer exchange
+    fn test_graft_on_full_mesh_is_refused_with_px() {
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("topic1")], true);
+        gs.config.do_px = true;
+        for peer in &peers {
+            gs.peer_protocols.insert(peer.clone(), PeerKind::Gossipsubv1_1);
+        }
+        let mesh_n_high = gs.config.mesh_n_high;
+        let mesh = gs.mesh.get_mut(&topic_hashes[0]).unwrap();
+        mesh.clear();
+        mesh.extend(peers[.. mesh_n_high].iter().cloned());
+
+        let grafting = &peers[mesh_n_high];
+        gs.handle_graft(grafting, topic_hashes.clone());
+
+        assert!(
+            !gs.mesh.get(&topic_hashes[0]).unwrap().contains(grafting),
+            "Expected the peer not to be added to the full mesh"
+        );
+        match &collect_prunes(&gs, grafting)[..] {
+            [GossipsubControlAction::Prune { peers: px, .. }] => {
+                assert!(!px.is_empty(), "Expected peer exchange in the PRUNE");
+            }
+            other => panic!("Expected a single PRUNE, got {:?}", other),
+        }
+    }
+
+    #[test]
+    // tests that a peer grafting during its backoff is refused
+    fn test_graft_during_backoff_is_refused() {
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("topic1")], true);
+
+        gs.handle_prune(
+            &peers[7],
+            vec![(topic_hashes[0].clone(), vec![], Some(60))],
+        );
+        gs.handle_graft(&peers[7], topic_hashes.clone());
+
+        assert!(
+            !gs.mesh.get(&topic_hashes[0]).unwrap().contains(&peers[7]),
+            "Expected the backed off peer not to be added to the mesh"
+        );
+        assert!(
+            !collect_prunes(&gs, &peers[7]).is_empty(),
+            "Expected a PRUNE to be sent to the backed off peer"
+        );
+    }
+
+    #[test]
+    // tests that the backoff requested by a pruning peer is capped
+    fn test_prune_backoff_is_capped() {
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("topic1")], true);
+
+        gs.handle_prune(
+            &peers[7],
+            vec![(topic_hashes[0].clone(), vec![], Some(u64::MAX))],
+        );
+
+        let max_backoff = gs.config.prune_backoff * MAX_REMOTE_BACKOFF_FACTOR;
+        assert!(gs.backoffs.is_backoff(&topic_hashes[0], &peers[7]));
+        assert!(!gs
+            .backoffs
+            .is_backoff_with_slack(&topic_hashes[0], &peers[7], max_backoff));
+    }
+
+    #[test]
+    // tests that a PRUNE for a topic we are not subscribed to is ignored
+    fn test_prune_for_unsubscribed_topic_is_ignored() {
+        let (mut gs, peers, _) = build_and_inject_nodes(20, vec![String::from("topic1")], true);
+        let topic_hash = Topic::new("unsubscribed".into()).no_hash();
+
+        let px = vec![PeerInfo {
+            peer_id: Some(PeerId::random()),
+            signed_peer_record: None,
+        }];
+        gs.handle_prune(&peers[7], vec![(topic_hash.clone(), px, Some(60))]);
+
+        assert!(!gs.backoffs.is_backoff(&topic_hash, &peers[7]));
+        assert_eq!(count_dialed_peers(&gs), 0);
+    }
+
+    #[test]
+    // tests that the signed peer records received through peer exchange are dialed and passed on
+    fn test_px_signed_peer_records() {
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("topic1")], true);
+        gs.config.do_px = true;
+
+        let key = Keypair::generate_ed25519();
+        let px_peer = key.public().into_peer_id();
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        let record = PeerRecord::new(&key, vec![addr.clone()]).unwrap();
+        let px = vec![PeerInfo {
+            peer_id: Some(px_peer.clone()),
+            signed_peer_record: Some(record.clone()),
+        }];
+        gs.handle_prune(&peers[7], vec![(topic_hashes[0].clone(), px, None)]);
+
+        assert_eq!(count_dialed_peers(&gs), 1);
+        assert_eq!(gs.addresses_of_peer(&px_peer), vec![addr]);
+
+        // once connected, the record is passed on to the peers pruned with peer exchange
+        gs.inject_connected(&px_peer);
+        gs.handle_received_subscriptions(
+            &[GossipsubSubscription {
+                action: GossipsubSubscriptionAction::Subscribe,
+                topic_hash: topic_hashes[0].clone(),
+            }],
+            &px_peer,
+        );
+        gs.peer_protocols
+            .insert(peers[0].clone(), PeerKind::Gossipsubv1_1);
+        gs.config.prune_peers = 100;
+        match gs.make_prune(&topic_hashes[0], &peers[0], true) {
+            GossipsubControlAction::Prune { peers: px, .. } => {
+                let info = px
+                    .iter()
+                    .find(|p| p.peer_id.as_ref() == Some(&px_peer))
+                    .expect("Expected the peer in the peer exchange");
+                assert_eq!(info.signed_peer_record, Some(record));
+            }
+            _ => panic!("Expected a PRUNE"),
+        }
+
+        // the record is dropped when the peer disconnects
+        gs.inject_disconnected(&px_peer);
+        assert!(gs.addresses_of_peer(&px_peer).is_empty());
+    }
+
+    #[test]
+    // tests that a pruned peer is not grafted again by the heartbeat during its backoff
+    fn test_heartbeat_respects_backoff() {
+        let config = GossipsubConfig::default();
+        let (mut gs, peers, topics) =
+            build_and_inject_nodes(config.mesh_n_low, vec!["test".into()], true);
+        assert_eq!(gs.mesh.get(&topics[0]).unwrap().len(), config.mesh_n_low);
+
+        gs.handle_prune(&peers[0], vec![(topics[0].clone(), vec![], None)]);
+        gs.heartbeat();
+
+        assert!(
+            !gs.mesh.get(&topics[0]).unwrap().contains(&peers[0]),
+            "Expected the pruned peer not to be grafted again"
+        );
+    }
+
+    #[test]
+    // tests that the peers received through peer exchange get dialed
+    fn test_px_dials_suggested_peers() {
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("topic1")], true);
+
+        let px = vec![
+            PeerInfo {
+                peer_id: Some(PeerId::random()),
+                signed_peer_record: None,
+            },
+            PeerInfo {
+                peer_id: Some(PeerId::random()),
+                signed_peer_record: None,
+            },
+            // already connected
+            PeerInfo {
+                peer_id: Some(peers[3].clone()),
+                signed_peer_record: None,
+            },
+            PeerInfo {
+                peer_id: None,
+                signed_peer_record: None,
+            },
+        ];
+        gs.handle_prune(&peers[7], vec![(topic_hashes[0].clone(), px, None)]);
+
+        assert_eq!(count_dialed_peers(&gs), 2);
+    }
+
+    #[test]
+    // tests that peer exchange from peers below the accept px threshold is ignored
+    fn test_px_ignored_below_accept_threshold() {
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("topic1")], true);
+        enable_app_score(&mut gs);
+
+        let px = vec![PeerInfo {
+            peer_id: Some(PeerId::random()),
+            signed_peer_record: None,
+        }];
+        gs.handle_prune(&peers[7], vec![(topic_hashes[0].clone(), px.clone(), None)]);
+        assert_eq!(count_dialed_peers(&gs), 0);
+
+        let accept_px_threshold = PeerScoreThresholds::default().accept_px_threshold;
+        gs.set_application_score(&peers[8], accept_px_threshold);
+        gs.handle_prune(&peers[8], vec![(topic_hashes[0].clone(), px, None)]);
+        assert_eq!(count_dialed_peers(&gs), 1);
+    }
+
+    // Collects the peers which have been sent at least one message.
+    fn collect_message_recipients(gs: &Gossipsub) -> HashSet<PeerId> {
+        gs.events
+            .iter()
+            .filter_map(|e| match e {
+                NetworkBehaviourAction::NotifyHandler { peer_id, event, .. }
+                    if !event.messages.is_empty() =>
+                {
+                    Some(peer_id.clone())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Collects the peers which have been sent an IDONTWANT.
+    fn collect_idontwant_recipients(gs: &Gossipsub) -> HashSet<PeerId> {
+        gs.events
+            .iter()
+            .filter_map(|e| match e {
+                NetworkBehaviourAction::NotifyHandler { peer_id, event, .. } => {
+                    if event.control_msgs.iter().any(|c| match c {
+                        GossipsubControlAction::IDontWant { .. } => true,
+                        _ => false,
+                    }) {
+                        Some(peer_id.clone())
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Builds a message of `len` bytes on `topic` authored by `source`.
+    fn build_message(source: &PeerId, topic: &TopicHash, len: usize) -> GossipsubMessage {
+        GossipsubMessage {
+            source: Some(source.clone()),
+            data: vec![7; len],
+            sequence_number: Some(rand::random()),
+            topics: vec![topic.clone()],
+            signature: None,
+            key: None,
+            validated: true,
+        }
+    }
+
+    #[test]
+    // tests that with flood publishing our own messages are sent to all the subscribed peers
+    fn test_flood_publish() {
+        let topic = String::from("test_flood_publish");
+        let (mut gs, peers, topic_hashes) = build_and_inject_nodes(20, vec![topic.clone()], true);
+        assert!(gs.mesh.get(&topic_hashes[0]).unwrap().len() < peers.len());
+
+        gs.publish(&Topic::new(topic), vec![0; 42]).unwrap();
+
+        assert_eq!(
+            collect_message_recipients(&gs),
+            peers.into_iter().collect::<HashSet<_>>(),
+            "Expected the message to be sent to all the subscribed peers"
+        );
+    }
+
+    #[test]
+    // tests that flood publishing skips the peers whose score is below the publish threshold
+    fn test_flood_publish_respects_publish_threshold() {
+        let topic = String::from("test_flood_publish");
+        let (mut gs, peers, _) = build_and_inject_nodes(20, vec![topic.clone()], true);
+        enable_app_score(&mut gs);
+
+        let publish_threshold = PeerScoreThresholds::default().publish_threshold;
+        gs.set_application_score(&peers[0], publish_threshold - 1.0);
+
+        gs.publish(&Topic::new(topic), vec![0; 42]).unwrap();
+
+        let recipients = collect_message_recipients(&gs);
+        assert!(!recipients.contains(&peers[0]));
+        assert_eq!(recipients.len(), peers.len() - 1);
+    }
+
+    #[test]
+    // tests that large received messages are announced through IDONTWANT to the v1.2 mesh peers
+    fn test_idontwant_sent_to_v1_2_mesh_peers() {
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("topic1")], true);
+        for peer in &peers {
+            gs.peer_protocols
+                .insert(peer.clone(), PeerKind::Gossipsubv1_2);
+        }
+        let mesh_peers = gs.mesh.get(&topic_hashes[0]).unwrap().clone();
+        let mut mesh_iter = mesh_peers.iter();
+        let source = mesh_iter.next().unwrap().clone();
+        let v1_1_peer = mesh_iter.next().unwrap().clone();
+        gs.peer_protocols
+            .insert(v1_1_peer.clone(), PeerKind::Gossipsubv1_1);
+
+        // small messages are not announced
+        let threshold = gs.config.idontwant_message_size_threshold;
+        let small = build_message(&peers[0], &topic_hashes[0], threshold - 1);
+        gs.handle_received_message(small, &source);
+        assert!(collect_idontwant_recipients(&gs).is_empty());
+
+        let large = build_message(&peers[0], &topic_hashes[0], threshold);
+        gs.handle_received_message(large, &source);
+
+        let expected = mesh_peers
+            .into_iter()
+            .filter(|p| p != &source && p != &v1_1_peer)
+            .collect::<HashSet<_>>();
+        assert!(!expected.is_empty());
+        assert_eq!(collect_idontwant_recipients(&gs), expected);
+    }
+
+    #[test]
+    // tests that messages announced through IDONTWANT are not forwarded to the announcing peer
+    fn test_idontwant_suppresses_forwarding() {
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("topic1")], true);
+        let mesh_peers = gs.mesh.get(&topic_hashes[0]).unwrap().clone();
+        let mut mesh_iter = mesh_peers.iter();
+        let source = mesh_iter.next().unwrap().clone();
+        let announcer = mesh_iter.next().unwrap().clone();
+
+        let message = build_message(&peers[0], &topic_hashes[0], 42);
+        let msg_id = (gs.config.message_id_fn)(&message);
+        gs.inject_event(
+            announcer.clone(),
+            ConnectionId::new(0),
+            HandlerEvent::Message {
+                rpc: GossipsubRpc {
+                    subscriptions: Vec::new(),
+                    messages: Vec::new(),
+                    control_msgs: vec![GossipsubControlAction::IDontWant {
+                        message_ids: vec![msg_id],
+                    }],
+                },
+                invalid_messages: Vec::new(),
+            },
+        );
+
+        gs.handle_received_message(message, &source);
+
+        let recipients = collect_message_recipients(&gs);
+        assert!(!recipients.contains(&announcer));
+        assert!(!recipients.contains(&source));
+        assert_eq!(recipients.len(), mesh_peers.len() - 2);
+
+        // the announced ids are forgotten along with the peer
+        gs.inject_disconnected(&announcer);
+        assert!(gs.peer_dont_send.get(&announcer).is_none());
+    }
+
+    #[test]
+    // tests that messages announced through IDONTWANT are neither gossiped nor sent in response
+    // to an IWANT
+    fn test_idontwant_suppresses_gossip_and_iwant_responses() {
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("topic1")], true);
+        gs.config.gossip_lazy = 20;
+        let mesh_peers = gs.mesh.get(&topic_hashes[0]).unwrap().clone();
+        let source = mesh_peers.iter().next().unwrap().clone();
+        let announcer = peers
+            .iter()
+            .find(|p| !mesh_peers.contains(*p))
+            .unwrap()
+            .clone();
+
+        let message = build_message(&peers[0], &topic_hashes[0], 42);
+        let msg_id = (gs.config.message_id_fn)(&message);
+        gs.handle_received_message(message, &source);
+        gs.handle_idontwant(&announcer, vec![msg_id.clone()]);
+        gs.events.clear();
+
+        gs.handle_iwant(&announcer, vec![msg_id.clone()]);
+        assert!(!collect_message_recipients(&gs).contains(&announcer));
+
+        gs.emit_gossip();
+        let announced = gs.control_pool.get(&announcer).map_or(false, |msgs| {
+            msgs.iter().any(|c| match c {
+                GossipsubControlAction::IHave { message_ids, .. } => message_ids.contains(&msg_id),
+                _ => false,
+            })
+        });
+        assert!(!announced, "Expected no IHAVE of the unwanted message");
+        assert!(
+            !gs.control_pool.is_empty(),
+            "Expected the message to be gossiped to the other peers"
+        );
+    }
+
+    // Builds a node subscribed to `topic` with an explicit peer and `peer_no` other peers, all
+    // connected and subscribed to the topic.
+    fn build_with_explicit_peer(
+        peer_no: usize,
+        topic: &str,
+        config: GossipsubConfig,
+    ) -> (Gossipsub, Vec<PeerId>, PeerId, TopicHash) {
+        let explicit = PeerId::random();
+        let mut config = config;
+        config.explicit_peers.push((explicit.clone(), Vec::new()));
+        let mut gs = Gossipsub::new(
+            MessageAuthenticity::Signed(libp2p_core::identity::Keypair::generate_ed25519()),
+            config,
+        );
+        let topic = Topic::new(topic.into());
+        gs.subscribe(topic.clone());
+        let topic_hash = topic.no_hash();
+
+        let peers = (0..peer_no).map(|_| PeerId::random()).collect::<Vec<_>>();
+        for peer in peers.iter().chain(iter::once(&explicit)) {
+            gs.inject_connected(peer);
+            gs.handle_received_subscriptions(
+                &[GossipsubSubscription {
+                    action: GossipsubSubscriptionAction::Subscribe,
+                    topic_hash: topic_hash.clone(),
+                }],
+                peer,
+            );
+        }
+        (gs, peers, explicit, topic_hash)
+    }
+
+    #[test]
+    // tests that explicit peers are dialed, and redialed while disconnected
+    fn test_explicit_peers_are_dialed() {
+        let explicit = PeerId::random();
+        let address: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        let config = crate::GossipsubConfigBuilder::new()
+            .explicit_peer(explicit.clone(), vec![address.clone()])
+            .check_explicit_peers_ticks(2)
+            .build();
+        let mut gs = Gossipsub::new(
+            MessageAuthenticity::Signed(libp2p_core::identity::Keypair::generate_ed25519()),
+            config,
+        );
+        assert_eq!(count_dialed_peers(&gs), 1);
+        assert_eq!(gs.addresses_of_peer(&explicit), vec![address.clone()]);
+
+        // the addresses of an explicit peer are merged
+        let other: Multiaddr = "/ip4/127.0.0.1/tcp/4002".parse().unwrap();
+        gs.add_explicit_peer(&explicit, vec![address.clone(), other.clone()]);
+        assert_eq!(gs.addresses_of_peer(&explicit), vec![address, other]);
+        gs.events.clear();
+        gs.events.clear();
+
+        // connected explicit peers are not dialed
+        gs.inject_connected(&explicit);
+        gs.heartbeat();
+        gs.heartbeat();
+        assert_eq!(count_dialed_peers(&gs), 0);
+
+        // disconnected ones are, on every check
+        gs.inject_disconnected(&explicit);
+        gs.heartbeat();
+        assert_eq!(count_dialed_peers(&gs), 0);
+        gs.heartbeat();
+        assert_eq!(count_dialed_peers(&gs), 1);
+
+        // explicit peers added later are dialed right away
+        gs.events.clear();
+        gs.add_explicit_peer(&PeerId::random(), Vec::new());
+        assert_eq!(count_dialed_peers(&gs), 1);
+
+        // removed explicit peers are no longer dialed
+        gs.events.clear();
+        gs.remove_explicit_peer(&explicit);
+        gs.heartbeat();
+        gs.heartbeat();
+        assert_eq!(count_dialed_peers(&gs), 1);
+    }
+
+    #[test]
+    // tests that explicit peers are never grafted into the mesh, and that their GRAFTs are refused
+    fn test_explicit_peers_not_in_mesh() {
+        let (mut gs, _, explicit, topic_hash) =
+            build_with_explicit_peer(3, "test", GossipsubConfig::default());
+        assert!(!gs.mesh.get(&topic_hash).unwrap().contains(&explicit));
+
+        // the mesh is below mesh_n_low, but the explicit peer is still not grafted
+        gs.heartbeat();
+        assert!(!gs.mesh.get(&topic_hash).unwrap().contains(&explicit));
+
+        gs.handle_graft(&explicit, vec![topic_hash.clone()]);
+        assert!(!gs.mesh.get(&topic_hash).unwrap().contains(&explicit));
+        assert!(!collect_prunes(&gs, &explicit).is_empty());
+
+        // an explicit peer already in the mesh leaves it
+        let peer = gs
+            .mesh
+            .get(&topic_hash)
+            .unwrap()
+            .iter()
+            .next()
+            .unwrap()
+            .clone();
+        gs.add_explicit_peer(&peer, Vec::new());
+        assert!(!gs.mesh.get(&topic_hash).unwrap().contains(&peer));
+        assert!(!collect_prunes(&gs, &peer).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "The check_explicit_peers_ticks must be greater than 0")]
+    // tests that a configuration without checks of the explicit peers is refused
+    fn test_explicit_peers_ticks_zero() {
+        let config = GossipsubConfig {
+            check_explicit_peers_ticks: 0,
+            ..GossipsubConfig::default()
+        };
+        Gossipsub::new(
+            MessageAuthenticity::Signed(libp2p_core::identity::Keypair::generate_ed25519()),
+            config,
+        );
+    }
+
+    #[test]
+    // tests that explicit peers are sent all the messages of the topics they subscribe to
+    fn test_explicit_peers_receive_all_messages() {
+        let config = crate::GossipsubConfigBuilder::new()
+            .flood_publish(false)
+            .build();
+        let (mut gs, peers, explicit, topic_hash) = build_with_explicit_peer(20, "test", config);
+
+        gs.publish(&Topic::new("test".into()), vec![0; 42]).unwrap();
+        assert!(collect_message_recipients(&gs).contains(&explicit));
+
+        gs.events.clear();
+        let message = build_message(&peers[0], &topic_hash, 42);
+        gs.handle_received_message(message, &peers[0]);
+        assert!(collect_message_recipients(&gs).contains(&explicit));
+
+        // messages are not sent back to the explicit peer they were received from
+        gs.events.clear();
+        let message = build_message(&peers[0], &topic_hash, 42);
+        gs.handle_received_message(message, &explicit);
+        assert!(!collect_message_recipients(&gs).contains(&explicit));
+    }
+
+    #[test]
+    // tests that the subscriptions not allowed by the subscription filter are dropped
+    fn test_subscription_filter() {
+        let allowed = TopicHash::from_raw("allowed");
+        let filter = crate::MaxCountSubscriptionFilter {
+            filter: crate::WhitelistSubscriptionFilter(
+                vec![allowed.clone(), TopicHash::from_raw("other")]
+                    .into_iter()
+                    .collect(),
+            ),
+            max_subscribed_topics: 1,
+            max_subscriptions_per_request: 10,
+        };
+        let mut gs = Gossipsub::new_with_subscription_filter(
+            MessageAuthenticity::Signed(libp2p_core::identity::Keypair::generate_ed25519()),
+            GossipsubConfig::default(),
+            filter,
+        );
+        assert!(!gs.subscribe(Topic::new("forbidden".into())));
+        assert!(gs.mesh.is_empty());
+        assert!(gs.subscribe(Topic::new("allowed".into())));
+
+        let peer = PeerId::random();
+        gs.inject_connected(&peer);
+        let subscriptions = ["forbidden", "allowed", "other"]
+            .iter()
+            .map(|t| GossipsubSubscription {
+                action: GossipsubSubscriptionAction::Subscribe,
+                topic_hash: TopicHash::from_raw(*t),
+            })
+            .collect::<Vec<_>>();
+        gs.handle_received_subscriptions(&subscriptions, &peer);
+
+        // the forbidden topic is dropped, and the peer can only subscribe to one topic
+        assert_eq!(
+            gs.peer_topics.get(&peer).unwrap(),
+            &vec![allowed.clone()].into_iter().collect()
+        );
+        assert_eq!(gs.topic_peers.keys().collect::<Vec<_>>(), vec![&allowed]);
+
+        // unsubscribing from unknown topics doesn't track them
+        gs.handle_received_subscriptions(
+            &[GossipsubSubscription {
+                action: GossipsubSubscriptionAction::Unsubscribe,
+                topic_hash: TopicHash::from_raw("other"),
+            }],
+            &peer,
+        );
+        assert_eq!(gs.topic_peers.keys().collect::<Vec<_>>(), vec![&allowed]);
+    }
+
+    #[test]
+    // tests that the mesh peers are protected in the connection manager while in the mesh
+    fn test_mesh_peers_protected() {
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![String::from("test_protect")], true);
+        let mut connection_manager = libp2p_swarm::ConnectionManager::default();
+        gs.protect_mesh_peers(&mut connection_manager);
+
+        let mesh = gs.mesh.get(&topic_hashes[0]).unwrap().clone();
+        assert!(!mesh.is_empty());
+        for peer in &peers {
+            assert_eq!(connection_manager.is_protected(peer), mesh.contains(peer));
+        }
+
+        // a peer leaving the mesh through a PRUNE is no longer protected
+        let pruned = mesh.iter().next().unwrap().clone();
+        gs.handle_prune(&pruned, vec![(topic_hashes[0].clone(), Vec::new(), None)]);
+        gs.protect_mesh_peers(&mut connection_manager);
+        assert!(!connection_manager.is_protected(&pruned));
+
+        // a peer joining the mesh through a GRAFT is protected
+        let grafted = peers.iter().find(|p| !mesh.contains(*p)).unwrap().clone();
+        gs.handle_graft(&grafted, vec![topic_hashes[0].clone()]);
+        gs.protect_mesh_peers(&mut connection_manager);
+        assert!(connection_manager.is_protected(&grafted));
+        assert!(gs.mesh_changes.is_empty());
+    }
+
+    // Builds a node validating its messages, subscribed to `topic` along with `peer_no` connected
+    // peers, and receives a message on the topic from the first peer.
+    fn build_with_pending_message(
+        peer_no: usize,
+        topic: &str,
+        config: GossipsubConfig,
+    ) -> (Gossipsub, Vec<PeerId>, MessageId) {
+        let mut gs = Gossipsub::new(
+            MessageAuthenticity::Signed(libp2p_core::identity::Keypair::generate_ed25519()),
+            config,
+        );
+        let topic = Topic::new(topic.into());
+        gs.subscribe(topic.clone());
+        let topic_hash = topic.no_hash();
+
+        let peers = (0..peer_no).map(|_| PeerId::random()).collect::<Vec<_>>();
+        for peer in &peers {
+            gs.inject_connected(peer);
+            gs.handle_received_subscriptions(
+                &[GossipsubSubscription {
+                    action: GossipsubSubscriptionAction::Subscribe,
+                    topic_hash: topic_hash.clone(),
+                }],
+                peer,
+            );
+        }
+
+        let mut message = build_message(&PeerId::random(), &topic_hash, 10);
+        message.validated = false;
+        let message_id = (gs.config.message_id_fn)(&message);
+        gs.handle_received_message(message, &peers[0]);
+        gs.events.clear();
+        (gs, peers, message_id)
+    }
+
+    #[test]
+    // tests that a rejected message is dropped and its senders are reported
+    fn test_validation_reject() {
+        let config = crate::GossipsubConfigBuilder::new()
+            .validate_messages()
+            .build();
+        let (mut gs, peers, message_id) = build_with_pending_message(5, "test_reject", config);
+        let duplicate = gs.mcache.get(&message_id).unwrap().clone();
+        gs.handle_received_message(duplicate, &peers[1]);
+
+        assert!(gs.report_message_validation_result(
+            &message_id,
+            &peers[0],
+            MessageAcceptance::Reject
+        ));
+        assert!(gs.mcache.get(&message_id).is_none());
+        assert!(collect_message_recipients(&gs).is_empty());
+        let rejected = gs
+            .events
+            .iter()
+            .filter_map(|e| match e {
+                NetworkBehaviourAction::GenerateEvent(GossipsubEvent::MessageRejected {
+                    message_id,
+                    peers,
+                }) => Some((message_id.clone(), peers.clone())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rejected, vec![(message_id.clone(), peers[..2].to_vec())]);
+
+        // the message can't be validated anymore
+        assert!(!gs.validate_message(&message_id, &peers[0]));
+    }
+
+    #[test]
+    // tests that an ignored message is dropped silently, and an accepted one is forwarded
+    fn test_validation_ignore_and_accept() {
+        let config = crate::GossipsubConfigBuilder::new()
+            .validate_messages()
+            .build();
+        let (mut gs, peers, message_id) = build_with_pending_message(5, "test_ignore", config);
+        assert!(gs.report_message_validation_result(
+            &message_id,
+            &peers[0],
+            MessageAcceptance::Ignore
+        ));
+        assert!(gs.mcache.get(&message_id).is_none());
+        assert!(gs.events.is_empty());
+
+        let config = crate::GossipsubConfigBuilder::new()
+            .validate_messages()
+            .build();
+        let (mut gs, peers, message_id) = build_with_pending_message(5, "test_accept", config);
+        assert!(gs.report_message_validation_result(
+            &message_id,
+            &peers[0],
+            MessageAcceptance::Accept
+        ));
+        assert!(gs.mcache.get(&message_id).unwrap().validated);
+        assert!(!collect_message_recipients(&gs).is_empty());
+        assert!(gs.pending_validations.is_empty());
+    }
+
+    #[test]
+    // tests that the messages not validated in time are dropped on heartbeat
+    fn test_validation_timeout() {
+        let config = crate::GossipsubConfigBuilder::new()
+            .validate_messages()
+            .validation_timeout(Duration::from_millis(0))
+            .build();
+        let (mut gs, peers, message_id) = build_with_pending_message(5, "test_timeout", config);
+        gs.heartbeat();
+
+        assert!(gs.mcache.get(&message_id).is_none());
+        assert!(gs.pending_validations.is_empty());
+        assert!(!gs.validate_message(&message_id, &peers[0]));
+    }
 }
@@ -19,7 +19,7 @@
 // DEALINGS IN THE SOFTWARE.
 
 use crate::protocol::{GossipsubMessage, MessageId};
-use libp2p_core::PeerId;
+use libp2p_core::{Multiaddr, PeerId};
 use std::borrow::Cow;
 use std::time::Duration;
 
@@ -48,8 +48,9 @@ pub enum ValidationMode {
 /// Configuration parameters that define the performance of the gossipsub network.
 #[derive(Clone)]
 pub struct GossipsubConfig {
-    /// The protocol id to negotiate this protocol (default is `/meshsub/1.0.0`).
-    pub protocol_id: Cow<'static, [u8]>,
+    /// The prefix of the protocol ids to negotiate this protocol (default is `/meshsub`). Both
+    /// `<prefix>/1.1.0` and `<prefix>/1.0.0` are supported, preferring the former.
+    pub protocol_id_prefix: Cow<'static, str>,
 
     // Overlay network parameters.
     /// Number of heartbeats to keep in the `memcache` (default is 5).
@@ -80,6 +81,52 @@ pub struct GossipsubConfig {
     /// Time to live for fanout peers (default is 60 seconds).
     pub fanout_ttl: Duration,
 
+    /// Number of heartbeats between two attempts at opportunistic grafting, when peer scoring is
+    /// enabled (default is 60). Must be greater than 0.
+    pub opportunistic_graft_ticks: u64,
+
+    /// Number of peers to graft during opportunistic grafting, when the median score of the mesh
+    /// falls below the opportunistic grafting threshold (default is 2).
+    pub opportunistic_graft_peers: usize,
+
+    /// Time to wait for a message requested through IWANT before the peer that advertised it is
+    /// penalised, when peer scoring is enabled (default is 3 seconds).
+    pub iwant_followup_time: Duration,
+
+    /// Whether to send the peers of a topic as peer exchange (PX) when pruning a peer from a mesh
+    /// (default is false). It is recommended to only enable this on well connected nodes, e.g.
+    /// bootstrap nodes.
+    pub do_px: bool,
+
+    /// Maximum number of peers to send as peer exchange in a PRUNE, and to dial when receiving
+    /// peer exchange (default is 16).
+    pub prune_peers: usize,
+
+    /// Duration during which a pruned peer is not grafted again, and during which a peer that
+    /// pruned us doesn't get grafted by us (default is 60 seconds). Sent to the peer along with
+    /// the PRUNE.
+    pub prune_backoff: Duration,
+
+    /// Whether our own messages are published to all the peers subscribed to their topics whose
+    /// score is above the publish threshold, rather than only to the mesh and fanout peers
+    /// (default is true). This reduces the latency at the origin of a message.
+    pub flood_publish: bool,
+
+    /// Explicit peers with their known addresses, which are sent all the messages of the topics
+    /// they subscribe to and are never part of a mesh (default is empty). They are dialed by the
+    /// behaviour and redialed while disconnected. More explicit peers can be added with
+    /// `Gossipsub::add_explicit_peer`.
+    pub explicit_peers: Vec<(PeerId, Vec<Multiaddr>)>,
+
+    /// Number of heartbeats between two checks of the connections to the explicit peers, dialing
+    /// the disconnected ones (default is 300). Must be greater than 0.
+    pub check_explicit_peers_ticks: u64,
+
+    /// Messages at least this large (in bytes) are announced through IDONTWANT to the mesh peers
+    /// speaking gossipsub v1.2 as soon as they are received, so that they don't send them to us
+    /// again (default is 1000 bytes).
+    pub idontwant_message_size_threshold: usize,
+
     /// The maximum byte size for each gossip (default is 2048 bytes).
     pub max_transmit_size: usize,
 
@@ -94,12 +141,16 @@ pub struct GossipsubConfig {
 
     /// When set to `true`, prevents automatic forwarding of all received messages. This setting
     /// allows a user to validate the messages before propagating them to their peers. If set to
-    /// true, the user must manually call `validate_message()` on the behaviour to forward message
-    /// once validated (default is `false`). Furthermore, the application may optionally call
-    /// `invalidate_message()` on the behaviour to remove the message from the memcache. The
-    /// default is false.
+    /// true, the user must manually call `report_message_validation_result()` on the behaviour to
+    /// forward a message once validated, or to drop it from the memcache and penalise its senders
+    /// if it is invalid (default is `false`).
     pub validate_messages: bool,
 
+    /// The time after which a message that has not been validated by the application is dropped
+    /// from the memcache, as if it had been ignored. Only used if `validate_messages` is set, and
+    /// should be shorter than the time a message spends in the memcache (default is 3 seconds).
+    pub validation_timeout: Duration,
+
     /// Determines the level of validation used when receiving messages. See [`ValidationMode`]
     /// for the available types. The default is ValidationMode::Strict.
     pub validation_mode: ValidationMode,
@@ -118,7 +169,7 @@ pub struct GossipsubConfig {
 impl Default for GossipsubConfig {
     fn default() -> GossipsubConfig {
         GossipsubConfig {
-            protocol_id: Cow::Borrowed(b"/meshsub/1.0.0"),
+            protocol_id_prefix: Cow::Borrowed("/meshsub"),
             history_length: 5,
             history_gossip: 3,
             mesh_n: 6,
@@ -128,10 +179,21 @@ impl Default for GossipsubConfig {
             heartbeat_initial_delay: Duration::from_secs(5),
             heartbeat_interval: Duration::from_secs(1),
             fanout_ttl: Duration::from_secs(60),
+            opportunistic_graft_ticks: 60,
+            opportunistic_graft_peers: 2,
+            iwant_followup_time: Duration::from_secs(3),
+            do_px: false,
+            prune_peers: 16,
+            prune_backoff: Duration::from_secs(60),
+            flood_publish: true,
+            explicit_peers: Vec::new(),
+            check_explicit_peers_ticks: 300,
+            idontwant_message_size_threshold: 1000,
             max_transmit_size: 2048,
             duplicate_cache_time: Duration::from_secs(60),
             hash_topics: false, // default compatibility with floodsub
             validate_messages: false,
+            validation_timeout: Duration::from_secs(3),
             validation_mode: ValidationMode::Strict,
             message_id_fn: |message| {
                 // default message id is: source + sequence number
@@ -171,9 +233,13 @@ impl GossipsubConfigBuilder {
         }
     }
 
-    /// The protocol id to negotiate this protocol (default is `/meshsub/1.0.0`).
-    pub fn protocol_id(&mut self, protocol_id: impl Into<Cow<'static, [u8]>>) -> &mut Self {
-        self.config.protocol_id = protocol_id.into();
+    /// The prefix of the protocol ids to negotiate this protocol (default is `/meshsub`). Both
+    /// `<prefix>/1.1.0` and `<prefix>/1.0.0` are supported, preferring the former.
+    pub fn protocol_id_prefix(
+        &mut self,
+        protocol_id_prefix: impl Into<Cow<'static, str>>,
+    ) -> &mut Self {
+        self.config.protocol_id_prefix = protocol_id_prefix.into();
         self
     }
 
@@ -252,6 +318,85 @@ impl GossipsubConfigBuilder {
         self
     }
 
+    /// Number of heartbeats between two attempts at opportunistic grafting, when peer scoring is
+    /// enabled (default is 60).
+    pub fn opportunistic_graft_ticks(&mut self, opportunistic_graft_ticks: u64) -> &mut Self {
+        assert!(
+            opportunistic_graft_ticks > 0,
+            "The opportunistic_graft_ticks must be greater than 0"
+        );
+        self.config.opportunistic_graft_ticks = opportunistic_graft_ticks;
+        self
+    }
+
+    /// Number of peers to graft during opportunistic grafting, when the median score of the mesh
+    /// falls below the opportunistic grafting threshold (default is 2).
+    pub fn opportunistic_graft_peers(&mut self, opportunistic_graft_peers: usize) -> &mut Self {
+        self.config.opportunistic_graft_peers = opportunistic_graft_peers;
+        self
+    }
+
+    /// Time to wait for a message requested through IWANT before the peer that advertised it is
+    /// penalised, when peer scoring is enabled (default is 3 seconds).
+    pub fn iwant_followup_time(&mut self, iwant_followup_time: Duration) -> &mut Self {
+        self.config.iwant_followup_time = iwant_followup_time;
+        self
+    }
+
+    /// When set, the peers of a topic are sent as peer exchange (PX) when pruning a peer from a
+    /// mesh. It is recommended to only enable this on well connected nodes, e.g. bootstrap nodes.
+    pub fn do_px(&mut self) -> &mut Self {
+        self.config.do_px = true;
+        self
+    }
+
+    /// Maximum number of peers to send as peer exchange in a PRUNE, and to dial when receiving
+    /// peer exchange (default is 16).
+    pub fn prune_peers(&mut self, prune_peers: usize) -> &mut Self {
+        self.config.prune_peers = prune_peers;
+        self
+    }
+
+    /// Duration during which a pruned peer is not grafted again, and during which a peer that
+    /// pruned us doesn't get grafted by us (default is 60 seconds).
+    pub fn prune_backoff(&mut self, prune_backoff: Duration) -> &mut Self {
+        self.config.prune_backoff = prune_backoff;
+        self
+    }
+
+    /// Whether our own messages are published to all the peers subscribed to their topics whose
+    /// score is above the publish threshold, rather than only to the mesh and fanout peers
+    /// (default is true).
+    pub fn flood_publish(&mut self, flood_publish: bool) -> &mut Self {
+        self.config.flood_publish = flood_publish;
+        self
+    }
+
+    /// Adds an explicit peer with its known addresses, which is sent all the messages of the
+    /// topics it subscribes to and is never part of a mesh.
+    pub fn explicit_peer(&mut self, peer_id: PeerId, addresses: Vec<Multiaddr>) -> &mut Self {
+        self.config.explicit_peers.push((peer_id, addresses));
+        self
+    }
+
+    /// Number of heartbeats between two checks of the connections to the explicit peers, dialing
+    /// the disconnected ones (default is 300).
+    pub fn check_explicit_peers_ticks(&mut self, check_explicit_peers_ticks: u64) -> &mut Self {
+        assert!(
+            check_explicit_peers_ticks > 0,
+            "The check_explicit_peers_ticks must be greater than 0"
+        );
+        self.config.check_explicit_peers_ticks = check_explicit_peers_ticks;
+        self
+    }
+
+    /// Messages at least this large (in bytes) are announced through IDONTWANT to the mesh peers
+    /// as soon as they are received (default is 1000 bytes).
+    pub fn idontwant_message_size_threshold(&mut self, threshold: usize) -> &mut Self {
+        self.config.idontwant_message_size_threshold = threshold;
+        self
+    }
+
     /// The maximum byte size for each gossip (default is 2048 bytes).
     pub fn max_transmit_size(&mut self, max_transmit_size: usize) -> &mut Self {
         self.config.max_transmit_size = max_transmit_size;
@@ -275,13 +420,20 @@ impl GossipsubConfigBuilder {
 
     /// When set, prevents automatic forwarding of all received messages. This setting
     /// allows a user to validate the messages before propagating them to their peers. If set,
-    /// the user must manually call `validate_message()` on the behaviour to forward a message
-    /// once validated.
+    /// the user must manually call `report_message_validation_result()` on the behaviour to
+    /// forward a message once validated.
     pub fn validate_messages(&mut self) -> &mut Self {
         self.config.validate_messages = true;
         self
     }
 
+    /// The time after which a message that has not been validated by the application is dropped
+    /// from the memcache (default is 3 seconds).
+    pub fn validation_timeout(&mut self, validation_timeout: Duration) -> &mut Self {
+        self.config.validation_timeout = validation_timeout;
+        self
+    }
+
     /// Determines the level of validation used when receiving messages. See [`ValidationMode`]
     /// for the available types. The default is ValidationMode::Strict.
     pub fn validation_mode(&mut self, validation_mode: ValidationMode) -> &mut Self {
@@ -311,11 +463,7 @@ impl GossipsubConfigBuilder {
 impl std::fmt::Debug for GossipsubConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut builder = f.debug_struct("GossipsubConfig");
-        let _ = if let Ok(text) = std::str::from_utf8(&self.protocol_id) {
-            builder.field("protocol_id", &text)
-        } else {
-            builder.field("protocol_id", &hex_fmt::HexFmt(&self.protocol_id))
-        };
+        let _ = builder.field("protocol_id_prefix", &self.protocol_id_prefix);
         let _ = builder.field("history_length", &self.history_length);
         let _ = builder.field("history_gossip", &self.history_gossip);
         let _ = builder.field("mesh_n", &self.mesh_n);
@@ -325,10 +473,27 @@ impl std::fmt::Debug for GossipsubConfig {
         let _ = builder.field("heartbeat_initial_delay", &self.heartbeat_initial_delay);
         let _ = builder.field("heartbeat_interval", &self.heartbeat_interval);
         let _ = builder.field("fanout_ttl", &self.fanout_ttl);
+        let _ = builder.field("opportunistic_graft_ticks", &self.opportunistic_graft_ticks);
+        let _ = builder.field("opportunistic_graft_peers", &self.opportunistic_graft_peers);
+        let _ = builder.field("iwant_followup_time", &self.iwant_followup_time);
+        let _ = builder.field("do_px", &self.do_px);
+        let _ = builder.field("prune_peers", &self.prune_peers);
+        let _ = builder.field("prune_backoff", &self.prune_backoff);
+        let _ = builder.field("flood_publish", &self.flood_publish);
+        let _ = builder.field("explicit_peers", &self.explicit_peers);
+        let _ = builder.field(
+            "check_explicit_peers_ticks",
+            &self.check_explicit_peers_ticks,
+        );
+        let _ = builder.field(
+            "idontwant_message_size_threshold",
+            &self.idontwant_message_size_threshold,
+        );
         let _ = builder.field("max_transmit_size", &self.max_transmit_size);
         let _ = builder.field("duplicate_cache_time", &self.duplicate_cache_time);
         let _ = builder.field("hash_topics", &self.hash_topics);
         let _ = builder.field("validate_messages", &self.validate_messages);
+        let _ = builder.field("validation_timeout", &self.validation_timeout);
         builder.finish()
     }
 }
@@ -20,7 +20,8 @@
 
 use crate::behaviour::GossipsubRpc;
 use crate::config::ValidationMode;
-use crate::protocol::{GossipsubCodec, ProtocolConfig};
+use crate::error::ValidationError;
+use crate::protocol::{GossipsubCodec, GossipsubMessage, PeerKind, ProtocolConfig};
 use futures::prelude::*;
 use futures_codec::Framed;
 use libp2p_core::upgrade::{InboundUpgrade, OutboundUpgrade};
@@ -31,12 +32,28 @@ use libp2p_swarm::NegotiatedSubstream;
 use log::{debug, error, trace, warn};
 use smallvec::SmallVec;
 use std::{
-    borrow::Cow,
     io,
     pin::Pin,
     task::{Context, Poll},
 };
 
+/// The event emitted by the handler, informing the behaviour of what has been received from the
+/// remote.
+#[derive(Debug)]
+pub enum HandlerEvent {
+    /// A `GossipsubRpc` has been received. This also contains the messages of the RPC that failed
+    /// their validation, if any.
+    Message {
+        /// The RPC, holding the valid messages.
+        rpc: GossipsubRpc,
+        /// The messages that failed their validation, along with the reason.
+        invalid_messages: Vec<(GossipsubMessage, ValidationError)>,
+    },
+    /// The version of the protocol spoken by the remote, reported once the first substream has
+    /// been negotiated.
+    PeerKind(PeerKind),
+}
+
 /// Protocol Handler that manages a single long-lived substream with a peer.
 pub struct GossipsubHandler {
     /// Upgrade configuration for the gossipsub protocol.
@@ -57,6 +74,13 @@ pub struct GossipsubHandler {
 
     /// Flag determining whether to maintain the connection to the peer.
     keep_alive: KeepAlive,
+
+    /// The version of the protocol spoken by the remote, once known.
+    peer_kind: Option<PeerKind>,
+
+    /// Whether the version of the protocol spoken by the remote has been reported to the
+    /// behaviour.
+    peer_kind_sent: bool,
 }
 
 /// State of the inbound substream, opened either by us or by the remote.
@@ -86,13 +110,13 @@ enum OutboundSubstreamState {
 impl GossipsubHandler {
     /// Builds a new `GossipsubHandler`.
     pub fn new(
-        protocol_id: impl Into<Cow<'static, [u8]>>,
+        protocol_id_prefix: &str,
         max_transmit_size: usize,
         validation_mode: ValidationMode,
     ) -> Self {
         GossipsubHandler {
             listen_protocol: SubstreamProtocol::new(ProtocolConfig::new(
-                protocol_id,
+                protocol_id_prefix,
                 max_transmit_size,
                 validation_mode,
             )),
@@ -101,13 +125,15 @@ impl GossipsubHandler {
             outbound_substream_establishing: false,
             send_queue: SmallVec::new(),
             keep_alive: KeepAlive::Yes,
+            peer_kind: None,
+            peer_kind_sent: false,
         }
     }
 }
 
 impl ProtocolsHandler for GossipsubHandler {
     type InEvent = GossipsubRpc;
-    type OutEvent = GossipsubRpc;
+    type OutEvent = HandlerEvent;
     type Error = io::Error;
     type InboundProtocol = ProtocolConfig;
     type OutboundProtocol = ProtocolConfig;
@@ -119,8 +145,12 @@ impl ProtocolsHandler for GossipsubHandler {
 
     fn inject_fully_negotiated_inbound(
         &mut self,
-        substream: <Self::InboundProtocol as InboundUpgrade<NegotiatedSubstream>>::Output,
+        (substream, peer_kind): <Self::InboundProtocol as InboundUpgrade<NegotiatedSubstream>>::Output,
     ) {
+        if self.peer_kind.is_none() {
+            self.peer_kind = Some(peer_kind);
+        }
+
         // new inbound substream. Replace the current one, if it exists.
         trace!("New inbound substream request");
         self.inbound_substream = Some(InboundSubstreamState::WaitingInput(substream));
@@ -128,10 +158,14 @@ impl ProtocolsHandler for GossipsubHandler {
 
     fn inject_fully_negotiated_outbound(
         &mut self,
-        substream: <Self::OutboundProtocol as OutboundUpgrade<NegotiatedSubstream>>::Output,
+        (substream, peer_kind): <Self::OutboundProtocol as OutboundUpgrade<NegotiatedSubstream>>::Output,
         message: Self::OutboundOpenInfo,
     ) {
         self.outbound_substream_establishing = false;
+        if self.peer_kind.is_none() {
+            self.peer_kind = Some(peer_kind);
+        }
+
         // Should never establish a new outbound substream if one already exists.
         // If this happens, an outbound message is not sent.
         if self.outbound_substream.is_some() {
@@ -175,6 +209,16 @@ impl ProtocolsHandler for GossipsubHandler {
             Self::Error,
         >,
     > {
+        // report the version of the protocol spoken by the remote
+        if !self.peer_kind_sent {
+            if let Some(peer_kind) = self.peer_kind {
+                self.peer_kind_sent = true;
+                return Poll::Ready(ProtocolsHandlerEvent::Custom(HandlerEvent::PeerKind(
+                    peer_kind,
+                )));
+            }
+        }
+
         // determine if we need to create the stream
         if !self.send_queue.is_empty()
             && self.outbound_substream.is_none()
@@ -362,4 +406,18 @@ impl ProtocolsHandler for GossipsubHandler {
 
         Poll::Pending
     }
+
+    fn poll_close(&mut self, _: &mut Context<'_>) -> Poll<()> {
+        // Deliver the queued messages. The long-lived substreams themselves are
+        // not waited for, as they are idle once flushed.
+        let sending = match self.outbound_substream {
+            None | Some(OutboundSubstreamState::WaitingOutput(_)) => false,
+            Some(_) => true,
+        };
+        if self.send_queue.is_empty() && !self.outbound_substream_establishing && !sending {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
 }
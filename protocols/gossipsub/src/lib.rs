@@ -60,7 +60,9 @@
 //!
 //! [`GossipsubConfig`]: struct.GossipsubConfig.html
 //!
-//! - `protocol_id` - The protocol id that this implementation will accept connections on.
+//! - `protocol_id_prefix` - The prefix of the protocol ids that this implementation will accept
+//! connections on. `<prefix>/1.2.0`, `<prefix>/1.1.0` and `<prefix>/1.0.0` are negotiated
+//! (default: `/meshsub`).
 //! - `history_length` - The number of heartbeats which past messages are kept in cache (default: 5).
 //! - `history_gossip` - The number of past heartbeats that the node will send gossip metadata
 //! about (default: 3).
@@ -75,12 +77,25 @@
 //! - `heartbeat_interval` - The time between each heartbeat (default: 1 second).
 //! - `fanout_ttl` - The fanout time to live time period. The timeout required before removing peers from the fanout
 //! for a given topic (default: 1 minute).
+//! - `opportunistic_graft_ticks` - The number of heartbeats between two attempts at opportunistic
+//! grafting, when peer scoring is enabled (default: 60).
+//! - `opportunistic_graft_peers` - The number of peers grafted during opportunistic grafting
+//! (default: 2).
+//! - `iwant_followup_time` - The time to wait for a message requested through IWANT before
+//! penalising the peer that advertised it, when peer scoring is enabled (default: 3 seconds).
+//! - `do_px` - Whether to send peer exchange to the peers pruned from a mesh (default: false).
+//! - `prune_peers` - The maximum number of peers sent or dialed through peer exchange (default: 16).
+//! - `prune_backoff` - The time during which a pruned peer is not grafted again (default: 1 minute).
+//! - `flood_publish` - Publish our own messages to all the subscribed peers whose score is above
+//! the publish threshold, rather than to the mesh or fanout peers only (default: true).
+//! - `idontwant_message_size_threshold` - The size from which received messages are announced to
+//! the mesh peers through IDONTWANT (default: 1000 bytes).
 //! - `max_transmit_size` - This sets the maximum transmission size for total gossipsub messages on the network.
 //! - `hash_topics` - Whether to hash the topics using base64(SHA256(topic)) or to leave as plain utf-8 strings.
-//! - `manual_propagation` - Whether gossipsub should immediately forward received messages on the
-//! network. For applications requiring message validation, this should be set to false, then the
-//! application should call `propagate_message(message_id, propagation_source)` once validated, to
-//! propagate the message to peers.
+//! - `validate_messages` - Whether received messages await their validation by the application
+//! before being forwarded on the network, see [Message validation](#message-validation).
+//! - `validation_timeout` - The time after which a message that has not been validated is dropped
+//! (default: 3 seconds).
 //!
 //! This struct implements the `Default` trait and can be initialised via
 //! `GossipsubConfig::default()`.
@@ -93,6 +108,60 @@
 //! [`GossipsubConfig`].
 //!
 //! [`Gossipsub`]: struct.Gossipsub.html
+//!
+//! ## Peer scoring
+//!
+//! The v1.1 peer scoring function is enabled by calling `Gossipsub::with_peer_score` with a set
+//! of [`PeerScoreParams`] and [`PeerScoreThresholds`]. Peers are then scored on their time in the
+//! mesh, their first and mesh message deliveries, the invalid messages they send, the number of
+//! peers sharing their IP address and their misbehaviour. The scores drive the mesh maintenance
+//! and the thresholds determine which peers receive gossip and published messages, and which
+//! peers are ignored altogether. The score of a peer can be queried through
+//! `Gossipsub::peer_score`.
+//!
+//! [`PeerScoreParams`]: struct.PeerScoreParams.html
+//! [`PeerScoreThresholds`]: struct.PeerScoreThresholds.html
+//!
+//! ## Peer exchange and backoff
+//!
+//! Peers speaking `/meshsub/1.1.0` receive a backoff duration with every PRUNE, during which
+//! neither side grafts the other again in that topic. GRAFTs received during a backoff are refused
+//! and, when peer scoring is enabled, penalised. If `do_px` is set, a PRUNE also carries a
+//! selection of other peers of the topic, which the pruned peer dials to find new mesh peers.
+//! Peers speaking `/meshsub/1.0.0` keep receiving plain PRUNEs.
+//!
+//! ## Flood publishing and IDONTWANT
+//!
+//! With `flood_publish` (the default), a message we publish is sent to every peer subscribed to its
+//! topics, not just our mesh, so that it spreads faster from its origin. When a large message is
+//! received, an IDONTWANT carrying its id is sent right away to the mesh peers speaking
+//! `/meshsub/1.2.0`, which then refrain from forwarding us the same message.
+//!
+//! ## Subscription filtering
+//!
+//! The subscriptions received from remote peers, as well as our own, go through a
+//! [`TopicSubscriptionFilter`] supplied with `Gossipsub::new_with_subscription_filter`. The
+//! subscriptions it doesn't allow are dropped, which bounds the topics tracked for each peer.
+//! Filters based on an allow-list, a regular expression and a maximum number of topics are
+//! provided. By default, any topic is allowed, up to 100 topics per peer.
+//!
+//! The filter is applied by the behaviour when it handles a received RPC, before any of its
+//! subscriptions is recorded, rather than while the RPC is decoded: filters may be stateful and
+//! are given the topics the peer is already subscribed to, which only the behaviour knows. The
+//! size of an RPC is still bounded by `GossipsubConfig::max_transmit_size` during decoding.
+//!
+//! [`TopicSubscriptionFilter`]: trait.TopicSubscriptionFilter.html
+//!
+//! ## Message validation
+//!
+//! With `validate_messages`, the received messages are not forwarded until the application reports
+//! a [`MessageAcceptance`] through `Gossipsub::report_message_validation_result`. Accepted messages
+//! are forwarded, while rejected and ignored messages are dropped from the message cache. The peers
+//! that sent a rejected message are penalised by the peer score and reported in a
+//! `GossipsubEvent::MessageRejected` event. Messages not validated within `validation_timeout` are
+//! dropped as if they had been ignored.
+//!
+//! [`MessageAcceptance`]: enum.MessageAcceptance.html
 
 //! ## Example
 //!
@@ -138,17 +207,31 @@
 pub mod error;
 pub mod protocol;
 
+mod backoff;
 mod behaviour;
 mod config;
+mod gossip_promises;
 mod handler;
 mod mcache;
+mod peer_score;
+mod subscription_filter;
 mod topic;
 
 mod rpc_proto {
     include!(concat!(env!("OUT_DIR"), "/gossipsub.pb.rs"));
 }
 
-pub use self::behaviour::{Gossipsub, GossipsubEvent, GossipsubRpc, MessageAuthenticity};
+pub use self::behaviour::{
+    Gossipsub, GossipsubEvent, GossipsubRpc, MessageAcceptance, MessageAuthenticity,
+};
 pub use self::config::{GossipsubConfig, GossipsubConfigBuilder, ValidationMode};
+pub use self::peer_score::{
+    score_parameter_decay, score_parameter_decay_with_base, PeerScoreParams, PeerScoreThresholds,
+    TopicScoreParams,
+};
 pub use self::protocol::{GossipsubMessage, MessageId};
+pub use self::subscription_filter::{
+    AllowAllSubscriptionFilter, MaxCountSubscriptionFilter, RegexSubscriptionFilter,
+    TopicSubscriptionFilter, WhitelistSubscriptionFilter,
+};
 pub use self::topic::{Topic, TopicHash};
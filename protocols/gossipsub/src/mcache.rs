@@ -96,6 +96,15 @@ impl MessageCache {
         })
     }
 
+    /// Removes a message with `message_id`, e.g. after it failed its validation.
+    pub fn remove(&mut self, message_id: &MessageId) -> Option<GossipsubMessage> {
+        let message = self.msgs.remove(message_id)?;
+        for entries in self.history.iter_mut() {
+            entries.retain(|entry| entry.mid != *message_id);
+        }
+        Some(message)
+    }
+
     /// Get a list of GossipIds for a given topic
     pub fn get_gossip_ids(&self, topic: &TopicHash) -> Vec<MessageId> {
         self.history[..self.gossip]
@@ -331,4 +340,21 @@ mod tests {
         assert_eq!(mc.history[0].len(), 0);
         assert_eq!(mc.msgs.len(), 0);
     }
+
+    #[test]
+    /// Test that a removed message is neither retrievable nor gossiped.
+    fn test_remove() {
+        let mut mc = new_cache(3, 5);
+
+        let topic1_hash = Topic::new("topic1".into()).no_hash().clone();
+        let m = gen_testm(1, vec![topic1_hash.clone()]);
+        mc.put(m.clone());
+        let id = (mc.msg_id)(&m);
+
+        assert_eq!(mc.remove(&id), Some(m));
+        assert!(mc.get(&id).is_none());
+        assert!(mc.get_gossip_ids(&topic1_hash).is_empty());
+        assert!(mc.history[0].is_empty());
+        assert!(mc.remove(&id).is_none());
+    }
 }
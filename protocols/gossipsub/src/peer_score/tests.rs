@@ -0,0 +1,431 @@
+// Copyright 2020 Sigma Prime Pty Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+// collection of tests for the gossipsub peer scoring
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::thread::sleep;
+
+    // helper functions for testing
+
+    // Returns topic parameters with every weight set to zero, so that a test can enable the
+    // single component it covers.
+    fn zero_topic_params() -> TopicScoreParams {
+        TopicScoreParams {
+            topic_weight: 1.0,
+            time_in_mesh_weight: 0.0,
+            first_message_deliveries_weight: 0.0,
+            mesh_message_deliveries_weight: 0.0,
+            mesh_failure_penalty_weight: 0.0,
+            invalid_message_deliveries_weight: 0.0,
+            ..TopicScoreParams::default()
+        }
+    }
+
+    // Builds a `PeerScore` scoring only `topic` with the given parameters, and adds `peer_no`
+    // connected peers to it.
+    fn build_peer_score(
+        topic_params: TopicScoreParams,
+        params: PeerScoreParams,
+        peer_no: usize,
+    ) -> (PeerScore, TopicHash, Vec<PeerId>) {
+        let topic = TopicHash::from_raw("test");
+        let mut params = params;
+        params.topics.insert(topic.clone(), topic_params);
+        let mut peer_score = PeerScore::new(params);
+        let peers = (0..peer_no)
+            .map(|_| {
+                let peer = PeerId::random();
+                peer_score.add_peer(peer.clone());
+                peer
+            })
+            .collect();
+        (peer_score, topic, peers)
+    }
+
+    fn deliver(peer_score: &mut PeerScore, peer: &PeerId, topic: &TopicHash, id: usize) {
+        let msg_id = MessageId::from(id.to_string());
+        peer_score.validate_message(&msg_id);
+        peer_score.deliver_message(peer, &msg_id, &[topic.clone()]);
+    }
+
+    #[test]
+    fn test_score_unknown_peer() {
+        let (peer_score, _, _) =
+            build_peer_score(zero_topic_params(), PeerScoreParams::default(), 0);
+        assert_eq!(peer_score.score(&PeerId::random()), 0.0);
+    }
+
+    #[test]
+    fn test_score_time_in_mesh() {
+        let topic_params = TopicScoreParams {
+            time_in_mesh_weight: 1.0,
+            time_in_mesh_quantum: Duration::from_millis(1),
+            ..zero_topic_params()
+        };
+        let (mut peer_score, topic, peers) =
+            build_peer_score(topic_params, PeerScoreParams::default(), 1);
+        let peer = &peers[0];
+
+        peer_score.graft(peer, topic);
+        sleep(Duration::from_millis(50));
+        peer_score.refresh_scores();
+
+        assert!(
+            peer_score.score(peer) >= 50.0,
+            "The score should grow with the time spent in the mesh"
+        );
+    }
+
+    #[test]
+    fn test_score_first_message_deliveries_capped() {
+        let topic_params = TopicScoreParams {
+            first_message_deliveries_weight: 1.0,
+            first_message_deliveries_cap: 5.0,
+            ..zero_topic_params()
+        };
+        let (mut peer_score, topic, peers) =
+            build_peer_score(topic_params, PeerScoreParams::default(), 1);
+        let peer = &peers[0];
+
+        for id in 0..10 {
+            deliver(&mut peer_score, peer, &topic, id);
+        }
+        assert_eq!(peer_score.score(peer), 5.0);
+
+        // the counter decays by half at each refresh
+        peer_score.refresh_scores();
+        assert_eq!(peer_score.score(peer), 2.5);
+    }
+
+    #[test]
+    fn test_score_mesh_message_deliveries_deficit() {
+        let topic_params = TopicScoreParams {
+            mesh_message_deliveries_weight: -1.0,
+            mesh_message_deliveries_threshold: 20.0,
+            mesh_message_deliveries_activation: Duration::from_millis(1),
+            ..zero_topic_params()
+        };
+        let (mut peer_score, topic, peers) =
+            build_peer_score(topic_params, PeerScoreParams::default(), 2);
+
+        peer_score.graft(&peers[0], topic.clone());
+        peer_score.graft(&peers[1], topic.clone());
+
+        // the deficit is not accounted before the activation time
+        assert_eq!(peer_score.score(&peers[0]), 0.0);
+
+        sleep(Duration::from_millis(5));
+        peer_score.refresh_scores();
+
+        for id in 0..5 {
+            deliver(&mut peer_score, &peers[0], &topic, id);
+        }
+
+        assert_eq!(peer_score.score(&peers[0]), -(15.0 * 15.0));
+        assert_eq!(peer_score.score(&peers[1]), -(20.0 * 20.0));
+    }
+
+    #[test]
+    fn test_score_mesh_message_deliveries_counts_early_duplicates() {
+        let topic_params = TopicScoreParams {
+            mesh_message_deliveries_weight: -1.0,
+            mesh_message_deliveries_threshold: 1.0,
+            mesh_message_deliveries_activation: Duration::from_millis(1),
+            ..zero_topic_params()
+        };
+        let (mut peer_score, topic, peers) =
+            build_peer_score(topic_params, PeerScoreParams::default(), 3);
+        for peer in &peers {
+            peer_score.graft(peer, topic.clone());
+        }
+        sleep(Duration::from_millis(5));
+        peer_score.refresh_scores();
+
+        // peer 1 forwards the message while it is being validated, peer 2 never does
+        let msg_id = MessageId::from("message");
+        peer_score.validate_message(&msg_id);
+        peer_score.duplicated_message(&peers[1], &msg_id, &[topic.clone()]);
+        peer_score.deliver_message(&peers[0], &msg_id, &[topic.clone()]);
+
+        assert_eq!(peer_score.score(&peers[0]), 0.0);
+        assert_eq!(peer_score.score(&peers[1]), 0.0);
+        assert_eq!(peer_score.score(&peers[2]), -1.0);
+    }
+
+    #[test]
+    fn test_score_mesh_failure_penalty() {
+        let topic_params = TopicScoreParams {
+            mesh_message_deliveries_threshold: 20.0,
+            mesh_message_deliveries_activation: Duration::from_millis(1),
+            mesh_failure_penalty_weight: -1.0,
+            ..zero_topic_params()
+        };
+        let (mut peer_score, topic, peers) =
+            build_peer_score(topic_params, PeerScoreParams::default(), 2);
+        peer_score.graft(&peers[0], topic.clone());
+        peer_score.graft(&peers[1], topic.clone());
+        sleep(Duration::from_millis(5));
+        peer_score.refresh_scores();
+
+        for id in 0..10 {
+            deliver(&mut peer_score, &peers[0], &topic, id);
+        }
+        assert_eq!(peer_score.score(&peers[0]), 0.0);
+
+        // the deficit becomes sticky once the peer leaves the mesh
+        peer_score.prune(&peers[0], topic.clone());
+        assert_eq!(peer_score.score(&peers[0]), -(10.0 * 10.0));
+        // peers that are still in the mesh are not affected
+        assert_eq!(peer_score.score(&peers[1]), 0.0);
+    }
+
+    #[test]
+    fn test_score_invalid_message_deliveries() {
+        let topic_params = TopicScoreParams {
+            invalid_message_deliveries_weight: -1.0,
+            ..zero_topic_params()
+        };
+        let (mut peer_score, topic, peers) =
+            build_peer_score(topic_params, PeerScoreParams::default(), 1);
+        let peer = &peers[0];
+
+        for _ in 0..3 {
+            peer_score.reject_invalid_message(peer, &[topic.clone()]);
+        }
+        assert_eq!(peer_score.score(peer), -9.0);
+    }
+
+    #[test]
+    fn test_score_rejected_and_ignored_messages() {
+        let topic_params = TopicScoreParams {
+            invalid_message_deliveries_weight: -1.0,
+            ..zero_topic_params()
+        };
+        let (mut peer_score, topic, peers) =
+            build_peer_score(topic_params, PeerScoreParams::default(), 4);
+        let topics = [topic.clone()];
+
+        // peer 1 forwards the message while it is being validated, peer 2 after the rejection
+        let msg_id = MessageId::from("rejected");
+        peer_score.validate_message(&msg_id);
+        peer_score.duplicated_message(&peers[1], &msg_id, &topics);
+        peer_score.reject_message(&peers[0], &msg_id, &topics);
+        peer_score.duplicated_message(&peers[2], &msg_id, &topics);
+        peer_score.duplicated_message(&peers[2], &msg_id, &topics);
+        for peer in &peers[..3] {
+            assert_eq!(peer_score.score(peer), -1.0);
+        }
+
+        // nobody is penalised for an ignored message
+        let msg_id = MessageId::from("ignored");
+        peer_score.validate_message(&msg_id);
+        peer_score.duplicated_message(&peers[1], &msg_id, &topics);
+        peer_score.ignore_message(&msg_id);
+        peer_score.duplicated_message(&peers[3], &msg_id, &topics);
+        assert_eq!(peer_score.score(&peers[1]), -1.0);
+        assert_eq!(peer_score.score(&peers[3]), 0.0);
+    }
+
+    #[test]
+    fn test_score_unscored_topic_is_ignored() {
+        let topic_params = TopicScoreParams {
+            invalid_message_deliveries_weight: -1.0,
+            ..zero_topic_params()
+        };
+        let (mut peer_score, _, peers) =
+            build_peer_score(topic_params, PeerScoreParams::default(), 1);
+        let peer = &peers[0];
+
+        peer_score.reject_invalid_message(peer, &[TopicHash::from_raw("other")]);
+        assert_eq!(peer_score.score(peer), 0.0);
+    }
+
+    #[test]
+    fn test_score_application_score() {
+        let params = PeerScoreParams {
+            app_specific_weight: 0.5,
+            ..PeerScoreParams::default()
+        };
+        let (mut peer_score, _, peers) = build_peer_score(zero_topic_params(), params, 1);
+        let peer = &peers[0];
+
+        assert!(peer_score.set_application_score(peer, 4.0));
+        assert_eq!(peer_score.score(peer), 2.0);
+
+        assert!(
+            !peer_score.set_application_score(&PeerId::random(), 4.0),
+            "Unknown peers can't get an application score"
+        );
+    }
+
+    #[test]
+    fn test_score_ip_colocation() {
+        let params = PeerScoreParams {
+            ip_colocation_factor_weight: -1.0,
+            ip_colocation_factor_threshold: 1.0,
+            ..PeerScoreParams::default()
+        };
+        let (mut peer_score, _, peers) = build_peer_score(zero_topic_params(), params, 4);
+        let shared_ip: IpAddr = [10, 0, 0, 1].into();
+        for peer in &peers[..3] {
+            peer_score.add_ip(peer, shared_ip);
+        }
+        peer_score.add_ip(&peers[3], [10, 0, 0, 2].into());
+
+        for peer in &peers[..3] {
+            assert_eq!(peer_score.score(peer), -4.0);
+        }
+        assert_eq!(peer_score.score(&peers[3]), 0.0);
+
+        peer_score.remove_ip(&peers[0], &shared_ip);
+        assert_eq!(peer_score.score(&peers[0]), 0.0);
+        assert_eq!(peer_score.score(&peers[1]), -1.0);
+    }
+
+    #[test]
+    fn test_score_ip_colocation_whitelist() {
+        let shared_ip: IpAddr = [10, 0, 0, 1].into();
+        let mut params = PeerScoreParams {
+            ip_colocation_factor_weight: -1.0,
+            ip_colocation_factor_threshold: 1.0,
+            ..PeerScoreParams::default()
+        };
+        params.ip_colocation_factor_whitelist.insert(shared_ip);
+        let (mut peer_score, _, peers) = build_peer_score(zero_topic_params(), params, 3);
+        for peer in &peers {
+            peer_score.add_ip(peer, shared_ip);
+        }
+
+        for peer in &peers {
+            assert_eq!(peer_score.score(peer), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_score_behaviour_penalty() {
+        let params = PeerScoreParams {
+            behaviour_penalty_weight: -1.0,
+            behaviour_penalty_threshold: 0.0,
+            behaviour_penalty_decay: 0.5,
+            ..PeerScoreParams::default()
+        };
+        let (mut peer_score, _, peers) = build_peer_score(zero_topic_params(), params, 1);
+        let peer = &peers[0];
+
+        peer_score.add_penalty(peer, 3);
+        assert_eq!(peer_score.score(peer), -9.0);
+
+        peer_score.refresh_scores();
+        assert_eq!(peer_score.score(peer), -2.25);
+    }
+
+    #[test]
+    fn test_score_retention() {
+        let params = PeerScoreParams {
+            app_specific_weight: 1.0,
+            retain_score: Duration::from_secs(3600),
+            ..PeerScoreParams::default()
+        };
+        let (mut peer_score, _, peers) = build_peer_score(zero_topic_params(), params, 2);
+        peer_score.set_application_score(&peers[0], -10.0);
+        peer_score.set_application_score(&peers[1], 10.0);
+
+        peer_score.remove_peer(&peers[0]);
+        peer_score.remove_peer(&peers[1]);
+
+        // negative scores are retained and don't decay while disconnected
+        peer_score.refresh_scores();
+        assert_eq!(peer_score.score(&peers[0]), -10.0);
+        // positive scores are forgotten
+        assert_eq!(peer_score.score(&peers[1]), 0.0);
+
+        // reconnecting doesn't reset the score
+        peer_score.add_peer(peers[0].clone());
+        assert_eq!(peer_score.score(&peers[0]), -10.0);
+    }
+
+    #[test]
+    fn test_score_retention_expires() {
+        let params = PeerScoreParams {
+            app_specific_weight: 1.0,
+            retain_score: Duration::from_millis(1),
+            ..PeerScoreParams::default()
+        };
+        let (mut peer_score, _, peers) = build_peer_score(zero_topic_params(), params, 1);
+        let peer = &peers[0];
+        peer_score.set_application_score(peer, -10.0);
+        peer_score.remove_peer(peer);
+
+        sleep(Duration::from_millis(5));
+        peer_score.refresh_scores();
+        assert_eq!(peer_score.score(peer), 0.0);
+    }
+
+    #[test]
+    fn test_score_topic_cap() {
+        let topic_params = TopicScoreParams {
+            first_message_deliveries_weight: 1.0,
+            ..zero_topic_params()
+        };
+        let params = PeerScoreParams {
+            topic_score_cap: 3.0,
+            ..PeerScoreParams::default()
+        };
+        let (mut peer_score, topic, peers) = build_peer_score(topic_params, params, 1);
+        let peer = &peers[0];
+
+        for id in 0..10 {
+            deliver(&mut peer_score, peer, &topic, id);
+        }
+        assert_eq!(peer_score.score(peer), 3.0);
+    }
+
+    #[test]
+    fn test_params_validation() {
+        assert!(PeerScoreParams::default().validate().is_ok());
+        assert!(PeerScoreThresholds::default().validate().is_ok());
+
+        let thresholds = PeerScoreThresholds {
+            gossip_threshold: 1.0,
+            ..PeerScoreThresholds::default()
+        };
+        assert!(thresholds.validate().is_err());
+
+        let mut params = PeerScoreParams::default();
+        params.topics.insert(
+            TopicHash::from_raw("test"),
+            TopicScoreParams {
+                invalid_message_deliveries_weight: 1.0,
+                ..TopicScoreParams::default()
+            },
+        );
+        assert!(params.validate().is_err());
+    }
+
+    #[test]
+    fn test_score_parameter_decay() {
+        let decay = score_parameter_decay(Duration::from_secs(10));
+        // after 10 decay intervals of 1s, the counter should have decayed to zero
+        assert!((decay.powi(10) - 0.1).abs() < 1e-9);
+    }
+}
@@ -20,6 +20,8 @@
 
 use crate::behaviour::GossipsubRpc;
 use crate::config::ValidationMode;
+use crate::error::ValidationError;
+use crate::handler::HandlerEvent;
 use crate::rpc_proto;
 use crate::topic::TopicHash;
 use byteorder::{BigEndian, ByteOrder};
@@ -28,19 +30,67 @@ use bytes::BytesMut;
 use futures::future;
 use futures::prelude::*;
 use futures_codec::{Decoder, Encoder, Framed};
-use libp2p_core::{identity::PublicKey, InboundUpgrade, OutboundUpgrade, PeerId, UpgradeInfo};
+use libp2p_core::{
+    identity::PublicKey, peer_record::PeerRecord, signed_envelope::SignedEnvelope,
+    upgrade::ProtocolName, InboundUpgrade, OutboundUpgrade, PeerId, UpgradeInfo,
+};
 use log::{debug, warn};
 use prost::Message as ProtobufMessage;
-use std::{borrow::Cow, fmt, io, iter, pin::Pin};
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    io,
+    pin::Pin,
+};
 use unsigned_varint::codec;
 
 pub const SIGNING_PREFIX: &'static [u8] = b"libp2p-pubsub:";
 
+/// The version of the gossipsub protocol spoken by a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeerKind {
+    /// The peer speaks `/meshsub/1.2.0`, additionally supporting IDONTWANT.
+    Gossipsubv1_2,
+    /// The peer speaks `/meshsub/1.1.0`, supporting peer exchange and prune backoff.
+    Gossipsubv1_1,
+    /// The peer speaks `/meshsub/1.0.0`.
+    Gossipsub,
+}
+
+/// A gossipsub protocol id, along with the version of the protocol it identifies.
+#[derive(Debug, Clone)]
+pub struct ProtocolId {
+    /// The protocol id as negotiated on the wire.
+    protocol_id: Vec<u8>,
+    /// The version of the protocol.
+    kind: PeerKind,
+}
+
+impl ProtocolId {
+    fn new(prefix: &str, kind: PeerKind) -> Self {
+        let version = match kind {
+            PeerKind::Gossipsubv1_2 => "1.2.0",
+            PeerKind::Gossipsubv1_1 => "1.1.0",
+            PeerKind::Gossipsub => "1.0.0",
+        };
+        ProtocolId {
+            protocol_id: format!("{}/{}", prefix, version).into_bytes(),
+            kind,
+        }
+    }
+}
+
+impl ProtocolName for ProtocolId {
+    fn protocol_name(&self) -> &[u8] {
+        &self.protocol_id
+    }
+}
+
 /// Implementation of the `ConnectionUpgrade` for the Gossipsub protocol.
 #[derive(Clone)]
 pub struct ProtocolConfig {
-    /// The gossipsub protocol id to listen on.
-    protocol_id: Cow<'static, [u8]>,
+    /// The gossipsub protocol ids to listen on, in order of preference.
+    protocol_ids: Vec<ProtocolId>,
     /// The maximum transmit size for a packet.
     max_transmit_size: usize,
     /// Determines the level of validation to be done on incoming messages.
@@ -48,15 +98,20 @@ pub struct ProtocolConfig {
 }
 
 impl ProtocolConfig {
-    /// Builds a new `ProtocolConfig`.
+    /// Builds a new `ProtocolConfig`, supporting the 1.2, 1.1 and 1.0 versions of the protocol under
+    /// the given prefix.
     /// Sets the maximum gossip transmission size.
     pub fn new(
-        protocol_id: impl Into<Cow<'static, [u8]>>,
+        protocol_id_prefix: &str,
         max_transmit_size: usize,
         validation_mode: ValidationMode,
     ) -> ProtocolConfig {
         ProtocolConfig {
-            protocol_id: protocol_id.into(),
+            protocol_ids: vec![
+                ProtocolId::new(protocol_id_prefix, PeerKind::Gossipsubv1_2),
+                ProtocolId::new(protocol_id_prefix, PeerKind::Gossipsubv1_1),
+                ProtocolId::new(protocol_id_prefix, PeerKind::Gossipsub),
+            ],
             max_transmit_size,
             validation_mode,
         }
@@ -64,11 +119,11 @@ impl ProtocolConfig {
 }
 
 impl UpgradeInfo for ProtocolConfig {
-    type Info = Cow<'static, [u8]>;
-    type InfoIter = iter::Once<Self::Info>;
+    type Info = ProtocolId;
+    type InfoIter = Vec<Self::Info>;
 
     fn protocol_info(&self) -> Self::InfoIter {
-        iter::once(self.protocol_id.clone())
+        self.protocol_ids.clone()
     }
 }
 
@@ -76,16 +131,19 @@ impl<TSocket> InboundUpgrade<TSocket> for ProtocolConfig
 where
     TSocket: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    type Output = Framed<TSocket, GossipsubCodec>;
+    type Output = (Framed<TSocket, GossipsubCodec>, PeerKind);
     type Error = io::Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send>>;
 
-    fn upgrade_inbound(self, socket: TSocket, _: Self::Info) -> Self::Future {
+    fn upgrade_inbound(self, socket: TSocket, protocol_id: Self::Info) -> Self::Future {
         let mut length_codec = codec::UviBytes::default();
         length_codec.set_max_len(self.max_transmit_size);
-        Box::pin(future::ok(Framed::new(
-            socket,
-            GossipsubCodec::new(length_codec, self.validation_mode),
+        Box::pin(future::ok((
+            Framed::new(
+                socket,
+                GossipsubCodec::new(length_codec, self.validation_mode),
+            ),
+            protocol_id.kind,
         )))
     }
 }
@@ -94,16 +152,19 @@ impl<TSocket> OutboundUpgrade<TSocket> for ProtocolConfig
 where
     TSocket: AsyncWrite + AsyncRead + Unpin + Send + 'static,
 {
-    type Output = Framed<TSocket, GossipsubCodec>;
+    type Output = (Framed<TSocket, GossipsubCodec>, PeerKind);
     type Error = io::Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send>>;
 
-    fn upgrade_outbound(self, socket: TSocket, _: Self::Info) -> Self::Future {
+    fn upgrade_outbound(self, socket: TSocket, protocol_id: Self::Info) -> Self::Future {
         let mut length_codec = codec::UviBytes::default();
         length_codec.set_max_len(self.max_transmit_size);
-        Box::pin(future::ok(Framed::new(
-            socket,
-            GossipsubCodec::new(length_codec, self.validation_mode),
+        Box::pin(future::ok((
+            Framed::new(
+                socket,
+                GossipsubCodec::new(length_codec, self.validation_mode),
+            ),
+            protocol_id.kind,
         )))
     }
 }
@@ -226,6 +287,7 @@ impl Encoder for GossipsubCodec {
             iwant: Vec::new(),
             graft: Vec::new(),
             prune: Vec::new(),
+            idontwant: Vec::new(),
         };
 
         let empty_control_msg = item.control_msgs.is_empty();
@@ -255,12 +317,32 @@ impl Encoder for GossipsubCodec {
                     };
                     control.graft.push(rpc_graft);
                 }
-                GossipsubControlAction::Prune { topic_hash } => {
+                GossipsubControlAction::Prune {
+                    topic_hash,
+                    peers,
+                    backoff,
+                } => {
                     let rpc_prune = rpc_proto::ControlPrune {
                         topic_id: Some(topic_hash.into()),
+                        peers: peers
+                            .into_iter()
+                            .map(|info| rpc_proto::PeerInfo {
+                                peer_id: info.peer_id.map(|id| id.into_bytes()),
+                                signed_peer_record: info
+                                    .signed_peer_record
+                                    .map(|r| r.into_signed_envelope().into_protobuf_encoding()),
+                            })
+                            .collect(),
+                        backoff,
                     };
                     control.prune.push(rpc_prune);
                 }
+                GossipsubControlAction::IDontWant { message_ids } => {
+                    let rpc_idontwant = rpc_proto::ControlIDontWant {
+                        message_ids: message_ids.into_iter().map(|msg_id| msg_id.0).collect(),
+                    };
+                    control.idontwant.push(rpc_idontwant);
+                }
             }
         }
 
@@ -285,7 +367,7 @@ impl Encoder for GossipsubCodec {
 }
 
 impl Decoder for GossipsubCodec {
-    type Item = GossipsubRpc;
+    type Item = HandlerEvent;
     type Error = io::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
@@ -297,10 +379,15 @@ impl Decoder for GossipsubCodec {
         let rpc = rpc_proto::Rpc::decode(&packet[..])?;
 
         let mut messages = Vec::with_capacity(rpc.publish.len());
+        // Messages that failed their validation are reported to the behaviour, which may
+        // penalise the sender.
+        let mut invalid_messages = Vec::new();
+
         for message in rpc.publish.into_iter() {
             let mut verify_signature = false;
             let mut verify_sequence_no = false;
             let mut verify_source = false;
+            let mut invalid_kind = None;
 
             match self.validation_mode {
                 ValidationMode::Strict => {
@@ -324,71 +411,87 @@ impl Decoder for GossipsubCodec {
                 ValidationMode::Anonymous => {
                     if message.signature.is_some() {
                         warn!("Message dropped. Signature field was non-empty and anonymous validation mode is set");
-                        return Ok(None);
-                    }
-                    if message.seqno.is_some() {
+                        invalid_kind = Some(ValidationError::SignaturePresent);
+                    } else if message.seqno.is_some() {
                         warn!("Message dropped. Sequence number was non-empty and anonymous validation mode is set");
-                        return Ok(None);
-                    }
-                    if message.from.is_some() {
+                        invalid_kind = Some(ValidationError::SequenceNumberPresent);
+                    } else if message.from.is_some() {
                         warn!("Message dropped. Message source was non-empty and anonymous validation mode is set");
-                        return Ok(None);
+                        invalid_kind = Some(ValidationError::MessageSourcePresent);
                     }
                 }
                 ValidationMode::None => {}
             }
 
             // verify message signatures if required
-            if verify_signature {
-                // If a single message is unsigned, we will drop all of them
-                // Most implementations should not have a list of mixed signed/not-signed messages in a single RPC
-                // NOTE: Invalid messages are simply dropped with a warning log. We don't throw an
-                // error to avoid extra logic to deal with these errors in the handler.
-                if !GossipsubCodec::verify_signature(&message) {
-                    warn!("Message dropped. Invalid signature");
-                    // Drop the message
-                    return Ok(None);
-                }
+            // NOTE: Invalid messages are not returned as errors, to avoid extra logic to deal
+            // with these errors in the handler. They are reported alongside the valid messages
+            // of the RPC instead.
+            if invalid_kind.is_none()
+                && verify_signature
+                && !GossipsubCodec::verify_signature(&message)
+            {
+                warn!("Message dropped. Invalid signature");
+                invalid_kind = Some(ValidationError::InvalidSignature);
             }
 
             // ensure the sequence number is a u64
-            let sequence_number = if verify_sequence_no {
-                let seq_no = message.seqno.ok_or_else(|| {
-                    io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "sequence number was not provided",
-                    )
-                })?;
-                if seq_no.len() != 8 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "sequence number has an incorrect size",
-                    ));
+            let mut sequence_number = None;
+            if invalid_kind.is_none() && verify_sequence_no {
+                match message.seqno.as_ref() {
+                    Some(seq_no) if seq_no.len() == 8 => {
+                        sequence_number = Some(BigEndian::read_u64(seq_no));
+                    }
+                    Some(_) => {
+                        debug!("Message dropped. Sequence number has an incorrect size");
+                        invalid_kind = Some(ValidationError::InvalidSequenceNumber);
+                    }
+                    None => {
+                        debug!("Message dropped. Sequence number was not provided");
+                        invalid_kind = Some(ValidationError::EmptySequenceNumber);
+                    }
                 }
-                Some(BigEndian::read_u64(&seq_no))
-            } else {
-                None
-            };
+            }
 
-            let source = if verify_source {
-                Some(
-                    PeerId::from_bytes(message.from.unwrap_or_default()).map_err(|_| {
-                        io::Error::new(io::ErrorKind::InvalidData, "Invalid Peer Id")
-                    })?,
-                )
-            } else {
-                None
-            };
+            let mut source = None;
+            if invalid_kind.is_none() && verify_source {
+                match PeerId::from_bytes(message.from.clone().unwrap_or_default()) {
+                    Ok(peer_id) => source = Some(peer_id),
+                    Err(_) => {
+                        debug!("Message dropped. Invalid Peer Id");
+                        invalid_kind = Some(ValidationError::InvalidPeerId);
+                    }
+                }
+            }
+
+            let topics = message
+                .topic_ids
+                .into_iter()
+                .map(TopicHash::from_raw)
+                .collect();
+
+            if let Some(validation_error) = invalid_kind {
+                // Only the topics of an invalid message are of interest to the behaviour.
+                invalid_messages.push((
+                    GossipsubMessage {
+                        source: None,
+                        data: message.data.unwrap_or_default(),
+                        sequence_number: None,
+                        topics,
+                        signature: None,
+                        key: None,
+                        validated: false,
+                    },
+                    validation_error,
+                ));
+                continue;
+            }
 
             messages.push(GossipsubMessage {
                 source,
                 data: message.data.unwrap_or_default(),
                 sequence_number,
-                topics: message
-                    .topic_ids
-                    .into_iter()
-                    .map(TopicHash::from_raw)
-                    .collect(),
+                topics,
                 signature: message.signature,
                 key: message.key,
                 validated: false,
@@ -437,6 +540,36 @@ impl Decoder for GossipsubCodec {
                 .into_iter()
                 .map(|prune| GossipsubControlAction::Prune {
                     topic_hash: TopicHash::from_raw(prune.topic_id.unwrap_or_default()),
+                    peers: prune
+                        .peers
+                        .into_iter()
+                        .map(|info| {
+                            let peer_id = info.peer_id.and_then(|id| PeerId::from_bytes(id).ok());
+                            // only records signed by the suggested peer itself are kept
+                            let signed_peer_record = info
+                                .signed_peer_record
+                                .and_then(|r| SignedEnvelope::from_protobuf_encoding(&r).ok())
+                                .and_then(|e| PeerRecord::from_signed_envelope(e).ok())
+                                .filter(|r| Some(r.peer_id()) == peer_id.as_ref());
+                            PeerInfo {
+                                peer_id,
+                                signed_peer_record,
+                            }
+                        })
+                        .collect(),
+                    backoff: prune.backoff,
+                })
+                .collect();
+
+            let idontwant_msgs: Vec<GossipsubControlAction> = rpc_control
+                .idontwant
+                .into_iter()
+                .map(|idontwant| GossipsubControlAction::IDontWant {
+                    message_ids: idontwant
+                        .message_ids
+                        .into_iter()
+                        .map(MessageId::from)
+                        .collect::<Vec<_>>(),
                 })
                 .collect();
 
@@ -444,9 +577,10 @@ impl Decoder for GossipsubCodec {
             control_msgs.extend(iwant_msgs);
             control_msgs.extend(graft_msgs);
             control_msgs.extend(prune_msgs);
+            control_msgs.extend(idontwant_msgs);
         }
 
-        Ok(Some(GossipsubRpc {
+        let rpc = GossipsubRpc {
             messages,
             subscriptions: rpc
                 .subscriptions
@@ -461,6 +595,11 @@ impl Decoder for GossipsubCodec {
                 })
                 .collect(),
             control_msgs,
+        };
+
+        Ok(Some(HandlerEvent::Message {
+            rpc,
+            invalid_messages,
         }))
     }
 }
@@ -573,7 +712,39 @@ pub enum GossipsubControlAction {
     Prune {
         /// The mesh topic the peer should be removed from.
         topic_hash: TopicHash,
+        /// A list of peers of the topic to connect to instead (peer exchange), only sent to
+        /// gossipsub v1.1 peers.
+        peers: Vec<PeerInfo>,
+        /// The number of seconds during which the pruned peer should not GRAFT again, only sent to
+        /// gossipsub v1.1 peers.
+        backoff: Option<u64>,
     },
+    /// The node already has the given messages and asks not to be sent them - IDontWant control
+    /// message, only sent to gossipsub v1.2 peers.
+    IDontWant {
+        /// A list of message ids the node already received.
+        message_ids: Vec<MessageId>,
+    },
+}
+
+/// A peer suggested through peer exchange in a PRUNE.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    /// The id of the peer, if it could be decoded.
+    pub peer_id: Option<PeerId>,
+    /// The signed record of the addresses of the peer, if it is known. A received record is
+    /// only kept if its signature is valid and it describes `peer_id`.
+    pub signed_peer_record: Option<PeerRecord>,
+}
+
+impl Hash for PeerInfo {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.peer_id.hash(state);
+        self.signed_peer_record
+            .as_ref()
+            .map(|r| r.seq())
+            .hash(state);
+    }
 }
 
 #[cfg(test)]
@@ -658,13 +829,106 @@ mod tests {
             let mut codec = GossipsubCodec::new(codec::UviBytes::default(), ValidationMode::Strict);
             let mut buf = BytesMut::new();
             codec.encode(rpc.clone(), &mut buf).unwrap();
-            let mut decoded_rpc = codec.decode(&mut buf).unwrap().unwrap();
-            // mark as validated as its a published message
-            decoded_rpc.messages[0].validated = true;
-
-            assert_eq!(rpc, decoded_rpc);
+            let decoded = codec.decode(&mut buf).unwrap().unwrap();
+            match decoded {
+                HandlerEvent::Message { rpc: mut decoded_rpc, invalid_messages } => {
+                    assert!(invalid_messages.is_empty());
+                    // mark as validated as its a published message
+                    decoded_rpc.messages[0].validated = true;
+
+                    assert_eq!(rpc, decoded_rpc);
+                }
+                _ => panic!("Must decode a message"),
+            }
         }
 
         QuickCheck::new().quickcheck(prop as fn(_) -> _)
     }
+
+    #[test]
+    /// Test that the peer exchange and backoff of a PRUNE survive the encoding, and that only
+    /// the signed peer records of the suggested peers themselves are kept.
+    fn encode_decode_prune() {
+        let key = Keypair::generate_ed25519();
+        let record =
+            PeerRecord::new(&key, vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap()]).unwrap();
+        let prune = |peers| GossipsubRpc {
+            messages: vec![],
+            subscriptions: vec![],
+            control_msgs: vec![GossipsubControlAction::Prune {
+                topic_hash: Topic::new("test".into()).no_hash(),
+                peers,
+                backoff: Some(60),
+            }],
+        };
+        let rpc = prune(vec![
+            PeerInfo {
+                peer_id: Some(PeerId::random()),
+                signed_peer_record: None,
+            },
+            PeerInfo {
+                peer_id: Some(key.public().into_peer_id()),
+                signed_peer_record: Some(record.clone()),
+            },
+            // the record of another peer
+            PeerInfo {
+                peer_id: Some(PeerId::random()),
+                signed_peer_record: Some(record),
+            },
+        ]);
+
+        let mut codec = GossipsubCodec::new(codec::UviBytes::default(), ValidationMode::Strict);
+        let mut buf = BytesMut::new();
+        codec.encode(rpc.clone(), &mut buf).unwrap();
+        let mut expected = match rpc.control_msgs[0].clone() {
+            GossipsubControlAction::Prune { peers, .. } => peers,
+            _ => unreachable!(),
+        };
+        expected[2].signed_peer_record = None;
+        match codec.decode(&mut buf).unwrap().unwrap() {
+            HandlerEvent::Message {
+                rpc: decoded_rpc, ..
+            } => assert_eq!(prune(expected), decoded_rpc),
+            _ => panic!("Must decode a message"),
+        }
+    }
+
+    #[test]
+    /// Test that the message ids of an IDONTWANT survive the encoding.
+    fn encode_decode_idontwant() {
+        let rpc = GossipsubRpc {
+            messages: vec![],
+            subscriptions: vec![],
+            control_msgs: vec![GossipsubControlAction::IDontWant {
+                message_ids: vec![MessageId::from(vec![1, 2, 3]), MessageId::from(vec![4, 5])],
+            }],
+        };
+
+        let mut codec = GossipsubCodec::new(codec::UviBytes::default(), ValidationMode::Strict);
+        let mut buf = BytesMut::new();
+        codec.encode(rpc.clone(), &mut buf).unwrap();
+        match codec.decode(&mut buf).unwrap().unwrap() {
+            HandlerEvent::Message { rpc: decoded_rpc, .. } => assert_eq!(rpc, decoded_rpc),
+            _ => panic!("Must decode a message"),
+        }
+    }
+
+    #[test]
+    /// Test that all versions of the protocol are negotiated, preferring the most recent one.
+    fn protocol_info_supports_all_versions() {
+        let config = ProtocolConfig::new("/meshsub", 2048, ValidationMode::Strict);
+        let protocols = config
+            .protocol_info()
+            .into_iter()
+            .map(|p| (p.protocol_name().to_vec(), p.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            protocols,
+            vec![
+                (b"/meshsub/1.2.0".to_vec(), PeerKind::Gossipsubv1_2),
+                (b"/meshsub/1.1.0".to_vec(), PeerKind::Gossipsubv1_1),
+                (b"/meshsub/1.0.0".to_vec(), PeerKind::Gossipsub),
+            ]
+        );
+    }
 }
@@ -0,0 +1,230 @@
+// Copyright 2020 Sigma Prime Pty Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Filters deciding which topics we and the remote peers may subscribe to.
+
+use crate::protocol::{GossipsubSubscription, GossipsubSubscriptionAction};
+use crate::topic::TopicHash;
+use log::debug;
+use std::collections::{BTreeSet, HashSet};
+
+/// Decides which topics can be subscribed to, by us through [`crate::Gossipsub::subscribe`] and
+/// by the remote peers through the subscriptions of their RPCs.
+///
+/// The subscriptions that are not allowed are dropped, so that they neither reach the topic
+/// peers of the behaviour nor the application.
+pub trait TopicSubscriptionFilter {
+    /// Returns whether the topic can be subscribed to.
+    fn can_subscribe(&mut self, topic_hash: &TopicHash) -> bool;
+
+    /// Filters the subscriptions of an RPC received from a peer that is currently subscribed to
+    /// `currently_subscribed_topics`, returning the allowed subscriptions. Returning an error
+    /// drops all the subscriptions of the RPC.
+    fn filter_incoming_subscriptions<'a>(
+        &mut self,
+        subscriptions: &'a [GossipsubSubscription],
+        currently_subscribed_topics: &BTreeSet<TopicHash>,
+    ) -> Result<HashSet<&'a GossipsubSubscription>, String> {
+        let _ = currently_subscribed_topics;
+        Ok(subscriptions
+            .iter()
+            .filter(|s| {
+                let allowed = self.allow_incoming_subscription(s);
+                if !allowed {
+                    debug!("Filtered incoming subscription {:?}", s);
+                }
+                allowed
+            })
+            .collect())
+    }
+
+    /// Returns whether a single subscription received from a peer is allowed.
+    fn allow_incoming_subscription(&mut self, subscription: &GossipsubSubscription) -> bool {
+        self.can_subscribe(&subscription.topic_hash)
+    }
+}
+
+/// Allows all the subscriptions.
+#[derive(Debug, Clone, Default)]
+pub struct AllowAllSubscriptionFilter;
+
+impl TopicSubscriptionFilter for AllowAllSubscriptionFilter {
+    fn can_subscribe(&mut self, _: &TopicHash) -> bool {
+        true
+    }
+}
+
+/// Only allows the subscriptions to the topics of an allow-list.
+#[derive(Debug, Clone, Default)]
+pub struct WhitelistSubscriptionFilter(pub HashSet<TopicHash>);
+
+impl TopicSubscriptionFilter for WhitelistSubscriptionFilter {
+    fn can_subscribe(&mut self, topic_hash: &TopicHash) -> bool {
+        self.0.contains(topic_hash)
+    }
+}
+
+/// Only allows the subscriptions to the topics whose hash matches a regular expression, e.g.
+/// `^/eth2/` to allow the topics starting with a given prefix.
+#[derive(Debug, Clone)]
+pub struct RegexSubscriptionFilter(pub regex::Regex);
+
+impl TopicSubscriptionFilter for RegexSubscriptionFilter {
+    fn can_subscribe(&mut self, topic_hash: &TopicHash) -> bool {
+        self.0.is_match(topic_hash.as_str())
+    }
+}
+
+/// Wraps another filter, additionally capping the number of topics each peer can be subscribed
+/// to and the number of subscriptions per RPC.
+#[derive(Debug, Clone)]
+pub struct MaxCountSubscriptionFilter<T> {
+    /// The wrapped filter.
+    pub filter: T,
+    /// The maximum number of topics a peer can be subscribed to. The subscriptions exceeding it
+    /// are dropped.
+    pub max_subscribed_topics: usize,
+    /// The maximum number of subscriptions in an RPC. All the subscriptions of an RPC exceeding
+    /// it are dropped.
+    pub max_subscriptions_per_request: usize,
+}
+
+impl Default for MaxCountSubscriptionFilter<AllowAllSubscriptionFilter> {
+    /// Allows all the topics, up to 100 topics per peer and 100 subscriptions per RPC.
+    fn default() -> Self {
+        MaxCountSubscriptionFilter {
+            filter: AllowAllSubscriptionFilter,
+            max_subscribed_topics: 100,
+            max_subscriptions_per_request: 100,
+        }
+    }
+}
+
+impl<T: TopicSubscriptionFilter> TopicSubscriptionFilter for MaxCountSubscriptionFilter<T> {
+    fn can_subscribe(&mut self, topic_hash: &TopicHash) -> bool {
+        self.filter.can_subscribe(topic_hash)
+    }
+
+    fn filter_incoming_subscriptions<'a>(
+        &mut self,
+        subscriptions: &'a [GossipsubSubscription],
+        currently_subscribed_topics: &BTreeSet<TopicHash>,
+    ) -> Result<HashSet<&'a GossipsubSubscription>, String> {
+        if subscriptions.len() > self.max_subscriptions_per_request {
+            return Err(format!(
+                "Too many subscriptions in one request: {} > {}",
+                subscriptions.len(),
+                self.max_subscriptions_per_request
+            ));
+        }
+
+        let allowed = self
+            .filter
+            .filter_incoming_subscriptions(subscriptions, currently_subscribed_topics)?;
+
+        // Count the topics the peer is subscribed to once the subscriptions are applied,
+        // dropping the new subscriptions beyond the limit.
+        let mut topics = currently_subscribed_topics.iter().collect::<HashSet<_>>();
+        let mut result = HashSet::new();
+        for s in subscriptions.iter().filter(|s| allowed.contains(s)) {
+            match s.action {
+                GossipsubSubscriptionAction::Subscribe => {
+                    if topics.contains(&s.topic_hash) || topics.len() < self.max_subscribed_topics {
+                        topics.insert(&s.topic_hash);
+                        result.insert(s);
+                    } else {
+                        debug!("Filtered incoming subscription {:?}: too many topics", s);
+                    }
+                }
+                GossipsubSubscriptionAction::Unsubscribe => {
+                    topics.remove(&s.topic_hash);
+                    result.insert(s);
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subscription(topic: &str, action: GossipsubSubscriptionAction) -> GossipsubSubscription {
+        GossipsubSubscription {
+            action,
+            topic_hash: TopicHash::from_raw(topic),
+        }
+    }
+
+    #[test]
+    fn whitelist_filter() {
+        let mut filter =
+            WhitelistSubscriptionFilter(vec![TopicHash::from_raw("t1")].into_iter().collect());
+        let subscriptions = vec![
+            subscription("t1", GossipsubSubscriptionAction::Subscribe),
+            subscription("t2", GossipsubSubscriptionAction::Subscribe),
+            subscription("t2", GossipsubSubscriptionAction::Unsubscribe),
+        ];
+
+        let allowed = filter
+            .filter_incoming_subscriptions(&subscriptions, &BTreeSet::new())
+            .unwrap();
+        assert_eq!(allowed, vec![&subscriptions[0]].into_iter().collect());
+        assert!(!filter.can_subscribe(&TopicHash::from_raw("t2")));
+    }
+
+    #[test]
+    fn regex_filter() {
+        let mut filter = RegexSubscriptionFilter(regex::Regex::new("^/app/").unwrap());
+        assert!(filter.can_subscribe(&TopicHash::from_raw("/app/blocks")));
+        assert!(!filter.can_subscribe(&TopicHash::from_raw("/other/app/blocks")));
+    }
+
+    #[test]
+    fn max_count_filter() {
+        let mut filter = MaxCountSubscriptionFilter {
+            filter: AllowAllSubscriptionFilter,
+            max_subscribed_topics: 2,
+            max_subscriptions_per_request: 4,
+        };
+        let current = vec![TopicHash::from_raw("t1")].into_iter().collect();
+
+        // t1 is replaced by t3, so that t2 and t3 fit, but t4 doesn't
+        let subscriptions = vec![
+            subscription("t2", GossipsubSubscriptionAction::Subscribe),
+            subscription("t1", GossipsubSubscriptionAction::Unsubscribe),
+            subscription("t3", GossipsubSubscriptionAction::Subscribe),
+            subscription("t4", GossipsubSubscriptionAction::Subscribe),
+        ];
+        let allowed = filter
+            .filter_incoming_subscriptions(&subscriptions, &current)
+            .unwrap();
+        assert_eq!(allowed, subscriptions[..3].iter().collect());
+
+        // too many subscriptions in one request
+        let subscriptions = (0..5)
+            .map(|i| subscription(&i.to_string(), GossipsubSubscriptionAction::Subscribe))
+            .collect::<Vec<_>>();
+        assert!(filter
+            .filter_incoming_subscriptions(&subscriptions, &current)
+            .is_err());
+    }
+}
@@ -30,6 +30,7 @@ use libp2p_core::{
     upgrade::{ReadOneError, UpgradeError}
 };
 use libp2p_swarm::{
+    AddressSource,
     NegotiatedSubstream,
     NetworkBehaviour,
     NetworkBehaviourAction,
@@ -176,6 +177,22 @@ impl NetworkBehaviour for Identify {
         >,
     > {
         if let Some(event) = self.events.pop_front() {
+            if let NetworkBehaviourAction::GenerateEvent(
+                IdentifyEvent::Received { peer_id, info, .. }
+            ) = &event {
+                let peer_store = params.peer_store_mut();
+                peer_store.set_protocols(peer_id, info.protocols.clone());
+                peer_store.set_agent_version(peer_id, info.agent_version.clone());
+                for addr in &info.listen_addrs {
+                    // Keep what is already known better than a reported address.
+                    let known = peer_store.peer(peer_id)
+                        .and_then(|r| r.addresses().find(|a| a.address == *addr))
+                        .map_or(false, |a| a.source == AddressSource::Manual || a.source == AddressSource::Connected);
+                    if !known {
+                        peer_store.add_address(peer_id, addr.clone(), AddressSource::Reported, None);
+                    }
+                }
+            }
             return Poll::Ready(event);
         }
 
@@ -279,7 +296,7 @@ mod tests {
     };
     use libp2p_tcp::TcpConfig;
     use libp2p_secio::SecioConfig;
-    use libp2p_swarm::{Swarm, SwarmEvent};
+    use libp2p_swarm::{AddressSource, Swarm, SwarmEvent};
     use libp2p_mplex::MplexConfig;
     use std::{fmt, io};
 
@@ -363,4 +380,64 @@ mod tests {
             }
         })
     }
+
+    #[test]
+    fn listen_addrs_are_recorded() {
+        let mut swarm1 = {
+            let (pubkey, transport) = transport();
+            let protocol = Identify::new("a".to_string(), "b".to_string(), pubkey.clone());
+            Swarm::new(transport, protocol, pubkey.into_peer_id())
+        };
+        let swarm1_id = Swarm::local_peer_id(&swarm1).clone();
+
+        let mut swarm2 = {
+            let (pubkey, transport) = transport();
+            let protocol = Identify::new("c".to_string(), "d".to_string(), pubkey.clone());
+            Swarm::new(transport, protocol, pubkey.into_peer_id())
+        };
+
+        Swarm::listen_on(&mut swarm1, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+        Swarm::listen_on(&mut swarm2, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+        let (listen_addr1, listen_addr2) = async_std::task::block_on(async {
+            let addr1 = loop {
+                if let SwarmEvent::NewListenAddr(addr) = swarm1.next_event().await {
+                    break addr
+                }
+            };
+            let addr2 = loop {
+                if let SwarmEvent::NewListenAddr(addr) = swarm2.next_event().await {
+                    break addr
+                }
+            };
+            (addr1, addr2)
+        });
+        async_std::task::block_on(async move {
+            let mut dial = true;
+            loop {
+                // `swarm2` only learns the listen address of `swarm1` through identify.
+                if dial {
+                    Swarm::dial_addr(&mut swarm1, listen_addr2.clone()).unwrap();
+                    dial = false;
+                }
+                let swarm1_fut = swarm1.next_event();
+                pin_mut!(swarm1_fut);
+                let swarm2_fut = swarm2.next_event();
+                pin_mut!(swarm2_fut);
+
+                let event = future::select(swarm1_fut, swarm2_fut).await.factor_second().0;
+                match event {
+                    future::Either::Right(SwarmEvent::Behaviour(IdentifyEvent::Received { .. })) => break,
+                    // `swarm1` may close the connection once it identified
+                    // `swarm2`, before `swarm2` identified it.
+                    future::Either::Left(SwarmEvent::ConnectionClosed { .. }) => dial = true,
+                    _ => {}
+                }
+            }
+            let record = Swarm::peer_store(&swarm2).peer(&swarm1_id).unwrap();
+            let addresses = record.addresses().collect::<Vec<_>>();
+            assert_eq!(addresses.len(), 1);
+            assert_eq!(addresses[0].address, listen_addr1);
+            assert_eq!(addresses[0].source, AddressSource::Reported);
+        })
+    }
 }
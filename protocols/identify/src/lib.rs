@@ -30,7 +30,8 @@
 //!
 //! The [`Identify`] struct implements a `NetworkBehaviour` that negotiates
 //! and executes the protocol on every established connection, emitting
-//! [`IdentifyEvent`]s.
+//! [`IdentifyEvent`]s. The protocols and agent version reported by a peer
+//! are recorded in the `PeerStore` of the `Swarm`.
 //!
 //! [Identify]: https://github.com/libp2p/specs/tree/master/identify
 //! [`Identify`]: self::Identify
@@ -24,16 +24,19 @@ mod test;
 
 use crate::K_VALUE;
 use crate::addresses::Addresses;
-use crate::handler::{KademliaHandler, KademliaHandlerConfig, KademliaRequestId, KademliaHandlerEvent, KademliaHandlerIn};
+use crate::handler::{KademliaHandlerProto, KademliaHandlerConfig, KademliaRequestId, KademliaHandlerEvent, KademliaHandlerIn};
 use crate::jobs::*;
-use crate::kbucket::{self, KBucketsTable, NodeStatus};
+use crate::kbucket::{self, DiversityFilter, DiversityGroup, DiversityLimits, KBucketsTable, NodeStatus};
 use crate::protocol::{KademliaProtocolConfig, KadConnectionType, KadPeer};
 use crate::query::{Query, QueryId, QueryPool, QueryConfig, QueryPoolState};
-use crate::record::{self, store::{self, RecordStore}, Record, ProviderRecord};
+use crate::record::{self, store::{self, RecordStore}, Record, RecordValidator, ProviderRecord};
+use crate::snapshot::{RoutingTableEntry, RoutingTableSnapshot};
 use fnv::{FnvHashMap, FnvHashSet};
-use libp2p_core::{ConnectedPoint, Multiaddr, PeerId, connection::ConnectionId};
+use futures::prelude::*;
+use libp2p_core::{ConnectedPoint, Multiaddr, PeerId, connection::ConnectionId, multiaddr::Protocol};
 use libp2p_swarm::{
     DialPeerCondition,
+    IntoProtocolsHandler,
     NetworkBehaviour,
     NetworkBehaviourAction,
     NotifyHandler,
@@ -43,12 +46,13 @@ use libp2p_swarm::{
 use log::{info, debug, warn};
 use smallvec::SmallVec;
 use std::{borrow::{Borrow, Cow}, error, iter, time::Duration};
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::num::NonZeroUsize;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::vec;
-use wasm_timer::Instant;
+use wasm_timer::{Delay, Instant, SystemTime};
 
 pub use crate::query::QueryStats;
 
@@ -61,6 +65,12 @@ pub struct Kademlia<TStore> {
     /// The k-bucket insertion strategy.
     kbucket_inserts: KademliaBucketInserts,
 
+    /// The record storage insertion strategy for inbound records.
+    record_filtering: KademliaStoreInserts,
+
+    /// Whether the local node acts as a client or as a server of the DHT.
+    mode: KademliaMode,
+
     /// Configuration of the wire protocol.
     protocol_config: KademliaProtocolConfig,
 
@@ -72,6 +82,17 @@ pub struct Kademlia<TStore> {
     /// This is a superset of the connected peers currently in the routing table.
     connected_peers: FnvHashSet<PeerId>,
 
+    /// When the local node was last connected to the currently
+    /// disconnected peers in the routing table, as far as known.
+    last_seen: FnvHashMap<PeerId, SystemTime>,
+
+    /// The peers in the routing table imported from a snapshot with whom
+    /// the local node has not yet been connected.
+    unverified: FnvHashSet<PeerId>,
+
+    /// The interval and timer for periodic snapshots of the routing table.
+    routing_table_snapshot: Option<(Duration, Delay)>,
+
     /// Periodic job for re-publication of provider records for keys
     /// provided by the local node.
     add_provider_job: Option<AddProviderJob>,
@@ -80,6 +101,13 @@ pub struct Kademlia<TStore> {
     /// regular (value-)records.
     put_record_job: Option<PutRecordJob>,
 
+    /// Periodic job for refreshing the buckets of the routing table.
+    bucket_refresh_job: Option<BucketRefreshJob>,
+
+    /// Whether the local node bootstraps as soon as the routing
+    /// table is populated.
+    bootstrap_on_start: bool,
+
     /// The TTL of regular (value-)records.
     record_ttl: Option<Duration>,
 
@@ -89,6 +117,9 @@ pub struct Kademlia<TStore> {
     /// How long to keep connections alive when they're idle.
     connection_idle_timeout: Duration,
 
+    /// The validator for records received from remote nodes, if any.
+    record_validator: Option<Arc<dyn RecordValidator>>,
+
     /// Queued events to return when the behaviour is being polled.
     queued_events: VecDeque<NetworkBehaviourAction<KademliaHandlerIn<QueryId>, KademliaEvent>>,
 
@@ -120,6 +151,42 @@ pub enum KademliaBucketInserts {
     Manual,
 }
 
+/// The configurable filtering strategies for the acceptance of
+/// incoming records.
+///
+/// This can be used for e.g. signature verification, rate limiting
+/// or allow-listing of key namespaces.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KademliaStoreInserts {
+    /// Whenever a (provider) record is received,
+    /// the record is forwarded immediately to the [`RecordStore`].
+    Unfiltered,
+    /// Whenever a (provider) record is received, an event is emitted.
+    /// Provider records generate a [`InboundRequest::AddProvider`] under
+    /// [`KademliaEvent::InboundRequest`], normal records generate a
+    /// [`InboundRequest::PutRecord`] under [`KademliaEvent::InboundRequest`].
+    ///
+    /// When deemed valid, a (provider) record needs to be explicitly stored in
+    /// the [`RecordStore`] via [`RecordStore::put`] or [`RecordStore::add_provider`],
+    /// whichever is applicable. A mutable reference to the [`RecordStore`] can
+    /// be retrieved via [`Kademlia::store_mut`].
+    FilterBoth,
+}
+
+/// The modes in which the local node can participate in the DHT.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KademliaMode {
+    /// The local node only queries the DHT. It does not accept inbound
+    /// Kademlia substreams and thus does not serve requests of remote
+    /// nodes, which consequently never add it to their routing tables.
+    ///
+    /// Suitable for nodes that are not publicly reachable, e.g. mobile
+    /// or browser nodes.
+    Client,
+    /// The local node queries the DHT and serves requests of remote nodes.
+    Server,
+}
+
 /// The configuration for the `Kademlia` behaviour.
 ///
 /// The configuration is consumed by [`Kademlia::new`].
@@ -135,6 +202,28 @@ pub struct KademliaConfig {
     provider_publication_interval: Option<Duration>,
     connection_idle_timeout: Duration,
     kbucket_inserts: KademliaBucketInserts,
+    record_filtering: KademliaStoreInserts,
+    mode: KademliaMode,
+    record_validator: Option<Arc<dyn RecordValidator>>,
+    routing_table_snapshot_interval: Option<Duration>,
+    bootstrap_on_start: bool,
+    bucket_refresh_interval: Option<Duration>,
+    diversity_limits: DiversityLimits,
+    diversity_grouping: Option<DiversityGrouping>,
+}
+
+/// A custom grouping of addresses for the diversity limits of the routing table.
+///
+/// See [`KademliaConfig::set_diversity_grouping`].
+#[derive(Clone)]
+struct DiversityGrouping(Arc<GroupingFn>);
+
+type GroupingFn = dyn Fn(&Multiaddr) -> Option<u64> + Send + Sync;
+
+impl fmt::Debug for DiversityGrouping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DiversityGrouping").finish()
+    }
 }
 
 impl Default for KademliaConfig {
@@ -150,6 +239,14 @@ impl Default for KademliaConfig {
             provider_record_ttl: Some(Duration::from_secs(24 * 60 * 60)),
             connection_idle_timeout: Duration::from_secs(10),
             kbucket_inserts: KademliaBucketInserts::OnConnected,
+            record_filtering: KademliaStoreInserts::Unfiltered,
+            mode: KademliaMode::Server,
+            record_validator: None,
+            routing_table_snapshot_interval: None,
+            bootstrap_on_start: false,
+            bucket_refresh_interval: None,
+            diversity_limits: Default::default(),
+            diversity_grouping: None,
         }
     }
 }
@@ -311,6 +408,112 @@ impl KademliaConfig {
         self.kbucket_inserts = inserts;
         self
     }
+
+    /// Sets whether inbound (provider) records are stored as received or
+    /// only after explicit acceptance by the application.
+    ///
+    /// The default is [`KademliaStoreInserts::Unfiltered`].
+    pub fn set_record_filtering(&mut self, filtering: KademliaStoreInserts) -> &mut Self {
+        self.record_filtering = filtering;
+        self
+    }
+
+    /// Sets the mode in which the local node participates in the DHT.
+    ///
+    /// The default is [`KademliaMode::Server`]. The mode can be changed
+    /// later on via [`Kademlia::set_mode`].
+    pub fn set_mode(&mut self, mode: KademliaMode) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the validator for records received from remote nodes.
+    ///
+    /// Without a validator, all (unexpired) records received from remote
+    /// nodes are accepted and the records of a successful `GET_VALUE`
+    /// query are returned in the order in which they were received.
+    /// With a validator, `GET_VALUE` queries run until they terminate
+    /// instead of finishing once their quorum is reached.
+    /// See [`RecordValidator`] for details.
+    pub fn set_record_validator<V: RecordValidator>(&mut self, validator: V) -> &mut Self {
+        self.record_validator = Some(Arc::new(validator));
+        self
+    }
+
+    /// Sets the interval at which a snapshot of the routing table is
+    /// reported in a [`KademliaEvent::RoutingTableSnapshot`], e.g. to be
+    /// persisted for a warm restart.
+    ///
+    /// `None` means that no periodic snapshots are taken, the default.
+    /// See also [`Kademlia::routing_table_snapshot`].
+    pub fn set_routing_table_snapshot_interval(&mut self, interval: Option<Duration>) -> &mut Self {
+        self.routing_table_snapshot_interval = interval;
+        self
+    }
+
+    /// Sets whether the local node automatically bootstraps, as per
+    /// [`Kademlia::bootstrap`], as soon as its routing table contains a
+    /// peer, e.g. after the addresses of the bootstrap nodes are added.
+    ///
+    /// The default is `false`.
+    pub fn set_bootstrap_on_start(&mut self, enabled: bool) -> &mut Self {
+        self.bootstrap_on_start = enabled;
+        self
+    }
+
+    /// Sets the interval for refreshing the buckets of the routing table.
+    ///
+    /// A bucket for whose range of keys no lookup has been performed within
+    /// the interval is refreshed by a lookup for a random key in that range,
+    /// reported as a [`QueryResult::Bootstrap`]. The Kademlia paper proposes
+    /// an interval of one hour.
+    ///
+    /// Only the 10 buckets farthest from the local key can be refreshed, since
+    /// a random key in the range of a closer bucket is too unlikely to be
+    /// found, see [`kbucket::KBucketRef::rand_peer_key`]. In a network of more
+    /// than about a thousand nodes, the closest non-empty buckets are thus not
+    /// refreshed automatically. They are refreshed by the lookup for the local
+    /// key performed by [`Kademlia::bootstrap`], which should be called
+    /// periodically in such networks.
+    ///
+    /// `None` means that buckets are never refreshed automatically, the default.
+    pub fn set_bucket_refresh_interval(&mut self, interval: Option<Duration>) -> &mut Self {
+        self.bucket_refresh_interval = interval;
+        self
+    }
+
+    /// Sets the limits on the number of peers per IP subnet, or per custom
+    /// group (see [`KademliaConfig::set_diversity_grouping`]), in the buckets
+    /// and in the routing table as a whole.
+    ///
+    /// A peer is in the IPv4 `/24` or IPv6 `/48` subnets of the IP addresses
+    /// its known addresses start with. A peer whose insertion into the routing
+    /// table would exceed a limit is rejected, as reported by
+    /// [`RoutingUpdate::Rejected`]. So is a peer without any group, e.g. a
+    /// peer with only `/dns4` or `/dns6` addresses, since it cannot be
+    /// accounted for. A new address of a peer in the routing table that
+    /// puts the peer into another group is subject to the same limits.
+    ///
+    /// By default, there are no limits.
+    pub fn set_diversity_limits(&mut self, limits: DiversityLimits) -> &mut Self {
+        self.diversity_limits = limits;
+        self
+    }
+
+    /// Sets a custom grouping of peers for the diversity limits, e.g. by
+    /// the autonomous system an address belongs to.
+    ///
+    /// A peer is in the [`DiversityGroup::Custom`] groups of its known
+    /// addresses, as determined by `grouping`. The number of peers per
+    /// custom group is limited by [`DiversityLimits::custom_per_bucket`]
+    /// and [`DiversityLimits::custom_per_table`].
+    pub fn set_diversity_grouping<F>(&mut self, grouping: F) -> &mut Self
+    where
+        F: Fn(&Multiaddr) -> Option<u64> + Send + Sync + 'static
+    {
+        self.diversity_grouping = Some(DiversityGrouping(Arc::new(grouping)));
+        self
+    }
 }
 
 impl<TStore> Kademlia<TStore>
@@ -345,19 +548,64 @@ where
             .provider_publication_interval
             .map(AddProviderJob::new);
 
+        let mut kbuckets = KBucketsTable::new(local_key, config.kbucket_pending_timeout);
+        if config.diversity_limits != DiversityLimits::default() {
+            let grouping = config.diversity_grouping;
+            let filter = DiversityFilter::new(config.diversity_limits, move |addrs: &Addresses| {
+                diversity_groups(addrs, grouping.as_ref())
+            });
+            kbuckets.set_diversity_filter(Some(filter));
+        }
+
         Kademlia {
             store,
-            kbuckets: KBucketsTable::new(local_key, config.kbucket_pending_timeout),
+            kbuckets,
             kbucket_inserts: config.kbucket_inserts,
+            record_filtering: config.record_filtering,
+            mode: config.mode,
             protocol_config: config.protocol_config,
             queued_events: VecDeque::with_capacity(config.query_config.replication_factor.get()),
             queries: QueryPool::new(config.query_config),
             connected_peers: Default::default(),
             add_provider_job,
             put_record_job,
+            bucket_refresh_job: config.bucket_refresh_interval.map(BucketRefreshJob::new),
+            bootstrap_on_start: config.bootstrap_on_start,
             record_ttl: config.record_ttl,
             provider_record_ttl: config.provider_record_ttl,
             connection_idle_timeout: config.connection_idle_timeout,
+            record_validator: config.record_validator,
+            last_seen: Default::default(),
+            unverified: Default::default(),
+            routing_table_snapshot: config.routing_table_snapshot_interval
+                .map(|interval| (interval, Delay::new(interval))),
+        }
+    }
+
+    /// Returns the mode in which the local node currently participates in the DHT.
+    pub fn mode(&self) -> KademliaMode {
+        self.mode
+    }
+
+    /// Sets the mode in which the local node participates in the DHT.
+    ///
+    /// This can be used to switch from [`KademliaMode::Client`] to
+    /// [`KademliaMode::Server`] once the local node learns that it is
+    /// publicly reachable, or vice versa. The change applies to existing
+    /// as well as to new connections.
+    pub fn set_mode(&mut self, mode: KademliaMode) {
+        if self.mode == mode {
+            return
+        }
+
+        self.mode = mode;
+        let allow_listening = mode == KademliaMode::Server;
+        for peer_id in self.connected_peers.iter() {
+            self.queued_events.push_back(NetworkBehaviourAction::NotifyHandler {
+                peer_id: peer_id.clone(),
+                handler: NotifyHandler::All,
+                event: KademliaHandlerIn::SetAllowListening(allow_listening),
+            });
         }
     }
 
@@ -422,11 +670,17 @@ where
         let key = kbucket::Key::new(peer.clone());
         match self.kbuckets.entry(&key) {
             kbucket::Entry::Present(mut entry, _) => {
-                if entry.value().insert(address) {
+                let mut addresses = entry.value().clone();
+                if addresses.insert(address) {
+                    if let Err(group) = entry.set_value(addresses.clone()) {
+                        debug!("Diversity limit of {:?} exceeded. Address of {} not added.",
+                               group, peer);
+                        return RoutingUpdate::Rejected { group }
+                    }
                     self.queued_events.push_back(NetworkBehaviourAction::GenerateEvent(
                         KademliaEvent::RoutingUpdated {
                             peer: peer.clone(),
-                            addresses: entry.value().clone(),
+                            addresses,
                             old_peer: None,
                         }
                     ))
@@ -460,6 +714,11 @@ where
                         debug!("Bucket full. Peer not added to routing table: {}", peer);
                         RoutingUpdate::Failed
                     },
+                    kbucket::InsertResult::Rejected { group } => {
+                        debug!("Diversity limit of {:?} exceeded. Peer not added to routing table: {}",
+                               group, peer);
+                        RoutingUpdate::Rejected { group }
+                    },
                     kbucket::InsertResult::Pending { disconnected } => {
                         self.queued_events.push_back(NetworkBehaviourAction::DialPeer {
                             peer_id: disconnected.into_preimage(),
@@ -489,9 +748,15 @@ where
         let key = kbucket::Key::new(peer.clone());
         match self.kbuckets.entry(&key) {
             kbucket::Entry::Present(mut entry, _) => {
-                if entry.value().remove(address).is_err() {
+                let mut addresses = entry.value().clone();
+                if addresses.remove(address).is_err() {
+                    self.last_seen.remove(peer);
+                    self.unverified.remove(peer);
                     Some(entry.remove()) // it is the last address, thus remove the peer.
                 } else {
+                    // Removing an address never puts the peer into a new group.
+                    entry.set_value(addresses)
+                        .expect("The peer is already counted in all remaining groups; QED");
                     None
                 }
             }
@@ -516,6 +781,8 @@ where
         -> Option<kbucket::EntryView<kbucket::Key<PeerId>, Addresses>>
     {
         let key = kbucket::Key::new(peer.clone());
+        self.last_seen.remove(peer);
+        self.unverified.remove(peer);
         match self.kbuckets.entry(&key) {
             kbucket::Entry::Present(entry, _) => {
                 Some(entry.remove())
@@ -529,6 +796,119 @@ where
         }
     }
 
+    /// Takes a snapshot of the routing table, e.g. to be persisted
+    /// for a warm restart via [`Kademlia::import_routing_table`].
+    pub fn routing_table_snapshot(&mut self) -> RoutingTableSnapshot {
+        let now = SystemTime::now();
+        let mut entries = Vec::new();
+        for bucket in self.kbuckets.iter() {
+            for entry in bucket.iter() {
+                let peer = entry.node.key.preimage().clone();
+                let status = entry.status;
+                let last_seen = if status == NodeStatus::Connected {
+                    Some(now)
+                } else {
+                    self.last_seen.get(&peer).cloned()
+                };
+                entries.push(RoutingTableEntry {
+                    peer,
+                    addresses: entry.node.value.iter().cloned().collect(),
+                    status,
+                    last_seen,
+                });
+            }
+        }
+
+        // Forget about peers that are no longer in the routing table.
+        let peers = entries.iter().map(|e| &e.peer).collect::<HashSet<_>>();
+        self.last_seen.retain(|p, _| peers.contains(p));
+        self.unverified.retain(|p| peers.contains(p));
+
+        RoutingTableSnapshot { entries }
+    }
+
+    /// Imports the entries of a snapshot of the routing table, e.g. taken
+    /// via [`Kademlia::routing_table_snapshot`] before a restart, returning
+    /// the number of peers inserted into the routing table.
+    ///
+    /// Entries for the local node, entries without addresses and entries for
+    /// peers that are already in the routing table are ignored, as are entries
+    /// for which the corresponding k-bucket is full. The imported peers are
+    /// inserted as disconnected and are _unverified_ until the local node
+    /// connects to them for the first time: Unverified peers are not shared
+    /// with other nodes and are removed from the routing table if dialing them
+    /// fails. See [`Kademlia::unverified_peers`].
+    ///
+    /// Entries of peers that were connected when the snapshot was taken are
+    /// imported before those of peers that were not, so that the former take
+    /// precedence in full k-buckets.
+    pub fn import_routing_table(&mut self, snapshot: RoutingTableSnapshot) -> usize {
+        let mut entries = snapshot.entries;
+        entries.sort_by_key(|e| e.status != NodeStatus::Connected);
+        let mut num_imported = 0;
+        for entry in entries {
+            let mut addrs = entry.addresses.into_iter();
+            let mut addresses = match addrs.next() {
+                Some(a) => Addresses::new(a),
+                None => {
+                    debug!("Ignoring snapshot entry without addresses: {}", entry.peer);
+                    continue
+                }
+            };
+            for a in addrs {
+                addresses.insert(a);
+            }
+
+            let key = kbucket::Key::new(entry.peer.clone());
+            let result = match self.kbuckets.entry(&key) {
+                kbucket::Entry::Absent(e) => e.insert(addresses.clone(), NodeStatus::Disconnected),
+                kbucket::Entry::SelfEntry => {
+                    debug!("Ignoring snapshot entry of the local node.");
+                    continue
+                }
+                kbucket::Entry::Present(..) | kbucket::Entry::Pending(..) => continue,
+            };
+            match result {
+                kbucket::InsertResult::Inserted => {
+                    num_imported += 1;
+                    self.unverified.insert(entry.peer.clone());
+                    if let Some(t) = entry.last_seen {
+                        self.last_seen.insert(entry.peer.clone(), t);
+                    }
+                    self.queued_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                        KademliaEvent::RoutingUpdated {
+                            peer: entry.peer,
+                            addresses,
+                            old_peer: None,
+                        }
+                    ));
+                }
+                kbucket::InsertResult::Pending { .. } => {
+                    // Imported peers never replace existing ones.
+                    if let kbucket::Entry::Pending(e, _) = self.kbuckets.entry(&key) {
+                        e.remove();
+                    }
+                    debug!("Bucket full. Snapshot entry not imported: {}", entry.peer);
+                }
+                kbucket::InsertResult::Full => {
+                    debug!("Bucket full. Snapshot entry not imported: {}", entry.peer);
+                }
+                kbucket::InsertResult::Rejected { group } => {
+                    debug!("Diversity limit of {:?} exceeded. Snapshot entry not imported: {}",
+                           group, entry.peer);
+                }
+            }
+        }
+        num_imported
+    }
+
+    /// Returns an iterator over the peers in the routing table that were
+    /// imported via [`Kademlia::import_routing_table`] and with whom the
+    /// local node has not yet been connected.
+    pub fn unverified_peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.unverified.iter()
+    }
+
     /// Returns an iterator over all non-empty buckets in the routing table.
     pub fn kbuckets(&mut self)
         -> impl Iterator<Item = kbucket::KBucketRef<'_, kbucket::Key<PeerId>, Addresses>>
@@ -566,6 +946,10 @@ where
     ///
     /// The result of this operation is delivered in a
     /// [`KademliaEvent::QueryResult{QueryResult::GetRecord}`].
+    ///
+    /// If a [`RecordValidator`] is configured, the query does not finish as
+    /// soon as the quorum is reached but collects the records of all peers
+    /// it contacts, so that [`RecordValidator::select`] chooses among them.
     pub fn get_record(&mut self, key: &record::Key, quorum: Quorum) -> QueryId {
         let quorum = quorum.eval(self.queries.config().replication_factor);
         let mut records = Vec::with_capacity(quorum.get());
@@ -577,14 +961,22 @@ where
                 records.push(PeerRecord{ peer: None, record: record.into_owned()});
             }
         }
+        let local_record = records.first().cloned();
 
-        let done = records.len() >= quorum.get();
+        // With a validator, the query collects the records of all peers
+        // to select among them and correct the peers holding stale ones.
+        let done = records.len() >= quorum.get() && self.record_validator.is_none();
         let target = kbucket::Key::new(key.clone());
         let info = QueryInfo::GetRecord { key: key.clone(), records, quorum, cache_at: None };
         let peers = self.kbuckets.closest_keys(&target);
         let inner = QueryInner::new(info);
         let id = self.queries.add_iter_closest(target.clone(), peers, inner); // (*)
 
+        if let Some(record) = local_record {
+            let progress = QueryProgress::GetRecord { key: key.clone(), records: vec![record] };
+            self.query_progressed(id, progress);
+        }
+
         // Instantly finish the query if we already have enough records.
         if done {
             self.queries.get_mut(&id).expect("by (*)").finish();
@@ -659,7 +1051,8 @@ where
     ///
     /// Subsequently, all buckets farther from the bucket of the closest neighbour are
     /// refreshed by initiating an additional bootstrapping query for each such
-    /// bucket with random keys.
+    /// bucket with random keys. Buckets for which no random key is found are
+    /// skipped, see [`kbucket::KBucketRef::rand_peer_key`].
     ///
     /// Returns `Ok` if bootstrapping has been initiated with a self-lookup, providing the
     /// `QueryId` for the entire bootstrapping process. The progress of bootstrapping is
@@ -685,6 +1078,49 @@ where
         }
     }
 
+    /// Crawls the DHT, enumerating the peers that are reachable from the
+    /// local routing table.
+    ///
+    /// The crawl walks the keyspace bucket by bucket, starting with the bucket
+    /// of the closest neighbour. For every bucket, a `FIND_NODE` request for a
+    /// random key in that bucket is sent to every peer known to the crawl,
+    /// including all peers discovered along the way, with the configured
+    /// parallelism. Every response is reported via a
+    /// [`KademliaEvent::CrawlProgress`] and the peers that responded are
+    /// eventually reported via [`KademliaEvent::QueryResult{QueryResult::Crawl}`].
+    ///
+    /// The configured query timeout applies to the walk of every bucket
+    /// individually. A walk that times out does not abort the crawl. Peers
+    /// that do not respond within 10 seconds no longer count towards the
+    /// parallelism, though their responses are still taken into account.
+    ///
+    /// Returns `Err` if crawling is impossible due an empty routing table.
+    ///
+    /// > **Note**: Since every peer is contacted once for every bucket, a
+    /// > crawl is considerably more expensive than a regular query and
+    /// > intended for network monitoring.
+    pub fn crawl(&mut self) -> Result<QueryId, NoKnownPeers> {
+        let local_key = self.kbuckets.local_key().clone();
+        let mut remaining = self.kbuckets.iter()
+            .skip_while(|b| b.is_empty())
+            .filter_map(|b| b.rand_peer_key(&local_key))
+            .collect::<Vec<_>>().into_iter();
+        if let Some(target) = remaining.next() {
+            let info = QueryInfo::Crawl {
+                peer: target.into_preimage(),
+                remaining,
+                peers: HashSet::new(),
+                requests: HashMap::new(),
+                timed_out: false,
+            };
+            let peers = self.crawl_peers(&HashSet::new());
+            let inner = QueryInner::new(info);
+            Ok(self.queries.add_crawl(peers, inner))
+        } else {
+            Err(NoKnownPeers())
+        }
+    }
+
     /// Establishes the local node as a provider of a value for the given key.
     ///
     /// This operation publishes a provider record with the given key and
@@ -765,6 +1201,30 @@ where
         }
     }
 
+    /// Reports an intermediate result of a running query via
+    /// [`KademliaEvent::QueryProgressed`].
+    fn query_progressed(&mut self, id: QueryId, progress: QueryProgress) {
+        if let Some(query) = self.queries.get_mut(&id) {
+            let step = query.inner.next_step(false);
+            self.queued_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                KademliaEvent::QueryProgressed { id, progress, step }
+            ));
+        }
+    }
+
+    /// Emits the result of a query that finished or timed out, preceded
+    /// by the last progress step of the query, if any.
+    fn query_completed(&mut self, last_step: Option<KademliaEvent>, result: KademliaEvent)
+        -> KademliaEvent
+    {
+        if let Some(last_step) = last_step {
+            self.queued_events.push_front(NetworkBehaviourAction::GenerateEvent(result));
+            last_step
+        } else {
+            result
+        }
+    }
+
     /// Finds the closest peers to a `target` in the context of a request by
     /// the `source` peer, such that the `source` peer is never included in the
     /// result.
@@ -772,9 +1232,12 @@ where
         if target == self.kbuckets.local_key() {
             Vec::new()
         } else {
+            let unverified = &self.unverified;
             self.kbuckets
                 .closest(target)
                 .filter(|e| e.node.key.preimage() != source)
+                // Peers imported from a snapshot may be stale.
+                .filter(|e| !unverified.contains(e.node.key.preimage()))
                 .take(self.queries.config().replication_factor.get())
                 .map(KadPeer::from)
                 .collect()
@@ -797,6 +1260,70 @@ where
             .collect()
     }
 
+    /// Starts a lookup for the given key, refreshing the bucket of the
+    /// routing table whose range the key falls into.
+    fn start_bucket_refresh(&mut self, target: kbucket::Key<PeerId>) {
+        let info = QueryInfo::Bootstrap {
+            peer: target.preimage().clone(),
+            remaining: Some(Vec::new().into_iter()),
+        };
+        let peers = self.kbuckets.closest_keys(&target);
+        let inner = QueryInner::new(info);
+        self.queries.add_iter_closest(target.clone(), peers, inner);
+    }
+
+    /// Returns the peers to start the walk of a bucket with in a crawl,
+    /// i.e. the peers in the routing table and the peers that responded
+    /// in earlier walks.
+    fn crawl_peers(&mut self, responded: &HashSet<PeerId>) -> Vec<PeerId> {
+        let mut peers = responded.iter().cloned().collect::<Vec<_>>();
+        for bucket in self.kbuckets.iter() {
+            peers.extend(bucket.iter().map(|e| e.node.key.preimage().clone()));
+        }
+        peers
+    }
+
+    /// Continues a crawl with the walk of the next remaining bucket,
+    /// returning the result of the crawl if all buckets have been walked.
+    fn continue_crawl(
+        &mut self,
+        query_id: QueryId,
+        addresses: FnvHashMap<PeerId, SmallVec<[Multiaddr; 8]>>,
+        mut remaining: vec::IntoIter<kbucket::Key<PeerId>>,
+        peers: HashSet<PeerId>,
+        timed_out: bool,
+    ) -> Option<QueryResult> {
+        if let Some(target) = remaining.next() {
+            let start = self.crawl_peers(&peers);
+            let info = QueryInfo::Crawl {
+                peer: target.into_preimage(),
+                remaining,
+                peers,
+                requests: HashMap::new(),
+                timed_out,
+            };
+            let mut inner = QueryInner::new(info);
+            inner.addresses = addresses;
+            self.queries.continue_crawl(query_id, start, inner);
+            None
+        } else {
+            let peers = peers.into_iter().collect();
+            Some(QueryResult::Crawl(if timed_out {
+                Err(CrawlError::Timeout { peers })
+            } else {
+                Ok(CrawlOk { peers })
+            }))
+        }
+    }
+
+    /// Records the lookup performed by a finished query for the
+    /// bucket refresh job.
+    fn lookup_performed(&mut self, info: &QueryInfo, now: Instant) {
+        if let Some(job) = self.bucket_refresh_job.as_mut() {
+            job.on_lookup(self.kbuckets.local_key(), &info.target(), now);
+        }
+    }
+
     /// Starts an iterative `ADD_PROVIDER` query for the given key.
     fn start_add_provider(&mut self, key: record::Key, context: AddProviderContext) {
         let info = QueryInfo::AddProvider {
@@ -824,18 +1351,32 @@ where
 
     /// Updates the routing table with a new connection status and address of a peer.
     fn connection_updated(&mut self, peer: PeerId, address: Option<Multiaddr>, new_status: NodeStatus) {
+        if new_status == NodeStatus::Connected {
+            // A peer imported from a snapshot is verified by the first connection.
+            self.unverified.remove(&peer);
+        }
+
         let key = kbucket::Key::new(peer.clone());
         match self.kbuckets.entry(&key) {
             kbucket::Entry::Present(mut entry, old_status) => {
                 if let Some(address) = address {
-                    if entry.value().insert(address) {
-                        self.queued_events.push_back(NetworkBehaviourAction::GenerateEvent(
-                            KademliaEvent::RoutingUpdated {
-                                peer,
-                                addresses: entry.value().clone(),
-                                old_peer: None,
+                    let mut addresses = entry.value().clone();
+                    if addresses.insert(address) {
+                        match entry.set_value(addresses.clone()) {
+                            Ok(()) => {
+                                self.queued_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                                    KademliaEvent::RoutingUpdated {
+                                        peer: peer.clone(),
+                                        addresses,
+                                        old_peer: None,
+                                    }
+                                ))
                             }
-                        ))
+                            Err(group) => {
+                                debug!("Diversity limit of {:?} exceeded. Address of {} not added.",
+                                       group, peer);
+                            }
+                        }
                     }
                 }
                 if old_status != new_status {
@@ -887,6 +1428,10 @@ where
                                     KademliaEvent::RoutablePeer { peer, address }
                                 ));
                             },
+                            kbucket::InsertResult::Rejected { group } => {
+                                debug!("Diversity limit of {:?} exceeded. Peer not added to routing table: {}",
+                                       group, peer);
+                            },
                             kbucket::InsertResult::Pending { disconnected } => {
                                 debug_assert!(!self.connected_peers.contains(disconnected.preimage()));
                                 let address = addresses.first().clone();
@@ -925,30 +1470,8 @@ where
                     self.kbuckets.iter()
                         .skip_while(|b| b.is_empty())
                         .skip(1) // Skip the bucket with the closest neighbour.
-                        .map(|b| {
-                            // Try to find a key that falls into the bucket. While such keys can
-                            // be generated fully deterministically, the current libp2p kademlia
-                            // wire protocol requires transmission of the preimages of the actual
-                            // keys in the DHT keyspace, hence for now this is just a "best effort"
-                            // to find a key that hashes into a specific bucket. The probabilities
-                            // of finding a key in the bucket `b` with as most 16 trials are as
-                            // follows:
-                            //
-                            // Pr(bucket-255) = 1 - (1/2)^16   ~= 1
-                            // Pr(bucket-254) = 1 - (3/4)^16   ~= 1
-                            // Pr(bucket-253) = 1 - (7/8)^16   ~= 0.88
-                            // Pr(bucket-252) = 1 - (15/16)^16 ~= 0.64
-                            // ...
-                            let mut target = kbucket::Key::new(PeerId::random());
-                            for _ in 0 .. 16 {
-                                let d = local_key.distance(&target);
-                                if b.contains(&d) {
-                                    break;
-                                }
-                                target = kbucket::Key::new(PeerId::random());
-                            }
-                            target
-                        }).collect::<Vec<_>>().into_iter()
+                        .filter_map(|b| b.rand_peer_key(&local_key))
+                        .collect::<Vec<_>>().into_iter()
                 });
 
                 let num_remaining = remaining.len().saturating_sub(1) as u32;
@@ -970,6 +1493,13 @@ where
                 })
             }
 
+            QueryInfo::Crawl { remaining, mut peers, timed_out, .. } => {
+                peers.extend(result.peers);
+                let stats = result.stats;
+                self.continue_crawl(query_id, result.inner.addresses, remaining, peers, timed_out)
+                    .map(|result| KademliaEvent::QueryResult { id: query_id, stats, result })
+            }
+
             QueryInfo::GetClosestPeers { key, .. } => {
                 Some(KademliaEvent::QueryResult {
                     id: query_id,
@@ -1037,8 +1567,31 @@ where
                 }
             }
 
-            QueryInfo::GetRecord { key, records, quorum, cache_at } => {
+            QueryInfo::GetRecord { key, mut records, quorum, cache_at } => {
                 let results = if records.len() >= quorum.get() { // [not empty]
+                    if let Some(validator) = &self.record_validator {
+                        select_record(validator.as_ref(), &key, &mut records);
+                        // Correct the remote nodes that returned a different value
+                        // than the selected record.
+                        let best = &records.first().expect("[not empty]").record;
+                        let stale = records.iter()
+                            .filter(|r| r.record.value != best.value)
+                            .filter_map(|r| r.peer.clone())
+                            .collect::<Vec<_>>();
+                        if !stale.is_empty() {
+                            let info = QueryInfo::PutRecord {
+                                context: PutRecordContext::Correct,
+                                record: best.clone(),
+                                quorum: NonZeroUsize::new(1).expect("1 > 0"),
+                                phase: PutRecordPhase::PutRecord {
+                                    success: vec![],
+                                    get_closest_peers_stats: QueryStats::empty()
+                                }
+                            };
+                            let inner = QueryInner::new(info);
+                            self.queries.add_fixed(stale, inner);
+                        }
+                    }
                     if let Some(cache_key) = cache_at {
                         // Cache the record at the closest node to the key that
                         // did not return the record.
@@ -1127,6 +1680,10 @@ where
                         debug!("Record cached: {:?}", record.key);
                         None
                     }
+                    PutRecordContext::Correct => {
+                        debug!("Record corrected: {:?}", record.key);
+                        None
+                    }
                 }
             }
         }
@@ -1183,6 +1740,14 @@ where
                         }
                 }),
 
+            QueryInfo::Crawl { remaining, mut peers, .. } => {
+                // The crawl continues with the remaining buckets.
+                peers.extend(result.peers);
+                let stats = result.stats;
+                self.continue_crawl(query_id, result.inner.addresses, remaining, peers, true)
+                    .map(|result| KademliaEvent::QueryResult { id: query_id, stats, result })
+            }
+
             QueryInfo::GetClosestPeers { key } => {
                 Some(KademliaEvent::QueryResult {
                     id: query_id,
@@ -1240,6 +1805,13 @@ where
                             None
                         }
                     }
+                    PutRecordContext::Correct => {
+                        // Correcting stale records is a direct query to the
+                        // peers that returned them. A timeout in either phase
+                        // only fails the correction, it is not reported.
+                        debug!("Correcting record failed: {:?}", err);
+                        None
+                    }
                 }
             }
 
@@ -1291,6 +1863,18 @@ where
             return
         }
 
+        if let Some(validator) = &self.record_validator {
+            if let Err(e) = validator.validate(&record) {
+                info!("Invalid record from {}: {:?}: {}", source, record.key, e);
+                self.queued_events.push_back(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: source,
+                    handler: NotifyHandler::One(connection),
+                    event: KademliaHandlerIn::Reset(request_id)
+                });
+                return
+            }
+        }
+
         let now = Instant::now();
 
         // Calculate the expiration exponentially inversely proportional to the
@@ -1324,25 +1908,43 @@ where
         // overridden as it avoids having to load the existing record in the
         // first place.
 
+        let mut filtered = None;
         if !record.is_expired(now) {
-            // The record is cloned because of the weird libp2p protocol
-            // requirement to send back the value in the response, although this
-            // is a waste of resources.
-            match self.store.put(record.clone()) {
-                Ok(()) => debug!("Record stored: {:?}; {} bytes", record.key, record.value.len()),
-                Err(e) => {
-                    info!("Record not stored: {:?}", e);
-                    self.queued_events.push_back(NetworkBehaviourAction::NotifyHandler {
-                        peer_id: source,
-                        handler: NotifyHandler::One(connection),
-                        event: KademliaHandlerIn::Reset(request_id)
-                    });
+            match self.record_filtering {
+                KademliaStoreInserts::Unfiltered => {
+                    // The record is cloned because of the weird libp2p protocol
+                    // requirement to send back the value in the response, although this
+                    // is a waste of resources.
+                    match self.store.put(record.clone()) {
+                        Ok(()) => debug!("Record stored: {:?}; {} bytes", record.key, record.value.len()),
+                        Err(e) => {
+                            info!("Record not stored: {:?}", e);
+                            self.queued_events.push_back(NetworkBehaviourAction::NotifyHandler {
+                                peer_id: source,
+                                handler: NotifyHandler::One(connection),
+                                event: KademliaHandlerIn::Reset(request_id)
+                            });
 
-                    return
+                            return
+                        }
+                    }
+                }
+                KademliaStoreInserts::FilterBoth => {
+                    filtered = Some(record.clone());
                 }
             }
         }
 
+        self.queued_events.push_back(NetworkBehaviourAction::GenerateEvent(
+            KademliaEvent::InboundRequest {
+                request: InboundRequest::PutRecord {
+                    source: source.clone(),
+                    connection,
+                    record: filtered,
+                }
+            }
+        ));
+
         // The remote receives a [`KademliaHandlerIn::PutRecordRes`] even in the
         // case where the record is discarded due to being expired. Given that
         // the remote sent the local node a [`KademliaHandlerEvent::PutRecord`]
@@ -1362,16 +1964,34 @@ where
     }
 
     /// Processes a provider record received from a peer.
-    fn provider_received(&mut self, key: record::Key, provider: KadPeer) {
+    fn provider_received(
+        &mut self,
+        key: record::Key,
+        provider: KadPeer,
+        connection: ConnectionId
+    ) {
         if &provider.node_id != self.kbuckets.local_key().preimage() {
+            let source = provider.node_id;
             let record = ProviderRecord {
                 key,
-                provider: provider.node_id,
+                provider: source.clone(),
                 expires: self.provider_record_ttl.map(|ttl| Instant::now() + ttl)
             };
-            if let Err(e) = self.store.add_provider(record) {
-                info!("Provider record not stored: {:?}", e);
-            }
+            let record = match self.record_filtering {
+                KademliaStoreInserts::Unfiltered => {
+                    if let Err(e) = self.store.add_provider(record) {
+                        info!("Provider record not stored: {:?}", e);
+                        return
+                    }
+                    None
+                }
+                KademliaStoreInserts::FilterBoth => Some(record),
+            };
+            self.queued_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                KademliaEvent::InboundRequest {
+                    request: InboundRequest::AddProvider { source, connection, record }
+                }
+            ));
         }
     }
 }
@@ -1381,18 +2001,72 @@ fn exp_decrease(ttl: Duration, exp: u32) -> Duration {
     Duration::from_secs(ttl.as_secs().checked_shr(exp).unwrap_or(0))
 }
 
+/// Moves the record selected by the given validator to the front of the
+/// given (non-empty) records.
+fn select_record(validator: &dyn RecordValidator, key: &record::Key, records: &mut [PeerRecord]) {
+    let index = {
+        let candidates = records.iter().map(|r| &r.record).collect::<Vec<_>>();
+        validator.select(key, &candidates)
+    };
+    if index < records.len() {
+        records[..=index].rotate_right(1);
+    } else {
+        warn!("Invalid record selected: {} of {} for {:?}.", index, records.len(), key);
+    }
+}
+
+/// The address of the remote of a connection that may be put into the
+/// routing table.
+///
+/// The remote's address can only be put into the routing table,
+/// and thus shared with other nodes, if the local node is the dialer,
+/// since the remote address on an inbound connection is specific to
+/// that connection (e.g. typically the TCP port numbers).
+fn remote_address(endpoint: &ConnectedPoint) -> Option<Multiaddr> {
+    match endpoint {
+        ConnectedPoint::Dialer { address } => Some(address.clone()),
+        ConnectedPoint::Listener { .. } => None,
+    }
+}
+
+/// Determines the groups of a peer with the given addresses w.r.t. the
+/// diversity limits of the routing table.
+fn diversity_groups(addresses: &Addresses, grouping: Option<&DiversityGrouping>) -> Vec<DiversityGroup> {
+    let mut groups = Vec::new();
+    for addr in addresses.iter() {
+        let subnet = match addr.iter().next() {
+            Some(Protocol::Ip4(ip)) => {
+                let o = ip.octets();
+                Some(DiversityGroup::Ipv4Subnet([o[0], o[1], o[2]]))
+            }
+            Some(Protocol::Ip6(ip)) => {
+                let o = ip.octets();
+                Some(DiversityGroup::Ipv6Subnet([o[0], o[1], o[2], o[3], o[4], o[5]]))
+            }
+            _ => None
+        };
+        let custom = grouping.and_then(|g| (g.0)(addr)).map(DiversityGroup::Custom);
+        for group in subnet.into_iter().chain(custom) {
+            if !groups.contains(&group) {
+                groups.push(group)
+            }
+        }
+    }
+    groups
+}
+
 impl<TStore> NetworkBehaviour for Kademlia<TStore>
 where
     for<'a> TStore: RecordStore<'a>,
     TStore: Send + 'static,
 {
-    type ProtocolsHandler = KademliaHandler<QueryId>;
+    type ProtocolsHandler = KademliaHandlerProto<QueryId>;
     type OutEvent = KademliaEvent;
 
     fn new_handler(&mut self) -> Self::ProtocolsHandler {
-        KademliaHandler::new(KademliaHandlerConfig {
+        KademliaHandlerProto::new(KademliaHandlerConfig {
             protocol_config: self.protocol_config.clone(),
-            allow_listening: true,
+            allow_listening: self.mode == KademliaMode::Server,
             idle_timeout: self.connection_idle_timeout,
         })
     }
@@ -1421,16 +2095,19 @@ where
     }
 
     fn inject_connection_established(&mut self, peer: &PeerId, _: &ConnectionId, endpoint: &ConnectedPoint) {
-        // The remote's address can only be put into the routing table,
-        // and thus shared with other nodes, if the local node is the dialer,
-        // since the remote address on an inbound connection is specific to
-        // that connection (e.g. typically the TCP port numbers).
-        let address = match endpoint {
-            ConnectedPoint::Dialer { address } => Some(address.clone()),
-            ConnectedPoint::Listener { .. } => None,
+        // Peers that are not yet in the routing table are only considered
+        // once they are known to serve the Kademlia protocol, i.e. on
+        // `KademliaHandlerEvent::ProtocolConfirmed`, since they may only
+        // act as clients of the DHT.
+        let key = kbucket::Key::new(peer.clone());
+        let is_known = match self.kbuckets.entry(&key) {
+            kbucket::Entry::Absent(_) => false,
+            _ => true,
         };
 
-        self.connection_updated(peer.clone(), address, NodeStatus::Connected);
+        if is_known {
+            self.connection_updated(peer.clone(), remote_address(endpoint), NodeStatus::Connected);
+        }
     }
 
     fn inject_connected(&mut self, peer: &PeerId) {
@@ -1439,7 +2116,14 @@ where
         for (peer_id, event) in self.queries.iter_mut().filter_map(|q|
             q.inner.pending_rpcs.iter()
                 .position(|(p, _)| p == peer)
-                .map(|p| q.inner.pending_rpcs.remove(p)))
+                .map(|p| {
+                    if let QueryInfo::Crawl { requests, .. } = &mut q.inner.info {
+                        // The latency of a crawl request is measured from
+                        // the moment it is actually sent.
+                        requests.insert(peer.clone(), Instant::now());
+                    }
+                    q.inner.pending_rpcs.remove(p)
+                }))
         {
             self.queued_events.push_back(NetworkBehaviourAction::NotifyHandler {
                 peer_id, event, handler: NotifyHandler::Any
@@ -1547,12 +2231,28 @@ where
         for query in self.queries.iter_mut() {
             query.on_failure(peer_id);
         }
+
+        if self.unverified.contains(peer_id) {
+            // The snapshot entry of the peer is presumably stale.
+            debug!("Removing unverified peer {} from the routing table.", peer_id);
+            if let Some(entry) = self.remove_peer(peer_id) {
+                self.queued_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                    KademliaEvent::UnverifiedPeerRemoved {
+                        peer: entry.node.key.into_preimage(),
+                    }
+                ));
+            }
+        }
     }
 
     fn inject_disconnected(&mut self, id: &PeerId) {
         for query in self.queries.iter_mut() {
             query.on_failure(id);
         }
+        let key = kbucket::Key::new(id.clone());
+        if !matches!(self.kbuckets.entry(&key), kbucket::Entry::Absent(_) | kbucket::Entry::SelfEntry) {
+            self.last_seen.insert(id.clone(), SystemTime::now());
+        }
         self.connection_updated(id.clone(), None, NodeStatus::Disconnected);
         self.connected_peers.remove(id);
     }
@@ -1564,8 +2264,22 @@ where
         event: KademliaHandlerEvent<QueryId>
     ) {
         match event {
+            KademliaHandlerEvent::ProtocolConfirmed { endpoint } => {
+                debug_assert!(self.connected_peers.contains(&source));
+                self.connection_updated(source, remote_address(&endpoint), NodeStatus::Connected);
+            }
+
             KademliaHandlerEvent::FindNodeReq { key, request_id } => {
                 let closer_peers = self.find_closest(&kbucket::Key::new(key), &source);
+
+                self.queued_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                    KademliaEvent::InboundRequest {
+                        request: InboundRequest::FindNode {
+                            num_closer_peers: closer_peers.len(),
+                        }
+                    }
+                ));
+
                 self.queued_events.push_back(NetworkBehaviourAction::NotifyHandler {
                     peer_id: source,
                     handler: NotifyHandler::One(connection),
@@ -1580,12 +2294,66 @@ where
                 closer_peers,
                 user_data,
             } => {
+                let sent = self.queries.get_mut(&user_data).and_then(|query| {
+                    let is_waiting = query.is_waiting(&source);
+                    match &mut query.inner.info {
+                        QueryInfo::Crawl { requests, .. } if is_waiting => requests.remove(&source),
+                        _ => None
+                    }
+                });
+                if let Some(sent) = sent {
+                    let addresses = self.addresses_of_peer(&source);
+                    self.queued_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                        KademliaEvent::CrawlProgress {
+                            id: user_data,
+                            peer: source.clone(),
+                            addresses,
+                            closer_peers: closer_peers.clone(),
+                            latency: Instant::now() - sent,
+                        }
+                    ));
+                }
+                let local_id = self.kbuckets.local_key().preimage();
+                let progress = self.queries.get_mut(&user_data).and_then(|query| {
+                    if !query.is_waiting(&source) {
+                        return None
+                    }
+                    if let QueryInfo::GetClosestPeers { key } = &query.inner.info {
+                        // Report the peers that have not been reported earlier in the query,
+                        // once each even if the response lists them repeatedly.
+                        let reported = &query.inner.addresses;
+                        let mut seen = HashSet::new();
+                        let peers = closer_peers.iter()
+                            .map(|p| &p.node_id)
+                            .filter(|p| *p != local_id && !reported.contains_key(*p))
+                            .filter(|p| seen.insert(*p))
+                            .cloned()
+                            .collect::<Vec<_>>();
+                        if !peers.is_empty() {
+                            return Some(QueryProgress::GetClosestPeers { key: key.clone(), peers })
+                        }
+                    }
+                    None
+                });
+                if let Some(progress) = progress {
+                    self.query_progressed(user_data, progress);
+                }
                 self.discovered(&user_data, &source, closer_peers.iter());
             }
 
             KademliaHandlerEvent::GetProvidersReq { key, request_id } => {
                 let provider_peers = self.provider_peers(&key, &source);
                 let closer_peers = self.find_closest(&kbucket::Key::new(key), &source);
+
+                self.queued_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                    KademliaEvent::InboundRequest {
+                        request: InboundRequest::GetProvider {
+                            num_closer_peers: closer_peers.len(),
+                            num_provider_peers: provider_peers.len(),
+                        }
+                    }
+                ));
+
                 self.queued_events.push_back(NetworkBehaviourAction::NotifyHandler {
                     peer_id: source,
                     handler: NotifyHandler::One(connection),
@@ -1604,15 +2372,26 @@ where
             } => {
                 let peers = closer_peers.iter().chain(provider_peers.iter());
                 self.discovered(&user_data, &source, peers);
+                let mut progress = None;
                 if let Some(query) = self.queries.get_mut(&user_data) {
                     if let QueryInfo::GetProviders {
-                        providers, ..
+                        key, providers
                     } = &mut query.inner.info {
-                        for peer in provider_peers {
-                            providers.insert(peer.node_id);
+                        let new_providers = provider_peers.into_iter()
+                            .map(|p| p.node_id)
+                            .filter(|p| providers.insert(p.clone()))
+                            .collect::<Vec<_>>();
+                        if !new_providers.is_empty() {
+                            progress = Some(QueryProgress::GetProviders {
+                                key: key.clone(),
+                                providers: new_providers,
+                            });
                         }
                     }
                 }
+                if let Some(progress) = progress {
+                    self.query_progressed(user_data, progress);
+                }
             }
 
             KademliaHandlerEvent::QueryError { user_data, error } => {
@@ -1631,7 +2410,7 @@ where
                     return
                 }
 
-                self.provider_received(key, provider)
+                self.provider_received(key, provider, connection)
             }
 
             KademliaHandlerEvent::GetRecord { key, request_id } => {
@@ -1650,6 +2429,15 @@ where
 
                 let closer_peers = self.find_closest(&kbucket::Key::new(key), &source);
 
+                self.queued_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                    KademliaEvent::InboundRequest {
+                        request: InboundRequest::GetRecord {
+                            num_closer_peers: closer_peers.len(),
+                            present_locally: record.is_some(),
+                        }
+                    }
+                ));
+
                 self.queued_events.push_back(NetworkBehaviourAction::NotifyHandler {
                     peer_id: source,
                     handler: NotifyHandler::One(connection),
@@ -1666,15 +2454,36 @@ where
                 closer_peers,
                 user_data,
             } => {
+                let validator = &self.record_validator;
+                let mut progress = None;
                 if let Some(query) = self.queries.get_mut(&user_data) {
                     if let QueryInfo::GetRecord {
                         key, records, quorum, cache_at
                     } = &mut query.inner.info {
+                        // Invalid records are treated as if the peer did not
+                        // return a record at all.
+                        let record = record.filter(|r| match validator {
+                            Some(v) => match v.validate(r) {
+                                Ok(()) => true,
+                                Err(e) => {
+                                    info!("Invalid record from {}: {:?}: {}", source, r.key, e);
+                                    false
+                                }
+                            }
+                            None => true,
+                        });
                         if let Some(record) = record {
-                            records.push(PeerRecord{ peer: Some(source.clone()), record });
+                            let record = PeerRecord{ peer: Some(source.clone()), record };
+                            progress = Some(QueryProgress::GetRecord {
+                                key: key.clone(),
+                                records: vec![record.clone()],
+                            });
+                            records.push(record);
 
                             let quorum = quorum.get();
-                            if records.len() >= quorum {
+                            // With a validator, the query keeps collecting records
+                            // until it terminates, see [`Kademlia::get_record`].
+                            if records.len() >= quorum && validator.is_none() {
                                 // Desired quorum reached. The query may finish. See
                                 // [`Query::try_finish`] for details.
                                 let peers = records.iter()
@@ -1708,6 +2517,9 @@ where
                     }
                 }
 
+                if let Some(progress) = progress {
+                    self.query_progressed(user_data, progress);
+                }
                 self.discovered(&user_data, &source, closer_peers.iter());
             }
 
@@ -1748,7 +2560,7 @@ where
 
     fn poll(&mut self, cx: &mut Context<'_>, parameters: &mut impl PollParameters) -> Poll<
         NetworkBehaviourAction<
-            <KademliaHandler<QueryId> as ProtocolsHandler>::InEvent,
+            <<Self::ProtocolsHandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InEvent,
             Self::OutEvent,
         >,
     > {
@@ -1786,9 +2598,46 @@ where
                     break
                 }
             }
+            jobs_query_capacity -= num;
             self.put_record_job = Some(job);
         }
 
+        // Bootstrap as soon as the routing table is populated, if configured.
+        if self.bootstrap_on_start && self.bootstrap().is_ok() {
+            self.bootstrap_on_start = false;
+        }
+
+        // Run the periodic bucket refresh job.
+        if let Some(mut job) = self.bucket_refresh_job.take() {
+            let num = usize::min(JOBS_MAX_NEW_QUERIES, jobs_query_capacity);
+            for _ in 0 .. num {
+                if let Poll::Ready(target) = job.poll(cx, &mut self.kbuckets, now) {
+                    self.start_bucket_refresh(target)
+                } else {
+                    break
+                }
+            }
+            self.bucket_refresh_job = Some(job);
+        }
+
+        // Take a periodic snapshot of the routing table.
+        let snapshot_due = match &mut self.routing_table_snapshot {
+            Some((interval, delay)) => {
+                let due = delay.poll_unpin(cx).is_ready();
+                if due {
+                    delay.reset_at(now + *interval);
+                }
+                due
+            }
+            None => false,
+        };
+        if snapshot_due {
+            let snapshot = self.routing_table_snapshot();
+            self.queued_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                KademliaEvent::RoutingTableSnapshot { snapshot }
+            ));
+        }
+
         loop {
             // Drain queued events first.
             if let Some(event) = self.queued_events.pop_front() {
@@ -1797,6 +2646,10 @@ where
 
             // Drain applied pending entries from the routing table.
             if let Some(entry) = self.kbuckets.take_applied_pending() {
+                if let Some(evicted) = &entry.evicted {
+                    self.last_seen.remove(evicted.key.preimage());
+                    self.unverified.remove(evicted.key.preimage());
+                }
                 let kbucket::Node { key, value } = entry.inserted;
                 let event = KademliaEvent::RoutingUpdated {
                     peer: key.into_preimage(),
@@ -1809,13 +2662,31 @@ where
             // Look for a finished query.
             loop {
                 match self.queries.poll(now) {
-                    QueryPoolState::Finished(q) => {
+                    QueryPoolState::Finished(mut q) => {
+                        self.lookup_performed(&q.inner.info, now);
+                        let last_step = q.inner.last_step(q.id());
                         if let Some(event) = self.query_finished(q, parameters) {
+                            let event = self.query_completed(last_step, event);
                             return Poll::Ready(NetworkBehaviourAction::GenerateEvent(event))
                         }
                     }
-                    QueryPoolState::Timeout(q) => {
-                        if let Some(event) = self.query_timeout(q) {
+                    QueryPoolState::Timeout(mut q) => {
+                        self.lookup_performed(&q.inner.info, now);
+                        let last_step = q.inner.last_step(q.id());
+                        // A `GetRecord` query with a validator keeps running after
+                        // reaching its quorum, so it succeeds if it did so in time.
+                        let quorum_reached = match &q.inner.info {
+                            QueryInfo::GetRecord { records, quorum, .. } =>
+                                self.record_validator.is_some() && records.len() >= quorum.get(),
+                            _ => false,
+                        };
+                        let event = if quorum_reached {
+                            self.query_finished(q, parameters)
+                        } else {
+                            self.query_timeout(q)
+                        };
+                        if let Some(event) = event {
+                            let event = self.query_completed(last_step, event);
                             return Poll::Ready(NetworkBehaviourAction::GenerateEvent(event))
                         }
                     }
@@ -1832,6 +2703,9 @@ where
                         } = &query.inner.info {
                             query.on_success(&peer_id, vec![])
                         }
+                        if let QueryInfo::Crawl { requests, .. } = &mut query.inner.info {
+                            requests.insert(peer_id.clone(), now);
+                        }
                         if self.connected_peers.contains(&peer_id) {
                             self.queued_events.push_back(NetworkBehaviourAction::NotifyHandler {
                                 peer_id, event, handler: NotifyHandler::Any
@@ -1898,6 +2772,15 @@ pub struct PeerRecord {
 /// See [`NetworkBehaviour::poll`].
 #[derive(Debug)]
 pub enum KademliaEvent {
+    /// An inbound request has been received and handled.
+    //
+    // Note on the difference between 'request' and 'query': A request is a
+    // single request-response style exchange with a single remote peer. A query
+    // is made of multiple requests across multiple remote peers.
+    InboundRequest {
+        request: InboundRequest
+    },
+
     /// A query has produced a result.
     QueryResult {
         /// The ID of the query that finished.
@@ -1908,6 +2791,23 @@ pub enum KademliaEvent {
         stats: QueryStats
     },
 
+    /// A query has produced an intermediate result.
+    ///
+    /// Intermediate results are reported by [`Kademlia::get_providers`],
+    /// [`Kademlia::get_record`] and [`Kademlia::get_closest_peers`], without
+    /// affecting the termination of the query. If a query reported any
+    /// progress, a last step carrying no new results is reported once the
+    /// query finished or timed out, immediately before its
+    /// [`KademliaEvent::QueryResult`].
+    QueryProgressed {
+        /// The ID of the query.
+        id: QueryId,
+        /// The intermediate result of the query.
+        progress: QueryProgress,
+        /// The step of the query this result belongs to.
+        step: ProgressStep,
+    },
+
     /// The routing table has been updated with a new peer and / or
     /// address, thereby possibly evicting another peer.
     RoutingUpdated {
@@ -1958,9 +2858,80 @@ pub enum KademliaEvent {
     PendingRoutablePeer {
         peer: PeerId,
         address: Multiaddr,
+    },
+
+    /// A peer imported via [`Kademlia::import_routing_table`] has been
+    /// removed from the routing table, since dialing it failed before the
+    /// local node was ever connected to it.
+    UnverifiedPeerRemoved {
+        peer: PeerId,
+    },
+
+    /// A peer responded to a `FIND_NODE` request of a crawl.
+    ///
+    /// See [`Kademlia::crawl`].
+    CrawlProgress {
+        /// The ID of the crawl.
+        id: QueryId,
+        /// The peer that responded.
+        peer: PeerId,
+        /// The known addresses of `peer`.
+        addresses: Vec<Multiaddr>,
+        /// The peers reported by `peer`, with their addresses.
+        closer_peers: Vec<KadPeer>,
+        /// The time between sending the request and receiving the response.
+        latency: Duration,
+    },
+
+    /// A periodic snapshot of the routing table has been taken.
+    ///
+    /// See [`KademliaConfig::set_routing_table_snapshot_interval`].
+    RoutingTableSnapshot {
+        snapshot: RoutingTableSnapshot,
     }
 }
 
+/// Information about a received and handled inbound request.
+#[derive(Debug)]
+pub enum InboundRequest {
+    /// Request for the list of nodes whose IDs are the closest to `key`.
+    FindNode {
+        num_closer_peers: usize,
+    },
+    /// Same as `FindNode`, but should also return the entries of the local
+    /// providers list for this key.
+    GetProvider {
+        num_closer_peers: usize,
+        num_provider_peers: usize,
+    },
+    /// A peer sent a provider record.
+    ///
+    /// If filtering [`KademliaStoreInserts::FilterBoth`] is enabled, the [`ProviderRecord`] is
+    /// included.
+    ///
+    /// See [`KademliaStoreInserts`] and [`KademliaConfig::set_record_filtering`] for details.
+    AddProvider {
+        source: PeerId,
+        connection: ConnectionId,
+        record: Option<ProviderRecord>,
+    },
+    /// Request to retrieve a record.
+    GetRecord {
+        num_closer_peers: usize,
+        present_locally: bool,
+    },
+    /// A peer sent a record.
+    ///
+    /// If filtering [`KademliaStoreInserts::FilterBoth`] is enabled, the [`Record`] is included.
+    ///
+    /// See [`KademliaStoreInserts`] and [`KademliaConfig::set_record_filtering`] for details.
+    PutRecord {
+        source: PeerId,
+        connection: ConnectionId,
+        record: Option<Record>,
+    },
+}
+
 /// The results of Kademlia queries.
 #[derive(Debug)]
 pub enum QueryResult {
@@ -1987,12 +2958,49 @@ pub enum QueryResult {
 
     /// The result of a (automatic) republishing of a (value-)record.
     RepublishRecord(PutRecordResult),
+
+    /// The result of [`Kademlia::crawl`].
+    Crawl(CrawlResult),
+}
+
+/// An intermediate result of a query, see [`KademliaEvent::QueryProgressed`].
+#[derive(Debug, Clone)]
+pub enum QueryProgress {
+    /// Providers found by [`Kademlia::get_providers`] that have
+    /// not been reported in an earlier step.
+    GetProviders {
+        key: record::Key,
+        providers: Vec<PeerId>,
+    },
+    /// Records found by [`Kademlia::get_record`].
+    GetRecord {
+        key: record::Key,
+        records: Vec<PeerRecord>,
+    },
+    /// Peers discovered by [`Kademlia::get_closest_peers`] that have
+    /// not been reported in an earlier step.
+    GetClosestPeers {
+        key: Vec<u8>,
+        peers: Vec<PeerId>,
+    },
+}
+
+/// A step in the progress of a query.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ProgressStep {
+    /// The number of the step, starting at 1.
+    pub count: usize,
+    /// Whether this is the last step of the query.
+    pub last: bool,
 }
 
 /// The result of [`Kademlia::get_record`].
 pub type GetRecordResult = Result<GetRecordOk, GetRecordError>;
 
 /// The successful result of [`Kademlia::get_record`].
+///
+/// If a [`RecordValidator`] is configured, the first record is the
+/// one selected by [`RecordValidator::select`].
 #[derive(Debug, Clone)]
 pub struct GetRecordOk {
     pub records: Vec<PeerRecord>
@@ -2102,6 +3110,26 @@ pub enum BootstrapError {
     }
 }
 
+/// The result of [`Kademlia::crawl`].
+pub type CrawlResult = Result<CrawlOk, CrawlError>;
+
+/// The successful result of [`Kademlia::crawl`].
+#[derive(Debug, Clone)]
+pub struct CrawlOk {
+    /// The peers that responded during the crawl.
+    pub peers: Vec<PeerId>,
+}
+
+/// The error result of [`Kademlia::crawl`].
+#[derive(Debug, Clone)]
+pub enum CrawlError {
+    /// The walk of at least one bucket timed out.
+    Timeout {
+        /// The peers that responded during the crawl.
+        peers: Vec<PeerId>,
+    }
+}
+
 /// The result of [`Kademlia::get_closest_peers`].
 pub type GetClosestPeersResult = Result<GetClosestPeersOk, GetClosestPeersError>;
 
@@ -2235,7 +3263,9 @@ struct QueryInner {
     ///
     /// A request is pending if the targeted peer is not currently connected
     /// and these requests are sent as soon as a connection to the peer is established.
-    pending_rpcs: SmallVec<[(PeerId, KademliaHandlerIn<QueryId>); K_VALUE.get()]>
+    pending_rpcs: SmallVec<[(PeerId, KademliaHandlerIn<QueryId>); K_VALUE.get()]>,
+    /// The number of progress steps reported so far.
+    num_steps: usize,
 }
 
 impl QueryInner {
@@ -2243,9 +3273,35 @@ impl QueryInner {
         QueryInner {
             info,
             addresses: Default::default(),
-            pending_rpcs: SmallVec::default()
+            pending_rpcs: SmallVec::default(),
+            num_steps: 0,
         }
     }
+
+    /// Advances the progress of the query to the next step.
+    fn next_step(&mut self, last: bool) -> ProgressStep {
+        self.num_steps += 1;
+        ProgressStep { count: self.num_steps, last }
+    }
+
+    /// Creates the event for the last progress step of a query that
+    /// finished or timed out, if the query reported any progress before.
+    fn last_step(&mut self, id: QueryId) -> Option<KademliaEvent> {
+        if self.num_steps == 0 {
+            return None
+        }
+        let progress = match &self.info {
+            QueryInfo::GetProviders { key, .. } =>
+                QueryProgress::GetProviders { key: key.clone(), providers: Vec::new() },
+            QueryInfo::GetRecord { key, .. } =>
+                QueryProgress::GetRecord { key: key.clone(), records: Vec::new() },
+            QueryInfo::GetClosestPeers { key } =>
+                QueryProgress::GetClosestPeers { key: key.clone(), peers: Vec::new() },
+            _ => return None
+        };
+        let step = self.next_step(true);
+        Some(KademliaEvent::QueryProgressed { id, progress, step })
+    }
 }
 
 /// The context of a [`QueryInfo::AddProvider`] query.
@@ -2262,6 +3318,9 @@ pub enum PutRecordContext {
     Republish,
     Replicate,
     Cache,
+    /// The record is re-published to nodes that returned a different
+    /// value than the one selected by the [`RecordValidator`].
+    Correct,
 }
 
 /// Information about a running query.
@@ -2283,6 +3342,20 @@ pub enum QueryInfo {
     /// A query initiated by [`Kademlia::get_closest_peers`].
     GetClosestPeers { key: Vec<u8> },
 
+    /// A query initiated by [`Kademlia::crawl`].
+    Crawl {
+        /// The random peer ID targeted by the walk of the current bucket.
+        peer: PeerId,
+        /// The random peer IDs of the buckets that remain to be walked.
+        remaining: vec::IntoIter<kbucket::Key<PeerId>>,
+        /// The peers that responded in the walks of earlier buckets.
+        peers: HashSet<PeerId>,
+        /// The instants at which the pending requests have been sent.
+        requests: HashMap<PeerId, Instant>,
+        /// Whether the walk of any bucket timed out.
+        timed_out: bool,
+    },
+
     /// A query initiated by [`Kademlia::get_providers`].
     GetProviders {
         /// The key for which to search for providers.
@@ -2330,6 +3403,19 @@ pub enum QueryInfo {
 }
 
 impl QueryInfo {
+    /// The key in the DHT keyspace targeted by the query.
+    fn target(&self) -> kbucket::KeyBytes {
+        match self {
+            QueryInfo::Bootstrap { peer, .. } => kbucket::KeyBytes::new(peer.as_bytes()),
+            QueryInfo::GetClosestPeers { key } => kbucket::KeyBytes::new(&key[..]),
+            QueryInfo::Crawl { peer, .. } => kbucket::KeyBytes::new(peer.as_bytes()),
+            QueryInfo::GetProviders { key, .. } => kbucket::KeyBytes::new(key.as_ref()),
+            QueryInfo::AddProvider { key, .. } => kbucket::KeyBytes::new(key.as_ref()),
+            QueryInfo::PutRecord { record, .. } => kbucket::KeyBytes::new(record.key.as_ref()),
+            QueryInfo::GetRecord { key, .. } => kbucket::KeyBytes::new(key.as_ref()),
+        }
+    }
+
     /// Creates an event for a handler to issue an outgoing request in the
     /// context of a query.
     fn to_request(&self, query_id: QueryId) -> KademliaHandlerIn<QueryId> {
@@ -2342,6 +3428,10 @@ impl QueryInfo {
                 key: key.clone(),
                 user_data: query_id,
             },
+            QueryInfo::Crawl { peer, .. } => KademliaHandlerIn::FindNodeReq {
+                key: peer.clone().into_bytes(),
+                user_data: query_id,
+            },
             QueryInfo::GetProviders { key, .. } => KademliaHandlerIn::GetProvidersReq {
                 key: key.clone(),
                 user_data: query_id,
@@ -2496,4 +3586,12 @@ pub enum RoutingUpdate {
     /// peer ID is deemed invalid (e.g. refers to the local
     /// peer ID).
     Failed,
+    /// The peer or address has not been added to the routing
+    /// table, since it would exceed the diversity limit of the
+    /// given group, or the peer is in no group at all, i.e.
+    /// [`DiversityGroup::Unknown`].
+    /// See [`KademliaConfig::set_diversity_limits`].
+    Rejected {
+        group: DiversityGroup,
+    },
 }
@@ -24,7 +24,7 @@ use super::*;
 
 use crate::K_VALUE;
 use crate::kbucket::Distance;
-use crate::record::{Key, store::MemoryStore};
+use crate::record::{Key, store::{DiskStore, MemoryStore}};
 use futures::{
     prelude::*,
     executor::block_on,
@@ -46,16 +46,104 @@ use libp2p_swarm::Swarm;
 use libp2p_yamux as yamux;
 use quickcheck::*;
 use rand::{Rng, random, thread_rng, rngs::StdRng, SeedableRng};
-use std::{collections::{HashSet, HashMap}, time::Duration, io, num::NonZeroUsize, u64};
+use std::{borrow::Cow, collections::{HashSet, HashMap}, time::Duration, io, num::NonZeroUsize, u64};
 use multihash::{wrap, Code, Multihash};
 
-type TestSwarm = Swarm<Kademlia<MemoryStore>>;
+type TestSwarm<TStore = MemoryStore> = Swarm<Kademlia<TStore>>;
 
-fn build_node() -> (Multiaddr, TestSwarm) {
+/// A record store the behaviour tests can be run with.
+trait TestStore: for<'a> RecordStore<'a> + Send + 'static {
+    /// Creates an empty store for the given local peer.
+    fn build(local_id: PeerId) -> Self;
+}
+
+impl TestStore for MemoryStore {
+    fn build(local_id: PeerId) -> Self {
+        MemoryStore::new(local_id)
+    }
+}
+
+/// A `DiskStore` in a temporary directory, which is removed along with the store.
+struct TempDiskStore {
+    store: DiskStore,
+    _dir: tempfile::TempDir,
+}
+
+impl TestStore for TempDiskStore {
+    fn build(local_id: PeerId) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DiskStore::open(local_id, dir.path()).unwrap();
+        TempDiskStore { store, _dir: dir }
+    }
+}
+
+impl<'a> RecordStore<'a> for TempDiskStore {
+    type RecordsIter = <DiskStore as RecordStore<'a>>::RecordsIter;
+    type ProvidedIter = <DiskStore as RecordStore<'a>>::ProvidedIter;
+
+    fn get(&'a self, k: &Key) -> Option<Cow<'_, Record>> {
+        self.store.get(k)
+    }
+
+    fn put(&'a mut self, r: Record) -> store::Result<()> {
+        self.store.put(r)
+    }
+
+    fn remove(&'a mut self, k: &Key) {
+        self.store.remove(k)
+    }
+
+    fn records(&'a self) -> Self::RecordsIter {
+        self.store.records()
+    }
+
+    fn add_provider(&'a mut self, record: ProviderRecord) -> store::Result<()> {
+        self.store.add_provider(record)
+    }
+
+    fn providers(&'a self, key: &Key) -> Vec<ProviderRecord> {
+        self.store.providers(key)
+    }
+
+    fn provided(&'a self) -> Self::ProvidedIter {
+        self.store.provided()
+    }
+
+    fn remove_provider(&'a mut self, k: &Key, p: &PeerId) {
+        self.store.remove_provider(k, p)
+    }
+}
+
+/// Runs the given (generic) tests with each of the record stores.
+macro_rules! store_tests {
+    ($($test:ident),*) => {
+        mod memory_store {
+            $(
+                #[test]
+                fn $test() {
+                    super::$test::<super::MemoryStore>()
+                }
+            )*
+        }
+
+        mod disk_store {
+            $(
+                #[test]
+                fn $test() {
+                    super::$test::<super::TempDiskStore>()
+                }
+            )*
+        }
+    }
+}
+
+store_tests!(put_record, get_record, get_record_many, add_provider);
+
+fn build_node<TStore: TestStore>() -> (Multiaddr, TestSwarm<TStore>) {
     build_node_with_config(Default::default())
 }
 
-fn build_node_with_config(cfg: KademliaConfig) -> (Multiaddr, TestSwarm) {
+fn build_node_with_config<TStore: TestStore>(cfg: KademliaConfig) -> (Multiaddr, TestSwarm<TStore>) {
     let local_key = identity::Keypair::generate_ed25519();
     let local_public_key = local_key.public();
     let transport = MemoryTransport::default()
@@ -67,7 +155,7 @@ fn build_node_with_config(cfg: KademliaConfig) -> (Multiaddr, TestSwarm) {
         .boxed();
 
     let local_id = local_public_key.clone().into_peer_id();
-    let store = MemoryStore::new(local_id.clone());
+    let store = TStore::build(local_id.clone());
     let behaviour = Kademlia::with_config(local_id.clone(), store, cfg.clone());
 
     let mut swarm = Swarm::new(transport, behaviour, local_id);
@@ -79,21 +167,25 @@ fn build_node_with_config(cfg: KademliaConfig) -> (Multiaddr, TestSwarm) {
 }
 
 /// Builds swarms, each listening on a port. Does *not* connect the nodes together.
-fn build_nodes(num: usize) -> Vec<(Multiaddr, TestSwarm)> {
+fn build_nodes<TStore: TestStore>(num: usize) -> Vec<(Multiaddr, TestSwarm<TStore>)> {
     build_nodes_with_config(num, Default::default())
 }
 
 /// Builds swarms, each listening on a port. Does *not* connect the nodes together.
-fn build_nodes_with_config(num: usize, cfg: KademliaConfig) -> Vec<(Multiaddr, TestSwarm)> {
+fn build_nodes_with_config<TStore: TestStore>(num: usize, cfg: KademliaConfig)
+    -> Vec<(Multiaddr, TestSwarm<TStore>)>
+{
     (0..num).map(|_| build_node_with_config(cfg.clone())).collect()
 }
 
-fn build_connected_nodes(total: usize, step: usize) -> Vec<(Multiaddr, TestSwarm)> {
+fn build_connected_nodes<TStore: TestStore>(total: usize, step: usize)
+    -> Vec<(Multiaddr, TestSwarm<TStore>)>
+{
     build_connected_nodes_with_config(total, step, Default::default())
 }
 
-fn build_connected_nodes_with_config(total: usize, step: usize, cfg: KademliaConfig)
-    -> Vec<(Multiaddr, TestSwarm)>
+fn build_connected_nodes_with_config<TStore: TestStore>(total: usize, step: usize, cfg: KademliaConfig)
+    -> Vec<(Multiaddr, TestSwarm<TStore>)>
 {
     let mut swarms = build_nodes_with_config(total, cfg);
     let swarm_ids: Vec<_> = swarms.iter()
@@ -113,8 +205,8 @@ fn build_connected_nodes_with_config(total: usize, step: usize, cfg: KademliaCon
     swarms
 }
 
-fn build_fully_connected_nodes_with_config(total: usize, cfg: KademliaConfig)
-    -> Vec<(Multiaddr, TestSwarm)>
+fn build_fully_connected_nodes_with_config<TStore: TestStore>(total: usize, cfg: KademliaConfig)
+    -> Vec<(Multiaddr, TestSwarm<TStore>)>
 {
     let mut swarms = build_nodes_with_config(total, cfg);
     let swarm_addr_and_peer_id: Vec<_> = swarms.iter()
@@ -162,7 +254,7 @@ fn bootstrap() {
             cfg.disjoint_query_paths(true);
         }
 
-        let mut swarms = build_connected_nodes_with_config(
+        let mut swarms = build_connected_nodes_with_config::<MemoryStore>(
             num_total,
             num_group,
             cfg,
@@ -233,7 +325,7 @@ fn query_iter() {
 
     fn run(rng: &mut impl Rng) {
         let num_total = rng.gen_range(2, 20);
-        let mut swarms = build_connected_nodes(num_total, 1).into_iter()
+        let mut swarms = build_connected_nodes::<MemoryStore>(num_total, 1).into_iter()
             .map(|(_a, s)| s)
             .collect::<Vec<_>>();
         let swarm_ids: Vec<_> = swarms.iter().map(Swarm::local_peer_id).cloned().collect();
@@ -301,7 +393,7 @@ fn unresponsive_not_returned_direct() {
     // Build one node. It contains fake addresses to non-existing nodes. We ask it to find a
     // random peer. We make sure that no fake address is returned.
 
-    let mut swarms = build_nodes(1).into_iter()
+    let mut swarms = build_nodes::<MemoryStore>(1).into_iter()
         .map(|(_a, s)| s)
         .collect::<Vec<_>>();
 
@@ -345,7 +437,7 @@ fn unresponsive_not_returned_indirect() {
     // non-existing nodes. We ask node #2 to find a random peer. We make sure that no fake address
     // is returned.
 
-    let mut swarms = build_nodes(2);
+    let mut swarms = build_nodes::<MemoryStore>(2);
 
     // Add fake addresses to first.
     for _ in 0 .. 10 {
@@ -392,7 +484,7 @@ fn unresponsive_not_returned_indirect() {
 
 #[test]
 fn get_record_not_found() {
-    let mut swarms = build_nodes(3);
+    let mut swarms = build_nodes::<MemoryStore>(3);
 
     let swarm_ids: Vec<_> = swarms.iter()
         .map(|(_addr, swarm)| Swarm::local_peer_id(swarm))
@@ -444,9 +536,8 @@ fn get_record_not_found() {
 /// A node joining a fully connected network via three (ALPHA_VALUE) bootnodes
 /// should be able to put a record to the X closest nodes of the network where X
 /// is equal to the configured replication factor.
-#[test]
-fn put_record() {
-    fn prop(records: Vec<Record>, seed: Seed) {
+fn put_record<TStore: TestStore>() {
+    fn prop<TStore: TestStore>(records: Vec<Record>, seed: Seed) {
         let mut rng = StdRng::from_seed(seed.0);
         let replication_factor = NonZeroUsize::new(rng.gen_range(1, (K_VALUE.get() / 2) + 1)).unwrap();
         // At least 4 nodes, 1 under test + 3 bootnodes.
@@ -459,7 +550,7 @@ fn put_record() {
         }
 
         let mut swarms = {
-            let mut fully_connected_swarms = build_fully_connected_nodes_with_config(
+            let mut fully_connected_swarms = build_fully_connected_nodes_with_config::<TStore>(
                 num_total - 1,
                 config.clone(),
             );
@@ -626,12 +717,11 @@ fn put_record() {
         )
     }
 
-    QuickCheck::new().tests(3).quickcheck(prop as fn(_,_) -> _)
+    QuickCheck::new().tests(3).quickcheck(prop::<TStore> as fn(_,_) -> _)
 }
 
-#[test]
-fn get_record() {
-    let mut swarms = build_nodes(3);
+fn get_record<TStore: TestStore>() {
+    let mut swarms = build_nodes::<TStore>(3);
 
     // Let first peer know of second peer and second peer know of third peer.
     for i in 0..2 {
@@ -675,11 +765,10 @@ fn get_record() {
     )
 }
 
-#[test]
-fn get_record_many() {
+fn get_record_many<TStore: TestStore>() {
     // TODO: Randomise
     let num_nodes = 12;
-    let mut swarms = build_connected_nodes(num_nodes, 3).into_iter()
+    let mut swarms = build_connected_nodes::<TStore>(num_nodes, 3).into_iter()
         .map(|(_addr, swarm)| swarm)
         .collect::<Vec<_>>();
     let num_results = 10;
@@ -723,9 +812,8 @@ fn get_record_many() {
 /// A node joining a fully connected network via three (ALPHA_VALUE) bootnodes
 /// should be able to add itself as a provider to the X closest nodes of the
 /// network where X is equal to the configured replication factor.
-#[test]
-fn add_provider() {
-    fn prop(keys: Vec<record::Key>, seed: Seed) {
+fn add_provider<TStore: TestStore>() {
+    fn prop<TStore: TestStore>(keys: Vec<record::Key>, seed: Seed) {
         let mut rng = StdRng::from_seed(seed.0);
         let replication_factor = NonZeroUsize::new(rng.gen_range(1, (K_VALUE.get() / 2) + 1)).unwrap();
         // At least 4 nodes, 1 under test + 3 bootnodes.
@@ -738,7 +826,7 @@ fn add_provider() {
         }
 
         let mut swarms = {
-            let mut fully_connected_swarms = build_fully_connected_nodes_with_config(
+            let mut fully_connected_swarms = build_fully_connected_nodes_with_config::<TStore>(
                 num_total - 1,
                 config.clone(),
             );
@@ -876,7 +964,7 @@ fn add_provider() {
         )
     }
 
-    QuickCheck::new().tests(3).quickcheck(prop as fn(_,_))
+    QuickCheck::new().tests(3).quickcheck(prop::<TStore> as fn(_,_))
 }
 
 /// User code should be able to start queries beyond the internal
@@ -884,7 +972,7 @@ fn add_provider() {
 /// arithmetic overflow, see https://github.com/libp2p/rust-libp2p/issues/1290.
 #[test]
 fn exceed_jobs_max_queries() {
-    let (_addr, mut swarm) = build_node();
+    let (_addr, mut swarm) = build_node::<MemoryStore>();
     let num = JOBS_MAX_QUERIES + 1;
     for _ in 0 .. num {
         swarm.get_closest_peers(PeerId::random());
@@ -932,9 +1020,9 @@ fn disjoint_query_does_not_finish_before_all_paths_did() {
     // I.e. setting the amount disjoint paths to be explored to 2.
     config.set_parallelism(NonZeroUsize::new(2).unwrap());
 
-    let mut alice = build_node_with_config(config);
-    let mut trudy = build_node(); // Trudy the intrudor, an adversary.
-    let mut bob = build_node();
+    let mut alice = build_node_with_config::<MemoryStore>(config);
+    let mut trudy = build_node::<MemoryStore>(); // Trudy the intrudor, an adversary.
+    let mut bob = build_node::<MemoryStore>();
 
     let key = Key::new(&multihash::Sha2_256::digest(&thread_rng().gen::<[u8; 32]>()));
     let record_bob = Record::new(key.clone(), b"bob".to_vec());
@@ -1064,7 +1152,7 @@ fn manual_bucket_inserts() {
     let mut cfg = KademliaConfig::default();
     cfg.set_kbucket_inserts(KademliaBucketInserts::Manual);
     // 1 -> 2 -> [3 -> ...]
-    let mut swarms = build_connected_nodes_with_config(3, 1, cfg);
+    let mut swarms = build_connected_nodes_with_config::<MemoryStore>(3, 1, cfg);
     // The peers and their addresses for which we expect `RoutablePeer` events.
     let mut expected = swarms.iter().skip(2)
         .map(|(a, s)| (a.clone(), Swarm::local_peer_id(s).clone()))
@@ -1120,6 +1208,14 @@ fn network_behaviour_inject_address_change() {
         &connection_id,
         &ConnectedPoint::Dialer { address:  old_address.clone() },
     );
+    kademlia.inject_connected(&remote_peer_id);
+    kademlia.inject_event(
+        remote_peer_id.clone(),
+        connection_id,
+        KademliaHandlerEvent::ProtocolConfirmed {
+            endpoint: ConnectedPoint::Dialer { address: old_address.clone() },
+        },
+    );
 
     assert_eq!(
         vec![old_address.clone()],
@@ -1138,3 +1234,683 @@ fn network_behaviour_inject_address_change() {
         kademlia.addresses_of_peer(&remote_peer_id),
     );
 }
+
+#[test]
+fn client_mode() {
+    let mut cfg = KademliaConfig::default();
+    cfg.set_mode(KademliaMode::Client);
+    let (alice_addr, alice) = build_node_with_config::<MemoryStore>(cfg);
+    let alice_id = Swarm::local_peer_id(&alice).clone();
+    assert_eq!(alice.mode(), KademliaMode::Client);
+
+    let (_, mut bob) = build_node::<MemoryStore>();
+    let (carol_addr, mut carol) = build_node::<MemoryStore>();
+    let carol_id = Swarm::local_peer_id(&carol).clone();
+
+    // Bob learns about Alice via Carol and dials her during the query.
+    carol.add_address(&alice_id, alice_addr);
+    bob.add_address(&carol_id, carol_addr);
+
+    fn is_in_routing_table(swarm: &mut TestSwarm, peer: &PeerId) -> bool {
+        swarm.kbucket(peer.clone())
+            .map_or(false, |b| b.iter().any(|e| e.node.key.preimage() == peer))
+    }
+
+    let mut swarms = vec![alice, bob, carol];
+    for alice_mode in &[KademliaMode::Client, KademliaMode::Server] {
+        swarms[0].set_mode(*alice_mode);
+        swarms[1].get_closest_peers(alice_id.clone());
+
+        block_on(poll_fn(|ctx| {
+            for (i, swarm) in swarms.iter_mut().enumerate() {
+                loop {
+                    match swarm.poll_next_unpin(ctx) {
+                        Poll::Ready(Some(KademliaEvent::QueryResult {
+                            result: QueryResult::GetClosestPeers(Ok(_)), ..
+                        })) if i == 1 => return Poll::Ready(()),
+                        Poll::Ready(Some(_)) => {}
+                        e @ Poll::Ready(_) => panic!("Unexpected return value: {:?}", e),
+                        Poll::Pending => break,
+                    }
+                }
+            }
+            Poll::Pending
+        }));
+
+        // Bob only inserts Alice into its routing table once she
+        // acts as a server, i.e. accepts Kademlia substreams.
+        let inserted = is_in_routing_table(&mut swarms[1], &alice_id);
+        assert_eq!(inserted, *alice_mode == KademliaMode::Server);
+    }
+}
+
+/// Rejects records with empty values and selects the record with the
+/// highest value.
+#[derive(Debug)]
+struct HighestValueValidator;
+
+impl RecordValidator for HighestValueValidator {
+    fn validate(&self, record: &Record) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+        if record.value.is_empty() {
+            Err("empty value".into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn select(&self, _: &record::Key, records: &[&Record]) -> usize {
+        records.iter().enumerate().max_by_key(|(_, r)| &r.value).unwrap().0
+    }
+}
+
+#[test]
+fn put_record_rejected_by_validator() {
+    let mut cfg = KademliaConfig::default();
+    cfg.set_record_validator(HighestValueValidator);
+    let (alice_addr, alice) = build_node_with_config::<MemoryStore>(cfg);
+    let alice_id = Swarm::local_peer_id(&alice).clone();
+    let (_, mut bob) = build_node::<MemoryStore>();
+    bob.add_address(&alice_id, alice_addr);
+
+    let mut swarms = vec![alice, bob];
+    let key = record::Key::from(random_multihash());
+    for value in vec![vec![], vec![1]] {
+        let record = Record::new(key.clone(), value.clone());
+        let qid = swarms[1].put_record(record, Quorum::One).unwrap();
+
+        let result = block_on(poll_fn(|ctx| {
+            for swarm in swarms.iter_mut() {
+                loop {
+                    match swarm.poll_next_unpin(ctx) {
+                        Poll::Ready(Some(KademliaEvent::QueryResult {
+                            id, result: QueryResult::PutRecord(result), ..
+                        })) if id == qid => return Poll::Ready(result),
+                        Poll::Ready(Some(_)) => {}
+                        e @ Poll::Ready(_) => panic!("Unexpected return value: {:?}", e),
+                        Poll::Pending => break,
+                    }
+                }
+            }
+            Poll::Pending
+        }));
+
+        let stored = swarms[0].store.get(&key).map(|r| r.value.clone());
+        if value.is_empty() {
+            assert!(result.is_err());
+            assert_eq!(stored, None);
+        } else {
+            assert!(result.is_ok());
+            assert_eq!(stored, Some(value));
+        }
+    }
+}
+
+#[test]
+fn get_record_selects_and_corrects() {
+    // With a validator, the query collects all records regardless of its quorum.
+    for quorum in vec![Quorum::One, Quorum::N(NonZeroUsize::new(2).unwrap())] {
+        get_record_selects_and_corrects_with_quorum(quorum);
+    }
+}
+
+fn get_record_selects_and_corrects_with_quorum(quorum: Quorum) {
+    let mut cfg = KademliaConfig::default();
+    cfg.set_record_validator(HighestValueValidator);
+    let (_, mut alice) = build_node_with_config::<MemoryStore>(cfg);
+
+    // Bob holds a stale record, Carol the best one and Dave an invalid one.
+    let key = record::Key::from(random_multihash());
+    let mut swarms = vec![];
+    for value in vec![vec![1], vec![2], vec![]] {
+        let (addr, mut swarm) = build_node::<MemoryStore>();
+        swarm.store.put(Record::new(key.clone(), value)).unwrap();
+        alice.add_address(Swarm::local_peer_id(&swarm), addr);
+        swarms.push(swarm);
+    }
+    let carol_id = Swarm::local_peer_id(&swarms[1]).clone();
+    swarms.insert(0, alice);
+
+    let qid = swarms[0].get_record(&key, quorum);
+
+    block_on(poll_fn(|ctx| {
+        for swarm in swarms.iter_mut() {
+            loop {
+                match swarm.poll_next_unpin(ctx) {
+                    Poll::Ready(Some(KademliaEvent::QueryResult {
+                        id, result: QueryResult::GetRecord(Ok(GetRecordOk { records })), ..
+                    })) if id == qid => {
+                        assert_eq!(records.len(), 2);
+                        assert_eq!(records[0].peer, Some(carol_id.clone()));
+                        assert_eq!(records[0].record.value, vec![2]);
+                        assert_eq!(records[1].record.value, vec![1]);
+                        return Poll::Ready(());
+                    }
+                    Poll::Ready(Some(_)) => {}
+                    e @ Poll::Ready(_) => panic!("Unexpected return value: {:?}", e),
+                    Poll::Pending => break,
+                }
+            }
+        }
+        Poll::Pending
+    }));
+
+    // Bob's stale record is eventually corrected.
+    block_on(poll_fn(|ctx| {
+        for swarm in swarms.iter_mut() {
+            loop {
+                match swarm.poll_next_unpin(ctx) {
+                    Poll::Ready(Some(_)) => {}
+                    e @ Poll::Ready(_) => panic!("Unexpected return value: {:?}", e),
+                    Poll::Pending => break,
+                }
+            }
+        }
+        if swarms[1].store.get(&key).unwrap().value == vec![2] {
+            return Poll::Ready(())
+        }
+        Poll::Pending
+    }));
+}
+
+#[test]
+fn record_filtering() {
+    let mut cfg = KademliaConfig::default();
+    cfg.set_record_filtering(KademliaStoreInserts::FilterBoth);
+    let (alice_addr, alice) = build_node_with_config::<MemoryStore>(cfg);
+    let alice_id = Swarm::local_peer_id(&alice).clone();
+    let (_, mut bob) = build_node::<MemoryStore>();
+    bob.add_address(&alice_id, alice_addr);
+
+    let mut swarms = [alice, bob];
+    let record = Record::new(random_multihash(), vec![4, 5, 6]);
+    let key = record.key.clone();
+
+    // Polls all swarms until Alice handled an inbound request.
+    fn next_inbound_request(swarms: &mut [TestSwarm]) -> InboundRequest {
+        block_on(poll_fn(|ctx| {
+            for (i, swarm) in swarms.iter_mut().enumerate() {
+                loop {
+                    match swarm.poll_next_unpin(ctx) {
+                        Poll::Ready(Some(KademliaEvent::InboundRequest { request }))
+                            if i == 0 => return Poll::Ready(request),
+                        Poll::Ready(Some(_)) => {}
+                        e @ Poll::Ready(_) => panic!("Unexpected return value: {:?}", e),
+                        Poll::Pending => break,
+                    }
+                }
+            }
+            Poll::Pending
+        }))
+    }
+
+    swarms[1].put_record(record.clone(), Quorum::One).unwrap();
+    loop {
+        match next_inbound_request(&mut swarms) {
+            InboundRequest::PutRecord { source, record: Some(r), .. } => {
+                assert_eq!(&source, Swarm::local_peer_id(&swarms[1]));
+                assert_eq!(r.key, key);
+                assert_eq!(r.value, record.value);
+                break
+            }
+            InboundRequest::FindNode { .. } => {}
+            r => panic!("Unexpected request: {:?}", r),
+        }
+    }
+    assert!(swarms[0].store.get(&key).is_none());
+
+    swarms[1].start_providing(key.clone()).unwrap();
+    loop {
+        match next_inbound_request(&mut swarms) {
+            InboundRequest::AddProvider { source, record: Some(r), .. } => {
+                assert_eq!(&source, Swarm::local_peer_id(&swarms[1]));
+                assert_eq!(r.key, key);
+                assert_eq!(&r.provider, Swarm::local_peer_id(&swarms[1]));
+                break
+            }
+            InboundRequest::FindNode { .. } => {}
+            r => panic!("Unexpected request: {:?}", r),
+        }
+    }
+    assert!(swarms[0].store.providers(&key).is_empty());
+
+    swarms[1].store.remove(&key);
+    swarms[1].get_record(&key, Quorum::One);
+    match next_inbound_request(&mut swarms) {
+        InboundRequest::GetRecord { present_locally, .. } => assert!(!present_locally),
+        r => panic!("Unexpected request: {:?}", r),
+    }
+}
+
+#[test]
+fn import_routing_table_validates_entries() {
+    let local_id = PeerId::random();
+    let mut kademlia = Kademlia::new(local_id.clone(), MemoryStore::new(local_id.clone()));
+    let known = PeerId::random();
+    kademlia.add_address(&known, Protocol::Memory(1).into());
+
+    let entry = |peer: &PeerId, addresses: Vec<Multiaddr>| RoutingTableEntry {
+        peer: peer.clone(),
+        addresses,
+        status: NodeStatus::Connected,
+        last_seen: None,
+    };
+    let new = PeerId::random();
+    let snapshot = RoutingTableSnapshot {
+        entries: vec![
+            entry(&local_id, vec![Protocol::Memory(2).into()]),
+            entry(&PeerId::random(), vec![]),
+            entry(&known, vec![Protocol::Memory(3).into()]),
+            entry(&new, vec![Protocol::Memory(4).into(), Protocol::Memory(4).into()]),
+        ]
+    };
+
+    assert_eq!(kademlia.import_routing_table(snapshot), 1);
+    assert_eq!(kademlia.unverified_peers().collect::<Vec<_>>(), vec![&new]);
+    assert_eq!(kademlia.addresses_of_peer(&known), vec![Protocol::Memory(1).into()]);
+    assert_eq!(kademlia.addresses_of_peer(&new), vec![Protocol::Memory(4).into()]);
+
+    // Imported peers are neither connected nor shared with other nodes.
+    let snapshot = kademlia.routing_table_snapshot();
+    assert_eq!(snapshot.entries.len(), 2);
+    assert!(snapshot.entries.iter().all(|e| e.status == NodeStatus::Disconnected));
+    assert!(snapshot.entries.iter().all(|e| e.last_seen.is_none()));
+    let closest = kademlia.find_closest(&kbucket::Key::new(PeerId::random()), &local_id);
+    assert_eq!(closest.iter().map(|p| &p.node_id).collect::<Vec<_>>(), vec![&known]);
+}
+
+#[test]
+fn routing_table_snapshot_warm_restart() {
+    let (_, mut alice) = build_node::<MemoryStore>();
+    let (bob_addr, bob) = build_node::<MemoryStore>();
+    let bob_id = Swarm::local_peer_id(&bob).clone();
+    alice.add_address(&bob_id, bob_addr.clone());
+
+    // Runs a query from the first swarm to completion.
+    fn run_query(swarms: &mut [TestSwarm]) {
+        let qid = swarms[0].get_closest_peers(PeerId::random());
+        block_on(poll_fn(|ctx| {
+            for swarm in swarms.iter_mut() {
+                loop {
+                    match swarm.poll_next_unpin(ctx) {
+                        Poll::Ready(Some(KademliaEvent::QueryResult { id, .. }))
+                            if id == qid => return Poll::Ready(()),
+                        Poll::Ready(Some(_)) => {}
+                        e @ Poll::Ready(_) => panic!("Unexpected return value: {:?}", e),
+                        Poll::Pending => break,
+                    }
+                }
+            }
+            Poll::Pending
+        }))
+    }
+
+    let mut swarms = [alice, bob];
+    run_query(&mut swarms);
+
+    let snapshot = swarms[0].routing_table_snapshot();
+    assert_eq!(snapshot.entries.len(), 1);
+    assert_eq!(snapshot.entries[0].peer, bob_id);
+    assert_eq!(snapshot.entries[0].addresses, vec![bob_addr]);
+    assert_eq!(snapshot.entries[0].status, NodeStatus::Connected);
+    assert!(snapshot.entries[0].last_seen.is_some());
+    let bytes = snapshot.to_bytes();
+
+    // A restarted Alice imports the snapshot. Bob is unverified
+    // until Alice is connected to him again.
+    let [_, bob] = swarms;
+    let (_, mut alice) = build_node::<MemoryStore>();
+    let snapshot = RoutingTableSnapshot::from_bytes(&bytes).unwrap();
+    assert_eq!(alice.import_routing_table(snapshot), 1);
+    assert_eq!(alice.unverified_peers().collect::<Vec<_>>(), vec![&bob_id]);
+
+    let mut swarms = [alice, bob];
+    run_query(&mut swarms);
+    assert_eq!(swarms[0].unverified_peers().count(), 0);
+    assert!(swarms[0].kbucket(bob_id.clone()).unwrap().iter()
+        .any(|e| e.node.key.preimage() == &bob_id));
+}
+
+#[test]
+fn unreachable_unverified_peer_removed() {
+    let (_, mut alice) = build_node::<MemoryStore>();
+    let stale = PeerId::random();
+    let snapshot = RoutingTableSnapshot {
+        entries: vec![RoutingTableEntry {
+            peer: stale.clone(),
+            addresses: vec![multiaddr![Udp(10u16)]],
+            status: NodeStatus::Disconnected,
+            last_seen: None,
+        }]
+    };
+    assert_eq!(alice.import_routing_table(snapshot), 1);
+    alice.get_closest_peers(PeerId::random());
+
+    block_on(poll_fn(|ctx| {
+        loop {
+            match alice.poll_next_unpin(ctx) {
+                Poll::Ready(Some(KademliaEvent::UnverifiedPeerRemoved { peer })) => {
+                    assert_eq!(peer, stale);
+                    return Poll::Ready(())
+                }
+                Poll::Ready(Some(_)) => {}
+                e @ Poll::Ready(_) => panic!("Unexpected return value: {:?}", e),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }));
+    assert_eq!(alice.unverified_peers().count(), 0);
+    assert!(alice.kbuckets().next().is_none());
+}
+
+#[test]
+fn periodic_routing_table_snapshot() {
+    let mut cfg = KademliaConfig::default();
+    cfg.set_routing_table_snapshot_interval(Some(Duration::from_millis(50)));
+    let (_, mut alice) = build_node_with_config::<MemoryStore>(cfg);
+    let bob = PeerId::random();
+    alice.add_address(&bob, Protocol::Memory(1).into());
+
+    for _ in 0 .. 2 {
+        let snapshot = block_on(poll_fn(|ctx| {
+            loop {
+                match alice.poll_next_unpin(ctx) {
+                    Poll::Ready(Some(KademliaEvent::RoutingTableSnapshot { snapshot })) =>
+                        return Poll::Ready(snapshot),
+                    Poll::Ready(Some(_)) => {}
+                    e @ Poll::Ready(_) => panic!("Unexpected return value: {:?}", e),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }));
+        assert_eq!(snapshot.entries.len(), 1);
+        assert_eq!(snapshot.entries[0].peer, bob);
+    }
+}
+
+#[test]
+fn bootstrap_on_start() {
+    let mut cfg = KademliaConfig::default();
+    cfg.set_bootstrap_on_start(true);
+    let (_, mut alice) = build_node_with_config::<MemoryStore>(cfg);
+    let (bob_addr, bob) = build_node::<MemoryStore>();
+    let alice_id = Swarm::local_peer_id(&alice).clone();
+    alice.add_address(Swarm::local_peer_id(&bob), bob_addr);
+
+    let mut swarms = [alice, bob];
+    block_on(poll_fn(|ctx| {
+        for swarm in swarms.iter_mut() {
+            loop {
+                match swarm.poll_next_unpin(ctx) {
+                    Poll::Ready(Some(KademliaEvent::QueryResult {
+                        result: QueryResult::Bootstrap(Ok(ok)), ..
+                    })) => {
+                        assert_eq!(ok.peer, alice_id);
+                        return Poll::Ready(())
+                    }
+                    Poll::Ready(Some(_)) => {}
+                    e @ Poll::Ready(_) => panic!("Unexpected return value: {:?}", e),
+                    Poll::Pending => break,
+                }
+            }
+        }
+        Poll::Pending
+    }));
+}
+
+#[test]
+fn crawl() {
+    let (_, mut lonely) = build_node::<MemoryStore>();
+    assert!(lonely.crawl().is_err());
+
+    // Every node only knows the next one, so the crawl can only
+    // enumerate the nodes by contacting the discovered peers.
+    let mut swarms = build_connected_nodes::<MemoryStore>(6, 1)
+        .into_iter()
+        .map(|(_, swarm)| swarm)
+        .collect::<Vec<_>>();
+    let expected = swarms.iter().skip(1)
+        .map(|swarm| Swarm::local_peer_id(swarm).clone())
+        .collect::<HashSet<_>>();
+
+    let qid = swarms[0].crawl().unwrap();
+    let mut progressed = HashSet::new();
+
+    block_on(poll_fn(|ctx| {
+        for swarm in swarms.iter_mut() {
+            loop {
+                match swarm.poll_next_unpin(ctx) {
+                    Poll::Ready(Some(KademliaEvent::CrawlProgress { id, peer, addresses, .. })) => {
+                        assert_eq!(id, qid);
+                        assert!(!addresses.is_empty());
+                        progressed.insert(peer);
+                    }
+                    Poll::Ready(Some(KademliaEvent::QueryResult {
+                        id, result: QueryResult::Crawl(Ok(ok)), ..
+                    })) => {
+                        assert_eq!(id, qid);
+                        assert_eq!(ok.peers.into_iter().collect::<HashSet<_>>(), expected);
+                        assert_eq!(progressed, expected);
+                        return Poll::Ready(())
+                    }
+                    Poll::Ready(Some(KademliaEvent::QueryResult { result, .. })) => {
+                        panic!("Unexpected query result: {:?}", result)
+                    }
+                    Poll::Ready(Some(_)) => {}
+                    e @ Poll::Ready(_) => panic!("Unexpected return value: {:?}", e),
+                    Poll::Pending => break,
+                }
+            }
+        }
+        Poll::Pending
+    }));
+}
+
+#[test]
+fn diversity_limits() {
+    let mut cfg = KademliaConfig::default();
+    cfg.set_diversity_limits(kbucket::DiversityLimits {
+        ipv4_subnet_per_table: Some(2),
+        custom_per_bucket: Some(1),
+        .. Default::default()
+    });
+    // Groups the IPv6 addresses by their second segment.
+    cfg.set_diversity_grouping(|addr| match addr.iter().next() {
+        Some(Protocol::Ip6(ip)) => Some(u64::from(ip.segments()[1])),
+        _ => None
+    });
+    let (_, mut swarm) = build_node_with_config::<MemoryStore>(cfg);
+
+    for i in 0 .. 2 {
+        let addr: Multiaddr = format!("/ip4/10.0.0.{}/tcp/4001", i).parse().unwrap();
+        let update = swarm.add_address(&PeerId::random(), addr);
+        assert!(matches!(update, RoutingUpdate::Success));
+    }
+    let addr: Multiaddr = "/ip4/10.0.0.2/tcp/4001".parse().unwrap();
+    match swarm.add_address(&PeerId::random(), addr) {
+        RoutingUpdate::Rejected { group } => {
+            assert_eq!(group, kbucket::DiversityGroup::Ipv4Subnet([10, 0, 0]))
+        }
+        _ => panic!("Expected the peer to be rejected.")
+    }
+    let peer = PeerId::random();
+    let addr: Multiaddr = "/ip4/10.0.1.1/tcp/4001".parse().unwrap();
+    assert!(matches!(swarm.add_address(&peer, addr), RoutingUpdate::Success));
+
+    // A new address of a peer in the routing table is subject to the limits.
+    let addr: Multiaddr = "/ip4/10.0.0.3/tcp/4001".parse().unwrap();
+    match swarm.add_address(&peer, addr) {
+        RoutingUpdate::Rejected { group } => {
+            assert_eq!(group, kbucket::DiversityGroup::Ipv4Subnet([10, 0, 0]))
+        }
+        _ => panic!("Expected the address to be rejected.")
+    }
+    let addr: Multiaddr = "/ip4/10.0.1.2/tcp/4001".parse().unwrap();
+    assert!(matches!(swarm.add_address(&peer, addr), RoutingUpdate::Success));
+
+    // A peer without any group cannot be accounted for.
+    let addr: Multiaddr = "/dns4/example.com/tcp/4001".parse().unwrap();
+    match swarm.add_address(&PeerId::random(), addr) {
+        RoutingUpdate::Rejected { group } => {
+            assert_eq!(group, kbucket::DiversityGroup::Unknown)
+        }
+        _ => panic!("Expected the peer to be rejected.")
+    }
+
+    // Peers in the farthest bucket, which contains half of the keyspace,
+    // are subject to the per-bucket limit of the custom group.
+    let local_key = kbucket::Key::new(Swarm::local_peer_id(&swarm).clone());
+    let mut num_attempts = 0;
+    while num_attempts < 2 {
+        let peer = PeerId::random();
+        if local_key.distance(&kbucket::Key::new(peer.clone())).ilog2() != Some(255) {
+            continue
+        }
+        let addr: Multiaddr = format!("/ip6/2001:db8::{}/tcp/4001", num_attempts + 1).parse().unwrap();
+        match swarm.add_address(&peer, addr) {
+            RoutingUpdate::Success if num_attempts == 0 => {}
+            RoutingUpdate::Rejected { group } if num_attempts == 1 => {
+                assert_eq!(group, kbucket::DiversityGroup::Custom(0xdb8))
+            }
+            _ => panic!("Unexpected routing update.")
+        }
+        num_attempts += 1;
+    }
+}
+
+#[test]
+fn query_progress() {
+    // Every node only knows the next one, so the results of the
+    // queries of the first node are discovered step by step.
+    let mut swarms = build_connected_nodes::<MemoryStore>(3, 1)
+        .into_iter()
+        .map(|(_, swarm)| swarm)
+        .collect::<Vec<_>>();
+    let ids = swarms.iter()
+        .map(|swarm| Swarm::local_peer_id(swarm).clone())
+        .collect::<Vec<_>>();
+
+    // The second node knows the third node as a provider.
+    let key = Key::new(&random_multihash());
+    let record = ProviderRecord::new(key.clone(), ids[2].clone());
+    swarms[1].store_mut().add_provider(record).unwrap();
+
+    let qid_providers = swarms[0].get_providers(key);
+    let qid_peers = swarms[0].get_closest_peers(PeerId::random());
+
+    let mut providers = HashSet::new();
+    let mut peers = HashSet::new();
+    let mut steps = HashMap::new();
+    let mut finished = HashSet::new();
+
+    block_on(poll_fn(|ctx| {
+        for (i, swarm) in swarms.iter_mut().enumerate() {
+            loop {
+                match swarm.poll_next_unpin(ctx) {
+                    Poll::Ready(Some(KademliaEvent::QueryProgressed { id, progress, step })) => {
+                        assert_eq!(i, 0);
+                        assert!(!finished.contains(&id), "Progress after last step.");
+                        let count = steps.entry(id).or_insert(0);
+                        *count += 1;
+                        assert_eq!(step.count, *count);
+                        match progress {
+                            QueryProgress::GetProviders { providers: new, .. } => {
+                                assert_eq!(id, qid_providers);
+                                assert_eq!(new.is_empty(), step.last);
+                                for p in new {
+                                    assert!(providers.insert(p), "Provider reported twice.");
+                                }
+                            }
+                            QueryProgress::GetClosestPeers { peers: new, .. } => {
+                                assert_eq!(id, qid_peers);
+                                assert_eq!(new.is_empty(), step.last);
+                                for p in new {
+                                    assert!(peers.insert(p), "Peer reported twice.");
+                                }
+                            }
+                            p => panic!("Unexpected progress: {:?}", p)
+                        }
+                        if step.last {
+                            finished.insert(id);
+                        }
+                    }
+                    Poll::Ready(Some(KademliaEvent::QueryResult {
+                        id, result: QueryResult::GetProviders(Ok(ok)), ..
+                    })) if i == 0 => {
+                        assert_eq!(id, qid_providers);
+                        assert!(finished.contains(&id), "Result before last step.");
+                        assert_eq!(ok.providers, providers);
+                    }
+                    Poll::Ready(Some(KademliaEvent::QueryResult {
+                        id, result: QueryResult::GetClosestPeers(Ok(ok)), ..
+                    })) if i == 0 => {
+                        assert_eq!(id, qid_peers);
+                        assert!(finished.contains(&id), "Result before last step.");
+                        assert!(ok.peers.iter().all(|p| peers.contains(p) || p == &ids[1]));
+                    }
+                    Poll::Ready(Some(_)) => {}
+                    e @ Poll::Ready(_) => panic!("Unexpected return value: {:?}", e),
+                    Poll::Pending => break,
+                }
+            }
+        }
+        if swarms[0].queries.size() == 0 {
+            return Poll::Ready(())
+        }
+        Poll::Pending
+    }));
+
+    assert_eq!(providers, Some(ids[2].clone()).into_iter().collect());
+    assert_eq!(peers, Some(ids[2].clone()).into_iter().collect());
+    assert_eq!(finished.len(), 2);
+}
+
+#[test]
+fn query_progress_reports_peers_once() {
+    let mut swarms = build_connected_nodes::<MemoryStore>(2, 1)
+        .into_iter()
+        .map(|(_, swarm)| swarm)
+        .collect::<Vec<_>>();
+    let remote = Swarm::local_peer_id(&swarms[1]).clone();
+    let qid = swarms[0].get_closest_peers(PeerId::random());
+
+    // Let the query send its request to the second node.
+    block_on(poll_fn(|ctx| {
+        while let Poll::Ready(Some(_)) = swarms[0].poll_next_unpin(ctx) {}
+        Poll::Ready(())
+    }));
+
+    // The response lists the same peer twice.
+    let peer = KadPeer {
+        node_id: PeerId::random(),
+        multiaddrs: Vec::new(),
+        connection_ty: KadConnectionType::NotConnected,
+    };
+    swarms[0].inject_event(
+        remote,
+        ConnectionId::new(0),
+        KademliaHandlerEvent::FindNodeRes {
+            closer_peers: vec![peer.clone(), peer.clone()],
+            user_data: qid,
+        },
+    );
+
+    block_on(poll_fn(|ctx| {
+        loop {
+            match swarms[0].poll_next_unpin(ctx) {
+                Poll::Ready(Some(KademliaEvent::QueryProgressed { id, progress, .. })) => {
+                    assert_eq!(id, qid);
+                    match progress {
+                        QueryProgress::GetClosestPeers { peers, .. } => {
+                            assert_eq!(peers, vec![peer.node_id.clone()]);
+                            return Poll::Ready(())
+                        }
+                        p => panic!("Unexpected progress: {:?}", p)
+                    }
+                }
+                Poll::Ready(Some(_)) => {}
+                Poll::Ready(None) => panic!("Swarm stream ended."),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }))
+}
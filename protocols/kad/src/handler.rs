@@ -25,6 +25,7 @@ use crate::protocol::{
 use crate::record::{self, Record};
 use futures::prelude::*;
 use libp2p_swarm::{
+    IntoProtocolsHandler,
     NegotiatedSubstream,
     KeepAlive,
     SubstreamProtocol,
@@ -34,12 +35,43 @@ use libp2p_swarm::{
 };
 use libp2p_core::{
     either::EitherOutput,
-    upgrade::{self, InboundUpgrade, OutboundUpgrade}
+    upgrade::{self, InboundUpgrade, OutboundUpgrade},
+    ConnectedPoint,
+    PeerId,
 };
 use log::trace;
-use std::{error, fmt, io, pin::Pin, task::Context, task::Poll, time::Duration};
+use std::{error, fmt, io, marker::PhantomData, pin::Pin, task::Context, task::Poll, time::Duration};
 use wasm_timer::Instant;
 
+/// A prototype from which [`KademliaHandler`]s can be constructed.
+pub struct KademliaHandlerProto<T> {
+    config: KademliaHandlerConfig,
+    _type: PhantomData<T>,
+}
+
+impl<T> KademliaHandlerProto<T> {
+    /// Creates a [`KademliaHandlerProto`] using the given configuration.
+    pub fn new(config: KademliaHandlerConfig) -> Self {
+        KademliaHandlerProto { config, _type: PhantomData }
+    }
+}
+
+impl<T: Clone + Send + 'static> IntoProtocolsHandler for KademliaHandlerProto<T> {
+    type Handler = KademliaHandler<T>;
+
+    fn into_handler(self, _: &PeerId, endpoint: &ConnectedPoint) -> Self::Handler {
+        KademliaHandler::new(self.config, endpoint.clone())
+    }
+
+    fn inbound_protocol(&self) -> <Self::Handler as ProtocolsHandler>::InboundProtocol {
+        if self.config.allow_listening {
+            upgrade::EitherUpgrade::A(self.config.protocol_config.clone())
+        } else {
+            upgrade::EitherUpgrade::B(upgrade::DeniedUpgrade)
+        }
+    }
+}
+
 /// Protocol handler that handles Kademlia communications with the remote.
 ///
 /// The handler will automatically open a Kademlia substream with the remote for each request we
@@ -58,6 +90,26 @@ pub struct KademliaHandler<TUserData> {
 
     /// Until when to keep the connection alive.
     keep_alive: KeepAlive,
+
+    /// The connected endpoint of the connection that the handler
+    /// is associated with.
+    endpoint: ConnectedPoint,
+
+    /// Whether the remote is known to accept Kademlia requests.
+    protocol_status: ProtocolStatus,
+}
+
+/// The states of protocol confirmation that a connection
+/// handler transitions through.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ProtocolStatus {
+    /// It is as yet unknown whether the remote accepts Kademlia requests.
+    Unconfirmed,
+    /// The remote accepted a Kademlia substream, but this has not yet been
+    /// reported to the behaviour.
+    Confirmed,
+    /// The remote accepting Kademlia requests has been reported to the behaviour.
+    Reported,
 }
 
 /// Configuration of a [`KademliaHandler`].
@@ -66,7 +118,8 @@ pub struct KademliaHandlerConfig {
     /// Configuration of the wire protocol.
     pub protocol_config: KademliaProtocolConfig,
 
-    /// If false, we deny incoming requests.
+    /// If false, we deny incoming requests, e.g. because the local node
+    /// only acts as a client of the DHT.
     pub allow_listening: bool,
 
     /// Time after which we close an idle connection.
@@ -135,6 +188,13 @@ impl<TUserData> SubstreamState<TUserData> {
 /// Event produced by the Kademlia handler.
 #[derive(Debug)]
 pub enum KademliaHandlerEvent<TUserData> {
+    /// The remote accepted a Kademlia substream opened by us, confirming that it
+    /// serves the Kademlia protocol, i.e. that it can be added to the routing table.
+    ///
+    /// Only reported once per connection. Substreams opened by the remote do not
+    /// confirm the protocol, as the remote may only be a client of the DHT.
+    ProtocolConfirmed { endpoint: ConnectedPoint },
+
     /// Request for the list of nodes whose IDs are the closest to `key`. The number of nodes
     /// returned is not specified, but should be around 20.
     FindNodeReq {
@@ -279,6 +339,10 @@ pub enum KademliaHandlerIn<TUserData> {
     /// for the query on the remote.
     Reset(KademliaRequestId),
 
+    /// Enables or disables accepting requests from the remote, e.g. when the
+    /// local node switches between acting as a client and as a server of the DHT.
+    SetAllowListening(bool),
+
     /// Request for the list of nodes whose IDs are the closest to `key`. The number of nodes
     /// returned is not specified, but should be around 20.
     FindNodeReq {
@@ -379,8 +443,9 @@ pub struct KademliaRequestId {
 struct UniqueConnecId(u64);
 
 impl<TUserData> KademliaHandler<TUserData> {
-    /// Create a [`KademliaHandler`] using the given configuration.
-    pub fn new(config: KademliaHandlerConfig) -> Self {
+    /// Create a [`KademliaHandler`] for a connection with the given endpoint,
+    /// using the given configuration.
+    pub fn new(config: KademliaHandlerConfig, endpoint: ConnectedPoint) -> Self {
         let keep_alive = KeepAlive::Until(Instant::now() + config.idle_timeout);
 
         KademliaHandler {
@@ -388,16 +453,12 @@ impl<TUserData> KademliaHandler<TUserData> {
             next_connec_unique_id: UniqueConnecId(0),
             substreams: Vec::new(),
             keep_alive,
+            endpoint,
+            protocol_status: ProtocolStatus::Unconfirmed,
         }
     }
 }
 
-impl<TUserData> Default for KademliaHandler<TUserData> {
-    fn default() -> Self {
-        KademliaHandler::new(Default::default())
-    }
-}
-
 impl<TUserData> ProtocolsHandler for KademliaHandler<TUserData>
 where
     TUserData: Clone + Send + 'static,
@@ -426,6 +487,10 @@ where
     ) {
         self.substreams
             .push(SubstreamState::OutPendingSend(protocol, msg, user_data));
+        if self.protocol_status == ProtocolStatus::Unconfirmed {
+            // The remote accepted our substream, so it serves the protocol.
+            self.protocol_status = ProtocolStatus::Confirmed;
+        }
     }
 
     fn inject_fully_negotiated_inbound(
@@ -439,7 +504,12 @@ where
             EitherOutput::Second(p) => void::unreachable(p),
         };
 
-        debug_assert!(self.config.allow_listening);
+        // Listening may have been disabled while the substream was negotiated.
+        if !self.config.allow_listening {
+            trace!("Dropping inbound substream, as listening is disabled.");
+            return
+        }
+
         let connec_unique_id = self.next_connec_unique_id;
         self.next_connec_unique_id.0 += 1;
         self.substreams
@@ -448,6 +518,9 @@ where
 
     fn inject_event(&mut self, message: KademliaHandlerIn<TUserData>) {
         match message {
+            KademliaHandlerIn::SetAllowListening(allow_listening) => {
+                self.config.allow_listening = allow_listening;
+            }
             KademliaHandlerIn::Reset(request_id) => {
                 let pos = self.substreams.iter().position(|state| match state {
                         SubstreamState::InWaitingUser(conn_id, _) =>
@@ -616,6 +689,15 @@ where
     ) -> Poll<
         ProtocolsHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::OutEvent, Self::Error>,
     > {
+        if self.protocol_status == ProtocolStatus::Confirmed {
+            self.protocol_status = ProtocolStatus::Reported;
+            return Poll::Ready(ProtocolsHandlerEvent::Custom(
+                KademliaHandlerEvent::ProtocolConfirmed {
+                    endpoint: self.endpoint.clone(),
+                },
+            ));
+        }
+
         if self.substreams.is_empty() {
             return Poll::Pending;
         }
@@ -660,6 +742,18 @@ where
 
         Poll::Pending
     }
+
+    fn poll_close(&mut self, _: &mut Context<'_>) -> Poll<()> {
+        // Finish the ongoing requests in both directions, but don't wait for
+        // the remote to send new requests on its idle substreams.
+        let idle = self.substreams.iter()
+            .all(|s| matches!(s, SubstreamState::InWaitingMessage(..)));
+        if idle {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
 }
 
 impl Default for KademliaHandlerConfig {
@@ -61,6 +61,8 @@
 //! > to the size of all stored records. As a job runs, the records are moved
 //! > out of the job to the consumer, where they can be dropped after being sent.
 
+use crate::addresses::Addresses;
+use crate::kbucket::{self, KBucketsTable, NUM_BUCKETS};
 use crate::record::{self, Record, ProviderRecord, store::RecordStore};
 use libp2p_core::PeerId;
 use futures::prelude::*;
@@ -323,6 +325,111 @@ impl AddProviderJob {
     }
 }
 
+//////////////////////////////////////////////////////////////////////////////
+// BucketRefreshJob
+
+/// Periodic job for refreshing the buckets of the routing table.
+///
+/// As described in the Kademlia paper, a bucket is refreshed by a lookup
+/// for a random key in the range of the bucket if there has been no lookup
+/// for a key in that range within the refresh interval. Only the buckets
+/// starting with the closest non-empty bucket are refreshed, since the
+/// closer buckets are most likely empty. A bucket for which no random key
+/// is found (see [`kbucket::KBucketRef::rand_peer_key`]) is not refreshed
+/// and retried when the job runs the next time. Random keys are never found
+/// for the buckets closer than the 10 farthest ones, which are therefore
+/// never refreshed by this job.
+pub struct BucketRefreshJob {
+    /// The time of the last lookup for a key in the range of each bucket.
+    last_lookup: Vec<Instant>,
+    inner: PeriodicJob<vec::IntoIter<kbucket::Key<PeerId>>>,
+}
+
+impl BucketRefreshJob {
+    /// Creates a new periodic job for refreshing buckets that have
+    /// not seen a lookup within the given interval.
+    pub fn new(interval: Duration) -> Self {
+        let now = Instant::now();
+        let deadline = now + interval;
+        Self {
+            last_lookup: vec![now; NUM_BUCKETS],
+            inner: PeriodicJob {
+                interval,
+                state: PeriodicJobState::Waiting(Delay::new_at(deadline), deadline)
+            }
+        }
+    }
+
+    /// Checks whether the job is currently running.
+    pub fn is_running(&self) -> bool {
+        self.inner.is_running()
+    }
+
+    /// Records a lookup for the given key, which refreshes the bucket
+    /// of the routing table whose range the key falls into.
+    pub fn on_lookup(&mut self, local_key: &kbucket::Key<PeerId>, key: &kbucket::KeyBytes, now: Instant) {
+        if let Some(i) = local_key.distance(key).ilog2() {
+            self.last_lookup[i as usize] = now;
+        }
+    }
+
+    /// Polls the job for the target keys of bucket refreshes.
+    ///
+    /// Must be called in the context of a task. When `NotReady` is returned,
+    /// the current task is registered to be notified when the job is ready
+    /// to be run.
+    pub fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+        kbuckets: &mut KBucketsTable<kbucket::Key<PeerId>, Addresses>,
+        now: Instant
+    ) -> Poll<kbucket::Key<PeerId>> {
+        if self.inner.is_ready(cx, now) {
+            let local_key = kbuckets.local_key().clone();
+            let interval = self.inner.interval;
+            let last_lookup = &mut self.last_lookup;
+            let mut targets = Vec::new();
+            let mut nonempty = false;
+            for (i, bucket) in kbuckets.iter().enumerate() {
+                // The buckets closer than the closest non-empty bucket are skipped.
+                nonempty = nonempty || !bucket.is_empty();
+                if !nonempty || now < last_lookup[i] + interval {
+                    continue
+                }
+                if let Some(target) = bucket.rand_peer_key(&local_key) {
+                    targets.push(target);
+                }
+            }
+            self.inner.state = PeriodicJobState::Running(targets.into_iter());
+        }
+
+        if let PeriodicJobState::Running(targets) = &mut self.inner.state {
+            if let Some(target) = targets.next() {
+                // The bucket is refreshed by the lookup for the target.
+                if let Some(i) = kbuckets.local_key().distance(&target).ilog2() {
+                    self.last_lookup[i as usize] = now;
+                }
+                return Poll::Ready(target)
+            }
+
+            // Wait for the next bucket to become due for a refresh. The buckets
+            // that are overdue, i.e. have been skipped, are retried after the
+            // interval at the latest.
+            let interval = self.inner.interval;
+            let deadline = self.last_lookup.iter()
+                .map(|t| *t + interval)
+                .filter(|t| *t > now)
+                .min()
+                .unwrap_or(now + interval);
+            let delay = Delay::new_at(deadline);
+            self.inner.state = PeriodicJobState::Waiting(delay, deadline);
+            assert!(!self.inner.is_ready(cx, now));
+        }
+
+        Poll::Pending
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::record::store::MemoryStore;
@@ -411,4 +518,49 @@ mod tests {
 
         quickcheck(prop as fn(_))
     }
+
+    #[test]
+    fn run_bucket_refresh_job() {
+        let interval = Duration::from_secs(60);
+        let mut job = BucketRefreshJob::new(interval);
+        let local_key = kbucket::Key::new(PeerId::random());
+        let mut kbuckets = KBucketsTable::new(local_key.clone(), Duration::from_secs(60));
+        // A peer in one of the farthest buckets, for which random keys are
+        // found with overwhelming probability.
+        let (peer, index) = loop {
+            let peer = kbucket::Key::new(PeerId::random());
+            let index = local_key.distance(&peer).ilog2().unwrap() as usize;
+            if index >= NUM_BUCKETS - 8 {
+                break (peer, index)
+            }
+        };
+        if let kbucket::Entry::Absent(e) = kbuckets.entry(&peer) {
+            let addr = libp2p_core::multiaddr::Protocol::Memory(1).into();
+            let result = e.insert(Addresses::new(addr), kbucket::NodeStatus::Connected);
+            assert!(matches!(result, kbucket::InsertResult::Inserted));
+        }
+
+        block_on(poll_fn(|ctx| {
+            // Only the bucket of the peer and all farther buckets are refreshed.
+            let now = Instant::now() + interval;
+            let mut num_targets = 0;
+            while let Poll::Ready(target) = job.poll(ctx, &mut kbuckets, now) {
+                assert!(job.is_running());
+                assert!(local_key.distance(&target).ilog2().unwrap() as usize >= index);
+                num_targets += 1;
+            }
+            assert!(!job.is_running());
+            assert_eq!(num_targets, NUM_BUCKETS - index);
+
+            // A lookup in the bucket of the peer postpones its refresh.
+            job.on_lookup(&local_key, peer.as_ref(), now + interval / 2);
+            let now = now + interval;
+            let mut num_targets = 0;
+            while job.poll(ctx, &mut kbuckets, now).is_ready() {
+                num_targets += 1;
+            }
+            assert_eq!(num_targets, NUM_BUCKETS - index - 1);
+            Poll::Ready(())
+        }));
+    }
 }
@@ -73,12 +73,19 @@ mod key;
 pub use entry::*;
 
 use arrayvec::{self, ArrayVec};
-use bucket::KBucket;
+use bucket::{KBucket, TableDiversity};
+use libp2p_core::PeerId;
 use std::collections::VecDeque;
 use std::time::{Duration, Instant};
 
 /// Maximum number of k-buckets.
-const NUM_BUCKETS: usize = 256;
+pub(crate) const NUM_BUCKETS: usize = 256;
+
+/// The maximum expected number of random peer IDs to generate in order to
+/// find one whose key falls into a particular bucket.
+///
+/// See [`KBucketRef::rand_peer_key`].
+const MAX_EXPECTED_RAND_PEER_KEYS: u64 = 1 << 10;
 
 /// A `KBucketsTable` represents a Kademlia routing table.
 #[derive(Debug, Clone)]
@@ -89,7 +96,9 @@ pub struct KBucketsTable<TKey, TVal> {
     buckets: Vec<KBucket<TKey, TVal>>,
     /// The list of evicted entries that have been replaced with pending
     /// entries since the last call to [`KBucketsTable::take_applied_pending`].
-    applied_pending: VecDeque<AppliedPending<TKey, TVal>>
+    applied_pending: VecDeque<AppliedPending<TKey, TVal>>,
+    /// The diversity constraints enforced on the insertion of new entries, if any.
+    diversity: Option<TableDiversity<TVal>>,
 }
 
 /// A (type-safe) index into a `KBucketsTable`, i.e. a non-negative integer in the
@@ -158,10 +167,30 @@ where
         KBucketsTable {
             local_key,
             buckets: (0 .. NUM_BUCKETS).map(|_| KBucket::new(pending_timeout)).collect(),
-            applied_pending: VecDeque::new()
+            applied_pending: VecDeque::new(),
+            diversity: None,
         }
     }
 
+    /// Sets the diversity constraints enforced on the insertion of new
+    /// entries, see [`AbsentEntry::insert`], and on the updates of their
+    /// values, see [`PresentEntry::set_value`].
+    ///
+    /// Entries already in the routing table are not removed, but count
+    /// towards the limits.
+    pub fn set_diversity_filter(&mut self, filter: Option<DiversityFilter<TVal>>) {
+        self.diversity = filter.map(|filter| {
+            let mut diversity = TableDiversity::new(filter);
+            for (i, bucket) in self.buckets.iter().enumerate() {
+                for (node, _) in bucket.iter() {
+                    let groups = diversity.groups(&node.value);
+                    diversity.add(i, node.key.as_ref().clone(), groups);
+                }
+            }
+            diversity
+        });
+    }
+
     /// Returns the local key.
     pub fn local_key(&self) -> &TKey {
         &self.local_key
@@ -173,10 +202,10 @@ where
         let index = BucketIndex::new(&self.local_key.as_ref().distance(key));
         if let Some(i) = index {
             let bucket = &mut self.buckets[i.get()];
-            if let Some(applied) = bucket.apply_pending() {
+            if let Some(applied) = apply_pending(bucket, i, self.diversity.as_mut()) {
                 self.applied_pending.push_back(applied)
             }
-            Entry::new(bucket, key)
+            Entry::new(bucket, key, i, self.diversity.as_mut())
         } else {
             Entry::SelfEntry
         }
@@ -188,8 +217,9 @@ where
     /// bucket is the closest bucket (containing at most one key).
     pub fn iter<'a>(&'a mut self) -> impl Iterator<Item = KBucketRef<'a, TKey, TVal>> + 'a {
         let applied_pending = &mut self.applied_pending;
+        let mut diversity = self.diversity.as_mut();
         self.buckets.iter_mut().enumerate().map(move |(i, b)| {
-            if let Some(applied) = b.apply_pending() {
+            if let Some(applied) = apply_pending(b, BucketIndex(i), diversity.as_deref_mut()) {
                 applied_pending.push_back(applied)
             }
             KBucketRef {
@@ -209,7 +239,7 @@ where
         let d = self.local_key.as_ref().distance(key);
         if let Some(index) = BucketIndex::new(&d) {
             let bucket = &mut self.buckets[index.0];
-            if let Some(applied) = bucket.apply_pending() {
+            if let Some(applied) = apply_pending(bucket, index, self.diversity.as_mut()) {
                 self.applied_pending.push_back(applied)
             }
             Some(KBucketRef { bucket, index })
@@ -300,6 +330,44 @@ where
     }
 }
 
+/// Applies the pending node of a bucket like [`KBucket::apply_pending`],
+/// keeping the diversity counters of the routing table, if any, up to date.
+///
+/// A pending node that would exceed a diversity limit at the time it is
+/// applied is dropped.
+fn apply_pending<TKey, TVal>(
+    bucket: &mut KBucket<TKey, TVal>,
+    index: BucketIndex,
+    diversity: Option<&mut TableDiversity<TVal>>
+) -> Option<AppliedPending<TKey, TVal>>
+where
+    TKey: Clone + AsRef<KeyBytes>,
+    TVal: Clone
+{
+    let diversity = match diversity {
+        Some(d) => d,
+        None => return bucket.apply_pending()
+    };
+    let groups = match bucket.pending() {
+        Some(p) if p.is_ready() => {
+            let groups = diversity.groups(p.value());
+            let replaced = bucket.pending_replaces().map(|k| k.as_ref());
+            if groups.is_empty() || diversity.check(index.get(), &groups, replaced).is_err() {
+                bucket.remove_pending();
+                return None
+            }
+            groups
+        }
+        _ => return None
+    };
+    let applied = bucket.apply_pending()?;
+    if let Some(evicted) = &applied.evicted {
+        diversity.remove(index.get(), evicted.key.as_ref());
+    }
+    diversity.add(index.get(), applied.inserted.key.as_ref().clone(), groups);
+    Some(applied)
+}
+
 /// An iterator over (some projection of) the closest entries in a
 /// `KBucketsTable` w.r.t. some target `Key`.
 struct ClosestIter<'a, TTarget, TKey, TVal, TMap, TOut> {
@@ -431,7 +499,8 @@ where
                 None => {
                     if let Some(i) = self.buckets_iter.next() {
                         let bucket = &mut self.table.buckets[i.get()];
-                        if let Some(applied) = bucket.apply_pending() {
+                        let diversity = self.table.diversity.as_mut();
+                        if let Some(applied) = apply_pending(bucket, i, diversity) {
                             self.table.applied_pending.push_back(applied)
                         }
                         let mut v = (self.fmap)(bucket);
@@ -507,14 +576,44 @@ where
             }
         })
     }
+
+    /// Tries to generate a random peer ID whose key falls into this bucket,
+    /// w.r.t. the given local key.
+    ///
+    /// The libp2p Kademlia wire protocol requires the preimages of the keys
+    /// in the DHT keyspace, hence a key at a random distance in the range of
+    /// the bucket (see [`KBucketRef::rand_distance`]) cannot be looked up.
+    /// Instead, random peer IDs are generated until one hashes into the bucket.
+    /// Since a random key falls into the bucket `i` with a probability of
+    /// `2^(i - 256)`, `None` is returned without any trials for the buckets
+    /// that require more than 1024 trials on average, and after 16 times
+    /// the expected number of trials otherwise,
+    /// i.e. with a probability of about `e^-16`.
+    pub fn rand_peer_key(&self, local_key: &Key<PeerId>) -> Option<Key<PeerId>> {
+        self.rand_peer_key_with(local_key, PeerId::random)
+    }
+
+    /// Like [`KBucketRef::rand_peer_key`], drawing the candidate peer IDs
+    /// from `gen`.
+    fn rand_peer_key_with<F>(&self, local_key: &Key<PeerId>, mut gen: F) -> Option<Key<PeerId>>
+    where
+        F: FnMut() -> PeerId
+    {
+        let expected = 1u64.checked_shl((NUM_BUCKETS - self.index.get()) as u32)
+            .filter(|n| *n <= MAX_EXPECTED_RAND_PEER_KEYS)?;
+        (0 .. 16 * expected)
+            .map(|_| Key::from(gen()))
+            .find(|k| self.contains(&local_key.distance(k)))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use libp2p_core::PeerId;
+    use multihash::{wrap, Code};
     use quickcheck::*;
-    use rand::Rng;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
 
     type TestTable = KBucketsTable<KeyBytes, ()>;
 
@@ -598,6 +697,26 @@ mod tests {
         quickcheck(prop as fn(_) -> _);
     }
 
+    #[test]
+    fn rand_peer_key() {
+        // Draw the peer IDs from a seeded RNG, so that the outcome for every
+        // bucket is the same on each run.
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut gen = move || {
+            let hash = rng.gen::<[u8; 32]>();
+            PeerId::from_multihash(wrap(Code::Sha2_256, &hash)).unwrap()
+        };
+        let local_key = Key::from(gen());
+        let mut table = KBucketsTable::<_, ()>::new(local_key.clone(), Duration::from_secs(5));
+        for (i, bucket) in table.iter().enumerate() {
+            match bucket.rand_peer_key_with(&local_key, &mut gen) {
+                Some(key) => assert!(bucket.contains(&local_key.distance(&key))),
+                // Only the 10 farthest buckets are tried.
+                None => assert!(i < NUM_BUCKETS - 10, "no key for bucket {}", i),
+            }
+        }
+    }
+
     #[test]
     fn entry_inserted() {
         let local_key = Key::from(PeerId::random());
@@ -713,6 +832,53 @@ mod tests {
         assert_eq!(None, table.take_applied_pending());
     }
 
+    #[test]
+    fn diversity_counters() {
+        let local_key = Key::from(PeerId::random());
+        let mut table = KBucketsTable::<_, u64>::new(local_key, Duration::from_secs(5));
+        let limits = DiversityLimits { custom_per_table: Some(2), .. DiversityLimits::default() };
+        // The value `0` stands for a node without any group.
+        let filter = DiversityFilter::new(limits, |v: &u64| {
+            Some(DiversityGroup::Custom(*v)).filter(|_| *v != 0).into_iter().collect()
+        });
+        table.set_diversity_filter(Some(filter));
+
+        let insert = |table: &mut KBucketsTable<_, u64>, key: &Key<PeerId>, value| {
+            match table.entry(key) {
+                Entry::Absent(e) => e.insert(value, NodeStatus::Connected),
+                _ => panic!("entry exists")
+            }
+        };
+        let keys = (0 .. 4).map(|_| Key::from(PeerId::random())).collect::<Vec<_>>();
+        assert_eq!(InsertResult::Inserted, insert(&mut table, &keys[0], 1));
+        assert_eq!(InsertResult::Inserted, insert(&mut table, &keys[1], 1));
+        assert_eq!(InsertResult::Inserted, insert(&mut table, &keys[2], 2));
+        let rejected = InsertResult::Rejected { group: DiversityGroup::Custom(1) };
+        assert_eq!(rejected, insert(&mut table, &keys[3], 1));
+        let unknown = InsertResult::Rejected { group: DiversityGroup::Unknown };
+        assert_eq!(unknown, insert(&mut table, &keys[3], 0));
+
+        // Updates of values are subject to the limits of the groups the
+        // node is not yet in.
+        match table.entry(&keys[2]) {
+            Entry::Present(mut e, _) => {
+                assert_eq!(Err(DiversityGroup::Custom(1)), e.set_value(1));
+                assert_eq!(Ok(()), e.set_value(3));
+            }
+            _ => panic!("entry is absent")
+        }
+        match table.entry(&keys[1]) {
+            Entry::Present(mut e, _) => {
+                assert_eq!(Ok(()), e.set_value(1));
+                e.remove();
+            }
+            _ => panic!("entry is absent")
+        }
+
+        // A removed node no longer counts towards the limits.
+        assert_eq!(InsertResult::Inserted, insert(&mut table, &keys[3], 1));
+    }
+
     #[test]
     fn count_nodes_between() {
         fn prop(mut table: TestTable, target: Key<PeerId>) -> bool {
@@ -27,6 +27,7 @@
 
 pub use crate::K_VALUE;
 use super::*;
+use std::{collections::HashMap, fmt, sync::Arc};
 
 /// A `PendingNode` is a `Node` that is pending insertion into a `KBucket`.
 #[derive(Debug, Clone)]
@@ -63,6 +64,10 @@ impl<TKey, TVal> PendingNode<TKey, TVal> {
         self.status
     }
 
+    pub fn value(&self) -> &TVal {
+        &self.node.value
+    }
+
     pub fn value_mut(&mut self) -> &mut TVal {
         &mut self.node.value
     }
@@ -147,7 +152,13 @@ pub enum InsertResult<TKey> {
          disconnected: TKey
      },
      /// The entry was not inserted because the relevant bucket is full.
-     Full
+     Full,
+     /// The entry was not inserted because it would exceed the limit
+     /// on the number of entries of the given group, as per the
+     /// [`DiversityFilter`] of the routing table.
+     Rejected {
+         group: DiversityGroup
+     }
 }
 
 /// The result of applying a pending node to a bucket, possibly
@@ -161,6 +172,196 @@ pub struct AppliedPending<TKey, TVal> {
     pub evicted: Option<Node<TKey, TVal>>
 }
 
+/// A group of nodes w.r.t. the [`DiversityLimits`] of a routing table,
+/// e.g. the IP subnet of the addresses of the nodes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DiversityGroup {
+    /// The IPv4 `/24` subnet with the given prefix.
+    Ipv4Subnet([u8; 3]),
+    /// The IPv6 `/48` subnet with the given prefix.
+    Ipv6Subnet([u8; 6]),
+    /// A group determined by a custom grouping, e.g. an autonomous system.
+    Custom(u64),
+    /// The group of nodes for which no other group is determined, e.g. nodes
+    /// with only `/dns4` addresses.
+    ///
+    /// Nodes in this group cannot be accounted for and are not inserted into
+    /// a routing table with a [`DiversityFilter`].
+    Unknown,
+}
+
+/// Limits on the number of nodes per [`DiversityGroup`] in a routing table,
+/// protecting against a Sybil attacker with nodes in few networks filling
+/// the buckets.
+///
+/// A limit of `None` means that the number of nodes is not limited, which
+/// is the default.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct DiversityLimits {
+    /// The maximum number of nodes per IPv4 `/24` subnet in a bucket.
+    pub ipv4_subnet_per_bucket: Option<usize>,
+    /// The maximum number of nodes per IPv4 `/24` subnet in the routing table.
+    pub ipv4_subnet_per_table: Option<usize>,
+    /// The maximum number of nodes per IPv6 `/48` subnet in a bucket.
+    pub ipv6_subnet_per_bucket: Option<usize>,
+    /// The maximum number of nodes per IPv6 `/48` subnet in the routing table.
+    pub ipv6_subnet_per_table: Option<usize>,
+    /// The maximum number of nodes per custom group in a bucket.
+    pub custom_per_bucket: Option<usize>,
+    /// The maximum number of nodes per custom group in the routing table.
+    pub custom_per_table: Option<usize>,
+}
+
+impl DiversityLimits {
+    fn per_bucket(&self, group: &DiversityGroup) -> Option<usize> {
+        match group {
+            DiversityGroup::Ipv4Subnet(_) => self.ipv4_subnet_per_bucket,
+            DiversityGroup::Ipv6Subnet(_) => self.ipv6_subnet_per_bucket,
+            DiversityGroup::Custom(_) => self.custom_per_bucket,
+            DiversityGroup::Unknown => None,
+        }
+    }
+
+    fn per_table(&self, group: &DiversityGroup) -> Option<usize> {
+        match group {
+            DiversityGroup::Ipv4Subnet(_) => self.ipv4_subnet_per_table,
+            DiversityGroup::Ipv6Subnet(_) => self.ipv6_subnet_per_table,
+            DiversityGroup::Custom(_) => self.custom_per_table,
+            DiversityGroup::Unknown => None,
+        }
+    }
+}
+
+/// The diversity constraints enforced on the insertion of new nodes
+/// into the buckets of a routing table.
+pub struct DiversityFilter<TVal> {
+    /// The limits per group.
+    limits: DiversityLimits,
+    /// Determines the groups of a node from its value.
+    groups: Arc<GroupsFn<TVal>>,
+}
+
+type GroupsFn<TVal> = dyn Fn(&TVal) -> Vec<DiversityGroup> + Send + Sync;
+
+impl<TVal> DiversityFilter<TVal> {
+    /// Creates a new `DiversityFilter` with the given limits, whereby the
+    /// groups of a node are determined from its value by `groups`.
+    pub fn new<F>(limits: DiversityLimits, groups: F) -> Self
+    where
+        F: Fn(&TVal) -> Vec<DiversityGroup> + Send + Sync + 'static
+    {
+        DiversityFilter { limits, groups: Arc::new(groups) }
+    }
+
+    /// Returns the limits enforced by the filter.
+    pub fn limits(&self) -> &DiversityLimits {
+        &self.limits
+    }
+}
+
+impl<TVal> Clone for DiversityFilter<TVal> {
+    fn clone(&self) -> Self {
+        DiversityFilter { limits: self.limits, groups: self.groups.clone() }
+    }
+}
+
+impl<TVal> fmt::Debug for DiversityFilter<TVal> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiversityFilter").field("limits", &self.limits).finish()
+    }
+}
+
+/// The [`DiversityFilter`] of a routing table together with the number
+/// of nodes per group in every bucket and in the routing table as a whole.
+///
+/// Only the nodes in the buckets are counted. Pending nodes are checked
+/// against the limits when they are inserted as pending and again when
+/// they are applied.
+#[derive(Debug, Clone)]
+pub(crate) struct TableDiversity<TVal> {
+    filter: DiversityFilter<TVal>,
+    /// The groups every node in the routing table is counted in.
+    groups: HashMap<KeyBytes, Vec<DiversityGroup>>,
+    /// The number of nodes per group in the routing table.
+    per_table: HashMap<DiversityGroup, usize>,
+    /// The number of nodes per group in every bucket.
+    per_bucket: Vec<HashMap<DiversityGroup, usize>>,
+}
+
+impl<TVal> TableDiversity<TVal> {
+    pub(crate) fn new(filter: DiversityFilter<TVal>) -> Self {
+        TableDiversity {
+            filter,
+            groups: HashMap::new(),
+            per_table: HashMap::new(),
+            per_bucket: (0 .. NUM_BUCKETS).map(|_| HashMap::new()).collect(),
+        }
+    }
+
+    /// Determines the groups of a node with the given value.
+    pub(crate) fn groups(&self, value: &TVal) -> Vec<DiversityGroup> {
+        (self.filter.groups)(value)
+    }
+
+    /// Returns the groups the node with the given key is counted in.
+    pub(crate) fn counted(&self, key: &KeyBytes) -> &[DiversityGroup] {
+        self.groups.get(key).map_or(&[], |gs| gs.as_slice())
+    }
+
+    /// Checks whether a node in the given groups can be in the bucket with
+    /// the given index without exceeding a limit, whereby the node with the
+    /// key `replaced`, if any, is not counted.
+    ///
+    /// Returns the group whose limit would be exceeded otherwise.
+    pub(crate) fn check(
+        &self,
+        bucket: usize,
+        groups: &[DiversityGroup],
+        replaced: Option<&KeyBytes>
+    ) -> Result<(), DiversityGroup> {
+        let replaced = replaced.and_then(|k| self.groups.get(k));
+        for group in groups {
+            let discount = replaced.map_or(0, |gs| gs.contains(group) as usize);
+            if let Some(max) = self.filter.limits.per_bucket(group) {
+                let n = self.per_bucket[bucket].get(group).copied().unwrap_or(0);
+                if n - discount >= max {
+                    return Err(*group)
+                }
+            }
+            if let Some(max) = self.filter.limits.per_table(group) {
+                let n = self.per_table.get(group).copied().unwrap_or(0);
+                if n - discount >= max {
+                    return Err(*group)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts the node with the given key in the given groups.
+    pub(crate) fn add(&mut self, bucket: usize, key: KeyBytes, groups: Vec<DiversityGroup>) {
+        for group in &groups {
+            *self.per_bucket[bucket].entry(*group).or_insert(0) += 1;
+            *self.per_table.entry(*group).or_insert(0) += 1;
+        }
+        self.groups.insert(key, groups);
+    }
+
+    /// No longer counts the node with the given key.
+    pub(crate) fn remove(&mut self, bucket: usize, key: &KeyBytes) {
+        for group in self.groups.remove(key).unwrap_or_default() {
+            for counts in &mut [&mut self.per_bucket[bucket], &mut self.per_table] {
+                if let Some(n) = counts.get_mut(&group) {
+                    *n -= 1;
+                    if *n == 0 {
+                        counts.remove(&group);
+                    }
+                }
+            }
+        }
+    }
+}
+
 impl<TKey, TVal> KBucket<TKey, TVal>
 where
     TKey: Clone + AsRef<KeyBytes>,
@@ -261,6 +462,17 @@ where
         return None
     }
 
+    /// Returns the key of the node that is replaced if the pending node
+    /// is applied, i.e. the least-recently (dis)connected node of a full
+    /// bucket.
+    pub fn pending_replaces(&self) -> Option<&TKey> {
+        if self.nodes.is_full() {
+            self.nodes.first().map(|n| &n.key)
+        } else {
+            None
+        }
+    }
+
     /// Updates the status of the pending node, if any.
     pub fn update_pending(&mut self, status: NodeStatus) {
         if let Some(pending) = &mut self.pending {
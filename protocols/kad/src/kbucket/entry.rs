@@ -21,7 +21,10 @@
 //! The `Entry` API for quering and modifying the entries of a `KBucketsTable`
 //! representing the nodes participating in the Kademlia DHT.
 
-pub use super::bucket::{Node, NodeStatus, InsertResult, AppliedPending, K_VALUE};
+pub use super::bucket::{
+    Node, NodeStatus, InsertResult, AppliedPending, K_VALUE,
+    DiversityFilter, DiversityGroup, DiversityLimits,
+};
 pub use super::key::*;
 
 use super::*;
@@ -91,6 +94,10 @@ pub enum Entry<'a, TPeerId, TVal> {
 struct EntryRef<'a, TKey, TVal> {
     bucket: &'a mut KBucket<TKey, TVal>,
     key: &'a TKey,
+    /// The index of the bucket in the routing table.
+    index: BucketIndex,
+    /// The diversity constraints of the routing table, if any.
+    diversity: Option<&'a mut TableDiversity<TVal>>,
 }
 
 impl<'a, TKey, TVal> Entry<'a, TKey, TVal>
@@ -99,15 +106,21 @@ where
     TVal: Clone
 {
     /// Creates a new `Entry` for a `Key`, encapsulating access to a bucket.
-    pub(super) fn new(bucket: &'a mut KBucket<TKey, TVal>, key: &'a TKey) -> Self {
-        if let Some(pos) = bucket.position(key) {
-            let status = bucket.status(pos);
-            Entry::Present(PresentEntry::new(bucket, key), status)
-        } else if let Some(pending) = bucket.as_pending(key) {
+    pub(super) fn new(
+        bucket: &'a mut KBucket<TKey, TVal>,
+        key: &'a TKey,
+        index: BucketIndex,
+        diversity: Option<&'a mut TableDiversity<TVal>>
+    ) -> Self {
+        let entry = EntryRef { bucket, key, index, diversity };
+        if let Some(pos) = entry.bucket.position(key) {
+            let status = entry.bucket.status(pos);
+            Entry::Present(PresentEntry(entry), status)
+        } else if let Some(pending) = entry.bucket.as_pending(key) {
             let status = pending.status();
-            Entry::Pending(PendingEntry::new(bucket, key), status)
+            Entry::Pending(PendingEntry(entry), status)
         } else {
-            Entry::Absent(AbsentEntry::new(bucket, key))
+            Entry::Absent(AbsentEntry(entry))
         }
     }
 
@@ -172,16 +185,16 @@ where
     TKey: Clone + AsRef<KeyBytes>,
     TVal: Clone
 {
-    fn new(bucket: &'a mut KBucket<TKey, TVal>, key: &'a TKey) -> Self {
-        PresentEntry(EntryRef { bucket, key })
-    }
-
     /// Returns the key of the entry.
     pub fn key(&self) -> &TKey {
         self.0.key
     }
 
     /// Returns the value associated with the key.
+    ///
+    /// Changes of the value made through the returned reference are not
+    /// subject to the diversity constraints of the routing table, see
+    /// [`PresentEntry::set_value`].
     pub fn value(&mut self) -> &mut TVal {
         &mut self.0.bucket
             .get_mut(self.0.key)
@@ -189,10 +202,32 @@ where
             .value
     }
 
+    /// Replaces the value associated with the key.
+    ///
+    /// If the routing table has a [`DiversityFilter`], the value is not
+    /// replaced if the entry would exceed any of its limits in a group it
+    /// is not yet in, returning that group.
+    pub fn set_value(&mut self, value: TVal) -> Result<(), DiversityGroup> {
+        let index = self.0.index.get();
+        if let Some(diversity) = self.0.diversity.as_mut() {
+            let key = self.0.key.as_ref();
+            let groups = diversity.groups(&value);
+            let added = groups.iter()
+                .filter(|g| !diversity.counted(key).contains(g))
+                .copied()
+                .collect::<Vec<_>>();
+            diversity.check(index, &added, None)?;
+            diversity.remove(index, key);
+            diversity.add(index, key.clone(), groups);
+        }
+        *self.value() = value;
+        Ok(())
+    }
+
     /// Sets the status of the entry to `NodeStatus::Disconnected`.
     pub fn update(self, status: NodeStatus) -> Self {
         self.0.bucket.update(self.0.key, status);
-        Self::new(self.0.bucket, self.0.key)
+        self
     }
 
     /// Removes the entry from the bucket.
@@ -200,6 +235,9 @@ where
         let (node, status, _pos) = self.0.bucket
             .remove(&self.0.key)
             .expect("We can only build a PresentEntry if the entry is in the bucket; QED");
+        if let Some(diversity) = self.0.diversity {
+            diversity.remove(self.0.index.get(), self.0.key.as_ref());
+        }
         EntryView { node, status }
     }
 }
@@ -213,10 +251,6 @@ where
     TKey: Clone + AsRef<KeyBytes>,
     TVal: Clone
 {
-    fn new(bucket: &'a mut KBucket<TKey, TVal>, key: &'a TKey) -> Self {
-        PendingEntry(EntryRef { bucket, key })
-    }
-
     /// Returns the key of the entry.
     pub fn key(&self) -> &TKey {
         self.0.key
@@ -233,7 +267,7 @@ where
     /// Updates the status of the pending entry.
     pub fn update(self, status: NodeStatus) -> PendingEntry<'a, TKey, TVal> {
         self.0.bucket.update_pending(status);
-        PendingEntry::new(self.0.bucket, self.0.key)
+        self
     }
 
     /// Removes the pending entry from the bucket.
@@ -257,21 +291,37 @@ where
     TKey: Clone + AsRef<KeyBytes>,
     TVal: Clone
 {
-    fn new(bucket: &'a mut KBucket<TKey, TVal>, key: &'a TKey) -> Self {
-        AbsentEntry(EntryRef { bucket, key })
-    }
-
     /// Returns the key of the entry.
     pub fn key(&self) -> &TKey {
         self.0.key
     }
 
     /// Attempts to insert the entry into a bucket.
+    ///
+    /// If the routing table has a [`DiversityFilter`], the entry is
+    /// rejected if it exceeds any of its limits or if its groups are
+    /// unknown, see [`DiversityGroup::Unknown`]. An entry that is inserted
+    /// as pending is checked again when it is applied.
     pub fn insert(self, value: TVal, status: NodeStatus) -> InsertResult<TKey> {
-        self.0.bucket.insert(Node {
-            key: self.0.key.clone(),
-            value
-        }, status)
+        let EntryRef { bucket, key, index, diversity } = self.0;
+        let groups = match diversity {
+            Some(diversity) => {
+                let groups = diversity.groups(&value);
+                if groups.is_empty() {
+                    return InsertResult::Rejected { group: DiversityGroup::Unknown }
+                }
+                if let Err(group) = diversity.check(index.get(), &groups, None) {
+                    return InsertResult::Rejected { group }
+                }
+                Some((diversity, groups))
+            }
+            None => None
+        };
+        let result = bucket.insert(Node { key: key.clone(), value }, status);
+        if let (InsertResult::Inserted, Some((diversity, groups))) = (&result, groups) {
+            diversity.add(index.get(), key.as_ref().clone(), groups);
+        }
+        result
     }
 }
 
@@ -33,19 +33,23 @@ mod addresses;
 mod behaviour;
 mod jobs;
 mod query;
+mod snapshot;
 
 mod dht_proto {
     include!(concat!(env!("OUT_DIR"), "/dht.pb.rs"));
 }
 
 pub use addresses::Addresses;
-pub use behaviour::{Kademlia, KademliaBucketInserts, KademliaConfig, KademliaEvent, Quorum};
+pub use behaviour::{Kademlia, KademliaBucketInserts, KademliaConfig, KademliaEvent, KademliaMode, KademliaStoreInserts, Quorum, RoutingUpdate};
 pub use behaviour::{
     QueryRef,
     QueryMut,
 
+    InboundRequest,
     QueryResult,
     QueryInfo,
+    QueryProgress,
+    ProgressStep,
     QueryStats,
 
     PeerRecord,
@@ -68,6 +72,10 @@ pub use behaviour::{
     GetClosestPeersOk,
     GetClosestPeersError,
 
+    CrawlResult,
+    CrawlOk,
+    CrawlError,
+
     AddProviderPhase,
     AddProviderContext,
     AddProviderResult,
@@ -80,7 +88,8 @@ pub use behaviour::{
 };
 pub use query::QueryId;
 pub use protocol::KadConnectionType;
-pub use record::{store, Record, ProviderRecord};
+pub use snapshot::{RoutingTableEntry, RoutingTableSnapshot};
+pub use record::{store, Record, RecordValidator, ProviderRecord};
 
 use std::num::NonZeroUsize;
 
@@ -22,6 +22,7 @@ mod peers;
 
 use peers::PeersIterState;
 use peers::closest::{ClosestPeersIterConfig, ClosestPeersIter, disjoint::ClosestDisjointPeersIter};
+use peers::crawl::CrawlPeersIter;
 use peers::fixed::FixedPeersIter;
 
 use crate::{ALPHA_VALUE, K_VALUE};
@@ -145,6 +146,32 @@ impl<TInner> QueryPool<TInner> {
         self.queries.insert(id, query);
     }
 
+    /// Adds a query to the pool that contacts every peer it discovers,
+    /// starting with the given peers.
+    pub fn add_crawl<I>(&mut self, peers: I, inner: TInner) -> QueryId
+    where
+        I: IntoIterator<Item = PeerId>
+    {
+        let id = self.next_query_id();
+        self.continue_crawl(id, peers, inner);
+        id
+    }
+
+    /// Continues an earlier query with a crawl starting with the given
+    /// peers, reusing the given query ID, which must be from a query that
+    /// finished earlier.
+    pub fn continue_crawl<I>(&mut self, id: QueryId, peers: I, inner: TInner)
+    where
+        I: IntoIterator<Item = PeerId>
+    {
+        assert!(!self.queries.contains_key(&id));
+        let parallelism = self.config.parallelism;
+        let peer_timeout = ClosestPeersIterConfig::default().peer_timeout;
+        let peer_iter = QueryPeerIter::Crawl(CrawlPeersIter::new(peers, parallelism, peer_timeout));
+        let query = Query::new(id, peer_iter, inner);
+        self.queries.insert(id, query);
+    }
+
     fn next_query_id(&mut self) -> QueryId {
         let id = QueryId(self.next_id);
         self.next_id = self.next_id.wrapping_add(1);
@@ -269,7 +296,8 @@ pub struct Query<TInner> {
 enum QueryPeerIter {
     Closest(ClosestPeersIter),
     ClosestDisjoint(ClosestDisjointPeersIter),
-    Fixed(FixedPeersIter)
+    Fixed(FixedPeersIter),
+    Crawl(CrawlPeersIter)
 }
 
 impl<TInner> Query<TInner> {
@@ -293,7 +321,8 @@ impl<TInner> Query<TInner> {
         let updated = match &mut self.peer_iter {
             QueryPeerIter::Closest(iter) => iter.on_failure(peer),
             QueryPeerIter::ClosestDisjoint(iter) => iter.on_failure(peer),
-            QueryPeerIter::Fixed(iter) => iter.on_failure(peer)
+            QueryPeerIter::Fixed(iter) => iter.on_failure(peer),
+            QueryPeerIter::Crawl(iter) => iter.on_failure(peer)
         };
         if updated {
             self.stats.failure += 1;
@@ -310,7 +339,8 @@ impl<TInner> Query<TInner> {
         let updated = match &mut self.peer_iter {
             QueryPeerIter::Closest(iter) => iter.on_success(peer, new_peers),
             QueryPeerIter::ClosestDisjoint(iter) => iter.on_success(peer, new_peers),
-            QueryPeerIter::Fixed(iter) => iter.on_success(peer)
+            QueryPeerIter::Fixed(iter) => iter.on_success(peer),
+            QueryPeerIter::Crawl(iter) => iter.on_success(peer, new_peers)
         };
         if updated {
             self.stats.success += 1;
@@ -322,7 +352,8 @@ impl<TInner> Query<TInner> {
         match &self.peer_iter {
             QueryPeerIter::Closest(iter) => iter.is_waiting(peer),
             QueryPeerIter::ClosestDisjoint(iter) => iter.is_waiting(peer),
-            QueryPeerIter::Fixed(iter) => iter.is_waiting(peer)
+            QueryPeerIter::Fixed(iter) => iter.is_waiting(peer),
+            QueryPeerIter::Crawl(iter) => iter.is_waiting(peer)
         }
     }
 
@@ -331,7 +362,8 @@ impl<TInner> Query<TInner> {
         let state = match &mut self.peer_iter {
             QueryPeerIter::Closest(iter) => iter.next(now),
             QueryPeerIter::ClosestDisjoint(iter) => iter.next(now),
-            QueryPeerIter::Fixed(iter) => iter.next()
+            QueryPeerIter::Fixed(iter) => iter.next(),
+            QueryPeerIter::Crawl(iter) => iter.next(now)
         };
 
         if let PeersIterState::Waiting(Some(_)) = state {
@@ -365,7 +397,8 @@ impl<TInner> Query<TInner> {
         match &mut self.peer_iter {
             QueryPeerIter::Closest(iter) => { iter.finish(); true },
             QueryPeerIter::ClosestDisjoint(iter) => iter.finish_paths(peers),
-            QueryPeerIter::Fixed(iter) => { iter.finish(); true }
+            QueryPeerIter::Fixed(iter) => { iter.finish(); true },
+            QueryPeerIter::Crawl(iter) => { iter.finish(); true }
         }
     }
 
@@ -377,7 +410,8 @@ impl<TInner> Query<TInner> {
         match &mut self.peer_iter {
             QueryPeerIter::Closest(iter) => iter.finish(),
             QueryPeerIter::ClosestDisjoint(iter) => iter.finish(),
-            QueryPeerIter::Fixed(iter) => iter.finish()
+            QueryPeerIter::Fixed(iter) => iter.finish(),
+            QueryPeerIter::Crawl(iter) => iter.finish()
         }
     }
 
@@ -389,7 +423,8 @@ impl<TInner> Query<TInner> {
         match &self.peer_iter {
             QueryPeerIter::Closest(iter) => iter.is_finished(),
             QueryPeerIter::ClosestDisjoint(iter) => iter.is_finished(),
-            QueryPeerIter::Fixed(iter) => iter.is_finished()
+            QueryPeerIter::Fixed(iter) => iter.is_finished(),
+            QueryPeerIter::Crawl(iter) => iter.is_finished()
         }
     }
 
@@ -398,7 +433,8 @@ impl<TInner> Query<TInner> {
         let peers = match self.peer_iter {
             QueryPeerIter::Closest(iter) => Either::Left(Either::Left(iter.into_result())),
             QueryPeerIter::ClosestDisjoint(iter) => Either::Left(Either::Right(iter.into_result())),
-            QueryPeerIter::Fixed(iter) => Either::Right(iter.into_result())
+            QueryPeerIter::Fixed(iter) => Either::Right(Either::Left(iter.into_result())),
+            QueryPeerIter::Crawl(iter) => Either::Right(Either::Right(iter.into_result()))
         };
         QueryResult { peers, inner: self.inner, stats: self.stats }
     }
@@ -39,6 +39,7 @@
 //! [`Finished`]: PeersIterState::Finished
 
 pub mod closest;
+pub mod crawl;
 pub mod fixed;
 
 use libp2p_core::PeerId;
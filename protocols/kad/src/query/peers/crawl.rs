@@ -0,0 +1,323 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use super::*;
+
+use fnv::FnvHashMap;
+use libp2p_core::PeerId;
+use std::{collections::{VecDeque, hash_map::Entry}, num::NonZeroUsize, time::Duration};
+use wasm_timer::Instant;
+
+/// A peer iterator that contacts every peer it learns about.
+///
+/// In contrast to the [`ClosestPeersIter`](super::closest::ClosestPeersIter),
+/// the iterator does not converge towards a target but keeps contacting the
+/// peers discovered through successful requests until there are none left,
+/// thus crawling the part of the DHT that is reachable from the initial peers.
+///
+/// As for the [`ClosestPeersIter`](super::closest::ClosestPeersIter), a peer
+/// that does not deliver a result within the configured timeout is considered
+/// unresponsive and no longer counts towards the permitted parallelism.
+pub struct CrawlPeersIter {
+    /// The permitted parallelism, i.e. number of pending results.
+    parallelism: NonZeroUsize,
+
+    /// The timeout for the result of a request to a single peer.
+    peer_timeout: Duration,
+
+    /// The peers the iterator is waiting for, together with the instant at
+    /// which they are considered unresponsive, in the order of contact.
+    timeouts: VecDeque<(Instant, PeerId)>,
+
+    /// The state of all peers known to the iterator.
+    peers: FnvHashMap<PeerId, PeerState>,
+
+    /// The peers that have not yet been contacted, in the order
+    /// in which they were discovered.
+    backlog: VecDeque<PeerId>,
+
+    /// The internal state of the iterator.
+    state: State,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum State {
+    Waiting { num_waiting: usize },
+    Finished
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum PeerState {
+    /// The peer has not yet been contacted.
+    NotContacted,
+
+    /// The iterator is waiting for a result to be reported back for the peer.
+    Waiting,
+
+    /// A result was not delivered for the peer within the configured timeout.
+    ///
+    /// The peer is not taken into account for the termination conditions
+    /// of the iterator until and unless it responds.
+    Unresponsive,
+
+    /// The iterator has been informed that the attempt to contact the peer failed.
+    Failed,
+
+    /// The iterator has been informed of a successful result from the peer.
+    Succeeded,
+}
+
+impl CrawlPeersIter {
+    /// Creates a new iterator that starts crawling with the given peers.
+    pub fn new<I>(peers: I, parallelism: NonZeroUsize, peer_timeout: Duration) -> Self
+    where
+        I: IntoIterator<Item = PeerId>
+    {
+        let mut iter = Self {
+            parallelism,
+            peer_timeout,
+            timeouts: VecDeque::new(),
+            peers: FnvHashMap::default(),
+            backlog: VecDeque::new(),
+            state: State::Waiting { num_waiting: 0 },
+        };
+        iter.insert(peers);
+        iter
+    }
+
+    /// Adds peers to the backlog that are not yet known to the iterator.
+    fn insert<I>(&mut self, peers: I)
+    where
+        I: IntoIterator<Item = PeerId>
+    {
+        for peer in peers {
+            if let Entry::Vacant(e) = self.peers.entry(peer.clone()) {
+                e.insert(PeerState::NotContacted);
+                self.backlog.push_back(peer);
+            }
+        }
+    }
+
+    /// Callback for delivering the result of a successful request to a peer
+    /// that reported the given `new_peers`.
+    ///
+    /// If the iterator is currently waiting for a result from `peer`, or
+    /// `peer` has been considered unresponsive, the iterator state is
+    /// updated, the peers not yet known to the iterator are scheduled to
+    /// be contacted and `true` is returned. In that case, after calling
+    /// this function, `next` should eventually be called again to obtain
+    /// the new state of the iterator.
+    ///
+    /// If the iterator is finished, it never contacted `peer`, or a result
+    /// for `peer` has already been reported, calling this function has no
+    /// effect and `false` is returned.
+    pub fn on_success<I>(&mut self, peer: &PeerId, new_peers: I) -> bool
+    where
+        I: IntoIterator<Item = PeerId>
+    {
+        if let State::Waiting { num_waiting } = &mut self.state {
+            match self.peers.get_mut(peer) {
+                Some(state @ PeerState::Waiting) => {
+                    *num_waiting -= 1;
+                    *state = PeerState::Succeeded;
+                }
+                Some(state @ PeerState::Unresponsive) => {
+                    *state = PeerState::Succeeded;
+                }
+                _ => return false
+            }
+            self.insert(new_peers);
+            return true
+        }
+        false
+    }
+
+    /// Callback for informing the iterator about a failed request to a peer.
+    ///
+    /// If the iterator is currently waiting for a result from `peer`, or
+    /// `peer` has been considered unresponsive, the iterator state is
+    /// updated and `true` is returned. In that case, after calling this
+    /// function, `next` should eventually be called again to obtain the
+    /// new state of the iterator.
+    ///
+    /// If the iterator is finished, it never contacted `peer`, or a result
+    /// for `peer` has already been reported, calling this function has no
+    /// effect and `false` is returned.
+    pub fn on_failure(&mut self, peer: &PeerId) -> bool {
+        if let State::Waiting { num_waiting } = &mut self.state {
+            match self.peers.get_mut(peer) {
+                Some(state @ PeerState::Waiting) => {
+                    *num_waiting -= 1;
+                    *state = PeerState::Failed;
+                }
+                Some(state @ PeerState::Unresponsive) => {
+                    *state = PeerState::Failed;
+                }
+                _ => return false
+            }
+            return true
+        }
+        false
+    }
+
+    pub fn is_waiting(&self, peer: &PeerId) -> bool {
+        self.peers.get(peer) == Some(&PeerState::Waiting)
+    }
+
+    pub fn finish(&mut self) {
+        if let State::Waiting { .. } = self.state {
+            self.state = State::Finished
+        }
+    }
+
+    /// Checks whether the iterator has finished.
+    pub fn is_finished(&self) -> bool {
+        self.state == State::Finished
+    }
+
+    pub fn next(&mut self, now: Instant) -> PeersIterState<'_> {
+        match &mut self.state {
+            State::Finished => PeersIterState::Finished,
+            State::Waiting { num_waiting } => {
+                // Unresponsive peers no longer count towards the limit for the
+                // bounded parallelism, though their results can still be
+                // delivered to the iterator.
+                while let Some((timeout, _)) = self.timeouts.front() {
+                    if now < *timeout {
+                        break
+                    }
+                    let (_, peer) = self.timeouts.pop_front().expect("front exists");
+                    if let Some(state @ PeerState::Waiting) = self.peers.get_mut(&peer) {
+                        *state = PeerState::Unresponsive;
+                        *num_waiting -= 1;
+                    }
+                }
+
+                if *num_waiting >= self.parallelism.get() {
+                    return PeersIterState::WaitingAtCapacity
+                }
+                match self.backlog.pop_front() {
+                    None => if *num_waiting == 0 {
+                        self.state = State::Finished;
+                        PeersIterState::Finished
+                    } else {
+                        PeersIterState::Waiting(None)
+                    }
+                    Some(p) => {
+                        *num_waiting += 1;
+                        self.peers.insert(p.clone(), PeerState::Waiting);
+                        self.timeouts.push_back((now + self.peer_timeout, p.clone()));
+                        PeersIterState::Waiting(Some(Cow::Owned(p)))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Consumes the iterator, returning the peers that were successfully contacted.
+    pub fn into_result(self) -> impl Iterator<Item = PeerId> {
+        self.peers.into_iter()
+            .filter_map(|(p, s)|
+                if let PeerState::Succeeded = s {
+                    Some(p)
+                } else {
+                    None
+                })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn next_peer(iter: &mut CrawlPeersIter, now: Instant) -> PeerId {
+        match iter.next(now) {
+            PeersIterState::Waiting(Some(peer)) => peer.into_owned(),
+            s => panic!("Expected iterator to yield peer, got {:?}.", s),
+        }
+    }
+
+    #[test]
+    fn contacts_discovered_peers_once() {
+        let (a, b, c) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let parallelism = NonZeroUsize::new(3).unwrap();
+        let mut iter = CrawlPeersIter::new(vec![a.clone()], parallelism, TIMEOUT);
+        let now = Instant::now();
+
+        assert_eq!(next_peer(&mut iter, now), a);
+        assert_eq!(iter.next(now), PeersIterState::Waiting(None));
+        assert!(iter.on_success(&a, vec![b.clone(), a.clone()]));
+
+        assert_eq!(next_peer(&mut iter, now), b);
+        assert!(iter.on_success(&b, vec![a.clone(), b.clone(), c.clone()]));
+
+        assert_eq!(next_peer(&mut iter, now), c);
+        assert!(iter.on_failure(&c));
+        assert!(!iter.on_success(&c, vec![PeerId::random()]));
+
+        assert_eq!(iter.next(now), PeersIterState::Finished);
+        let result = iter.into_result().collect::<HashSet<_>>();
+        assert_eq!(result, vec![a, b].into_iter().collect());
+    }
+
+    #[test]
+    fn respects_parallelism() {
+        let peers = (0 .. 3).map(|_| PeerId::random()).collect::<Vec<_>>();
+        let mut iter = CrawlPeersIter::new(peers, NonZeroUsize::new(2).unwrap(), TIMEOUT);
+        let now = Instant::now();
+
+        let first = next_peer(&mut iter, now);
+        next_peer(&mut iter, now);
+        assert_eq!(iter.next(now), PeersIterState::WaitingAtCapacity);
+
+        assert!(iter.on_success(&first, Vec::new()));
+        next_peer(&mut iter, now);
+        assert_eq!(iter.next(now), PeersIterState::WaitingAtCapacity);
+    }
+
+    #[test]
+    fn unresponsive_peers_free_capacity() {
+        let (a, b, c) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let parallelism = NonZeroUsize::new(1).unwrap();
+        let mut iter = CrawlPeersIter::new(vec![a.clone(), b.clone()], parallelism, TIMEOUT);
+        let now = Instant::now();
+
+        assert_eq!(next_peer(&mut iter, now), a);
+        assert_eq!(iter.next(now + TIMEOUT / 2), PeersIterState::WaitingAtCapacity);
+
+        // The request to `a` times out, so `b` is contacted.
+        let now = now + TIMEOUT;
+        assert_eq!(next_peer(&mut iter, now), b);
+        assert!(iter.on_failure(&b));
+
+        // A late result of an unresponsive peer is still taken into account.
+        assert!(iter.on_success(&a, vec![c.clone()]));
+        assert!(!iter.on_success(&a, vec![]));
+        assert_eq!(next_peer(&mut iter, now), c);
+        assert!(iter.on_success(&c, vec![]));
+
+        assert_eq!(iter.next(now), PeersIterState::Finished);
+        let result = iter.into_result().collect::<HashSet<_>>();
+        assert_eq!(result, vec![a, c].into_iter().collect());
+    }
+}
@@ -26,6 +26,8 @@ use bytes::Bytes;
 use libp2p_core::PeerId;
 use multihash::Multihash;
 use std::borrow::Borrow;
+use std::error::Error;
+use std::fmt;
 use std::hash::{Hash, Hasher};
 use wasm_timer::Instant;
 
@@ -138,6 +140,34 @@ impl ProviderRecord {
     }
 }
 
+/// Application-specific validation of (value-)records, e.g. for records
+/// whose values are signed by the owner of the key.
+///
+/// A validator is configured via `KademliaConfig::set_record_validator`.
+/// Records received from remote nodes, both in inbound `PUT_VALUE` requests
+/// and in responses to `GET_VALUE` requests, are only accepted if they are
+/// valid. Records of the local node are not validated.
+pub trait RecordValidator: fmt::Debug + Send + Sync + 'static {
+    /// Checks whether the given record received from a remote node is valid.
+    ///
+    /// Invalid records are never put into the `RecordStore` and are
+    /// not part of the result of a `GET_VALUE` query.
+    fn validate(&self, record: &Record) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Selects the "best" among the given valid records for the given key,
+    /// returning its index in `records`. `records` is never empty.
+    ///
+    /// The records are those collected by a `GET_VALUE` query until it
+    /// terminated, regardless of its quorum. The selected record is the
+    /// first record of a successful query, and the record is re-published
+    /// to all remote nodes that returned a different value.
+    ///
+    /// The default implementation selects the first record.
+    fn select(&self, _key: &Key, _records: &[&Record]) -> usize {
+        0
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -18,13 +18,16 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+mod disk;
 mod memory;
 
+pub use disk::{DiskStore, DiskStoreConfig};
 pub use memory::{MemoryStore, MemoryStoreConfig};
 
 use crate::K_VALUE;
 use super::*;
 use std::borrow::Cow;
+use std::io;
 
 /// The result of an operation on a `RecordStore`.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -39,6 +42,8 @@ pub enum Error {
     MaxProvidedKeys,
     /// The value of a record to be stored is too large.
     ValueTooLarge,
+    /// The store failed to persist the operation.
+    Io(io::Error),
 }
 
 /// Trait for types implementing a record store.
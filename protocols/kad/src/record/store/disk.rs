@@ -0,0 +1,715 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use super::*;
+
+use crate::kbucket;
+use libp2p_core::PeerId;
+use log::warn;
+use prost::Message;
+use std::borrow::Cow;
+use std::cmp;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+mod disk_proto {
+    include!(concat!(env!("OUT_DIR"), "/disk_store.pb.rs"));
+}
+
+use disk_proto::entry::Kind;
+
+/// The name of the log file in the directory of a `DiskStore`.
+const LOG_FILE: &str = "records.log";
+
+/// The number of entries the log may hold before it is first compacted.
+const MIN_COMPACTION_ENTRIES: usize = 128;
+
+/// Disk-backed implementation of a `RecordStore`.
+///
+/// The records are held in memory, and every change to them is appended
+/// to a log file in the directory of the store, from which they are
+/// restored when the store is opened again. Expired records are dropped
+/// when the store is opened, and the log is compacted whenever it holds
+/// twice as many entries as after its last compaction.
+///
+/// When the store is at capacity, expired records are evicted first and
+/// then the record whose key is the farthest from the local node, provided
+/// it is farther than the key of the new record.
+pub struct DiskStore {
+    /// The identity of the peer owning the store.
+    local_key: kbucket::Key<PeerId>,
+    /// The configuration of the store.
+    config: DiskStoreConfig,
+    /// The records currently in the store.
+    memory: MemoryStore,
+    /// The path of the log file.
+    path: PathBuf,
+    /// The log file, opened for appending once the store has been loaded.
+    log: Option<File>,
+    /// The number of entries in the log.
+    log_entries: usize,
+    /// The number of entries in the log after its last compaction.
+    compacted_entries: usize,
+}
+
+/// Configuration for a `DiskStore`.
+#[derive(Debug, Clone)]
+pub struct DiskStoreConfig {
+    /// The maximum number of records.
+    pub max_records: usize,
+    /// The maximum size of record values, in bytes.
+    pub max_value_bytes: usize,
+    /// The maximum number of providers stored for a key.
+    ///
+    /// This should match up with the chosen replication factor.
+    pub max_providers_per_key: usize,
+    /// The maximum number of keys for which provider records are stored.
+    pub max_provided_keys: usize,
+}
+
+impl Default for DiskStoreConfig {
+    fn default() -> Self {
+        let config = MemoryStoreConfig::default();
+        Self {
+            max_records: config.max_records,
+            max_value_bytes: config.max_value_bytes,
+            max_provided_keys: config.max_provided_keys,
+            max_providers_per_key: config.max_providers_per_key,
+        }
+    }
+}
+
+impl DiskStore {
+    /// Opens the `DiskStore` in the given directory with a default configuration.
+    ///
+    /// See [`DiskStore::open_with_config`].
+    pub fn open(local_id: PeerId, dir: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with_config(local_id, dir, Default::default())
+    }
+
+    /// Opens the `DiskStore` in the given directory with the given configuration,
+    /// restoring the records it holds that have not expired.
+    ///
+    /// Entries of the log that cannot be decoded are skipped, as is an incomplete
+    /// entry at its end. A log whose entries cannot be told apart anymore is not
+    /// touched and an error of kind [`io::ErrorKind::InvalidData`] is returned.
+    ///
+    /// The directory is created if it does not exist.
+    pub fn open_with_config(local_id: PeerId, dir: impl AsRef<Path>, config: DiskStoreConfig)
+        -> io::Result<Self>
+    {
+        fs::create_dir_all(dir.as_ref())?;
+        let memory_config = MemoryStoreConfig {
+            max_records: config.max_records,
+            max_value_bytes: config.max_value_bytes,
+            max_provided_keys: config.max_provided_keys,
+            max_providers_per_key: config.max_providers_per_key,
+        };
+        let mut store = DiskStore {
+            local_key: kbucket::Key::new(local_id.clone()),
+            config,
+            memory: MemoryStore::with_config(local_id, memory_config),
+            path: dir.as_ref().join(LOG_FILE),
+            log: None,
+            log_entries: 0,
+            compacted_entries: 0,
+        };
+
+        let bytes = match fs::read(&store.path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let mut buf = &bytes[..];
+        while !buf.is_empty() {
+            match decode_entry(&mut buf)? {
+                Frame::Entry(entry) => store.replay(entry),
+                Frame::Invalid => {
+                    warn!("Skipping an invalid entry of the record log {:?}.", store.path);
+                }
+                Frame::Truncated => {
+                    // A write may have been interrupted, e.g. by a crash.
+                    warn!("Ignoring the truncated end of the record log {:?}.", store.path);
+                    break
+                }
+            }
+        }
+
+        store.compact()?;
+        Ok(store)
+    }
+
+    /// Applies an entry read from the log to the store.
+    fn replay(&mut self, entry: disk_proto::Entry) {
+        let key = Key::from(entry.key);
+        let expires = expiry_from_unix_millis(entry.expires);
+        match Kind::from_i32(entry.kind) {
+            Some(Kind::PutRecord) => match expires {
+                Some(expires) => {
+                    let record = Record {
+                        key,
+                        value: entry.value,
+                        publisher: PeerId::from_bytes(entry.peer).ok(),
+                        expires,
+                    };
+                    if let Err(e) = self.put(record) {
+                        warn!("Record not restored: {:?}", e);
+                    }
+                }
+                // The record expired, including any previous version of it.
+                None => self.remove(&key),
+            },
+            Some(Kind::RemoveRecord) => self.remove(&key),
+            Some(Kind::AddProvider) => {
+                let provider = match PeerId::from_bytes(entry.peer) {
+                    Ok(provider) => provider,
+                    Err(_) => {
+                        warn!("Ignoring provider record with an invalid provider.");
+                        return
+                    }
+                };
+                match expires {
+                    Some(expires) => {
+                        let record = ProviderRecord { key, provider, expires };
+                        if let Err(e) = self.add_provider(record) {
+                            warn!("Provider record not restored: {:?}", e);
+                        }
+                    }
+                    None => self.remove_provider(&key, &provider),
+                }
+            }
+            Some(Kind::RemoveProvider) => {
+                if let Ok(provider) = PeerId::from_bytes(entry.peer) {
+                    self.remove_provider(&key, &provider)
+                }
+            }
+            None => warn!("Ignoring record log entry of unknown kind {}.", entry.kind),
+        }
+    }
+
+    /// Rewrites the log with the (provider) records currently in the store
+    /// which have not expired.
+    fn compact(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        let mut buf = Vec::new();
+        let mut entries = 0;
+
+        for r in self.memory.records() {
+            if !r.is_expired(now) {
+                encode_entry(&record_entry(&r), &mut buf);
+                entries += 1;
+            }
+        }
+        for key in self.memory.provider_keys() {
+            for p in self.memory.providers(key) {
+                if !p.is_expired(now) {
+                    encode_entry(&provider_entry(Kind::AddProvider, &p), &mut buf);
+                    entries += 1;
+                }
+            }
+        }
+
+        // Replace the log atomically, so that it is never left half-written.
+        let tmp_path = self.path.with_extension("tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&buf)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.log = Some(OpenOptions::new().append(true).open(&self.path)?);
+        self.log_entries = entries;
+        self.compacted_entries = entries;
+        Ok(())
+    }
+
+    /// Appends an entry to the log, unless the store is still being loaded.
+    fn append(&mut self, entry: &disk_proto::Entry) -> io::Result<()> {
+        if let Some(log) = &mut self.log {
+            let mut buf = Vec::new();
+            encode_entry(entry, &mut buf);
+            log.write_all(&buf)?;
+            self.log_entries += 1;
+        }
+        Ok(())
+    }
+
+    /// Compacts the log if it has grown to twice its size after the last compaction.
+    fn compact_if_needed(&mut self) {
+        if self.log.is_some()
+            && self.log_entries >= 2 * cmp::max(self.compacted_entries, MIN_COMPACTION_ENTRIES)
+        {
+            if let Err(e) = self.compact() {
+                warn!("Failed to compact the record log {:?}: {:?}", self.path, e);
+            }
+        }
+    }
+
+    /// Makes room for a new record with the given key, evicting the expired
+    /// records or else the record farthest from the local node, if it is
+    /// farther than the new one.
+    fn evict_record(&mut self, key: &Key) -> Result<()> {
+        let now = Instant::now();
+        let mut evicted = self.memory.records()
+            .filter(|r| r.is_expired(now))
+            .map(|r| r.key.clone())
+            .collect::<Vec<_>>();
+
+        if evicted.is_empty() {
+            let local_key = &self.local_key;
+            let distance = |k: &Key| kbucket::Key::new(k.clone()).distance(local_key);
+            match self.memory.records().map(|r| r.key.clone()).max_by_key(|k| distance(k)) {
+                Some(farthest) if distance(&farthest) > distance(key) => evicted.push(farthest),
+                _ => return Err(Error::MaxRecords),
+            }
+        }
+
+        for k in evicted {
+            self.remove(&k);
+        }
+        Ok(())
+    }
+
+    /// Makes room for provider records for a new key, evicting the expired
+    /// provider records or else the provider records for the key farthest from
+    /// the local node, if it is farther than the new one. Keys provided by the
+    /// local node are never evicted.
+    fn evict_provider_key(&mut self, key: &Key) -> Result<()> {
+        let now = Instant::now();
+        let expired = self.memory.provider_keys()
+            .flat_map(|k| self.memory.providers(k))
+            .filter(|p| p.is_expired(now))
+            .collect::<Vec<_>>();
+        for p in expired {
+            self.remove_provider(&p.key, &p.provider);
+        }
+        if self.memory.num_provider_keys() < self.config.max_provided_keys {
+            return Ok(())
+        }
+
+        let local_key = &self.local_key;
+        let distance = |k: &Key| kbucket::Key::new(k.clone()).distance(local_key);
+        let farthest = self.memory.provider_keys()
+            .filter(|k| self.memory.providers(k).iter().all(|p| &p.provider != local_key.preimage()))
+            .max_by_key(|k| distance(k))
+            .cloned();
+        match farthest {
+            Some(farthest) if distance(&farthest) > distance(key) => {
+                for p in self.memory.providers(&farthest) {
+                    self.remove_provider(&p.key, &p.provider);
+                }
+                Ok(())
+            }
+            _ => Err(Error::MaxProvidedKeys),
+        }
+    }
+}
+
+impl<'a> RecordStore<'a> for DiskStore {
+    type RecordsIter = <MemoryStore as RecordStore<'a>>::RecordsIter;
+    type ProvidedIter = <MemoryStore as RecordStore<'a>>::ProvidedIter;
+
+    fn get(&'a self, k: &Key) -> Option<Cow<'_, Record>> {
+        self.memory.get(k)
+    }
+
+    fn put(&'a mut self, r: Record) -> Result<()> {
+        if r.value.len() >= self.config.max_value_bytes {
+            return Err(Error::ValueTooLarge)
+        }
+
+        if self.memory.get(&r.key).is_none()
+            && self.memory.num_records() >= self.config.max_records
+        {
+            self.evict_record(&r.key)?;
+        }
+
+        // Only log the record once the memory store accepted it, so that
+        // the log never replays a record the store refused.
+        let entry = record_entry(&r);
+        let previous = self.memory.get(&r.key).map(Cow::into_owned);
+        let key = r.key.clone();
+        self.memory.put(r)?;
+        if let Err(e) = self.append(&entry) {
+            // Keep the records in memory consistent with the log.
+            match previous {
+                Some(previous) => { let _ = self.memory.put(previous); }
+                None => self.memory.remove(&key),
+            }
+            return Err(Error::Io(e))
+        }
+        self.compact_if_needed();
+        Ok(())
+    }
+
+    fn remove(&'a mut self, k: &Key) {
+        if self.memory.get(k).is_none() {
+            return
+        }
+
+        let entry = disk_proto::Entry {
+            kind: Kind::RemoveRecord as i32,
+            key: k.to_vec(),
+            .. Default::default()
+        };
+        // Keep the record if its removal cannot be logged, as it would be
+        // restored from the log otherwise.
+        if let Err(e) = self.append(&entry) {
+            warn!("Failed to log the removal of record {:?}: {:?}", k, e);
+            return
+        }
+        self.memory.remove(k);
+        self.compact_if_needed();
+    }
+
+    fn records(&'a self) -> Self::RecordsIter {
+        self.memory.records()
+    }
+
+    fn add_provider(&'a mut self, record: ProviderRecord) -> Result<()> {
+        if self.memory.providers(&record.key).is_empty()
+            && self.memory.num_provider_keys() >= self.config.max_provided_keys
+        {
+            self.evict_provider_key(&record.key)?;
+        }
+
+        // Only log the provider record once the memory store accepted it,
+        // which it may also silently decline if the key has enough closer providers.
+        let entry = provider_entry(Kind::AddProvider, &record);
+        let previous = self.memory.providers(&record.key).into_iter()
+            .find(|p| p.provider == record.provider);
+        let (key, provider) = (record.key.clone(), record.provider.clone());
+        self.memory.add_provider(record)?;
+        if self.memory.providers(&key).iter().all(|p| p.provider != provider) {
+            return Ok(())
+        }
+        if let Err(e) = self.append(&entry) {
+            // Keep the provider records in memory consistent with the log.
+            match previous {
+                Some(previous) => { let _ = self.memory.add_provider(previous); }
+                None => self.memory.remove_provider(&key, &provider),
+            }
+            return Err(Error::Io(e))
+        }
+        self.compact_if_needed();
+        Ok(())
+    }
+
+    fn providers(&'a self, key: &Key) -> Vec<ProviderRecord> {
+        self.memory.providers(key)
+    }
+
+    fn provided(&'a self) -> Self::ProvidedIter {
+        self.memory.provided()
+    }
+
+    fn remove_provider(&'a mut self, key: &Key, provider: &PeerId) {
+        if self.memory.providers(key).iter().all(|p| &p.provider != provider) {
+            return
+        }
+
+        let record = ProviderRecord::new(key.clone(), provider.clone());
+        if let Err(e) = self.append(&provider_entry(Kind::RemoveProvider, &record)) {
+            warn!("Failed to log the removal of provider record {:?}: {:?}", record, e);
+            return
+        }
+        self.memory.remove_provider(key, provider);
+        self.compact_if_needed();
+    }
+}
+
+/// Builds the log entry storing the given record.
+fn record_entry(r: &Record) -> disk_proto::Entry {
+    disk_proto::Entry {
+        kind: Kind::PutRecord as i32,
+        key: r.key.to_vec(),
+        value: r.value.clone(),
+        peer: r.publisher.as_ref().map(|p| p.clone().into_bytes()).unwrap_or_default(),
+        expires: expiry_to_unix_millis(r.expires),
+    }
+}
+
+/// Builds the log entry adding or removing the given provider record.
+fn provider_entry(kind: Kind, r: &ProviderRecord) -> disk_proto::Entry {
+    disk_proto::Entry {
+        kind: kind as i32,
+        key: r.key.to_vec(),
+        value: Vec::new(),
+        peer: r.provider.clone().into_bytes(),
+        expires: expiry_to_unix_millis(r.expires),
+    }
+}
+
+/// Appends the length-prefixed encoding of an entry to `buf`.
+fn encode_entry(entry: &disk_proto::Entry, buf: &mut Vec<u8>) {
+    let mut len_buf = unsigned_varint::encode::usize_buffer();
+    buf.extend_from_slice(unsigned_varint::encode::usize(entry.encoded_len(), &mut len_buf));
+    entry.encode(buf).expect("Vec<u8> provides capacity as needed");
+}
+
+/// A length-prefixed entry read from the log.
+enum Frame {
+    /// A valid entry.
+    Entry(disk_proto::Entry),
+    /// A complete entry that cannot be decoded.
+    Invalid,
+    /// The log ends with an incomplete entry.
+    Truncated,
+}
+
+/// Decodes the next length-prefixed entry from `buf`, advancing it past the entry.
+///
+/// Returns an error if the length prefix is invalid, as the start of the
+/// following entries is unknown then.
+fn decode_entry(buf: &mut &[u8]) -> io::Result<Frame> {
+    let (len, rest) = match unsigned_varint::decode::usize(buf) {
+        Ok(decoded) => decoded,
+        Err(unsigned_varint::decode::Error::Insufficient) => return Ok(Frame::Truncated),
+        Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+    };
+    if rest.len() < len {
+        return Ok(Frame::Truncated)
+    }
+    *buf = &rest[len ..];
+    match disk_proto::Entry::decode(&rest[.. len]) {
+        Ok(entry) => Ok(Frame::Entry(entry)),
+        Err(_) => Ok(Frame::Invalid),
+    }
+}
+
+/// Converts an expiration time, as measured by the local monotonic clock,
+/// to milliseconds since the UNIX epoch, using 0 for no expiration.
+fn expiry_to_unix_millis(expires: Option<Instant>) -> u64 {
+    expires.map_or(0, |t| {
+        let now = Instant::now();
+        let remaining = if t > now { t - now } else { Duration::from_secs(0) };
+        (SystemTime::now() + remaining).duration_since(UNIX_EPOCH)
+            .map_or(1, |d| cmp::max(d.as_millis() as u64, 1))
+    })
+}
+
+/// Converts milliseconds since the UNIX epoch back to an expiration time as
+/// measured by the local monotonic clock.
+///
+/// Returns `None` if the expiration time has passed.
+fn expiry_from_unix_millis(millis: u64) -> Option<Option<Instant>> {
+    if millis == 0 {
+        return Some(None)
+    }
+    match (UNIX_EPOCH + Duration::from_millis(millis)).duration_since(SystemTime::now()) {
+        Ok(remaining) if remaining > Duration::from_secs(0) => Some(Some(Instant::now() + remaining)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multihash::{wrap, Code};
+    use quickcheck::*;
+    use rand::Rng;
+
+    fn random_multihash() -> Multihash {
+        wrap(Code::Sha2_256, &rand::thread_rng().gen::<[u8; 32]>())
+    }
+
+    #[test]
+    fn records_survive_reopening() {
+        fn prop(records: Vec<Record>, providers: Vec<ProviderRecord>) {
+            let dir = tempfile::tempdir().unwrap();
+            let id = PeerId::random();
+            let mut store = DiskStore::open(id.clone(), dir.path()).unwrap();
+            for r in &records {
+                assert!(store.put(r.clone()).is_ok());
+            }
+            for p in &providers {
+                assert!(store.add_provider(p.clone()).is_ok());
+            }
+            drop(store);
+
+            let now = Instant::now();
+            let store = DiskStore::open(id, dir.path()).unwrap();
+            for r in records.iter().filter(|r| !r.is_expired(now)) {
+                let stored = store.get(&r.key).expect("Record to be restored").into_owned();
+                assert_eq!(stored.value, r.value);
+                assert_eq!(stored.publisher, r.publisher);
+                assert_eq!(stored.expires.is_some(), r.expires.is_some());
+            }
+            for p in providers.iter().filter(|p| !p.is_expired(now)) {
+                assert!(store.providers(&p.key).iter().any(|s| s.provider == p.provider));
+            }
+        }
+        QuickCheck::new().tests(10).quickcheck(prop as fn(_,_))
+    }
+
+    #[test]
+    fn removals_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let id = PeerId::random();
+        let record = Record::new(random_multihash(), vec![1, 2, 3]);
+        let provider = ProviderRecord::new(random_multihash(), id.clone());
+
+        let mut store = DiskStore::open(id.clone(), dir.path()).unwrap();
+        store.put(record.clone()).unwrap();
+        store.add_provider(provider.clone()).unwrap();
+        store.remove(&record.key);
+        store.remove_provider(&provider.key, &provider.provider);
+        drop(store);
+
+        let store = DiskStore::open(id, dir.path()).unwrap();
+        assert!(store.get(&record.key).is_none());
+        assert!(store.providers(&provider.key).is_empty());
+        assert_eq!(store.provided().count(), 0);
+    }
+
+    #[test]
+    fn expired_records_dropped_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let id = PeerId::random();
+        let mut record = Record::new(random_multihash(), vec![1, 2, 3]);
+        let mut provider = ProviderRecord::new(random_multihash(), PeerId::random());
+
+        let mut store = DiskStore::open(id.clone(), dir.path()).unwrap();
+        store.put(record.clone()).unwrap();
+        store.add_provider(provider.clone()).unwrap();
+        // Updates which expire right away.
+        record.expires = Some(Instant::now());
+        provider.expires = Some(Instant::now());
+        store.put(record.clone()).unwrap();
+        store.add_provider(provider.clone()).unwrap();
+        drop(store);
+
+        let store = DiskStore::open(id, dir.path()).unwrap();
+        assert!(store.get(&record.key).is_none());
+        assert!(store.providers(&provider.key).is_empty());
+    }
+
+    #[test]
+    fn farthest_record_evicted_at_capacity() {
+        let dir = tempfile::tempdir().unwrap();
+        let id = PeerId::random();
+        let local_key = kbucket::Key::new(id.clone());
+        let config = DiskStoreConfig { max_records: 10, .. Default::default() };
+        let mut store = DiskStore::open_with_config(id, dir.path(), config).unwrap();
+
+        let mut records = (0 .. 11)
+            .map(|_| Record::new(random_multihash(), vec![1]))
+            .collect::<Vec<_>>();
+        records.sort_by_key(|r| kbucket::Key::new(r.key.clone()).distance(&local_key));
+        let farthest = records.pop().unwrap();
+
+        store.put(farthest.clone()).unwrap();
+        for r in &records[1 ..] {
+            store.put(r.clone()).unwrap();
+        }
+        // The store is full, so the farthest record makes room for a closer one.
+        store.put(records[0].clone()).unwrap();
+        assert!(store.get(&farthest.key).is_none());
+        assert_eq!(store.records().count(), 10);
+
+        // A record farther than all stored ones is rejected.
+        match store.put(farthest) {
+            Err(Error::MaxRecords) => {}
+            _ => panic!("Unexpected result"),
+        }
+    }
+
+    #[test]
+    fn declined_provider_not_logged() {
+        let dir = tempfile::tempdir().unwrap();
+        let id = PeerId::random();
+        let config = DiskStoreConfig { max_providers_per_key: 1, .. Default::default() };
+        let mut store = DiskStore::open_with_config(id.clone(), dir.path(), config.clone()).unwrap();
+
+        let key = random_multihash();
+        let target = kbucket::Key::new(Key::from(key.clone()));
+        let mut providers = (0 .. 2)
+            .map(|_| ProviderRecord::new(key.clone(), PeerId::random()))
+            .collect::<Vec<_>>();
+        providers.sort_by_key(|p| kbucket::Key::new(p.provider.clone()).distance(&target));
+
+        store.add_provider(providers[0].clone()).unwrap();
+        let log_entries = store.log_entries;
+        // The farther provider is declined by the memory store and thus not logged.
+        store.add_provider(providers[1].clone()).unwrap();
+        assert_eq!(store.log_entries, log_entries);
+        drop(store);
+
+        let store = DiskStore::open_with_config(id, dir.path(), config).unwrap();
+        assert_eq!(store.providers(&Key::from(key)), vec![providers[0].clone()]);
+    }
+
+    #[test]
+    fn invalid_entry_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let id = PeerId::random();
+        let mut store = DiskStore::open(id.clone(), dir.path()).unwrap();
+        let records = (0 .. 3)
+            .map(|i| Record::new(random_multihash(), vec![i]))
+            .collect::<Vec<_>>();
+        for r in &records {
+            store.put(r.clone()).unwrap();
+        }
+        drop(store);
+
+        // Corrupt the entry in the middle of the log, keeping its length.
+        let path = dir.path().join(LOG_FILE);
+        let mut bytes = fs::read(&path).unwrap();
+        let (len, rest) = unsigned_varint::decode::usize(&bytes).unwrap();
+        let start = bytes.len() - rest.len() + len;
+        let (len, rest) = unsigned_varint::decode::usize(&bytes[start ..]).unwrap();
+        let body = bytes.len() - rest.len();
+        for b in &mut bytes[body .. body + len] {
+            *b = 0xff;
+        }
+        fs::write(&path, &bytes).unwrap();
+
+        for _ in 0 .. 2 {
+            let store = DiskStore::open(id.clone(), dir.path()).unwrap();
+            assert_eq!(store.get(&records[0].key).unwrap().into_owned(), records[0]);
+            assert!(store.get(&records[1].key).is_none());
+            assert_eq!(store.get(&records[2].key).unwrap().into_owned(), records[2]);
+        }
+
+        // The entries cannot be told apart with an invalid length prefix.
+        fs::write(&path, &[0xff; 11]).unwrap();
+        let error = DiskStore::open(id, dir.path()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(&path).unwrap(), vec![0xff; 11]);
+    }
+
+    #[test]
+    fn log_compacted() {
+        let dir = tempfile::tempdir().unwrap();
+        let id = PeerId::random();
+        let mut store = DiskStore::open(id.clone(), dir.path()).unwrap();
+        let record = Record::new(random_multihash(), vec![1, 2, 3]);
+        for _ in 0 .. 10 * MIN_COMPACTION_ENTRIES {
+            store.put(record.clone()).unwrap();
+        }
+        assert!(store.log_entries < 2 * MIN_COMPACTION_ENTRIES);
+        drop(store);
+
+        let store = DiskStore::open(id, dir.path()).unwrap();
+        assert_eq!(store.log_entries, 1);
+        assert_eq!(store.get(&record.key).unwrap().into_owned(), record);
+    }
+}
@@ -93,6 +93,21 @@ impl MemoryStore {
     {
         self.records.retain(f);
     }
+
+    /// Gets the number of stored (regular) records.
+    pub(super) fn num_records(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Gets the number of keys for which provider records are stored.
+    pub(super) fn num_provider_keys(&self) -> usize {
+        self.providers.len()
+    }
+
+    /// Gets an iterator over the keys for which provider records are stored.
+    pub(super) fn provider_keys(&self) -> hash_map::Keys<'_, Key, SmallVec<[ProviderRecord; K_VALUE.get()]>> {
+        self.providers.keys()
+    }
 }
 
 impl<'a> RecordStore<'a> for MemoryStore {
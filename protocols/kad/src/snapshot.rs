@@ -0,0 +1,156 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Serialisable snapshots of the Kademlia routing table.
+//!
+//! A snapshot taken via [`Kademlia::routing_table_snapshot`](crate::Kademlia::routing_table_snapshot)
+//! can be persisted with [`RoutingTableSnapshot::to_bytes`] and, after a restart,
+//! be restored with [`RoutingTableSnapshot::from_bytes`] and
+//! [`Kademlia::import_routing_table`](crate::Kademlia::import_routing_table).
+
+use crate::kbucket::NodeStatus;
+use libp2p_core::{Multiaddr, PeerId};
+use log::debug;
+use prost::Message;
+use std::convert::TryFrom;
+use std::io;
+use std::time::Duration;
+use wasm_timer::{SystemTime, UNIX_EPOCH};
+
+mod snapshot_proto {
+    include!(concat!(env!("OUT_DIR"), "/snapshot.pb.rs"));
+}
+
+/// A snapshot of the routing table.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoutingTableSnapshot {
+    /// The entries of the routing table, ordered by bucket and, within
+    /// a bucket, from the least-recently to the most-recently connected peer.
+    pub entries: Vec<RoutingTableEntry>,
+}
+
+/// An entry of a [`RoutingTableSnapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingTableEntry {
+    /// The ID of the peer.
+    pub peer: PeerId,
+    /// The known addresses of the peer.
+    pub addresses: Vec<Multiaddr>,
+    /// The connection status of the peer when the snapshot was taken.
+    pub status: NodeStatus,
+    /// When the local node was last connected to the peer, if known.
+    ///
+    /// For peers that were connected when the snapshot was taken,
+    /// this is the time of the snapshot.
+    pub last_seen: Option<SystemTime>,
+}
+
+impl RoutingTableSnapshot {
+    /// Encodes the snapshot.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let table = snapshot_proto::RoutingTable {
+            peers: self.entries.iter().map(|e| snapshot_proto::Peer {
+                id: e.peer.clone().into_bytes(),
+                addrs: e.addresses.iter().map(|a| a.to_vec()).collect(),
+                connected: e.status == NodeStatus::Connected,
+                last_seen: e.last_seen.map_or(0, to_unix_millis),
+            }).collect(),
+        };
+        let mut buf = Vec::with_capacity(table.encoded_len());
+        table.encode(&mut buf).expect("Vec<u8> provides capacity as needed");
+        buf
+    }
+
+    /// Decodes a snapshot previously encoded with [`RoutingTableSnapshot::to_bytes`].
+    ///
+    /// Entries with a `last_seen` time that is not representable on the
+    /// current platform are dropped.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, io::Error> {
+        let table = snapshot_proto::RoutingTable::decode(bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut entries = Vec::with_capacity(table.peers.len());
+        for p in table.peers {
+            let peer = PeerId::from_bytes(p.id)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid peer ID"))?;
+            let addresses = p.addrs.into_iter()
+                .map(|a| Multiaddr::try_from(a)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+                .collect::<Result<_, _>>()?;
+            let last_seen = if p.last_seen == 0 {
+                None
+            } else {
+                match UNIX_EPOCH.checked_add(Duration::from_millis(p.last_seen)) {
+                    Some(t) => Some(t),
+                    None => {
+                        debug!("Ignoring snapshot entry with invalid last seen time: {}", peer);
+                        continue
+                    }
+                }
+            };
+            let status = if p.connected { NodeStatus::Connected } else { NodeStatus::Disconnected };
+            entries.push(RoutingTableEntry { peer, addresses, status, last_seen });
+        }
+        Ok(RoutingTableSnapshot { entries })
+    }
+}
+
+fn to_unix_millis(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_core::multiaddr::Protocol;
+
+    #[test]
+    fn encode_decode() {
+        let entry = |status, last_seen| RoutingTableEntry {
+            peer: PeerId::random(),
+            addresses: vec![Protocol::Memory(1).into(), Protocol::Memory(2).into()],
+            status,
+            last_seen,
+        };
+        let snapshot = RoutingTableSnapshot {
+            entries: vec![
+                entry(NodeStatus::Connected, Some(UNIX_EPOCH + Duration::from_millis(1_600_000_000_000))),
+                entry(NodeStatus::Disconnected, Some(UNIX_EPOCH + Duration::from_millis(1_500_000_000_000))),
+                entry(NodeStatus::Disconnected, None),
+            ]
+        };
+        let decoded = RoutingTableSnapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+        assert_eq!(snapshot, decoded);
+    }
+
+    #[test]
+    fn decode_invalid() {
+        let table = snapshot_proto::RoutingTable {
+            peers: vec![snapshot_proto::Peer {
+                id: vec![1, 2, 3],
+                addrs: vec![],
+                connected: false,
+                last_seen: 0,
+            }]
+        };
+        let mut buf = Vec::new();
+        table.encode(&mut buf).unwrap();
+        assert!(RoutingTableSnapshot::from_bytes(&buf).is_err());
+    }
+}
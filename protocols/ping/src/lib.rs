@@ -32,7 +32,8 @@
 //! the connection will be closed.
 //!
 //! The `Ping` network behaviour produces [`PingEvent`]s, which may be consumed from the `Swarm`
-//! by an application, e.g. to collect statistics.
+//! by an application, e.g. to collect statistics. The round-trip times are recorded as the
+//! latencies of the peers in the `PeerStore` of the `Swarm`.
 //!
 //! > **Note**: The ping protocol does not keep otherwise idle connections alive,
 //! > it only adds an additional condition for terminating the connection, namely
@@ -108,10 +109,13 @@ impl NetworkBehaviour for Ping {
         self.events.push_front(PingEvent { peer, result })
     }
 
-    fn poll(&mut self, _: &mut Context<'_>, _: &mut impl PollParameters)
+    fn poll(&mut self, _: &mut Context<'_>, params: &mut impl PollParameters)
         -> Poll<NetworkBehaviourAction<Void, PingEvent>>
     {
         if let Some(e) = self.events.pop_back() {
+            if let Ok(PingSuccess::Ping { rtt }) = &e.result {
+                params.peer_store_mut().set_latency(&e.peer, *rtt);
+            }
             Poll::Ready(NetworkBehaviourAction::GenerateEvent(e))
         } else {
             Poll::Pending
@@ -30,7 +30,7 @@ use libp2p_core::{
 };
 use libp2p_ping::*;
 use libp2p_secio::SecioConfig;
-use libp2p_swarm::Swarm;
+use libp2p_swarm::{Swarm, SwarmEvent};
 use libp2p_tcp::TcpConfig;
 use futures::{prelude::*, channel::mpsc};
 use std::{io, time::Duration};
@@ -61,6 +61,8 @@ fn ping() {
         loop {
             match swarm1.next().await {
                 PingEvent { peer, result: Ok(PingSuccess::Ping { rtt }) } => {
+                    let latency = Swarm::peer_store(&swarm1).peer(&peer).and_then(|r| r.latency());
+                    assert_eq!(latency, Some(rtt));
                     return (pid1.clone(), peer, rtt)
                 },
                 _ => {}
@@ -75,6 +77,8 @@ fn ping() {
         loop {
             match swarm2.next().await {
                 PingEvent { peer, result: Ok(PingSuccess::Ping { rtt }) } => {
+                    let latency = Swarm::peer_store(&swarm2).peer(&peer).and_then(|r| r.latency());
+                    assert_eq!(latency, Some(rtt));
                     return (pid2.clone(), peer, rtt)
                 },
                 _ => {}
@@ -88,6 +92,69 @@ fn ping() {
     assert!(rtt < Duration::from_millis(50));
 }
 
+#[test]
+fn ping_bandwidth() {
+    let cfg = PingConfig::new().with_keep_alive(true);
+
+    let (peer1_id, trans) = mk_transport();
+    let mut swarm1 = Swarm::new(trans, Ping::new(cfg.clone()), peer1_id.clone());
+
+    let (peer2_id, trans) = mk_transport();
+    let mut swarm2 = Swarm::new(trans, Ping::new(cfg), peer2_id.clone());
+
+    let addr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+    Swarm::listen_on(&mut swarm1, addr).unwrap();
+    let addr = async_std::task::block_on(async {
+        loop {
+            if let SwarmEvent::NewListenAddr(addr) = swarm1.next_event().await {
+                return addr
+            }
+        }
+    });
+
+    let peer1 = async move {
+        loop {
+            swarm1.next().await;
+        }
+    };
+
+    let peer2 = async move {
+        Swarm::dial_addr(&mut swarm2, addr).unwrap();
+
+        // Either peer may ping first, both ways move the payload in both directions.
+        loop {
+            let event = swarm2.next_event().await;
+            if let SwarmEvent::Behaviour(PingEvent { peer, result: Ok(_) }) = event {
+                assert_eq!(peer, peer1_id);
+                break
+            }
+        }
+
+        // The ping payload has been sent and echoed back on a substream that may
+        // still be open.
+        let stats = Swarm::protocol_stats(&swarm2);
+        let substreams = stats.get("/ipfs/ping/1.0.0");
+        assert!(substreams.inbound + substreams.outbound >= 1);
+        let bytes = stats.peer_bandwidth(&peer1_id).get("/ipfs/ping/1.0.0");
+        assert!(bytes.sent >= 32 && bytes.received >= 32);
+        assert_eq!(stats.bandwidth().total(), stats.peer_bandwidth(&peer1_id).total());
+
+        // The bytes are reported once the connection is closed.
+        Swarm::ban_peer_id(&mut swarm2, peer1_id.clone());
+        loop {
+            if let SwarmEvent::ConnectionClosed { bandwidth, .. } = swarm2.next_event().await {
+                assert!(bandwidth.get("/ipfs/ping/1.0.0").sent >= 32);
+                break
+            }
+        }
+        let stats = Swarm::protocol_stats(&swarm2);
+        assert_eq!(stats.peer_bandwidth(&peer1_id).total(), Default::default());
+        assert!(stats.bandwidth().get("/ipfs/ping/1.0.0").sent >= 32);
+    };
+
+    async_std::task::block_on(future::select(Box::pin(peer1), Box::pin(peer2)));
+}
+
 fn mk_transport() -> (
     PeerId,
     Boxed<
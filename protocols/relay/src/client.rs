@@ -0,0 +1,371 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The [`Client`] behaviour, making reservations with and establishing
+//! circuits via relays.
+
+pub mod handler;
+pub mod transport;
+
+use crate::protocol::Limit;
+use futures::{channel::{mpsc, oneshot}, prelude::*};
+use handler::{ClientHandlerEvent, ClientHandlerIn, Handler};
+use libp2p_core::{ConnectedPoint, Multiaddr, PeerId, connection::ConnectionId};
+use libp2p_swarm::{
+    DialPeerCondition,
+    NetworkBehaviour,
+    NetworkBehaviourAction,
+    NotifyHandler,
+    PollParameters
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    task::{Context, Poll}
+};
+use transport::{ClientTransport, Connection, RelayError, ToListenerMsg, TransportToBehaviourMsg};
+
+/// Event emitted by the [`Client`] behaviour.
+#[derive(Debug)]
+pub enum ClientEvent {
+    /// A reservation with a relay has been accepted.
+    ReservationReqAccepted {
+        /// The relay the reservation was made with.
+        relay_peer_id: PeerId,
+        /// Whether the reservation renewed an existing one.
+        renewal: bool,
+    },
+    /// A reservation with a relay failed.
+    ReservationReqFailed {
+        /// The relay the reservation was requested from.
+        relay_peer_id: PeerId,
+        /// Whether the reservation was a renewal of an existing one.
+        renewal: bool,
+    },
+    /// A circuit to a remote peer has been established via a relay.
+    OutboundCircuitEstablished {
+        /// The relay of the circuit.
+        relay_peer_id: PeerId,
+        /// The limits of the circuit.
+        limit: Limit,
+    },
+    /// A circuit to a remote peer could not be established.
+    OutboundCircuitReqFailed {
+        /// The relay the circuit was requested from.
+        relay_peer_id: PeerId,
+    },
+    /// A remote peer established a circuit to the local node via a relay.
+    InboundCircuitEstablished {
+        /// The peer on the other end of the circuit.
+        src_peer_id: PeerId,
+        /// The limits of the circuit.
+        limit: Limit,
+    },
+}
+
+/// A `NetworkBehaviour` acting as a client of relays speaking the circuit
+/// relay v2 protocol.
+///
+/// The behaviour is driven by the [`ClientTransport`] it is created
+/// together with, see [`Client::new_transport_and_behaviour`]. Listening
+/// on a relayed address via the transport makes a reservation with the
+/// relay, dialing a relayed address establishes a circuit.
+pub struct Client {
+    /// Requests of the [`ClientTransport`].
+    from_transport: mpsc::UnboundedReceiver<TransportToBehaviourMsg>,
+    /// Addresses of the relays with a listener or a dial in progress.
+    relay_addrs: HashMap<PeerId, Multiaddr>,
+    /// Established connections to relays.
+    connections: HashMap<PeerId, Vec<ConnectionId>>,
+    /// Listeners on relayed addresses, by relay.
+    listeners: HashMap<PeerId, Listener>,
+    /// Dial requests waiting for a connection to their relay.
+    pending_dials: HashMap<PeerId, Vec<(u64, PeerId)>>,
+    /// Dial requests sent to a relay, waiting for the outcome.
+    dials: HashMap<u64, Dial>,
+    /// Actions to yield on the next call to `poll`.
+    queued_actions: VecDeque<NetworkBehaviourAction<ClientHandlerIn, ClientEvent>>,
+    next_request_id: u64,
+}
+
+struct Listener {
+    /// The connection the reservation is made on, once connected.
+    connection: Option<ConnectionId>,
+    to_listener: mpsc::UnboundedSender<ToListenerMsg>,
+}
+
+struct Dial {
+    relay_peer_id: PeerId,
+    /// The connection the circuit is requested on, once connected.
+    connection: Option<ConnectionId>,
+    send_back: oneshot::Sender<Result<Connection, RelayError>>,
+}
+
+impl Client {
+    /// Creates a new [`ClientTransport`] and the [`Client`] behaviour
+    /// serving its requests.
+    pub fn new_transport_and_behaviour() -> (ClientTransport, Client) {
+        let (to_behaviour, from_transport) = mpsc::unbounded();
+        let behaviour = Client {
+            from_transport,
+            relay_addrs: HashMap::new(),
+            connections: HashMap::new(),
+            listeners: HashMap::new(),
+            pending_dials: HashMap::new(),
+            dials: HashMap::new(),
+            queued_actions: VecDeque::new(),
+            next_request_id: 0,
+        };
+        (ClientTransport::new(to_behaviour), behaviour)
+    }
+
+    fn dial_relay(&mut self, relay_peer_id: PeerId) {
+        self.queued_actions.push_back(NetworkBehaviourAction::DialPeer {
+            peer_id: relay_peer_id,
+            condition: DialPeerCondition::Disconnected,
+        });
+    }
+
+    /// Forgets the address of a relay that is no longer needed by any
+    /// listener or dial.
+    fn remove_unused_relay_addr(&mut self, relay_peer_id: &PeerId) {
+        let in_use = self.listeners.contains_key(relay_peer_id)
+            || self.dials.values().any(|d| d.relay_peer_id == *relay_peer_id);
+        if !in_use {
+            self.relay_addrs.remove(relay_peer_id);
+        }
+    }
+
+    /// Removes the dials whose [`ClientTransport`] dial future has been
+    /// dropped.
+    fn remove_cancelled_dials(&mut self, cx: &mut Context<'_>) {
+        let cancelled = self.dials.iter_mut()
+            .filter_map(|(id, d)| match d.send_back.poll_canceled(cx) {
+                Poll::Ready(()) => Some(*id),
+                Poll::Pending => None,
+            })
+            .collect::<Vec<_>>();
+        for request_id in cancelled {
+            if let Some(dial) = self.dials.remove(&request_id) {
+                if let Some(pending) = self.pending_dials.get_mut(&dial.relay_peer_id) {
+                    pending.retain(|(id, _)| *id != request_id);
+                    if pending.is_empty() {
+                        self.pending_dials.remove(&dial.relay_peer_id);
+                    }
+                }
+                self.remove_unused_relay_addr(&dial.relay_peer_id);
+            }
+        }
+    }
+
+    fn on_transport_msg(&mut self, msg: TransportToBehaviourMsg) {
+        match msg {
+            TransportToBehaviourMsg::ListenReq { relay_peer_id, relay_addr, to_listener } => {
+                // A listener that has been dropped no longer blocks new ones.
+                if self.listeners.get(&relay_peer_id).map_or(false, |l| l.to_listener.is_closed()) {
+                    self.listeners.remove(&relay_peer_id);
+                }
+                if self.listeners.contains_key(&relay_peer_id) {
+                    let _ = to_listener.unbounded_send(
+                        ToListenerMsg::Reservation(Err(RelayError::AlreadyListening)));
+                    return
+                }
+                self.relay_addrs.insert(relay_peer_id.clone(), relay_addr);
+                let connection = self.connections.get(&relay_peer_id)
+                    .and_then(|c| c.first().copied());
+                match connection {
+                    Some(connection) => {
+                        self.queued_actions.push_back(NetworkBehaviourAction::NotifyHandler {
+                            peer_id: relay_peer_id.clone(),
+                            handler: NotifyHandler::One(connection),
+                            event: ClientHandlerIn::Reserve,
+                        });
+                    }
+                    None => self.dial_relay(relay_peer_id.clone()),
+                }
+                self.listeners.insert(relay_peer_id, Listener { connection, to_listener });
+            }
+            TransportToBehaviourMsg::DialReq { relay_peer_id, relay_addr, dst_peer_id, send_back } => {
+                self.relay_addrs.insert(relay_peer_id.clone(), relay_addr);
+                let request_id = self.next_request_id;
+                self.next_request_id += 1;
+                let connection = self.connections.get(&relay_peer_id)
+                    .and_then(|c| c.first().copied());
+                match connection {
+                    Some(connection) => {
+                        self.queued_actions.push_back(NetworkBehaviourAction::NotifyHandler {
+                            peer_id: relay_peer_id.clone(),
+                            handler: NotifyHandler::One(connection),
+                            event: ClientHandlerIn::EstablishCircuit { request_id, dst_peer_id },
+                        });
+                    }
+                    None => {
+                        self.pending_dials.entry(relay_peer_id.clone())
+                            .or_default()
+                            .push((request_id, dst_peer_id));
+                        self.dial_relay(relay_peer_id.clone());
+                    }
+                }
+                self.dials.insert(request_id, Dial { relay_peer_id, connection, send_back });
+            }
+        }
+    }
+}
+
+impl NetworkBehaviour for Client {
+    type ProtocolsHandler = Handler;
+    type OutEvent = ClientEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        Handler::new()
+    }
+
+    fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        self.relay_addrs.get(peer_id).cloned().into_iter().collect()
+    }
+
+    fn inject_connected(&mut self, _: &PeerId) {}
+
+    fn inject_disconnected(&mut self, _: &PeerId) {}
+
+    fn inject_connection_established(&mut self, peer_id: &PeerId, connection: &ConnectionId, _: &ConnectedPoint) {
+        self.connections.entry(peer_id.clone()).or_default().push(*connection);
+
+        if let Some(listener) = self.listeners.get_mut(peer_id) {
+            if listener.connection.is_none() {
+                listener.connection = Some(*connection);
+                self.queued_actions.push_back(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: peer_id.clone(),
+                    handler: NotifyHandler::One(*connection),
+                    event: ClientHandlerIn::Reserve,
+                });
+            }
+        }
+
+        for (request_id, dst_peer_id) in self.pending_dials.remove(peer_id).unwrap_or_default() {
+            if let Some(dial) = self.dials.get_mut(&request_id) {
+                dial.connection = Some(*connection);
+            }
+            self.queued_actions.push_back(NetworkBehaviourAction::NotifyHandler {
+                peer_id: peer_id.clone(),
+                handler: NotifyHandler::One(*connection),
+                event: ClientHandlerIn::EstablishCircuit { request_id, dst_peer_id },
+            });
+        }
+    }
+
+    fn inject_connection_closed(&mut self, peer_id: &PeerId, connection: &ConnectionId, _: &ConnectedPoint) {
+        if let Some(connections) = self.connections.get_mut(peer_id) {
+            connections.retain(|c| c != connection);
+            if connections.is_empty() {
+                self.connections.remove(peer_id);
+            }
+        }
+
+        // Dropping the sender closes the listener.
+        if self.listeners.get(peer_id).map_or(false, |l| l.connection == Some(*connection)) {
+            self.listeners.remove(peer_id);
+        }
+
+        let closed = self.dials.iter()
+            .filter(|(_, d)| d.relay_peer_id == *peer_id && d.connection == Some(*connection))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for request_id in closed {
+            if let Some(dial) = self.dials.remove(&request_id) {
+                let _ = dial.send_back.send(Err(RelayError::ConnectionClosed));
+            }
+        }
+        self.remove_unused_relay_addr(peer_id);
+    }
+
+    fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+        if let Some(listener) = self.listeners.remove(peer_id) {
+            let _ = listener.to_listener.unbounded_send(
+                ToListenerMsg::Reservation(Err(RelayError::DialFailed)));
+        }
+        for (request_id, _) in self.pending_dials.remove(peer_id).unwrap_or_default() {
+            if let Some(dial) = self.dials.remove(&request_id) {
+                let _ = dial.send_back.send(Err(RelayError::DialFailed));
+            }
+        }
+        self.remove_unused_relay_addr(peer_id);
+    }
+
+    fn inject_event(&mut self, relay_peer_id: PeerId, _: ConnectionId, event: ClientHandlerEvent) {
+        let event = match event {
+            ClientHandlerEvent::ReservationReqAccepted { renewal, .. } => {
+                if !renewal {
+                    if let Some(listener) = self.listeners.get(&relay_peer_id) {
+                        let _ = listener.to_listener.unbounded_send(ToListenerMsg::Reservation(Ok(())));
+                    }
+                }
+                ClientEvent::ReservationReqAccepted { relay_peer_id, renewal }
+            }
+            ClientHandlerEvent::ReservationReqFailed { renewal, error } => {
+                if let Some(listener) = self.listeners.remove(&relay_peer_id) {
+                    let _ = listener.to_listener.unbounded_send(
+                        ToListenerMsg::Reservation(Err(RelayError::Outbound(error.to_string()))));
+                }
+                self.remove_unused_relay_addr(&relay_peer_id);
+                ClientEvent::ReservationReqFailed { relay_peer_id, renewal }
+            }
+            ClientHandlerEvent::OutboundCircuitEstablished { request_id, substream, limit, alive } => {
+                if let Some(dial) = self.dials.remove(&request_id) {
+                    let _ = dial.send_back.send(Ok(Connection::new(substream, limit, alive)));
+                }
+                self.remove_unused_relay_addr(&relay_peer_id);
+                ClientEvent::OutboundCircuitEstablished { relay_peer_id, limit }
+            }
+            ClientHandlerEvent::OutboundCircuitReqFailed { request_id, error } => {
+                if let Some(dial) = self.dials.remove(&request_id) {
+                    let _ = dial.send_back.send(Err(RelayError::Outbound(error.to_string())));
+                }
+                self.remove_unused_relay_addr(&relay_peer_id);
+                ClientEvent::OutboundCircuitReqFailed { relay_peer_id }
+            }
+            ClientHandlerEvent::InboundCircuitEstablished { src_peer_id, substream, limit, alive } => {
+                if let Some(listener) = self.listeners.get(&relay_peer_id) {
+                    let _ = listener.to_listener.unbounded_send(ToListenerMsg::IncomingRelayedConnection {
+                        connection: Box::new(Connection::new(substream, limit, alive)),
+                        src_peer_id: src_peer_id.clone(),
+                    });
+                }
+                ClientEvent::InboundCircuitEstablished { src_peer_id, limit }
+            }
+        };
+        self.queued_actions.push_back(NetworkBehaviourAction::GenerateEvent(event));
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>, _: &mut impl PollParameters)
+        -> Poll<NetworkBehaviourAction<ClientHandlerIn, ClientEvent>>
+    {
+        while let Poll::Ready(Some(msg)) = self.from_transport.poll_next_unpin(cx) {
+            self.on_transport_msg(msg);
+        }
+
+        self.remove_cancelled_dials(cx);
+
+        if let Some(action) = self.queued_actions.pop_front() {
+            return Poll::Ready(action)
+        }
+
+        Poll::Pending
+    }
+}
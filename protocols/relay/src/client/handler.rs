@@ -0,0 +1,329 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The [`ProtocolsHandler`] of the [`Client`](crate::Client) behaviour.
+
+use crate::protocol::{self, inbound_stop, outbound_hop, Limit, OutboundError, ProtocolError, Status};
+use futures::{future::BoxFuture, prelude::*, stream::FuturesUnordered};
+use libp2p_core::{Multiaddr, PeerId};
+use libp2p_swarm::{
+    KeepAlive,
+    NegotiatedSubstream,
+    ProtocolsHandler,
+    ProtocolsHandlerEvent,
+    ProtocolsHandlerUpgrErr,
+    SubstreamProtocol
+};
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration
+};
+use wasm_timer::{Delay, Instant};
+
+/// The time an idle connection, i.e. one without an active reservation
+/// or circuit, is kept alive.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The share of the reservation duration after which the reservation is
+/// renewed.
+const RENEWAL_FACTOR: f64 = 0.75;
+
+/// Event sent from the [`Client`](crate::Client) behaviour to a [`Handler`].
+#[derive(Debug, Clone)]
+pub enum ClientHandlerIn {
+    /// Make a reservation with the relay, keeping it renewed as long as the
+    /// connection is alive.
+    Reserve,
+    /// Establish a circuit to `dst_peer_id` via the relay.
+    EstablishCircuit {
+        /// The ID of the dial request.
+        request_id: u64,
+        /// The peer to connect to.
+        dst_peer_id: PeerId,
+    },
+}
+
+/// Event produced by a [`Handler`] for the [`Client`](crate::Client) behaviour.
+#[derive(Debug)]
+pub enum ClientHandlerEvent {
+    /// The relay accepted a reservation request.
+    ReservationReqAccepted {
+        /// Whether the reservation is a renewal of an existing one.
+        renewal: bool,
+        /// The addresses of the relay the reservation applies to.
+        addrs: Vec<Multiaddr>,
+    },
+    /// The relay denied a reservation request or the request failed.
+    ReservationReqFailed {
+        /// Whether the reservation was a renewal of an existing one.
+        renewal: bool,
+        /// The reason of the failure.
+        error: ProtocolsHandlerUpgrErr<OutboundError>,
+    },
+    /// A circuit to the requested destination has been established.
+    OutboundCircuitEstablished {
+        /// The ID of the dial request.
+        request_id: u64,
+        /// The substream to the destination.
+        substream: NegotiatedSubstream,
+        /// The limits of the circuit.
+        limit: Limit,
+        /// Keeps the connection to the relay alive while held.
+        alive: Arc<()>,
+    },
+    /// A circuit to the requested destination could not be established.
+    OutboundCircuitReqFailed {
+        /// The ID of the dial request.
+        request_id: u64,
+        /// The reason of the failure.
+        error: ProtocolsHandlerUpgrErr<OutboundError>,
+    },
+    /// A remote established a circuit to the local node via the relay.
+    InboundCircuitEstablished {
+        /// The peer on the other end of the circuit.
+        src_peer_id: PeerId,
+        /// The substream to the source.
+        substream: NegotiatedSubstream,
+        /// The limits of the circuit.
+        limit: Limit,
+        /// Keeps the connection to the relay alive while held.
+        alive: Arc<()>,
+    },
+}
+
+/// Context of an outbound hop request.
+#[derive(Debug)]
+pub enum OutboundOpenInfo {
+    Reserve { renewal: bool },
+    Connect { request_id: u64 },
+}
+
+/// Protocol handler of a relay client for a single connection to a relay.
+pub struct Handler {
+    /// Events to yield on the next call to `poll`.
+    queued_events: VecDeque<ProtocolsHandlerEvent<
+        outbound_hop::Upgrade,
+        OutboundOpenInfo,
+        ClientHandlerEvent,
+        void::Void,
+    >>,
+    /// State of the reservation with the relay.
+    reservation: Reservation,
+    /// Inbound circuits being accepted.
+    pending_inbound: FuturesUnordered<BoxFuture<'static, Option<ClientHandlerEvent>>>,
+    /// Tokens of circuits using this connection. A circuit is alive as long
+    /// as its token has other owners.
+    circuits: Vec<Arc<()>>,
+    /// The moment this connection is considered idle.
+    idle_deadline: Instant,
+}
+
+enum Reservation {
+    /// No reservation has been requested.
+    None,
+    /// A reservation has been requested.
+    Requested,
+    /// A reservation is active and will be renewed once the timer fires.
+    Accepted { renew: Delay },
+}
+
+impl Handler {
+    pub(crate) fn new() -> Self {
+        Handler {
+            queued_events: VecDeque::new(),
+            reservation: Reservation::None,
+            pending_inbound: FuturesUnordered::new(),
+            circuits: Vec::new(),
+            idle_deadline: Instant::now() + IDLE_TIMEOUT,
+        }
+    }
+
+    fn new_circuit_token(&mut self) -> Arc<()> {
+        let token = Arc::new(());
+        self.circuits.push(token.clone());
+        token
+    }
+
+    fn request_reservation(&mut self, renewal: bool) {
+        self.reservation = Reservation::Requested;
+        self.queued_events.push_back(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+            protocol: SubstreamProtocol::new(outbound_hop::Upgrade::Reserve),
+            info: OutboundOpenInfo::Reserve { renewal },
+        });
+    }
+}
+
+impl ProtocolsHandler for Handler {
+    type InEvent = ClientHandlerIn;
+    type OutEvent = ClientHandlerEvent;
+    type Error = void::Void;
+    type InboundProtocol = inbound_stop::Upgrade;
+    type OutboundProtocol = outbound_hop::Upgrade;
+    type OutboundOpenInfo = OutboundOpenInfo;
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol> {
+        SubstreamProtocol::new(inbound_stop::Upgrade)
+    }
+
+    fn inject_fully_negotiated_inbound(&mut self, circuit: inbound_stop::Circuit) {
+        self.idle_deadline = Instant::now() + IDLE_TIMEOUT;
+        if let Reservation::None = self.reservation {
+            self.pending_inbound.push(async move {
+                if let Err(e) = circuit.deny(Status::NoReservation).await {
+                    log::debug!("Failed to deny inbound circuit: {:?}", e);
+                }
+                None
+            }.boxed());
+            return
+        }
+
+        let alive = self.new_circuit_token();
+        self.pending_inbound.push(async move {
+            let src_peer_id = circuit.src_peer_id().clone();
+            let limit = circuit.limit();
+            match circuit.accept().await {
+                Ok(substream) => Some(ClientHandlerEvent::InboundCircuitEstablished {
+                    src_peer_id,
+                    substream,
+                    limit,
+                    alive,
+                }),
+                Err(e) => {
+                    log::debug!("Failed to accept inbound circuit: {:?}", e);
+                    None
+                }
+            }
+        }.boxed());
+    }
+
+    fn inject_fully_negotiated_outbound(&mut self, output: outbound_hop::Output, info: OutboundOpenInfo) {
+        self.idle_deadline = Instant::now() + IDLE_TIMEOUT;
+        match (output, info) {
+            (outbound_hop::Output::Reservation { expires_in, addrs, .. }, OutboundOpenInfo::Reserve { renewal }) => {
+                let renew_in = expires_in.mul_f64(RENEWAL_FACTOR);
+                self.reservation = Reservation::Accepted { renew: Delay::new(renew_in) };
+                self.queued_events.push_back(ProtocolsHandlerEvent::Custom(
+                    ClientHandlerEvent::ReservationReqAccepted { renewal, addrs }
+                ));
+            }
+            (outbound_hop::Output::Circuit { substream, limit }, OutboundOpenInfo::Connect { request_id }) => {
+                let alive = self.new_circuit_token();
+                self.queued_events.push_back(ProtocolsHandlerEvent::Custom(
+                    ClientHandlerEvent::OutboundCircuitEstablished { request_id, substream, limit, alive }
+                ));
+            }
+            _ => unreachable!("The output of an upgrade matches its request; qed"),
+        }
+    }
+
+    fn inject_event(&mut self, event: ClientHandlerIn) {
+        self.idle_deadline = Instant::now() + IDLE_TIMEOUT;
+        match event {
+            ClientHandlerIn::Reserve => match self.reservation {
+                // A reservation that is still active, e.g. of a listener that
+                // has since been removed, is requested anew for the new listener.
+                Reservation::None | Reservation::Accepted { .. } => self.request_reservation(false),
+                Reservation::Requested => {}
+            },
+            ClientHandlerIn::EstablishCircuit { request_id, dst_peer_id } => {
+                self.queued_events.push_back(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+                    protocol: SubstreamProtocol::new(outbound_hop::Upgrade::Connect { dst_peer_id }),
+                    info: OutboundOpenInfo::Connect { request_id },
+                });
+            }
+        }
+    }
+
+    fn inject_dial_upgrade_error(
+        &mut self,
+        info: OutboundOpenInfo,
+        error: ProtocolsHandlerUpgrErr<OutboundError>,
+    ) {
+        let event = match info {
+            OutboundOpenInfo::Reserve { renewal } => {
+                self.reservation = Reservation::None;
+                ClientHandlerEvent::ReservationReqFailed { renewal, error }
+            }
+            OutboundOpenInfo::Connect { request_id } => {
+                ClientHandlerEvent::OutboundCircuitReqFailed { request_id, error }
+            }
+        };
+        self.queued_events.push_back(ProtocolsHandlerEvent::Custom(event));
+    }
+
+    fn inject_listen_upgrade_error(&mut self, error: ProtocolsHandlerUpgrErr<ProtocolError>) {
+        log::debug!("Failed to negotiate inbound {:?} substream: {:?}",
+            String::from_utf8_lossy(protocol::STOP_PROTOCOL_NAME), error);
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        match self.reservation {
+            Reservation::None => {}
+            Reservation::Requested | Reservation::Accepted { .. } => return KeepAlive::Yes,
+        }
+        if self.circuits.iter().any(|c| Arc::strong_count(c) > 1) || !self.pending_inbound.is_empty() {
+            return KeepAlive::Yes
+        }
+        KeepAlive::Until(self.idle_deadline)
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<
+        ProtocolsHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::OutEvent, Self::Error>
+    > {
+        if let Some(event) = self.queued_events.pop_front() {
+            return Poll::Ready(event)
+        }
+
+        if let Reservation::Accepted { renew } = &mut self.reservation {
+            if renew.poll_unpin(cx).is_ready() {
+                self.request_reservation(true);
+                if let Some(event) = self.queued_events.pop_front() {
+                    return Poll::Ready(event)
+                }
+            }
+        }
+
+        while let Poll::Ready(Some(result)) = self.pending_inbound.poll_next_unpin(cx) {
+            if let Some(event) = result {
+                return Poll::Ready(ProtocolsHandlerEvent::Custom(event))
+            }
+        }
+
+        let before = self.circuits.len();
+        self.circuits.retain(|c| Arc::strong_count(c) > 1);
+        if self.circuits.len() < before {
+            self.idle_deadline = Instant::now() + IDLE_TIMEOUT;
+        }
+
+        Poll::Pending
+    }
+
+    fn poll_close(&mut self, _: &mut Context<'_>) -> Poll<()> {
+        // Report the outcome of the requests and finish accepting or denying
+        // the inbound circuits. Established circuits are not waited for.
+        if self.queued_events.is_empty() && self.pending_inbound.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
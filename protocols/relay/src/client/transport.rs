@@ -0,0 +1,355 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The [`ClientTransport`], dialing and listening via relays.
+
+use crate::protocol::Limit;
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{BoxFuture, Ready, ready},
+    io::{AsyncRead, AsyncWrite},
+    prelude::*,
+    stream::BoxStream
+};
+use libp2p_core::{
+    Multiaddr,
+    PeerId,
+    multiaddr::Protocol,
+    transport::{ListenerEvent, Transport, TransportError}
+};
+use libp2p_swarm::NegotiatedSubstream;
+use std::{
+    error,
+    fmt,
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll}
+};
+
+/// A [`Transport`] dialing and listening on relayed addresses of the form
+/// `<relay-addr>/p2p/<relay-id>/p2p-circuit[/p2p/<dst-id>]`.
+///
+/// The transport does not do any I/O itself. Instead it forwards requests
+/// to the [`Client`](crate::Client) behaviour, which has to be part of the
+/// same `Swarm`. Addresses without a `/p2p-circuit` component are rejected
+/// with [`TransportError::MultiaddrNotSupported`], thus the transport is
+/// meant to be combined with a regular transport via
+/// [`Transport::or_transport`].
+#[derive(Clone)]
+pub struct ClientTransport {
+    to_behaviour: mpsc::UnboundedSender<TransportToBehaviourMsg>,
+}
+
+impl ClientTransport {
+    pub(crate) fn new(to_behaviour: mpsc::UnboundedSender<TransportToBehaviourMsg>) -> Self {
+        ClientTransport { to_behaviour }
+    }
+}
+
+impl fmt::Debug for ClientTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientTransport").finish()
+    }
+}
+
+impl Transport for ClientTransport {
+    type Output = Connection;
+    type Error = RelayError;
+    type Listener = RelayListener;
+    type ListenerUpgrade = Ready<Result<Connection, RelayError>>;
+    type Dial = BoxFuture<'static, Result<Connection, RelayError>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>> {
+        let RelayedAddr { relay_peer_id, relay_addr, dst_peer_id } = parse_relayed_addr(&addr)
+            .ok_or_else(|| TransportError::MultiaddrNotSupported(addr.clone()))?;
+        if dst_peer_id.is_some() {
+            return Err(TransportError::MultiaddrNotSupported(addr))
+        }
+        let (relay_peer_id, relay_addr) = match (relay_peer_id, relay_addr) {
+            (Some(peer_id), Some(addr)) => (peer_id, addr),
+            _ => return Err(TransportError::Other(RelayError::MissingRelayAddr)),
+        };
+
+        let (to_listener, from_behaviour) = mpsc::unbounded();
+        self.to_behaviour
+            .unbounded_send(TransportToBehaviourMsg::ListenReq {
+                relay_peer_id: relay_peer_id.clone(),
+                relay_addr: relay_addr.clone(),
+                to_listener,
+            })
+            .map_err(|_| TransportError::Other(RelayError::BehaviourGone))?;
+
+        Ok(RelayListener {
+            listen_addr: relay_addr
+                .with(Protocol::P2p(relay_peer_id.into()))
+                .with(Protocol::P2pCircuit),
+            from_behaviour: from_behaviour.boxed(),
+        })
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        let RelayedAddr { relay_peer_id, relay_addr, dst_peer_id } = parse_relayed_addr(&addr)
+            .ok_or_else(|| TransportError::MultiaddrNotSupported(addr.clone()))?;
+        let (relay_peer_id, relay_addr, dst_peer_id) = match (relay_peer_id, relay_addr, dst_peer_id) {
+            (Some(relay_peer_id), Some(relay_addr), Some(dst_peer_id)) =>
+                (relay_peer_id, relay_addr, dst_peer_id),
+            (_, _, None) => return Err(TransportError::Other(RelayError::MissingDstPeerId)),
+            _ => return Err(TransportError::Other(RelayError::MissingRelayAddr)),
+        };
+
+        let mut to_behaviour = self.to_behaviour;
+        Ok(async move {
+            let (send_back, recv) = oneshot::channel();
+            to_behaviour
+                .send(TransportToBehaviourMsg::DialReq {
+                    relay_peer_id,
+                    relay_addr,
+                    dst_peer_id,
+                    send_back,
+                })
+                .await
+                .map_err(|_| RelayError::BehaviourGone)?;
+            recv.await.map_err(|_| RelayError::BehaviourGone)?
+        }.boxed())
+    }
+}
+
+/// The components of a relayed address.
+struct RelayedAddr {
+    relay_peer_id: Option<PeerId>,
+    relay_addr: Option<Multiaddr>,
+    dst_peer_id: Option<PeerId>,
+}
+
+/// Splits an address of the form
+/// `<relay-addr>/p2p/<relay-id>/p2p-circuit[/p2p/<dst-id>]` into its
+/// components, returning `None` if the address is not a relayed address.
+fn parse_relayed_addr(addr: &Multiaddr) -> Option<RelayedAddr> {
+    let mut relay_addr = Multiaddr::empty();
+    let mut relay_peer_id = None;
+    let mut iter = addr.iter();
+
+    loop {
+        match iter.next()? {
+            Protocol::P2pCircuit => break,
+            Protocol::P2p(hash) => {
+                if relay_peer_id.is_some() {
+                    return None
+                }
+                relay_peer_id = Some(PeerId::from_multihash(hash).ok()?);
+            }
+            proto => {
+                if relay_peer_id.is_some() {
+                    return None
+                }
+                relay_addr.push(proto);
+            }
+        }
+    }
+
+    let dst_peer_id = match iter.next() {
+        Some(Protocol::P2p(hash)) => Some(PeerId::from_multihash(hash).ok()?),
+        Some(_) => return None,
+        None => None,
+    };
+    if iter.next().is_some() {
+        return None
+    }
+
+    Some(RelayedAddr {
+        relay_peer_id,
+        relay_addr: if relay_addr.iter().next().is_none() { None } else { Some(relay_addr) },
+        dst_peer_id,
+    })
+}
+
+/// A listener on a relayed address, yielding the connections relayed to
+/// the local node.
+pub struct RelayListener {
+    listen_addr: Multiaddr,
+    from_behaviour: BoxStream<'static, ToListenerMsg>,
+}
+
+impl Stream for RelayListener {
+    type Item = Result<ListenerEvent<Ready<Result<Connection, RelayError>>, RelayError>, RelayError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let msg = match futures::ready!(self.from_behaviour.poll_next_unpin(cx)) {
+            Some(msg) => msg,
+            // The behaviour dropped the listener, e.g. due to the
+            // connection to the relay being closed.
+            None => return Poll::Ready(Some(Err(RelayError::ConnectionClosed))),
+        };
+
+        let event = match msg {
+            ToListenerMsg::Reservation(Ok(())) => ListenerEvent::NewAddress(self.listen_addr.clone()),
+            ToListenerMsg::Reservation(Err(e)) => return Poll::Ready(Some(Err(e))),
+            ToListenerMsg::IncomingRelayedConnection { connection, src_peer_id } => {
+                let remote_addr = self.listen_addr.clone().with(Protocol::P2p(src_peer_id.into()));
+                ListenerEvent::Upgrade {
+                    upgrade: ready(Ok(*connection)),
+                    local_addr: self.listen_addr.clone(),
+                    remote_addr,
+                }
+            }
+        };
+
+        Poll::Ready(Some(Ok(event)))
+    }
+}
+
+/// A connection relayed via a relay server.
+pub struct Connection {
+    substream: NegotiatedSubstream,
+    limit: Limit,
+    /// Keeps the connection to the relay alive while this connection exists.
+    _alive: Arc<()>,
+}
+
+impl Connection {
+    pub(crate) fn new(substream: NegotiatedSubstream, limit: Limit, alive: Arc<()>) -> Self {
+        Connection { substream, limit, _alive: alive }
+    }
+
+    /// The limits the relay imposes on this connection.
+    pub fn limit(&self) -> Limit {
+        self.limit
+    }
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connection").field("limit", &self.limit).finish()
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8])
+        -> Poll<Result<usize, io::Error>>
+    {
+        Pin::new(&mut self.substream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+        -> Poll<Result<usize, io::Error>>
+    {
+        Pin::new(&mut self.substream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.substream).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.substream).poll_close(cx)
+    }
+}
+
+/// Error of the [`ClientTransport`].
+#[derive(Debug)]
+pub enum RelayError {
+    /// The address does not contain the address of the relay.
+    MissingRelayAddr,
+    /// The address to dial does not contain the destination peer.
+    MissingDstPeerId,
+    /// The [`Client`](crate::Client) behaviour has been dropped.
+    BehaviourGone,
+    /// The connection to the relay has been closed.
+    ConnectionClosed,
+    /// Dialing the relay failed.
+    DialFailed,
+    /// The local node already listens via the relay.
+    AlreadyListening,
+    /// The relay denied the reservation or circuit request.
+    Outbound(String),
+}
+
+impl fmt::Display for RelayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelayError::MissingRelayAddr => write!(f, "Missing relay address"),
+            RelayError::MissingDstPeerId => write!(f, "Missing destination peer ID"),
+            RelayError::BehaviourGone => write!(f, "Relay client behaviour has been dropped"),
+            RelayError::ConnectionClosed => write!(f, "Connection to the relay has been closed"),
+            RelayError::DialFailed => write!(f, "Failed to dial the relay"),
+            RelayError::AlreadyListening => write!(f, "Already listening via the relay"),
+            RelayError::Outbound(e) => write!(f, "Relay request failed: {}", e),
+        }
+    }
+}
+
+impl error::Error for RelayError {}
+
+/// Message from the [`ClientTransport`] to the [`Client`](crate::Client)
+/// behaviour.
+pub(crate) enum TransportToBehaviourMsg {
+    /// Dial `dst_peer_id` via the given relay.
+    DialReq {
+        relay_peer_id: PeerId,
+        relay_addr: Multiaddr,
+        dst_peer_id: PeerId,
+        send_back: oneshot::Sender<Result<Connection, RelayError>>,
+    },
+    /// Make a reservation with the given relay and forward relayed
+    /// connections to the listener.
+    ListenReq {
+        relay_peer_id: PeerId,
+        relay_addr: Multiaddr,
+        to_listener: mpsc::UnboundedSender<ToListenerMsg>,
+    },
+}
+
+/// Message from the [`Client`](crate::Client) behaviour to a
+/// [`RelayListener`].
+pub(crate) enum ToListenerMsg {
+    /// The outcome of the (initial) reservation.
+    Reservation(Result<(), RelayError>),
+    /// A connection relayed to the local node.
+    IncomingRelayedConnection {
+        connection: Box<Connection>,
+        src_peer_id: PeerId,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_relayed_addrs() {
+        let relay = PeerId::random();
+        let dst = PeerId::random();
+        let addr: Multiaddr = format!("/memory/1234/p2p/{}/p2p-circuit/p2p/{}", relay, dst)
+            .parse().unwrap();
+        let parsed = parse_relayed_addr(&addr).unwrap();
+        assert_eq!(parsed.relay_peer_id, Some(relay.clone()));
+        assert_eq!(parsed.relay_addr, Some("/memory/1234".parse().unwrap()));
+        assert_eq!(parsed.dst_peer_id, Some(dst));
+
+        let addr: Multiaddr = format!("/memory/1234/p2p/{}/p2p-circuit", relay).parse().unwrap();
+        let parsed = parse_relayed_addr(&addr).unwrap();
+        assert_eq!(parsed.dst_peer_id, None);
+
+        assert!(parse_relayed_addr(&"/memory/1234".parse().unwrap()).is_none());
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Bidirectional copying of data between the two halves of a circuit,
+//! subject to the [`Limit`] of the circuit.
+
+use crate::protocol::Limit;
+use futures::{future::{self, BoxFuture, Either}, prelude::*};
+use std::io;
+use wasm_timer::Delay;
+
+/// Relays data between `a` and `b` until both sides closed their write
+/// half or the given limit is exceeded.
+///
+/// Exceeding the duration limit results in an error of kind
+/// [`io::ErrorKind::TimedOut`], exceeding the data limit in either
+/// direction in an error of kind [`io::ErrorKind::Other`].
+pub(crate) async fn copy_bidirectional<A, B>(a: A, b: B, limit: Limit) -> Result<(), io::Error>
+where
+    A: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    B: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (a_read, a_write) = a.split();
+    let (b_read, b_write) = b.split();
+    let a_to_b = copy_one_way(a_read, b_write, limit.data_in_bytes());
+    let b_to_a = copy_one_way(b_read, a_write, limit.data_in_bytes());
+
+    let both = async move {
+        match future::select(a_to_b, b_to_a).await {
+            Either::Left((result, other)) | Either::Right((result, other)) => {
+                result?;
+                other.await
+            }
+        }
+    };
+
+    match limit.duration() {
+        None => both.await,
+        Some(duration) => match future::select(both.boxed(), Delay::new(duration)).await {
+            Either::Left((result, _)) => result,
+            Either::Right((Ok(()), _)) => Err(io::Error::new(
+                io::ErrorKind::TimedOut, "Circuit exceeded its maximum duration.")),
+            Either::Right((Err(err), _)) => Err(err),
+        }
+    }
+}
+
+/// Copies data from `src` to `dst` until `src` reaches EOF, closing
+/// `dst` afterwards.
+///
+/// Unlike [`futures::io::copy`], every chunk of data is flushed right
+/// away, as the remote may wait for it before sending more.
+fn copy_one_way<R, W>(mut src: R, mut dst: W, max_bytes: Option<u64>)
+    -> BoxFuture<'static, Result<(), io::Error>>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    async move {
+        let mut buf = [0; 4096];
+        let mut copied = 0u64;
+        loop {
+            let n = src.read(&mut buf).await?;
+            if n == 0 {
+                break
+            }
+            copied += n as u64;
+            if max_bytes.map_or(false, |max| copied > max) {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other, "Circuit exceeded its maximum data transfer."))
+            }
+            dst.write_all(&buf[..n]).await?;
+            dst.flush().await?;
+        }
+        dst.close().await
+    }.boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use libp2p_core::transport::{MemoryTransport, Transport, ListenerEvent};
+    use std::time::Duration;
+
+    /// Establishes a pair of connected in-memory sockets.
+    fn socket_pair() -> (impl AsyncRead + AsyncWrite + Unpin + Send, impl AsyncRead + AsyncWrite + Unpin + Send) {
+        let mut listener = MemoryTransport.listen_on("/memory/0".parse().unwrap()).unwrap();
+        let addr = block_on(listener.next()).unwrap().unwrap().into_new_address().unwrap();
+        let dialer = block_on(MemoryTransport.dial(addr).unwrap()).unwrap();
+        let listener = match block_on(listener.next()).unwrap().unwrap() {
+            ListenerEvent::Upgrade { upgrade, .. } => block_on(upgrade).unwrap(),
+            _ => panic!("Expected an upgrade."),
+        };
+        (dialer, listener)
+    }
+
+    #[test]
+    fn data_limit_is_enforced() {
+        let (mut a_outer, a_inner) = socket_pair();
+        let (b_inner, mut b_outer) = socket_pair();
+        let limit = Limit::new(None, Some(4));
+        let relay = async_std::task::spawn(copy_bidirectional(a_inner, b_inner, limit));
+
+        block_on(async {
+            a_outer.write_all(b"12345678").await.unwrap();
+            a_outer.flush().await.unwrap();
+            let err = relay.await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Other);
+            let mut buf = Vec::new();
+            let _ = b_outer.read_to_end(&mut buf).await;
+            assert!(buf.len() <= 4);
+        });
+    }
+
+    #[test]
+    fn duration_limit_is_enforced() {
+        let (_a_outer, a_inner) = socket_pair();
+        let (b_inner, _b_outer) = socket_pair();
+        let limit = Limit::new(Some(Duration::from_millis(50)), None);
+        let err = block_on(copy_bidirectional(a_inner, b_inner, limit)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
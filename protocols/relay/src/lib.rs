@@ -0,0 +1,51 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Implementation of the [circuit relay v2] protocol.
+//!
+//! A relay allows peers that are not directly reachable, e.g. due to a NAT
+//! or firewall, to be reached via a publicly reachable peer acting as the
+//! relay server.
+//!
+//! - The [`Relay`] behaviour implements the relay server. It accepts
+//!   reservations of peers and relays connections to them, subject to the
+//!   limits of its [`RelayConfig`].
+//!
+//! - The [`Client`] behaviour together with the [`ClientTransport`]
+//!   implements the client side. Listening on an address of the form
+//!   `<relay-addr>/p2p/<relay-id>/p2p-circuit` makes a reservation with the
+//!   relay, dialing `<relay-addr>/p2p/<relay-id>/p2p-circuit/p2p/<dst-id>`
+//!   establishes a circuit to `<dst-id>` via the relay.
+//!
+//! [circuit relay v2]: https://github.com/libp2p/specs/blob/master/relay/circuit-v2.md
+
+mod client;
+mod copy_future;
+mod relay;
+
+pub mod protocol;
+
+pub use client::{Client, ClientEvent, handler as client_handler};
+pub use client::transport::{ClientTransport, Connection, RelayError, RelayListener};
+pub use relay::{CircuitId, Relay, RelayConfig, RelayEvent, handler as relay_handler};
+
+mod message_proto {
+    include!(concat!(env!("OUT_DIR"), "/message_v2.pb.rs"));
+}
@@ -0,0 +1,225 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Wire protocol of circuit relay v2.
+//!
+//! The protocol consists of two sub-protocols: the *hop* protocol spoken
+//! between a client and the relay, used to make reservations and to
+//! request circuits to other peers, and the *stop* protocol spoken by the
+//! relay towards the destination of a circuit.
+
+use crate::message_proto;
+use futures::prelude::*;
+use libp2p_core::{Multiaddr, PeerId, upgrade};
+use prost::Message;
+use std::{convert::TryFrom, error, fmt, io, time::Duration};
+
+pub mod inbound_hop;
+pub mod inbound_stop;
+pub mod outbound_hop;
+pub mod outbound_stop;
+
+pub use message_proto::Status;
+
+/// Protocol name of the hop protocol.
+pub const HOP_PROTOCOL_NAME: &[u8] = b"/libp2p/circuit/relay/0.2.0/hop";
+/// Protocol name of the stop protocol.
+pub const STOP_PROTOCOL_NAME: &[u8] = b"/libp2p/circuit/relay/0.2.0/stop";
+
+/// Maximum size of a single hop or stop message.
+const MAX_MESSAGE_SIZE: usize = 4096;
+
+/// Limits imposed by a relay on a relayed connection.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Limit {
+    duration: Option<Duration>,
+    data_in_bytes: Option<u64>,
+}
+
+impl Limit {
+    /// Creates a new `Limit` with the given maximum duration and maximum
+    /// number of bytes relayed in each direction.
+    pub fn new(duration: Option<Duration>, data_in_bytes: Option<u64>) -> Self {
+        Limit { duration, data_in_bytes }
+    }
+
+    /// The maximum duration of a relayed connection, if any.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    /// The maximum number of bytes relayed in each direction, if any.
+    pub fn data_in_bytes(&self) -> Option<u64> {
+        self.data_in_bytes
+    }
+}
+
+impl From<message_proto::Limit> for Limit {
+    fn from(limit: message_proto::Limit) -> Self {
+        Limit {
+            duration: limit.duration.map(|d| Duration::from_secs(d.into())),
+            data_in_bytes: limit.data,
+        }
+    }
+}
+
+impl From<Limit> for message_proto::Limit {
+    fn from(limit: Limit) -> Self {
+        message_proto::Limit {
+            duration: limit.duration.map(|d| {
+                u32::try_from(d.as_secs()).unwrap_or(u32::max_value())
+            }),
+            data: limit.data_in_bytes,
+        }
+    }
+}
+
+/// Error while negotiating one of the relay protocols on a substream.
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// Reading from or writing to the substream failed.
+    Io(io::Error),
+    /// A message could not be decoded.
+    Decode(prost::DecodeError),
+    /// A message was decoded but is not valid in the current context.
+    InvalidMessage(&'static str),
+}
+
+impl From<io::Error> for ProtocolError {
+    fn from(err: io::Error) -> Self {
+        ProtocolError::Io(err)
+    }
+}
+
+impl From<upgrade::ReadOneError> for ProtocolError {
+    fn from(err: upgrade::ReadOneError) -> Self {
+        match err {
+            upgrade::ReadOneError::Io(err) => ProtocolError::Io(err),
+            upgrade::ReadOneError::TooLarge { .. } =>
+                ProtocolError::InvalidMessage("message exceeds maximum size"),
+        }
+    }
+}
+
+impl From<prost::DecodeError> for ProtocolError {
+    fn from(err: prost::DecodeError) -> Self {
+        ProtocolError::Decode(err)
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Io(err) => write!(f, "I/O error: {}", err),
+            ProtocolError::Decode(err) => write!(f, "Failed to decode message: {}", err),
+            ProtocolError::InvalidMessage(msg) => write!(f, "Invalid message: {}", msg),
+        }
+    }
+}
+
+impl error::Error for ProtocolError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ProtocolError::Io(err) => Some(err),
+            ProtocolError::Decode(err) => Some(err),
+            ProtocolError::InvalidMessage(_) => None,
+        }
+    }
+}
+
+/// Error of an outbound hop or stop request.
+#[derive(Debug)]
+pub enum OutboundError {
+    /// The request failed due to a protocol violation or an I/O error.
+    Protocol(ProtocolError),
+    /// The remote answered the request with a non-`OK` status.
+    Refused(Status),
+}
+
+impl From<ProtocolError> for OutboundError {
+    fn from(err: ProtocolError) -> Self {
+        OutboundError::Protocol(err)
+    }
+}
+
+impl From<io::Error> for OutboundError {
+    fn from(err: io::Error) -> Self {
+        OutboundError::Protocol(err.into())
+    }
+}
+
+impl fmt::Display for OutboundError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutboundError::Protocol(err) => write!(f, "{}", err),
+            OutboundError::Refused(status) => write!(f, "Request refused with status {:?}", status),
+        }
+    }
+}
+
+impl error::Error for OutboundError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            OutboundError::Protocol(err) => Some(err),
+            OutboundError::Refused(_) => None,
+        }
+    }
+}
+
+/// Writes a single length-prefixed protobuf message to the substream,
+/// flushing it afterwards.
+async fn send_message<S, M>(substream: &mut S, msg: M) -> Result<(), io::Error>
+where
+    S: AsyncWrite + Unpin,
+    M: Message,
+{
+    let mut bytes = Vec::with_capacity(msg.encoded_len());
+    msg.encode(&mut bytes).expect("Vec<u8> provides capacity as needed");
+    upgrade::write_with_len_prefix(substream, bytes).await
+}
+
+/// Reads a single length-prefixed protobuf message from the substream.
+async fn recv_message<S, M>(substream: &mut S) -> Result<M, ProtocolError>
+where
+    S: AsyncRead + Unpin,
+    M: Message + Default,
+{
+    let bytes = upgrade::read_one(substream, MAX_MESSAGE_SIZE).await?;
+    Ok(M::decode(bytes.as_slice())?)
+}
+
+/// Parses the given status code of a message.
+fn parse_status(status: Option<i32>) -> Result<Status, ProtocolError> {
+    status.and_then(Status::from_i32)
+        .ok_or(ProtocolError::InvalidMessage("missing or unknown status"))
+}
+
+/// Parses the peer contained in a message.
+fn parse_peer(peer: Option<message_proto::Peer>) -> Result<PeerId, ProtocolError> {
+    peer.and_then(|p| PeerId::from_bytes(p.id).ok())
+        .ok_or(ProtocolError::InvalidMessage("missing or invalid peer ID"))
+}
+
+/// Parses a list of encoded multiaddresses, skipping invalid ones.
+fn parse_addrs(addrs: Vec<Vec<u8>>) -> Vec<Multiaddr> {
+    addrs.into_iter()
+        .filter_map(|a| Multiaddr::try_from(a).ok())
+        .collect()
+}
@@ -0,0 +1,179 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The hop protocol as seen by the relay, i.e. for inbound requests of clients.
+
+use crate::message_proto::{self, hop_message};
+use crate::protocol::{
+    HOP_PROTOCOL_NAME, Limit, ProtocolError, Status,
+    parse_peer, recv_message, send_message
+};
+use futures::{future::BoxFuture, prelude::*};
+use libp2p_core::{Multiaddr, PeerId, upgrade};
+use libp2p_swarm::NegotiatedSubstream;
+use std::{io, iter, time::{Duration, SystemTime}};
+
+/// Upgrade for an inbound hop substream.
+#[derive(Debug, Clone)]
+pub struct Upgrade {
+    /// The duration of a reservation granted to the remote.
+    pub reservation_duration: Duration,
+    /// The limits of circuits requested by the remote.
+    pub limit: Limit,
+}
+
+impl upgrade::UpgradeInfo for Upgrade {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(HOP_PROTOCOL_NAME)
+    }
+}
+
+impl upgrade::InboundUpgrade<NegotiatedSubstream> for Upgrade {
+    type Output = Req;
+    type Error = ProtocolError;
+    type Future = BoxFuture<'static, Result<Req, ProtocolError>>;
+
+    fn upgrade_inbound(self, mut substream: NegotiatedSubstream, _: Self::Info) -> Self::Future {
+        async move {
+            let msg: message_proto::HopMessage = recv_message(&mut substream).await?;
+            match hop_message::Type::from_i32(msg.r#type) {
+                Some(hop_message::Type::Reserve) => Ok(Req::Reserve(ReservationReq {
+                    substream,
+                    reservation_duration: self.reservation_duration,
+                    limit: self.limit,
+                })),
+                Some(hop_message::Type::Connect) => {
+                    let dst = parse_peer(msg.peer)?;
+                    Ok(Req::Connect(CircuitReq { dst, substream, limit: self.limit }))
+                }
+                Some(hop_message::Type::Status) | None => {
+                    let _ = send_status(&mut substream, Status::UnexpectedMessage).await;
+                    Err(ProtocolError::InvalidMessage("unexpected hop message type"))
+                }
+            }
+        }.boxed()
+    }
+}
+
+/// A request received on an inbound hop substream.
+#[derive(Debug)]
+pub enum Req {
+    /// The remote requests a reservation.
+    Reserve(ReservationReq),
+    /// The remote requests a circuit to another peer.
+    Connect(CircuitReq),
+}
+
+/// A pending reservation request of a client.
+pub struct ReservationReq {
+    substream: NegotiatedSubstream,
+    reservation_duration: Duration,
+    limit: Limit,
+}
+
+impl ReservationReq {
+    /// Accepts the reservation, announcing the given addresses of the relay
+    /// to the client.
+    pub async fn accept(mut self, addrs: Vec<Multiaddr>) -> Result<(), io::Error> {
+        let expire = (SystemTime::now() + self.reservation_duration)
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let msg = message_proto::HopMessage {
+            r#type: hop_message::Type::Status.into(),
+            peer: None,
+            reservation: Some(message_proto::Reservation {
+                expire,
+                addrs: addrs.into_iter().map(|a| a.to_vec()).collect(),
+                voucher: None,
+            }),
+            limit: Some(self.limit.into()),
+            status: Some(Status::Ok.into()),
+        };
+        send_message(&mut self.substream, msg).await?;
+        self.substream.close().await
+    }
+
+    /// Denies the reservation with the given status.
+    pub async fn deny(mut self, status: Status) -> Result<(), io::Error> {
+        send_status(&mut self.substream, status).await?;
+        self.substream.close().await
+    }
+}
+
+impl std::fmt::Debug for ReservationReq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReservationReq").finish()
+    }
+}
+
+/// A pending request of a client for a circuit to another peer.
+pub struct CircuitReq {
+    dst: PeerId,
+    substream: NegotiatedSubstream,
+    limit: Limit,
+}
+
+impl CircuitReq {
+    /// The destination peer of the requested circuit.
+    pub fn dst(&self) -> &PeerId {
+        &self.dst
+    }
+
+    /// Accepts the circuit request, returning the substream to relay
+    /// data on.
+    pub async fn accept(mut self) -> Result<NegotiatedSubstream, io::Error> {
+        let msg = message_proto::HopMessage {
+            r#type: hop_message::Type::Status.into(),
+            peer: None,
+            reservation: None,
+            limit: Some(self.limit.into()),
+            status: Some(Status::Ok.into()),
+        };
+        send_message(&mut self.substream, msg).await?;
+        Ok(self.substream)
+    }
+
+    /// Denies the circuit request with the given status.
+    pub async fn deny(mut self, status: Status) -> Result<(), io::Error> {
+        send_status(&mut self.substream, status).await?;
+        self.substream.close().await
+    }
+}
+
+impl std::fmt::Debug for CircuitReq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CircuitReq").field("dst", &self.dst).finish()
+    }
+}
+
+async fn send_status(substream: &mut NegotiatedSubstream, status: Status) -> Result<(), io::Error> {
+    let msg = message_proto::HopMessage {
+        r#type: hop_message::Type::Status.into(),
+        peer: None,
+        reservation: None,
+        limit: None,
+        status: Some(status.into()),
+    };
+    send_message(substream, msg).await
+}
@@ -0,0 +1,115 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The stop protocol as seen by the destination of a circuit, i.e. for
+//! inbound requests of a relay.
+
+use crate::message_proto::{self, stop_message};
+use crate::protocol::{
+    Limit, ProtocolError, Status, STOP_PROTOCOL_NAME,
+    parse_peer, recv_message, send_message
+};
+use futures::{future::BoxFuture, prelude::*};
+use libp2p_core::{PeerId, upgrade};
+use libp2p_swarm::NegotiatedSubstream;
+use std::{fmt, io, iter};
+
+/// Upgrade for an inbound stop substream.
+#[derive(Debug, Clone)]
+pub struct Upgrade;
+
+impl upgrade::UpgradeInfo for Upgrade {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(STOP_PROTOCOL_NAME)
+    }
+}
+
+impl upgrade::InboundUpgrade<NegotiatedSubstream> for Upgrade {
+    type Output = Circuit;
+    type Error = ProtocolError;
+    type Future = BoxFuture<'static, Result<Circuit, ProtocolError>>;
+
+    fn upgrade_inbound(self, mut substream: NegotiatedSubstream, _: Self::Info) -> Self::Future {
+        async move {
+            let msg: message_proto::StopMessage = recv_message(&mut substream).await?;
+            if stop_message::Type::from_i32(msg.r#type) != Some(stop_message::Type::Connect) {
+                let _ = send_status(&mut substream, Status::UnexpectedMessage).await;
+                return Err(ProtocolError::InvalidMessage("expected stop connect message"))
+            }
+            let src_peer_id = parse_peer(msg.peer)?;
+            let limit = msg.limit.map(Limit::from).unwrap_or_default();
+            Ok(Circuit { src_peer_id, limit, substream })
+        }.boxed()
+    }
+}
+
+/// A circuit offered by a relay, yet to be accepted or denied.
+pub struct Circuit {
+    src_peer_id: PeerId,
+    limit: Limit,
+    substream: NegotiatedSubstream,
+}
+
+impl Circuit {
+    /// The peer on the other end of the circuit.
+    pub fn src_peer_id(&self) -> &PeerId {
+        &self.src_peer_id
+    }
+
+    /// The limits the relay imposes on the circuit.
+    pub fn limit(&self) -> Limit {
+        self.limit
+    }
+
+    /// Accepts the circuit, returning the substream to communicate with
+    /// the source peer on.
+    pub async fn accept(mut self) -> Result<NegotiatedSubstream, io::Error> {
+        send_status(&mut self.substream, Status::Ok).await?;
+        Ok(self.substream)
+    }
+
+    /// Denies the circuit with the given status.
+    pub async fn deny(mut self, status: Status) -> Result<(), io::Error> {
+        send_status(&mut self.substream, status).await?;
+        self.substream.close().await
+    }
+}
+
+impl fmt::Debug for Circuit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Circuit")
+            .field("src_peer_id", &self.src_peer_id)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+async fn send_status(substream: &mut NegotiatedSubstream, status: Status) -> Result<(), io::Error> {
+    let msg = message_proto::StopMessage {
+        r#type: stop_message::Type::Status.into(),
+        peer: None,
+        limit: None,
+        status: Some(status.into()),
+    };
+    send_message(substream, msg).await
+}
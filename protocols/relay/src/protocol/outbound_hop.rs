@@ -0,0 +1,148 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The hop protocol as seen by a client, i.e. for outbound requests to a relay.
+
+use crate::message_proto::{self, hop_message};
+use crate::protocol::{
+    HOP_PROTOCOL_NAME, Limit, OutboundError, ProtocolError, Status,
+    parse_addrs, parse_status, recv_message, send_message
+};
+use futures::{future::BoxFuture, prelude::*};
+use libp2p_core::{Multiaddr, PeerId, upgrade};
+use libp2p_swarm::NegotiatedSubstream;
+use std::{fmt, iter, time::{Duration, SystemTime}};
+
+/// Upgrade for an outbound hop substream.
+#[derive(Debug, Clone)]
+pub enum Upgrade {
+    /// Requests a reservation with the relay.
+    Reserve,
+    /// Requests a circuit to the given destination.
+    Connect {
+        /// The peer to connect to via the relay.
+        dst_peer_id: PeerId,
+    },
+}
+
+impl upgrade::UpgradeInfo for Upgrade {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(HOP_PROTOCOL_NAME)
+    }
+}
+
+impl upgrade::OutboundUpgrade<NegotiatedSubstream> for Upgrade {
+    type Output = Output;
+    type Error = OutboundError;
+    type Future = BoxFuture<'static, Result<Output, OutboundError>>;
+
+    fn upgrade_outbound(self, mut substream: NegotiatedSubstream, _: Self::Info) -> Self::Future {
+        let msg = match &self {
+            Upgrade::Reserve => message_proto::HopMessage {
+                r#type: hop_message::Type::Reserve.into(),
+                peer: None,
+                reservation: None,
+                limit: None,
+                status: None,
+            },
+            Upgrade::Connect { dst_peer_id } => message_proto::HopMessage {
+                r#type: hop_message::Type::Connect.into(),
+                peer: Some(message_proto::Peer {
+                    id: dst_peer_id.clone().into_bytes(),
+                    addrs: Vec::new(),
+                }),
+                reservation: None,
+                limit: None,
+                status: None,
+            },
+        };
+
+        async move {
+            send_message(&mut substream, msg).await?;
+
+            let msg: message_proto::HopMessage = recv_message(&mut substream).await?;
+            if hop_message::Type::from_i32(msg.r#type) != Some(hop_message::Type::Status) {
+                return Err(ProtocolError::InvalidMessage("expected hop status message").into())
+            }
+            match parse_status(msg.status)? {
+                Status::Ok => {}
+                status => return Err(OutboundError::Refused(status)),
+            }
+            let limit = msg.limit.map(Limit::from).unwrap_or_default();
+
+            match self {
+                Upgrade::Reserve => {
+                    let reservation = msg.reservation
+                        .ok_or(ProtocolError::InvalidMessage("missing reservation"))?;
+                    let now = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or_default();
+                    let expires_in = Duration::from_secs(reservation.expire.saturating_sub(now));
+                    substream.close().await?;
+                    Ok(Output::Reservation {
+                        expires_in,
+                        addrs: parse_addrs(reservation.addrs),
+                        limit,
+                    })
+                }
+                Upgrade::Connect { .. } => Ok(Output::Circuit { substream, limit }),
+            }
+        }.boxed()
+    }
+}
+
+/// The outcome of a successful outbound hop request.
+pub enum Output {
+    /// The relay accepted the reservation.
+    Reservation {
+        /// The time until the reservation expires.
+        expires_in: Duration,
+        /// The addresses of the relay the reservation applies to.
+        addrs: Vec<Multiaddr>,
+        /// The limits of circuits relayed on the basis of the reservation.
+        limit: Limit,
+    },
+    /// The relay established a circuit to the requested destination.
+    Circuit {
+        /// The substream to communicate with the destination on.
+        substream: NegotiatedSubstream,
+        /// The limits of the circuit.
+        limit: Limit,
+    },
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::Reservation { expires_in, addrs, limit } => f.debug_struct("Reservation")
+                .field("expires_in", expires_in)
+                .field("addrs", addrs)
+                .field("limit", limit)
+                .finish(),
+            Output::Circuit { limit, .. } => f.debug_struct("Circuit")
+                .field("limit", limit)
+                .finish(),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The stop protocol as seen by the relay, i.e. for outbound requests to
+//! the destination of a circuit.
+
+use crate::message_proto::{self, stop_message};
+use crate::protocol::{
+    Limit, OutboundError, ProtocolError, Status, STOP_PROTOCOL_NAME,
+    parse_status, recv_message, send_message
+};
+use futures::{future::BoxFuture, prelude::*};
+use libp2p_core::{PeerId, upgrade};
+use libp2p_swarm::NegotiatedSubstream;
+use std::iter;
+
+/// Upgrade for an outbound stop substream, asking the destination
+/// to accept a circuit from `src_peer_id`.
+#[derive(Debug, Clone)]
+pub struct Upgrade {
+    /// The source of the circuit.
+    pub src_peer_id: PeerId,
+    /// The limits of the circuit.
+    pub limit: Limit,
+}
+
+impl upgrade::UpgradeInfo for Upgrade {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(STOP_PROTOCOL_NAME)
+    }
+}
+
+impl upgrade::OutboundUpgrade<NegotiatedSubstream> for Upgrade {
+    type Output = NegotiatedSubstream;
+    type Error = OutboundError;
+    type Future = BoxFuture<'static, Result<NegotiatedSubstream, OutboundError>>;
+
+    fn upgrade_outbound(self, mut substream: NegotiatedSubstream, _: Self::Info) -> Self::Future {
+        async move {
+            let msg = message_proto::StopMessage {
+                r#type: stop_message::Type::Connect.into(),
+                peer: Some(message_proto::Peer {
+                    id: self.src_peer_id.into_bytes(),
+                    addrs: Vec::new(),
+                }),
+                limit: Some(self.limit.into()),
+                status: None,
+            };
+            send_message(&mut substream, msg).await?;
+
+            let msg: message_proto::StopMessage = recv_message(&mut substream).await?;
+            if stop_message::Type::from_i32(msg.r#type) != Some(stop_message::Type::Status) {
+                return Err(ProtocolError::InvalidMessage("expected stop status message").into())
+            }
+            match parse_status(msg.status)? {
+                Status::Ok => Ok(substream),
+                status => Err(OutboundError::Refused(status)),
+            }
+        }.boxed()
+    }
+}
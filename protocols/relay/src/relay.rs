@@ -0,0 +1,514 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The [`Relay`] behaviour, acting as a relay server for other peers.
+
+pub mod handler;
+
+use crate::copy_future::copy_bidirectional;
+use crate::protocol::{inbound_hop, Limit, OutboundError, Status};
+use handler::{Handler, RelayHandlerEvent, RelayHandlerIn};
+use futures::{future::BoxFuture, prelude::*, stream::FuturesUnordered};
+use libp2p_core::{ConnectedPoint, Multiaddr, PeerId, connection::ConnectionId, multiaddr::Protocol};
+use libp2p_swarm::{
+    NetworkBehaviour,
+    NetworkBehaviourAction,
+    NotifyHandler,
+    PollParameters,
+    ProtocolsHandlerUpgrErr
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    task::{Context, Poll},
+    time::Duration
+};
+use wasm_timer::Instant;
+
+/// Configuration of a [`Relay`].
+#[derive(Debug, Clone)]
+pub struct RelayConfig {
+    max_reservations: usize,
+    max_reservations_per_peer: usize,
+    reservation_duration: Duration,
+    max_circuits: usize,
+    max_circuits_per_peer: usize,
+    max_circuit_duration: Duration,
+    max_circuit_bytes: u64,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        RelayConfig {
+            max_reservations: 128,
+            max_reservations_per_peer: 4,
+            reservation_duration: Duration::from_secs(60 * 60),
+            max_circuits: 16,
+            max_circuits_per_peer: 4,
+            max_circuit_duration: Duration::from_secs(2 * 60),
+            max_circuit_bytes: 1 << 17, // 128 KiB
+        }
+    }
+}
+
+impl RelayConfig {
+    /// Sets the maximum number of reservations the relay accepts in total.
+    pub fn set_max_reservations(&mut self, n: usize) -> &mut Self {
+        self.max_reservations = n;
+        self
+    }
+
+    /// Sets the maximum number of reservations the relay accepts per peer,
+    /// counting one reservation per connection.
+    pub fn set_max_reservations_per_peer(&mut self, n: usize) -> &mut Self {
+        self.max_reservations_per_peer = n;
+        self
+    }
+
+    /// Sets the duration of a reservation, after which a client has to
+    /// renew it.
+    pub fn set_reservation_duration(&mut self, duration: Duration) -> &mut Self {
+        self.reservation_duration = duration;
+        self
+    }
+
+    /// Sets the maximum number of circuits relayed simultaneously.
+    pub fn set_max_circuits(&mut self, n: usize) -> &mut Self {
+        self.max_circuits = n;
+        self
+    }
+
+    /// Sets the maximum number of circuits relayed simultaneously per
+    /// source peer.
+    pub fn set_max_circuits_per_peer(&mut self, n: usize) -> &mut Self {
+        self.max_circuits_per_peer = n;
+        self
+    }
+
+    /// Sets the maximum duration of a single circuit.
+    pub fn set_max_circuit_duration(&mut self, duration: Duration) -> &mut Self {
+        self.max_circuit_duration = duration;
+        self
+    }
+
+    /// Sets the maximum number of bytes relayed in each direction of a
+    /// single circuit.
+    pub fn set_max_circuit_bytes(&mut self, n: u64) -> &mut Self {
+        self.max_circuit_bytes = n;
+        self
+    }
+
+    fn circuit_limit(&self) -> Limit {
+        Limit::new(Some(self.max_circuit_duration), Some(self.max_circuit_bytes))
+    }
+}
+
+/// The ID of a circuit relayed by a [`Relay`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CircuitId(u64);
+
+/// Event emitted by the [`Relay`] behaviour.
+#[derive(Debug)]
+pub enum RelayEvent {
+    /// A reservation of a peer has been accepted.
+    ReservationReqAccepted {
+        /// The peer that made the reservation.
+        src_peer_id: PeerId,
+        /// Whether the request renewed an existing reservation.
+        renewed: bool,
+    },
+    /// A reservation request of a peer has been denied.
+    ReservationReqDenied {
+        /// The peer that requested the reservation.
+        src_peer_id: PeerId,
+    },
+    /// A circuit request has been denied.
+    CircuitReqDenied {
+        /// The peer that requested the circuit.
+        src_peer_id: PeerId,
+        /// The requested destination of the circuit.
+        dst_peer_id: PeerId,
+    },
+    /// The destination of a requested circuit did not accept the circuit.
+    CircuitReqOutboundConnectFailed {
+        /// The peer that requested the circuit.
+        src_peer_id: PeerId,
+        /// The destination of the circuit.
+        dst_peer_id: PeerId,
+        /// The reason of the failure.
+        error: ProtocolsHandlerUpgrErr<OutboundError>,
+    },
+    /// A circuit has been established.
+    CircuitReqAccepted {
+        /// The peer that requested the circuit.
+        src_peer_id: PeerId,
+        /// The destination of the circuit.
+        dst_peer_id: PeerId,
+    },
+    /// A circuit has been closed.
+    CircuitClosed {
+        /// The source of the circuit.
+        src_peer_id: PeerId,
+        /// The destination of the circuit.
+        dst_peer_id: PeerId,
+        /// The error that caused the closure, if any, e.g. the circuit
+        /// exceeding its limits.
+        error: Option<io::Error>,
+    },
+}
+
+/// A `NetworkBehaviour` that relays connections between peers, implementing
+/// the relay side of the circuit relay v2 protocol.
+///
+/// Peers make reservations with the relay, allowing other peers to connect
+/// to them via the relay. Both the number of reservations and the number,
+/// duration and volume of relayed connections are limited according to
+/// the [`RelayConfig`].
+pub struct Relay {
+    config: RelayConfig,
+    /// Active reservations per peer and connection.
+    reservations: HashMap<PeerId, HashMap<ConnectionId, Instant>>,
+    /// Circuits waiting for the destination to accept them.
+    pending_circuits: HashMap<CircuitId, PendingCircuit>,
+    /// Circuits currently relaying data.
+    circuits: HashMap<CircuitId, Circuit>,
+    /// Requests received from handlers, yet to be processed.
+    queued_requests: VecDeque<(PeerId, ConnectionId, RelayHandlerEvent)>,
+    /// Actions to yield on the next call to `poll`.
+    queued_actions: VecDeque<NetworkBehaviourAction<RelayHandlerIn, RelayEvent>>,
+    /// Ongoing I/O on hop substreams, including relayed circuits.
+    tasks: FuturesUnordered<BoxFuture<'static, Option<ClosedCircuit>>>,
+    next_circuit_id: u64,
+}
+
+/// A circuit that has been closed, together with the error that caused
+/// the closure, if any.
+type ClosedCircuit = (CircuitId, Option<io::Error>);
+
+struct PendingCircuit {
+    src_peer_id: PeerId,
+    src_connection: ConnectionId,
+    dst_peer_id: PeerId,
+    dst_connection: ConnectionId,
+    request: inbound_hop::CircuitReq,
+}
+
+struct Circuit {
+    src_peer_id: PeerId,
+    src_connection: ConnectionId,
+    dst_peer_id: PeerId,
+    dst_connection: ConnectionId,
+}
+
+impl Relay {
+    /// Creates a new `Relay` with the given configuration.
+    pub fn new(config: RelayConfig) -> Self {
+        Relay {
+            config,
+            reservations: HashMap::new(),
+            pending_circuits: HashMap::new(),
+            circuits: HashMap::new(),
+            queued_requests: VecDeque::new(),
+            queued_actions: VecDeque::new(),
+            tasks: FuturesUnordered::new(),
+            next_circuit_id: 0,
+        }
+    }
+
+    /// Returns `true` if the given peer holds a valid reservation.
+    pub fn has_reservation(&self, peer_id: &PeerId) -> bool {
+        let now = Instant::now();
+        self.reservations.get(peer_id)
+            .map_or(false, |r| r.values().any(|expires| *expires > now))
+    }
+
+    fn remove_expired_reservations(&mut self) {
+        let now = Instant::now();
+        self.reservations.retain(|_, r| {
+            r.retain(|_, expires| *expires > now);
+            !r.is_empty()
+        });
+    }
+
+    fn handle_reservation_req(
+        &mut self,
+        src_peer_id: PeerId,
+        connection: ConnectionId,
+        request: inbound_hop::ReservationReq,
+        params: &impl PollParameters,
+    ) {
+        self.remove_expired_reservations();
+        let renewed = self.reservations.get(&src_peer_id)
+            .map_or(false, |r| r.contains_key(&connection));
+        let num_reservations: usize = self.reservations.values().map(|r| r.len()).sum();
+        let num_peer_reservations = self.reservations.get(&src_peer_id).map_or(0, |r| r.len());
+
+        if !renewed && (num_reservations >= self.config.max_reservations
+            || num_peer_reservations >= self.config.max_reservations_per_peer)
+        {
+            self.tasks.push(async move {
+                if let Err(e) = request.deny(Status::ResourceLimitExceeded).await {
+                    log::debug!("Failed to deny reservation: {:?}", e);
+                }
+                None
+            }.boxed());
+            self.queued_actions.push_back(NetworkBehaviourAction::GenerateEvent(
+                RelayEvent::ReservationReqDenied { src_peer_id }
+            ));
+            return
+        }
+
+        let local_peer_id = params.local_peer_id().clone();
+        let mut addrs = params.external_addresses().collect::<Vec<_>>();
+        if addrs.is_empty() {
+            addrs = params.listened_addresses().collect();
+        }
+        let addrs = addrs.into_iter()
+            .map(|a| a.with(Protocol::P2p(local_peer_id.clone().into())))
+            .collect::<Vec<Multiaddr>>();
+
+        self.tasks.push(async move {
+            if let Err(e) = request.accept(addrs).await {
+                log::debug!("Failed to accept reservation: {:?}", e);
+            }
+            None
+        }.boxed());
+
+        let expires = Instant::now() + self.config.reservation_duration;
+        self.reservations.entry(src_peer_id.clone())
+            .or_default()
+            .insert(connection, expires);
+        self.queued_actions.push_back(NetworkBehaviourAction::NotifyHandler {
+            peer_id: src_peer_id.clone(),
+            handler: NotifyHandler::One(connection),
+            event: RelayHandlerIn::ReservationAccepted { expires },
+        });
+        self.queued_actions.push_back(NetworkBehaviourAction::GenerateEvent(
+            RelayEvent::ReservationReqAccepted { src_peer_id, renewed }
+        ));
+    }
+
+    fn handle_circuit_req(
+        &mut self,
+        src_peer_id: PeerId,
+        src_connection: ConnectionId,
+        request: inbound_hop::CircuitReq,
+    ) {
+        self.remove_expired_reservations();
+        let dst_peer_id = request.dst().clone();
+        let num_circuits = self.circuits.len() + self.pending_circuits.len();
+        let num_peer_circuits = self.circuits.values().map(|c| &c.src_peer_id)
+            .chain(self.pending_circuits.values().map(|c| &c.src_peer_id))
+            .filter(|p| **p == src_peer_id)
+            .count();
+
+        let status = if num_circuits >= self.config.max_circuits
+            || num_peer_circuits >= self.config.max_circuits_per_peer
+        {
+            Err(Status::ResourceLimitExceeded)
+        } else {
+            let now = Instant::now();
+            self.reservations.get(&dst_peer_id)
+                .and_then(|r| r.iter().find(|(_, expires)| **expires > now))
+                .map(|(connection, _)| *connection)
+                .ok_or(Status::NoReservation)
+        };
+
+        match status {
+            Ok(dst_connection) => {
+                let circuit_id = CircuitId(self.next_circuit_id);
+                self.next_circuit_id += 1;
+                self.queued_actions.push_back(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: dst_peer_id.clone(),
+                    handler: NotifyHandler::One(dst_connection),
+                    event: RelayHandlerIn::NegotiateOutboundConnect {
+                        circuit_id,
+                        src_peer_id: src_peer_id.clone(),
+                        limit: self.config.circuit_limit(),
+                    },
+                });
+                self.pending_circuits.insert(circuit_id, PendingCircuit {
+                    src_peer_id,
+                    src_connection,
+                    dst_peer_id,
+                    dst_connection,
+                    request,
+                });
+            }
+            Err(status) => {
+                self.tasks.push(async move {
+                    if let Err(e) = request.deny(status).await {
+                        log::debug!("Failed to deny circuit request: {:?}", e);
+                    }
+                    None
+                }.boxed());
+                self.queued_actions.push_back(NetworkBehaviourAction::GenerateEvent(
+                    RelayEvent::CircuitReqDenied { src_peer_id, dst_peer_id }
+                ));
+            }
+        }
+    }
+}
+
+impl Default for Relay {
+    fn default() -> Self {
+        Relay::new(RelayConfig::default())
+    }
+}
+
+impl NetworkBehaviour for Relay {
+    type ProtocolsHandler = Handler;
+    type OutEvent = RelayEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        Handler::new(self.config.reservation_duration, self.config.circuit_limit())
+    }
+
+    fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
+        Vec::new()
+    }
+
+    fn inject_connected(&mut self, _: &PeerId) {}
+
+    fn inject_disconnected(&mut self, _: &PeerId) {}
+
+    fn inject_connection_closed(&mut self, peer_id: &PeerId, connection: &ConnectionId, _: &ConnectedPoint) {
+        if let Some(reservations) = self.reservations.get_mut(peer_id) {
+            reservations.remove(connection);
+            if reservations.is_empty() {
+                self.reservations.remove(peer_id);
+            }
+        }
+        // Dropping the pending requests of the source resets the
+        // corresponding hop substreams.
+        self.pending_circuits.retain(|_, c| {
+            let is_src = c.src_peer_id == *peer_id && c.src_connection == *connection;
+            let is_dst = c.dst_peer_id == *peer_id && c.dst_connection == *connection;
+            !is_src && !is_dst
+        });
+    }
+
+    fn inject_event(&mut self, peer_id: PeerId, connection: ConnectionId, event: RelayHandlerEvent) {
+        self.queued_requests.push_back((peer_id, connection, event));
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>, params: &mut impl PollParameters)
+        -> Poll<NetworkBehaviourAction<RelayHandlerIn, RelayEvent>>
+    {
+        while let Some((peer_id, connection, event)) = self.queued_requests.pop_front() {
+            match event {
+                RelayHandlerEvent::ReservationReqReceived(request) => {
+                    self.handle_reservation_req(peer_id, connection, request, params);
+                }
+                RelayHandlerEvent::CircuitReqReceived(request) => {
+                    self.handle_circuit_req(peer_id, connection, request);
+                }
+                RelayHandlerEvent::OutboundConnectNegotiated { circuit_id, dst_substream } => {
+                    let pending = match self.pending_circuits.remove(&circuit_id) {
+                        Some(pending) => pending,
+                        None => continue,
+                    };
+                    let limit = self.config.circuit_limit();
+                    let request = pending.request;
+                    self.tasks.push(async move {
+                        let result = match request.accept().await {
+                            Ok(src_substream) =>
+                                copy_bidirectional(src_substream, dst_substream, limit).await,
+                            Err(e) => Err(e),
+                        };
+                        Some((circuit_id, result.err()))
+                    }.boxed());
+                    for (peer, connection) in &[
+                        (&pending.src_peer_id, pending.src_connection),
+                        (&pending.dst_peer_id, pending.dst_connection),
+                    ] {
+                        self.queued_actions.push_back(NetworkBehaviourAction::NotifyHandler {
+                            peer_id: (*peer).clone(),
+                            handler: NotifyHandler::One(*connection),
+                            event: RelayHandlerIn::CircuitOpened,
+                        });
+                    }
+                    self.queued_actions.push_back(NetworkBehaviourAction::GenerateEvent(
+                        RelayEvent::CircuitReqAccepted {
+                            src_peer_id: pending.src_peer_id.clone(),
+                            dst_peer_id: pending.dst_peer_id.clone(),
+                        }
+                    ));
+                    self.circuits.insert(circuit_id, Circuit {
+                        src_peer_id: pending.src_peer_id,
+                        src_connection: pending.src_connection,
+                        dst_peer_id: pending.dst_peer_id,
+                        dst_connection: pending.dst_connection,
+                    });
+                }
+                RelayHandlerEvent::OutboundConnectNegotiationFailed { circuit_id, error } => {
+                    let pending = match self.pending_circuits.remove(&circuit_id) {
+                        Some(pending) => pending,
+                        None => continue,
+                    };
+                    let request = pending.request;
+                    self.tasks.push(async move {
+                        if let Err(e) = request.deny(Status::ConnectionFailed).await {
+                            log::debug!("Failed to deny circuit request: {:?}", e);
+                        }
+                        None
+                    }.boxed());
+                    self.queued_actions.push_back(NetworkBehaviourAction::GenerateEvent(
+                        RelayEvent::CircuitReqOutboundConnectFailed {
+                            src_peer_id: pending.src_peer_id,
+                            dst_peer_id: pending.dst_peer_id,
+                            error,
+                        }
+                    ));
+                }
+            }
+        }
+
+        while let Poll::Ready(Some(result)) = self.tasks.poll_next_unpin(cx) {
+            if let Some((circuit_id, error)) = result {
+                if let Some(circuit) = self.circuits.remove(&circuit_id) {
+                    for (peer, connection) in &[
+                        (&circuit.src_peer_id, circuit.src_connection),
+                        (&circuit.dst_peer_id, circuit.dst_connection),
+                    ] {
+                        self.queued_actions.push_back(NetworkBehaviourAction::NotifyHandler {
+                            peer_id: (*peer).clone(),
+                            handler: NotifyHandler::One(*connection),
+                            event: RelayHandlerIn::CircuitClosed,
+                        });
+                    }
+                    self.queued_actions.push_back(NetworkBehaviourAction::GenerateEvent(
+                        RelayEvent::CircuitClosed {
+                            src_peer_id: circuit.src_peer_id,
+                            dst_peer_id: circuit.dst_peer_id,
+                            error,
+                        }
+                    ));
+                }
+            }
+        }
+
+        if let Some(action) = self.queued_actions.pop_front() {
+            return Poll::Ready(action)
+        }
+
+        Poll::Pending
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The [`ProtocolsHandler`] of the [`Relay`](crate::Relay) behaviour.
+
+use crate::protocol::{self, inbound_hop, outbound_stop, Limit, OutboundError, ProtocolError};
+use crate::relay::CircuitId;
+use libp2p_core::PeerId;
+use libp2p_swarm::{
+    KeepAlive,
+    NegotiatedSubstream,
+    ProtocolsHandler,
+    ProtocolsHandlerEvent,
+    ProtocolsHandlerUpgrErr,
+    SubstreamProtocol
+};
+use std::{collections::VecDeque, task::{Context, Poll}, time::Duration};
+use wasm_timer::Instant;
+
+/// The time an idle connection, i.e. one without an active reservation
+/// or circuit, is kept alive.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Event sent from the [`Relay`](crate::Relay) behaviour to a [`Handler`].
+#[derive(Debug, Clone)]
+pub enum RelayHandlerIn {
+    /// A reservation of the remote has been accepted and is valid until
+    /// the given instant.
+    ReservationAccepted {
+        /// The moment the reservation expires.
+        expires: Instant,
+    },
+    /// Ask the remote, the destination of a circuit, to accept a circuit
+    /// from `src_peer_id`.
+    NegotiateOutboundConnect {
+        /// The ID of the circuit.
+        circuit_id: CircuitId,
+        /// The source of the circuit.
+        src_peer_id: PeerId,
+        /// The limits of the circuit.
+        limit: Limit,
+    },
+    /// A circuit involving this connection is now active.
+    CircuitOpened,
+    /// A circuit involving this connection has been closed.
+    CircuitClosed,
+}
+
+/// Event produced by a [`Handler`] for the [`Relay`](crate::Relay) behaviour.
+#[derive(Debug)]
+pub enum RelayHandlerEvent {
+    /// The remote requested a reservation.
+    ReservationReqReceived(inbound_hop::ReservationReq),
+    /// The remote requested a circuit to another peer.
+    CircuitReqReceived(inbound_hop::CircuitReq),
+    /// The remote, the destination of a circuit, accepted the circuit.
+    OutboundConnectNegotiated {
+        /// The ID of the circuit.
+        circuit_id: CircuitId,
+        /// The substream to the destination.
+        dst_substream: NegotiatedSubstream,
+    },
+    /// The remote, the destination of a circuit, did not accept the circuit.
+    OutboundConnectNegotiationFailed {
+        /// The ID of the circuit.
+        circuit_id: CircuitId,
+        /// The reason of the failure.
+        error: ProtocolsHandlerUpgrErr<OutboundError>,
+    },
+}
+
+/// Protocol handler of the relay for a single connection.
+pub struct Handler {
+    /// The upgrade to apply on inbound hop substreams.
+    inbound_hop: inbound_hop::Upgrade,
+    /// Events to yield on the next call to `poll`.
+    queued_events: VecDeque<ProtocolsHandlerEvent<
+        outbound_stop::Upgrade,
+        CircuitId,
+        RelayHandlerEvent,
+        void::Void,
+    >>,
+    /// The moment the reservation of the remote expires, if any.
+    reservation_expires: Option<Instant>,
+    /// The number of circuits currently relayed via this connection,
+    /// including circuits not yet accepted by the destination.
+    active_circuits: usize,
+    /// The moment this connection is considered idle.
+    idle_deadline: Instant,
+}
+
+impl Handler {
+    /// Creates a new `Handler` granting reservations of the given duration
+    /// and imposing the given limit on circuits.
+    pub(crate) fn new(reservation_duration: Duration, limit: Limit) -> Self {
+        Handler {
+            inbound_hop: inbound_hop::Upgrade { reservation_duration, limit },
+            queued_events: VecDeque::new(),
+            reservation_expires: None,
+            active_circuits: 0,
+            idle_deadline: Instant::now() + IDLE_TIMEOUT,
+        }
+    }
+}
+
+impl ProtocolsHandler for Handler {
+    type InEvent = RelayHandlerIn;
+    type OutEvent = RelayHandlerEvent;
+    type Error = void::Void;
+    type InboundProtocol = inbound_hop::Upgrade;
+    type OutboundProtocol = outbound_stop::Upgrade;
+    type OutboundOpenInfo = CircuitId;
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol> {
+        SubstreamProtocol::new(self.inbound_hop.clone())
+    }
+
+    fn inject_fully_negotiated_inbound(&mut self, request: inbound_hop::Req) {
+        self.idle_deadline = Instant::now() + IDLE_TIMEOUT;
+        let event = match request {
+            inbound_hop::Req::Reserve(req) => RelayHandlerEvent::ReservationReqReceived(req),
+            inbound_hop::Req::Connect(req) => RelayHandlerEvent::CircuitReqReceived(req),
+        };
+        self.queued_events.push_back(ProtocolsHandlerEvent::Custom(event));
+    }
+
+    fn inject_fully_negotiated_outbound(
+        &mut self,
+        dst_substream: NegotiatedSubstream,
+        circuit_id: CircuitId,
+    ) {
+        self.queued_events.push_back(ProtocolsHandlerEvent::Custom(
+            RelayHandlerEvent::OutboundConnectNegotiated { circuit_id, dst_substream }
+        ));
+    }
+
+    fn inject_event(&mut self, event: RelayHandlerIn) {
+        self.idle_deadline = Instant::now() + IDLE_TIMEOUT;
+        match event {
+            RelayHandlerIn::ReservationAccepted { expires } => {
+                self.reservation_expires = Some(expires);
+            }
+            RelayHandlerIn::NegotiateOutboundConnect { circuit_id, src_peer_id, limit } => {
+                self.queued_events.push_back(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+                    protocol: SubstreamProtocol::new(outbound_stop::Upgrade { src_peer_id, limit }),
+                    info: circuit_id,
+                });
+            }
+            RelayHandlerIn::CircuitOpened => {
+                self.active_circuits += 1;
+            }
+            RelayHandlerIn::CircuitClosed => {
+                self.active_circuits = self.active_circuits.saturating_sub(1);
+            }
+        }
+    }
+
+    fn inject_dial_upgrade_error(
+        &mut self,
+        circuit_id: CircuitId,
+        error: ProtocolsHandlerUpgrErr<OutboundError>,
+    ) {
+        self.queued_events.push_back(ProtocolsHandlerEvent::Custom(
+            RelayHandlerEvent::OutboundConnectNegotiationFailed { circuit_id, error }
+        ));
+    }
+
+    fn inject_listen_upgrade_error(&mut self, error: ProtocolsHandlerUpgrErr<ProtocolError>) {
+        log::debug!("Failed to negotiate inbound {:?} substream: {:?}",
+            String::from_utf8_lossy(protocol::HOP_PROTOCOL_NAME), error);
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        if self.active_circuits > 0 {
+            return KeepAlive::Yes
+        }
+        match self.reservation_expires {
+            Some(expires) if expires > self.idle_deadline => KeepAlive::Until(expires),
+            _ => KeepAlive::Until(self.idle_deadline),
+        }
+    }
+
+    fn poll(&mut self, _: &mut Context<'_>) -> Poll<
+        ProtocolsHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::OutEvent, Self::Error>
+    > {
+        if let Some(event) = self.queued_events.pop_front() {
+            return Poll::Ready(event)
+        }
+
+        Poll::Pending
+    }
+
+    fn poll_close(&mut self, _: &mut Context<'_>) -> Poll<()> {
+        // Hand the received requests and negotiated circuits over to the
+        // behaviour. Active circuits are not waited for, as they last for as
+        // long as the peers use them.
+        if self.queued_events.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
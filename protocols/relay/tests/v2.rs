@@ -0,0 +1,247 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Integration tests for the circuit relay v2 protocol.
+
+use futures::{channel::mpsc, future, prelude::*};
+use libp2p_core::{
+    Multiaddr,
+    PeerId,
+    identity,
+    multiaddr::Protocol,
+    muxing::StreamMuxerBox,
+    transport::{MemoryTransport, Transport, boxed::Boxed},
+    upgrade
+};
+use libp2p_mplex::MplexConfig;
+use libp2p_plaintext::PlainText2Config;
+use libp2p_relay::{Client, ClientEvent, Relay, RelayConfig, RelayEvent};
+use libp2p_swarm::{Swarm, SwarmEvent};
+use std::io;
+
+#[test]
+fn reservation_and_circuit() {
+    let relay_addr: Multiaddr = Protocol::Memory(rand_port()).into();
+    let mut relay = build_relay();
+    let relay_peer_id = Swarm::local_peer_id(&relay).clone();
+    Swarm::listen_on(&mut relay, relay_addr.clone()).unwrap();
+
+    let mut listener = build_client();
+    let listener_peer_id = Swarm::local_peer_id(&listener).clone();
+    let circuit_addr = relay_addr
+        .with(Protocol::P2p(relay_peer_id.clone().into()))
+        .with(Protocol::P2pCircuit);
+    Swarm::listen_on(&mut listener, circuit_addr.clone()).unwrap();
+
+    let mut dialer = build_client();
+    let dialer_peer_id = Swarm::local_peer_id(&dialer).clone();
+
+    async_std::task::block_on(async move {
+        async_std::task::spawn(relay.for_each(|_| future::ready(())));
+
+        // Wait for the reservation to be accepted.
+        loop {
+            match listener.next_event().await {
+                SwarmEvent::NewListenAddr(addr) => {
+                    assert_eq!(addr, circuit_addr);
+                    break
+                }
+                SwarmEvent::Behaviour(ClientEvent::ReservationReqFailed { .. }) |
+                SwarmEvent::ListenerClosed { .. } |
+                SwarmEvent::ListenerError { .. } => panic!("Reservation failed"),
+                _ => {}
+            }
+        }
+
+        let (mut tx, mut rx) = mpsc::channel(1);
+        async_std::task::spawn(async move {
+            loop {
+                if let SwarmEvent::ConnectionEstablished { peer_id, .. } = listener.next_event().await {
+                    tx.send(peer_id).await.unwrap();
+                }
+            }
+        });
+
+        Swarm::dial_addr(&mut dialer, circuit_addr.with(Protocol::P2p(listener_peer_id.clone().into())))
+            .unwrap();
+        loop {
+            match dialer.next_event().await {
+                SwarmEvent::ConnectionEstablished { peer_id, .. } if peer_id == listener_peer_id => break,
+                SwarmEvent::Behaviour(ClientEvent::OutboundCircuitReqFailed { .. }) |
+                SwarmEvent::UnknownPeerUnreachableAddr { .. } => panic!("Dialing via relay failed"),
+                _ => {}
+            }
+        }
+
+        // The listener observes the relayed connection from the dialer, in
+        // addition to its connection to the relay.
+        async_std::task::spawn(dialer.for_each(|_| future::ready(())));
+        loop {
+            if rx.next().await.unwrap() == dialer_peer_id {
+                break
+            }
+        }
+    });
+}
+
+#[test]
+fn second_listener_via_same_relay_is_rejected() {
+    let relay_addr: Multiaddr = Protocol::Memory(rand_port()).into();
+    let mut relay = build_relay();
+    let relay_peer_id = Swarm::local_peer_id(&relay).clone();
+    Swarm::listen_on(&mut relay, relay_addr.clone()).unwrap();
+
+    let mut listener = build_client();
+    let circuit_addr = relay_addr
+        .with(Protocol::P2p(relay_peer_id.into()))
+        .with(Protocol::P2pCircuit);
+    let first = Swarm::listen_on(&mut listener, circuit_addr.clone()).unwrap();
+
+    async_std::task::block_on(async move {
+        async_std::task::spawn(relay.for_each(|_| future::ready(())));
+
+        loop {
+            match listener.next_event().await {
+                SwarmEvent::NewListenAddr(_) => break,
+                SwarmEvent::Behaviour(ClientEvent::ReservationReqFailed { .. }) |
+                SwarmEvent::ListenerClosed { .. } |
+                SwarmEvent::ListenerError { .. } => panic!("Reservation failed"),
+                _ => {}
+            }
+        }
+
+        let second = Swarm::listen_on(&mut listener, circuit_addr).unwrap();
+        assert_ne!(first, second);
+        loop {
+            match listener.next_event().await {
+                SwarmEvent::ListenerClosed { addresses, .. } => {
+                    assert!(addresses.is_empty());
+                    break
+                }
+                SwarmEvent::ListenerError { .. } => break,
+                SwarmEvent::ExpiredListenAddr(_) => panic!("First listener was replaced"),
+                _ => {}
+            }
+        }
+    });
+}
+
+#[test]
+fn listening_via_relay_again_after_removing_listener() {
+    let relay_addr: Multiaddr = Protocol::Memory(rand_port()).into();
+    let mut relay = build_relay();
+    let relay_peer_id = Swarm::local_peer_id(&relay).clone();
+    Swarm::listen_on(&mut relay, relay_addr.clone()).unwrap();
+
+    let mut listener = build_client();
+    let circuit_addr = relay_addr
+        .with(Protocol::P2p(relay_peer_id.into()))
+        .with(Protocol::P2pCircuit);
+
+    async_std::task::block_on(async move {
+        async_std::task::spawn(relay.for_each(|_| future::ready(())));
+
+        for _ in 0 .. 2 {
+            let id = Swarm::listen_on(&mut listener, circuit_addr.clone()).unwrap();
+            loop {
+                match listener.next_event().await {
+                    SwarmEvent::NewListenAddr(_) => break,
+                    SwarmEvent::Behaviour(ClientEvent::ReservationReqFailed { .. }) |
+                    SwarmEvent::ListenerClosed { .. } |
+                    SwarmEvent::ListenerError { .. } => panic!("Reservation failed"),
+                    _ => {}
+                }
+            }
+            Swarm::remove_listener(&mut listener, id).unwrap();
+        }
+    });
+}
+
+#[test]
+fn circuit_to_peer_without_reservation_is_denied() {
+    let relay_addr: Multiaddr = Protocol::Memory(rand_port()).into();
+    let mut relay = build_relay();
+    let relay_peer_id = Swarm::local_peer_id(&relay).clone();
+    Swarm::listen_on(&mut relay, relay_addr.clone()).unwrap();
+
+    let mut dialer = build_client();
+    let dst_addr = relay_addr
+        .with(Protocol::P2p(relay_peer_id.into()))
+        .with(Protocol::P2pCircuit)
+        .with(Protocol::P2p(PeerId::random().into()));
+    Swarm::dial_addr(&mut dialer, dst_addr).unwrap();
+
+    async_std::task::block_on(async move {
+        let (mut tx, mut rx) = mpsc::channel(1);
+        async_std::task::spawn(async move {
+            loop {
+                if let RelayEvent::CircuitReqDenied { .. } = relay.next().await {
+                    tx.send(()).await.unwrap();
+                }
+            }
+        });
+
+        loop {
+            if let SwarmEvent::Behaviour(ClientEvent::OutboundCircuitReqFailed { .. }) =
+                dialer.next_event().await
+            {
+                break
+            }
+        }
+        rx.next().await.unwrap();
+    });
+}
+
+fn rand_port() -> u64 {
+    1 + rand::random::<u64>() % (u64::max_value() - 1)
+}
+
+fn build_relay() -> Swarm<Relay> {
+    let local_key = identity::Keypair::generate_ed25519();
+    let local_peer_id = local_key.public().into_peer_id();
+    let transport = upgrade_transport(MemoryTransport::default(), local_key);
+    Swarm::new(transport, Relay::new(RelayConfig::default()), local_peer_id)
+}
+
+fn build_client() -> Swarm<Client> {
+    let local_key = identity::Keypair::generate_ed25519();
+    let local_peer_id = local_key.public().into_peer_id();
+    let (relay_transport, behaviour) = Client::new_transport_and_behaviour();
+    let transport = upgrade_transport(relay_transport.or_transport(MemoryTransport::default()), local_key);
+    Swarm::new(transport, behaviour, local_peer_id)
+}
+
+fn upgrade_transport<T>(transport: T, local_key: identity::Keypair) -> Boxed<(PeerId, StreamMuxerBox), io::Error>
+where
+    T: Transport + Clone + Send + Sync + 'static,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::Error: Send + Sync + 'static,
+    T::Listener: Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+    T::Dial: Send + 'static,
+{
+    transport
+        .upgrade(upgrade::Version::V1)
+        .authenticate(PlainText2Config { local_public_key: local_key.public() })
+        .multiplex(MplexConfig::new())
+        .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        .boxed()
+}
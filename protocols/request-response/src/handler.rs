@@ -322,5 +322,16 @@ where
 
         Poll::Pending
     }
+
+    fn poll_close(&mut self, _: &mut Context<'_>) -> Poll<()> {
+        // The substreams of the requests being sent or answered are awaited by
+        // the connection itself, only the work not yet handed over to a
+        // substream is left to be done.
+        if self.outbound.is_empty() && self.inbound.is_empty() && self.pending_events.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
 }
 
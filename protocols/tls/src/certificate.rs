@@ -0,0 +1,337 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Generation and verification of libp2p TLS certificates.
+//!
+//! A libp2p certificate is a self-signed X.509 certificate carrying the libp2p public key
+//! extension. The extension contains the host public key of the node as well as a signature,
+//! made with the host private key, over the public key of the certificate.
+
+use libp2p_core::identity;
+use std::{error, fmt, time::SystemTime};
+use yasna::{ASN1Error, Tag};
+
+/// The Object Identifier of the libp2p public key extension, allocated by IANA to the libp2p
+/// project at Protocol Labs.
+const P2P_EXT_OID: [u64; 9] = [1, 3, 6, 1, 4, 1, 53594, 1, 1];
+
+/// Prefix of the message signed by the host key, followed by the DER encoding of the
+/// `SubjectPublicKeyInfo` of the certificate.
+const P2P_SIGNING_PREFIX: [u8; 21] = *b"libp2p-tls-handshake:";
+
+/// Signature algorithm of the certificate keys that we generate.
+static P2P_SIGNATURE_ALGORITHM: &rcgen::SignatureAlgorithm = &rcgen::PKCS_ECDSA_P256_SHA256;
+
+/// Signature algorithms accepted for the self-signature of a remote certificate.
+static SUPPORTED_SIGNATURE_ALGORITHMS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+/// Generates a self-signed certificate carrying the public key of `keypair` in the libp2p
+/// public key extension.
+///
+/// A fresh certificate key is generated on every call.
+pub fn make_certificate(keypair: &identity::Keypair) -> Result<rcgen::Certificate, GenError> {
+    let certificate_keypair = rcgen::KeyPair::generate(P2P_SIGNATURE_ALGORITHM)?;
+
+    let mut message = Vec::from(&P2P_SIGNING_PREFIX[..]);
+    message.extend(certificate_keypair.public_key_der());
+    let signature = keypair.sign(&message)?;
+
+    let public_key = keypair.public().into_protobuf_encoding();
+    let content = yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_bytes(&public_key);
+            writer.next().write_bytes(&signature);
+        })
+    });
+
+    let mut params = rcgen::CertificateParams::new(Vec::new());
+    params.alg = P2P_SIGNATURE_ALGORITHM;
+    params.key_pair = Some(certificate_keypair);
+    params.custom_extensions.push(rcgen::CustomExtension::from_oid_content(&P2P_EXT_OID, content));
+
+    Ok(rcgen::Certificate::from_params(params)?)
+}
+
+/// Verifies a certificate presented by a server and returns the libp2p public key it carries.
+pub fn verify_server_certificate(der: &[u8]) -> Result<identity::PublicKey, VerificationError> {
+    let anchor = webpki::trust_anchor_util::cert_der_as_trust_anchor(der)?;
+    let certificate = webpki::EndEntityCert::from(der)?;
+    certificate.verify_is_valid_tls_server_cert(
+        SUPPORTED_SIGNATURE_ALGORITHMS,
+        &webpki::TLSServerTrustAnchors(&[anchor]),
+        &[],
+        now()?,
+    )?;
+    extract_public_key(der)
+}
+
+/// Verifies a certificate presented by a client and returns the libp2p public key it carries.
+pub fn verify_client_certificate(der: &[u8]) -> Result<identity::PublicKey, VerificationError> {
+    let anchor = webpki::trust_anchor_util::cert_der_as_trust_anchor(der)?;
+    let certificate = webpki::EndEntityCert::from(der)?;
+    certificate.verify_is_valid_tls_client_cert(
+        SUPPORTED_SIGNATURE_ALGORITHMS,
+        &webpki::TLSClientTrustAnchors(&[anchor]),
+        &[],
+        now()?,
+    )?;
+    extract_public_key(der)
+}
+
+/// Returns the current time, against which the validity of certificates is checked.
+fn now() -> Result<webpki::Time, webpki::Error> {
+    webpki::Time::try_from(SystemTime::now()).map_err(|_| webpki::Error::InvalidCertValidity)
+}
+
+/// Extracts the libp2p public key from the extension of a certificate and checks that the
+/// signature it contains covers the public key of the certificate.
+///
+/// This does not check the self-signature of the certificate itself.
+fn extract_public_key(der: &[u8]) -> Result<identity::PublicKey, VerificationError> {
+    let (spki, extensions) = parse_tbs_certificate(der)?;
+
+    let mut libp2p_extension = None;
+    for (oid, value) in extensions {
+        if oid.components().as_slice() != P2P_EXT_OID {
+            continue
+        }
+        if libp2p_extension.is_some() {
+            return Err(VerificationError::DuplicateExtension)
+        }
+        libp2p_extension = Some(value);
+    }
+    let extension = libp2p_extension.ok_or(VerificationError::MissingExtension)?;
+
+    let (public_key, signature) = yasna::parse_der(&extension, |reader| {
+        reader.read_sequence(|reader| {
+            let public_key = reader.next().read_bytes()?;
+            let signature = reader.next().read_bytes()?;
+            Ok((public_key, signature))
+        })
+    })?;
+
+    let public_key = identity::PublicKey::from_protobuf_encoding(&public_key)
+        .map_err(|_| VerificationError::InvalidPublicKey)?;
+
+    let mut message = Vec::from(&P2P_SIGNING_PREFIX[..]);
+    message.extend(spki);
+    if !public_key.verify(&message, &signature) {
+        return Err(VerificationError::InvalidSignature)
+    }
+
+    Ok(public_key)
+}
+
+/// Identifier and value of a certificate extension.
+type Extension = (yasna::models::ObjectIdentifier, Vec<u8>);
+
+/// Parses the `TBSCertificate` of a DER-encoded certificate and returns the DER encoding of its
+/// `SubjectPublicKeyInfo` together with its extensions.
+fn parse_tbs_certificate(der: &[u8]) -> Result<(Vec<u8>, Vec<Extension>), ASN1Error> {
+    let (spki, extensions) = yasna::parse_der(der, |reader| {
+        reader.read_sequence(|reader| {
+            let tbs = reader.next().read_sequence(|reader| {
+                // version
+                reader.read_optional(|reader| {
+                    reader.read_tagged(Tag::context(0), |reader| reader.read_u8())
+                })?;
+                // serialNumber, signature, issuer, validity, subject
+                for _ in 0 .. 5 {
+                    reader.next().read_der()?;
+                }
+                let spki = reader.next().read_der()?;
+                // issuerUniqueID, subjectUniqueID, extensions
+                let mut extensions = None;
+                while let Some(value) = reader.read_optional(|reader| reader.read_tagged_der())? {
+                    if value.tag() == Tag::context(3) {
+                        extensions = Some(value.value().to_vec());
+                    }
+                }
+                Ok((spki, extensions))
+            })?;
+            // signatureAlgorithm, signatureValue
+            reader.next().read_der()?;
+            reader.next().read_der()?;
+            Ok(tbs)
+        })
+    })?;
+
+    let extensions = match extensions {
+        Some(extensions) => yasna::parse_der(&extensions, |reader| {
+            reader.collect_sequence_of(|reader| {
+                reader.read_sequence(|reader| {
+                    let oid = reader.next().read_oid()?;
+                    reader.read_default(false, |reader| reader.read_bool())?;
+                    let value = reader.next().read_bytes()?;
+                    Ok((oid, value))
+                })
+            })
+        })?,
+        None => Vec::new(),
+    };
+
+    Ok((spki, extensions))
+}
+
+/// Error that can happen when generating a certificate.
+#[derive(Debug)]
+pub enum GenError {
+    /// Failed to generate the certificate.
+    Certificate(rcgen::RcgenError),
+    /// Failed to sign the certificate public key with the host key.
+    Signing(identity::error::SigningError),
+}
+
+impl fmt::Display for GenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenError::Certificate(err) => write!(f, "Failed to generate certificate: {}", err),
+            GenError::Signing(err) => write!(f, "Failed to sign certificate key: {}", err),
+        }
+    }
+}
+
+impl error::Error for GenError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            GenError::Certificate(_) => None,
+            GenError::Signing(err) => Some(err),
+        }
+    }
+}
+
+impl From<rcgen::RcgenError> for GenError {
+    fn from(err: rcgen::RcgenError) -> Self {
+        GenError::Certificate(err)
+    }
+}
+
+impl From<identity::error::SigningError> for GenError {
+    fn from(err: identity::error::SigningError) -> Self {
+        GenError::Signing(err)
+    }
+}
+
+/// Error that can happen when verifying a remote certificate.
+#[derive(Debug)]
+pub enum VerificationError {
+    /// The certificate is malformed, expired or its self-signature is invalid.
+    WebPki(webpki::Error),
+    /// The certificate could not be parsed.
+    Asn1(ASN1Error),
+    /// The certificate does not carry the libp2p public key extension.
+    MissingExtension,
+    /// The certificate carries the libp2p public key extension more than once.
+    DuplicateExtension,
+    /// The public key in the libp2p extension could not be decoded.
+    InvalidPublicKey,
+    /// The signature in the libp2p extension does not match the certificate key.
+    InvalidSignature,
+}
+
+impl fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerificationError::WebPki(err) => write!(f, "Invalid certificate: {:?}", err),
+            VerificationError::Asn1(err) => write!(f, "Failed to parse certificate: {}", err),
+            VerificationError::MissingExtension =>
+                f.write_str("Certificate lacks the libp2p public key extension"),
+            VerificationError::DuplicateExtension =>
+                f.write_str("Certificate has more than one libp2p public key extension"),
+            VerificationError::InvalidPublicKey =>
+                f.write_str("Invalid public key in the libp2p extension"),
+            VerificationError::InvalidSignature =>
+                f.write_str("Invalid signature in the libp2p extension"),
+        }
+    }
+}
+
+impl error::Error for VerificationError {}
+
+impl From<webpki::Error> for VerificationError {
+    fn from(err: webpki::Error) -> Self {
+        VerificationError::WebPki(err)
+    }
+}
+
+impl From<ASN1Error> for VerificationError {
+    fn from(err: ASN1Error) -> Self {
+        VerificationError::Asn1(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn certificate_carries_public_key() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let certificate = make_certificate(&keypair).unwrap().serialize_der().unwrap();
+
+        let server_key = verify_server_certificate(&certificate).unwrap();
+        let client_key = verify_client_certificate(&certificate).unwrap();
+        assert_eq!(server_key, keypair.public());
+        assert_eq!(client_key, keypair.public());
+    }
+
+    #[test]
+    fn certificate_signed_by_other_key_is_rejected() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let other = identity::Keypair::generate_ed25519();
+        let certificate = make_certificate(&keypair).unwrap();
+
+        // Re-sign the certificate key with a different host key while claiming `keypair`.
+        let mut message = Vec::from(&P2P_SIGNING_PREFIX[..]);
+        message.extend(certificate.get_key_pair().public_key_der());
+        let signature = other.sign(&message).unwrap();
+        let content = yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                writer.next().write_bytes(&keypair.public().into_protobuf_encoding());
+                writer.next().write_bytes(&signature);
+            })
+        });
+
+        let mut params = rcgen::CertificateParams::new(Vec::new());
+        params.alg = P2P_SIGNATURE_ALGORITHM;
+        params.key_pair = Some(rcgen::KeyPair::from_der(
+            &certificate.serialize_private_key_der()).unwrap());
+        params.custom_extensions.push(rcgen::CustomExtension::from_oid_content(&P2P_EXT_OID, content));
+        let forged = rcgen::Certificate::from_params(params).unwrap().serialize_der().unwrap();
+
+        match verify_server_certificate(&forged) {
+            Err(VerificationError::InvalidSignature) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! TLS 1.3 security upgrade for libp2p, as described in the
+//! [libp2p TLS specification](https://github.com/libp2p/specs/blob/master/tls/tls.md).
+//!
+//! Each side of a connection presents a self-signed X.509 certificate, generated from a fresh
+//! certificate key. The certificate carries the libp2p public key extension, which contains the
+//! identity public key of the node together with a signature over the certificate key, made
+//! with the identity private key. Certificates are not checked against any trust anchors.
+//! Instead, the remote identity public key is taken from the extension once its signature has
+//! been verified, and the upgrade yields the corresponding `PeerId`.
+//!
+//! The output of the upgrade is a pair consisting of the `PeerId` of the remote and a
+//! `TlsStream` representing the established session, implementing `futures::io::AsyncRead`
+//! and `futures::io::AsyncWrite`.
+//!
+//! # Usage
+//!
+//! Example:
+//!
+//! ```
+//! use libp2p_core::{identity, Transport, upgrade};
+//! use libp2p_tcp::TcpConfig;
+//! use libp2p_tls::TlsConfig;
+//!
+//! # fn main() {
+//! let id_keys = identity::Keypair::generate_ed25519();
+//! let tls = TlsConfig::new(&id_keys).unwrap();
+//! let builder = TcpConfig::new().upgrade(upgrade::Version::V1).authenticate(tls);
+//! // let transport = builder.multiplex(...);
+//! # }
+//! ```
+
+pub mod certificate;
+mod error;
+mod io;
+mod verifier;
+
+pub use certificate::GenError;
+pub use error::TlsError;
+pub use io::TlsStream;
+
+use futures::prelude::*;
+use libp2p_core::{identity, InboundUpgrade, OutboundUpgrade, PeerId, UpgradeInfo};
+use std::{fmt, iter, pin::Pin, sync::Arc};
+use verifier::Libp2pCertificateVerifier;
+
+/// The ALPN protocol negotiated during the TLS handshake.
+const P2P_ALPN: &[u8] = b"libp2p";
+
+/// Name of the server passed to the TLS client. It is neither sent to nor checked against the
+/// certificate of the remote.
+const SERVER_NAME: &str = "libp2p";
+
+/// Configuration of the `/tls/1.0.0` security upgrade.
+///
+/// The same certificate, carrying the public key of the local node, is presented on all the
+/// connections.
+#[derive(Clone)]
+pub struct TlsConfig {
+    client: rustls::ClientConfig,
+    server: rustls::ServerConfig,
+}
+
+impl TlsConfig {
+    /// Creates a new configuration that authenticates the local node with the given keypair.
+    pub fn new(keypair: &identity::Keypair) -> Result<Self, GenError> {
+        let certificate = certificate::make_certificate(keypair)?;
+        let key = rustls::PrivateKey(certificate.serialize_private_key_der());
+        let certificate = rustls::Certificate(certificate.serialize_der()?);
+
+        let mut client = rustls::ClientConfig::new();
+        client.versions = vec![rustls::ProtocolVersion::TLSv1_3];
+        client.alpn_protocols = vec![P2P_ALPN.to_vec()];
+        client.enable_sni = false;
+        client.set_single_client_cert(vec![certificate.clone()], key.clone())
+            .expect("Client cert key DER is valid; qed");
+
+        // The certificate verifier is set for every upgrade, see `upgrade_inbound`.
+        let mut server = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+        server.versions = vec![rustls::ProtocolVersion::TLSv1_3];
+        server.alpn_protocols = vec![P2P_ALPN.to_vec()];
+        server.set_single_cert(vec![certificate], key)
+            .expect("Server cert key DER is valid; qed");
+
+        Ok(TlsConfig { client, server })
+    }
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TlsConfig")
+    }
+}
+
+impl UpgradeInfo for TlsConfig {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(b"/tls/1.0.0")
+    }
+}
+
+impl<T> InboundUpgrade<T> for TlsConfig
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = (PeerId, TlsStream<T>);
+    type Error = TlsError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send>>;
+
+    fn upgrade_inbound(self, socket: T, _: Self::Info) -> Self::Future {
+        // Every upgrade gets its own verifier, which records the public key of the remote.
+        let verifier = Arc::new(Libp2pCertificateVerifier::new());
+        let mut config = self.server;
+        config.set_client_certificate_verifier(verifier.clone());
+        let acceptor = async_tls::TlsAcceptor::from(Arc::new(config));
+
+        Box::pin(async move {
+            let stream = acceptor.accept(socket).await?;
+            let peer_id = verifier.remote_peer_id().ok_or(TlsError::AuthenticationFailed)?;
+            Ok((peer_id, TlsStream::server(stream)))
+        })
+    }
+}
+
+impl<T> OutboundUpgrade<T> for TlsConfig
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = (PeerId, TlsStream<T>);
+    type Error = TlsError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send>>;
+
+    fn upgrade_outbound(self, socket: T, _: Self::Info) -> Self::Future {
+        // Every upgrade gets its own verifier, which records the public key of the remote.
+        let verifier = Arc::new(Libp2pCertificateVerifier::new());
+        let mut config = self.client;
+        config.dangerous().set_certificate_verifier(verifier.clone());
+        let connector = async_tls::TlsConnector::from(Arc::new(config));
+
+        Box::pin(async move {
+            let stream = connector.connect(SERVER_NAME, socket).await?;
+            let peer_id = verifier.remote_peer_id().ok_or(TlsError::AuthenticationFailed)?;
+            Ok((peer_id, TlsStream::client(stream)))
+        })
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Verification of the certificates presented during the TLS handshake.
+
+use crate::certificate;
+use libp2p_core::{identity, PeerId};
+use std::sync::Mutex;
+
+/// Verifies the certificate presented by the remote, whether it acts as TLS client or server,
+/// and records the libp2p public key it carries.
+///
+/// Client authentication is mandatory.
+pub(crate) struct Libp2pCertificateVerifier {
+    remote_public_key: Mutex<Option<identity::PublicKey>>,
+}
+
+impl Libp2pCertificateVerifier {
+    pub(crate) fn new() -> Self {
+        Libp2pCertificateVerifier { remote_public_key: Mutex::new(None) }
+    }
+
+    /// Returns the `PeerId` of the remote, if its certificate has been successfully verified.
+    pub(crate) fn remote_peer_id(&self) -> Option<PeerId> {
+        self.remote_public_key.lock()
+            .expect("lock is never poisoned")
+            .clone()
+            .map(identity::PublicKey::into_peer_id)
+    }
+
+    fn record(&self, public_key: identity::PublicKey) {
+        *self.remote_public_key.lock().expect("lock is never poisoned") = Some(public_key);
+    }
+}
+
+impl rustls::ServerCertVerifier for Libp2pCertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &rustls::RootCertStore,
+        presented_certs: &[rustls::Certificate],
+        _dns_name: webpki::DNSNameRef<'_>,
+        _ocsp_response: &[u8],
+    ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        let certificate = single_certificate(presented_certs)?;
+        let public_key = certificate::verify_server_certificate(&certificate.0)
+            .map_err(|err| rustls::TLSError::General(err.to_string()))?;
+        self.record(public_key);
+        Ok(rustls::ServerCertVerified::assertion())
+    }
+}
+
+impl rustls::ClientCertVerifier for Libp2pCertificateVerifier {
+    fn offer_client_auth(&self) -> bool {
+        true
+    }
+
+    fn client_auth_mandatory(&self, _sni: Option<&webpki::DNSName>) -> Option<bool> {
+        Some(true)
+    }
+
+    fn client_auth_root_subjects(
+        &self,
+        _sni: Option<&webpki::DNSName>,
+    ) -> Option<rustls::DistinguishedNames> {
+        Some(Vec::new())
+    }
+
+    fn verify_client_cert(
+        &self,
+        presented_certs: &[rustls::Certificate],
+        _sni: Option<&webpki::DNSName>,
+    ) -> Result<rustls::ClientCertVerified, rustls::TLSError> {
+        let certificate = single_certificate(presented_certs)?;
+        let public_key = certificate::verify_client_certificate(&certificate.0)
+            .map_err(|err| rustls::TLSError::General(err.to_string()))?;
+        self.record(public_key);
+        Ok(rustls::ClientCertVerified::assertion())
+    }
+}
+
+/// Libp2p certificates are self-signed, hence exactly one certificate must be presented.
+fn single_certificate(
+    presented_certs: &[rustls::Certificate],
+) -> Result<&rustls::Certificate, rustls::TLSError> {
+    match presented_certs {
+        [certificate] => Ok(certificate),
+        [] => Err(rustls::TLSError::NoCertificatesPresented),
+        _ => Err(rustls::TLSError::General("Expected exactly one certificate".into())),
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::prelude::*;
+use libp2p_core::identity;
+use libp2p_core::transport::{Transport, ListenerEvent};
+use libp2p_core::upgrade;
+use libp2p_tcp::TcpConfig;
+use libp2p_tls::TlsConfig;
+use quickcheck::QuickCheck;
+use std::convert::TryInto;
+
+#[allow(dead_code)]
+fn core_upgrade_compat() {
+    // Tests API compatibility with the libp2p-core upgrade API,
+    // i.e. if it compiles, the "test" is considered a success.
+    let id_keys = identity::Keypair::generate_ed25519();
+    let tls = TlsConfig::new(&id_keys).unwrap();
+    let _ = TcpConfig::new().upgrade(upgrade::Version::V1).authenticate(tls);
+}
+
+#[test]
+fn authenticated_echo() {
+    fn prop(mut messages: Vec<Message>) -> bool {
+        messages.truncate(5);
+        let server_id = identity::Keypair::generate_ed25519();
+        let client_id = identity::Keypair::generate_secp256k1();
+
+        let server_peer_id = server_id.public().into_peer_id();
+        let client_peer_id = client_id.public().into_peer_id();
+
+        let server_tls = TlsConfig::new(&server_id).unwrap();
+        let server_transport = TcpConfig::new()
+            .and_then(move |output, endpoint| {
+                upgrade::apply(output, server_tls, endpoint, upgrade::Version::V1)
+            });
+        let client_tls = TlsConfig::new(&client_id).unwrap();
+        let client_transport = TcpConfig::new()
+            .and_then(move |output, endpoint| {
+                upgrade::apply(output, client_tls, endpoint, upgrade::Version::V1)
+            });
+
+        async_std::task::block_on(async move {
+            let mut server = server_transport
+                .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .unwrap();
+
+            let server_address = server.try_next()
+                .await
+                .expect("some event")
+                .expect("no error")
+                .into_new_address()
+                .expect("listen address");
+
+            let outbound_msgs = messages.clone();
+            let client_fut = async {
+                let (peer_id, mut client_session) = client_transport.dial(server_address)
+                    .unwrap()
+                    .await
+                    .expect("no error");
+                assert_eq!(peer_id, server_peer_id);
+
+                for m in outbound_msgs {
+                    let n = (m.0.len() as u64).to_be_bytes();
+                    client_session.write_all(&n[..]).await.expect("len written");
+                    client_session.write_all(&m.0).await.expect("no error")
+                }
+                client_session.flush().await.expect("no error");
+                client_session
+            };
+
+            let server_fut = async {
+                let (peer_id, mut server_session) = server.try_next()
+                    .await
+                    .expect("some event")
+                    .map(ListenerEvent::into_upgrade)
+                    .expect("no error")
+                    .map(|client| client.0)
+                    .expect("listener upgrade")
+                    .await
+                    .expect("no error");
+                assert_eq!(peer_id, client_peer_id);
+
+                for m in messages {
+                    let mut n = [0; 8];
+                    server_session.read_exact(&mut n).await.expect("len read");
+                    let len = u64::from_be_bytes(n);
+                    let mut server_buffer = vec![0; len.try_into().unwrap()];
+                    server_session.read_exact(&mut server_buffer).await.expect("no error");
+                    assert_eq!(server_buffer, m.0)
+                }
+            };
+
+            // The client session is kept alive until the server has read all the messages.
+            future::join(server_fut, client_fut).await;
+        });
+        true
+    }
+    QuickCheck::new().max_tests(10).quickcheck(prop as fn(Vec<Message>) -> bool)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Message(Vec<u8>);
+
+impl quickcheck::Arbitrary for Message {
+    fn arbitrary<G: quickcheck::Gen>(g: &mut G) -> Self {
+        let s = 1 + g.next_u32() % (128 * 1024);
+        let mut v = vec![0; s.try_into().unwrap()];
+        g.fill_bytes(&mut v);
+        Message(v)
+    }
+}
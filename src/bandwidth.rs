@@ -28,6 +28,9 @@ use std::{
 
 /// Wraps around a `Transport` and counts the number of bytes that go through all the opened
 /// connections.
+///
+/// See [`Swarm::protocol_stats`](crate::swarm::ExpandedSwarm::protocol_stats) for the bytes
+/// sent and received on the substreams, per connection, peer and protocol.
 #[derive(Clone)]
 pub struct BandwidthLogging<TInner> {
     inner: TInner,
@@ -18,6 +18,7 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+use crate::{ConnectionManager, PeerStore};
 use crate::protocols_handler::{IntoProtocolsHandler, ProtocolsHandler};
 use libp2p_core::{ConnectedPoint, Multiaddr, PeerId, connection::{ConnectionId, ListenerId}};
 use std::{error, task::Context, task::Poll};
@@ -111,6 +112,9 @@ pub trait NetworkBehaviour: Send + 'static {
     {}
 
     /// Informs the behaviour that the [`ConnectedPoint`] of an existing connection has changed.
+    ///
+    /// If `old` is relayed but `new` is not (see [`ConnectedPoint::is_relayed`]),
+    /// the connection has been migrated to a direct connection.
     fn inject_address_change(
         &mut self,
         _: &PeerId,
@@ -200,6 +204,18 @@ pub trait PollParameters {
 
     /// Returns the peer id of the local node.
     fn local_peer_id(&self) -> &PeerId;
+
+    /// Returns the [`PeerStore`] shared by all behaviours, holding the addresses, protocols,
+    /// agent versions and latencies known for remote peers.
+    fn peer_store(&self) -> &PeerStore;
+
+    /// Returns the [`PeerStore`] shared by all behaviours, in order to record what they learn
+    /// about remote peers, e.g. the protocols and agent version reported through identify.
+    fn peer_store_mut(&mut self) -> &mut PeerStore;
+
+    /// Returns the [`ConnectionManager`] shared by all behaviours, through which they tag the
+    /// peers whose connections are valuable to them and protect those that must be kept.
+    fn connection_manager(&mut self) -> &mut ConnectionManager;
 }
 
 /// When deriving [`NetworkBehaviour`] this trait must by default be implemented for all the
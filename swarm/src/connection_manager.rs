@@ -0,0 +1,314 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Trimming of the established connections of a `Swarm` that exceed a budget.
+
+use futures::prelude::*;
+use libp2p_core::{PeerId, connection::ConnectionId};
+use std::{collections::{HashMap, HashSet}, pin::Pin, task::{Context, Poll}, time::Duration};
+use wasm_timer::{Delay, Instant};
+
+/// Keeps the number of established connections of a `Swarm` within a budget.
+///
+/// Once the number of established connections exceeds the high watermark, the
+/// least valuable connections are closed until the low watermark is reached.
+/// The value of a connection is the sum of the tags of its peer, set with
+/// [`ConnectionManager::tag_peer`]. Connections established less than the grace
+/// period ago, as well as the connections of peers protected with
+/// [`ConnectionManager::protect`], are never closed.
+///
+/// The tags of a peer are forgotten once the last connection to the peer closed, unless
+/// the peer is protected. Protections are kept until they are removed with
+/// [`ConnectionManager::unprotect`].
+///
+/// Behaviours access the connection manager of their `Swarm` through
+/// [`PollParameters::connection_manager`](crate::PollParameters::connection_manager).
+#[derive(Debug, Default)]
+pub struct ConnectionManager {
+    /// The watermarks and grace period, if trimming is enabled.
+    limits: Option<Limits>,
+    /// The established connections that are not closing yet, with their peer and the time
+    /// they were established at.
+    connections: HashMap<ConnectionId, (PeerId, Instant)>,
+    /// The tags of the peers and their values.
+    tags: HashMap<PeerId, HashMap<String, i32>>,
+    /// The tags protecting the peers.
+    protected: HashMap<PeerId, HashSet<String>>,
+    /// Wakes up the `Swarm` once the grace period of a connection has elapsed, while the number
+    /// of connections is above the high watermark.
+    delay: Option<Delay>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    low_watermark: usize,
+    high_watermark: usize,
+    grace_period: Duration,
+}
+
+impl ConnectionManager {
+    /// Creates a `ConnectionManager` trimming the connections down to `low_watermark` once there
+    /// are more than `high_watermark`, sparing the connections younger than `grace_period`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `low_watermark` is greater than `high_watermark`.
+    pub fn new(low_watermark: usize, high_watermark: usize, grace_period: Duration) -> Self {
+        assert!(low_watermark <= high_watermark,
+            "The low watermark must not be greater than the high watermark");
+        ConnectionManager {
+            limits: Some(Limits { low_watermark, high_watermark, grace_period }),
+            .. ConnectionManager::default()
+        }
+    }
+
+    /// Sets the value of a tag of a peer, replacing the previous value of the tag.
+    ///
+    /// The tags of a peer that is not protected are removed once the last connection
+    /// to the peer closed.
+    pub fn tag_peer(&mut self, peer_id: &PeerId, tag: impl Into<String>, value: i32) {
+        self.tags.entry(peer_id.clone()).or_default().insert(tag.into(), value);
+    }
+
+    /// Removes a tag of a peer.
+    pub fn untag_peer(&mut self, peer_id: &PeerId, tag: &str) {
+        if let Some(tags) = self.tags.get_mut(peer_id) {
+            tags.remove(tag);
+            if tags.is_empty() {
+                self.tags.remove(peer_id);
+            }
+        }
+    }
+
+    /// Returns the value of a peer, i.e. the sum of the values of its tags.
+    pub fn peer_value(&self, peer_id: &PeerId) -> i32 {
+        self.tags.get(peer_id).map_or(0, |tags| tags.values().fold(0, |v, t| v.saturating_add(*t)))
+    }
+
+    /// Protects the connections of a peer from being closed, until [`ConnectionManager::unprotect`]
+    /// is called with the same tag.
+    ///
+    /// The protection is kept while the peer is disconnected, which also keeps its tags.
+    pub fn protect(&mut self, peer_id: &PeerId, tag: impl Into<String>) {
+        self.protected.entry(peer_id.clone()).or_default().insert(tag.into());
+    }
+
+    /// Removes a protection of a peer. Returns whether the peer is still protected by other tags.
+    pub fn unprotect(&mut self, peer_id: &PeerId, tag: &str) -> bool {
+        if let Some(tags) = self.protected.get_mut(peer_id) {
+            tags.remove(tag);
+            if tags.is_empty() {
+                self.protected.remove(peer_id);
+                // The tags of a peer are only kept while it is connected or protected.
+                if !self.connections.values().any(|(p, _)| p == peer_id) {
+                    self.tags.remove(peer_id);
+                }
+                return false
+            }
+            return true
+        }
+        false
+    }
+
+    /// Returns whether the connections of a peer are protected from being closed.
+    pub fn is_protected(&self, peer_id: &PeerId) -> bool {
+        self.protected.contains_key(peer_id)
+    }
+
+    /// Returns the number of established connections that are not being closed.
+    pub fn num_connections(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Records a new established connection.
+    pub(crate) fn inject_connection_established(&mut self, peer_id: &PeerId, id: ConnectionId) {
+        self.connections.insert(id, (peer_id.clone(), Instant::now()));
+    }
+
+    /// Records the closure of a connection.
+    pub(crate) fn inject_connection_closed(&mut self, id: &ConnectionId) {
+        self.connections.remove(id);
+    }
+
+    /// Records that the last connection to a peer closed, forgetting its tags
+    /// unless it is protected.
+    pub(crate) fn inject_disconnected(&mut self, peer_id: &PeerId) {
+        if !self.is_protected(peer_id) {
+            self.tags.remove(peer_id);
+        }
+    }
+
+    /// Returns the connections to close if the number of connections exceeds the high watermark.
+    ///
+    /// The returned connections are considered closed from then on.
+    pub(crate) fn poll(&mut self, cx: &mut Context<'_>) -> Poll<Vec<(PeerId, ConnectionId)>> {
+        let limits = match self.limits {
+            Some(limits) if self.connections.len() > limits.high_watermark => limits,
+            _ => {
+                self.delay = None;
+                return Poll::Pending
+            }
+        };
+
+        let now = Instant::now();
+        let mut candidates = Vec::new();
+        let mut next_candidate: Option<Instant> = None;
+        for (id, (peer_id, established)) in &self.connections {
+            if self.is_protected(peer_id) {
+                continue
+            }
+            let grace_end = *established + limits.grace_period;
+            if grace_end > now {
+                next_candidate = Some(next_candidate.map_or(grace_end, |t| t.min(grace_end)));
+                continue
+            }
+            candidates.push((self.peer_value(peer_id), *established, *id, peer_id.clone()));
+        }
+
+        // Close the least valuable connections first, and the youngest among equally
+        // valuable ones.
+        candidates.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+        let excess = self.connections.len() - limits.low_watermark;
+        let to_close = candidates.into_iter()
+            .take(excess)
+            .map(|(_, _, id, peer_id)| (peer_id, id))
+            .collect::<Vec<_>>();
+        for (_, id) in &to_close {
+            self.connections.remove(id);
+        }
+
+        // Wake up once the next connection leaves its grace period, if we are still
+        // over the budget by then.
+        if self.connections.len() > limits.high_watermark {
+            if let Some(t) = next_candidate {
+                let mut delay = Delay::new_at(t);
+                if Pin::new(&mut delay).poll(cx).is_ready() {
+                    cx.waker().wake_by_ref();
+                } else {
+                    self.delay = Some(delay);
+                }
+            }
+        } else {
+            self.delay = None;
+        }
+
+        if to_close.is_empty() {
+            Poll::Pending
+        } else {
+            log::debug!("Closing {} connections above the high watermark.", to_close.len());
+            Poll::Ready(to_close)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::task::noop_waker;
+    use libp2p_core::{PeerId, connection::ConnectionId};
+    use std::{collections::HashSet, task::{Context, Poll}, thread::sleep, time::Duration};
+    use super::ConnectionManager;
+
+    fn closed(manager: &mut ConnectionManager) -> HashSet<PeerId> {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        match manager.poll(&mut cx) {
+            Poll::Ready(closed) => closed.into_iter().map(|(peer, _)| peer).collect(),
+            Poll::Pending => HashSet::new(),
+        }
+    }
+
+    #[test]
+    fn trims_least_valuable_unprotected_connections() {
+        let mut manager = ConnectionManager::new(2, 4, Duration::from_secs(0));
+        let peers = (0 .. 5).map(|_| PeerId::random()).collect::<Vec<_>>();
+        for (i, peer) in peers.iter().enumerate() {
+            manager.inject_connection_established(peer, ConnectionId::new(i));
+        }
+        manager.tag_peer(&peers[0], "kad", 10);
+        manager.tag_peer(&peers[1], "app", 5);
+        manager.tag_peer(&peers[1], "kad", 1);
+        manager.protect(&peers[2], "mesh");
+        assert_eq!(manager.peer_value(&peers[1]), 6);
+
+        // Three connections are closed, sparing the protected and the most valuable peers.
+        let trimmed = closed(&mut manager);
+        assert_eq!(trimmed, vec![peers[1].clone(), peers[3].clone(), peers[4].clone()].into_iter().collect());
+        assert_eq!(manager.num_connections(), 2);
+
+        // Nothing happens below the high watermark.
+        manager.inject_connection_established(&peers[3], ConnectionId::new(5));
+        assert!(closed(&mut manager).is_empty());
+    }
+
+    #[test]
+    fn spares_connections_in_grace_period() {
+        let mut manager = ConnectionManager::new(0, 1, Duration::from_millis(50));
+        let peers = (0 .. 3).map(|_| PeerId::random()).collect::<Vec<_>>();
+        for (i, peer) in peers.iter().enumerate() {
+            manager.inject_connection_established(peer, ConnectionId::new(i));
+        }
+        assert!(closed(&mut manager).is_empty());
+
+        assert!(!manager.unprotect(&peers[0], "mesh"));
+        manager.protect(&peers[0], "mesh");
+        manager.protect(&peers[0], "kad");
+        assert!(manager.unprotect(&peers[0], "kad"));
+
+        sleep(Duration::from_millis(60));
+        assert_eq!(closed(&mut manager), peers[1 ..].iter().cloned().collect());
+        manager.inject_connection_closed(&ConnectionId::new(0));
+        assert_eq!(manager.num_connections(), 0);
+    }
+
+    #[test]
+    fn forgets_tags_of_disconnected_unprotected_peers() {
+        let mut manager = ConnectionManager::new(2, 4, Duration::from_secs(0));
+        let tagged = PeerId::random();
+        let protected = PeerId::random();
+        manager.inject_connection_established(&tagged, ConnectionId::new(0));
+        manager.inject_connection_established(&protected, ConnectionId::new(1));
+        manager.tag_peer(&tagged, "kad", 10);
+        manager.tag_peer(&protected, "kad", 10);
+        manager.protect(&protected, "mesh");
+
+        for (i, peer) in [&tagged, &protected].iter().enumerate() {
+            manager.inject_connection_closed(&ConnectionId::new(i));
+            manager.inject_disconnected(peer);
+        }
+        assert_eq!(manager.peer_value(&tagged), 0);
+        assert!(manager.tags.get(&tagged).is_none());
+
+        // The protection is explicit and thus kept, along with the tags, until removed.
+        assert!(manager.is_protected(&protected));
+        assert_eq!(manager.peer_value(&protected), 10);
+        assert!(!manager.unprotect(&protected, "mesh"));
+        assert!(manager.tags.is_empty());
+        assert!(manager.protected.is_empty());
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let mut manager = ConnectionManager::default();
+        for i in 0 .. 10 {
+            manager.inject_connection_established(&PeerId::random(), ConnectionId::new(i));
+        }
+        assert!(closed(&mut manager).is_empty());
+    }
+}
@@ -54,6 +54,9 @@
 //!
 
 mod behaviour;
+mod connection_manager;
+mod peer_store;
+mod protocol_stats;
 mod registry;
 #[cfg(test)]
 mod test;
@@ -70,6 +73,14 @@ pub use behaviour::{
     NotifyHandler,
     DialPeerCondition
 };
+pub use connection_manager::ConnectionManager;
+pub use peer_store::{
+    AddressRecord,
+    AddressSource,
+    PeerRecord,
+    PeerStore
+};
+pub use protocol_stats::{Bandwidth, ByteCounts, NegotiatedSubstream, ProtocolStats, SubstreamCounts};
 pub use protocols_handler::{
     IntoProtocolsHandler,
     IntoProtocolsHandlerSelect,
@@ -96,7 +107,6 @@ use libp2p_core::{
     Executor,
     Transport,
     Multiaddr,
-    Negotiated,
     PeerId,
     connection::{
         ConnectionError,
@@ -108,7 +118,6 @@ use libp2p_core::{
         IntoConnectionHandler,
         ListenerId,
         PendingConnectionError,
-        Substream
     },
     transport::{TransportError, boxed::Boxed as BoxTransport},
     muxing::{StreamMuxer, StreamMuxerBox},
@@ -123,10 +132,11 @@ use libp2p_core::{
 };
 use registry::{Addresses, AddressIntoIter};
 use smallvec::SmallVec;
-use std::{error, fmt, hash::Hash, io, ops::{Deref, DerefMut}, pin::Pin, task::{Context, Poll}};
+use std::{error, fmt, hash::Hash, io, ops::{Deref, DerefMut}, pin::Pin, task::{Context, Poll}, time::Duration};
 use std::collections::HashSet;
 use std::num::{NonZeroU32, NonZeroUsize};
 use upgrade::UpgradeInfoSend as _;
+use wasm_timer::Delay;
 
 /// Contains the state of the network, plus the way it should behave.
 pub type Swarm<TBehaviour, TConnInfo = PeerId> = ExpandedSwarm<
@@ -137,12 +147,6 @@ pub type Swarm<TBehaviour, TConnInfo = PeerId> = ExpandedSwarm<
     TConnInfo,
 >;
 
-/// Substream for which a protocol has been chosen.
-///
-/// Implements the [`AsyncRead`](futures::io::AsyncRead) and
-/// [`AsyncWrite`](futures::io::AsyncWrite) traits.
-pub type NegotiatedSubstream = Negotiated<Substream<StreamMuxerBox>>;
-
 /// Event generated by the `Swarm`.
 #[derive(Debug)]
 pub enum SwarmEvent<TBvEv, THandleErr> {
@@ -152,6 +156,8 @@ pub enum SwarmEvent<TBvEv, THandleErr> {
     ConnectionEstablished {
         /// Identity of the peer that we have connected to.
         peer_id: PeerId,
+        /// Identifier of the connection that has been opened.
+        connection_id: ConnectionId,
         /// Endpoint of the connection that has been opened.
         endpoint: ConnectedPoint,
         /// Number of established connections to this peer, including the one that has just been
@@ -163,13 +169,18 @@ pub enum SwarmEvent<TBvEv, THandleErr> {
     ConnectionClosed {
         /// Identity of the peer that we have connected to.
         peer_id: PeerId,
+        /// Identifier of the connection that has been closed.
+        connection_id: ConnectionId,
         /// Endpoint of the connection that has been closed.
         endpoint: ConnectedPoint,
         /// Number of other remaining connections to this same peer.
         num_established: u32,
         /// Reason for the disconnection, if it was not a successful
-        /// active close.
+        /// active close. Connections closed by [`ExpandedSwarm::shutdown`]
+        /// are reported with [`ConnectionError::Shutdown`].
         cause: Option<ConnectionError<NodeHandlerWrapperError<THandleErr>>>,
+        /// The bytes sent and received on the substreams of the connection, per protocol.
+        bandwidth: Bandwidth,
     },
     /// A new connection arrived on a listener and is in the process of protocol negotiation.
     ///
@@ -286,6 +297,25 @@ where
     /// List of nodes for which we deny any incoming connection.
     banned_peers: HashSet<PeerId>,
 
+    /// Knowledge about remote peers shared by all behaviours.
+    peer_store: PeerStore,
+
+    /// Timer of the next removal of the expired addresses of the `peer_store`.
+    peer_store_cleanup: Delay,
+
+    /// Closes the least valuable connections when there are too many.
+    connection_manager: ConnectionManager,
+
+    /// Counts the substreams negotiated on the connections, per protocol.
+    protocol_stats: ProtocolStats,
+
+    /// Whether the `Swarm` is shutting down, see [`ExpandedSwarm::shutdown`].
+    shutting_down: bool,
+
+    /// The connections closed by [`ExpandedSwarm::shutdown`], reported with
+    /// [`ConnectionError::Shutdown`] once closed.
+    shutdown_connections: HashSet<ConnectionId>,
+
     /// Pending event to be delivered to connection handlers
     /// (or dropped if the peer disconnected) before the `behaviour`
     /// can be polled again.
@@ -324,6 +354,27 @@ where
 {
 }
 
+/// Ends the shutdown of a `Swarm` started with [`ExpandedSwarm::shutdown`] when dropped
+/// together with the stream returned by it.
+struct ShutdownGuard<'a, TBehaviour, TInEvent, TOutEvent, THandler, TConnInfo>
+where
+    THandler: IntoProtocolsHandler,
+    TConnInfo: ConnectionInfo<PeerId = PeerId>,
+{
+    swarm: &'a mut ExpandedSwarm<TBehaviour, TInEvent, TOutEvent, THandler, TConnInfo>,
+}
+
+impl<'a, TBehaviour, TInEvent, TOutEvent, THandler, TConnInfo> Drop for
+    ShutdownGuard<'a, TBehaviour, TInEvent, TOutEvent, THandler, TConnInfo>
+where
+    THandler: IntoProtocolsHandler,
+    TConnInfo: ConnectionInfo<PeerId = PeerId>,
+{
+    fn drop(&mut self) {
+        self.swarm.shutting_down = false;
+    }
+}
+
 impl<TBehaviour, TInEvent, TOutEvent, THandler, TConnInfo, THandleErr>
     ExpandedSwarm<TBehaviour, TInEvent, TOutEvent, THandler, TConnInfo>
 where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
@@ -372,8 +423,10 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
 
     /// Initiates a new dialing attempt to the given address.
     pub fn dial_addr(me: &mut Self, addr: Multiaddr) -> Result<(), ConnectionLimit> {
-        let handler = me.behaviour.new_handler();
-        me.network.dial(&addr, handler.into_node_handler_builder()).map(|_id| ())
+        let handler = me.behaviour.new_handler()
+            .into_node_handler_builder()
+            .with_protocol_stats(me.protocol_stats.clone());
+        me.network.dial(&addr, handler).map(|_id| ())
     }
 
     /// Initiates a new dialing attempt to the given peer.
@@ -383,14 +436,13 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
             return Err(DialError::Banned)
         }
 
-        let self_listening = &me.listened_addrs;
-        let mut addrs = me.behaviour.addresses_of_peer(peer_id)
-            .into_iter()
-            .filter(|a| !self_listening.contains(a));
+        let mut addrs = ExpandedSwarm::addresses_of_peer(me, peer_id).into_iter();
 
         let result =
             if let Some(first) = addrs.next() {
-                let handler = me.behaviour.new_handler().into_node_handler_builder();
+                let handler = me.behaviour.new_handler()
+                    .into_node_handler_builder()
+                    .with_protocol_stats(me.protocol_stats.clone());
                 me.network.peer(peer_id.clone())
                     .dial(first, addrs, handler)
                     .map(|_| ())
@@ -409,6 +461,49 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
         result
     }
 
+    /// Returns the addresses to dial a peer on: those of the behaviour, followed by
+    /// those of the peer store, excluding our own listening addresses.
+    fn addresses_of_peer(me: &mut Self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        let mut addrs = me.behaviour.addresses_of_peer(peer_id);
+        for a in me.peer_store.addresses_of_peer(peer_id) {
+            if !addrs.contains(&a) {
+                addrs.push(a)
+            }
+        }
+        let self_listening = &me.listened_addrs;
+        addrs.retain(|a| !self_listening.contains(a));
+        addrs
+    }
+
+    /// Returns the [`PeerStore`] shared by the behaviours.
+    pub fn peer_store(me: &Self) -> &PeerStore {
+        &me.peer_store
+    }
+
+    /// Returns the [`PeerStore`] shared by the behaviours, e.g. in order to record the
+    /// information reported by identify or ping, or to write a snapshot of it.
+    pub fn peer_store_mut(me: &mut Self) -> &mut PeerStore {
+        &mut me.peer_store
+    }
+
+    /// Returns the [`ConnectionManager`] shared by the behaviours.
+    pub fn connection_manager(me: &Self) -> &ConnectionManager {
+        &me.connection_manager
+    }
+
+    /// Returns the [`ConnectionManager`] shared by the behaviours, e.g. in order to tag or
+    /// protect peers on behalf of the application.
+    pub fn connection_manager_mut(me: &mut Self) -> &mut ConnectionManager {
+        &mut me.connection_manager
+    }
+
+    /// Returns the [`ProtocolStats`] of the substreams negotiated on the connections, i.e.
+    /// their number and the bytes sent and received on them, per connection, peer and
+    /// protocol.
+    pub fn protocol_stats(me: &Self) -> &ProtocolStats {
+        &me.protocol_stats
+    }
+
     /// Returns an iterator that produces the list of addresses we're listening on.
     pub fn listeners(me: &Self) -> impl Iterator<Item = &Multiaddr> {
         me.network.listen_addrs()
@@ -461,6 +556,81 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
         me.banned_peers.remove(&peer_id);
     }
 
+    /// Shuts the `Swarm` down gracefully.
+    ///
+    /// Removes all listeners, aborts all dialing attempts and starts an orderly close of
+    /// all established connections. Each connection is closed once its [`ProtocolsHandler`]
+    /// has finished its in-flight work (see [`ProtocolsHandler::poll_close`]). In the meantime,
+    /// the `NetworkBehaviour` keeps being polled, but its dialing requests are ignored and
+    /// new connections are closed right away.
+    ///
+    /// The returned stream produces the events that happen in the `Swarm` during the
+    /// shutdown and ends once all connections are closed or once `timeout` has elapsed.
+    /// The connections closed in an orderly manner by the shutdown are reported with
+    /// [`ConnectionError::Shutdown`] as the cause of the [`SwarmEvent::ConnectionClosed`]
+    /// event, while connections closing for other reasons keep their cause. Once the stream
+    /// is dropped, whether it has ended or not, the `Swarm` accepts dialing requests and new
+    /// connections again.
+    pub fn shutdown(me: &mut Self, timeout: Duration)
+        -> impl Stream<Item = SwarmEvent<TBehaviour::OutEvent, THandleErr>> + '_
+    {
+        me.shutting_down = true;
+
+        let listeners = me.network.listener_ids().collect::<Vec<_>>();
+        for id in listeners {
+            if me.network.remove_listener(id).is_ok() {
+                me.behaviour.inject_listener_closed(id, Ok(()));
+            }
+        }
+        for addr in me.listened_addrs.drain(..) {
+            me.behaviour.inject_expired_listen_addr(&addr);
+        }
+
+        let dialing = me.network.dialing_peers().cloned().collect::<Vec<_>>();
+        for peer_id in dialing {
+            if let Some(mut peer) = me.network.peer(peer_id.clone()).into_dialing() {
+                while let Some(attempt) = peer.attempts().into_first() {
+                    attempt.abort();
+                }
+            }
+            me.behaviour.inject_dial_failure(&peer_id);
+        }
+        for address in me.network.abort_unknown_dials() {
+            let error = io::Error::new(io::ErrorKind::Other, "Dialing aborted by the shutdown.");
+            me.behaviour.inject_addr_reach_failure(None, &address, &error);
+        }
+
+        let connected = me.network.connected_peers().cloned().collect::<Vec<_>>();
+        for peer_id in connected {
+            if let Some(mut peer) = me.network.peer(peer_id).into_connected() {
+                let ids = peer.connections().into_ids().collect::<Vec<_>>();
+                for id in ids {
+                    if let Some(conn) = peer.connection(id) {
+                        conn.start_close();
+                        me.shutdown_connections.insert(id);
+                    }
+                }
+            }
+        }
+
+        // The shutdown lasts as long as the returned stream, even if it is
+        // dropped before it ended.
+        let guard = ShutdownGuard { swarm: me };
+        let mut deadline = Delay::new(timeout);
+        stream::poll_fn(move |cx| {
+            let me = &mut *guard.swarm;
+            if me.network.info().num_connections == 0 {
+                return Poll::Ready(None)
+            }
+            if deadline.poll_unpin(cx).is_ready() {
+                log::debug!("Shutdown timed out with {} remaining connections.",
+                    me.network.info().num_connections);
+                return Poll::Ready(None)
+            }
+            ExpandedSwarm::poll_next_event(Pin::new(me), cx).map(Some)
+        }).fuse()
+    }
+
     /// Returns the next event that happens in the `Swarm`.
     ///
     /// Includes events from the `NetworkBehaviour` but also events about the connections status.
@@ -490,6 +660,11 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
         // across a `Deref`.
         let this = &mut *self;
 
+        while this.peer_store_cleanup.poll_unpin(cx).is_ready() {
+            this.peer_store.remove_expired();
+            this.peer_store_cleanup.reset(this.peer_store.cleanup_interval());
+        }
+
         loop {
             let mut network_not_ready = false;
 
@@ -522,12 +697,21 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
                         log::debug!("Connection established: {:?}; Total (peer): {}.",
                             connection.connected(), num_established);
                         let endpoint = connection.endpoint().clone();
-                        this.behaviour.inject_connection_established(&peer_id, &connection.id(), &endpoint);
+                        let connection_id = connection.id();
+                        if let ConnectedPoint::Dialer { address } = &endpoint {
+                            this.peer_store.on_connected(&peer_id, address);
+                        }
+                        this.connection_manager.inject_connection_established(&peer_id, connection_id);
+                        this.behaviour.inject_connection_established(&peer_id, &connection_id, &endpoint);
                         if num_established.get() == 1 {
                             this.behaviour.inject_connected(&peer_id);
                         }
+                        if this.shutting_down {
+                            connection.start_close();
+                            this.shutdown_connections.insert(connection_id);
+                        }
                         return Poll::Ready(SwarmEvent::ConnectionEstablished {
-                            peer_id, num_established, endpoint
+                            peer_id, connection_id, num_established, endpoint
                         });
                     }
                 },
@@ -539,22 +723,37 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
                     }
                     let info = connected.info;
                     let endpoint = connected.endpoint;
+                    this.connection_manager.inject_connection_closed(&id);
+                    let bandwidth = this.protocol_stats.remove_connection(&id);
+                    log::debug!("Connection {:?} closed after {:?} bytes received and {:?} bytes sent.",
+                        id, bandwidth.total().received, bandwidth.total().sent);
                     this.behaviour.inject_connection_closed(info.peer_id(), &id, &endpoint);
                     if num_established == 0 {
+                        this.peer_store.on_disconnected(info.peer_id());
+                        this.connection_manager.inject_disconnected(info.peer_id());
                         this.behaviour.inject_disconnected(info.peer_id());
                     }
+                    let shutdown = this.shutdown_connections.remove(&id);
+                    let cause = match error {
+                        None if shutdown => Some(ConnectionError::Shutdown),
+                        error => error,
+                    };
                     return Poll::Ready(SwarmEvent::ConnectionClosed {
                         peer_id: info.peer_id().clone(),
+                        connection_id: id,
                         endpoint,
-                        cause: error,
+                        cause,
                         num_established,
+                        bandwidth,
                     });
                 },
                 Poll::Ready(NetworkEvent::IncomingConnection(incoming)) => {
-                    let handler = this.behaviour.new_handler();
+                    let handler = this.behaviour.new_handler()
+                        .into_node_handler_builder()
+                        .with_protocol_stats(this.protocol_stats.clone());
                     let local_addr = incoming.local_addr().clone();
                     let send_back_addr = incoming.send_back_addr().clone();
-                    if let Err(e) = incoming.accept(handler.into_node_handler_builder()) {
+                    if let Err(e) = incoming.accept(handler) {
                         log::warn!("Incoming connection rejected: {:?}", e);
                     }
                     return Poll::Ready(SwarmEvent::IncomingConnection {
@@ -608,6 +807,12 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
                     log::debug!(
                         "Connection attempt to {:?} via {:?} failed with {:?}. Attempts remaining: {}.",
                         peer_id, multiaddr, error, attempts_remaining);
+                    let is_manual = this.peer_store.peer(&peer_id)
+                        .and_then(|r| r.addresses().find(|a| a.address == multiaddr))
+                        .map_or(false, |a| a.source == AddressSource::Manual);
+                    if !is_manual {
+                        this.peer_store.remove_address(&peer_id, &multiaddr);
+                    }
                     this.behaviour.inject_addr_reach_failure(Some(&peer_id), &multiaddr, &error);
                     if attempts_remaining == 0 {
                         this.behaviour.inject_dial_failure(&peer_id);
@@ -630,6 +835,17 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
                 },
             }
 
+            // Close the connections exceeding the budget of the connection manager.
+            if let Poll::Ready(to_close) = this.connection_manager.poll(cx) {
+                for (peer_id, id) in to_close {
+                    if let Some(mut peer) = this.network.peer(peer_id).into_connected() {
+                        if let Some(conn) = peer.connection(id) {
+                            conn.start_close();
+                        }
+                    }
+                }
+            }
+
             // After the network had a chance to make progress, try to deliver
             // the pending event emitted by the behaviour in the previous iteration
             // to the connection handler(s). The pending event must be delivered
@@ -670,7 +886,9 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
                     local_peer_id: &mut this.network.local_peer_id(),
                     supported_protocols: &this.supported_protocols,
                     listened_addrs: &this.listened_addrs,
-                    external_addrs: &this.external_addrs
+                    external_addrs: &this.external_addrs,
+                    peer_store: &mut this.peer_store,
+                    connection_manager: &mut this.connection_manager
                 };
                 this.behaviour.poll(cx, &mut parameters)
             };
@@ -682,10 +900,14 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
                     return Poll::Ready(SwarmEvent::Behaviour(event))
                 },
                 Poll::Ready(NetworkBehaviourAction::DialAddress { address }) => {
-                    let _ = ExpandedSwarm::dial_addr(&mut *this, address);
+                    if this.shutting_down {
+                        log::debug!("Not dialing {:?} while shutting down.", address);
+                    } else {
+                        let _ = ExpandedSwarm::dial_addr(&mut *this, address);
+                    }
                 },
                 Poll::Ready(NetworkBehaviourAction::DialPeer { peer_id, condition }) => {
-                    if this.banned_peers.contains(&peer_id) {
+                    if this.banned_peers.contains(&peer_id) || this.shutting_down {
                         this.behaviour.inject_dial_failure(&peer_id);
                     } else {
                         let condition_matched = match condition {
@@ -693,6 +915,7 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
                                 if this.network.is_disconnected(&peer_id) => true,
                             DialPeerCondition::NotDialing
                                 if !this.network.is_dialing(&peer_id) => true,
+                            DialPeerCondition::Always => true,
                             _ => false
                         };
                         if condition_matched {
@@ -705,12 +928,11 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
                             // ongoing dialing attempt, if there is one.
                             log::trace!("Condition for new dialing attempt to {:?} not met: {:?}",
                                 peer_id, condition);
-                            let self_listening = &this.listened_addrs;
-                            if let Some(mut peer) = this.network.peer(peer_id.clone()).into_dialing() {
-                                let addrs = this.behaviour.addresses_of_peer(peer.id());
-                                let mut attempt = peer.some_attempt();
-                                for a in addrs {
-                                    if !self_listening.contains(&a) {
+                            if this.network.is_dialing(&peer_id) {
+                                let addrs = ExpandedSwarm::addresses_of_peer(this, &peer_id);
+                                if let Some(mut peer) = this.network.peer(peer_id.clone()).into_dialing() {
+                                    let mut attempt = peer.some_attempt();
+                                    for a in addrs {
                                         attempt.add_address(a);
                                     }
                                 }
@@ -944,6 +1166,8 @@ pub struct SwarmPollParameters<'a> {
     supported_protocols: &'a [Vec<u8>],
     listened_addrs: &'a [Multiaddr],
     external_addrs: &'a Addresses,
+    peer_store: &'a mut PeerStore,
+    connection_manager: &'a mut ConnectionManager,
 }
 
 impl<'a> PollParameters for SwarmPollParameters<'a> {
@@ -966,6 +1190,18 @@ impl<'a> PollParameters for SwarmPollParameters<'a> {
     fn local_peer_id(&self) -> &PeerId {
         self.local_peer_id
     }
+
+    fn peer_store(&self) -> &PeerStore {
+        self.peer_store
+    }
+
+    fn peer_store_mut(&mut self) -> &mut PeerStore {
+        self.peer_store
+    }
+
+    fn connection_manager(&mut self) -> &mut ConnectionManager {
+        self.connection_manager
+    }
 }
 
 /// A `SwarmBuilder` provides an API for configuring and constructing a `Swarm`,
@@ -975,6 +1211,8 @@ pub struct SwarmBuilder<TBehaviour, TConnInfo> {
     transport: BoxTransport<(TConnInfo, StreamMuxerBox), io::Error>,
     behaviour: TBehaviour,
     network_config: NetworkConfig,
+    peer_store: PeerStore,
+    connection_manager: ConnectionManager,
 }
 
 impl<TBehaviour, TConnInfo> SwarmBuilder<TBehaviour, TConnInfo>
@@ -1006,6 +1244,8 @@ where TBehaviour: NetworkBehaviour,
             transport,
             behaviour,
             network_config: Default::default(),
+            peer_store: PeerStore::default(),
+            connection_manager: ConnectionManager::default(),
         }
     }
 
@@ -1060,6 +1300,14 @@ where TBehaviour: NetworkBehaviour,
         self
     }
 
+    /// Configures the maximum time a [`ProtocolsHandler`] is given to finish its
+    /// in-flight work once its connection is being closed, see
+    /// [`ProtocolsHandler::poll_close`].
+    pub fn connection_close_timeout(mut self, timeout: Duration) -> Self {
+        self.network_config.set_connection_close_timeout(timeout);
+        self
+    }
+
     /// Configures a limit for the number of simultaneous incoming
     /// connection attempts.
     pub fn incoming_connection_limit(mut self, n: usize) -> Self {
@@ -1081,6 +1329,20 @@ where TBehaviour: NetworkBehaviour,
         self
     }
 
+    /// Configures the initial content of the [`PeerStore`], e.g. a store restored
+    /// with [`PeerStore::load`].
+    pub fn peer_store(mut self, peer_store: PeerStore) -> Self {
+        self.peer_store = peer_store;
+        self
+    }
+
+    /// Configures the [`ConnectionManager`] closing the least valuable connections
+    /// once there are too many. By default, no connection is ever closed.
+    pub fn connection_manager(mut self, connection_manager: ConnectionManager) -> Self {
+        self.connection_manager = connection_manager;
+        self
+    }
+
     /// Builds a `Swarm` with the current configuration.
     pub fn build(mut self) -> Swarm<TBehaviour, TConnInfo> {
         let supported_protocols = self.behaviour
@@ -1121,6 +1383,12 @@ where TBehaviour: NetworkBehaviour,
             listened_addrs: SmallVec::new(),
             external_addrs: Addresses::default(),
             banned_peers: HashSet::new(),
+            peer_store_cleanup: Delay::new(self.peer_store.cleanup_interval()),
+            peer_store: self.peer_store,
+            connection_manager: self.connection_manager,
+            protocol_stats: ProtocolStats::default(),
+            shutting_down: false,
+            shutdown_connections: HashSet::new(),
             pending_event: None
         }
     }
@@ -1134,8 +1402,8 @@ pub enum DialError {
     /// The configured limit for simultaneous outgoing connections
     /// has been reached.
     ConnectionLimit(ConnectionLimit),
-    /// [`NetworkBehaviour::addresses_of_peer`] returned no addresses
-    /// for the peer to dial.
+    /// Neither [`NetworkBehaviour::addresses_of_peer`] nor the [`PeerStore`]
+    /// returned addresses for the peer to dial.
     NoAddresses
 }
 
@@ -1207,6 +1475,7 @@ mod tests {
         transport::{self, dummy::*}
     };
     use libp2p_mplex::Multiplex;
+    use std::time::Duration;
     use super::*;
 
     fn get_random_id() -> identity::PublicKey {
@@ -1214,6 +1483,16 @@ mod tests {
     }
 
     fn new_test_swarm<T, O>(handler_proto: T) -> Swarm<CallTraceBehaviour<MockBehaviour<T, O>>>
+    where
+        T: ProtocolsHandler + Clone,
+        T::OutEvent: Clone,
+        O: Send + 'static
+    {
+        new_test_swarm_builder(handler_proto).build()
+    }
+
+    fn new_test_swarm_builder<T, O>(handler_proto: T)
+        -> SwarmBuilder<CallTraceBehaviour<MockBehaviour<T, O>>, PeerId>
     where
         T: ProtocolsHandler + Clone,
         T::OutEvent: Clone,
@@ -1229,7 +1508,7 @@ mod tests {
             .map_err(|e| -> io::Error { panic!("Failed to create transport: {:?}", e); })
             .boxed();
         let behaviour1 = CallTraceBehaviour::new(MockBehaviour::new(handler_proto));
-        SwarmBuilder::new(transport1, behaviour1, pubkey1.into()).build()
+        SwarmBuilder::new(transport1, behaviour1, pubkey1.into())
     }
 
     #[test]
@@ -1354,4 +1633,299 @@ mod tests {
             }
         }))
     }
+
+    /// Opens more connections than the high watermark of the connection
+    /// manager, which then closes the excess down to the low watermark.
+    #[test]
+    fn test_connection_manager_trims_connections() {
+        let mut handler_proto = DummyProtocolsHandler::default();
+        handler_proto.keep_alive = KeepAlive::Yes;
+
+        let mut swarm1 = new_test_swarm::<_, ()>(handler_proto.clone());
+        let mut swarm2 = new_test_swarm_builder::<_, ()>(handler_proto)
+            .connection_manager(ConnectionManager::new(1, 2, Duration::from_secs(0)))
+            .build();
+
+        let addr1: Multiaddr = multiaddr::Protocol::Memory(rand::random::<u64>()).into();
+        Swarm::listen_on(&mut swarm1, addr1.clone()).unwrap();
+
+        // Exceeding the high watermark by one connection closes two of them.
+        let num_connections = 3;
+        for _ in 0 .. num_connections {
+            Swarm::dial_addr(&mut swarm2, addr1.clone()).unwrap();
+        }
+
+        executor::block_on(future::poll_fn(move |cx| {
+            loop {
+                let poll1 = Swarm::poll_next_event(Pin::new(&mut swarm1), cx);
+                let poll2 = Swarm::poll_next_event(Pin::new(&mut swarm2), cx);
+
+                let established = swarm2.behaviour.inject_connection_established.len();
+                let closed = swarm2.behaviour.inject_connection_closed.len();
+                assert!(established == 0 || established > closed);
+                if established == num_connections && closed == num_connections - 1 {
+                    assert_eq!(Swarm::connection_manager(&swarm2).num_connections(), 1);
+                    assert!(swarm2.behaviour.inject_disconnected.is_empty());
+                    return Poll::Ready(())
+                }
+
+                if poll1.is_pending() && poll2.is_pending() {
+                    return Poll::Pending
+                }
+            }
+        }))
+    }
+
+    /// Dials a peer that is already connected with `DialPeerCondition::Always`,
+    /// which establishes an additional connection.
+    #[test]
+    fn test_dial_peer_condition_always() {
+        let handler_proto = DummyProtocolsHandler { keep_alive: KeepAlive::Yes };
+
+        let mut swarm1 = new_test_swarm::<_, ()>(handler_proto.clone());
+        let mut swarm2 = new_test_swarm::<_, ()>(handler_proto);
+
+        let addr1: Multiaddr = multiaddr::Protocol::Memory(rand::random::<u64>()).into();
+        Swarm::listen_on(&mut swarm1, addr1.clone()).unwrap();
+        let swarm1_id = Swarm::local_peer_id(&swarm1).clone();
+
+        swarm2.behaviour.inner().addresses.insert(swarm1_id.clone(), vec![addr1]);
+        Swarm::dial(&mut swarm2, &swarm1_id).unwrap();
+
+        let mut dialed_again = false;
+        executor::block_on(future::poll_fn(move |cx| {
+            loop {
+                let poll1 = Swarm::poll_next_event(Pin::new(&mut swarm1), cx);
+                let poll2 = Swarm::poll_next_event(Pin::new(&mut swarm2), cx);
+
+                match swarm2.behaviour.inject_connection_established.len() {
+                    1 if !dialed_again => {
+                        swarm2.behaviour.inner().next_action = Some(NetworkBehaviourAction::DialPeer {
+                            peer_id: swarm1_id.clone(),
+                            condition: DialPeerCondition::Always,
+                        });
+                        dialed_again = true;
+                    }
+                    2 => {
+                        assert_eq!(swarm2.behaviour.inject_connected.len(), 1);
+                        return Poll::Ready(())
+                    }
+                    _ => {}
+                }
+
+                // A queued action must be polled by the swarm before waiting.
+                if poll1.is_pending() && poll2.is_pending()
+                    && swarm2.behaviour.inner().next_action.is_none()
+                {
+                    return Poll::Pending
+                }
+            }
+        }))
+    }
+
+    /// Dials a peer whose address is only known to the peer store, after
+    /// which the address is recorded as connected.
+    #[test]
+    fn test_dial_peer_store_address() {
+        let mut handler_proto = DummyProtocolsHandler::default();
+        handler_proto.keep_alive = KeepAlive::Yes;
+
+        let mut swarm1 = new_test_swarm::<_, ()>(handler_proto.clone());
+        let mut swarm2 = new_test_swarm::<_, ()>(handler_proto);
+
+        let addr1: Multiaddr = multiaddr::Protocol::Memory(rand::random::<u64>()).into();
+        Swarm::listen_on(&mut swarm1, addr1.clone()).unwrap();
+        let swarm1_id = Swarm::local_peer_id(&swarm1).clone();
+
+        match Swarm::dial(&mut swarm2, &swarm1_id) {
+            Err(DialError::NoAddresses) => {},
+            other => panic!("Unexpected dialing result: {:?}", other),
+        }
+        Swarm::peer_store_mut(&mut swarm2)
+            .add_address(&swarm1_id, addr1.clone(), AddressSource::Discovered, None);
+        Swarm::dial(&mut swarm2, &swarm1_id).unwrap();
+
+        executor::block_on(future::poll_fn(move |cx| {
+            loop {
+                let poll1 = Swarm::poll_next_event(Pin::new(&mut swarm1), cx);
+                let poll2 = Swarm::poll_next_event(Pin::new(&mut swarm2), cx);
+
+                if swarm2.behaviour.inject_connected.len() == 1 {
+                    let record = Swarm::peer_store(&swarm2).peer(&swarm1_id).unwrap();
+                    let addresses = record.addresses().collect::<Vec<_>>();
+                    assert_eq!(addresses.len(), 1);
+                    assert_eq!(addresses[0].address, addr1);
+                    assert_eq!(addresses[0].source, AddressSource::Connected);
+                    return Poll::Ready(())
+                }
+
+                if poll1.is_pending() && poll2.is_pending() {
+                    return Poll::Pending
+                }
+            }
+        }))
+    }
+
+    /// The expired addresses are removed from the peer store without any
+    /// other activity of the `Swarm`.
+    #[test]
+    fn test_peer_store_cleanup() {
+        let mut peer_store = PeerStore::new();
+        peer_store.set_cleanup_interval(Duration::from_millis(10));
+        let peer = PeerId::random();
+        let addr: Multiaddr = multiaddr::Protocol::Memory(rand::random::<u64>()).into();
+        peer_store.add_address(&peer, addr, AddressSource::Discovered, Some(Duration::from_millis(5)));
+
+        let mut swarm = new_test_swarm_builder::<_, ()>(DummyProtocolsHandler::default())
+            .peer_store(peer_store)
+            .build();
+
+        executor::block_on(future::poll_fn(move |cx| {
+            while let Poll::Ready(_) = Swarm::poll_next_event(Pin::new(&mut swarm), cx) {}
+            if Swarm::peer_store(&swarm).peer(&peer).is_none() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }))
+    }
+
+    /// Establishes connections between two peers, after which one of them
+    /// shuts down, closing its listener and all connections.
+    #[test]
+    fn test_shutdown() {
+        let handler_proto = DummyProtocolsHandler { keep_alive: KeepAlive::Yes };
+
+        let mut swarm1 = new_test_swarm::<_, ()>(handler_proto.clone());
+        let mut swarm2 = new_test_swarm::<_, ()>(handler_proto);
+
+        let addr1: Multiaddr = multiaddr::Protocol::Memory(rand::random::<u64>()).into();
+        let addr2: Multiaddr = multiaddr::Protocol::Memory(rand::random::<u64>()).into();
+        Swarm::listen_on(&mut swarm1, addr1.clone()).unwrap();
+        Swarm::listen_on(&mut swarm2, addr2).unwrap();
+
+        let num_connections = 2;
+        for _ in 0 .. num_connections {
+            Swarm::dial_addr(&mut swarm2, addr1.clone()).unwrap();
+        }
+
+        executor::block_on(future::poll_fn(|cx| {
+            loop {
+                let poll1 = Swarm::poll_next_event(Pin::new(&mut swarm1), cx);
+                let poll2 = Swarm::poll_next_event(Pin::new(&mut swarm2), cx);
+
+                if swarm2.behaviour.inject_connection_established.len() == num_connections
+                    && Swarm::listeners(&swarm2).count() == 1
+                {
+                    return Poll::Ready(())
+                }
+
+                if poll1.is_pending() && poll2.is_pending() {
+                    return Poll::Pending
+                }
+            }
+        }));
+
+        let mut closed = 0;
+        {
+            let mut shutdown = Swarm::shutdown(&mut swarm2, Duration::from_secs(10));
+            executor::block_on(future::poll_fn(|cx| {
+                loop {
+                    let poll1 = Swarm::poll_next_event(Pin::new(&mut swarm1), cx);
+                    match shutdown.poll_next_unpin(cx) {
+                        Poll::Ready(Some(SwarmEvent::ConnectionClosed { cause, .. })) => {
+                            match cause {
+                                Some(ConnectionError::Shutdown) => closed += 1,
+                                other => panic!("Unexpected cause: {:?}", other),
+                            }
+                        }
+                        Poll::Ready(Some(_)) => {}
+                        Poll::Ready(None) => return Poll::Ready(()),
+                        Poll::Pending if poll1.is_pending() => return Poll::Pending,
+                        Poll::Pending => {}
+                    }
+                }
+            }));
+        }
+
+        assert_eq!(closed, num_connections);
+        assert_eq!(swarm2.behaviour.inject_connection_closed.len(), num_connections);
+        assert_eq!(swarm2.behaviour.inject_disconnected.len(), 1);
+        assert_eq!(swarm2.behaviour.inject_listener_closed.len(), 1);
+        assert_eq!(Swarm::listeners(&swarm2).count(), 0);
+        assert_eq!(Swarm::network_info(&swarm2).num_connections, 0);
+
+        // The `Swarm` accepts new connections once the shutdown is over.
+        Swarm::dial_addr(&mut swarm2, addr1).unwrap();
+        executor::block_on(future::poll_fn(|cx| {
+            loop {
+                let poll1 = Swarm::poll_next_event(Pin::new(&mut swarm1), cx);
+                let poll2 = Swarm::poll_next_event(Pin::new(&mut swarm2), cx);
+
+                if swarm2.behaviour.inject_connection_established.len() == num_connections + 1 {
+                    return Poll::Ready(())
+                }
+
+                if poll1.is_pending() && poll2.is_pending() {
+                    return Poll::Pending
+                }
+            }
+        }));
+        assert_eq!(Swarm::network_info(&swarm2).num_connections, 1);
+    }
+
+    /// Starts a shutdown whose stream is dropped before it ended, after
+    /// which the `Swarm` accepts new connections again.
+    #[test]
+    fn test_shutdown_stream_dropped() {
+        let handler_proto = DummyProtocolsHandler { keep_alive: KeepAlive::Yes };
+
+        let mut swarm1 = new_test_swarm::<_, ()>(handler_proto.clone());
+        let mut swarm2 = new_test_swarm::<_, ()>(handler_proto);
+
+        let addr1: Multiaddr = multiaddr::Protocol::Memory(rand::random::<u64>()).into();
+        Swarm::listen_on(&mut swarm1, addr1.clone()).unwrap();
+
+        drop(Swarm::shutdown(&mut swarm2, Duration::from_secs(10)));
+        assert!(!swarm2.shutting_down);
+
+        Swarm::dial_addr(&mut swarm2, addr1).unwrap();
+        executor::block_on(future::poll_fn(|cx| {
+            loop {
+                let poll1 = Swarm::poll_next_event(Pin::new(&mut swarm1), cx);
+                let poll2 = Swarm::poll_next_event(Pin::new(&mut swarm2), cx);
+
+                if swarm2.behaviour.inject_connection_established.len() == 1 {
+                    return Poll::Ready(())
+                }
+
+                if poll1.is_pending() && poll2.is_pending() {
+                    return Poll::Pending
+                }
+            }
+        }));
+        // The new connection is not closed as part of the shutdown.
+        assert!(swarm2.shutdown_connections.is_empty());
+        assert!(swarm2.behaviour.inject_connection_closed.is_empty());
+        assert_eq!(Swarm::network_info(&swarm2).num_connections, 1);
+    }
+
+    /// Shuts down while dialing an address without a known peer ID.
+    #[test]
+    fn test_shutdown_aborts_address_dials() {
+        let handler_proto = DummyProtocolsHandler { keep_alive: KeepAlive::Yes };
+
+        // `swarm1` is never polled, so that the connection is never established.
+        let mut swarm1 = new_test_swarm::<_, ()>(handler_proto.clone());
+        let mut swarm2 = new_test_swarm::<_, ()>(handler_proto);
+
+        let addr1: Multiaddr = multiaddr::Protocol::Memory(rand::random::<u64>()).into();
+        Swarm::listen_on(&mut swarm1, addr1.clone()).unwrap();
+        Swarm::dial_addr(&mut swarm2, addr1.clone()).unwrap();
+        assert_eq!(Swarm::network_info(&swarm2).num_connections_pending, 1);
+
+        executor::block_on(Swarm::shutdown(&mut swarm2, Duration::from_secs(10)).for_each(|_| future::ready(())));
+        assert_eq!(Swarm::network_info(&swarm2).num_connections, 0);
+        assert_eq!(swarm2.behaviour.inject_addr_reach_failure, vec![(None, addr1)]);
+    }
 }
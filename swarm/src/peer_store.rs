@@ -0,0 +1,561 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Knowledge about remote peers shared by all the behaviours of a `Swarm`.
+
+use libp2p_core::{Multiaddr, PeerId};
+use std::{collections::HashMap, fs, io::{self, BufRead, Write}, path::Path, time::Duration};
+use wasm_timer::Instant;
+
+/// The first line of a snapshot written by [`PeerStore::write_snapshot`].
+const SNAPSHOT_HEADER: &str = "# libp2p peer store v1";
+
+/// The default time the addresses we were connected to are kept after the last
+/// connection to the peer closed, see [`PeerStore::set_connected_address_ttl`].
+const CONNECTED_ADDRESS_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// The default interval at which the `Swarm` removes the expired addresses,
+/// see [`PeerStore::set_cleanup_interval`].
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Records the addresses of remote peers along with where they come from, as well as the
+/// protocols, agent version and latency reported for them.
+///
+/// The `Swarm` records the addresses it successfully dialed and forgets those that turn out
+/// to be unreachable, unless they were added manually. The addresses it dialed, as well as
+/// those reported by the peer without an expiration, expire some time after the last
+/// connection to the peer closed. The `Swarm` periodically removes the expired addresses.
+/// The record of a peer is only removed
+/// once it holds neither an address nor any other information. The other information is recorded
+/// by the behaviours through [`PollParameters::peer_store_mut`](crate::PollParameters::peer_store_mut),
+/// e.g. by identify and ping, or by the application through
+/// [`ExpandedSwarm::peer_store_mut`](crate::ExpandedSwarm::peer_store_mut).
+#[derive(Debug, Clone)]
+pub struct PeerStore {
+    peers: HashMap<PeerId, PeerRecord>,
+    connected_address_ttl: Duration,
+    cleanup_interval: Duration,
+}
+
+/// What is known about a single peer.
+#[derive(Debug, Clone, Default)]
+pub struct PeerRecord {
+    addresses: Vec<AddressRecord>,
+    protocols: Vec<String>,
+    agent_version: Option<String>,
+    latency: Option<Duration>,
+}
+
+/// An address of a peer together with its provenance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressRecord {
+    /// The address of the peer.
+    pub address: Multiaddr,
+    /// Where the address comes from.
+    pub source: AddressSource,
+    /// When the address expires, if ever.
+    pub expires: Option<Instant>,
+}
+
+/// Where an address in the [`PeerStore`] comes from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AddressSource {
+    /// The address has been added by the application. Such an address is not
+    /// removed when it turns out to be unreachable.
+    Manual,
+    /// We successfully connected to the peer on this address.
+    Connected,
+    /// The address has been reported by the peer itself, e.g. through identify.
+    Reported,
+    /// The address has been learned from other peers, e.g. through Kademlia or mDNS.
+    Discovered,
+}
+
+impl AddressSource {
+    /// Whether an address of this source is only valid while we are
+    /// connected to the peer, i.e. expires once disconnected.
+    fn expires_on_disconnect(&self) -> bool {
+        match self {
+            AddressSource::Connected | AddressSource::Reported => true,
+            AddressSource::Manual | AddressSource::Discovered => false,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            AddressSource::Manual => "manual",
+            AddressSource::Connected => "connected",
+            AddressSource::Reported => "reported",
+            AddressSource::Discovered => "discovered",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "manual" => Some(AddressSource::Manual),
+            "connected" => Some(AddressSource::Connected),
+            "reported" => Some(AddressSource::Reported),
+            "discovered" => Some(AddressSource::Discovered),
+            _ => None,
+        }
+    }
+}
+
+impl AddressRecord {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires.map_or(false, |t| t <= now)
+    }
+}
+
+impl PeerRecord {
+    /// Returns the addresses of the peer that have not expired.
+    pub fn addresses(&self) -> impl Iterator<Item = &AddressRecord> {
+        let now = Instant::now();
+        self.addresses.iter().filter(move |r| !r.is_expired(now))
+    }
+
+    /// Returns the protocols the peer reported to support.
+    pub fn protocols(&self) -> &[String] {
+        &self.protocols
+    }
+
+    /// Returns the agent version the peer reported.
+    pub fn agent_version(&self) -> Option<&str> {
+        self.agent_version.as_deref()
+    }
+
+    /// Returns the last measured latency to the peer.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+            && self.protocols.is_empty()
+            && self.agent_version.is_none()
+            && self.latency.is_none()
+    }
+}
+
+impl Default for PeerStore {
+    fn default() -> Self {
+        PeerStore {
+            peers: HashMap::new(),
+            connected_address_ttl: CONNECTED_ADDRESS_TTL,
+            cleanup_interval: CLEANUP_INTERVAL,
+        }
+    }
+}
+
+impl PeerStore {
+    /// Creates an empty `PeerStore`.
+    pub fn new() -> Self {
+        PeerStore::default()
+    }
+
+    /// Sets how long the addresses we were connected to are kept after the last
+    /// connection to the peer closed (default is 10 minutes).
+    pub fn set_connected_address_ttl(&mut self, ttl: Duration) {
+        self.connected_address_ttl = ttl;
+    }
+
+    /// Sets the interval at which the `Swarm` removes the expired addresses
+    /// with [`PeerStore::remove_expired`] (default is 1 minute).
+    pub fn set_cleanup_interval(&mut self, interval: Duration) {
+        self.cleanup_interval = interval;
+    }
+
+    pub(crate) fn cleanup_interval(&self) -> Duration {
+        self.cleanup_interval
+    }
+
+    /// Returns the record of a peer, if anything is known about it.
+    pub fn peer(&self, peer_id: &PeerId) -> Option<&PeerRecord> {
+        self.peers.get(peer_id)
+    }
+
+    /// Returns an iterator over the peers known to the store.
+    pub fn peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.peers.keys()
+    }
+
+    /// Returns the addresses of a peer that have not expired.
+    pub fn addresses_of_peer(&self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        self.peers.get(peer_id)
+            .map(|r| r.addresses().map(|a| a.address.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Adds an address of a peer, which expires after `ttl` if given.
+    ///
+    /// Adding a known address replaces its source and expiration. An address
+    /// whose `ttl` exceeds the range of [`Instant`] never expires.
+    pub fn add_address(&mut self, peer_id: &PeerId, address: Multiaddr, source: AddressSource, ttl: Option<Duration>) {
+        let now = Instant::now();
+        let expires = ttl.and_then(|ttl| now.checked_add(ttl));
+        let record = self.peers.entry(peer_id.clone()).or_default();
+        record.addresses.retain(|r| !r.is_expired(now));
+        if let Some(r) = record.addresses.iter_mut().find(|r| r.address == address) {
+            r.source = source;
+            r.expires = expires;
+        } else {
+            record.addresses.push(AddressRecord { address, source, expires });
+        }
+    }
+
+    /// Removes an address of a peer. Returns `true` if the address was known.
+    pub fn remove_address(&mut self, peer_id: &PeerId, address: &Multiaddr) -> bool {
+        let removed = if let Some(record) = self.peers.get_mut(peer_id) {
+            let len = record.addresses.len();
+            record.addresses.retain(|r| r.address != *address);
+            record.addresses.len() != len
+        } else {
+            false
+        };
+        self.remove_if_empty(peer_id);
+        removed
+    }
+
+    /// Sets the protocols a peer reported to support.
+    pub fn set_protocols(&mut self, peer_id: &PeerId, protocols: Vec<String>) {
+        self.peers.entry(peer_id.clone()).or_default().protocols = protocols;
+    }
+
+    /// Sets the agent version a peer reported.
+    pub fn set_agent_version(&mut self, peer_id: &PeerId, agent_version: String) {
+        self.peers.entry(peer_id.clone()).or_default().agent_version = Some(agent_version);
+    }
+
+    /// Sets the last measured latency to a peer.
+    pub fn set_latency(&mut self, peer_id: &PeerId, latency: Duration) {
+        self.peers.entry(peer_id.clone()).or_default().latency = Some(latency);
+    }
+
+    /// Forgets everything about a peer, returning its record.
+    pub fn remove_peer(&mut self, peer_id: &PeerId) -> Option<PeerRecord> {
+        self.peers.remove(peer_id)
+    }
+
+    /// Removes the expired addresses, as well as the peers about which nothing
+    /// else is known.
+    ///
+    /// Peers with protocols, an agent version or a latency, e.g. connected peers
+    /// that only dialed us, are kept until removed with [`PeerStore::remove_peer`].
+    pub fn remove_expired(&mut self) {
+        let now = Instant::now();
+        for record in self.peers.values_mut() {
+            record.addresses.retain(|r| !r.is_expired(now));
+        }
+        self.peers.retain(|_, r| !r.is_empty());
+    }
+
+    /// Records that we are connected to a peer on an address we dialed. The address
+    /// no longer expires, unless it was added manually, which it remains.
+    pub(crate) fn on_connected(&mut self, peer_id: &PeerId, address: &Multiaddr) {
+        let is_manual = self.peers.get(peer_id)
+            .and_then(|r| r.addresses.iter().find(|a| a.address == *address))
+            .map_or(false, |a| a.source == AddressSource::Manual);
+        if !is_manual {
+            self.add_address(peer_id, address.clone(), AddressSource::Connected, None);
+        }
+    }
+
+    /// Records that the last connection to a peer closed, after which the addresses we
+    /// were connected to and those reported without expiration expire, and removes the
+    /// expired addresses of the peer.
+    pub(crate) fn on_disconnected(&mut self, peer_id: &PeerId) {
+        let now = Instant::now();
+        let expires = now + self.connected_address_ttl;
+        if let Some(record) = self.peers.get_mut(peer_id) {
+            record.addresses.retain(|r| !r.is_expired(now));
+            for a in &mut record.addresses {
+                if a.source.expires_on_disconnect() && a.expires.is_none() {
+                    a.expires = Some(expires);
+                }
+            }
+        }
+        self.remove_if_empty(peer_id);
+    }
+
+    /// Writes a snapshot of the store, which can be read back with
+    /// [`PeerStore::read_snapshot`].
+    ///
+    /// The snapshot is a text format with one entry per line. The expiration of
+    /// the addresses is stored as the time remaining until they expire, in
+    /// milliseconds.
+    pub fn write_snapshot<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let now = Instant::now();
+        writeln!(w, "{}", SNAPSHOT_HEADER)?;
+        for (peer_id, record) in &self.peers {
+            for a in record.addresses.iter().filter(|r| !r.is_expired(now)) {
+                let ttl = a.expires
+                    .map(|t| (t - now).as_millis().to_string())
+                    .unwrap_or_else(|| "-".to_string());
+                writeln!(w, "{} addr {} {} {}", peer_id, a.address, a.source.as_str(), ttl)?;
+            }
+            for p in &record.protocols {
+                writeln!(w, "{} protocol {}", peer_id, single_line(p))?;
+            }
+            if let Some(agent_version) = &record.agent_version {
+                writeln!(w, "{} agent {}", peer_id, single_line(agent_version))?;
+            }
+            if let Some(latency) = record.latency {
+                writeln!(w, "{} latency {}", peer_id, latency.as_micros())?;
+            }
+        }
+        w.flush()
+    }
+
+    /// Reads a snapshot written by [`PeerStore::write_snapshot`].
+    ///
+    /// As we are not connected to any peer yet, the addresses that only expire
+    /// once disconnected expire after [`PeerStore::set_connected_address_ttl`].
+    pub fn read_snapshot<R: BufRead>(r: R) -> io::Result<Self> {
+        let mut store = PeerStore::new();
+        for line in r.lines() {
+            let line = line?;
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            store.read_snapshot_line(&line)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                    format!("Invalid peer store snapshot entry: {:?}", line)))?;
+        }
+        Ok(store)
+    }
+
+    /// Writes a snapshot of the store to a file, replacing its content.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write_snapshot(io::BufWriter::new(fs::File::create(path)?))
+    }
+
+    /// Reads a snapshot of a store from a file.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        PeerStore::read_snapshot(io::BufReader::new(fs::File::open(path)?))
+    }
+
+    fn read_snapshot_line(&mut self, line: &str) -> Option<()> {
+        let mut parts = line.splitn(3, ' ');
+        let peer_id = parts.next()?.parse::<PeerId>().ok()?;
+        let kind = parts.next()?;
+        let value = parts.next()?;
+        match kind {
+            "addr" => {
+                let mut fields = value.split(' ');
+                let address = fields.next()?.parse::<Multiaddr>().ok()?;
+                let source = AddressSource::from_str(fields.next()?)?;
+                let ttl = match fields.next()? {
+                    "-" if source.expires_on_disconnect() => Some(self.connected_address_ttl),
+                    "-" => None,
+                    millis => Some(Duration::from_millis(millis.parse().ok()?)),
+                };
+                // Reject a TTL beyond the range of `Instant`.
+                Instant::now().checked_add(ttl.unwrap_or_default())?;
+                self.add_address(&peer_id, address, source, ttl);
+            }
+            "protocol" => {
+                self.peers.entry(peer_id).or_default().protocols.push(value.to_string());
+            }
+            "agent" => self.set_agent_version(&peer_id, value.to_string()),
+            "latency" => {
+                let micros = value.parse::<u64>().ok()?;
+                self.set_latency(&peer_id, Duration::from_micros(micros));
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn remove_if_empty(&mut self, peer_id: &PeerId) {
+        if self.peers.get(peer_id).map_or(false, |r| r.is_empty()) {
+            self.peers.remove(peer_id);
+        }
+    }
+}
+
+// Replaces the line breaks of a value stored in a snapshot.
+fn single_line(s: &str) -> String {
+    s.replace(&['\n', '\r'][..], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p_core::{Multiaddr, PeerId};
+    use std::{io, thread::sleep, time::Duration};
+    use super::{AddressSource, PeerStore};
+    use wasm_timer::Instant;
+
+    #[test]
+    fn addresses_expire() {
+        let mut store = PeerStore::new();
+        let peer = PeerId::random();
+        let permanent: Multiaddr = "/ip4/1.2.3.4/tcp/1".parse().unwrap();
+        let temporary: Multiaddr = "/ip4/1.2.3.4/tcp/2".parse().unwrap();
+
+        store.add_address(&peer, permanent.clone(), AddressSource::Manual, None);
+        store.add_address(&peer, temporary.clone(), AddressSource::Discovered, Some(Duration::from_millis(10)));
+        assert_eq!(store.addresses_of_peer(&peer), vec![permanent.clone(), temporary]);
+
+        sleep(Duration::from_millis(20));
+        assert_eq!(store.addresses_of_peer(&peer), vec![permanent.clone()]);
+
+        assert!(store.remove_address(&peer, &permanent));
+        store.remove_expired();
+        assert!(store.peer(&peer).is_none());
+    }
+
+    #[test]
+    fn known_address_is_updated() {
+        let mut store = PeerStore::new();
+        let peer = PeerId::random();
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/1".parse().unwrap();
+
+        store.add_address(&peer, addr.clone(), AddressSource::Discovered, Some(Duration::from_secs(1)));
+        store.add_address(&peer, addr.clone(), AddressSource::Connected, None);
+
+        let records = store.peer(&peer).unwrap().addresses().collect::<Vec<_>>();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].source, AddressSource::Connected);
+        assert_eq!(records[0].expires, None);
+    }
+
+    #[test]
+    fn connected_addresses_expire_after_disconnect() {
+        let mut store = PeerStore::new();
+        store.set_connected_address_ttl(Duration::from_millis(10));
+        let peer = PeerId::random();
+        let manual: Multiaddr = "/ip4/1.2.3.4/tcp/1".parse().unwrap();
+        let dialed: Multiaddr = "/ip4/1.2.3.4/tcp/2".parse().unwrap();
+
+        store.add_address(&peer, manual.clone(), AddressSource::Manual, None);
+        store.on_connected(&peer, &manual);
+        store.on_connected(&peer, &dialed);
+        store.set_agent_version(&peer, "rust-libp2p".into());
+        store.on_disconnected(&peer);
+        assert_eq!(store.addresses_of_peer(&peer), vec![manual.clone(), dialed]);
+
+        sleep(Duration::from_millis(20));
+        store.remove_expired();
+        let record = store.peer(&peer).unwrap();
+        let addresses = record.addresses().collect::<Vec<_>>();
+        assert_eq!(addresses.len(), 1);
+        assert_eq!(addresses[0].source, AddressSource::Manual);
+
+        // a peer without addresses is kept as long as something else is known about it
+        store.remove_address(&peer, &manual);
+        store.remove_expired();
+        assert_eq!(store.peer(&peer).unwrap().agent_version(), Some("rust-libp2p"));
+        store.remove_peer(&peer);
+        assert!(store.peer(&peer).is_none());
+    }
+
+    #[test]
+    fn disconnect_keeps_other_peers() {
+        let mut store = PeerStore::new();
+        store.set_connected_address_ttl(Duration::from_millis(10));
+        let inbound = PeerId::random();
+        let dialed = PeerId::random();
+        let expired = PeerId::random();
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/1".parse().unwrap();
+
+        // an inbound peer we have no address of, but which reported its metadata
+        store.set_protocols(&inbound, vec!["/ipfs/id/1.0.0".into()]);
+        store.set_agent_version(&inbound, "rust-libp2p".into());
+        store.set_latency(&inbound, Duration::from_millis(42));
+        store.add_address(&expired, addr.clone(), AddressSource::Discovered, Some(Duration::from_millis(10)));
+        store.on_connected(&dialed, &addr);
+
+        sleep(Duration::from_millis(20));
+        store.on_disconnected(&dialed);
+
+        let record = store.peer(&inbound).unwrap();
+        assert_eq!(record.protocols(), &["/ipfs/id/1.0.0".to_string()]);
+        assert_eq!(record.agent_version(), Some("rust-libp2p"));
+        assert_eq!(record.latency(), Some(Duration::from_millis(42)));
+        assert_eq!(store.addresses_of_peer(&dialed), vec![addr]);
+        // only the addresses of the disconnected peer are removed
+        assert!(store.peer(&expired).is_some());
+    }
+
+    #[test]
+    fn snapshot_roundtrip() {
+        let mut store = PeerStore::new();
+        let peer = PeerId::random();
+        let other = PeerId::random();
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/1".parse().unwrap();
+        let discovered: Multiaddr = "/dns4/example.com/tcp/2".parse().unwrap();
+
+        store.add_address(&peer, addr.clone(), AddressSource::Connected, None);
+        store.add_address(&peer, discovered.clone(), AddressSource::Discovered, Some(Duration::from_millis(1500)));
+        store.set_protocols(&peer, vec!["/ipfs/id/1.0.0".into(), "/ipfs/ping/1.0.0".into()]);
+        store.set_agent_version(&peer, "rust-libp2p/0.28\nnext line".into());
+        store.set_latency(&other, Duration::from_millis(42));
+
+        let mut snapshot = Vec::new();
+        store.write_snapshot(&mut snapshot).unwrap();
+        let restored = PeerStore::read_snapshot(&snapshot[..]).unwrap();
+
+        let record = restored.peer(&peer).unwrap();
+        let addresses = record.addresses().collect::<Vec<_>>();
+        assert_eq!(addresses.len(), 2);
+        // we are not connected to the peer anymore
+        assert!(addresses.iter().any(|r| r.address == addr && r.source == AddressSource::Connected && r.expires.is_some()));
+        assert!(addresses.iter().any(|r| r.address == discovered
+            && r.expires.map_or(false, |t| t > Instant::now() + Duration::from_secs(1))));
+        assert_eq!(record.protocols(), &["/ipfs/id/1.0.0".to_string(), "/ipfs/ping/1.0.0".to_string()]);
+        assert_eq!(record.agent_version(), Some("rust-libp2p/0.28 next line"));
+        assert_eq!(restored.peer(&other).unwrap().latency(), Some(Duration::from_millis(42)));
+
+        assert!(PeerStore::read_snapshot(&b"not a snapshot"[..]).is_err());
+    }
+
+    #[test]
+    fn huge_ttl() {
+        let peer = PeerId::random();
+        let snapshot = format!("{} addr /ip4/1.2.3.4/tcp/1 discovered {}0", peer, u64::max_value());
+        let error = PeerStore::read_snapshot(snapshot.as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // a TTL beyond the range of `Instant` never expires
+        let mut store = PeerStore::new();
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/1".parse().unwrap();
+        store.add_address(&peer, addr, AddressSource::Discovered, Some(Duration::new(u64::max_value(), 0)));
+        assert_eq!(store.peer(&peer).unwrap().addresses().next().unwrap().expires, None);
+    }
+
+    #[test]
+    fn reported_addresses_expire_after_disconnect() {
+        let mut store = PeerStore::new();
+        store.set_connected_address_ttl(Duration::from_millis(10));
+        let peer = PeerId::random();
+        let reported: Multiaddr = "/ip4/1.2.3.4/tcp/1".parse().unwrap();
+        let dialed: Multiaddr = "/ip4/1.2.3.4/tcp/2".parse().unwrap();
+
+        store.on_connected(&peer, &dialed);
+        store.add_address(&peer, reported.clone(), AddressSource::Reported, None);
+        store.on_disconnected(&peer);
+        assert_eq!(store.addresses_of_peer(&peer), vec![dialed, reported]);
+
+        sleep(Duration::from_millis(20));
+        store.remove_expired();
+        assert!(store.peer(&peer).is_none());
+    }
+}
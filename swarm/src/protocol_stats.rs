@@ -0,0 +1,498 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+
+//! Statistics about the protocols negotiated on the substreams of a `Swarm`.
+
+use crate::upgrade::{InboundUpgradeSend, OutboundUpgradeSend, UpgradeInfoSend};
+use futures::{prelude::*, io::{IoSlice, IoSliceMut}};
+use libp2p_core::{
+    Negotiated,
+    PeerId,
+    connection::{ConnectionId, Substream},
+    muxing::StreamMuxerBox,
+    upgrade::{self, ProtocolName},
+};
+use std::{
+    collections::HashMap,
+    io,
+    ops::AddAssign,
+    pin::Pin,
+    sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}},
+    task::{Context, Poll},
+};
+
+/// Counts the substreams negotiated on the connections of a `Swarm` and the bytes
+/// sent and received on them, per protocol.
+///
+/// The counters are updated by the background tasks of the connections, hence
+/// `ProtocolStats` is a cheaply cloneable handle to shared counters.
+///
+/// The bytes are those read from and written to the [`NegotiatedSubstream`]s, i.e.
+/// the overhead of the protocol negotiation, of the multiplexing and of the encryption
+/// is not accounted for. Each substream counts its own bytes in counters that are
+/// read whenever the `ProtocolStats` are queried, and folded into them once the
+/// substream is dropped. The bytes of substreams that outlive their connection only
+/// show in [`ProtocolStats::bandwidth`] afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct ProtocolStats {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// The substream counts of all protocols.
+    substreams: HashMap<String, SubstreamCounts>,
+    /// The bytes sent and received on closed connections and on the closed
+    /// substreams that outlived them.
+    closed: Bandwidth,
+    /// The bytes sent and received on the open connections.
+    connections: HashMap<ConnectionId, ConnectionBandwidth>,
+    /// The open substreams that outlived their connection.
+    detached: OpenSubstreams,
+    /// The key of the next negotiated substream.
+    next_substream: u64,
+}
+
+/// The byte counters of open substreams, with their protocols.
+type OpenSubstreams = HashMap<u64, (String, Arc<ByteCounters>)>;
+
+#[derive(Debug)]
+struct ConnectionBandwidth {
+    peer_id: PeerId,
+    /// The bytes sent and received on the closed substreams of the connection.
+    closed: Bandwidth,
+    /// The open substreams of the connection.
+    open: OpenSubstreams,
+}
+
+impl ConnectionBandwidth {
+    fn bandwidth(&self) -> Bandwidth {
+        let mut bandwidth = self.closed.clone();
+        add_open(&mut bandwidth, &self.open);
+        bandwidth
+    }
+}
+
+fn add_open(bandwidth: &mut Bandwidth, open: &OpenSubstreams) {
+    for (protocol, counters) in open.values() {
+        bandwidth.add(protocol, counters.get());
+    }
+}
+
+/// The bytes sent and received on a substream.
+#[derive(Debug, Default)]
+struct ByteCounters {
+    received: AtomicU64,
+    sent: AtomicU64,
+}
+
+impl ByteCounters {
+    fn get(&self) -> ByteCounts {
+        ByteCounts {
+            received: self.received.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The byte counters of an open substream, registered in the [`ProtocolStats`]
+/// and folded into them when dropped.
+#[derive(Debug)]
+struct SubstreamCounters {
+    stats: ProtocolStats,
+    id: ConnectionId,
+    key: u64,
+    counters: Arc<ByteCounters>,
+}
+
+impl Drop for SubstreamCounters {
+    fn drop(&mut self) {
+        self.stats.fold(self.id, self.key)
+    }
+}
+
+/// The number of substreams negotiated for a protocol.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubstreamCounts {
+    /// The number of substreams opened by remotes.
+    pub inbound: u64,
+    /// The number of substreams opened by the local node.
+    pub outbound: u64,
+}
+
+/// A number of bytes sent and received.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ByteCounts {
+    /// The number of bytes received.
+    pub received: u64,
+    /// The number of bytes sent.
+    pub sent: u64,
+}
+
+impl AddAssign for ByteCounts {
+    fn add_assign(&mut self, other: ByteCounts) {
+        self.received += other.received;
+        self.sent += other.sent;
+    }
+}
+
+/// The bytes sent and received on the substreams of one or more connections, per protocol.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bandwidth {
+    protocols: HashMap<String, ByteCounts>,
+}
+
+impl Bandwidth {
+    /// Returns the bytes sent and received for a protocol.
+    pub fn get(&self, protocol: &str) -> ByteCounts {
+        self.protocols.get(protocol).copied().unwrap_or_default()
+    }
+
+    /// Returns the bytes sent and received for all protocols, ordered by protocol.
+    pub fn protocols(&self) -> Vec<(String, ByteCounts)> {
+        let mut protocols = self.protocols.iter()
+            .map(|(p, c)| (p.clone(), *c))
+            .collect::<Vec<_>>();
+        protocols.sort_by(|a, b| a.0.cmp(&b.0));
+        protocols
+    }
+
+    /// Returns the bytes sent and received for all protocols together.
+    pub fn total(&self) -> ByteCounts {
+        let mut total = ByteCounts::default();
+        for counts in self.protocols.values() {
+            total += *counts;
+        }
+        total
+    }
+
+    fn add(&mut self, protocol: &str, counts: ByteCounts) {
+        match self.protocols.get_mut(protocol) {
+            Some(c) => *c += counts,
+            None => { self.protocols.insert(protocol.to_owned(), counts); }
+        }
+    }
+
+    fn extend(&mut self, other: &Bandwidth) {
+        for (protocol, counts) in &other.protocols {
+            self.add(protocol, *counts);
+        }
+    }
+}
+
+impl ProtocolStats {
+    /// Returns the substream counts of a protocol.
+    pub fn get(&self, protocol: &str) -> SubstreamCounts {
+        self.inner.lock().expect("not poisoned").substreams.get(protocol).copied().unwrap_or_default()
+    }
+
+    /// Returns the substream counts of all protocols negotiated so far, ordered by protocol.
+    pub fn protocols(&self) -> Vec<(String, SubstreamCounts)> {
+        let mut protocols = self.inner.lock().expect("not poisoned").substreams
+            .iter()
+            .map(|(p, c)| (p.clone(), *c))
+            .collect::<Vec<_>>();
+        protocols.sort_by(|a, b| a.0.cmp(&b.0));
+        protocols
+    }
+
+    /// Returns the bytes sent and received on all connections so far, open and closed.
+    pub fn bandwidth(&self) -> Bandwidth {
+        let inner = self.inner.lock().expect("not poisoned");
+        let mut bandwidth = inner.closed.clone();
+        for connection in inner.connections.values() {
+            bandwidth.extend(&connection.bandwidth());
+        }
+        add_open(&mut bandwidth, &inner.detached);
+        bandwidth
+    }
+
+    /// Returns the bytes sent and received on an open connection.
+    pub fn connection_bandwidth(&self, id: &ConnectionId) -> Bandwidth {
+        self.inner.lock().expect("not poisoned").connections.get(id)
+            .map(ConnectionBandwidth::bandwidth)
+            .unwrap_or_default()
+    }
+
+    /// Returns the bytes sent and received on the open connections to a peer.
+    pub fn peer_bandwidth(&self, peer_id: &PeerId) -> Bandwidth {
+        let inner = self.inner.lock().expect("not poisoned");
+        let mut bandwidth = Bandwidth::default();
+        for connection in inner.connections.values().filter(|c| &c.peer_id == peer_id) {
+            bandwidth.extend(&connection.bandwidth());
+        }
+        bandwidth
+    }
+
+    /// Stops accounting for a closed connection and returns the bytes sent and received on it.
+    ///
+    /// The substreams of the connection that are still open keep being accounted for
+    /// in [`ProtocolStats::bandwidth`] until they are dropped.
+    pub(crate) fn remove_connection(&self, id: &ConnectionId) -> Bandwidth {
+        let mut inner = self.inner.lock().expect("not poisoned");
+        match inner.connections.remove(id) {
+            Some(connection) => {
+                let bandwidth = connection.bandwidth();
+                inner.closed.extend(&connection.closed);
+                inner.detached.extend(connection.open);
+                bandwidth
+            }
+            None => Bandwidth::default(),
+        }
+    }
+
+    /// Folds the bytes sent and received on a closed substream of a connection
+    /// into the totals.
+    fn fold(&self, id: ConnectionId, key: u64) {
+        let mut inner = self.inner.lock().expect("not poisoned");
+        let inner = &mut *inner;
+        if let Some(connection) = inner.connections.get_mut(&id) {
+            if let Some((protocol, counters)) = connection.open.remove(&key) {
+                connection.closed.add(&protocol, counters.get());
+            }
+        } else if let Some((protocol, counters)) = inner.detached.remove(&key) {
+            inner.closed.add(&protocol, counters.get());
+        }
+    }
+
+    /// Records a substream negotiated on a connection and returns the counters of its bytes.
+    fn record(&self, id: ConnectionId, peer_id: &PeerId, protocol: &[u8], inbound: bool) -> SubstreamCounters {
+        let protocol = String::from_utf8_lossy(protocol);
+        let mut inner = self.inner.lock().expect("not poisoned");
+
+        let counts = match inner.substreams.get_mut(protocol.as_ref()) {
+            Some(counts) => counts,
+            None => inner.substreams.entry(protocol.clone().into_owned()).or_default(),
+        };
+        if inbound {
+            counts.inbound += 1;
+        } else {
+            counts.outbound += 1;
+        }
+
+        let key = inner.next_substream;
+        inner.next_substream += 1;
+        let counters = Arc::new(ByteCounters::default());
+        let connection = inner.connections.entry(id).or_insert_with(|| ConnectionBandwidth {
+            peer_id: peer_id.clone(),
+            closed: Bandwidth::default(),
+            open: HashMap::new(),
+        });
+        connection.open.insert(key, (protocol.into_owned(), counters.clone()));
+
+        SubstreamCounters { stats: self.clone(), id, key, counters }
+    }
+}
+
+/// The [`ProtocolStats`] of a `Swarm` and the connection to record them for.
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionStats {
+    pub(crate) stats: ProtocolStats,
+    pub(crate) id: ConnectionId,
+    pub(crate) peer_id: PeerId,
+}
+
+impl ConnectionStats {
+    fn record(&self, protocol: &[u8], inbound: bool) -> SubstreamCounters {
+        self.stats.record(self.id, &self.peer_id, protocol, inbound)
+    }
+}
+
+/// Substream for which a protocol has been chosen.
+///
+/// Implements the [`AsyncRead`](futures::io::AsyncRead) and
+/// [`AsyncWrite`](futures::io::AsyncWrite) traits. The bytes read and written are
+/// accounted for in the [`ProtocolStats`] of the `Swarm`.
+#[derive(Debug)]
+pub struct NegotiatedSubstream {
+    inner: Negotiated<Substream<StreamMuxerBox>>,
+    counters: Option<SubstreamCounters>,
+}
+
+impl AsyncRead for NegotiatedSubstream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8])
+        -> Poll<io::Result<usize>>
+    {
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        this.count_received(&result);
+        result
+    }
+
+    fn poll_read_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &mut [IoSliceMut<'_>])
+        -> Poll<io::Result<usize>>
+    {
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_read_vectored(cx, bufs);
+        this.count_received(&result);
+        result
+    }
+}
+
+impl AsyncWrite for NegotiatedSubstream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+        -> Poll<io::Result<usize>>
+    {
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        this.count_sent(&result);
+        result
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[IoSlice<'_>])
+        -> Poll<io::Result<usize>>
+    {
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_write_vectored(cx, bufs);
+        this.count_sent(&result);
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+impl NegotiatedSubstream {
+    fn count_received(&self, result: &Poll<io::Result<usize>>) {
+        if let (Some(counters), Poll::Ready(Ok(n))) = (&self.counters, result) {
+            counters.counters.received.fetch_add(*n as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn count_sent(&self, result: &Poll<io::Result<usize>>) {
+        if let (Some(counters), Poll::Ready(Ok(n))) = (&self.counters, result) {
+            counters.counters.sent.fetch_add(*n as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Wraps around an upgrade and records the protocol it is applied with in
+/// [`ProtocolStats`], if any, wrapping the substream into a [`NegotiatedSubstream`]
+/// that accounts for its bytes.
+pub(crate) struct RecordProtocol<T> {
+    pub(crate) upgrade: T,
+    pub(crate) stats: Option<ConnectionStats>,
+}
+
+impl<T: UpgradeInfoSend> upgrade::UpgradeInfo for RecordProtocol<T> {
+    type Info = T::Info;
+    type InfoIter = T::InfoIter;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        self.upgrade.protocol_info()
+    }
+}
+
+impl<T: InboundUpgradeSend> upgrade::InboundUpgrade<Negotiated<Substream<StreamMuxerBox>>> for RecordProtocol<T> {
+    type Output = T::Output;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn upgrade_inbound(self, socket: Negotiated<Substream<StreamMuxerBox>>, info: T::Info) -> Self::Future {
+        let counters = self.stats.map(|stats| stats.record(info.protocol_name(), true));
+        let socket = NegotiatedSubstream { inner: socket, counters };
+        self.upgrade.upgrade_inbound(socket, info)
+    }
+}
+
+impl<T: OutboundUpgradeSend> upgrade::OutboundUpgrade<Negotiated<Substream<StreamMuxerBox>>> for RecordProtocol<T> {
+    type Output = T::Output;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn upgrade_outbound(self, socket: Negotiated<Substream<StreamMuxerBox>>, info: T::Info) -> Self::Future {
+        let counters = self.stats.map(|stats| stats.record(info.protocol_name(), false));
+        let socket = NegotiatedSubstream { inner: socket, counters };
+        self.upgrade.upgrade_outbound(socket, info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p_core::{PeerId, connection::ConnectionId};
+    use std::sync::atomic::Ordering;
+    use super::{ByteCounts, ProtocolStats, SubstreamCounts};
+
+    #[test]
+    fn counts_substreams_per_protocol() {
+        let stats = ProtocolStats::default();
+        let peer_id = PeerId::random();
+        stats.record(ConnectionId::new(0), &peer_id, b"/ipfs/ping/1.0.0", true);
+        stats.clone().record(ConnectionId::new(0), &peer_id, b"/ipfs/ping/1.0.0", false);
+        stats.record(ConnectionId::new(1), &peer_id, b"/ipfs/id/1.0.0", true);
+
+        assert_eq!(stats.get("/ipfs/ping/1.0.0"), SubstreamCounts { inbound: 1, outbound: 1 });
+        assert_eq!(stats.get("/unknown"), SubstreamCounts::default());
+        assert_eq!(stats.protocols(), vec![
+            ("/ipfs/id/1.0.0".to_string(), SubstreamCounts { inbound: 1, outbound: 0 }),
+            ("/ipfs/ping/1.0.0".to_string(), SubstreamCounts { inbound: 1, outbound: 1 }),
+        ]);
+    }
+
+    #[test]
+    fn accounts_bytes_per_connection_peer_and_protocol() {
+        let stats = ProtocolStats::default();
+        let (peer1, peer2) = (PeerId::random(), PeerId::random());
+        let (conn1, conn2, conn3) = (ConnectionId::new(1), ConnectionId::new(2), ConnectionId::new(3));
+
+        let ping1 = stats.record(conn1, &peer1, b"/ipfs/ping/1.0.0", false);
+        ping1.counters.sent.fetch_add(32, Ordering::Relaxed);
+        ping1.counters.received.fetch_add(32, Ordering::Relaxed);
+        let ping2 = stats.record(conn1, &peer1, b"/ipfs/ping/1.0.0", true);
+        ping2.counters.received.fetch_add(32, Ordering::Relaxed);
+        let kad = stats.record(conn2, &peer1, b"/ipfs/kad/1.0.0", false);
+        kad.counters.sent.fetch_add(100, Ordering::Relaxed);
+        let id = stats.record(conn3, &peer2, b"/ipfs/id/1.0.0", true);
+        id.counters.received.fetch_add(10, Ordering::Relaxed);
+
+        // The bytes of open substreams are accounted for.
+        let ping_counts = ByteCounts { received: 64, sent: 32 };
+        assert_eq!(stats.connection_bandwidth(&conn1).get("/ipfs/ping/1.0.0"), ping_counts);
+        assert_eq!(stats.connection_bandwidth(&conn1).get("/ipfs/kad/1.0.0"), ByteCounts::default());
+        assert_eq!(stats.peer_bandwidth(&peer1).total(), ByteCounts { received: 64, sent: 132 });
+        assert_eq!(stats.bandwidth().total(), ByteCounts { received: 74, sent: 132 });
+
+        // Closing substreams folds their bytes into the totals without counting them twice.
+        drop((ping2, kad, id));
+        assert_eq!(stats.connection_bandwidth(&conn1).get("/ipfs/ping/1.0.0"), ping_counts);
+        assert_eq!(stats.peer_bandwidth(&peer1).total(), ByteCounts { received: 64, sent: 132 });
+        assert_eq!(stats.bandwidth().total(), ByteCounts { received: 74, sent: 132 });
+
+        // The bytes of closed connections are reported and kept in the totals,
+        // including those of their substreams that are still open.
+        let closed = stats.remove_connection(&conn1);
+        assert_eq!(closed.protocols(), vec![("/ipfs/ping/1.0.0".to_string(), ping_counts)]);
+        assert_eq!(stats.connection_bandwidth(&conn1), Default::default());
+        assert_eq!(stats.peer_bandwidth(&peer1).total(), ByteCounts { received: 0, sent: 100 });
+        assert_eq!(stats.bandwidth().get("/ipfs/ping/1.0.0"), ping_counts);
+
+        // The bytes of substreams that outlive their connection keep being accounted for.
+        ping1.counters.sent.fetch_add(5, Ordering::Relaxed);
+        assert_eq!(stats.bandwidth().total(), ByteCounts { received: 74, sent: 137 });
+        drop(ping1);
+        assert_eq!(stats.bandwidth().total(), ByteCounts { received: 74, sent: 137 });
+    }
+}
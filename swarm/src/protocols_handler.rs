@@ -188,6 +188,21 @@ pub trait ProtocolsHandler: Send + 'static {
         ProtocolsHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::OutEvent, Self::Error>
     >;
 
+    /// Polls the handler for the completion of its in-flight work, once the connection
+    /// is being closed in an orderly manner, e.g. as part of a shutdown of the `Swarm`.
+    ///
+    /// Until this method returns `Poll::Ready`, the handler keeps being polled and
+    /// receiving events as usual, but should not start any new work, and no new inbound
+    /// substreams are negotiated. The connection is closed afterwards, regardless of
+    /// [`ProtocolsHandler::connection_keep_alive`], or once the close timeout configured
+    /// with [`SwarmBuilder::connection_close_timeout`](crate::SwarmBuilder::connection_close_timeout)
+    /// has expired.
+    ///
+    /// The default implementation returns `Poll::Ready` immediately.
+    fn poll_close(&mut self, _: &mut Context<'_>) -> Poll<()> {
+        Poll::Ready(())
+    }
+
     /// Adds a closure that turns the input event into something else.
     #[inline]
     fn map_in_event<TNewIn, TMap>(self, map: TMap) -> MapInEvent<Self, TNewIn, TMap>
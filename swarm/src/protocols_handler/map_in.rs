@@ -110,4 +110,9 @@ where
     > {
         self.inner.poll(cx)
     }
+
+    #[inline]
+    fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        self.inner.poll_close(cx)
+    }
 }
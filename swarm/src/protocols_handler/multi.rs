@@ -182,6 +182,19 @@ where
 
         Poll::Pending
     }
+
+    fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        // Poll all handlers, so that each of them can make progress.
+        let mut ready = true;
+        for h in self.handlers.values_mut() {
+            ready &= h.poll_close(cx).is_ready();
+        }
+        if ready {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
 }
 
 /// A [`IntoProtocolsHandler`] for multiple other `IntoProtocolsHandler`s.
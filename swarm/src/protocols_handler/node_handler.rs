@@ -18,6 +18,7 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+use crate::protocol_stats::{ConnectionStats, ProtocolStats, RecordProtocol};
 use crate::upgrade::SendWrapper;
 use crate::protocols_handler::{
     KeepAlive,
@@ -36,6 +37,7 @@ use libp2p_core::{
     connection::{
         ConnectionHandler,
         ConnectionHandlerEvent,
+        ConnectionId,
         IntoConnectionHandler,
         Substream,
         SubstreamEndpoint,
@@ -50,6 +52,8 @@ use wasm_timer::{Delay, Instant};
 pub struct NodeHandlerWrapperBuilder<TIntoProtoHandler> {
     /// The underlying handler.
     handler: TIntoProtoHandler,
+    /// The statistics to record the negotiated protocols in, if any.
+    protocol_stats: Option<ProtocolStats>,
 }
 
 impl<TIntoProtoHandler> NodeHandlerWrapperBuilder<TIntoProtoHandler>
@@ -60,8 +64,15 @@ where
     pub(crate) fn new(handler: TIntoProtoHandler) -> Self {
         NodeHandlerWrapperBuilder {
             handler,
+            protocol_stats: None,
         }
     }
+
+    /// Records the protocols negotiated on the substreams of the connection in `stats`.
+    pub(crate) fn with_protocol_stats(mut self, stats: ProtocolStats) -> Self {
+        self.protocol_stats = Some(stats);
+        self
+    }
 }
 
 impl<TIntoProtoHandler, TProtoHandler, TConnInfo> IntoConnectionHandler<TConnInfo>
@@ -73,7 +84,12 @@ where
 {
     type Handler = NodeHandlerWrapper<TIntoProtoHandler::Handler>;
 
-    fn into_handler(self, connected: &Connected<TConnInfo>) -> Self::Handler {
+    fn into_handler(self, id: ConnectionId, connected: &Connected<TConnInfo>) -> Self::Handler {
+        let protocol_stats = self.protocol_stats.map(|stats| ConnectionStats {
+            stats,
+            id,
+            peer_id: connected.peer_id().clone(),
+        });
         NodeHandlerWrapper {
             handler: self.handler.into_handler(connected.peer_id(), &connected.endpoint),
             negotiating_in: Vec::new(),
@@ -81,6 +97,8 @@ where
             queued_dial_upgrades: Vec::new(),
             unique_dial_upgrade_id: 0,
             shutdown: Shutdown::None,
+            closing: false,
+            protocol_stats,
         }
     }
 }
@@ -95,22 +113,29 @@ where
     /// The underlying handler.
     handler: TProtoHandler,
     /// Futures that upgrade incoming substreams.
-    negotiating_in:
-        Vec<(InboundUpgradeApply<Substream<StreamMuxerBox>, SendWrapper<TProtoHandler::InboundProtocol>>, Delay)>,
+    negotiating_in: Vec<(
+        InboundUpgradeApply<Substream<StreamMuxerBox>, RecordProtocol<SendWrapper<TProtoHandler::InboundProtocol>>>,
+        Delay,
+    )>,
     /// Futures that upgrade outgoing substreams. The first element of the tuple is the userdata
     /// to pass back once successfully opened.
     negotiating_out: Vec<(
         TProtoHandler::OutboundOpenInfo,
-        OutboundUpgradeApply<Substream<StreamMuxerBox>, SendWrapper<TProtoHandler::OutboundProtocol>>,
+        OutboundUpgradeApply<Substream<StreamMuxerBox>, RecordProtocol<SendWrapper<TProtoHandler::OutboundProtocol>>>,
         Delay,
     )>,
     /// For each outbound substream request, how to upgrade it. The first element of the tuple
     /// is the unique identifier (see `unique_dial_upgrade_id`).
-    queued_dial_upgrades: Vec<(u64, (upgrade::Version, SendWrapper<TProtoHandler::OutboundProtocol>))>,
+    queued_dial_upgrades: Vec<(u64, (upgrade::Version, RecordProtocol<SendWrapper<TProtoHandler::OutboundProtocol>>))>,
     /// Unique identifier assigned to each queued dial upgrade.
     unique_dial_upgrade_id: u64,
     /// The currently planned connection & handler shutdown.
     shutdown: Shutdown,
+    /// Whether the connection is being closed, in which case no shutdown
+    /// is planned anymore, see [`ConnectionHandler::poll_close`].
+    closing: bool,
+    /// The statistics to record the negotiated protocols and their bytes in, if any.
+    protocol_stats: Option<ConnectionStats>,
 }
 
 /// The options for a planned connection & handler shutdown.
@@ -192,7 +217,11 @@ where
             SubstreamEndpoint::Listener => {
                 let protocol = self.handler.listen_protocol();
                 let timeout = protocol.timeout().clone();
-                let upgrade = upgrade::apply_inbound(substream, SendWrapper(protocol.into_upgrade().1));
+                let upgrade = RecordProtocol {
+                    upgrade: SendWrapper(protocol.into_upgrade().1),
+                    stats: self.protocol_stats.clone(),
+                };
+                let upgrade = upgrade::apply_inbound(substream, upgrade);
                 let timeout = Delay::new(timeout);
                 self.negotiating_in.push((upgrade, timeout));
             }
@@ -316,7 +345,11 @@ where
                 let timeout = protocol.timeout().clone();
                 self.unique_dial_upgrade_id += 1;
                 let (version, upgrade) = protocol.into_upgrade();
-                self.queued_dial_upgrades.push((id, (version, SendWrapper(upgrade))));
+                let upgrade = RecordProtocol {
+                    upgrade: SendWrapper(upgrade),
+                    stats: self.protocol_stats.clone(),
+                };
+                self.queued_dial_upgrades.push((id, (version, upgrade)));
                 return Poll::Ready(Ok(
                     ConnectionHandlerEvent::OutboundSubstreamRequest((id, info, timeout)),
                 ));
@@ -327,7 +360,8 @@ where
 
         // Check if the connection (and handler) should be shut down.
         // As long as we're still negotiating substreams, shutdown is always postponed.
-        if self.negotiating_in.is_empty() && self.negotiating_out.is_empty() {
+        // A connection that is being closed is closed by `poll_close` instead.
+        if !self.closing && self.negotiating_in.is_empty() && self.negotiating_out.is_empty() {
             match self.shutdown {
                 Shutdown::None => {},
                 Shutdown::Asap => return Poll::Ready(Err(NodeHandlerWrapperError::KeepAliveTimeout)),
@@ -340,4 +374,13 @@ where
 
         Poll::Pending
     }
+
+    fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        self.closing = true;
+        // Substreams being negotiated are in-flight work of the handler.
+        if !self.negotiating_in.is_empty() || !self.negotiating_out.is_empty() {
+            return Poll::Pending
+        }
+        self.handler.poll_close(cx)
+    }
 }
@@ -225,6 +225,15 @@ where
 
         Poll::Pending
     }
+
+    fn poll_close(&mut self, _: &mut Context<'_>) -> Poll<()> {
+        // Let the requests that have been sent get their answers.
+        if self.pending_requests() == 0 && self.events_out.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
 }
 
 /// Configuration parameters for the `OneShotHandler`
@@ -229,4 +229,11 @@ where
 
         Poll::Pending
     }
+
+    fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        match (self.proto1.poll_close(cx), self.proto2.poll_close(cx)) {
+            (Poll::Ready(()), Poll::Ready(())) => Poll::Ready(()),
+            _ => Poll::Pending
+        }
+    }
 }
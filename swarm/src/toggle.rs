@@ -262,4 +262,12 @@ where
             Poll::Pending
         }
     }
+
+    fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(inner) = self.inner.as_mut() {
+            inner.poll_close(cx)
+        } else {
+            Poll::Ready(())
+        }
+    }
 }
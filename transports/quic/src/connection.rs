@@ -0,0 +1,256 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A QUIC connection, driven by whoever owns it.
+//!
+//! The [`Connection`] wraps the state machine of `quinn_proto` and exchanges packets with the
+//! background task of its [`Endpoint`] through channels.
+
+use crate::{endpoint::{Endpoint, ToEndpoint}, error::Error};
+use libp2p_tls::certificate;
+use bytes::Bytes;
+use futures::{channel::mpsc, prelude::*};
+use futures_timer::Delay;
+use libp2p_core::PeerId;
+use quinn_proto::{ConnectionEvent, ConnectionHandle, Dir, Event, Side, StreamId, VarInt};
+use std::{
+    fmt,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+/// State of a single QUIC connection.
+pub(crate) struct Connection {
+    /// Handle to the endpoint, which keeps its background task alive.
+    endpoint: Arc<Endpoint>,
+    /// Channel to the background task of the endpoint.
+    to_endpoint: mpsc::Sender<ToEndpoint>,
+    /// Events sent by the endpoint, in particular the received packets.
+    from_endpoint: mpsc::Receiver<ConnectionEvent>,
+    /// Identifier of the connection within the endpoint.
+    connection_id: ConnectionHandle,
+    /// The QUIC state machine of the connection. Boxed because of its size, as connections are
+    /// moved around within listener events and upgrade futures.
+    connection: Box<quinn_proto::Connection>,
+    /// Timer for the next timeout of the state machine, if any.
+    next_timeout: Option<(Instant, Delay)>,
+    /// Message for the endpoint that is waiting for space in the channel.
+    pending_to_endpoint: Option<ToEndpoint>,
+    /// Whether the endpoint has been told that the connection is drained.
+    drained_notified: bool,
+}
+
+impl Connection {
+    pub(crate) fn new(
+        endpoint: Arc<Endpoint>,
+        connection_id: ConnectionHandle,
+        connection: quinn_proto::Connection,
+        from_endpoint: mpsc::Receiver<ConnectionEvent>,
+    ) -> Self {
+        Connection {
+            to_endpoint: endpoint.to_endpoint(),
+            endpoint,
+            from_endpoint,
+            connection_id,
+            connection: Box::new(connection),
+            next_timeout: None,
+            pending_to_endpoint: None,
+            drained_notified: false,
+        }
+    }
+
+    /// Returns the address of the local socket of the connection.
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.endpoint.local_addr()
+    }
+
+    /// Returns the address of the remote.
+    pub(crate) fn remote_addr(&self) -> SocketAddr {
+        self.connection.remote_address()
+    }
+
+    /// Returns the `PeerId` of the remote, derived from the certificate it presented.
+    ///
+    /// Must only be called once the handshake has completed.
+    pub(crate) fn remote_peer_id(&self) -> Result<PeerId, Error> {
+        debug_assert!(!self.connection.is_handshaking());
+        let certificates = self.connection.crypto_session()
+            .get_peer_certificates()
+            .ok_or(Error::MissingCertificate)?;
+        let certificate = certificates.first().ok_or(Error::MissingCertificate)?;
+        let public_key = match self.connection.side() {
+            Side::Client => certificate::verify_server_certificate(&certificate.0),
+            Side::Server => certificate::verify_client_certificate(&certificate.0),
+        }.map_err(Error::BadCertificate)?;
+        Ok(public_key.into_peer_id())
+    }
+
+    /// Opens a new bidirectional substream, if the remote allows it.
+    pub(crate) fn open_substream(&mut self) -> Option<StreamId> {
+        self.connection.open(Dir::Bi)
+    }
+
+    /// Accepts a bidirectional substream opened by the remote, if any.
+    pub(crate) fn accept_substream(&mut self) -> Option<StreamId> {
+        self.connection.accept(Dir::Bi)
+    }
+
+    /// Gives access to the state machine, for operations on substreams.
+    pub(crate) fn inner_mut(&mut self) -> &mut quinn_proto::Connection {
+        &mut self.connection
+    }
+
+    /// Closes the connection. The remote is notified through the next calls to `poll_event`.
+    pub(crate) fn close(&mut self) {
+        self.connection.close(Instant::now(), VarInt::from_u32(0), Bytes::new());
+    }
+
+    /// Returns `true` if all the packets produced so far have been handed to the endpoint.
+    pub(crate) fn is_flushed(&self) -> bool {
+        self.pending_to_endpoint.is_none()
+    }
+
+    /// Drives the connection and returns the next event of the state machine.
+    ///
+    /// The connection makes progress, in particular its packets are sent, only while this
+    /// method is being called.
+    pub(crate) fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Event> {
+        loop {
+            // Deliver the message that could not be sent earlier.
+            if let Some(message) = self.pending_to_endpoint.take() {
+                match self.to_endpoint.poll_ready(cx) {
+                    Poll::Ready(Ok(())) => {
+                        let is_drained = message.is_drained();
+                        if self.to_endpoint.start_send(message).is_ok() && is_drained {
+                            self.drained_notified = true;
+                        }
+                    }
+                    // The endpoint is gone, which only happens once we are dropped.
+                    Poll::Ready(Err(_)) => {}
+                    Poll::Pending => {
+                        self.pending_to_endpoint = Some(message);
+                        return Poll::Pending
+                    }
+                }
+            }
+
+            // Process the packets and events sent by the endpoint.
+            match Pin::new(&mut self.from_endpoint).poll_next(cx) {
+                Poll::Ready(Some(event)) => {
+                    self.connection.handle_event(event);
+                    continue
+                }
+                Poll::Ready(None) | Poll::Pending => {}
+            }
+
+            // Hand the outgoing packets to the endpoint.
+            if let Some(transmit) = self.connection.poll_transmit(Instant::now()) {
+                self.pending_to_endpoint = Some(ToEndpoint::SendUdpPacket(transmit));
+                continue
+            }
+
+            // Handle the timers of the state machine.
+            match self.connection.poll_timeout() {
+                Some(timeout) => {
+                    let now = Instant::now();
+                    if timeout <= now {
+                        self.next_timeout = None;
+                        self.connection.handle_timeout(now);
+                        continue
+                    }
+                    match &mut self.next_timeout {
+                        Some((at, delay)) if *at == timeout => {
+                            if delay.poll_unpin(cx).is_ready() {
+                                self.next_timeout = None;
+                                self.connection.handle_timeout(Instant::now());
+                                continue
+                            }
+                        }
+                        _ => {
+                            let mut delay = Delay::new(timeout - now);
+                            if delay.poll_unpin(cx).is_ready() {
+                                self.connection.handle_timeout(Instant::now());
+                                continue
+                            }
+                            self.next_timeout = Some((timeout, delay));
+                        }
+                    }
+                }
+                None => self.next_timeout = None,
+            }
+
+            // Forward the events of the state machine to the endpoint.
+            if let Some(event) = self.connection.poll_endpoint_events() {
+                self.pending_to_endpoint = Some(ToEndpoint::ProcessConnectionEvent {
+                    connection_id: self.connection_id,
+                    event,
+                });
+                continue
+            }
+
+            if let Some(event) = self.connection.poll() {
+                return Poll::Ready(event)
+            }
+
+            return Poll::Pending
+        }
+    }
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("local_addr", &self.local_addr())
+            .field("remote_addr", &self.remote_addr())
+            .finish()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if self.drained_notified {
+            return
+        }
+
+        // Every clone of a sender is guaranteed a slot in the channel, hence the messages
+        // below are delivered as long as the endpoint is running.
+        if let Some(message) = self.pending_to_endpoint.take() {
+            let is_drained = message.is_drained();
+            let _ = self.to_endpoint.clone().try_send(message);
+            if is_drained {
+                return
+            }
+        }
+        if !self.connection.is_closed() {
+            self.close();
+        }
+        let now = Instant::now();
+        while let Some(transmit) = self.connection.poll_transmit(now) {
+            let _ = self.to_endpoint.clone().try_send(ToEndpoint::SendUdpPacket(transmit));
+        }
+        let _ = self.to_endpoint.clone().try_send(ToEndpoint::ProcessConnectionEvent {
+            connection_id: self.connection_id,
+            event: quinn_proto::EndpointEvent::drained(),
+        });
+    }
+}
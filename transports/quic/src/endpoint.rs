@@ -0,0 +1,272 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The endpoint owns the UDP socket of a QUIC transport.
+//!
+//! Each endpoint runs a background task which reads datagrams from the socket and dispatches
+//! them to the corresponding [`Connection`], and which sends out the packets produced by the
+//! connections. The task stops once all the [`Endpoint`] handles, held by listeners and
+//! connections, have been dropped.
+
+use crate::{connection::Connection, error::Error};
+use async_std::net::UdpSocket;
+use bytes::BytesMut;
+use futures::{channel::{mpsc, oneshot}, future::Either, prelude::*};
+use quinn_proto::{ConnectionEvent, ConnectionHandle, DatagramEvent, EndpointEvent, Transmit};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Weak},
+    time::Instant,
+};
+
+/// Name used as SNI when dialing. The server certificate is never checked against it.
+const SERVER_NAME: &str = "l";
+
+/// Size of the buffers of the channels between the endpoint and its connections.
+const CHANNEL_BUFFER: usize = 16;
+
+/// Message sent to the background task of an endpoint.
+pub(crate) enum ToEndpoint {
+    /// Open a new connection to the given address.
+    Dial {
+        addr: SocketAddr,
+        result: oneshot::Sender<Result<Connection, Error>>,
+    },
+    /// Event produced by a connection for the endpoint state machine.
+    ProcessConnectionEvent {
+        connection_id: ConnectionHandle,
+        event: EndpointEvent,
+    },
+    /// Packet produced by a connection, to be sent on the socket.
+    SendUdpPacket(Transmit),
+}
+
+impl ToEndpoint {
+    /// Returns `true` if this message tells the endpoint that a connection is drained.
+    pub(crate) fn is_drained(&self) -> bool {
+        match self {
+            ToEndpoint::ProcessConnectionEvent { event, .. } => event.is_drained(),
+            _ => false,
+        }
+    }
+}
+
+/// Handle to the background task of a QUIC endpoint.
+pub(crate) struct Endpoint {
+    /// Channel to the background task. Cloned for every connection.
+    to_endpoint: mpsc::Sender<ToEndpoint>,
+    /// Address the UDP socket is bound to.
+    local_addr: SocketAddr,
+}
+
+impl Endpoint {
+    /// Creates an endpoint on the given socket and spawns its background task.
+    ///
+    /// If `server` is `Some`, incoming connections are accepted and sent on the given channel.
+    /// Otherwise incoming connections are rejected.
+    pub(crate) fn new(
+        socket: std::net::UdpSocket,
+        endpoint_config: Arc<quinn_proto::EndpointConfig>,
+        client_config: quinn_proto::ClientConfig,
+        server: Option<(Arc<quinn_proto::ServerConfig>, mpsc::Sender<Connection>)>,
+    ) -> Result<Arc<Endpoint>, Error> {
+        let local_addr = socket.local_addr()?;
+        let (to_endpoint, receiver) = mpsc::channel(CHANNEL_BUFFER);
+        let endpoint = Arc::new(Endpoint { to_endpoint, local_addr });
+
+        let (server_config, new_connections) = match server {
+            Some((config, sender)) => (Some(config), Some(sender)),
+            None => (None, None),
+        };
+        let background = BackgroundTask {
+            endpoint: quinn_proto::Endpoint::new(endpoint_config, server_config),
+            handle: Arc::downgrade(&endpoint),
+            client_config,
+            socket: UdpSocket::from(socket),
+            local_addr,
+            receiver,
+            new_connections,
+            alive_connections: HashMap::new(),
+        };
+        async_std::task::spawn(background.run());
+
+        Ok(endpoint)
+    }
+
+    /// Returns the address the UDP socket of the endpoint is bound to.
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns a new channel to the background task.
+    pub(crate) fn to_endpoint(&self) -> mpsc::Sender<ToEndpoint> {
+        self.to_endpoint.clone()
+    }
+
+    /// Opens a new connection to the given address.
+    ///
+    /// The returned connection still has to complete its handshake.
+    pub(crate) async fn dial(self: Arc<Self>, addr: SocketAddr) -> Result<Connection, Error> {
+        let (result, rx) = oneshot::channel();
+        self.to_endpoint()
+            .send(ToEndpoint::Dial { addr, result })
+            .await
+            .map_err(|_| Error::EndpointClosed)?;
+        rx.await.map_err(|_| Error::EndpointClosed)?
+    }
+}
+
+/// State of the background task of an endpoint.
+struct BackgroundTask {
+    /// The QUIC state machine of the endpoint.
+    endpoint: quinn_proto::Endpoint,
+    /// Handle given to new connections. Only a weak reference is kept, so that the task stops
+    /// once all the handles are gone.
+    handle: Weak<Endpoint>,
+    /// Configuration of outgoing connections.
+    client_config: quinn_proto::ClientConfig,
+    /// The UDP socket.
+    socket: UdpSocket,
+    /// Address the UDP socket is bound to.
+    local_addr: SocketAddr,
+    /// Messages from the handles and connections.
+    receiver: mpsc::Receiver<ToEndpoint>,
+    /// Channel on which incoming connections are reported to the listener, if any.
+    new_connections: Option<mpsc::Sender<Connection>>,
+    /// Channels to the connections, for dispatching their datagrams.
+    alive_connections: HashMap<ConnectionHandle, mpsc::Sender<ConnectionEvent>>,
+}
+
+impl BackgroundTask {
+    async fn run(mut self) {
+        let mut socket_recv_buffer = vec![0; 65536];
+
+        loop {
+            while let Some(transmit) = self.endpoint.poll_transmit() {
+                self.send(transmit).await;
+            }
+
+            let next = futures::select! {
+                message = self.receiver.next() => Either::Left(message),
+                result = self.socket.recv_from(&mut socket_recv_buffer).fuse() => Either::Right(result),
+            };
+
+            match next {
+                Either::Left(Some(message)) => self.on_message(message).await,
+                // All handles are gone.
+                Either::Left(None) => break,
+                Either::Right(Ok((len, from))) => {
+                    let packet = BytesMut::from(&socket_recv_buffer[.. len]);
+                    self.on_packet(from, packet);
+                }
+                Either::Right(Err(err)) =>
+                    log::debug!("Error receiving on {}: {}", self.local_addr, err),
+            }
+        }
+
+        log::debug!("QUIC endpoint on {} stopped", self.local_addr);
+    }
+
+    async fn send(&self, transmit: Transmit) {
+        if let Err(err) = self.socket.send_to(&transmit.contents, transmit.destination).await {
+            log::debug!("Error sending packet to {}: {}", transmit.destination, err);
+        }
+    }
+
+    async fn on_message(&mut self, message: ToEndpoint) {
+        match message {
+            ToEndpoint::Dial { addr, result } => {
+                let connection = self.endpoint
+                    .connect(self.client_config.clone(), addr, SERVER_NAME)
+                    .map_err(Error::Connect)
+                    .and_then(|(id, connection)| self.new_connection(id, connection));
+                let _ = result.send(connection);
+            }
+            ToEndpoint::ProcessConnectionEvent { connection_id, event } => {
+                if event.is_drained() {
+                    self.alive_connections.remove(&connection_id);
+                }
+                if let Some(event) = self.endpoint.handle_event(connection_id, event) {
+                    if let Some(sender) = self.alive_connections.get(&connection_id) {
+                        // Every clone of a sender is guaranteed a slot in the channel, which
+                        // ensures that events of the state machine are never dropped.
+                        let _ = sender.clone().try_send(event);
+                    }
+                }
+            }
+            ToEndpoint::SendUdpPacket(transmit) => self.send(transmit).await,
+        }
+    }
+
+    fn on_packet(&mut self, from: SocketAddr, packet: BytesMut) {
+        match self.endpoint.handle(Instant::now(), from, None, packet) {
+            None => {}
+            Some((connection_id, DatagramEvent::ConnectionEvent(event))) => {
+                if let Some(sender) = self.alive_connections.get_mut(&connection_id) {
+                    if let Err(err) = sender.try_send(event) {
+                        // Like on the network, packets are dropped if the connection does not
+                        // keep up.
+                        if err.is_full() {
+                            log::trace!("Dropping packet from {} for busy connection", from);
+                        }
+                    }
+                }
+            }
+            Some((connection_id, DatagramEvent::NewConnection(connection))) => {
+                // Release the slot in the accept buffer of the endpoint.
+                self.endpoint.accept();
+                let connection = match self.new_connection(connection_id, connection) {
+                    Ok(connection) => connection,
+                    Err(_) => return,
+                };
+                let delivered = match self.new_connections.as_mut() {
+                    Some(sender) => match sender.try_send(connection) {
+                        Ok(()) => true,
+                        Err(err) => {
+                            if err.is_disconnected() {
+                                log::debug!("Listener on {} is gone, rejecting new connections",
+                                    self.local_addr);
+                                self.endpoint.reject_new_connections();
+                                self.new_connections = None;
+                            }
+                            false
+                        }
+                    },
+                    None => false,
+                };
+                if !delivered {
+                    log::debug!("Dropping incoming connection from {}", from);
+                }
+            }
+        }
+    }
+
+    fn new_connection(
+        &mut self,
+        id: ConnectionHandle,
+        connection: quinn_proto::Connection,
+    ) -> Result<Connection, Error> {
+        let endpoint = self.handle.upgrade().ok_or(Error::EndpointClosed)?;
+        let (sender, receiver) = mpsc::channel(CHANNEL_BUFFER);
+        self.alive_connections.insert(id, sender);
+        Ok(Connection::new(endpoint, id, connection, receiver))
+    }
+}
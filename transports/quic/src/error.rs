@@ -0,0 +1,94 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use libp2p_tls::certificate::VerificationError;
+use std::{error, fmt, io};
+
+/// Error that can happen on a QUIC connection or endpoint.
+#[derive(Debug)]
+pub enum Error {
+    /// Error on the UDP socket.
+    Io(io::Error),
+    /// The background task driving the endpoint has stopped.
+    EndpointClosed,
+    /// Failed to initiate a connection.
+    Connect(quinn_proto::ConnectError),
+    /// The connection was lost, either during or after the handshake.
+    ConnectionLost(quinn_proto::ConnectionError),
+    /// The certificate presented by the remote is not a valid libp2p certificate.
+    BadCertificate(VerificationError),
+    /// The remote did not present a certificate.
+    MissingCertificate,
+    /// The remote has reset the substream with the given error code.
+    Reset(quinn_proto::VarInt),
+    /// The remote has stopped reading from the substream with the given error code.
+    Stopped(quinn_proto::VarInt),
+    /// The substream is not known to the connection.
+    UnknownSubstream,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::EndpointClosed => f.write_str("QUIC endpoint has stopped"),
+            Error::Connect(err) => write!(f, "Failed to connect: {}", err),
+            Error::ConnectionLost(err) => write!(f, "Connection lost: {}", err),
+            Error::BadCertificate(err) => write!(f, "Invalid remote certificate: {}", err),
+            Error::MissingCertificate => f.write_str("Remote did not present a certificate"),
+            Error::Reset(code) => write!(f, "Substream reset by remote (code {})", code),
+            Error::Stopped(code) => write!(f, "Substream stopped by remote (code {})", code),
+            Error::UnknownSubstream => f.write_str("Unknown substream"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Connect(err) => Some(err),
+            Error::ConnectionLost(err) => Some(err),
+            Error::BadCertificate(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            Error::Reset(_) => io::Error::new(io::ErrorKind::ConnectionReset, err),
+            Error::Stopped(_) => io::Error::new(io::ErrorKind::BrokenPipe, err),
+            Error::ConnectionLost(quinn_proto::ConnectionError::TimedOut) =>
+                io::Error::new(io::ErrorKind::TimedOut, err),
+            Error::ConnectionLost(_) | Error::EndpointClosed =>
+                io::Error::new(io::ErrorKind::ConnectionAborted, err),
+            _ => io::Error::new(io::ErrorKind::Other, err),
+        }
+    }
+}
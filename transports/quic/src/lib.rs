@@ -0,0 +1,106 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Implementation of the libp2p `Transport` trait for QUIC.
+//!
+//! # Usage
+//!
+//! Example:
+//!
+//! ```
+//! use libp2p_core::{identity, Multiaddr, Transport};
+//! use libp2p_quic::QuicConfig;
+//!
+//! let keypair = identity::Keypair::generate_ed25519();
+//! let quic = QuicConfig::new(&keypair).expect("certificate generation to succeed");
+//!
+//! let addr: Multiaddr = "/ip4/127.0.0.1/udp/0/quic".parse().expect("bad multiaddress");
+//! let _listener = quic.listen_on(addr).expect("listen error.");
+//! ```
+//!
+//! The `QuicConfig` struct implements the `Transport` trait of the `core` library. See the
+//! documentation of `core` and of libp2p in general to learn how to use the `Transport` trait.
+//!
+//! # Combining with other transports
+//!
+//! Contrary to TCP, QUIC connections are already encrypted and multiplexed. The output of the
+//! transport is a `(PeerId, QuicMuxer)` tuple, meaning that no additional security or
+//! multiplexing upgrade needs to be applied. When combined with other transports through
+//! `OrTransport`, the other transports are upgraded separately and both outputs are mapped to
+//! a `StreamMuxerBox`:
+//!
+//! ```
+//! use libp2p_core::{either::EitherOutput, identity, muxing::StreamMuxerBox, upgrade, Transport};
+//! use libp2p_mplex::MplexConfig;
+//! use libp2p_noise::{Keypair, NoiseConfig, X25519Spec};
+//! use libp2p_quic::QuicConfig;
+//! use libp2p_tcp::TcpConfig;
+//!
+//! let keypair = identity::Keypair::generate_ed25519();
+//! let noise_keys = Keypair::<X25519Spec>::new().into_authentic(&keypair).unwrap();
+//!
+//! let quic = QuicConfig::new(&keypair).unwrap()
+//!     .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)));
+//! let tcp = TcpConfig::new()
+//!     .upgrade(upgrade::Version::V1)
+//!     .authenticate(NoiseConfig::xx(noise_keys).into_authenticated())
+//!     .multiplex(MplexConfig::new())
+//!     .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)));
+//! let transport = quic.or_transport(tcp)
+//!     .map(|output, _| match output {
+//!         EitherOutput::First(output) => output,
+//!         EitherOutput::Second(output) => output,
+//!     });
+//!
+//! let _quic_listener = transport.clone()
+//!     .listen_on("/ip4/127.0.0.1/udp/0/quic".parse().unwrap())
+//!     .expect("listen error.");
+//! let _tcp_listener = transport
+//!     .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+//!     .expect("listen error.");
+//! ```
+//!
+//! # Authentication
+//!
+//! Each side of a connection presents a self-signed X.509 certificate that embeds the libp2p
+//! public key of the node, along with a signature made with the corresponding private key, in
+//! the libp2p public key extension. The `PeerId` of the remote is derived from that public key
+//! once the TLS 1.3 handshake has completed. See the
+//! [libp2p TLS specification](https://github.com/libp2p/specs/blob/master/tls/tls.md) for
+//! details.
+//!
+//! # Connection migration
+//!
+//! Connection migration is disabled. A connection is bound to the pair of UDP addresses it was
+//! established on for its entire lifetime.
+
+mod connection;
+mod endpoint;
+mod error;
+mod muxer;
+mod tls;
+mod transport;
+mod upgrade;
+
+pub use libp2p_tls::certificate::GenError;
+pub use error::Error;
+pub use muxer::QuicMuxer;
+pub use transport::{QuicConfig, QuicListener};
+pub use upgrade::Upgrade;
@@ -0,0 +1,315 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::{connection::Connection, error::Error};
+use libp2p_core::{StreamMuxer, muxing::StreamMuxerEvent};
+use parking_lot::Mutex;
+use quinn_proto::{Event, FinishError, ReadError, StreamId, VarInt, WriteError};
+use std::{
+    collections::HashMap,
+    fmt,
+    task::{Context, Poll, Waker},
+};
+
+/// State for a single opened QUIC connection, implementing `StreamMuxer`.
+///
+/// Substreams are QUIC bidirectional streams. The connection is driven by `poll_event`, which
+/// hence needs to be polled continuously for substreams to make progress.
+pub struct QuicMuxer {
+    inner: Mutex<QuicMuxerInner>,
+}
+
+struct QuicMuxerInner {
+    /// The QUIC connection.
+    connection: Connection,
+    /// State of the substreams that are currently open.
+    substreams: HashMap<StreamId, SubstreamState>,
+    /// Task waiting for the connection to make progress.
+    poll_event_waker: Option<Waker>,
+    /// Task waiting for a new outbound substream to become available.
+    poll_outbound_waker: Option<Waker>,
+    /// Error that has closed the connection, if any.
+    error: Option<quinn_proto::ConnectionError>,
+    /// Whether `close` has been called.
+    closing: bool,
+}
+
+#[derive(Default)]
+struct SubstreamState {
+    /// Task waiting for data to read.
+    read_waker: Option<Waker>,
+    /// Task waiting for room to write.
+    write_waker: Option<Waker>,
+    /// Whether the remote has finished writing and all the data has been read.
+    read_finished: bool,
+    /// Whether we have finished writing.
+    write_finished: bool,
+}
+
+impl QuicMuxer {
+    /// Wraps a connection whose handshake has completed.
+    pub(crate) fn from_connection(connection: Connection) -> Self {
+        QuicMuxer {
+            inner: Mutex::new(QuicMuxerInner {
+                connection,
+                substreams: HashMap::new(),
+                poll_event_waker: None,
+                poll_outbound_waker: None,
+                error: None,
+                closing: false,
+            })
+        }
+    }
+}
+
+impl QuicMuxerInner {
+    /// Wakes up the task driving the connection, for the packets to be sent.
+    fn wake_driver(&mut self) {
+        if let Some(waker) = self.poll_event_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Wakes up all the tasks waiting on the connection.
+    fn wake_all(&mut self) {
+        self.wake_driver();
+        if let Some(waker) = self.poll_outbound_waker.take() {
+            waker.wake();
+        }
+        for substream in self.substreams.values_mut() {
+            if let Some(waker) = substream.read_waker.take() {
+                waker.wake();
+            }
+            if let Some(waker) = substream.write_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn connection_error(&self) -> Option<Error> {
+        self.error.clone().map(Error::ConnectionLost)
+    }
+
+    fn substream(&mut self, id: StreamId) -> Result<&mut SubstreamState, Error> {
+        self.substreams.get_mut(&id).ok_or(Error::UnknownSubstream)
+    }
+}
+
+impl StreamMuxer for QuicMuxer {
+    type Substream = StreamId;
+    type OutboundSubstream = ();
+    type Error = Error;
+
+    fn poll_event(&self, cx: &mut Context<'_>) -> Poll<Result<StreamMuxerEvent<Self::Substream>, Self::Error>> {
+        let mut inner = self.inner.lock();
+        if let Some(err) = inner.connection_error() {
+            return Poll::Ready(Err(err))
+        }
+        inner.poll_event_waker = Some(cx.waker().clone());
+
+        while let Poll::Ready(event) = inner.connection.poll_event(cx) {
+            match event {
+                Event::Connected | Event::StreamOpened { .. } | Event::DatagramReceived => {}
+                Event::ConnectionLost { reason } => {
+                    log::debug!("Connection to {} lost: {}", inner.connection.remote_addr(), reason);
+                    inner.error = Some(reason.clone());
+                    inner.wake_all();
+                    return Poll::Ready(Err(Error::ConnectionLost(reason)))
+                }
+                Event::StreamReadable { stream } => {
+                    if let Some(waker) = inner.substreams.get_mut(&stream).and_then(|s| s.read_waker.take()) {
+                        waker.wake();
+                    }
+                }
+                Event::StreamWritable { stream } | Event::StreamFinished { stream, .. } => {
+                    if let Some(waker) = inner.substreams.get_mut(&stream).and_then(|s| s.write_waker.take()) {
+                        waker.wake();
+                    }
+                }
+                Event::StreamAvailable { .. } => {
+                    if let Some(waker) = inner.poll_outbound_waker.take() {
+                        waker.wake();
+                    }
+                }
+            }
+        }
+
+        if let Some(id) = inner.connection.accept_substream() {
+            inner.substreams.insert(id, SubstreamState::default());
+            return Poll::Ready(Ok(StreamMuxerEvent::InboundSubstream(id)))
+        }
+
+        Poll::Pending
+    }
+
+    fn open_outbound(&self) -> Self::OutboundSubstream {}
+
+    fn poll_outbound(&self, cx: &mut Context<'_>, _: &mut Self::OutboundSubstream)
+        -> Poll<Result<Self::Substream, Self::Error>>
+    {
+        let mut inner = self.inner.lock();
+        if let Some(err) = inner.connection_error() {
+            return Poll::Ready(Err(err))
+        }
+
+        match inner.connection.open_substream() {
+            Some(id) => {
+                inner.substreams.insert(id, SubstreamState::default());
+                Poll::Ready(Ok(id))
+            }
+            None => {
+                inner.poll_outbound_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn destroy_outbound(&self, _: Self::OutboundSubstream) {}
+
+    fn read_substream(&self, cx: &mut Context<'_>, substream: &mut Self::Substream, buf: &mut [u8])
+        -> Poll<Result<usize, Self::Error>>
+    {
+        let mut inner = self.inner.lock();
+        if inner.substream(*substream)?.read_finished {
+            return Poll::Ready(Ok(0))
+        }
+
+        match inner.connection.inner_mut().read(*substream, buf) {
+            Ok(Some(len)) => {
+                // Reading may have opened the flow control window of the remote.
+                inner.wake_driver();
+                Poll::Ready(Ok(len))
+            }
+            Ok(None) => {
+                inner.substream(*substream)?.read_finished = true;
+                Poll::Ready(Ok(0))
+            }
+            Err(ReadError::Blocked) => {
+                if let Some(err) = inner.connection_error() {
+                    return Poll::Ready(Err(err))
+                }
+                inner.substream(*substream)?.read_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            Err(ReadError::Reset(code)) => Poll::Ready(Err(Error::Reset(code))),
+            Err(ReadError::UnknownStream) => Poll::Ready(Err(Error::UnknownSubstream)),
+        }
+    }
+
+    fn write_substream(&self, cx: &mut Context<'_>, substream: &mut Self::Substream, buf: &[u8])
+        -> Poll<Result<usize, Self::Error>>
+    {
+        let mut inner = self.inner.lock();
+        if let Some(err) = inner.connection_error() {
+            return Poll::Ready(Err(err))
+        }
+
+        match inner.connection.inner_mut().write(*substream, buf) {
+            Ok(len) => {
+                inner.wake_driver();
+                Poll::Ready(Ok(len))
+            }
+            Err(WriteError::Blocked) => {
+                inner.substream(*substream)?.write_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            Err(WriteError::Stopped(code)) => Poll::Ready(Err(Error::Stopped(code))),
+            Err(WriteError::UnknownStream) => Poll::Ready(Err(Error::UnknownSubstream)),
+        }
+    }
+
+    fn flush_substream(&self, _: &mut Context<'_>, _: &mut Self::Substream)
+        -> Poll<Result<(), Self::Error>>
+    {
+        // Written data is sent by the connection as soon as possible.
+        Poll::Ready(Ok(()))
+    }
+
+    fn shutdown_substream(&self, _: &mut Context<'_>, substream: &mut Self::Substream)
+        -> Poll<Result<(), Self::Error>>
+    {
+        let mut inner = self.inner.lock();
+        if inner.substream(*substream)?.write_finished {
+            return Poll::Ready(Ok(()))
+        }
+
+        match inner.connection.inner_mut().finish(*substream) {
+            Ok(()) => {
+                inner.substream(*substream)?.write_finished = true;
+                inner.wake_driver();
+                Poll::Ready(Ok(()))
+            }
+            Err(FinishError::Stopped(code)) => Poll::Ready(Err(Error::Stopped(code))),
+            Err(FinishError::UnknownStream) => Poll::Ready(Err(Error::UnknownSubstream)),
+        }
+    }
+
+    fn destroy_substream(&self, substream: Self::Substream) {
+        let mut inner = self.inner.lock();
+        let state = match inner.substreams.remove(&substream) {
+            Some(state) => state,
+            None => return,
+        };
+        if !state.write_finished {
+            inner.connection.inner_mut().reset(substream, VarInt::from_u32(0));
+        }
+        if !state.read_finished {
+            let _ = inner.connection.inner_mut().stop_sending(substream, VarInt::from_u32(0));
+        }
+        inner.wake_driver();
+    }
+
+    fn close(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let mut inner = self.inner.lock();
+        if inner.error.is_some() {
+            return Poll::Ready(Ok(()))
+        }
+        if !inner.closing {
+            inner.closing = true;
+            inner.connection.close();
+            inner.wake_all();
+        }
+
+        // Drive the connection until the close frame has been handed to the endpoint.
+        while let Poll::Ready(event) = inner.connection.poll_event(cx) {
+            if let Event::ConnectionLost { .. } = event {
+                return Poll::Ready(Ok(()))
+            }
+        }
+
+        if inner.connection.is_flushed() {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn flush_all(&self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl fmt::Debug for QuicMuxer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QuicMuxer")
+            .field("connection", &self.inner.lock().connection)
+            .finish()
+    }
+}
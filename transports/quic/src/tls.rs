@@ -0,0 +1,125 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! TLS 1.3 configuration used to secure QUIC connections.
+//!
+//! Certificates are not verified against a set of trusted roots. Instead, both sides must
+//! present a self-signed libp2p certificate, from which the public key of the remote is
+//! extracted.
+//!
+//! The verifiers mirror those of `libp2p-tls`, which implement the traits of rustls 0.18 whereas
+//! `quinn-proto` requires rustls 0.17.
+
+use libp2p_tls::certificate;
+use std::sync::Arc;
+
+/// The ALPN protocol negotiated on libp2p QUIC connections.
+const P2P_ALPN: &[u8] = b"libp2p";
+
+/// Builds the client side TLS configuration presenting the given certificate.
+pub(crate) fn make_client_config(
+    certificate: rustls::Certificate,
+    key: rustls::PrivateKey,
+) -> rustls::ClientConfig {
+    let mut crypto = rustls::ClientConfig::new();
+    crypto.versions = vec![rustls::ProtocolVersion::TLSv1_3];
+    crypto.alpn_protocols = vec![P2P_ALPN.to_vec()];
+    crypto.enable_early_data = false;
+    crypto.set_single_client_cert(vec![certificate], key)
+        .expect("Client cert key DER is valid; qed");
+    crypto.dangerous().set_certificate_verifier(Arc::new(Libp2pServerCertificateVerifier));
+    crypto
+}
+
+/// Builds the server side TLS configuration presenting the given certificate.
+pub(crate) fn make_server_config(
+    certificate: rustls::Certificate,
+    key: rustls::PrivateKey,
+) -> rustls::ServerConfig {
+    let mut crypto = rustls::ServerConfig::new(Arc::new(Libp2pClientCertificateVerifier));
+    crypto.versions = vec![rustls::ProtocolVersion::TLSv1_3];
+    crypto.alpn_protocols = vec![P2P_ALPN.to_vec()];
+    crypto.set_single_cert(vec![certificate], key)
+        .expect("Server cert key DER is valid; qed");
+    crypto
+}
+
+/// Verifies the certificate presented by a server.
+///
+/// The server name is ignored, as the identity of the remote is given by the libp2p public key
+/// carried by the certificate.
+struct Libp2pServerCertificateVerifier;
+
+impl rustls::ServerCertVerifier for Libp2pServerCertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &rustls::RootCertStore,
+        presented_certs: &[rustls::Certificate],
+        _dns_name: webpki::DNSNameRef<'_>,
+        _ocsp_response: &[u8],
+    ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        let certificate = single_certificate(presented_certs)?;
+        certificate::verify_server_certificate(&certificate.0)
+            .map_err(|err| rustls::TLSError::General(err.to_string()))?;
+        Ok(rustls::ServerCertVerified::assertion())
+    }
+}
+
+/// Verifies the certificate presented by a client. Client authentication is mandatory.
+struct Libp2pClientCertificateVerifier;
+
+impl rustls::ClientCertVerifier for Libp2pClientCertificateVerifier {
+    fn offer_client_auth(&self) -> bool {
+        true
+    }
+
+    fn client_auth_mandatory(&self, _sni: Option<&webpki::DNSName>) -> Option<bool> {
+        Some(true)
+    }
+
+    fn client_auth_root_subjects(
+        &self,
+        _sni: Option<&webpki::DNSName>,
+    ) -> Option<rustls::DistinguishedNames> {
+        Some(Vec::new())
+    }
+
+    fn verify_client_cert(
+        &self,
+        presented_certs: &[rustls::Certificate],
+        _sni: Option<&webpki::DNSName>,
+    ) -> Result<rustls::ClientCertVerified, rustls::TLSError> {
+        let certificate = single_certificate(presented_certs)?;
+        certificate::verify_client_certificate(&certificate.0)
+            .map_err(|err| rustls::TLSError::General(err.to_string()))?;
+        Ok(rustls::ClientCertVerified::assertion())
+    }
+}
+
+/// Libp2p certificates are self-signed, hence exactly one certificate must be presented.
+fn single_certificate(
+    presented_certs: &[rustls::Certificate],
+) -> Result<&rustls::Certificate, rustls::TLSError> {
+    match presented_certs {
+        [certificate] => Ok(certificate),
+        [] => Err(rustls::TLSError::NoCertificatesPresented),
+        _ => Err(rustls::TLSError::General("Expected exactly one certificate".into())),
+    }
+}
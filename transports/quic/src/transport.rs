@@ -0,0 +1,292 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::{
+    connection::Connection,
+    endpoint::Endpoint,
+    error::Error,
+    muxer::QuicMuxer,
+    tls,
+    upgrade::Upgrade,
+};
+use futures::{channel::mpsc, future::BoxFuture, prelude::*};
+use get_if_addrs::get_if_addrs;
+use libp2p_core::{
+    identity,
+    multiaddr::{Multiaddr, Protocol},
+    transport::{ListenerEvent, Transport, TransportError},
+    PeerId,
+};
+use libp2p_tls::certificate::{self, GenError};
+use parking_lot::Mutex;
+use std::{
+    collections::VecDeque,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Weak},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// Number of incoming connections that are buffered until the listener accepts them.
+const NEW_CONNECTIONS_BUFFER: usize = 16;
+
+/// Represents the configuration for a QUIC transport capability for libp2p.
+///
+/// The same certificate, carrying the public key of the local node, is presented on all the
+/// connections.
+#[derive(Clone)]
+pub struct QuicConfig {
+    /// Configuration of the endpoints.
+    endpoint_config: Arc<quinn_proto::EndpointConfig>,
+    /// Configuration of outgoing connections.
+    client_config: quinn_proto::ClientConfig,
+    /// Configuration of incoming connections.
+    server_config: Arc<quinn_proto::ServerConfig>,
+    /// Endpoints of the active listeners. Dialing reuses them, such that outgoing connections
+    /// originate from a listening port.
+    listeners: Arc<Mutex<Vec<Weak<Endpoint>>>>,
+}
+
+impl QuicConfig {
+    /// Creates a new configuration that authenticates the local node with the given keypair.
+    pub fn new(keypair: &identity::Keypair) -> Result<Self, GenError> {
+        let certificate = certificate::make_certificate(keypair)?;
+        let key = rustls::PrivateKey(certificate.serialize_private_key_der());
+        let certificate = rustls::Certificate(certificate.serialize_der()?);
+
+        let mut transport = quinn_proto::TransportConfig::default();
+        // Substreams are always bidirectional.
+        transport.stream_window_uni(0);
+        transport.keep_alive_interval(Some(Duration::from_secs(5)));
+        let transport = Arc::new(transport);
+
+        let client_config = quinn_proto::ClientConfig {
+            transport: transport.clone(),
+            crypto: Arc::new(tls::make_client_config(certificate.clone(), key.clone())),
+        };
+
+        let mut server_config = quinn_proto::ServerConfig::default();
+        server_config.transport = transport;
+        server_config.crypto = Arc::new(tls::make_server_config(certificate, key));
+        server_config.migration(false);
+
+        Ok(QuicConfig {
+            endpoint_config: Arc::new(quinn_proto::EndpointConfig::default()),
+            client_config,
+            server_config: Arc::new(server_config),
+            listeners: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    /// Returns the endpoint of a listener suitable for dialing the given address, if any.
+    ///
+    /// The endpoint must be bound to the same IP version as the remote and either to the
+    /// unspecified address or to an address whose loopback-ness matches the one of the remote.
+    fn listener_for_dial(&self, remote: &SocketAddr) -> Option<Arc<Endpoint>> {
+        let mut listeners = self.listeners.lock();
+        listeners.retain(|endpoint| endpoint.strong_count() > 0);
+        listeners.iter()
+            .filter_map(Weak::upgrade)
+            .find(|endpoint| {
+                let local_ip = endpoint.local_addr().ip();
+                local_ip.is_ipv4() == remote.is_ipv4()
+                    && (local_ip.is_unspecified() || local_ip.is_loopback() == remote.ip().is_loopback())
+            })
+    }
+}
+
+impl fmt::Debug for QuicConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QuicConfig").finish()
+    }
+}
+
+impl Transport for QuicConfig {
+    type Output = (PeerId, QuicMuxer);
+    type Error = Error;
+    type Listener = QuicListener;
+    type ListenerUpgrade = Upgrade;
+    type Dial = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>> {
+        let socket_addr = multiaddr_to_socketaddr(&addr)
+            .ok_or(TransportError::MultiaddrNotSupported(addr))?;
+
+        let socket = std::net::UdpSocket::bind(socket_addr)
+            .map_err(|err| TransportError::Other(Error::Io(err)))?;
+        let (sender, new_connections) = mpsc::channel(NEW_CONNECTIONS_BUFFER);
+        let endpoint = Endpoint::new(
+            socket,
+            self.endpoint_config.clone(),
+            self.client_config.clone(),
+            Some((self.server_config.clone(), sender)),
+        ).map_err(TransportError::Other)?;
+        self.listeners.lock().push(Arc::downgrade(&endpoint));
+
+        // Report either the single local address or, if listening on the unspecified address,
+        // the addresses of all the interfaces of the same IP version.
+        let local_addr = endpoint.local_addr();
+        let pending_events = if local_addr.ip().is_unspecified() {
+            get_if_addrs()
+                .map_err(|err| TransportError::Other(Error::Io(err)))?
+                .into_iter()
+                .map(|iface| iface.ip())
+                .filter(|ip| ip.is_ipv4() == local_addr.is_ipv4())
+                .map(|ip| ListenerEvent::NewAddress(ip_to_multiaddr(ip, local_addr.port())))
+                .collect()
+        } else {
+            VecDeque::from(vec![ListenerEvent::NewAddress(socketaddr_to_multiaddr(&local_addr))])
+        };
+        log::debug!("Listening on {}", local_addr);
+
+        Ok(QuicListener { endpoint, new_connections, pending_events })
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        let socket_addr = match multiaddr_to_socketaddr(&addr) {
+            Some(socket_addr) if socket_addr.port() != 0 && !socket_addr.ip().is_unspecified() =>
+                socket_addr,
+            _ => return Err(TransportError::MultiaddrNotSupported(addr)),
+        };
+
+        let endpoint = match self.listener_for_dial(&socket_addr) {
+            Some(endpoint) => endpoint,
+            None => {
+                let bind_addr = if socket_addr.is_ipv4() {
+                    SocketAddr::from(([0, 0, 0, 0], 0))
+                } else {
+                    SocketAddr::from(([0u16; 8], 0))
+                };
+                let socket = std::net::UdpSocket::bind(bind_addr)
+                    .map_err(|err| TransportError::Other(Error::Io(err)))?;
+                Endpoint::new(socket, self.endpoint_config, self.client_config, None)
+                    .map_err(TransportError::Other)?
+            }
+        };
+
+        Ok(async move {
+            let connection = endpoint.dial(socket_addr).await?;
+            Upgrade::from_connection(connection).await
+        }.boxed())
+    }
+}
+
+/// Listener for incoming QUIC connections, returned by `QuicConfig::listen_on`.
+pub struct QuicListener {
+    /// The endpoint of the listener.
+    endpoint: Arc<Endpoint>,
+    /// Incoming connections reported by the endpoint.
+    new_connections: mpsc::Receiver<Connection>,
+    /// Events to report before any incoming connection.
+    pending_events: VecDeque<ListenerEvent<Upgrade, Error>>,
+}
+
+impl Stream for QuicListener {
+    type Item = Result<ListenerEvent<Upgrade, Error>, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(event) = self.pending_events.pop_front() {
+            return Poll::Ready(Some(Ok(event)))
+        }
+
+        match self.new_connections.poll_next_unpin(cx) {
+            Poll::Ready(Some(connection)) => {
+                let local_addr = socketaddr_to_multiaddr(&self.endpoint.local_addr());
+                let remote_addr = socketaddr_to_multiaddr(&connection.remote_addr());
+                log::debug!("Incoming connection from {}", remote_addr);
+                Poll::Ready(Some(Ok(ListenerEvent::Upgrade {
+                    upgrade: Upgrade::from_connection(connection),
+                    local_addr,
+                    remote_addr,
+                })))
+            }
+            // The endpoint has stopped.
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl fmt::Debug for QuicListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QuicListener")
+            .field("local_addr", &self.endpoint.local_addr())
+            .finish()
+    }
+}
+
+/// Tries to turn a QUIC multiaddress into a UDP socket address.
+fn multiaddr_to_socketaddr(addr: &Multiaddr) -> Option<SocketAddr> {
+    let mut iter = addr.iter();
+    let proto1 = iter.next()?;
+    let proto2 = iter.next()?;
+    let proto3 = iter.next()?;
+
+    if iter.next().is_some() {
+        return None
+    }
+
+    match (proto1, proto2, proto3) {
+        (Protocol::Ip4(ip), Protocol::Udp(port), Protocol::Quic) => Some(SocketAddr::new(ip.into(), port)),
+        (Protocol::Ip6(ip), Protocol::Udp(port), Protocol::Quic) => Some(SocketAddr::new(ip.into(), port)),
+        _ => None,
+    }
+}
+
+/// Creates a QUIC multiaddress from the given IP address and UDP port.
+fn ip_to_multiaddr(ip: IpAddr, port: u16) -> Multiaddr {
+    Multiaddr::empty()
+        .with(ip.into())
+        .with(Protocol::Udp(port))
+        .with(Protocol::Quic)
+}
+
+/// Creates a QUIC multiaddress from the given socket address.
+fn socketaddr_to_multiaddr(addr: &SocketAddr) -> Multiaddr {
+    ip_to_multiaddr(addr.ip(), addr.port())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn multiaddr_to_udp_conversion() {
+        assert!(multiaddr_to_socketaddr(&"/ip4/127.0.0.1/udp/1234".parse::<Multiaddr>().unwrap()).is_none());
+        assert!(multiaddr_to_socketaddr(&"/ip4/127.0.0.1/tcp/1234/quic".parse::<Multiaddr>().unwrap()).is_none());
+        assert!(multiaddr_to_socketaddr(&"/ip4/127.0.0.1/udp/1234/quic/p2p-circuit".parse::<Multiaddr>().unwrap()).is_none());
+
+        assert_eq!(
+            multiaddr_to_socketaddr(&"/ip4/127.0.0.1/udp/12345/quic".parse::<Multiaddr>().unwrap()),
+            Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 12345))
+        );
+        assert_eq!(
+            multiaddr_to_socketaddr(&"/ip6/::1/udp/12345/quic".parse::<Multiaddr>().unwrap()),
+            Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1)), 12345))
+        );
+        assert_eq!(
+            socketaddr_to_multiaddr(&SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 4001)),
+            "/ip4/10.0.0.1/udp/4001/quic".parse::<Multiaddr>().unwrap()
+        );
+    }
+}
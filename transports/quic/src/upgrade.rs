@@ -0,0 +1,64 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::{connection::Connection, error::Error, muxer::QuicMuxer};
+use libp2p_core::PeerId;
+use quinn_proto::Event;
+use std::{fmt, future::Future, pin::Pin, task::{Context, Poll}};
+
+/// Future that completes the handshake of a QUIC connection and authenticates the remote.
+pub struct Upgrade {
+    connection: Option<Connection>,
+}
+
+impl Upgrade {
+    pub(crate) fn from_connection(connection: Connection) -> Self {
+        Upgrade { connection: Some(connection) }
+    }
+}
+
+impl Future for Upgrade {
+    type Output = Result<(PeerId, QuicMuxer), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let connection = self.connection.as_mut().expect("Future polled after it has completed");
+
+        loop {
+            match connection.poll_event(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Event::Connected) => break,
+                Poll::Ready(Event::ConnectionLost { reason }) =>
+                    return Poll::Ready(Err(Error::ConnectionLost(reason))),
+                // Other events are not relevant during the handshake.
+                Poll::Ready(_) => {}
+            }
+        }
+
+        let connection = self.connection.take().expect("Future polled after it has completed");
+        let peer_id = connection.remote_peer_id()?;
+        Poll::Ready(Ok((peer_id, QuicMuxer::from_connection(connection))))
+    }
+}
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgrade").field("connection", &self.connection).finish()
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::{channel::mpsc, future, prelude::*};
+use libp2p_core::{
+    identity,
+    muxing::{self, StreamMuxer, StreamMuxerEvent},
+    multiaddr::{Multiaddr, Protocol},
+    transport::{ListenerEvent, Transport},
+};
+use libp2p_quic::{QuicConfig, QuicMuxer};
+use rand::RngCore;
+use std::sync::Arc;
+
+/// Drives the connection of the muxer and reports the inbound substreams.
+fn drive(muxer: Arc<QuicMuxer>) -> mpsc::UnboundedReceiver<muxing::SubstreamRef<Arc<QuicMuxer>>> {
+    let (tx, rx) = mpsc::unbounded();
+    async_std::task::spawn(async move {
+        loop {
+            match future::poll_fn(|cx| muxer.poll_event(cx)).await {
+                Ok(StreamMuxerEvent::InboundSubstream(substream)) => {
+                    let substream = muxing::substream_from_ref(muxer.clone(), substream);
+                    if tx.unbounded_send(substream).is_err() {
+                        return
+                    }
+                }
+                Ok(StreamMuxerEvent::AddressChange(_)) => {}
+                Err(_) => return,
+            }
+        }
+    });
+    rx
+}
+
+async fn listen(transport: QuicConfig, addr: Multiaddr) -> (Multiaddr, impl Stream<Item = ListenerEvent<libp2p_quic::Upgrade, libp2p_quic::Error>>) {
+    let mut listener = transport.listen_on(addr).unwrap().map(Result::unwrap);
+    let addr = match listener.next().await.unwrap() {
+        ListenerEvent::NewAddress(addr) => addr,
+        e => panic!("Unexpected event {:?}", e.map(|_| ())),
+    };
+    (addr, listener)
+}
+
+#[async_std::test]
+async fn connect_and_echo() {
+    let listener_keys = identity::Keypair::generate_ed25519();
+    let dialer_keys = identity::Keypair::generate_ed25519();
+    let listener_transport = QuicConfig::new(&listener_keys).unwrap();
+    let dialer_transport = QuicConfig::new(&dialer_keys).unwrap();
+
+    let (addr, mut listener) = listen(listener_transport, "/ip4/127.0.0.1/udp/0/quic".parse().unwrap()).await;
+    assert!(matches!(addr.iter().last(), Some(Protocol::Quic)));
+
+    let dialer_peer_id = dialer_keys.public().into_peer_id();
+    let listener_task = async_std::task::spawn(async move {
+        let (peer_id, muxer) = match listener.next().await.unwrap() {
+            ListenerEvent::Upgrade { upgrade, .. } => upgrade.await.unwrap(),
+            e => panic!("Unexpected event {:?}", e.map(|_| ())),
+        };
+        assert_eq!(peer_id, dialer_peer_id);
+
+        let mut inbound = drive(Arc::new(muxer));
+        let mut substream = inbound.next().await.unwrap();
+        let mut data = Vec::new();
+        substream.read_to_end(&mut data).await.unwrap();
+        substream.write_all(&data).await.unwrap();
+        substream.close().await.unwrap();
+        // Keep the connection alive until the dialer is done.
+        inbound.next().await;
+    });
+
+    let (peer_id, muxer) = dialer_transport.dial(addr).unwrap().await.unwrap();
+    assert_eq!(peer_id, listener_keys.public().into_peer_id());
+
+    let muxer = Arc::new(muxer);
+    let _inbound = drive(muxer.clone());
+    let mut substream = muxing::outbound_from_ref_and_wrap(muxer.clone()).await.unwrap();
+
+    let mut data = vec![0; 256 * 1024];
+    rand::thread_rng().fill_bytes(&mut data);
+    substream.write_all(&data).await.unwrap();
+    substream.close().await.unwrap();
+
+    let mut echoed = Vec::new();
+    substream.read_to_end(&mut echoed).await.unwrap();
+    assert_eq!(data, echoed);
+
+    future::poll_fn(|cx| muxer.close(cx)).await.unwrap();
+    listener_task.await;
+}
+
+#[async_std::test]
+async fn dial_reuses_listening_port() {
+    let listener_keys = identity::Keypair::generate_ed25519();
+    let dialer_keys = identity::Keypair::generate_ed25519();
+    let listener_transport = QuicConfig::new(&listener_keys).unwrap();
+    let dialer_transport = QuicConfig::new(&dialer_keys).unwrap();
+
+    let (listener_addr, mut listener) = listen(listener_transport, "/ip4/127.0.0.1/udp/0/quic".parse().unwrap()).await;
+    let (dialer_addr, _dialer_listener) = listen(dialer_transport.clone(), "/ip4/127.0.0.1/udp/0/quic".parse().unwrap()).await;
+
+    let dial = async_std::task::spawn(dialer_transport.dial(listener_addr).unwrap());
+    let (accept, remote_addr) = match listener.next().await.unwrap() {
+        ListenerEvent::Upgrade { upgrade, remote_addr, .. } => (upgrade, remote_addr),
+        e => panic!("Unexpected event {:?}", e.map(|_| ())),
+    };
+    assert_eq!(remote_addr, dialer_addr);
+
+    let (dialed, accepted) = future::join(dial, accept).await;
+    assert_eq!(dialed.unwrap().0, listener_keys.public().into_peer_id());
+    assert_eq!(accepted.unwrap().0, dialer_keys.public().into_peer_id());
+}
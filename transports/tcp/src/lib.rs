@@ -28,6 +28,16 @@
 //! Both the `TcpConfig` and `TokioTcpConfig` structs implement the `Transport` trait of the
 //! `core` library. See the documentation of `core` and of libp2p in general to learn how to
 //! use the `Transport` trait.
+//!
+//! # Port reuse
+//!
+//! With `port_reuse` enabled, outgoing connections are bound to the port of one of
+//! the listeners of the same transport. Remotes thus observe the dialer at its listening address,
+//! which is a prerequisite for establishing direct connections through NATs by means of a
+//! simultaneous TCP open.
+
+#[cfg(feature = "async-std")]
+extern crate async_std_dep as async_std;
 
 use futures::{future::{self, Ready}, prelude::*};
 use futures_timer::Delay;
@@ -41,18 +51,19 @@ use libp2p_core::{
 use log::{debug, trace};
 use socket2::{Socket, Domain, Type};
 use std::{
-    collections::VecDeque,
+    collections::{HashSet, VecDeque},
     convert::TryFrom,
     io,
     iter::{self, FromIterator},
     net::{IpAddr, SocketAddr},
     pin::Pin,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
     time::Duration
 };
 
 macro_rules! codegen {
-    ($feature_name:expr, $tcp_config:ident, $tcp_trans_stream:ident, $tcp_listen_stream:ident, $apply_config:ident, $tcp_stream:ty, $tcp_listener:ty) => {
+    ($feature_name:expr, $tcp_config:ident, $tcp_trans_stream:ident, $tcp_listen_stream:ident, $apply_config:ident, $connect_bound:ident, $tcp_stream:ty, $tcp_listener:ty) => {
 
 /// Represents the configuration for a TCP/IP transport capability for libp2p.
 ///
@@ -67,6 +78,11 @@ pub struct $tcp_config {
     ttl: Option<u32>,
     /// `TCP_NODELAY` to set for opened sockets, or `None` to keep default.
     nodelay: Option<bool>,
+    /// Whether outgoing connections are bound to the port of a listener.
+    port_reuse: bool,
+    /// The local addresses of the active listeners, shared by all clones of
+    /// the configuration.
+    listen_addrs: Arc<Mutex<HashSet<SocketAddr>>>,
 }
 
 impl $tcp_config {
@@ -76,6 +92,8 @@ impl $tcp_config {
             sleep_on_error: Duration::from_millis(100),
             ttl: None,
             nodelay: None,
+            port_reuse: false,
+            listen_addrs: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 
@@ -90,6 +108,31 @@ impl $tcp_config {
         self.nodelay = Some(value);
         self
     }
+
+    /// Sets whether outgoing connections should reuse the port of a listener.
+    ///
+    /// When enabled, listening sockets are created with `SO_REUSEPORT` on Unix
+    /// and with `SO_REUSEADDR` on other platforms, which have no `SO_REUSEPORT`
+    /// but allow binding to a port in use with `SO_REUSEADDR`. Dialing binds the new socket to the address of a listener of this
+    /// transport (or any of its clones) whose IP version and scope match the
+    /// remote address. If there is no such listener, an ephemeral port is used.
+    pub fn port_reuse(mut self, value: bool) -> Self {
+        self.port_reuse = value;
+        self
+    }
+
+    /// Returns the listen address to bind an outgoing connection to `remote`
+    /// to, if port reuse is enabled.
+    fn local_dial_addr(&self, remote: &SocketAddr) -> Option<SocketAddr> {
+        if !self.port_reuse {
+            return None
+        }
+        let listen_addrs = self.listen_addrs.lock().expect("lock is never poisoned");
+        listen_addrs.iter()
+            .find(|a| a.is_ipv4() == remote.is_ipv4()
+                && (a.ip().is_unspecified() || a.ip().is_loopback() == remote.ip().is_loopback()))
+            .cloned()
+    }
 }
 
 impl Transport for $tcp_config {
@@ -120,6 +163,18 @@ impl Transport for $tcp_config {
             if cfg!(target_family = "unix") {
                 socket.set_reuse_address(true)?;
             }
+            #[cfg(unix)]
+            {
+                if cfg.port_reuse {
+                    socket.set_reuse_port(true)?;
+                }
+            }
+            #[cfg(not(unix))]
+            {
+                if cfg.port_reuse {
+                    socket.set_reuse_address(true)?;
+                }
+            }
             socket.bind(&socket_addr.into())?;
             socket.listen(1024)?; // we may want to make this configurable
 
@@ -129,6 +184,10 @@ impl Transport for $tcp_config {
             let local_addr = listener.local_addr()?;
             let port = local_addr.port();
 
+            if cfg.port_reuse {
+                cfg.listen_addrs.lock().expect("lock is never poisoned").insert(local_addr);
+            }
+
             // Determine all our listen addresses which is either a single local IP address
             // or (if a wildcard IP address was used) the addresses of all our interfaces,
             // as reported by `get_if_addrs`.
@@ -165,6 +224,7 @@ impl Transport for $tcp_config {
                 stream: listener,
                 pause: None,
                 pause_duration: cfg.sleep_on_error,
+                local_addr,
                 port,
                 addrs,
                 pending,
@@ -192,7 +252,23 @@ impl Transport for $tcp_config {
         debug!("Dialing {}", addr);
 
         async fn do_dial(cfg: $tcp_config, socket_addr: SocketAddr) -> Result<$tcp_trans_stream, io::Error> {
-            let stream = <$tcp_stream>::connect(&socket_addr).await?;
+            let stream = if let Some(local_addr) = cfg.local_dial_addr(&socket_addr) {
+                trace!("Binding dialer for {} to {}", socket_addr, local_addr);
+                let domain = if socket_addr.is_ipv4() { Domain::ipv4() } else { Domain::ipv6() };
+                let socket = Socket::new(domain, Type::stream(), Some(socket2::Protocol::tcp()))?;
+                if socket_addr.is_ipv6() {
+                    socket.set_only_v6(true)?;
+                }
+                // Without `SO_REUSEPORT`, `SO_REUSEADDR` permits binding to
+                // the port of the listener on non-Unix platforms.
+                socket.set_reuse_address(true)?;
+                #[cfg(unix)]
+                socket.set_reuse_port(true)?;
+                socket.bind(&local_addr.into())?;
+                $connect_bound(socket, socket_addr).await?
+            } else {
+                <$tcp_stream>::connect(&socket_addr).await?
+            };
             $apply_config(&cfg, &stream)?;
             Ok($tcp_trans_stream { inner: stream })
         }
@@ -210,6 +286,8 @@ pub struct $tcp_listen_stream {
     pause: Option<Delay>,
     /// How long to pause after an error.
     pause_duration: Duration,
+    /// The local socket address of the listener.
+    local_addr: SocketAddr,
     /// The port which we use as our listen port in listener event addresses.
     port: u16,
     /// The set of known addresses.
@@ -289,6 +367,14 @@ impl $tcp_listen_stream {
     }
 }
 
+impl Drop for $tcp_listen_stream {
+    fn drop(&mut self) {
+        if self.config.port_reuse {
+            self.config.listen_addrs.lock().expect("lock is never poisoned").remove(&self.local_addr);
+        }
+    }
+}
+
 /// Wraps around a `TcpStream` and adds logging for important events.
 #[cfg_attr(docsrs, doc(cfg(feature = $feature_name)))]
 #[derive(Debug)]
@@ -323,10 +409,40 @@ fn $apply_config(config: &$tcp_config, socket: &$tcp_stream) -> Result<(), io::E
 }
 
 #[cfg(feature = "async-std")]
-codegen!("async-std", TcpConfig, TcpTransStream, TcpListenStream, apply_config_async_std, async_std::net::TcpStream, async_std::net::TcpListener);
+codegen!("async-std", TcpConfig, TcpTransStream, TcpListenStream, apply_config_async_std, connect_bound_async_std, async_std::net::TcpStream, async_std::net::TcpListener);
 
 #[cfg(feature = "tokio")]
-codegen!("tokio", TokioTcpConfig, TokioTcpTransStream, TokioTcpListenStream, apply_config_tokio, tokio::net::TcpStream, tokio::net::TcpListener);
+codegen!("tokio", TokioTcpConfig, TokioTcpTransStream, TokioTcpListenStream, apply_config_tokio, connect_bound_tokio, tokio::net::TcpStream, tokio::net::TcpListener);
+
+/// Connects an already bound socket to the given address.
+///
+/// `async-std` offers no way to connect a socket that has been bound beforehand,
+/// hence the socket is connected in non-blocking mode and registered with the
+/// reactor of `async-io` (on which `async-std` is built) until it is writable,
+/// i.e. connected or failed.
+#[cfg(feature = "async-std")]
+async fn connect_bound_async_std(socket: Socket, addr: SocketAddr) -> Result<async_std::net::TcpStream, io::Error> {
+    socket.set_nonblocking(true)?;
+    match socket.connect(&addr.into()) {
+        Ok(()) => {}
+        #[cfg(unix)]
+        Err(err) if err.raw_os_error() == Some(libc::EINPROGRESS) => {}
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+        Err(err) => return Err(err),
+    }
+    let stream = async_io::Async::new(socket.into_tcp_stream())?;
+    stream.writable().await?;
+    if let Some(err) = stream.get_ref().take_error()? {
+        return Err(err)
+    }
+    Ok(async_std::net::TcpStream::from(stream.into_inner()?))
+}
+
+/// Connects an already bound socket to the given address.
+#[cfg(feature = "tokio")]
+async fn connect_bound_tokio(socket: Socket, addr: SocketAddr) -> Result<tokio::net::TcpStream, io::Error> {
+    tokio::net::TcpStream::connect_std(socket.into_tcp_stream(), &addr).await
+}
 
 #[cfg(feature = "async-std")]
 impl AsyncRead for TcpTransStream {
@@ -638,6 +754,76 @@ mod tests {
         test("/ip6/::1/tcp/0".parse().unwrap());
     }
 
+    #[test]
+    #[cfg(feature = "async-std")]
+    fn port_reuse_dialing_uses_listen_port() {
+        fn test(addr: Multiaddr) {
+            let (ready_tx, ready_rx) = futures::channel::oneshot::channel();
+            let (remote_tx, remote_rx) = futures::channel::oneshot::channel();
+            let mut ready_tx = Some(ready_tx);
+            let mut remote_tx = Some(remote_tx);
+
+            let remote_listener_addr = addr.clone();
+            async_std::task::spawn(async move {
+                let tcp = TcpConfig::new();
+                let mut listener = tcp.listen_on(remote_listener_addr).unwrap();
+
+                loop {
+                    match listener.next().await.unwrap().unwrap() {
+                        ListenerEvent::NewAddress(listen_addr) => {
+                            ready_tx.take().unwrap().send(listen_addr).unwrap();
+                        },
+                        ListenerEvent::Upgrade { upgrade, remote_addr, .. } => {
+                            let mut upgrade = upgrade.await.unwrap();
+                            let mut buf = [0u8; 3];
+                            upgrade.read_exact(&mut buf).await.unwrap();
+                            remote_tx.take().unwrap().send(remote_addr).unwrap();
+                        },
+                        _ => unreachable!()
+                    }
+                }
+            });
+
+            async_std::task::block_on(async move {
+                let remote_addr = ready_rx.await.unwrap();
+                let tcp = TcpConfig::new().port_reuse(true);
+
+                let mut listener = tcp.clone().listen_on(addr).unwrap();
+                let listen_addr = match listener.next().await.unwrap().unwrap() {
+                    ListenerEvent::NewAddress(a) => a,
+                    _ => panic!("Expected a new listen address."),
+                };
+
+                let mut socket = tcp.dial(remote_addr).unwrap().await.unwrap();
+                socket.write_all(&[0x1, 0x2, 0x3]).await.unwrap();
+
+                assert_eq!(remote_rx.await.unwrap(), listen_addr);
+            });
+        }
+
+        test("/ip4/127.0.0.1/tcp/0".parse().unwrap());
+        test("/ip6/::1/tcp/0".parse().unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "async-std")]
+    fn port_reuse_dialing_reports_refused_connection() {
+        async_std::task::block_on(async move {
+            let tcp = TcpConfig::new().port_reuse(true);
+            let mut listener = tcp.clone().listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+            match listener.next().await.unwrap().unwrap() {
+                ListenerEvent::NewAddress(_) => {},
+                _ => panic!("Expected a new listen address."),
+            }
+
+            // A port nobody is listening on.
+            let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+            let remote_addr = format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap();
+            let err = tcp.dial(remote_addr).unwrap().await.err().unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+        });
+    }
+
     #[test]
     #[cfg(feature = "async-std")]
     fn replace_port_0_in_returned_multiaddr_ipv4() {